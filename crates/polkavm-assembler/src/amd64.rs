@@ -13,6 +13,7 @@ const PREFIX_OVERRIDE_SEGMENT_FS: u8 = 0x64;
 const PREFIX_OVERRIDE_SEGMENT_GS: u8 = 0x65;
 const PREFIX_OVERRIDE_OP_SIZE: u8 = 0x66;
 const PREFIX_OVERRIDE_ADDR_SIZE: u8 = 0x67;
+const PREFIX_REP: u8 = 0xf3;
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum Reg {
@@ -463,6 +464,7 @@ impl From<MemOp> for RegMem {
 struct Inst {
     override_op_size: bool,
     override_addr_size: bool,
+    prefix_rep: bool,
     op_alt: bool,
     force_enable_modrm: bool,
     rex: u8,
@@ -481,6 +483,7 @@ impl Inst {
         Inst {
             override_op_size: false,
             override_addr_size: false,
+            prefix_rep: false,
             op_alt: false,
             force_enable_modrm: false,
             rex: 0,
@@ -512,6 +515,12 @@ impl Inst {
         self
     }
 
+    #[inline]
+    const fn prefix_rep(mut self) -> Self {
+        self.prefix_rep = true;
+        self
+    }
+
     #[inline]
     const fn op_alt(mut self) -> Self {
         self.op_alt = true;
@@ -714,6 +723,10 @@ impl Inst {
             buf.append(PREFIX_OVERRIDE_ADDR_SIZE);
         }
 
+        if self.prefix_rep {
+            buf.append(PREFIX_REP);
+        }
+
         if self.rex != 0 {
             buf.append(self.rex);
         }
@@ -1023,6 +1036,20 @@ pub mod addr {
         }
     }
 
+    impl From<(RegSize, MemOp, Reg)> for Operands {
+        #[inline]
+        fn from((reg_size, dst, src): (RegSize, MemOp, Reg)) -> Self {
+            Self::RegMem_Reg(reg_size.into(), RegMem::Mem(dst), src)
+        }
+    }
+
+    impl From<(RegSize, MemOp, RegIndex)> for Operands {
+        #[inline]
+        fn from((reg_size, dst, src): (RegSize, MemOp, RegIndex)) -> Self {
+            Self::RegMem_Reg(reg_size.into(), RegMem::Mem(dst), src.into())
+        }
+    }
+
     impl From<(Reg, ImmKind)> for Operands {
         #[inline]
         fn from((dst, imm): (Reg, ImmKind)) -> Self {
@@ -1271,6 +1298,31 @@ pub mod inst {
             None,
             (fmt.write_str("ret")),
 
+        // https://www.felixcloutier.com/x86/cld
+        cld() =>
+            InstBuf::from_array([0xfc]),
+            None,
+            (fmt.write_str("cld")),
+
+        // https://www.felixcloutier.com/x86/std
+        std() =>
+            InstBuf::from_array([0xfd]),
+            None,
+            (fmt.write_str("std")),
+
+        // https://www.felixcloutier.com/x86/rep:repe:repz:repne:repnz
+        // https://www.felixcloutier.com/x86/movs:movsb:movsw:movsd:movsq
+        rep_movsb() =>
+            InstBuf::from_array([0xf3, 0xa4]),
+            None,
+            (fmt.write_str("rep movsb")),
+
+        // https://www.felixcloutier.com/x86/stos:stosb:stosw:stosd:stosq
+        rep_stosb() =>
+            InstBuf::from_array([0xf3, 0xaa]),
+            None,
+            (fmt.write_str("rep stosb")),
+
         // https://www.felixcloutier.com/x86/mov
         // https://www.felixcloutier.com/x86/movzx
         // https://www.felixcloutier.com/x86/movsx:movsxd
@@ -1284,6 +1336,29 @@ pub mod inst {
             None,
             (fmt.write_fmt(core::format_args!("movsxd {}, {}", self.0.name(), self.1.name32()))),
 
+        movsx_8(RegSize, Reg, Reg) =>
+            {
+                Inst::new(0xbe)
+                    .op_alt()
+                    .rex_if(matches!(self.2, Reg::rsp | Reg::rbp | Reg::rsi | Reg::rdi))
+                    .rex_64b_if(matches!(self.0, RegSize::R64))
+                    .modrm_reg(self.1)
+                    .modrm_rm_direct(self.2)
+                    .encode()
+            },
+            None,
+            (fmt.write_fmt(core::format_args!("movsx {}, {}", self.1.name_from(self.0), self.2.name8()))),
+
+        movsx_16(RegSize, Reg, Reg) =>
+            Inst::new(0xbf).op_alt().rex_64b_if(matches!(self.0, RegSize::R64)).modrm_reg(self.1).modrm_rm_direct(self.2).encode(),
+            None,
+            (fmt.write_fmt(core::format_args!("movsx {}, {}", self.1.name_from(self.0), self.2.name16()))),
+
+        movzx_16(RegSize, Reg, Reg) =>
+            Inst::new(0xb7).op_alt().rex_64b_if(matches!(self.0, RegSize::R64)).modrm_reg(self.1).modrm_rm_direct(self.2).encode(),
+            None,
+            (fmt.write_fmt(core::format_args!("movzx {}, {}", self.1.name_from(self.0), self.2.name16()))),
+
         mov_imm64(Reg, u64) =>
             {
                 if self.1 <= 0x7fffffff {
@@ -1503,6 +1578,16 @@ pub mod inst {
             (fmt.write_fmt(core::format_args!("shr {}, 0x{:x}", self.1.display(Size::from(self.0)), self.2))),
 
         // https://www.felixcloutier.com/x86/rcl:rcr:rol:ror
+        rol_cl(RegSize, RegMem) =>
+            Inst::new(0xd3).rex_64b_if(matches!(self.0, RegSize::R64)).regmem(self.1).modrm_opext(0b000).encode(),
+            None,
+            (fmt.write_fmt(core::format_args!("rol {}, cl", self.1.display(Size::from(self.0))))),
+
+        ror_cl(RegSize, RegMem) =>
+            Inst::new(0xd3).rex_64b_if(matches!(self.0, RegSize::R64)).regmem(self.1).modrm_opext(0b001).encode(),
+            None,
+            (fmt.write_fmt(core::format_args!("ror {}, cl", self.1.display(Size::from(self.0))))),
+
         ror_imm(RegSize, RegMem, u8) =>
             {
                 if self.2 == 1 {
@@ -1514,6 +1599,42 @@ pub mod inst {
             None,
             (fmt.write_fmt(core::format_args!("ror {}, 0x{:x}", self.1.display(Size::from(self.0)), self.2))),
 
+        // https://www.felixcloutier.com/x86/bsf
+        bsf(RegSize, Reg, RegMem) =>
+            Inst::new(0xbc).op_alt().rex_64b_if(matches!(self.0, RegSize::R64)).modrm_reg(self.1).regmem(self.2).encode(),
+            None,
+            (fmt.write_fmt(core::format_args!("bsf {}, {}", self.1.name_from(self.0), self.2.display_without_prefix(Size::from(self.0))))),
+
+        // https://www.felixcloutier.com/x86/bsr
+        bsr(RegSize, Reg, RegMem) =>
+            Inst::new(0xbd).op_alt().rex_64b_if(matches!(self.0, RegSize::R64)).modrm_reg(self.1).regmem(self.2).encode(),
+            None,
+            (fmt.write_fmt(core::format_args!("bsr {}, {}", self.1.name_from(self.0), self.2.display_without_prefix(Size::from(self.0))))),
+
+        // https://www.felixcloutier.com/x86/bswap
+        bswap(RegSize, Reg) =>
+            Inst::with_reg_in_op(0xc8, self.1).op_alt().rex_64b_if(matches!(self.0, RegSize::R64)).encode(),
+            None,
+            (fmt.write_fmt(core::format_args!("bswap {}", self.1.name_from(self.0)))),
+
+        // https://www.felixcloutier.com/x86/lzcnt
+        lzcnt(RegSize, Reg, RegMem) =>
+            Inst::new(0xbd).prefix_rep().op_alt().rex_64b_if(matches!(self.0, RegSize::R64)).modrm_reg(self.1).regmem(self.2).encode(),
+            None,
+            (fmt.write_fmt(core::format_args!("lzcnt {}, {}", self.1.name_from(self.0), self.2.display_without_prefix(Size::from(self.0))))),
+
+        // https://www.felixcloutier.com/x86/tzcnt
+        tzcnt(RegSize, Reg, RegMem) =>
+            Inst::new(0xbc).prefix_rep().op_alt().rex_64b_if(matches!(self.0, RegSize::R64)).modrm_reg(self.1).regmem(self.2).encode(),
+            None,
+            (fmt.write_fmt(core::format_args!("tzcnt {}, {}", self.1.name_from(self.0), self.2.display_without_prefix(Size::from(self.0))))),
+
+        // https://www.felixcloutier.com/x86/popcnt
+        popcnt(RegSize, Reg, RegMem) =>
+            Inst::new(0xb8).prefix_rep().op_alt().rex_64b_if(matches!(self.0, RegSize::R64)).modrm_reg(self.1).regmem(self.2).encode(),
+            None,
+            (fmt.write_fmt(core::format_args!("popcnt {}, {}", self.1.name_from(self.0), self.2.display_without_prefix(Size::from(self.0))))),
+
         // https://www.felixcloutier.com/x86/test
         test(Operands) =>
             {
@@ -1570,6 +1691,12 @@ pub mod inst {
                 }
             }),
 
+        // https://www.felixcloutier.com/x86/mul
+        mul(RegSize, RegMem) =>
+            Inst::new(0xf7).modrm_opext(0b100).rex_64b_if(matches!(self.0, RegSize::R64)).regmem(self.1).encode(),
+            None,
+            (fmt.write_fmt(core::format_args!("mul {}", self.1.display(Size::from(self.0))))),
+
         // https://www.felixcloutier.com/x86/div
         div(RegSize, RegMem) =>
             Inst::new(0xf7).modrm_opext(0b110).rex_64b_if(matches!(self.0, RegSize::R64)).regmem(self.1).encode(),
@@ -1588,6 +1715,12 @@ pub mod inst {
             None,
             (fmt.write_str("cdq")),
 
+        // https://www.felixcloutier.com/x86/cwd:cdq:cqo
+        cqo() =>
+            Inst::new(0x99).rex_64b().encode(),
+            None,
+            (fmt.write_str("cqo")),
+
         // https://www.felixcloutier.com/x86/setcc
         setcc(Condition, RegMem) =>
             {
@@ -2214,12 +2347,17 @@ mod tests {
     generate_tests! {
         add,
         and,
+        bsf,
+        bsr,
+        bswap,
         bts,
         call_rel32,
         call,
         cdq,
+        cld,
         cmov,
         cmp,
+        cqo,
         div,
         endbr64,
         idiv,
@@ -2233,10 +2371,15 @@ mod tests {
         jmp,
         lea,
         load,
+        lzcnt,
         mov_imm,
         mov_imm64,
         mov,
+        movsx_16,
+        movsx_8,
         movsxd_32_to_64,
+        movzx_16,
+        mul,
         neg,
         nop,
         nop10,
@@ -2252,8 +2395,13 @@ mod tests {
         not,
         or,
         pop,
+        popcnt,
         push,
+        rep_movsb,
+        rep_stosb,
         ret,
+        rol_cl,
+        ror_cl,
         ror_imm,
         sar_cl,
         sar_imm,
@@ -2262,10 +2410,12 @@ mod tests {
         shl_imm,
         shr_cl,
         shr_imm,
+        std,
         store,
         sub,
         syscall,
         test,
+        tzcnt,
         ud2,
         xor,
     }
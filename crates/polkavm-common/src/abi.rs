@@ -55,7 +55,6 @@ pub const VM_MAXIMUM_EXPORT_COUNT: u32 = 1024;
 pub const VM_MAXIMUM_EXTERN_ARG_COUNT: usize = 6;
 
 /// The minimum required alignment of runtime code pointers.
-// TODO: Support the C extension in the linker and lower this to 2.
 pub const VM_CODE_ADDRESS_ALIGNMENT: u32 = 4;
 
 /// The memory configuration used by a given guest program.
@@ -5,6 +5,15 @@ use crate::program::ExternTy;
 /// These are processed when relinking the ELf file and will *not* end up in the final payload.
 pub const INSTRUCTION_ECALLI: u32 = 0x0000000b;
 
+/// Custom instruction used to grow the heap.
+///
+/// This is an R-type instruction in the `custom-0` opcode space with `funct3` set to `1`;
+/// `rd` receives the previous top of the heap and `rs1` holds the number of bytes to grow it by.
+pub const INSTRUCTION_SBRK: u32 = 0x0000100b;
+
+/// The mask which must be applied to an instruction before comparing it to [`INSTRUCTION_SBRK`].
+pub const INSTRUCTION_SBRK_MASK: u32 = 0xfe00707f;
+
 pub struct Reader<'a> {
     pub buffer: &'a [u8],
     pub bytes_consumed: usize,
@@ -46,23 +55,34 @@ impl<'a> From<&'a [u8]> for Reader<'a> {
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct FnMetadata {
     pub name: alloc::string::String,
-    pub return_ty: Option<ExternTy>,
+    pub returns: [Option<ExternTy>; crate::abi::VM_MAXIMUM_EXTERN_ARG_COUNT],
     pub args: [Option<ExternTy>; crate::abi::VM_MAXIMUM_EXTERN_ARG_COUNT],
 }
 
 impl FnMetadata {
     pub fn new(name: impl Into<alloc::string::String>, args: &[ExternTy], return_ty: Option<ExternTy>) -> Self {
-        assert!(args.len() <= crate::abi::VM_MAXIMUM_EXTERN_ARG_COUNT);
+        match return_ty {
+            Some(return_ty) => Self::new_with_returns(name, args, &[return_ty]),
+            None => Self::new_with_returns(name, args, &[]),
+        }
+    }
+
+    /// Creates metadata for a function which returns any number of values.
+    pub fn new_with_returns(name: impl Into<alloc::string::String>, args: &[ExternTy], returns: &[ExternTy]) -> Self {
+        fn to_array(types: &[ExternTy]) -> [Option<ExternTy>; crate::abi::VM_MAXIMUM_EXTERN_ARG_COUNT] {
+            assert!(types.len() <= crate::abi::VM_MAXIMUM_EXTERN_ARG_COUNT);
 
-        let mut args_array = [None; crate::abi::VM_MAXIMUM_EXTERN_ARG_COUNT];
-        for (slot, arg) in args_array.iter_mut().zip(args.iter()) {
-            *slot = Some(*arg);
+            let mut array = [None; crate::abi::VM_MAXIMUM_EXTERN_ARG_COUNT];
+            for (slot, ty) in array.iter_mut().zip(types.iter()) {
+                *slot = Some(*ty);
+            }
+            array
         }
 
         FnMetadata {
             name: name.into(),
-            return_ty,
-            args: args_array,
+            returns: to_array(returns),
+            args: to_array(args),
         }
     }
 
@@ -70,8 +90,16 @@ impl FnMetadata {
         self.args.iter().take_while(|arg| arg.is_some()).flatten().copied()
     }
 
+    pub fn returns(&self) -> impl Iterator<Item = ExternTy> + '_ {
+        self.returns.iter().take_while(|ty| ty.is_some()).flatten().copied()
+    }
+
+    /// Returns the type of the return value, if the function returns exactly one value.
     pub fn return_ty(&self) -> Option<ExternTy> {
-        self.return_ty
+        match self.returns {
+            [Some(return_ty), None, ..] => Some(return_ty),
+            _ => None,
+        }
     }
 
     pub fn name(&self) -> &str {
@@ -81,7 +109,15 @@ impl FnMetadata {
     pub fn serialize(&self, mut cb: impl FnMut(&[u8])) {
         cb(&(self.name.len() as u32).to_le_bytes());
         cb(self.name.as_bytes());
-        cb(&[self.return_ty.map_or(0, |ty| ty as u8)]);
+        match self.returns().count() {
+            0 | 1 => cb(&[self.return_ty().map_or(0, |ty| ty as u8)]),
+            count => {
+                cb(&[crate::program::MULTIPLE_RETURN_VALUES_MARKER, count as u8]);
+                for ty in self.returns() {
+                    cb(&[ty as u8]);
+                }
+            }
+        }
         cb(&[self.args().count() as u8]);
         for arg in self.args() {
             cb(&[arg as u8]);
@@ -91,12 +127,25 @@ impl FnMetadata {
     pub fn try_deserialize(b: &mut Reader) -> Result<Self, &'static str> {
         let name_length = b.read_u32()? as usize;
         let name = core::str::from_utf8(b.read(name_length)?).map_err(|_| "name of the import is not valid UTF-8")?;
-        let return_ty = b.read_byte()?;
-        let return_ty = if return_ty == 0 {
-            None
-        } else {
-            Some(ExternTy::try_deserialize(return_ty).ok_or("invalid return type")?)
-        };
+        let mut returns = [None; crate::abi::VM_MAXIMUM_EXTERN_ARG_COUNT];
+        match b.read_byte()? {
+            0 => {}
+            crate::program::MULTIPLE_RETURN_VALUES_MARKER => {
+                let return_count = b.read_byte()? as usize;
+                if return_count > crate::abi::VM_MAXIMUM_EXTERN_ARG_COUNT {
+                    return Err("too many return values");
+                }
+
+                #[allow(clippy::needless_range_loop)]
+                for n in 0..return_count {
+                    returns[n] = Some(ExternTy::try_deserialize(b.read_byte()?).ok_or("invalid return type")?);
+                }
+            }
+            return_ty => {
+                returns[0] = Some(ExternTy::try_deserialize(return_ty).ok_or("invalid return type")?);
+            }
+        }
+
         let arg_count = b.read_byte()? as usize;
         if arg_count > crate::abi::VM_MAXIMUM_EXTERN_ARG_COUNT {
             return Err("too many arguments");
@@ -111,7 +160,7 @@ impl FnMetadata {
 
         Ok(Self {
             name: name.into(),
-            return_ty,
+            returns,
             args,
         })
     }
@@ -133,6 +182,10 @@ impl ImportMetadata {
         self.prototype.args()
     }
 
+    pub fn returns(&self) -> impl Iterator<Item = ExternTy> + '_ {
+        self.prototype.returns()
+    }
+
     pub fn return_ty(&self) -> Option<ExternTy> {
         self.prototype.return_ty()
     }
@@ -1,23 +1,317 @@
+#[cfg(feature = "alloc")]
+use alloc::{boxed::Box, string::String, vec::Vec};
+
+/// The reason why the execution trapped.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[non_exhaustive]
+pub enum TrapKind {
+    /// The exact reason for the trap is not known.
+    #[default]
+    Unknown,
+
+    /// The program executed a `trap` instruction.
+    TrapInstruction,
+
+    /// The program tried to load from an address which is not readable.
+    InvalidLoad { address: u32 },
+
+    /// The program tried to store to an address which is not writable.
+    InvalidStore { address: u32 },
+
+    /// The program tried to jump to an address which is not a valid jump target.
+    InvalidJumpTarget,
+
+    /// The program triggered a fault while performing a division.
+    DivisionFault,
+
+    /// A host function called by the program returned an error.
+    HostFunctionError,
+
+    /// The worker process in which the program was running has crashed.
+    WorkerCrashed,
+}
+
+impl core::fmt::Display for TrapKind {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match *self {
+            TrapKind::Unknown => fmt.write_str("unknown reason"),
+            TrapKind::TrapInstruction => fmt.write_str("trap instruction"),
+            TrapKind::InvalidLoad { address } => write!(fmt, "invalid load from 0x{address:x}"),
+            TrapKind::InvalidStore { address } => write!(fmt, "invalid store to 0x{address:x}"),
+            TrapKind::InvalidJumpTarget => fmt.write_str("invalid jump target"),
+            TrapKind::DivisionFault => fmt.write_str("division fault"),
+            TrapKind::HostFunctionError => fmt.write_str("host function error"),
+            TrapKind::WorkerCrashed => fmt.write_str("worker process crashed"),
+        }
+    }
+}
+
+/// A symbol (a function, possibly inlined) associated with a backtrace frame.
+#[cfg(feature = "alloc")]
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct BacktraceSymbol {
+    function_name: Option<String>,
+    path: Option<String>,
+    line: Option<u32>,
+    column: Option<u32>,
+}
+
+#[cfg(feature = "alloc")]
+impl BacktraceSymbol {
+    #[doc(hidden)]
+    pub fn new(function_name: Option<String>, path: Option<String>, line: Option<u32>, column: Option<u32>) -> Self {
+        BacktraceSymbol {
+            function_name,
+            path,
+            line,
+            column,
+        }
+    }
+
+    /// The full name of the function, if available.
+    pub fn function_name(&self) -> Option<&str> {
+        self.function_name.as_deref()
+    }
+
+    /// The path to the original source file, if available.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// The line in the original source file, if available.
+    pub fn line(&self) -> Option<u32> {
+        self.line
+    }
+
+    /// The column in the original source file, if available.
+    pub fn column(&self) -> Option<u32> {
+        self.column
+    }
+}
+
+/// A single frame of a guest backtrace.
+#[cfg(feature = "alloc")]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BacktraceFrame {
+    program_counter: u32,
+    symbols: Vec<BacktraceSymbol>,
+}
+
+#[cfg(feature = "alloc")]
+impl BacktraceFrame {
+    #[doc(hidden)]
+    pub fn new(program_counter: u32, symbols: Vec<BacktraceSymbol>) -> Self {
+        BacktraceFrame { program_counter, symbols }
+    }
+
+    /// The index of the instruction this frame is at.
+    ///
+    /// For every frame except the very first one this is the index of the call instruction.
+    pub fn program_counter(&self) -> u32 {
+        self.program_counter
+    }
+
+    /// The symbols associated with this frame, starting with the innermost inlined function.
+    ///
+    /// Will be empty if the program doesn't have any debug info.
+    pub fn symbols(&self) -> &[BacktraceSymbol] {
+        &self.symbols
+    }
+}
+
+/// A backtrace of the guest program, captured when the execution trapped.
+///
+/// Since the guest program doesn't keep any frame pointers this is recovered on a best-effort
+/// basis by scanning the stack for return addresses, so it might contain spurious frames.
+#[cfg(feature = "alloc")]
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Backtrace {
+    frames: Vec<BacktraceFrame>,
+}
+
+#[cfg(feature = "alloc")]
+impl Backtrace {
+    #[doc(hidden)]
+    pub fn new(frames: Vec<BacktraceFrame>) -> Self {
+        Backtrace { frames }
+    }
+
+    /// The frames of this backtrace, starting with the innermost one.
+    pub fn frames(&self) -> &[BacktraceFrame] {
+        &self.frames
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl core::fmt::Display for Backtrace {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        for (nth_frame, frame) in self.frames.iter().enumerate() {
+            if frame.symbols.is_empty() {
+                writeln!(fmt, "{nth_frame:>4}: #{} - <unknown>", frame.program_counter)?;
+                continue;
+            }
+
+            for (nth_symbol, symbol) in frame.symbols.iter().enumerate() {
+                if nth_symbol == 0 {
+                    write!(fmt, "{nth_frame:>4}: #{} - ", frame.program_counter)?;
+                } else {
+                    fmt.write_str("        inlined into ")?;
+                }
+
+                writeln!(fmt, "{}", symbol.function_name().unwrap_or("<unknown>"))?;
+                if let Some(path) = symbol.path() {
+                    write!(fmt, "          at {path}")?;
+                    if let Some(line) = symbol.line {
+                        write!(fmt, ":{line}")?;
+                        if let Some(column) = symbol.column {
+                            write!(fmt, ":{column}")?;
+                        }
+                    }
+                    writeln!(fmt)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Trap {
-    _private: (),
+    kind: TrapKind,
+    program_counter: Option<u32>,
+    #[cfg(feature = "alloc")]
+    backtrace: Option<Box<Backtrace>>,
+    #[cfg(feature = "std")]
+    error: Option<Box<dyn std::error::Error + Send + Sync>>,
+}
+
+impl Trap {
+    /// Creates a new trap of the given kind.
+    pub const fn new(kind: TrapKind) -> Self {
+        Trap {
+            kind,
+            program_counter: None,
+            #[cfg(feature = "alloc")]
+            backtrace: None,
+            #[cfg(feature = "std")]
+            error: None,
+        }
+    }
+
+    /// Creates a new trap which wraps an arbitrary user-defined error.
+    ///
+    /// This can be returned from a host function to abort the execution, and the error
+    /// can be then retrieved from the trap returned by the call which executed the program.
+    #[cfg(feature = "std")]
+    pub fn from_error(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+        Trap {
+            error: Some(error.into()),
+            ..Trap::new(TrapKind::HostFunctionError)
+        }
+    }
+
+    /// Returns the user-defined error this trap was created with, if any.
+    #[cfg(feature = "std")]
+    pub fn error(&self) -> Option<&(dyn std::error::Error + Send + Sync + 'static)> {
+        self.error.as_deref()
+    }
+
+    /// Consumes the trap and returns the user-defined error it was created with, if any.
+    #[cfg(feature = "std")]
+    pub fn into_error(self) -> Option<Box<dyn std::error::Error + Send + Sync>> {
+        self.error
+    }
+
+    /// Returns a reference to the user-defined error this trap was created with, if it's of type `E`.
+    #[cfg(feature = "std")]
+    pub fn downcast_ref<E>(&self) -> Option<&E>
+    where
+        E: std::error::Error + 'static,
+    {
+        self.error()?.downcast_ref()
+    }
+
+    /// Returns the reason why the execution trapped.
+    pub fn kind(&self) -> TrapKind {
+        self.kind
+    }
+
+    /// Returns the index of the instruction at which the execution trapped, if known.
+    pub fn program_counter(&self) -> Option<u32> {
+        self.program_counter
+    }
+
+    #[doc(hidden)]
+    pub fn with_kind(mut self, kind: TrapKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    #[doc(hidden)]
+    pub fn with_program_counter(mut self, program_counter: Option<u32>) -> Self {
+        self.program_counter = program_counter;
+        self
+    }
+
+    /// Returns the backtrace of the guest program at the point where the execution trapped, if available.
+    #[cfg(feature = "alloc")]
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        self.backtrace.as_deref()
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "alloc")]
+    pub fn with_backtrace(mut self, backtrace: Backtrace) -> Self {
+        self.backtrace = Some(Box::new(backtrace));
+        self
+    }
+}
+
+impl From<TrapKind> for Trap {
+    fn from(kind: TrapKind) -> Self {
+        Trap::new(kind)
+    }
 }
 
 impl core::fmt::Display for Trap {
     fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
-        // TODO: We should print out the exact reason for the trap.
-        fmt.write_str("execution trapped")
+        fmt.write_str("execution trapped")?;
+        if self.kind != TrapKind::Unknown {
+            write!(fmt, ": {}", self.kind)?;
+        }
+
+        if let Some(program_counter) = self.program_counter {
+            write!(fmt, " (at instruction #{program_counter})")?;
+        }
+
+        #[cfg(feature = "std")]
+        if let Some(ref error) = self.error {
+            write!(fmt, ": {error}")?;
+        }
+
+        Ok(())
     }
 }
 
 #[cfg(feature = "std")]
-impl std::error::Error for Trap {}
+impl std::error::Error for Trap {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self.error {
+            Some(ref error) => Some(&**error),
+            None => None,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum ExecutionError<T> {
     Trap(Trap),
     Error(T),
     OutOfGas,
+
+    /// The execution was interrupted, either explicitly or because its deadline has passed.
+    Interrupted,
 }
 
 impl<T> From<T> for ExecutionError<T> {
@@ -35,6 +329,7 @@ where
             ExecutionError::Trap(trap) => trap.fmt(fmt),
             ExecutionError::Error(error) => error.fmt(fmt),
             ExecutionError::OutOfGas => fmt.write_str("out of gas"),
+            ExecutionError::Interrupted => fmt.write_str("execution was interrupted"),
         }
     }
 }
@@ -6,6 +6,7 @@ pub struct GuestProgramInit<'a> {
     rw_data: &'a [u8],
     bss_size: u32,
     stack_size: u32,
+    max_heap_size: u32,
 }
 
 impl<'a> Default for GuestProgramInit<'a> {
@@ -21,6 +22,7 @@ impl<'a> GuestProgramInit<'a> {
             rw_data: &[],
             bss_size: 0,
             stack_size: 0,
+            max_heap_size: 0,
         }
     }
 
@@ -60,6 +62,15 @@ impl<'a> GuestProgramInit<'a> {
         self
     }
 
+    pub fn max_heap_size(self) -> u32 {
+        self.max_heap_size
+    }
+
+    pub fn with_max_heap_size(mut self, size: u32) -> Self {
+        self.max_heap_size = size;
+        self
+    }
+
     pub fn memory_config(&self) -> Result<GuestMemoryConfig, &'static str> {
         GuestMemoryConfig::new(
             self.ro_data.len() as u64,
@@ -35,3 +35,6 @@ pub mod zygote;
 
 /// A special hostcall number set by the *guest* to trigger a trace.
 pub const HOSTCALL_TRACE: u32 = 0x80000000;
+
+/// A special hostcall number set by the *guest* when it runs out of gas with synchronous gas metering.
+pub const HOSTCALL_OUT_OF_GAS: u32 = 0x80000001;
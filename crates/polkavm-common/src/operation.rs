@@ -55,6 +55,61 @@ pub const fn mulhu(lhs: u32, rhs: u32) -> u32 {
     ((lhs as i64).wrapping_mul(rhs as i64) >> 32) as u32
 }
 
+#[inline]
+pub const fn divu64(lhs: u64, rhs: u64) -> u64 {
+    if rhs == 0 {
+        u64::MAX
+    } else {
+        lhs / rhs
+    }
+}
+
+#[inline]
+pub const fn remu64(lhs: u64, rhs: u64) -> u64 {
+    if rhs == 0 {
+        lhs
+    } else {
+        lhs % rhs
+    }
+}
+
+#[inline]
+pub const fn div64(lhs: i64, rhs: i64) -> i64 {
+    if rhs == 0 {
+        -1
+    } else if lhs == i64::MIN && rhs == -1 {
+        lhs
+    } else {
+        lhs / rhs
+    }
+}
+
+#[inline]
+pub const fn rem64(lhs: i64, rhs: i64) -> i64 {
+    if rhs == 0 {
+        lhs
+    } else if lhs == i64::MIN && rhs == -1 {
+        0
+    } else {
+        lhs % rhs
+    }
+}
+
+#[inline]
+pub const fn mulh64(lhs: i64, rhs: i64) -> i64 {
+    ((lhs as i128).wrapping_mul(rhs as i128) >> 64) as i64
+}
+
+#[inline]
+pub const fn mulhsu64(lhs: i64, rhs: u64) -> i64 {
+    ((lhs as i128).wrapping_mul(rhs as i128) >> 64) as i64
+}
+
+#[inline]
+pub const fn mulhu64(lhs: u64, rhs: u64) -> u64 {
+    ((lhs as u128).wrapping_mul(rhs as u128) >> 64) as u64
+}
+
 #[test]
 fn test_div_rem() {
     assert_eq!(divu(10, 2), 5);
@@ -72,4 +127,17 @@ fn test_div_rem() {
     assert_eq!(rem(10, 5), 0);
     assert_eq!(rem(10, 0), 10);
     assert_eq!(rem(i32::MIN, -1), 0);
+
+    assert_eq!(divu64(10, 0), u64::MAX);
+    assert_eq!(div64(i64::MIN, -1), i64::MIN);
+    assert_eq!(remu64(10, 0), 10);
+    assert_eq!(rem64(i64::MIN, -1), 0);
+}
+
+#[test]
+fn test_mul_upper_64() {
+    assert_eq!(mulh64(-1, -1), 0);
+    assert_eq!(mulh64(i64::MIN, 2), -1);
+    assert_eq!(mulhsu64(-1, u64::MAX), -1);
+    assert_eq!(mulhu64(u64::MAX, u64::MAX), u64::MAX - 1);
 }
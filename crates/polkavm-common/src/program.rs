@@ -644,6 +644,10 @@ define_opcodes! {
         store_u8                                 = 71,
         store_u16                                = 69,
         store_u32                                = 22,
+
+        load_i32                                 = 107,
+        load_u64                                 = 108,
+        store_u64                                = 109,
     ]
 
     // Instructions with args: reg, imm, imm
@@ -661,6 +665,8 @@ define_opcodes! {
         store_imm_indirect_u8                    = 26,
         store_imm_indirect_u16                   = 54,
         store_imm_indirect_u32                   = 13,
+
+        store_imm_indirect_u64                   = 110,
     ]
 
     // Instructions with args: reg, reg, imm
@@ -692,12 +698,24 @@ define_opcodes! {
         shift_logical_right_imm_alt              = 72,
         shift_arithmetic_right_imm_alt           = 80,
         shift_logical_left_imm_alt               = 75,
+        rotate_right_imm                         = 95,
+        rotate_right_imm_alt                     = 96,
+        rotate_left_imm_alt                      = 97,
         branch_eq                                = 24,
         branch_not_eq                            = 30,
         branch_less_unsigned                     = 47,
         branch_less_signed                       = 48,
         branch_greater_or_equal_unsigned         = 41,
         branch_greater_or_equal_signed           = 43,
+
+        load_indirect_i32                        = 111,
+        load_indirect_u64                        = 112,
+        store_indirect_u64                       = 113,
+        add_imm_32                               = 114,
+        shift_logical_left_imm_32                = 115,
+        shift_logical_right_imm_32               = 116,
+        shift_arithmetic_right_imm_32            = 117,
+        rotate_right_imm_32                      = 118,
     ]
 
     // Instructions with args: reg, reg, reg
@@ -723,6 +741,32 @@ define_opcodes! {
 
         cmov_if_zero                             = 83,
         cmov_if_not_zero                         = 84,
+
+        and_inverted                             = 86,
+        or_inverted                              = 87,
+        xnor                                     = 88,
+        maximum                                  = 89,
+        maximum_unsigned                         = 90,
+        minimum                                  = 91,
+        minimum_unsigned                         = 92,
+        rotate_left                              = 93,
+        rotate_right                             = 94,
+
+        memcpy                                   = 105,
+        memset                                   = 106,
+
+        add_32                                   = 119,
+        sub_32                                   = 120,
+        mul_32                                   = 121,
+        shift_logical_left_32                    = 122,
+        shift_logical_right_32                   = 123,
+        shift_arithmetic_right_32                = 124,
+        div_unsigned_32                          = 125,
+        div_signed_32                            = 126,
+        rem_unsigned_32                          = 127,
+        rem_signed_32                            = 128,
+        rotate_left_32                           = 129,
+        rotate_right_32                          = 130,
     ]
 
     // Instructions with args: imm
@@ -736,15 +780,64 @@ define_opcodes! {
         store_imm_u8                             = 62,
         store_imm_u16                            = 79,
         store_imm_u32                            = 38,
+
+        store_imm_u64                            = 131,
     ]
 
     // Instructions with args: reg, reg
     [
         move_reg                                 = 82,
+        sbrk                                     = 85,
+        count_leading_zero_bits                  = 98,
+        count_trailing_zero_bits                 = 99,
+        count_set_bits                           = 100,
+        sign_extend_8                            = 101,
+        sign_extend_16                           = 102,
+        zero_extend_16                           = 103,
+        reverse_byte                             = 104,
+
+        count_leading_zero_bits_32               = 132,
+        count_trailing_zero_bits_32              = 133,
+        count_set_bits_32                        = 134,
     ]
 }
 
 impl Opcode {
+    /// Returns whether this opcode is only valid in 64-bit programs.
+    pub fn is_64_bit_only(self) -> bool {
+        matches!(
+            self,
+            Self::load_i32
+                | Self::load_u64
+                | Self::store_u64
+                | Self::store_imm_indirect_u64
+                | Self::load_indirect_i32
+                | Self::load_indirect_u64
+                | Self::store_indirect_u64
+                | Self::add_imm_32
+                | Self::shift_logical_left_imm_32
+                | Self::shift_logical_right_imm_32
+                | Self::shift_arithmetic_right_imm_32
+                | Self::rotate_right_imm_32
+                | Self::add_32
+                | Self::sub_32
+                | Self::mul_32
+                | Self::shift_logical_left_32
+                | Self::shift_logical_right_32
+                | Self::shift_arithmetic_right_32
+                | Self::div_unsigned_32
+                | Self::div_signed_32
+                | Self::rem_unsigned_32
+                | Self::rem_signed_32
+                | Self::rotate_left_32
+                | Self::rotate_right_32
+                | Self::store_imm_u64
+                | Self::count_leading_zero_bits_32
+                | Self::count_trailing_zero_bits_32
+                | Self::count_set_bits_32
+        )
+    }
+
     pub fn starts_new_basic_block(self) -> bool {
         matches!(
             self,
@@ -937,6 +1030,90 @@ impl<'a> InstructionVisitor for core::fmt::Formatter<'a> {
         write!(self, "{d} = {s1} %s {s2}")
     }
 
+    fn add_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "i32 {d} = {s1} + {s2}")
+    }
+
+    fn sub_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "i32 {d} = {s1} - {s2}")
+    }
+
+    fn mul_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "i32 {d} = {s1} * {s2}")
+    }
+
+    fn shift_logical_left_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "i32 {d} = {s1} << {s2}")
+    }
+
+    fn shift_logical_right_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "i32 {d} = {s1} >> {s2}")
+    }
+
+    fn shift_arithmetic_right_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "i32 {d} = {s1} >>a {s2}")
+    }
+
+    fn div_unsigned_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "i32 {d} = {s1} /u {s2}")
+    }
+
+    fn div_signed_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "i32 {d} = {s1} /s {s2}")
+    }
+
+    fn rem_unsigned_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "i32 {d} = {s1} %u {s2}")
+    }
+
+    fn rem_signed_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "i32 {d} = {s1} %s {s2}")
+    }
+
+    fn rotate_left_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "i32 {d} = {s1} <<r {s2}")
+    }
+
+    fn rotate_right_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "i32 {d} = {s1} >>r {s2}")
+    }
+
+    fn and_inverted(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "{d} = {s1} & ~{s2}")
+    }
+
+    fn or_inverted(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "{d} = {s1} | ~{s2}")
+    }
+
+    fn xnor(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "{d} = ~({s1} ^ {s2})")
+    }
+
+    fn maximum(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "{d} = maxs({s1}, {s2})")
+    }
+
+    fn maximum_unsigned(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "{d} = maxu({s1}, {s2})")
+    }
+
+    fn minimum(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "{d} = mins({s1}, {s2})")
+    }
+
+    fn minimum_unsigned(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "{d} = minu({s1}, {s2})")
+    }
+
+    fn rotate_left(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "{d} = {s1} <<r {s2}")
+    }
+
+    fn rotate_right(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        write!(self, "{d} = {s1} >>r {s2}")
+    }
+
     fn set_less_than_unsigned_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
         write!(self, "{d} = {s1} <u 0x{s2:x}")
     }
@@ -977,6 +1154,38 @@ impl<'a> InstructionVisitor for core::fmt::Formatter<'a> {
         write!(self, "{d} = {s1} << {s2}")
     }
 
+    fn rotate_right_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        write!(self, "{d} = {s1} >>r {s2}")
+    }
+
+    fn add_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        write!(self, "i32 {d} = {s1} + {s2}", s2 = s2 as i32)
+    }
+
+    fn shift_logical_left_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        write!(self, "i32 {d} = {s1} << {s2}")
+    }
+
+    fn shift_logical_right_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        write!(self, "i32 {d} = {s1} >> {s2}")
+    }
+
+    fn shift_arithmetic_right_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        write!(self, "i32 {d} = {s1} >>a {s2}")
+    }
+
+    fn rotate_right_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        write!(self, "i32 {d} = {s1} >>r {s2}")
+    }
+
+    fn rotate_right_imm_alt(&mut self, d: Reg, s2: Reg, s1: u32) -> Self::ReturnTy {
+        write!(self, "{d} = 0x{s1:x} >>r {s2}")
+    }
+
+    fn rotate_left_imm_alt(&mut self, d: Reg, s2: Reg, s1: u32) -> Self::ReturnTy {
+        write!(self, "{d} = 0x{s1:x} <<r {s2}")
+    }
+
     fn or_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
         write!(self, "{d} = {s1} | 0x{s2:x}")
     }
@@ -997,6 +1206,50 @@ impl<'a> InstructionVisitor for core::fmt::Formatter<'a> {
         write!(self, "{d} = {s}")
     }
 
+    fn sbrk(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        write!(self, "{d} = sbrk {s}")
+    }
+
+    fn count_leading_zero_bits(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        write!(self, "{d} = clz {s}")
+    }
+
+    fn count_trailing_zero_bits(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        write!(self, "{d} = ctz {s}")
+    }
+
+    fn count_set_bits(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        write!(self, "{d} = cpop {s}")
+    }
+
+    fn count_leading_zero_bits_32(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        write!(self, "i32 {d} = clz {s}")
+    }
+
+    fn count_trailing_zero_bits_32(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        write!(self, "i32 {d} = ctz {s}")
+    }
+
+    fn count_set_bits_32(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        write!(self, "i32 {d} = cpop {s}")
+    }
+
+    fn sign_extend_8(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        write!(self, "{d} = sext.b {s}")
+    }
+
+    fn sign_extend_16(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        write!(self, "{d} = sext.h {s}")
+    }
+
+    fn zero_extend_16(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        write!(self, "{d} = zext.h {s}")
+    }
+
+    fn reverse_byte(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        write!(self, "{d} = reverse {s}")
+    }
+
     fn cmov_if_zero(&mut self, d: Reg, s: Reg, c: Reg) -> Self::ReturnTy {
         write!(self, "{d} = ({c} == 0) ? {s} : 0")
     }
@@ -1005,6 +1258,14 @@ impl<'a> InstructionVisitor for core::fmt::Formatter<'a> {
         write!(self, "{d} = ({c} != 0) ? {s} : 0")
     }
 
+    fn memcpy(&mut self, dst: Reg, src: Reg, count: Reg) -> Self::ReturnTy {
+        write!(self, "memcpy [{dst}], [{src}], {count}")
+    }
+
+    fn memset(&mut self, dst: Reg, value: Reg, count: Reg) -> Self::ReturnTy {
+        write!(self, "memset [{dst}], {value}, {count}")
+    }
+
     fn add_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
         if (s2 as i32) < 0 && (s2 as i32) > -4096 {
             write!(self, "{d} = {s1} - {s2}", s2 = -(s2 as i32))
@@ -1033,6 +1294,10 @@ impl<'a> InstructionVisitor for core::fmt::Formatter<'a> {
         write!(self, "u32 [{base} + {offset}] = {value}")
     }
 
+    fn store_imm_indirect_u64(&mut self, base: Reg, offset: u32, value: u32) -> Self::ReturnTy {
+        write!(self, "u64 [{base} + {offset}] = {value}", value = value as i32)
+    }
+
     fn store_indirect_u8(&mut self, src: Reg, base: Reg, offset: u32) -> Self::ReturnTy {
         if offset != 0 {
             write!(self, "u8 [{base} + {offset}] = {src}")
@@ -1057,6 +1322,14 @@ impl<'a> InstructionVisitor for core::fmt::Formatter<'a> {
         }
     }
 
+    fn store_indirect_u64(&mut self, src: Reg, base: Reg, offset: u32) -> Self::ReturnTy {
+        if offset != 0 {
+            write!(self, "u64 [{base} + {offset}] = {src}")
+        } else {
+            write!(self, "u64 [{base}] = {src}")
+        }
+    }
+
     fn store_imm_u8(&mut self, value: u32, offset: u32) -> Self::ReturnTy {
         write!(self, "u8 [0x{offset:x}] = {value}")
     }
@@ -1069,6 +1342,10 @@ impl<'a> InstructionVisitor for core::fmt::Formatter<'a> {
         write!(self, "u32 [0x{offset:x}] = {value}")
     }
 
+    fn store_imm_u64(&mut self, value: u32, offset: u32) -> Self::ReturnTy {
+        write!(self, "u64 [0x{offset:x}] = {value}", value = value as i32)
+    }
+
     fn store_u8(&mut self, src: Reg, offset: u32) -> Self::ReturnTy {
         write!(self, "u8 [0x{offset:x}] = {src}")
     }
@@ -1081,6 +1358,10 @@ impl<'a> InstructionVisitor for core::fmt::Formatter<'a> {
         write!(self, "u32 [0x{offset:x}] = {src}")
     }
 
+    fn store_u64(&mut self, src: Reg, offset: u32) -> Self::ReturnTy {
+        write!(self, "u64 [0x{offset:x}] = {src}")
+    }
+
     fn load_indirect_u8(&mut self, dst: Reg, base: Reg, offset: u32) -> Self::ReturnTy {
         if offset != 0 {
             write!(self, "{} = u8 [{} + {}]", dst, base, offset)
@@ -1121,6 +1402,22 @@ impl<'a> InstructionVisitor for core::fmt::Formatter<'a> {
         }
     }
 
+    fn load_indirect_i32(&mut self, dst: Reg, base: Reg, offset: u32) -> Self::ReturnTy {
+        if offset != 0 {
+            write!(self, "{} = i32 [{} + {}]", dst, base, offset)
+        } else {
+            write!(self, "{} = i32 [{}]", dst, base)
+        }
+    }
+
+    fn load_indirect_u64(&mut self, dst: Reg, base: Reg, offset: u32) -> Self::ReturnTy {
+        if offset != 0 {
+            write!(self, "{} = u64 [{} + {}]", dst, base, offset)
+        } else {
+            write!(self, "{} = u64 [{}]", dst, base)
+        }
+    }
+
     fn load_u8(&mut self, dst: Reg, offset: u32) -> Self::ReturnTy {
         write!(self, "{} = u8 [0x{:x}]", dst, offset)
     }
@@ -1141,6 +1438,14 @@ impl<'a> InstructionVisitor for core::fmt::Formatter<'a> {
         write!(self, "{} = u32 [0x{:x}]", dst, offset)
     }
 
+    fn load_i32(&mut self, dst: Reg, offset: u32) -> Self::ReturnTy {
+        write!(self, "{} = i32 [0x{:x}]", dst, offset)
+    }
+
+    fn load_u64(&mut self, dst: Reg, offset: u32) -> Self::ReturnTy {
+        write!(self, "{} = u64 [0x{:x}]", dst, offset)
+    }
+
     fn branch_less_unsigned(&mut self, s1: Reg, s2: Reg, imm: u32) -> Self::ReturnTy {
         write!(self, "if {} <u {}: jump @{:x}", s1, s2, imm)
     }
@@ -1404,12 +1709,46 @@ impl<'a> ProgramImport<'a> {
     }
 }
 
+/// A marker used in place of a single return type for functions which return multiple values.
+///
+/// It's followed by the number of return values and their types.
+pub(crate) const MULTIPLE_RETURN_VALUES_MARKER: u8 = 0xff;
+
+#[derive(Clone)]
+struct ExternTyIter<'r> {
+    position: usize,
+    length: usize,
+    types: &'r [Option<ExternTy>; crate::abi::VM_MAXIMUM_EXTERN_ARG_COUNT],
+}
+
+impl<'r> Iterator for ExternTyIter<'r> {
+    type Item = ExternTy;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.length {
+            None
+        } else {
+            let ty = self.types[self.position].unwrap();
+            self.position += 1;
+            Some(ty)
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.length - self.position;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'r> ExactSizeIterator for ExternTyIter<'r> {}
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct ExternFnPrototype<'a> {
     name: CowString<'a>,
     arg_count: u32,
     args: [Option<ExternTy>; crate::abi::VM_MAXIMUM_EXTERN_ARG_COUNT],
-    return_ty: Option<ExternTy>,
+    return_count: u32,
+    returns: [Option<ExternTy>; crate::abi::VM_MAXIMUM_EXTERN_ARG_COUNT],
 }
 
 impl<'a> ExternFnPrototype<'a> {
@@ -1418,43 +1757,29 @@ impl<'a> ExternFnPrototype<'a> {
     }
 
     pub fn args(&'_ self) -> impl ExactSizeIterator<Item = ExternTy> + Clone + '_ {
-        #[derive(Clone)]
-        struct ArgIter<'r> {
-            position: usize,
-            length: usize,
-            args: &'r [Option<ExternTy>; crate::abi::VM_MAXIMUM_EXTERN_ARG_COUNT],
-        }
-
-        impl<'r> Iterator for ArgIter<'r> {
-            type Item = ExternTy;
-
-            fn next(&mut self) -> Option<Self::Item> {
-                if self.position >= self.length {
-                    None
-                } else {
-                    let ty = self.args[self.position].unwrap();
-                    self.position += 1;
-                    Some(ty)
-                }
-            }
-
-            fn size_hint(&self) -> (usize, Option<usize>) {
-                let remaining = self.length - self.position;
-                (remaining, Some(remaining))
-            }
+        ExternTyIter {
+            position: 0,
+            length: self.arg_count as usize,
+            types: &self.args,
         }
+    }
 
-        impl<'r> ExactSizeIterator for ArgIter<'r> {}
-
-        ArgIter {
+    /// Returns the types of the values returned by this function, in the order in which they're placed in the registers.
+    pub fn returns(&'_ self) -> impl ExactSizeIterator<Item = ExternTy> + Clone + '_ {
+        ExternTyIter {
             position: 0,
-            length: self.arg_count as usize,
-            args: &self.args,
+            length: self.return_count as usize,
+            types: &self.returns,
         }
     }
 
+    /// Returns the type of the return value, if this function returns exactly one value.
     pub fn return_ty(&self) -> Option<ExternTy> {
-        self.return_ty
+        if self.return_count == 1 {
+            self.returns[0]
+        } else {
+            None
+        }
     }
 
     #[cfg(feature = "alloc")]
@@ -1463,7 +1788,8 @@ impl<'a> ExternFnPrototype<'a> {
             name: self.name.into_owned(),
             arg_count: self.arg_count,
             args: self.args,
-            return_ty: self.return_ty,
+            return_count: self.return_count,
+            returns: self.returns,
         }
     }
 }
@@ -1472,6 +1798,7 @@ impl<'a> ExternFnPrototype<'a> {
 #[derive(Clone, Default)]
 pub struct ProgramBlob<'a> {
     blob: CowBytes<'a>,
+    is_64_bit: bool,
 
     bss_size: u32,
     stack_size: u32,
@@ -1632,13 +1959,32 @@ impl<'a> Reader<'a> {
             args[nth_arg as usize] = Some(ty);
         }
 
-        let return_ty = match self.read_byte()? {
-            0 => None,
+        let mut returns: [Option<ExternTy>; crate::abi::VM_MAXIMUM_EXTERN_ARG_COUNT] = [None; crate::abi::VM_MAXIMUM_EXTERN_ARG_COUNT];
+        let return_count = match self.read_byte()? {
+            0 => 0,
+            MULTIPLE_RETURN_VALUES_MARKER => {
+                let return_count = self.read_varint()?;
+                if return_count > crate::abi::VM_MAXIMUM_EXTERN_ARG_COUNT as u32 {
+                    return Err(ProgramParseError(ProgramParseErrorKind::Other(
+                        "found a function prototype which returns more than the maximum allowed number of values",
+                    )));
+                }
+
+                for nth_return in 0..return_count {
+                    let ty = ExternTy::try_deserialize(self.read_byte()?).ok_or(ProgramParseError(ProgramParseErrorKind::Other(
+                        "found a function prototype with an unrecognized return type",
+                    )))?;
+                    returns[nth_return as usize] = Some(ty);
+                }
+
+                return_count
+            }
             return_ty => {
                 let ty = ExternTy::try_deserialize(return_ty).ok_or(ProgramParseError(ProgramParseErrorKind::Other(
                     "found a function prototype with an unrecognized return type",
                 )))?;
-                Some(ty)
+                returns[0] = Some(ty);
+                1
             }
         };
 
@@ -1646,7 +1992,8 @@ impl<'a> Reader<'a> {
             name: name.into(),
             arg_count,
             args,
-            return_ty,
+            return_count,
+            returns,
         })
     }
 }
@@ -1681,11 +2028,15 @@ impl<'a> ProgramBlob<'a> {
         };
 
         let blob_version = reader.read_byte()?;
-        if blob_version != BLOB_VERSION_V1 {
-            return Err(ProgramParseError(ProgramParseErrorKind::UnsupportedVersion {
-                version: blob_version,
-            }));
-        }
+        program.is_64_bit = match blob_version {
+            BLOB_VERSION_V1_32 => false,
+            BLOB_VERSION_V1_64 => true,
+            _ => {
+                return Err(ProgramParseError(ProgramParseErrorKind::UnsupportedVersion {
+                    version: blob_version,
+                }))
+            }
+        };
 
         let mut section = reader.read_byte()?;
         if section == SECTION_MEMORY_CONFIG {
@@ -1769,6 +2120,11 @@ impl<'a> ProgramBlob<'a> {
         &self.blob[self.rw_data.clone()]
     }
 
+    /// Returns whether the program targets the 64-bit variant of the instruction set.
+    pub fn is_64_bit(&self) -> bool {
+        self.is_64_bit
+    }
+
     /// Returns the initial size of the BSS section.
     pub fn bss_size(&self) -> u32 {
         self.bss_size
@@ -1802,6 +2158,24 @@ impl<'a> ProgramBlob<'a> {
         self.basic_block_count
     }
 
+    /// Returns whether the program contains any debug info.
+    pub fn has_debug_info(&self) -> bool {
+        !self.debug_strings.is_empty() || !self.debug_line_program_ranges.is_empty() || !self.debug_line_programs.is_empty()
+    }
+
+    /// Returns the raw contents of the optional debug info sections, along with their section IDs.
+    #[cfg(feature = "alloc")]
+    pub(crate) fn raw_debug_sections(&self) -> [(u8, &[u8]); 3] {
+        [
+            (SECTION_OPT_DEBUG_STRINGS, &self.blob[self.debug_strings.clone()]),
+            (SECTION_OPT_DEBUG_LINE_PROGRAMS, &self.blob[self.debug_line_programs.clone()]),
+            (
+                SECTION_OPT_DEBUG_LINE_PROGRAM_RANGES,
+                &self.blob[self.debug_line_program_ranges.clone()],
+            ),
+        ]
+    }
+
     fn get_section_reader(&self, range: Range<usize>) -> Reader {
         Reader {
             blob: &self.blob[range.start..range.end],
@@ -2063,6 +2437,7 @@ impl<'a> ProgramBlob<'a> {
     pub fn into_owned(self) -> ProgramBlob<'static> {
         ProgramBlob {
             blob: self.blob.into_owned(),
+            is_64_bit: self.is_64_bit,
 
             bss_size: self.bss_size,
             stack_size: self.stack_size,
@@ -2562,7 +2937,8 @@ pub const SECTION_OPT_DEBUG_LINE_PROGRAMS: u8 = 129;
 pub const SECTION_OPT_DEBUG_LINE_PROGRAM_RANGES: u8 = 130;
 pub const SECTION_END_OF_FILE: u8 = 0;
 
-pub const BLOB_VERSION_V1: u8 = 1;
+pub const BLOB_VERSION_V1_32: u8 = 1;
+pub const BLOB_VERSION_V1_64: u8 = 2;
 
 pub const VERSION_DEBUG_LINE_PROGRAM_V1: u8 = 1;
 
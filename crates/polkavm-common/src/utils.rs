@@ -260,6 +260,16 @@ pub trait Access<'a> {
 
     fn get_reg(&self, reg: Reg) -> u32;
     fn set_reg(&mut self, reg: Reg, value: u32);
+
+    /// Gets the full value of a register, for programs which target the 64-bit variant of the instruction set.
+    fn get_reg64(&self, reg: Reg) -> u64 {
+        u64::from(self.get_reg(reg))
+    }
+
+    /// Sets the full value of a register, for programs which target the 64-bit variant of the instruction set.
+    fn set_reg64(&mut self, reg: Reg, value: u64) {
+        self.set_reg(reg, value as u32);
+    }
     fn read_memory_into_slice<'slice, T>(&self, address: u32, buffer: &'slice mut T) -> Result<&'slice mut [u8], Self::Error>
     where
         T: ?Sized + AsUninitSliceMut;
@@ -267,6 +277,9 @@ pub trait Access<'a> {
     fn program_counter(&self) -> Option<u32>;
     fn native_program_counter(&self) -> Option<u64>;
 
+    /// Returns the current size of the heap, including any memory which was allocated with `sbrk`.
+    fn heap_size(&self) -> u32;
+
     /// Gets the amount of gas remaining, or `None` if gas metering is not enabled for this instance.
     ///
     /// Note that this being zero doesn't necessarily mean that the execution ran out of gas,
@@ -275,6 +288,19 @@ pub trait Access<'a> {
 
     fn consume_gas(&mut self, gas: u64);
 
+    /// Adds the given amount of gas to the gas remaining.
+    ///
+    /// The default implementation goes through [`Access::gas_remaining`] and [`Access::set_gas`], so it can't pay off
+    /// any gas which was consumed beyond zero; backends which keep track of that should override it.
+    fn add_gas(&mut self, gas: u64) {
+        if let Some(gas_remaining) = self.gas_remaining() {
+            self.set_gas(Gas::new(gas_remaining.get().saturating_add(gas)).unwrap_or(Gas::MAX));
+        }
+    }
+
+    /// Sets the amount of gas remaining.
+    fn set_gas(&mut self, gas: Gas);
+
     #[cfg(feature = "alloc")]
     fn read_memory_into_new_vec(&self, address: u32, length: u32) -> Result<Vec<u8>, Self::Error> {
         let mut buffer = Vec::new();
@@ -296,6 +322,28 @@ pub trait Access<'a> {
 
         Ok(buffer)
     }
+
+    /// Calls the given closure with a view of the memory at the given address.
+    ///
+    /// By default this copies the memory into a temporary buffer; backends which can access
+    /// the memory directly should override this to avoid the copy.
+    #[cfg(feature = "alloc")]
+    fn with_memory<R>(&self, address: u32, length: u32, callback: impl FnOnce(&[u8]) -> R) -> Result<R, Self::Error> {
+        let buffer = self.read_memory_into_new_vec(address, length)?;
+        Ok(callback(&buffer))
+    }
+
+    /// Calls the given closure with a mutable view of the memory at the given address.
+    ///
+    /// By default this copies the memory into a temporary buffer and writes it back afterwards; backends
+    /// which can access the memory directly should override this to avoid the copies.
+    #[cfg(feature = "alloc")]
+    fn with_memory_mut<R>(&mut self, address: u32, length: u32, callback: impl FnOnce(&mut [u8]) -> R) -> Result<R, Self::Error> {
+        let mut buffer = self.read_memory_into_new_vec(address, length)?;
+        let result = callback(&mut buffer);
+        self.write_memory(address, &buffer)?;
+        Ok(result)
+    }
 }
 
 // Copied from `MaybeUninit::slice_assume_init_mut`.
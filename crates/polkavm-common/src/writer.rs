@@ -1,10 +1,11 @@
 use crate::elf::FnMetadata;
-use crate::program::{self, Instruction};
+use crate::program::{self, ExternFnPrototype, Instruction, ProgramBlob, ProgramParseError};
 use alloc::vec::Vec;
 use core::ops::Range;
 
 #[derive(Default)]
 pub struct ProgramBlobBuilder {
+    is_64_bit: bool,
     bss_size: u32,
     stack_size: u32,
     ro_data: Vec<u8>,
@@ -23,6 +24,54 @@ impl ProgramBlobBuilder {
         Self::default()
     }
 
+    /// Creates a new builder for a program targeting the 64-bit variant of the instruction set.
+    pub fn new_64bit() -> Self {
+        Self {
+            is_64_bit: true,
+            ..Self::default()
+        }
+    }
+
+    /// Creates a new builder with the same contents as the given program blob, including its debug info.
+    pub fn from_blob(blob: &ProgramBlob) -> Result<Self, ProgramParseError> {
+        fn to_metadata(prototype: &ExternFnPrototype) -> FnMetadata {
+            let args: Vec<_> = prototype.args().collect();
+            let returns: Vec<_> = prototype.returns().collect();
+            FnMetadata::new_with_returns(prototype.name(), &args, &returns)
+        }
+
+        let mut builder = if blob.is_64_bit() { Self::new_64bit() } else { Self::new() };
+        builder.set_bss_size(blob.bss_size());
+        builder.set_stack_size(blob.stack_size());
+        builder.set_ro_data(blob.ro_data().to_vec());
+        builder.set_rw_data(blob.rw_data().to_vec());
+
+        for import in blob.imports() {
+            let import = import?;
+            builder.add_import(import.index(), &to_metadata(import.prototype()));
+        }
+
+        for export in blob.exports() {
+            let export = export?;
+            builder.add_export(export.address(), &to_metadata(export.prototype()));
+        }
+
+        let jump_table = blob.jump_table().collect::<Result<Vec<_>, _>>()?;
+        builder.set_jump_table(&jump_table);
+
+        builder.code = blob.code().to_vec();
+        builder.instruction_count = blob.instruction_count();
+        builder.basic_block_count = blob.basic_block_count();
+
+        for (section, contents) in blob.raw_debug_sections() {
+            if !contents.is_empty() {
+                builder.add_custom_section(section, contents.to_vec());
+            }
+        }
+
+        Ok(builder)
+    }
+
     pub fn set_bss_size(&mut self, size: u32) {
         self.bss_size = size;
     }
@@ -79,7 +128,11 @@ impl ProgramBlobBuilder {
         let mut writer = Writer::new(&mut output);
 
         writer.push_raw_bytes(&program::BLOB_MAGIC);
-        writer.push_byte(program::BLOB_VERSION_V1);
+        writer.push_byte(if self.is_64_bit {
+            program::BLOB_VERSION_V1_64
+        } else {
+            program::BLOB_VERSION_V1_32
+        });
 
         if self.bss_size > 0 || self.stack_size > 0 {
             writer.push_section_inplace(program::SECTION_MEMORY_CONFIG, |writer| {
@@ -202,7 +255,16 @@ impl<'a> Writer<'a> {
         for arg_ty in meta.args() {
             self.push_byte(arg_ty as u8);
         }
-        self.push_byte(meta.return_ty().map_or(0, |ty| ty as u8));
+        match meta.returns().count() {
+            0 | 1 => self.push_byte(meta.return_ty().map_or(0, |ty| ty as u8)),
+            count => {
+                self.push_byte(crate::program::MULTIPLE_RETURN_VALUES_MARKER);
+                self.push_varint(count as u32);
+                for return_ty in meta.returns() {
+                    self.push_byte(return_ty as u8);
+                }
+            }
+        }
     }
 
     pub fn len(&self) -> usize {
@@ -213,3 +275,55 @@ impl<'a> Writer<'a> {
         self.buffer.is_empty()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ProgramBlobBuilder;
+    use crate::elf::FnMetadata;
+    use crate::program::{self, asm, ExternTy, ProgramBlob, Reg};
+    use alloc::vec;
+
+    #[test]
+    fn from_blob_round_trips() {
+        for is_64_bit in [false, true] {
+            let mut builder = if is_64_bit {
+                ProgramBlobBuilder::new_64bit()
+            } else {
+                ProgramBlobBuilder::new()
+            };
+
+            builder.set_bss_size(0x2000);
+            builder.set_stack_size(0x1000);
+            builder.set_ro_data(vec![1, 2, 3, 4]);
+            builder.set_rw_data(vec![5, 6, 7]);
+            builder.add_import(0, &FnMetadata::new("import_a", &[ExternTy::I32], None));
+            builder.add_import(2, &FnMetadata::new("import_b", &[], Some(ExternTy::I64)));
+            builder.add_export(
+                0,
+                &FnMetadata::new("export_a", &[ExternTy::I32, ExternTy::I32], Some(ExternTy::I32)),
+            );
+            builder.add_export(1, &FnMetadata::new("export_b", &[], None));
+            builder.set_jump_table(&[1, 0]);
+            builder.set_code(&[
+                asm::add(Reg::A0, Reg::A0, Reg::A1),
+                asm::ecalli(2),
+                asm::ret(),
+                asm::ecalli(0),
+                asm::ret(),
+            ]);
+            builder.add_custom_section(program::SECTION_OPT_DEBUG_STRINGS, vec![0xaa, 0xbb]);
+
+            let raw_blob = builder.into_vec();
+            let blob = ProgramBlob::parse(&raw_blob[..]).unwrap();
+            let raw_blob_roundtrip = ProgramBlobBuilder::from_blob(&blob).unwrap().into_vec();
+            assert_eq!(raw_blob_roundtrip, raw_blob);
+
+            let blob_roundtrip = ProgramBlob::parse(raw_blob_roundtrip).unwrap();
+            assert_eq!(blob_roundtrip.is_64_bit(), is_64_bit);
+            assert_eq!(blob_roundtrip.instruction_count(), 5);
+            assert_eq!(blob_roundtrip.basic_block_count(), blob.basic_block_count());
+            assert_eq!(blob_roundtrip.imports().count(), 2);
+            assert_eq!(blob_roundtrip.exports().count(), 2);
+        }
+    }
+}
@@ -54,10 +54,11 @@ macro_rules! define_address_table {
 
 // These are the addresses exported from the zygote.
 define_address_table! {
-    syscall_hostcall: unsafe extern "C" fn(u32),
+    syscall_hostcall: unsafe extern "C" fn(u32, u64),
     syscall_trap: unsafe extern "C" fn() -> !,
     syscall_return: unsafe extern "C" fn() -> !,
     syscall_trace: unsafe extern "C" fn(u32, u64),
+    syscall_sbrk: unsafe extern "C" fn(u32) -> u32,
 }
 
 /// The address where the native code starts inside of the VM.
@@ -76,6 +77,10 @@ pub const VM_ADDR_JUMP_TABLE_RETURN_TO_HOST: u64 = VM_ADDR_JUMP_TABLE + ((crate:
 /// A special hostcall number set by the *host* to signal that the guest should stop executing the program.
 pub const HOSTCALL_ABORT_EXECUTION: u32 = !0;
 
+/// A special hostcall number set by the *host* to signal that the guest should make a nested call
+/// into the program from within the current hostcall, jumping to the address in `rpc_address`.
+pub const HOSTCALL_NESTED_CALL: u32 = !0 - 1;
+
 /// A sentinel value to indicate that the instruction counter is not available.
 pub const SANDBOX_EMPTY_NTH_INSTRUCTION: u32 = !0;
 
@@ -101,7 +106,7 @@ pub const VM_ADDR_NATIVE_STACK_HIGH: u64 = VM_ADDR_NATIVE_STACK_LOW + VM_ADDR_NA
 ///
 /// This does *not* affect the VM ABI and can be changed at will,
 /// but should be high enough that it's never hit.
-pub const VM_COMPILER_MAXIMUM_INSTRUCTION_LENGTH: u32 = 53;
+pub const VM_COMPILER_MAXIMUM_INSTRUCTION_LENGTH: u32 = 66;
 
 /// The maximum number of native code bytes that can be emitted as an epilogue.
 ///
@@ -110,9 +115,12 @@ pub const VM_COMPILER_MAXIMUM_INSTRUCTION_LENGTH: u32 = 53;
 pub const VM_COMPILER_MAXIMUM_EPILOGUE_LENGTH: u32 = 1024 * 1024;
 
 /// The maximum number of bytes the jump table can be.
+///
+/// The jump table is padded to the native page size, so this includes some extra space for that.
 const VM_SANDBOX_MAXIMUM_JUMP_TABLE_SIZE: u64 = (crate::abi::VM_MAXIMUM_INSTRUCTION_COUNT as u64 + 1)
     * core::mem::size_of::<u64>() as u64
-    * crate::abi::VM_CODE_ADDRESS_ALIGNMENT as u64;
+    * crate::abi::VM_CODE_ADDRESS_ALIGNMENT as u64
+    + crate::abi::VM_PAGE_SIZE as u64;
 
 /// The maximum number of bytes the jump table can span in virtual memory.
 pub const VM_SANDBOX_MAXIMUM_JUMP_TABLE_VIRTUAL_SIZE: u64 = 0x100000000 * core::mem::size_of::<u64>() as u64;
@@ -127,6 +135,7 @@ pub struct SandboxMemoryConfig {
     guest_config: GuestMemoryConfig,
     code_size: u32,
     jump_table_size: u32,
+    max_heap_size: u32,
 }
 
 impl core::ops::Deref for SandboxMemoryConfig {
@@ -152,6 +161,7 @@ impl SandboxMemoryConfig {
             guest_config: GuestMemoryConfig::empty(),
             code_size: 0,
             jump_table_size: 0,
+            max_heap_size: 0,
         }
     }
 
@@ -205,6 +215,29 @@ impl SandboxMemoryConfig {
 
         Ok(())
     }
+
+    /// The maximum size to which the heap can grow through `sbrk`, including the read-write data and the BSS section.
+    #[inline]
+    pub const fn max_heap_size(&self) -> u32 {
+        self.max_heap_size
+    }
+
+    #[inline]
+    pub fn set_max_heap_size(&mut self, max_heap_size: u32) {
+        self.max_heap_size = max_heap_size;
+    }
+
+    /// The address past which the heap cannot grow any further.
+    #[inline]
+    pub const fn max_heap_top(&self) -> u64 {
+        let heap_size = if self.max_heap_size > self.guest_config.heap_size() {
+            self.max_heap_size
+        } else {
+            self.guest_config.heap_size()
+        };
+
+        self.guest_config.heap_address() as u64 + heap_size as u64
+    }
 }
 
 /// A flag which will trigger the sandbox to reload its program before execution.
@@ -216,6 +249,9 @@ pub const VM_RPC_FLAG_RESET_MEMORY_AFTER_EXECUTION: u32 = 1 << 1;
 /// A flag which will trigger the sandbox to unload its program after execution.
 pub const VM_RPC_FLAG_CLEAR_PROGRAM_AFTER_EXECUTION: u32 = 1 << 2;
 
+/// A flag which will trigger the sandbox to grow or shrink its heap to `new_heap_top` before execution.
+pub const VM_RPC_FLAG_SET_HEAP_TOP: u32 = 1 << 3;
+
 #[repr(C)]
 pub struct VmInit {
     pub stack_address: AtomicU64,
@@ -256,13 +292,13 @@ pub struct VmCtxSyscall {
     /// The hostcall number that was triggered.
     pub hostcall: UnsafeCell<u32>,
     /// A dump of all of the registers of the VM.
-    pub regs: UnsafeCell<[u32; REG_COUNT]>,
+    pub regs: UnsafeCell<[u64; REG_COUNT]>,
     /// The number of the instruction just about to be executed.
     ///
     /// Should be treated as empty if equal to `SANDBOX_EMPTY_NTH_INSTRUCTION`.
     pub nth_instruction: UnsafeCell<u32>,
 
-    /// The current RIP. Filled out in case of a trap or during tracing.
+    /// The current RIP. Filled out in case of a trap, a hostcall or during tracing.
     ///
     /// Should be treated as empty if equal to `SANDBOX_EMPTY_NATIVE_PROGRAM_COUNTER`.
     pub rip: UnsafeCell<u64>,
@@ -272,6 +308,8 @@ pub struct VmCtxSyscall {
 pub struct VmCtxCounters {
     pub syscall_wait_loop_start: UnsafeCell<u64>,
     pub syscall_futex_wait: UnsafeCell<u64>,
+    /// The number of basic blocks executed. Only incremented if the module was compiled with basic block counting enabled.
+    pub basic_blocks_executed: UnsafeCell<u64>,
 }
 
 /// The virtual machine context.
@@ -297,6 +335,19 @@ pub struct VmCtx {
     pub new_memory_config: UnsafeCell<SandboxMemoryConfig>,
     /// The new sysreturn trampoline address. Will be applied if the appropriate flag is set.
     pub new_sysreturn_address: UnsafeCell<u64>,
+    /// The current end of the heap, as moved by `sbrk`.
+    pub heap_top: UnsafeCell<u64>,
+    /// The new end of the heap. Will be applied if the appropriate flag is set.
+    pub new_heap_top: UnsafeCell<u64>,
+
+    /// The signal which triggered the last trap, or zero if the trap wasn't triggered by a signal.
+    pub trap_signal: UnsafeCell<u32>,
+    /// Whether the memory access which triggered the last trap was a write. Only valid for `SIGSEGV`.
+    pub trap_is_write: UnsafeCell<u32>,
+    /// The faulting address of the memory access which triggered the last trap. Only valid for `SIGSEGV`.
+    pub trap_address: UnsafeCell<u64>,
+    /// The native general purpose registers at the time of the last trap, indexed by their x86 encoding. Only valid if `trap_signal` is non-zero.
+    pub trap_native_regs: UnsafeCell<[u64; 16]>,
 
     /// Performance counters. Only for debugging.
     pub counters: CacheAligned<VmCtxCounters>,
@@ -339,6 +390,13 @@ impl VmCtx {
             memory_config: UnsafeCell::new(SandboxMemoryConfig::empty()),
             new_memory_config: UnsafeCell::new(SandboxMemoryConfig::empty()),
             new_sysreturn_address: UnsafeCell::new(0),
+            heap_top: UnsafeCell::new(0),
+            new_heap_top: UnsafeCell::new(0),
+
+            trap_signal: UnsafeCell::new(0),
+            trap_is_write: UnsafeCell::new(0),
+            trap_address: UnsafeCell::new(0),
+            trap_native_regs: UnsafeCell::new([0; 16]),
 
             syscall_ffi: CacheAligned(VmCtxSyscall {
                 gas: UnsafeCell::new(0),
@@ -351,6 +409,7 @@ impl VmCtx {
             counters: CacheAligned(VmCtxCounters {
                 syscall_wait_loop_start: UnsafeCell::new(0),
                 syscall_futex_wait: UnsafeCell::new(0),
+                basic_blocks_executed: UnsafeCell::new(0),
             }),
 
             init: VmInit {
@@ -388,7 +447,7 @@ impl VmCtx {
     }
 
     #[inline(always)]
-    pub const fn regs(&self) -> &UnsafeCell<[u32; REG_COUNT]> {
+    pub const fn regs(&self) -> &UnsafeCell<[u64; REG_COUNT]> {
         &self.syscall_ffi.0.regs
     }
 
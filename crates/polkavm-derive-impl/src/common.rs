@@ -1,6 +1,7 @@
 use polkavm_common::abi::VM_MAXIMUM_EXTERN_ARG_COUNT;
 use polkavm_common::elf::FnMetadata;
 use polkavm_common::program::ExternTy;
+use quote::quote;
 use syn::spanned::Spanned;
 
 macro_rules! unsupported {
@@ -186,22 +187,34 @@ pub fn create_fn_prototype(sig: &syn::Signature, bitness: Bitness) -> Result<FnM
         return Err(syn::Error::new(sig.span(), "too many arguments"));
     }
 
-    let parsed_return_ty = match sig.output {
-        syn::ReturnType::Default => None,
-        syn::ReturnType::Type(_, ref ty) => {
-            if let syn::Type::Tuple(syn::TypeTuple { ref elems, .. }) = **ty {
-                if elems.is_empty() {
-                    None
+    let parsed_returns: Vec<SimpleTy> = match tuple_return_types(sig) {
+        Some(types) => {
+            let mut parsed_returns = Vec::new();
+            for ty in types {
+                let Some(ty) = parse_ty(ty) else { unsupported!(ty) };
+                parsed_returns.push(ty);
+            }
+
+            let regs: usize = parsed_returns.iter().map(|&ty| used_regs(ty, bitness)).sum();
+            if regs > polkavm_common::abi::VM_MAXIMUM_EXTERN_ARG_COUNT {
+                return Err(syn::Error::new(sig.output.span(), "too many return values"));
+            }
+
+            parsed_returns
+        }
+        None => match sig.output {
+            syn::ReturnType::Default => Vec::new(),
+            syn::ReturnType::Type(_, ref ty) => {
+                if matches!(**ty, syn::Type::Tuple(syn::TypeTuple { ref elems, .. }) if elems.is_empty()) {
+                    Vec::new()
                 } else {
-                    unsupported!(ty);
-                }
-            } else {
-                match parse_ty(ty) {
-                    Some(ty) => Some(ty),
-                    None => unsupported!(ty),
+                    match parse_ty(ty) {
+                        Some(ty) => vec![ty],
+                        None => unsupported!(ty),
+                    }
                 }
             }
-        }
+        },
     };
 
     let name = sig.ident.to_string();
@@ -214,6 +227,106 @@ pub fn create_fn_prototype(sig: &syn::Signature, bitness: Bitness) -> Result<FnM
             }
             args
         },
-        return_ty: parsed_return_ty.map(|ty| conv_ty(ty, bitness)),
+        returns: {
+            let mut returns = [None; VM_MAXIMUM_EXTERN_ARG_COUNT];
+            for (return_in, return_out) in parsed_returns.into_iter().zip(returns.iter_mut()) {
+                *return_out = Some(conv_ty(return_in, bitness));
+            }
+            returns
+        },
     })
 }
+
+/// The maximum number of registers in which the values of a tuple can be returned.
+///
+/// Tuples are returned through the C ABI as a struct of register-sized integers,
+/// and only structs of up to two registers are returned in `a0` and `a1`.
+pub const MAX_TUPLE_RETURN_REGS: usize = 2;
+
+/// Returns the types of the values returned by the function if it returns a non-empty tuple.
+pub fn tuple_return_types(sig: &syn::Signature) -> Option<Vec<&syn::Type>> {
+    let syn::ReturnType::Type(_, ref ty) = sig.output else {
+        return None;
+    };
+    let syn::Type::Tuple(syn::TypeTuple { ref elems, .. }) = **ty else {
+        return None;
+    };
+
+    if elems.is_empty() {
+        return None;
+    }
+
+    Some(elems.iter().collect())
+}
+
+/// Returns the names of the function's arguments.
+pub fn arg_names(sig: &syn::Signature) -> Vec<&syn::Ident> {
+    sig.inputs
+        .iter()
+        .filter_map(|arg| match arg {
+            syn::FnArg::Typed(syn::PatType { pat, .. }) => match &**pat {
+                syn::Pat::Ident(pat) => Some(&pat.ident),
+                _ => None,
+            },
+            syn::FnArg::Receiver(..) => None,
+        })
+        .collect()
+}
+
+fn tuple_regs_required(types: &[&syn::Type], bitness: Bitness) -> usize {
+    types.iter().filter_map(|ty| parse_ty(ty)).map(|ty| used_regs(ty, bitness)).sum()
+}
+
+fn too_many_tuple_return_values() -> proc_macro2::TokenStream {
+    quote! { ::core::compile_error!("returning a tuple which needs more than two registers is not supported") }
+}
+
+/// Generates an expression which converts the tuple in `value` into the registers in which it's returned.
+pub fn tuple_into_regs(value: &syn::Ident, types: &[&syn::Type], bitness: Bitness) -> proc_macro2::TokenStream {
+    if tuple_regs_required(types, bitness) > MAX_TUPLE_RETURN_REGS {
+        return too_many_tuple_return_values();
+    }
+
+    let mut regs = Vec::new();
+    for (index, ty) in types.iter().enumerate() {
+        let index = syn::Index::from(index);
+        match parse_ty(ty).map(|ty| used_regs(ty, bitness)) {
+            Some(2) => {
+                regs.push(quote! { #value.#index as u64 as usize });
+                regs.push(quote! { (#value.#index as u64 >> 32) as usize });
+            }
+            _ => regs.push(quote! { #value.#index as usize }),
+        }
+    }
+
+    while regs.len() < MAX_TUPLE_RETURN_REGS {
+        regs.push(quote! { 0 });
+    }
+
+    quote! { [#(#regs),*] }
+}
+
+/// Generates an expression which converts the registers in `regs` into a tuple of the given types.
+pub fn tuple_from_regs(regs: &syn::Ident, types: &[&syn::Type], bitness: Bitness) -> proc_macro2::TokenStream {
+    if tuple_regs_required(types, bitness) > MAX_TUPLE_RETURN_REGS {
+        return too_many_tuple_return_values();
+    }
+
+    let mut values = Vec::new();
+    let mut reg = 0_usize;
+    for ty in types {
+        match parse_ty(ty).map(|ty| used_regs(ty, bitness)) {
+            Some(2) => {
+                let reg_hi = reg + 1;
+                values.push(quote! { ((#regs[#reg] as u64) | ((#regs[#reg_hi] as u64) << 32)) as #ty });
+                reg += 2;
+            }
+            _ => {
+                values.push(quote! { #regs[#reg] as #ty });
+                reg += 1;
+            }
+        }
+    }
+
+    quote! { (#(#values,)*) }
+}
@@ -1,9 +1,12 @@
 use quote::quote;
 use syn::spanned::Spanned;
 
-use crate::common::{bytes_to_asm, create_fn_prototype, is_cfg, is_doc, is_path_eq, is_rustfmt, Bitness};
+use crate::common::{
+    arg_names, bytes_to_asm, create_fn_prototype, is_cfg, is_doc, is_path_eq, is_rustfmt, tuple_into_regs, tuple_return_types, Bitness,
+    MAX_TUPLE_RETURN_REGS,
+};
 
-fn generate_export_assembly(sig: &syn::Signature, bitness: Bitness) -> Result<proc_macro2::TokenStream, syn::Error> {
+fn generate_export_assembly(sig: &syn::Signature, bitness: Bitness, symbol: &syn::Ident) -> Result<proc_macro2::TokenStream, syn::Error> {
     let prototype = create_fn_prototype(sig, bitness)?;
     let mut metadata_bytes = Vec::new();
     prototype.serialize(|slice| metadata_bytes.extend_from_slice(slice));
@@ -15,8 +18,7 @@ fn generate_export_assembly(sig: &syn::Signature, bitness: Bitness) -> Result<pr
     assembly.push_str(&bytes_to_asm(&metadata_bytes));
     assembly.push_str(".popsection\n");
 
-    let ident = &sig.ident;
-    Ok(quote! { ::core::arch::global_asm!(#assembly, address = sym #ident); })
+    Ok(quote! { ::core::arch::global_asm!(#assembly, address = sym #symbol); })
 }
 
 pub fn is_no_mangle(attr: &syn::Attribute) -> bool {
@@ -68,9 +70,6 @@ pub fn polkavm_export(input: syn::ItemFn) -> Result<proc_macro2::TokenStream, sy
         return Err(syn::Error::new(sig.ident.span(), "must be marked as 'extern' or 'extern \"C\"'"));
     }
 
-    let assembly_b32 = generate_export_assembly(&sig, Bitness::B32)?;
-    let assembly_b64 = generate_export_assembly(&sig, Bitness::B64)?;
-
     let ident = &sig.ident;
     let args = &sig.inputs;
     let return_ty = &sig.output;
@@ -78,6 +77,73 @@ pub fn polkavm_export(input: syn::ItemFn) -> Result<proc_macro2::TokenStream, sy
     let vis = &input.vis;
     let body = &input.block;
 
+    if let Some(return_types) = tuple_return_types(&sig) {
+        // Tuples don't have a stable ABI, so the function is exported through a trampoline which returns a struct of registers.
+        let module = syn::Ident::new(&format!("__polkavm_export_{}", ident), ident.span());
+        let trampoline = syn::Ident::new("__polkavm_trampoline", proc_macro2::Span::call_site());
+        let value = syn::Ident::new("value", proc_macro2::Span::mixed_site());
+        let arg_names = arg_names(&sig);
+        let max_regs = MAX_TUPLE_RETURN_REGS;
+        let call = if unsafety.is_some() {
+            quote! { unsafe { #ident(#(#arg_names),*) } }
+        } else {
+            quote! { #ident(#(#arg_names),*) }
+        };
+
+        let generate_trampoline = |bitness| -> Result<proc_macro2::TokenStream, syn::Error> {
+            let assembly = generate_export_assembly(&sig, bitness, &trampoline)?;
+            let regs = tuple_into_regs(&value, &return_types, bitness);
+            Ok(quote! {
+                #[doc(hidden)]
+                #[allow(non_snake_case)]
+                mod #module {
+                    use super::*;
+
+                    #[repr(C)]
+                    struct __PolkaVmRegs([usize; #max_regs]);
+
+                    #[link_section = ".text.polkavm_export"]
+                    #[allow(clippy::unnecessary_cast)]
+                    extern "C" fn #trampoline(#args) -> __PolkaVmRegs {
+                        let #value = #call;
+                        __PolkaVmRegs(#regs)
+                    }
+
+                    #assembly
+
+                    // A dirty hack to remove the need for a linker script.
+                    core::arch::global_asm!(
+                        ".pushsection .text.polkavm_export,\"x\",@progbits\n",
+                        ".global __polkavm_symbol_export_hack__{name}\n",
+                        "__polkavm_symbol_export_hack__{name}:\n",
+                        ".popsection\n",
+                        name = sym #trampoline
+                    );
+                }
+            })
+        };
+
+        let trampoline_b32 = generate_trampoline(Bitness::B32)?;
+        let trampoline_b64 = generate_trampoline(Bitness::B64)?;
+
+        return Ok(quote! {
+            #(#cfg_attributes)*
+            #(#fn_attributes)*
+            #vis #unsafety fn #ident(#args) #return_ty #body
+
+            #(#cfg_attributes)*
+            #[cfg(target_arch = "riscv32")]
+            #trampoline_b32
+
+            #(#cfg_attributes)*
+            #[cfg(target_arch = "riscv64")]
+            #trampoline_b64
+        });
+    }
+
+    let assembly_b32 = generate_export_assembly(&sig, Bitness::B32, ident)?;
+    let assembly_b64 = generate_export_assembly(&sig, Bitness::B64, ident)?;
+
     Ok(quote! {
         #(#cfg_attributes)*
         #[cfg(target_arch = "riscv32")]
@@ -4,14 +4,19 @@ use quote::quote;
 use syn::spanned::Spanned;
 use syn::Token;
 
-use crate::common::{bytes_to_asm, create_fn_prototype, is_cfg, is_doc, is_path_eq, is_rustfmt, Bitness};
+use crate::common::{
+    arg_names, bytes_to_asm, create_fn_prototype, is_cfg, is_doc, is_path_eq, is_rustfmt, tuple_from_regs, tuple_return_types, Bitness,
+    MAX_TUPLE_RETURN_REGS,
+};
 
 mod kw {
     syn::custom_keyword!(index);
+    syn::custom_keyword!(module);
 }
 
 enum ImportAttribute {
     Index(u32),
+    Module(syn::LitStr),
 }
 
 impl syn::parse::Parse for ImportAttribute {
@@ -23,14 +28,32 @@ impl syn::parse::Parse for ImportAttribute {
             let value: syn::LitInt = input.parse()?;
             let value = value.base10_parse::<u32>().map_err(|err| syn::Error::new(value.span(), err))?;
             Ok(ImportAttribute::Index(value))
+        } else if lookahead.peek(kw::module) {
+            input.parse::<kw::module>()?;
+            let _: Token![=] = input.parse()?;
+            let value: syn::LitStr = input.parse()?;
+            if value.value().is_empty() || value.value().contains("::") {
+                return Err(syn::Error::new(value.span(), "invalid import module name"));
+            }
+            Ok(ImportAttribute::Module(value))
         } else {
             Err(lookahead.error())
         }
     }
 }
 
-fn generate_import_assembly(index: Option<u32>, sig: &syn::Signature, bitness: Bitness) -> Result<proc_macro2::TokenStream, syn::Error> {
-    let prototype = create_fn_prototype(sig, bitness)?;
+fn generate_import_assembly(
+    index: Option<u32>,
+    module: Option<&str>,
+    sig: &syn::Signature,
+    bitness: Bitness,
+) -> Result<proc_macro2::TokenStream, syn::Error> {
+    let mut prototype = create_fn_prototype(sig, bitness)?;
+    if let Some(module) = module {
+        // Namespaced imports are stored as plain `module::name` strings, so the blob format doesn't change.
+        prototype.name = format!("{}::{}", module, prototype.name);
+    }
+
     let mut metadata_bytes = Vec::new();
     prototype.serialize(|slice| metadata_bytes.extend_from_slice(slice));
 
@@ -93,21 +116,37 @@ fn generate_import_assembly(index: Option<u32>, sig: &syn::Signature, bitness: B
     Ok(quote! { ::core::arch::global_asm!(#assembly); })
 }
 
+fn parse_attribute_list(tokens: proc_macro2::TokenStream) -> Result<Vec<ImportAttribute>, syn::Error> {
+    let parsed_attrs = syn::parse::Parser::parse2(syn::punctuated::Punctuated::<ImportAttribute, Token![,]>::parse_terminated, tokens)?;
+
+    Ok(parsed_attrs.into_iter().collect())
+}
+
 fn parse_import_attributes(attr: &syn::Attribute) -> Result<Option<Vec<ImportAttribute>>, syn::Error> {
     if !is_path_eq(attr.meta.path(), "polkavm_import") {
         return Ok(None);
     }
 
     let list = attr.meta.require_list()?;
-    let parsed_attrs = syn::parse::Parser::parse2(
-        syn::punctuated::Punctuated::<ImportAttribute, Token![,]>::parse_terminated,
-        list.tokens.clone(),
-    )?;
-
-    Ok(Some(parsed_attrs.into_iter().collect()))
+    parse_attribute_list(list.tokens.clone()).map(Some)
 }
 
-pub fn polkavm_import(input: syn::ItemForeignMod) -> Result<proc_macro2::TokenStream, syn::Error> {
+pub fn polkavm_import(args: proc_macro2::TokenStream, input: syn::ItemForeignMod) -> Result<proc_macro2::TokenStream, syn::Error> {
+    let mut outer_module = None;
+    for attribute in parse_attribute_list(args)? {
+        match attribute {
+            ImportAttribute::Module(module) => {
+                outer_module = Some(module.value());
+            }
+            ImportAttribute::Index(index) => {
+                return Err(syn::Error::new(
+                    proc_macro2::Span::call_site(),
+                    format!("an import index (here: {index}) can only be specified on individual functions"),
+                ));
+            }
+        }
+    }
+
     let mut outer_cfg_attributes = Vec::new();
     for attr in input.attrs {
         if is_cfg(&attr) {
@@ -130,6 +169,7 @@ pub fn polkavm_import(input: syn::ItemForeignMod) -> Result<proc_macro2::TokenSt
                 let mut inner_cfg_attributes = Vec::new();
                 let mut inner_doc_attributes = Vec::new();
                 let mut import_index = None;
+                let mut import_module = outer_module.clone();
                 for attr in attrs {
                     if is_rustfmt(&attr) {
                         continue;
@@ -151,6 +191,9 @@ pub fn polkavm_import(input: syn::ItemForeignMod) -> Result<proc_macro2::TokenSt
                                 ImportAttribute::Index(index) => {
                                     import_index = Some(index);
                                 }
+                                ImportAttribute::Module(module) => {
+                                    import_module = Some(module.value());
+                                }
                             }
                         }
 
@@ -170,8 +213,8 @@ pub fn polkavm_import(input: syn::ItemForeignMod) -> Result<proc_macro2::TokenSt
                 unsupported_if_some!(sig.generics.where_clause);
                 unsupported_if_some!(sig.variadic);
 
-                let assembly_b32 = generate_import_assembly(import_index, &sig, Bitness::B32)?;
-                let assembly_b64 = generate_import_assembly(import_index, &sig, Bitness::B64)?;
+                let assembly_b32 = generate_import_assembly(import_index, import_module.as_deref(), &sig, Bitness::B32)?;
+                let assembly_b64 = generate_import_assembly(import_index, import_module.as_deref(), &sig, Bitness::B64)?;
 
                 let ident = &sig.ident;
                 let args = &sig.inputs;
@@ -187,14 +230,54 @@ pub fn polkavm_import(input: syn::ItemForeignMod) -> Result<proc_macro2::TokenSt
                     #(#inner_cfg_attributes)*
                     #[cfg(target_arch = "riscv64")]
                     #assembly_b64
+                });
 
-                    #(#outer_cfg_attributes)*
-                    extern "C" {
+                if let Some(return_types) = tuple_return_types(&sig) {
+                    // Tuples don't have a stable ABI, so call the import through a struct of registers and unpack it here.
+                    let link_name = ident.to_string();
+                    let arg_names = arg_names(&sig);
+                    let regs = syn::Ident::new("regs", proc_macro2::Span::mixed_site());
+                    let value = syn::Ident::new("value", proc_macro2::Span::mixed_site());
+                    let value_b32 = tuple_from_regs(&regs, &return_types, Bitness::B32);
+                    let value_b64 = tuple_from_regs(&regs, &return_types, Bitness::B64);
+                    let max_regs = MAX_TUPLE_RETURN_REGS;
+
+                    output.push(quote! {
+                        #(#outer_cfg_attributes)*
                         #(#inner_doc_attributes)*
                         #(#inner_cfg_attributes)*
-                        #vis fn #ident(#args) #return_ty;
-                    }
-                });
+                        #[allow(clippy::unnecessary_cast)]
+                        #[inline(always)]
+                        #vis unsafe fn #ident(#args) #return_ty {
+                            #[repr(C)]
+                            struct __PolkaVmRegs([usize; #max_regs]);
+
+                            extern "C" {
+                                #[link_name = #link_name]
+                                fn __polkavm_import(#args) -> __PolkaVmRegs;
+                            }
+
+                            let __PolkaVmRegs(#regs) = unsafe { __polkavm_import(#(#arg_names),*) };
+
+                            #[cfg(target_arch = "riscv32")]
+                            let #value = #value_b32;
+
+                            #[cfg(not(target_arch = "riscv32"))]
+                            let #value = #value_b64;
+
+                            #value
+                        }
+                    });
+                } else {
+                    output.push(quote! {
+                        #(#outer_cfg_attributes)*
+                        extern "C" {
+                            #(#inner_doc_attributes)*
+                            #(#inner_cfg_attributes)*
+                            #vis fn #ident(#args) #return_ty;
+                        }
+                    });
+                }
             }
             item => unsupported!(item),
         }
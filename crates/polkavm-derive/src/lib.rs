@@ -7,9 +7,8 @@ use proc_macro::TokenStream;
 
 #[proc_macro_attribute]
 pub fn polkavm_import(args: TokenStream, input: TokenStream) -> TokenStream {
-    syn::parse_macro_input!(args as syn::parse::Nothing);
     let input = syn::parse_macro_input!(input as syn::ItemForeignMod);
-    match polkavm_derive_impl::polkavm_import(input) {
+    match polkavm_derive_impl::polkavm_import(args.into(), input) {
         Ok(result) => result.into(),
         Err(error) => error.into_compile_error().into(),
     }
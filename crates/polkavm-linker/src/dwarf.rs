@@ -575,7 +575,7 @@ fn try_fetch_relocation(
 
     let RelocationKind::Abs {
         target,
-        size: RelocationSize::U32,
+        size: RelocationSize::U32 | RelocationSize::U64,
     } = relocation
     else {
         return Err(ProgramFromElfError::other(format!(
@@ -602,7 +602,7 @@ fn try_fetch_size_relocation(
         } => Ok(Some((*section_index, *range))),
         RelocationKind::Abs {
             target,
-            size: RelocationSize::U32,
+            size: RelocationSize::U32 | RelocationSize::U64,
         } => Ok(Some((target.section_index, (target.offset..target.offset).into()))),
         _ => Err(ProgramFromElfError::other(format!(
             "failed to process DWARF: unexpected relocation at {relocation_target}: {relocation:?}"
@@ -1074,7 +1074,7 @@ where
             R: gimli::Reader,
         {
             let inline_source = inlined.source;
-            for offset in (inline_source.offset_range.start..inline_source.offset_range.end).step_by(4) {
+            for offset in (inline_source.offset_range.start..inline_source.offset_range.end).step_by(2) {
                 let list = output.get_mut(&offset).unwrap();
                 if inlined.call_location.is_some() {
                     list.push(LocationKindRef::InlineCall(inlined));
@@ -1117,7 +1117,7 @@ where
                 log::trace!("  Frame: {}", source);
 
                 let mut map: LocationsForOffset<R> = BTreeMap::new();
-                for offset in (source.offset_range.start..source.offset_range.end).step_by(4) {
+                for offset in (source.offset_range.start..source.offset_range.end).step_by(2) {
                     map.insert(offset, Vec::new());
                 }
 
@@ -1127,7 +1127,7 @@ where
 
                 #[allow(clippy::type_complexity)]
                 let mut last_emitted: Option<(Vec<LocationKindRef<R>>, Arc<[Location]>)> = None;
-                for offset in (source.offset_range.start..source.offset_range.end).step_by(4) {
+                for offset in (source.offset_range.start..source.offset_range.end).step_by(2) {
                     let mut list = map.remove(&offset).unwrap();
                     let mut fallback = false;
                     if let Some(line_entry) = line_range_map.get_value(offset) {
@@ -2220,7 +2220,7 @@ where
 
                 let RelocationKind::Abs {
                     target,
-                    size: RelocationSize::U32,
+                    size: RelocationSize::U32 | RelocationSize::U64,
                 } = relocation
                 else {
                     return Err(ProgramFromElfError::other(format!(
@@ -1,14 +1,37 @@
+use object::read::elf::FileHeader;
 use object::{LittleEndian, Object, ObjectSection, ObjectSymbol};
 use std::borrow::Cow;
 use std::collections::HashMap;
 
 use crate::program_from_elf::ProgramFromElfError;
 
-type ElfFile<'a> = object::read::elf::ElfFile<'a, object::elf::FileHeader32<object::endian::LittleEndian>, &'a [u8]>;
-type ElfSymbol<'data, 'file> =
+type ElfFile32<'a> = object::read::elf::ElfFile<'a, object::elf::FileHeader32<object::endian::LittleEndian>, &'a [u8]>;
+type ElfFile64<'a> = object::read::elf::ElfFile<'a, object::elf::FileHeader64<object::endian::LittleEndian>, &'a [u8]>;
+type ElfSymbol32<'data, 'file> =
     object::read::elf::ElfSymbol<'data, 'file, object::elf::FileHeader32<object::endian::LittleEndian>, &'data [u8]>;
+type ElfSymbol64<'data, 'file> =
+    object::read::elf::ElfSymbol<'data, 'file, object::elf::FileHeader64<object::endian::LittleEndian>, &'data [u8]>;
 type ElfSectionIndex = object::read::SectionIndex;
 
+enum ElfFile<'a> {
+    B32(ElfFile32<'a>),
+    B64(ElfFile64<'a>),
+}
+
+enum ElfSymbol<'data, 'file> {
+    B32(ElfSymbol32<'data, 'file>),
+    B64(ElfSymbol64<'data, 'file>),
+}
+
+macro_rules! dispatch {
+    ($value:expr, $enum:ident, |$inner:ident| $body:expr) => {
+        match $value {
+            $enum::B32($inner) => $body,
+            $enum::B64($inner) => $body,
+        }
+    };
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
 pub struct SectionIndex(usize);
 
@@ -80,7 +103,7 @@ impl<'data, 'file> Symbol<'data, 'file> {
     }
 
     pub fn name(&self) -> Option<&'data str> {
-        let name = self.elf_symbol.name().ok()?;
+        let name = dispatch!(&self.elf_symbol, ElfSymbol, |symbol| symbol.name()).ok()?;
         if name.is_empty() {
             None
         } else {
@@ -89,7 +112,7 @@ impl<'data, 'file> Symbol<'data, 'file> {
     }
 
     pub fn section_and_offset(&self) -> Result<(&Section, u64), ProgramFromElfError> {
-        let elf_section_index = match self.elf_symbol.section() {
+        let elf_section_index = match dispatch!(&self.elf_symbol, ElfSymbol, |symbol| symbol.section()) {
             object::read::SymbolSection::Section(section_index) => section_index,
             section => {
                 return Err(ProgramFromElfError::other(format!(
@@ -106,7 +129,8 @@ impl<'data, 'file> Symbol<'data, 'file> {
             .copied()
             .expect("unable to map section index");
         let section = self.elf.section_by_index(section_index);
-        let Some(offset) = self.elf_symbol.address().checked_sub(section.original_address()) else {
+        let address = dispatch!(&self.elf_symbol, ElfSymbol, |symbol| symbol.address());
+        let Some(offset) = address.checked_sub(section.original_address()) else {
             return Err(ProgramFromElfError::other("relative symbol address underflow"));
         };
 
@@ -114,11 +138,11 @@ impl<'data, 'file> Symbol<'data, 'file> {
     }
 
     pub fn size(&self) -> u64 {
-        self.elf_symbol.size()
+        dispatch!(&self.elf_symbol, ElfSymbol, |symbol| symbol.size())
     }
 
     pub fn kind(&self) -> u8 {
-        self.elf_symbol.raw_symbol().st_type()
+        dispatch!(&self.elf_symbol, ElfSymbol, |symbol| symbol.raw_symbol().st_type())
     }
 }
 
@@ -131,23 +155,34 @@ pub struct Elf<'data> {
 
 impl<'data> Elf<'data> {
     pub fn parse(data: &'data [u8]) -> Result<Self, ProgramFromElfError> {
-        let elf = ElfFile::parse(data)?;
-        if elf.raw_header().e_ident.data != object::elf::ELFDATA2LSB {
+        match object::FileKind::parse(data)? {
+            object::FileKind::Elf32 => Self::parse_impl(data, ElfFile32::parse(data)?, ElfFile::B32),
+            object::FileKind::Elf64 => Self::parse_impl(data, ElfFile64::parse(data)?, ElfFile::B64),
+            _ => Err(ProgramFromElfError::other("file is not an ELF file")),
+        }
+    }
+
+    fn parse_impl<H>(
+        data: &'data [u8],
+        elf: object::read::elf::ElfFile<'data, H, &'data [u8]>,
+        wrap: impl FnOnce(object::read::elf::ElfFile<'data, H, &'data [u8]>) -> ElfFile<'data>,
+    ) -> Result<Self, ProgramFromElfError>
+    where
+        H: FileHeader<Endian = LittleEndian>,
+    {
+        if elf.raw_header().e_ident().data != object::elf::ELFDATA2LSB {
             return Err(ProgramFromElfError::other("file is not a little endian ELF file"));
         }
 
-        if elf.raw_header().e_ident.os_abi != object::elf::ELFOSABI_SYSV {
+        if elf.raw_header().e_ident().os_abi != object::elf::ELFOSABI_SYSV {
             return Err(ProgramFromElfError::other("file doesn't use the System V ABI"));
         }
 
-        if !matches!(
-            elf.raw_header().e_type.get(LittleEndian),
-            object::elf::ET_EXEC | object::elf::ET_REL
-        ) {
+        if !matches!(elf.raw_header().e_type(LittleEndian), object::elf::ET_EXEC | object::elf::ET_REL) {
             return Err(ProgramFromElfError::other("file is not a supported ELF file (ET_EXEC or ET_REL)"));
         }
 
-        if elf.raw_header().e_machine.get(LittleEndian) != object::elf::EM_RISCV {
+        if elf.raw_header().e_machine(LittleEndian) != object::elf::EM_RISCV {
             return Err(ProgramFromElfError::other("file is not a RISC-V file (EM_RISCV)"));
         }
 
@@ -209,14 +244,22 @@ impl<'data> Elf<'data> {
             sections,
             section_index_by_name,
             section_index_map,
-            raw_elf: elf,
+            raw_elf: wrap(elf),
         })
     }
 
+    /// Returns whether this is a 64-bit ELF file.
+    pub fn is_64(&self) -> bool {
+        matches!(self.raw_elf, ElfFile::B64(..))
+    }
+
     pub fn symbol_by_index(&self, symbol_index: object::SymbolIndex) -> Result<Symbol, object::Error> {
-        self.raw_elf
-            .symbol_by_index(symbol_index)
-            .map(|elf_symbol| Symbol::new(self, elf_symbol))
+        let elf_symbol = match self.raw_elf {
+            ElfFile::B32(ref elf) => ElfSymbol::B32(elf.symbol_by_index(symbol_index)?),
+            ElfFile::B64(ref elf) => ElfSymbol::B64(elf.symbol_by_index(symbol_index)?),
+        };
+
+        Ok(Symbol::new(self, elf_symbol))
     }
 
     pub fn section_by_name(&self, name: &str) -> Option<&Section> {
@@ -237,7 +280,16 @@ impl<'data> Elf<'data> {
     }
 
     pub fn symbols<'r>(&'r self) -> impl Iterator<Item = Symbol<'data, 'r>> + 'r {
-        self.raw_elf.symbols().map(|elf_symbol| Symbol::new(self, elf_symbol))
+        let (symbols_32, symbols_64) = match self.raw_elf {
+            ElfFile::B32(ref elf) => (Some(elf.symbols().map(ElfSymbol::B32)), None),
+            ElfFile::B64(ref elf) => (None, Some(elf.symbols().map(ElfSymbol::B64))),
+        };
+
+        symbols_32
+            .into_iter()
+            .flatten()
+            .chain(symbols_64.into_iter().flatten())
+            .map(|elf_symbol| Symbol::new(self, elf_symbol))
     }
 
     pub fn add_empty_data_section(&mut self, name: &str) -> SectionIndex {
@@ -270,10 +322,28 @@ impl<'data> Elf<'data> {
     }
 
     pub fn relocations<'r>(&'r self, section: &Section) -> impl Iterator<Item = (u64, object::read::Relocation)> + 'r {
-        section
-            .raw_section_index
-            .and_then(move |index| self.raw_elf.section_by_index(index).ok())
-            .into_iter()
-            .flat_map(|raw_section| raw_section.relocations())
+        let (relocations_32, relocations_64) = match (section.raw_section_index, &self.raw_elf) {
+            (None, _) => (None, None),
+            (Some(index), ElfFile::B32(elf)) => (
+                Some(
+                    elf.section_by_index(index)
+                        .ok()
+                        .into_iter()
+                        .flat_map(|raw_section| raw_section.relocations()),
+                ),
+                None,
+            ),
+            (Some(index), ElfFile::B64(elf)) => (
+                None,
+                Some(
+                    elf.section_by_index(index)
+                        .ok()
+                        .into_iter()
+                        .flat_map(|raw_section| raw_section.relocations()),
+                ),
+            ),
+        };
+
+        relocations_32.into_iter().flatten().chain(relocations_64.into_iter().flatten())
     }
 }
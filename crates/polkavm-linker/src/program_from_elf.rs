@@ -1,5 +1,5 @@
 use polkavm_common::abi::{GuestMemoryConfig, VM_ADDR_USER_MEMORY, VM_CODE_ADDRESS_ALIGNMENT, VM_MAX_PAGE_SIZE, VM_PAGE_SIZE};
-use polkavm_common::elf::{FnMetadata, ImportMetadata, INSTRUCTION_ECALLI};
+use polkavm_common::elf::{FnMetadata, ImportMetadata, INSTRUCTION_ECALLI, INSTRUCTION_SBRK, INSTRUCTION_SBRK_MASK};
 use polkavm_common::program::{self, FrameKind, Instruction, LineProgramOp, ProgramBlob};
 use polkavm_common::utils::align_to_next_page_u64;
 use polkavm_common::varint;
@@ -13,7 +13,7 @@ use std::sync::Arc;
 use crate::dwarf::Location;
 use crate::elf::{Elf, Section, SectionIndex};
 use crate::riscv::Reg as RReg;
-use crate::riscv::{AtomicKind, BranchKind, CmovKind, Inst, LoadKind, RegImmKind, StoreKind};
+use crate::riscv::{AtomicKind, Bitness, BranchKind, CmovKind, Inst, LoadKind, RegImmKind, StoreKind, UnaryKind};
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
 #[repr(u8)]
@@ -226,7 +226,7 @@ impl Source {
 
     fn iter(&'_ self) -> impl Iterator<Item = SectionTarget> + '_ {
         (self.offset_range.start..self.offset_range.end)
-            .step_by(4)
+            .step_by(2)
             .map(|offset| SectionTarget {
                 section_index: self.section_index,
                 offset,
@@ -531,6 +531,11 @@ enum BasicInst<T> {
         src1: RegImm,
         src2: RegImm,
     },
+    Unary {
+        kind: UnaryKind,
+        dst: Reg,
+        src: Reg,
+    },
     Cmov {
         kind: CmovKind,
         dst: Reg,
@@ -540,6 +545,20 @@ enum BasicInst<T> {
     Ecalli {
         syscall: u32,
     },
+    Sbrk {
+        dst: Reg,
+        size: Reg,
+    },
+    Memcpy {
+        dst: Reg,
+        src: Reg,
+        count: Reg,
+    },
+    Memset {
+        dst: Reg,
+        value: Reg,
+        count: Reg,
+    },
     Nop,
 }
 
@@ -575,7 +594,11 @@ impl<T> BasicInst<T> {
             BasicInst::StoreIndirect { src, base, .. } => RegMask::from(src) | RegMask::from(base),
             BasicInst::RegReg { src1, src2, .. } => RegMask::from(src1) | RegMask::from(src2),
             BasicInst::AnyAny { src1, src2, .. } => RegMask::from(src1) | RegMask::from(src2),
+            BasicInst::Unary { src, .. } => RegMask::from(src),
             BasicInst::Cmov { src, cond, .. } => RegMask::from(src) | RegMask::from(cond),
+            BasicInst::Sbrk { size, .. } => RegMask::from(size),
+            BasicInst::Memcpy { dst, src, count } => RegMask::from(dst) | RegMask::from(src) | RegMask::from(count),
+            BasicInst::Memset { dst, value, count } => RegMask::from(dst) | RegMask::from(value) | RegMask::from(count),
             BasicInst::Ecalli { syscall } => imports
                 .iter()
                 .find(|import| import.metadata.index.unwrap() == syscall)
@@ -586,14 +609,20 @@ impl<T> BasicInst<T> {
 
     fn dst_mask(&self, imports: &[Import]) -> RegMask {
         match *self {
-            BasicInst::Nop | BasicInst::StoreAbsolute { .. } | BasicInst::StoreIndirect { .. } => RegMask::empty(),
+            BasicInst::Nop
+            | BasicInst::StoreAbsolute { .. }
+            | BasicInst::StoreIndirect { .. }
+            | BasicInst::Memcpy { .. }
+            | BasicInst::Memset { .. } => RegMask::empty(),
             BasicInst::LoadImmediate { dst, .. }
             | BasicInst::LoadAbsolute { dst, .. }
             | BasicInst::LoadAddress { dst, .. }
             | BasicInst::LoadAddressIndirect { dst, .. }
             | BasicInst::LoadIndirect { dst, .. }
             | BasicInst::RegReg { dst, .. }
+            | BasicInst::Unary { dst, .. }
             | BasicInst::Cmov { dst, .. }
+            | BasicInst::Sbrk { dst, .. }
             | BasicInst::AnyAny { dst, .. } => RegMask::from(dst),
 
             BasicInst::Ecalli { syscall } => imports
@@ -606,13 +635,19 @@ impl<T> BasicInst<T> {
 
     fn has_side_effects(&self, config: &Config) -> bool {
         match *self {
-            BasicInst::Ecalli { .. } | BasicInst::StoreAbsolute { .. } | BasicInst::StoreIndirect { .. } => true,
+            BasicInst::Ecalli { .. }
+            | BasicInst::Sbrk { .. }
+            | BasicInst::Memcpy { .. }
+            | BasicInst::Memset { .. }
+            | BasicInst::StoreAbsolute { .. }
+            | BasicInst::StoreIndirect { .. } => true,
             BasicInst::LoadAbsolute { .. } | BasicInst::LoadIndirect { .. } => !config.elide_unnecessary_loads,
             BasicInst::Nop
             | BasicInst::LoadImmediate { .. }
             | BasicInst::LoadAddress { .. }
             | BasicInst::LoadAddressIndirect { .. }
             | BasicInst::RegReg { .. }
+            | BasicInst::Unary { .. }
             | BasicInst::Cmov { .. }
             | BasicInst::AnyAny { .. } => false,
         }
@@ -664,12 +699,31 @@ impl<T> BasicInst<T> {
                 src2: src2.map_register(|reg| map(reg, false)),
                 dst: map(dst, true),
             }),
+            BasicInst::Unary { kind, dst, src } => Some(BasicInst::Unary {
+                kind,
+                src: map(src, false),
+                dst: map(dst, true),
+            }),
             BasicInst::Cmov { kind, dst, src, cond } => Some(BasicInst::Cmov {
                 kind,
                 src: map(src, false),
                 cond: map(cond, false),
                 dst: map(dst, true),
             }),
+            BasicInst::Sbrk { dst, size } => Some(BasicInst::Sbrk {
+                size: map(size, false),
+                dst: map(dst, true),
+            }),
+            BasicInst::Memcpy { dst, src, count } => Some(BasicInst::Memcpy {
+                dst: map(dst, false),
+                src: map(src, false),
+                count: map(count, false),
+            }),
+            BasicInst::Memset { dst, value, count } => Some(BasicInst::Memset {
+                dst: map(dst, false),
+                value: map(value, false),
+                count: map(count, false),
+            }),
             BasicInst::Ecalli { .. } => None,
             BasicInst::Nop => Some(BasicInst::Nop),
         }
@@ -735,8 +789,12 @@ impl<T> BasicInst<T> {
             BasicInst::StoreIndirect { kind, src, base, offset } => BasicInst::StoreIndirect { kind, src, base, offset },
             BasicInst::RegReg { kind, dst, src1, src2 } => BasicInst::RegReg { kind, dst, src1, src2 },
             BasicInst::AnyAny { kind, dst, src1, src2 } => BasicInst::AnyAny { kind, dst, src1, src2 },
+            BasicInst::Unary { kind, dst, src } => BasicInst::Unary { kind, dst, src },
             BasicInst::Cmov { kind, dst, src, cond } => BasicInst::Cmov { kind, dst, src, cond },
             BasicInst::Ecalli { syscall } => BasicInst::Ecalli { syscall },
+            BasicInst::Sbrk { dst, size } => BasicInst::Sbrk { dst, size },
+            BasicInst::Memcpy { dst, src, count } => BasicInst::Memcpy { dst, src, count },
+            BasicInst::Memset { dst, value, count } => BasicInst::Memset { dst, value, count },
             BasicInst::Nop => BasicInst::Nop,
         })
     }
@@ -754,8 +812,12 @@ impl<T> BasicInst<T> {
             | BasicInst::StoreIndirect { .. }
             | BasicInst::RegReg { .. }
             | BasicInst::AnyAny { .. }
+            | BasicInst::Unary { .. }
             | BasicInst::Cmov { .. }
-            | BasicInst::Ecalli { .. } => (None, None),
+            | BasicInst::Ecalli { .. }
+            | BasicInst::Sbrk { .. }
+            | BasicInst::Memcpy { .. }
+            | BasicInst::Memset { .. } => (None, None),
         }
     }
 }
@@ -1237,27 +1299,32 @@ fn extract_export_metadata(
     Ok(exports)
 }
 
+/// Returns how many registers are needed to pass a value of the given type to or from an import.
+fn regs_required(ty: polkavm_common::program::ExternTy, bitness: Bitness) -> usize {
+    use polkavm_common::program::ExternTy;
+    match (ty, bitness) {
+        (ExternTy::I32, _) | (ExternTy::I64, Bitness::B64) => 1,
+        (ExternTy::I64, Bitness::B32) => 2,
+    }
+}
+
 #[derive(Debug)]
 struct Import {
     metadata_locations: Vec<SectionTarget>,
     metadata: ImportMetadata,
+    bitness: Bitness,
 }
 
 impl Import {
     fn src(&'_ self) -> impl Iterator<Item = Reg> + '_ {
-        use polkavm_common::program::ExternTy;
         let arg_regs = [Reg::A0, Reg::A1, Reg::A2, Reg::A3, Reg::A4, Reg::A5];
         assert_eq!(Reg::ARG_REGS.len(), arg_regs.len()); // TODO: Use ARG_REGS here directly.
 
         let mut arg_regs = arg_regs.into_iter();
+        let bitness = self.bitness;
         self.metadata.args().flat_map(move |arg| {
             let mut chunk = [None, None];
-            let count = match arg {
-                ExternTy::I32 => 1,
-                ExternTy::I64 => 2,
-            };
-
-            for slot in chunk.iter_mut().take(count) {
+            for slot in chunk.iter_mut().take(regs_required(arg, bitness)) {
                 *slot = Some(arg_regs.next().expect("internal error: import with too many arguments"));
             }
             chunk.into_iter().flatten()
@@ -1273,15 +1340,16 @@ impl Import {
         mask
     }
 
-    fn dst(&self) -> impl Iterator<Item = Reg> {
-        use polkavm_common::program::ExternTy;
-        match self.metadata.return_ty() {
-            None => [None, None],
-            Some(ExternTy::I32) => [Some(Reg::A0), None],
-            Some(ExternTy::I64) => [Some(Reg::A0), Some(Reg::A1)],
-        }
-        .into_iter()
-        .flatten()
+    fn dst(&'_ self) -> impl Iterator<Item = Reg> + '_ {
+        let mut return_regs = Reg::ARG_REGS.into_iter();
+        let bitness = self.bitness;
+        self.metadata.returns().flat_map(move |return_ty| {
+            let mut chunk = [None, None];
+            for slot in chunk.iter_mut().take(regs_required(return_ty, bitness)) {
+                *slot = Some(return_regs.next().expect("internal error: import with too many return values"));
+            }
+            chunk.into_iter().flatten()
+        })
     }
 
     fn dst_mask(&self) -> RegMask {
@@ -1294,7 +1362,7 @@ impl Import {
     }
 }
 
-fn extract_import_metadata(elf: &Elf, sections: &[SectionIndex]) -> Result<Vec<Import>, ProgramFromElfError> {
+fn extract_import_metadata(elf: &Elf, bitness: Bitness, sections: &[SectionIndex]) -> Result<Vec<Import>, ProgramFromElfError> {
     let mut imports: Vec<Import> = Vec::new();
     let mut import_by_index: BTreeMap<u32, usize> = BTreeMap::new();
     let mut import_by_name: HashMap<String, usize> = HashMap::new();
@@ -1347,11 +1415,26 @@ fn extract_import_metadata(elf: &Elf, sections: &[SectionIndex]) -> Result<Vec<I
                         indexless.push(nth_import);
                     }
 
+                    if metadata.args().map(|ty| regs_required(ty, bitness)).sum::<usize>() > Reg::ARG_REGS.len() {
+                        return Err(ProgramFromElfError::other(format!(
+                            "import '{}' has too many arguments to fit in the registers",
+                            metadata.name()
+                        )));
+                    }
+
+                    if metadata.returns().map(|ty| regs_required(ty, bitness)).sum::<usize>() > Reg::ARG_REGS.len() {
+                        return Err(ProgramFromElfError::other(format!(
+                            "import '{}' returns more values than can fit in the registers",
+                            metadata.name()
+                        )));
+                    }
+
                     import_by_name.insert(metadata.name().to_owned(), nth_import);
 
                     let import = Import {
                         metadata_locations: vec![location],
                         metadata,
+                        bitness,
                     };
 
                     imports.push(import);
@@ -1502,10 +1585,29 @@ fn emit_minmax(
     }))
 }
 
+fn atomic_load_kind(bitness: Bitness, is_64_bit: bool) -> LoadKind {
+    match (bitness, is_64_bit) {
+        (_, true) => LoadKind::U64,
+        // On 64-bit the 32-bit atomics sign-extend the value they load.
+        (Bitness::B64, false) => LoadKind::I32,
+        (Bitness::B32, false) => LoadKind::U32,
+    }
+}
+
+fn atomic_store_kind(is_64_bit: bool) -> StoreKind {
+    if is_64_bit {
+        StoreKind::U64
+    } else {
+        StoreKind::U32
+    }
+}
+
 fn convert_instruction(
     section: &Section,
     current_location: SectionTarget,
     instruction: Inst,
+    instruction_size: u64,
+    bitness: Bitness,
     mut emit: impl FnMut(InstExt<SectionTarget, SectionTarget>),
 ) -> Result<(), ProgramFromElfError> {
     match instruction {
@@ -1525,7 +1627,7 @@ fn convert_instruction(
             }
 
             let next = if let Some(dst) = cast_reg_non_zero(dst)? {
-                let target_return = current_location.add(4);
+                let target_return = current_location.add(instruction_size);
                 ControlInst::Call {
                     ra: dst,
                     target,
@@ -1551,7 +1653,7 @@ fn convert_instruction(
                 return Err(ProgramFromElfError::other("out of range unrelocated branch"));
             }
 
-            let target_false = current_location.add(4);
+            let target_false = current_location.add(instruction_size);
             emit(InstExt::Control(ControlInst::Branch {
                 kind,
                 src1,
@@ -1567,7 +1669,7 @@ fn convert_instruction(
             };
 
             let next = if let Some(dst) = cast_reg_non_zero(dst)? {
-                let target_return = current_location.add(4);
+                let target_return = current_location.add(instruction_size);
                 ControlInst::CallIndirect {
                     ra: dst,
                     base,
@@ -1622,6 +1724,13 @@ fn convert_instruction(
                 RegImmKind::ShiftLogicalLeft => AnyAnyKind::ShiftLogicalLeft,
                 RegImmKind::ShiftLogicalRight => AnyAnyKind::ShiftLogicalRight,
                 RegImmKind::ShiftArithmeticRight => AnyAnyKind::ShiftArithmeticRight,
+                RegImmKind::RotateRight => AnyAnyKind::RotateRight,
+
+                RegImmKind::Add32 => AnyAnyKind::Add32,
+                RegImmKind::ShiftLogicalLeft32 => AnyAnyKind::ShiftLogicalLeft32,
+                RegImmKind::ShiftLogicalRight32 => AnyAnyKind::ShiftLogicalRight32,
+                RegImmKind::ShiftArithmeticRight32 => AnyAnyKind::ShiftArithmeticRight32,
+                RegImmKind::RotateRight32 => AnyAnyKind::RotateRight32,
             };
 
             emit(InstExt::Basic(BasicInst::AnyAny {
@@ -1646,6 +1755,21 @@ fn convert_instruction(
                 };
             }
 
+            macro_rules! anyany_no_imm_src1 {
+                ($kind:ident) => {
+                    match cast_reg_non_zero(src1)? {
+                        Some(src1) => BasicInst::AnyAny {
+                            kind: AnyAnyKind::$kind,
+                            dst,
+                            src1: src1.into(),
+                            src2: cast_reg_any(src2)?,
+                        },
+                        // There are no variants of these which take an immediate as the first operand, but zero shifted is always zero.
+                        None => BasicInst::LoadImmediate { dst, imm: 0 },
+                    }
+                };
+            }
+
             macro_rules! regreg {
                 ($kind:ident) => {
                     match (cast_reg_non_zero(src1)?, cast_reg_non_zero(src2)?) {
@@ -1665,6 +1789,28 @@ fn convert_instruction(
                 };
             }
 
+            macro_rules! regreg_with_zero {
+                ($kind:ident) => {{
+                    // There are no variants of these which take an immediate, so load the zero into a temporary register.
+                    let mut cast = |reg| -> Result<Reg, ProgramFromElfError> {
+                        Ok(match cast_reg_non_zero(reg)? {
+                            Some(reg) => reg,
+                            None => {
+                                emit(InstExt::Basic(BasicInst::LoadImmediate { dst: Reg::E0, imm: 0 }));
+                                Reg::E0
+                            }
+                        })
+                    };
+
+                    BasicInst::RegReg {
+                        kind: RegRegKind::$kind,
+                        dst,
+                        src1: cast(src1)?,
+                        src2: cast(src2)?,
+                    }
+                }};
+            }
+
             use crate::riscv::RegRegKind as K;
             let instruction = match kind {
                 K::Add => anyany!(Add),
@@ -1686,11 +1832,49 @@ fn convert_instruction(
                 K::DivUnsigned => regreg!(DivUnsigned),
                 K::Rem => regreg!(Rem),
                 K::RemUnsigned => regreg!(RemUnsigned),
+
+                K::RotateLeft => anyany!(RotateLeft),
+                K::RotateRight => anyany!(RotateRight),
+                K::AndInverted => regreg_with_zero!(AndInverted),
+                K::OrInverted => regreg_with_zero!(OrInverted),
+                K::Xnor => regreg_with_zero!(Xnor),
+                K::Maximum => regreg_with_zero!(Maximum),
+                K::MaximumUnsigned => regreg_with_zero!(MaximumUnsigned),
+                K::Minimum => regreg_with_zero!(Minimum),
+                K::MinimumUnsigned => regreg_with_zero!(MinimumUnsigned),
+
+                K::Add32 => anyany!(Add32),
+                K::Sub32 => regreg_with_zero!(Sub32),
+                K::Mul32 => regreg_with_zero!(Mul32),
+                K::ShiftLogicalLeft32 => anyany_no_imm_src1!(ShiftLogicalLeft32),
+                K::ShiftLogicalRight32 => anyany_no_imm_src1!(ShiftLogicalRight32),
+                K::ShiftArithmeticRight32 => anyany_no_imm_src1!(ShiftArithmeticRight32),
+                K::RotateLeft32 => anyany_no_imm_src1!(RotateLeft32),
+                K::RotateRight32 => anyany_no_imm_src1!(RotateRight32),
+                K::Div32 => regreg!(Div32),
+                K::DivUnsigned32 => regreg!(DivUnsigned32),
+                K::Rem32 => regreg!(Rem32),
+                K::RemUnsigned32 => regreg!(RemUnsigned32),
             };
 
             emit(InstExt::Basic(instruction));
             Ok(())
         }
+        Inst::Unary { kind, dst, src } => {
+            let Some(dst) = cast_reg_non_zero(dst)? else { return Ok(()) };
+            if let Some(src) = cast_reg_non_zero(src)? {
+                emit(InstExt::Basic(BasicInst::Unary { kind, dst, src }));
+            } else {
+                emit(InstExt::Basic(BasicInst::LoadImmediate {
+                    dst,
+                    imm: kind
+                        .apply_const(bitness, 0)
+                        .expect("internal error: constant evaluation of an unary operation on zero failed"),
+                }));
+            }
+
+            Ok(())
+        }
         Inst::AddUpperImmediateToPc { .. } => Err(ProgramFromElfError::other(format!(
             "found an unrelocated auipc instruction at offset {} in section '{}'; is the program compiled with relocations?",
             current_location.offset,
@@ -1715,7 +1899,7 @@ fn convert_instruction(
                 todo!();
             }
         }
-        Inst::LoadReserved { dst, src, .. } => {
+        Inst::LoadReserved { dst, src, is_64_bit, .. } => {
             let Some(dst) = cast_reg_non_zero(dst)? else {
                 return Err(ProgramFromElfError::other(
                     "found an atomic load with a zero register as the destination",
@@ -1729,7 +1913,7 @@ fn convert_instruction(
             };
 
             emit(InstExt::Basic(BasicInst::LoadIndirect {
-                kind: LoadKind::U32,
+                kind: atomic_load_kind(bitness, is_64_bit),
                 dst,
                 base: src,
                 offset: 0,
@@ -1737,7 +1921,9 @@ fn convert_instruction(
 
             Ok(())
         }
-        Inst::StoreConditional { src, addr, dst, .. } => {
+        Inst::StoreConditional {
+            src, addr, dst, is_64_bit, ..
+        } => {
             let Some(addr) = cast_reg_non_zero(addr)? else {
                 return Err(ProgramFromElfError::other(
                     "found an atomic store with a zero register as the address",
@@ -1746,7 +1932,7 @@ fn convert_instruction(
 
             let src = cast_reg_any(src)?;
             emit(InstExt::Basic(BasicInst::StoreIndirect {
-                kind: StoreKind::U32,
+                kind: atomic_store_kind(is_64_bit),
                 src,
                 base: addr,
                 offset: 0,
@@ -1764,6 +1950,7 @@ fn convert_instruction(
             dst: old_value,
             addr,
             src: operand,
+            is_64_bit,
             ..
         } => {
             let Some(addr) = cast_reg_non_zero(addr)? else {
@@ -1781,7 +1968,7 @@ fn convert_instruction(
             };
 
             emit(InstExt::Basic(BasicInst::LoadIndirect {
-                kind: LoadKind::U32,
+                kind: atomic_load_kind(bitness, is_64_bit),
                 dst: old_value,
                 base: addr,
                 offset: 0,
@@ -1843,7 +2030,7 @@ fn convert_instruction(
             }
 
             emit(InstExt::Basic(BasicInst::StoreIndirect {
-                kind: StoreKind::U32,
+                kind: atomic_store_kind(is_64_bit),
                 src: new_value.into(),
                 base: addr,
                 offset: 0,
@@ -1869,14 +2056,15 @@ fn parse_code_section(
     relocations: &BTreeMap<SectionTarget, RelocationKind>,
     instruction_overrides: &mut HashMap<SectionTarget, InstExt<SectionTarget, SectionTarget>>,
     output: &mut Vec<(Source, InstExt<SectionTarget, SectionTarget>)>,
+    bitness: Bitness,
 ) -> Result<(), ProgramFromElfError> {
     let section_index = section.index();
     let section_name = section.name();
     let text = &section.data();
 
-    if text.len() % 4 != 0 {
+    if text.len() % 2 != 0 {
         return Err(ProgramFromElfError::other(format!(
-            "size of section '{section_name}' is not divisible by 4"
+            "size of section '{section_name}' is not divisible by 2"
         )));
     }
 
@@ -1888,16 +2076,15 @@ fn parse_code_section(
             offset: relative_offset.try_into().expect("overflow"),
         };
 
-        let raw_inst = u32::from_le_bytes([
-            text[relative_offset],
-            text[relative_offset + 1],
-            text[relative_offset + 2],
-            text[relative_offset + 3],
-        ]);
+        let Some((raw_inst, inst_size)) = read_instruction(text, relative_offset) else {
+            return Err(ProgramFromElfError::other(format!(
+                "truncated instruction at the end of section '{section_name}'"
+            )));
+        };
 
         if raw_inst == INSTRUCTION_ECALLI {
             let initial_offset = relative_offset as u64;
-            if relative_offset + 12 > text.len() {
+            if relative_offset + 10 > text.len() {
                 return Err(ProgramFromElfError::other("truncated ecalli instruction"));
             }
 
@@ -1942,42 +2129,55 @@ fn parse_code_section(
                 value: 0,
             };
 
-            let next_raw_inst = u32::from_le_bytes([
-                text[relative_offset],
-                text[relative_offset + 1],
-                text[relative_offset + 2],
-                text[relative_offset + 3],
-            ]);
-
-            if Inst::decode(next_raw_inst) != Some(INST_RET) {
-                return Err(ProgramFromElfError::other("external call shim doesn't end with a 'ret'"));
-            }
+            let next_inst_size = match read_instruction(text, relative_offset) {
+                Some((next_raw_inst, next_inst_size)) if decode_instruction(bitness, next_raw_inst, next_inst_size) == Some(INST_RET) => {
+                    next_inst_size
+                }
+                _ => return Err(ProgramFromElfError::other("external call shim doesn't end with a 'ret'")),
+            };
 
             output.push((
                 Source {
                     section_index,
-                    offset_range: AddressRange::from(relative_offset as u64..relative_offset as u64 + 4),
+                    offset_range: AddressRange::from(relative_offset as u64..(relative_offset + next_inst_size) as u64),
                 },
                 InstExt::Control(ControlInst::JumpIndirect { base: Reg::RA, offset: 0 }),
             ));
 
-            relative_offset += 4;
+            relative_offset += next_inst_size;
             continue;
         }
 
         let source = Source {
             section_index,
-            offset_range: AddressRange::from(relative_offset as u64..relative_offset as u64 + 4),
+            offset_range: AddressRange::from(relative_offset as u64..(relative_offset + inst_size) as u64),
         };
 
-        relative_offset += 4;
+        relative_offset += inst_size;
+
+        if raw_inst & INSTRUCTION_SBRK_MASK == INSTRUCTION_SBRK {
+            let Some(dst) = cast_reg_non_zero(RReg::decode(raw_inst >> 7))? else {
+                return Err(ProgramFromElfError::other(format!(
+                    "found an 'sbrk' instruction with the zero register as its destination at {current_location}"
+                )));
+            };
+
+            let Some(size) = cast_reg_non_zero(RReg::decode(raw_inst >> 15))? else {
+                return Err(ProgramFromElfError::other(format!(
+                    "found an 'sbrk' instruction with the zero register as its size at {current_location}"
+                )));
+            };
+
+            output.push((source, InstExt::Basic(BasicInst::Sbrk { dst, size })));
+            continue;
+        }
 
         // Shadow the `relative_offset` to make sure it's not accidentally used again.
         #[allow(clippy::let_unit_value)]
         #[allow(unused_variables)]
         let relative_offset = ();
 
-        let Some(original_inst) = Inst::decode(raw_inst) else {
+        let Some(original_inst) = decode_instruction(bitness, raw_inst, inst_size) else {
             return Err(ProgramFromElfErrorKind::UnsupportedInstruction {
                 section: section.name().into(),
                 offset: current_location.offset,
@@ -1989,7 +2189,7 @@ fn parse_code_section(
         if let Some(inst) = instruction_overrides.remove(&current_location) {
             output.push((source, inst));
         } else {
-            convert_instruction(section, current_location, original_inst, |inst| {
+            convert_instruction(section, current_location, original_inst, inst_size as u64, bitness, |inst| {
                 output.push((source, inst));
             })?
         }
@@ -1998,6 +2198,25 @@ fn parse_code_section(
     Ok(())
 }
 
+/// Reads a single, possibly compressed, instruction; returns its raw bits and its size in bytes.
+fn read_instruction(text: &[u8], offset: usize) -> Option<(u32, usize)> {
+    let low = u16::from_le_bytes([*text.get(offset)?, *text.get(offset + 1)?]);
+    if Inst::is_compressed(u32::from(low)) {
+        return Some((u32::from(low), 2));
+    }
+
+    let high = u16::from_le_bytes([*text.get(offset + 2)?, *text.get(offset + 3)?]);
+    Some((u32::from(low) | (u32::from(high) << 16), 4))
+}
+
+fn decode_instruction(bitness: Bitness, raw_inst: u32, inst_size: usize) -> Option<Inst> {
+    if inst_size == 2 {
+        Inst::decode_compressed(bitness, raw_inst)
+    } else {
+        Inst::decode(bitness, raw_inst)
+    }
+}
+
 fn split_code_into_basic_blocks(
     jump_targets: &HashSet<SectionTarget>,
     instructions: Vec<(Source, InstExt<SectionTarget, SectionTarget>)>,
@@ -2022,7 +2241,7 @@ fn split_code_into_basic_blocks(
         let (block_section, block_start) = if !is_jump_target {
             // Make sure nothing wants to jump into the middle of this instruction.
             assert!((source.offset_range.start..source.offset_range.end)
-                .step_by(4)
+                .step_by(2)
                 .skip(1)
                 .all(|offset| !jump_targets.contains(&SectionTarget {
                     section_index: source.section_index,
@@ -2182,6 +2401,86 @@ fn resolve_basic_block_references(
     Ok(output)
 }
 
+/// Replaces calls to the `memcpy` and `memset` functions (usually provided by `compiler_builtins`)
+/// with the equivalent VM instructions, which are much faster than the byte-by-byte loops they're compiled into.
+fn lower_bulk_memory_calls(
+    elf: &Elf,
+    section_to_block: &HashMap<SectionTarget, BlockTarget>,
+    all_blocks: &mut [BasicBlock<AnyTarget, BlockTarget>],
+) -> Result<(), ProgramFromElfError> {
+    #[derive(Copy, Clone, Debug)]
+    enum BulkMemoryKind {
+        Memcpy,
+        Memset,
+    }
+
+    let mut functions = Vec::new();
+    for sym in elf.symbols() {
+        if sym.kind() != object::elf::STT_FUNC {
+            continue;
+        }
+
+        let kind = match sym.name() {
+            Some("memcpy") => BulkMemoryKind::Memcpy,
+            Some("memset") => BulkMemoryKind::Memset,
+            _ => continue,
+        };
+
+        let (section, offset) = sym.section_and_offset()?;
+        let target = SectionTarget {
+            section_index: section.index(),
+            offset,
+        };
+
+        if let Some(&block) = section_to_block.get(&target) {
+            functions.push((kind, block, section.index(), offset..offset + sym.size()));
+        }
+    }
+
+    if functions.is_empty() {
+        return Ok(());
+    }
+
+    for block in all_blocks {
+        let (target, next) = match block.next.instruction {
+            ControlInst::Call { target, target_return, .. } => (target, ControlInst::Jump { target: target_return }),
+            ControlInst::Jump { target } => (target, ControlInst::JumpIndirect { base: Reg::RA, offset: 0 }),
+            _ => continue,
+        };
+
+        let Some((kind, _, section_index, range)) = functions.iter().find(|(_, function_block, ..)| *function_block == target) else {
+            continue;
+        };
+
+        // A jump from inside of the function itself is not a tail call.
+        if block.source.section_index == *section_index && range.contains(&block.source.offset_range.start) {
+            continue;
+        }
+
+        log::trace!("Lowering a call to {kind:?} at {}", block.source);
+
+        // The arguments are already in the right registers, and since none of the registers are modified
+        // the destination is still in `a0`, which is exactly what these functions return.
+        let op = match *kind {
+            BulkMemoryKind::Memcpy => BasicInst::Memcpy {
+                dst: Reg::A0,
+                src: Reg::A1,
+                count: Reg::A2,
+            },
+            BulkMemoryKind::Memset => BasicInst::Memset {
+                dst: Reg::A0,
+                value: Reg::A1,
+                count: Reg::A2,
+            },
+        };
+
+        block.ops.push((block.next.source.clone(), op));
+        block.next.instruction = next;
+    }
+
+    Ok(())
+}
+
 fn garbage_collect_reachability(all_blocks: &[BasicBlock<AnyTarget, BlockTarget>], reachability_graph: &mut ReachabilityGraph) -> bool {
     let mut queue_code = VecSet::new();
     let mut queue_data = VecSet::new();
@@ -2906,10 +3205,20 @@ pub enum AnyAnyKind {
     ShiftLogicalLeft,
     ShiftLogicalRight,
     ShiftArithmeticRight,
+    RotateLeft,
+    RotateRight,
 
     Mul,
     MulUpperSignedSigned,
     MulUpperUnsignedUnsigned,
+
+    // These are only available on 64-bit.
+    Add32,
+    ShiftLogicalLeft32,
+    ShiftLogicalRight32,
+    ShiftArithmeticRight32,
+    RotateLeft32,
+    RotateRight32,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
@@ -2919,6 +3228,22 @@ pub enum RegRegKind {
     DivUnsigned,
     Rem,
     RemUnsigned,
+
+    AndInverted,
+    OrInverted,
+    Xnor,
+    Maximum,
+    MaximumUnsigned,
+    Minimum,
+    MinimumUnsigned,
+
+    // These are only available on 64-bit.
+    Sub32,
+    Mul32,
+    Div32,
+    DivUnsigned32,
+    Rem32,
+    RemUnsigned32,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
@@ -2933,6 +3258,8 @@ enum OperationKind {
     ShiftLogicalLeft,
     ShiftLogicalRight,
     ShiftArithmeticRight,
+    RotateLeft,
+    RotateRight,
 
     Mul,
     MulUpperSignedSigned,
@@ -2943,10 +3270,31 @@ enum OperationKind {
     Rem,
     RemUnsigned,
 
+    AndInverted,
+    OrInverted,
+    Xnor,
+    Maximum,
+    MaximumUnsigned,
+    Minimum,
+    MinimumUnsigned,
+
     Eq,
     NotEq,
     SetGreaterOrEqualSigned,
     SetGreaterOrEqualUnsigned,
+
+    Add32,
+    Sub32,
+    Mul32,
+    ShiftLogicalLeft32,
+    ShiftLogicalRight32,
+    ShiftArithmeticRight32,
+    RotateLeft32,
+    RotateRight32,
+    Div32,
+    DivUnsigned32,
+    Rem32,
+    RemUnsigned32,
 }
 
 impl From<AnyAnyKind> for OperationKind {
@@ -2962,9 +3310,17 @@ impl From<AnyAnyKind> for OperationKind {
             AnyAnyKind::ShiftLogicalLeft => Self::ShiftLogicalLeft,
             AnyAnyKind::ShiftLogicalRight => Self::ShiftLogicalRight,
             AnyAnyKind::ShiftArithmeticRight => Self::ShiftArithmeticRight,
+            AnyAnyKind::RotateLeft => Self::RotateLeft,
+            AnyAnyKind::RotateRight => Self::RotateRight,
             AnyAnyKind::Mul => Self::Mul,
             AnyAnyKind::MulUpperSignedSigned => Self::MulUpperSignedSigned,
             AnyAnyKind::MulUpperUnsignedUnsigned => Self::MulUpperUnsignedUnsigned,
+            AnyAnyKind::Add32 => Self::Add32,
+            AnyAnyKind::ShiftLogicalLeft32 => Self::ShiftLogicalLeft32,
+            AnyAnyKind::ShiftLogicalRight32 => Self::ShiftLogicalRight32,
+            AnyAnyKind::ShiftArithmeticRight32 => Self::ShiftArithmeticRight32,
+            AnyAnyKind::RotateLeft32 => Self::RotateLeft32,
+            AnyAnyKind::RotateRight32 => Self::RotateRight32,
         }
     }
 }
@@ -2977,6 +3333,19 @@ impl From<RegRegKind> for OperationKind {
             RegRegKind::DivUnsigned => Self::DivUnsigned,
             RegRegKind::Rem => Self::Rem,
             RegRegKind::RemUnsigned => Self::RemUnsigned,
+            RegRegKind::AndInverted => Self::AndInverted,
+            RegRegKind::OrInverted => Self::OrInverted,
+            RegRegKind::Xnor => Self::Xnor,
+            RegRegKind::Maximum => Self::Maximum,
+            RegRegKind::MaximumUnsigned => Self::MaximumUnsigned,
+            RegRegKind::Minimum => Self::Minimum,
+            RegRegKind::MinimumUnsigned => Self::MinimumUnsigned,
+            RegRegKind::Sub32 => Self::Sub32,
+            RegRegKind::Mul32 => Self::Mul32,
+            RegRegKind::Div32 => Self::Div32,
+            RegRegKind::DivUnsigned32 => Self::DivUnsigned32,
+            RegRegKind::Rem32 => Self::Rem32,
+            RegRegKind::RemUnsigned32 => Self::RemUnsigned32,
         }
     }
 }
@@ -2995,29 +3364,46 @@ impl From<BranchKind> for OperationKind {
 }
 
 impl OperationKind {
-    fn apply_const(self, lhs: i32, rhs: i32) -> i32 {
+    fn apply_const(self, bitness: Bitness, lhs: i32, rhs: i32) -> Option<i32> {
+        match bitness {
+            Bitness::B32 => Some(self.apply_const_32(lhs, rhs)),
+            Bitness::B64 => self.apply_const_64(lhs, rhs),
+        }
+    }
+
+    fn apply_const_32(self, lhs: i32, rhs: i32) -> i32 {
         use polkavm_common::operation::*;
         #[allow(clippy::unnecessary_cast)]
         match self {
-            Self::Add => lhs.wrapping_add(rhs),
-            Self::Sub => lhs.wrapping_sub(rhs),
+            Self::Add | Self::Add32 => lhs.wrapping_add(rhs),
+            Self::Sub | Self::Sub32 => lhs.wrapping_sub(rhs),
             Self::And => lhs & rhs,
             Self::Or => lhs | rhs,
             Self::Xor => lhs ^ rhs,
             Self::SetLessThanUnsigned => i32::from((lhs as u32) < (rhs as u32)),
             Self::SetLessThanSigned => i32::from((lhs as i32) < (rhs as i32)),
-            Self::ShiftLogicalLeft => ((lhs as u32).wrapping_shl(rhs as u32)) as i32,
-            Self::ShiftLogicalRight => ((lhs as u32).wrapping_shr(rhs as u32)) as i32,
-            Self::ShiftArithmeticRight => (lhs as i32).wrapping_shr(rhs as u32),
+            Self::ShiftLogicalLeft | Self::ShiftLogicalLeft32 => ((lhs as u32).wrapping_shl(rhs as u32)) as i32,
+            Self::ShiftLogicalRight | Self::ShiftLogicalRight32 => ((lhs as u32).wrapping_shr(rhs as u32)) as i32,
+            Self::ShiftArithmeticRight | Self::ShiftArithmeticRight32 => (lhs as i32).wrapping_shr(rhs as u32),
+            Self::RotateLeft | Self::RotateLeft32 => (lhs as u32).rotate_left(rhs as u32) as i32,
+            Self::RotateRight | Self::RotateRight32 => (lhs as u32).rotate_right(rhs as u32) as i32,
 
-            Self::Mul => (lhs as i32).wrapping_mul(rhs as i32),
+            Self::Mul | Self::Mul32 => (lhs as i32).wrapping_mul(rhs as i32),
             Self::MulUpperSignedSigned => mulh(lhs, rhs),
             Self::MulUpperSignedUnsigned => mulhsu(lhs, rhs as u32),
             Self::MulUpperUnsignedUnsigned => mulhu(lhs as u32, rhs as u32) as i32,
-            Self::Div => div(lhs, rhs),
-            Self::DivUnsigned => divu(lhs as u32, rhs as u32) as i32,
-            Self::Rem => rem(lhs, rhs),
-            Self::RemUnsigned => remu(lhs as u32, rhs as u32) as i32,
+            Self::Div | Self::Div32 => div(lhs, rhs),
+            Self::DivUnsigned | Self::DivUnsigned32 => divu(lhs as u32, rhs as u32) as i32,
+            Self::Rem | Self::Rem32 => rem(lhs, rhs),
+            Self::RemUnsigned | Self::RemUnsigned32 => remu(lhs as u32, rhs as u32) as i32,
+
+            Self::AndInverted => lhs & !rhs,
+            Self::OrInverted => lhs | !rhs,
+            Self::Xnor => !(lhs ^ rhs),
+            Self::Maximum => lhs.max(rhs),
+            Self::MaximumUnsigned => (lhs as u32).max(rhs as u32) as i32,
+            Self::Minimum => lhs.min(rhs),
+            Self::MinimumUnsigned => (lhs as u32).min(rhs as u32) as i32,
 
             Self::Eq => i32::from(lhs == rhs),
             Self::NotEq => i32::from(lhs != rhs),
@@ -3026,17 +3412,76 @@ impl OperationKind {
         }
     }
 
-    fn apply(self, lhs: RegValue, rhs: RegValue) -> Option<RegValue> {
+    /// Evaluates the operation on 64-bit registers; returns `None` if the result doesn't fit in a sign-extended `i32`.
+    fn apply_const_64(self, lhs: i32, rhs: i32) -> Option<i32> {
+        use polkavm_common::operation::*;
+        let lhs = i64::from(lhs);
+        let rhs = i64::from(rhs);
+        let value = match self {
+            Self::Add => lhs.wrapping_add(rhs),
+            Self::Sub => lhs.wrapping_sub(rhs),
+            Self::And => lhs & rhs,
+            Self::Or => lhs | rhs,
+            Self::Xor => lhs ^ rhs,
+            Self::SetLessThanUnsigned => i64::from((lhs as u64) < (rhs as u64)),
+            Self::SetLessThanSigned => i64::from(lhs < rhs),
+            Self::ShiftLogicalLeft => ((lhs as u64).wrapping_shl(rhs as u32)) as i64,
+            Self::ShiftLogicalRight => ((lhs as u64).wrapping_shr(rhs as u32)) as i64,
+            Self::ShiftArithmeticRight => lhs.wrapping_shr(rhs as u32),
+            Self::RotateLeft => (lhs as u64).rotate_left(rhs as u32) as i64,
+            Self::RotateRight => (lhs as u64).rotate_right(rhs as u32) as i64,
+
+            Self::Mul => lhs.wrapping_mul(rhs),
+            Self::MulUpperSignedSigned => mulh64(lhs, rhs),
+            Self::MulUpperSignedUnsigned => mulhsu64(lhs, rhs as u64),
+            Self::MulUpperUnsignedUnsigned => mulhu64(lhs as u64, rhs as u64) as i64,
+            Self::Div => div64(lhs, rhs),
+            Self::DivUnsigned => divu64(lhs as u64, rhs as u64) as i64,
+            Self::Rem => rem64(lhs, rhs),
+            Self::RemUnsigned => remu64(lhs as u64, rhs as u64) as i64,
+
+            Self::AndInverted => lhs & !rhs,
+            Self::OrInverted => lhs | !rhs,
+            Self::Xnor => !(lhs ^ rhs),
+            Self::Maximum => lhs.max(rhs),
+            Self::MaximumUnsigned => (lhs as u64).max(rhs as u64) as i64,
+            Self::Minimum => lhs.min(rhs),
+            Self::MinimumUnsigned => (lhs as u64).min(rhs as u64) as i64,
+
+            Self::Eq => i64::from(lhs == rhs),
+            Self::NotEq => i64::from(lhs != rhs),
+            Self::SetGreaterOrEqualUnsigned => i64::from((lhs as u64) >= (rhs as u64)),
+            Self::SetGreaterOrEqualSigned => i64::from(lhs >= rhs),
+
+            // The word-sized operations sign-extend their 32-bit result, so they always fit.
+            Self::Add32
+            | Self::Sub32
+            | Self::Mul32
+            | Self::ShiftLogicalLeft32
+            | Self::ShiftLogicalRight32
+            | Self::ShiftArithmeticRight32
+            | Self::RotateLeft32
+            | Self::RotateRight32
+            | Self::Div32
+            | Self::DivUnsigned32
+            | Self::Rem32
+            | Self::RemUnsigned32 => return Some(self.apply_const_32(lhs as i32, rhs as i32)),
+        };
+
+        i32::try_from(value).ok()
+    }
+
+    fn apply(self, bitness: Bitness, lhs: RegValue, rhs: RegValue) -> Option<RegValue> {
         use OperationKind as O;
         use RegValue::Constant as C;
 
         #[rustfmt::skip]
         let value = match (self, lhs, rhs) {
             (_, C(lhs), C(rhs)) => {
-                C(self.apply_const(lhs, rhs))
+                C(self.apply_const(bitness, lhs, rhs)?)
             },
             (O::Add | O::Sub, RegValue::DataAddress(lhs), C(rhs)) => {
-                RegValue::DataAddress(lhs.map_offset_i32(|lhs| self.apply_const(lhs, rhs)))
+                RegValue::DataAddress(lhs.map_offset_i32(|lhs| self.apply_const_32(lhs, rhs)))
             }
 
             // (x == x) = 1
@@ -3047,6 +3492,8 @@ impl OperationKind {
             (O::And,                    lhs, rhs) if lhs == rhs => lhs,
             // x | x = x
             (O::Or,                     lhs, rhs) if lhs == rhs => lhs,
+            // max(x, x) = x, min(x, x) = x
+            (O::Maximum | O::MaximumUnsigned | O::Minimum | O::MinimumUnsigned, lhs, rhs) if lhs == rhs => lhs,
 
             // x + 0 = x
             (O::Add,                    lhs, C(0)) => lhs,
@@ -3069,6 +3516,10 @@ impl OperationKind {
             (O::ShiftLogicalRight,      lhs, C(0)) => lhs,
             // x >> 0 = x
             (O::ShiftArithmeticRight,   lhs, C(0)) => lhs,
+            // x <<r 0 = x
+            (O::RotateLeft,             lhs, C(0)) => lhs,
+            // x >>r 0 = x
+            (O::RotateRight,            lhs, C(0)) => lhs,
             // x % 0 = x
             (O::Rem,                    lhs, C(0)) => lhs,
             // x % 0 = x
@@ -3094,13 +3545,37 @@ impl OperationKind {
     }
 }
 
+impl UnaryKind {
+    fn apply_const(self, bitness: Bitness, value: i32) -> Option<i32> {
+        let value = match (self, bitness) {
+            (Self::CountLeadingZeroBits | Self::CountLeadingZeroBits32, Bitness::B32) | (Self::CountLeadingZeroBits32, Bitness::B64) => {
+                value.leading_zeros() as i32
+            }
+            (Self::CountTrailingZeroBits | Self::CountTrailingZeroBits32, Bitness::B32) | (Self::CountTrailingZeroBits32, Bitness::B64) => {
+                value.trailing_zeros() as i32
+            }
+            (Self::CountSetBits | Self::CountSetBits32, Bitness::B32) | (Self::CountSetBits32, Bitness::B64) => value.count_ones() as i32,
+            (Self::CountLeadingZeroBits, Bitness::B64) => i64::from(value).leading_zeros() as i32,
+            (Self::CountTrailingZeroBits, Bitness::B64) => i64::from(value).trailing_zeros() as i32,
+            (Self::CountSetBits, Bitness::B64) => i64::from(value).count_ones() as i32,
+            (Self::SignExtend8, _) => i32::from(value as i8),
+            (Self::SignExtend16, _) => i32::from(value as i16),
+            (Self::ZeroExtend16, _) => i32::from(value as u16),
+            (Self::ReverseByte, Bitness::B32) => value.swap_bytes(),
+            (Self::ReverseByte, Bitness::B64) => return i32::try_from(i64::from(value).swap_bytes()).ok(),
+        };
+
+        Some(value)
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 enum RegValue {
     InputReg(Reg, BlockTarget),
     CodeAddress(BlockTarget),
     DataAddress(SectionTarget),
     Constant(i32),
-    Unknown { unique: u64, bits_used: u32 },
+    Unknown { unique: u64, bits_used: u64 },
 }
 
 impl RegValue {
@@ -3119,10 +3594,10 @@ impl RegValue {
         }
     }
 
-    fn bits_used(self) -> u32 {
+    fn bits_used(self) -> u64 {
         match self {
             RegValue::InputReg(..) | RegValue::CodeAddress(..) | RegValue::DataAddress(..) => !0,
-            RegValue::Constant(value) => value as u32,
+            RegValue::Constant(value) => i64::from(value) as u64,
             RegValue::Unknown { bits_used, .. } => bits_used,
         }
     }
@@ -3130,12 +3605,14 @@ impl RegValue {
 
 #[derive(Clone)]
 struct BlockRegs {
+    bitness: Bitness,
     regs: [RegValue; Reg::ALL.len()],
 }
 
 impl BlockRegs {
-    fn new(source_block: BlockTarget) -> Self {
+    fn new(bitness: Bitness, source_block: BlockTarget) -> Self {
         BlockRegs {
+            bitness,
             regs: Reg::ALL.map(|reg| RegValue::InputReg(reg, source_block)),
         }
     }
@@ -3151,6 +3628,13 @@ impl BlockRegs {
         self.regs[reg as usize] = value;
     }
 
+    fn shift_mask(&self) -> u32 {
+        match self.bitness {
+            Bitness::B32 => 31,
+            Bitness::B64 => 63,
+        }
+    }
+
     fn simplify_control_instruction(&self, instruction: ControlInst<BlockTarget>) -> Option<ControlInst<BlockTarget>> {
         match instruction {
             ControlInst::JumpIndirect { base, offset: 0 } => {
@@ -3171,7 +3655,7 @@ impl BlockRegs {
 
                 let src1_value = self.get_reg(src1);
                 let src2_value = self.get_reg(src2);
-                if let Some(value) = OperationKind::from(kind).apply(src1_value, src2_value) {
+                if let Some(value) = OperationKind::from(kind).apply(self.bitness, src1_value, src2_value) {
                     match value {
                         RegValue::Constant(0) => {
                             return Some(ControlInst::Jump { target: target_false });
@@ -3220,7 +3704,7 @@ impl BlockRegs {
             BasicInst::RegReg { kind, dst, src1, src2 } => {
                 let src1_value = self.get_reg(src1);
                 let src2_value = self.get_reg(src2);
-                if let Some(value) = OperationKind::from(kind).apply(src1_value, src2_value) {
+                if let Some(value) = OperationKind::from(kind).apply(self.bitness, src1_value, src2_value) {
                     if let Some(new_instruction) = value.to_instruction(dst) {
                         if new_instruction != instruction {
                             return Some(new_instruction);
@@ -3231,7 +3715,7 @@ impl BlockRegs {
             BasicInst::AnyAny { kind, dst, src1, src2 } => {
                 let src1_value = self.get_reg(src1);
                 let src2_value = self.get_reg(src2);
-                if let Some(value) = OperationKind::from(kind).apply(src1_value, src2_value) {
+                if let Some(value) = OperationKind::from(kind).apply(self.bitness, src1_value, src2_value) {
                     if value == self.get_reg(dst) {
                         return Some(BasicInst::Nop);
                     }
@@ -3243,8 +3727,20 @@ impl BlockRegs {
                     }
                 }
 
+                // The word-sized shifts and rotates don't have variants which take an immediate as the first operand.
+                let can_have_imm_src1 = !matches!(
+                    kind,
+                    AnyAnyKind::ShiftLogicalLeft32
+                        | AnyAnyKind::ShiftLogicalRight32
+                        | AnyAnyKind::ShiftArithmeticRight32
+                        | AnyAnyKind::RotateLeft32
+                        | AnyAnyKind::RotateRight32
+                );
+
+                // If both operands are constants and yet the operation couldn't be evaluated then the result
+                // doesn't fit in an immediate, so keep one of the operands in a register.
                 if let RegValue::Constant(value) = src1_value {
-                    if matches!(src1, RegImm::Reg(_)) {
+                    if matches!(src1, RegImm::Reg(_)) && can_have_imm_src1 && !matches!(src2_value, RegValue::Constant(..)) {
                         return Some(BasicInst::AnyAny {
                             kind,
                             dst,
@@ -3255,7 +3751,7 @@ impl BlockRegs {
                 }
 
                 if let RegValue::Constant(value) = src2_value {
-                    if matches!(src2, RegImm::Reg(_)) {
+                    if matches!(src2, RegImm::Reg(_)) && !matches!(src1, RegImm::Imm(..)) {
                         return Some(BasicInst::AnyAny {
                             kind,
                             dst,
@@ -3281,6 +3777,13 @@ impl BlockRegs {
                     });
                 }
             }
+            BasicInst::Unary { kind, dst, src } => {
+                if let RegValue::Constant(value) = self.get_reg(src) {
+                    if let Some(imm) = kind.apply_const(self.bitness, value) {
+                        return Some(BasicInst::LoadImmediate { dst, imm });
+                    }
+                }
+            }
             BasicInst::LoadIndirect { kind, dst, base, offset } => {
                 if let RegValue::DataAddress(base) = self.get_reg(base) {
                     return Some(BasicInst::LoadAbsolute {
@@ -3336,7 +3839,12 @@ impl BlockRegs {
         None
     }
 
-    fn set_reg_unknown(&mut self, dst: Reg, unknown_counter: &mut u64, bits_used: u32) {
+    fn set_reg_unknown(&mut self, dst: Reg, unknown_counter: &mut u64, bits_used: u64) {
+        let bits_used = match self.bitness {
+            Bitness::B32 => bits_used & u64::from(u32::MAX),
+            Bitness::B64 => bits_used,
+        };
+
         if bits_used == 0 {
             self.set_reg(dst, RegValue::Constant(0));
             return;
@@ -3433,7 +3941,7 @@ impl BlockRegs {
                 src2: RegImm::Imm(src2),
             } => {
                 let src1_value = self.get_reg(src1);
-                self.set_reg_unknown(dst, unknown_counter, src1_value.bits_used() >> src2);
+                self.set_reg_unknown(dst, unknown_counter, src1_value.bits_used() >> (src2 & self.shift_mask()));
             }
             BasicInst::AnyAny {
                 kind: AnyAnyKind::ShiftLogicalLeft,
@@ -3442,7 +3950,7 @@ impl BlockRegs {
                 src2: RegImm::Imm(src2),
             } => {
                 let src1_value = self.get_reg(src1);
-                self.set_reg_unknown(dst, unknown_counter, src1_value.bits_used() << src2);
+                self.set_reg_unknown(dst, unknown_counter, src1_value.bits_used() << (src2 & self.shift_mask()));
             }
             BasicInst::AnyAny {
                 kind: AnyAnyKind::SetLessThanSigned | AnyAnyKind::SetLessThanUnsigned,
@@ -3457,15 +3965,49 @@ impl BlockRegs {
             | BasicInst::LoadIndirect {
                 kind: LoadKind::U8, dst, ..
             } => {
-                self.set_reg_unknown(dst, unknown_counter, u32::from(u8::MAX));
+                self.set_reg_unknown(dst, unknown_counter, u64::from(u8::MAX));
             }
             BasicInst::LoadAbsolute {
                 kind: LoadKind::U16, dst, ..
             }
             | BasicInst::LoadIndirect {
                 kind: LoadKind::U16, dst, ..
+            }
+            | BasicInst::Unary {
+                kind: UnaryKind::ZeroExtend16,
+                dst,
+                ..
             } => {
-                self.set_reg_unknown(dst, unknown_counter, u32::from(u16::MAX));
+                self.set_reg_unknown(dst, unknown_counter, u64::from(u16::MAX));
+            }
+            BasicInst::LoadAbsolute {
+                kind: LoadKind::U32, dst, ..
+            }
+            | BasicInst::LoadIndirect {
+                kind: LoadKind::U32, dst, ..
+            } => {
+                self.set_reg_unknown(dst, unknown_counter, u64::from(u32::MAX));
+            }
+            BasicInst::Unary {
+                kind: UnaryKind::CountLeadingZeroBits | UnaryKind::CountTrailingZeroBits | UnaryKind::CountSetBits,
+                dst,
+                ..
+            } => {
+                // The result is at most 32 (or 64 on 64-bit).
+                let bits_used = match self.bitness {
+                    Bitness::B32 => 0b111111,
+                    Bitness::B64 => 0b1111111,
+                };
+
+                self.set_reg_unknown(dst, unknown_counter, bits_used);
+            }
+            BasicInst::Unary {
+                kind: UnaryKind::CountLeadingZeroBits32 | UnaryKind::CountTrailingZeroBits32 | UnaryKind::CountSetBits32,
+                dst,
+                ..
+            } => {
+                // The result is at most 32.
+                self.set_reg_unknown(dst, unknown_counter, 0b111111);
             }
             _ => {
                 for dst in instruction.dst_mask(imports) {
@@ -3523,10 +4065,22 @@ fn perform_constant_propagation(
                 let section = elf.section_by_index(target.section_index);
                 if section.is_allocated() && !section.is_writable() {
                     let value = match kind {
+                        LoadKind::I32 => section
+                            .data()
+                            .get(target.offset as usize..target.offset as usize + 4)
+                            .map(|xs| i32::from_le_bytes([xs[0], xs[1], xs[2], xs[3]])),
                         LoadKind::U32 => section
                             .data()
                             .get(target.offset as usize..target.offset as usize + 4)
-                            .map(|xs| u32::from_le_bytes([xs[0], xs[1], xs[2], xs[3]]) as i32),
+                            .map(|xs| u32::from_le_bytes([xs[0], xs[1], xs[2], xs[3]]) as i32)
+                            // On 64-bit this is zero-extended, so it can only be represented as an immediate if it's positive.
+                            .filter(|&value| regs.bitness == Bitness::B32 || value >= 0),
+                        LoadKind::U64 => section
+                            .data()
+                            .get(target.offset as usize..target.offset as usize + 8)
+                            .and_then(|xs| {
+                                i32::try_from(i64::from_le_bytes([xs[0], xs[1], xs[2], xs[3], xs[4], xs[5], xs[6], xs[7]])).ok()
+                            }),
                         LoadKind::U16 => section
                             .data()
                             .get(target.offset as usize..target.offset as usize + 2)
@@ -3682,6 +4236,7 @@ fn perform_load_address_and_jump_fusion(all_blocks: &mut [BasicBlock<AnyTarget,
 fn optimize_program(
     config: &Config,
     elf: &Elf,
+    bitness: Bitness,
     imports: &[Import],
     all_blocks: &mut [BasicBlock<AnyTarget, BlockTarget>],
     reachability_graph: &mut ReachabilityGraph,
@@ -3735,7 +4290,7 @@ fn optimize_program(
     let mut unknown_counter = 0;
     let mut regs_for_block = Vec::with_capacity(all_blocks.len());
     for current in (0..all_blocks.len()).map(BlockTarget::from_raw) {
-        regs_for_block.push(BlockRegs::new(current))
+        regs_for_block.push(BlockRegs::new(bitness, current))
     }
 
     let mut registers_needed_for_block = Vec::with_capacity(all_blocks.len());
@@ -4006,6 +4561,7 @@ fn merge_consecutive_fallthrough_blocks(
 
 fn spill_fake_registers(
     section_regspill: SectionIndex,
+    bitness: Bitness,
     all_blocks: &mut [BasicBlock<AnyTarget, BlockTarget>],
     reachability_graph: &mut ReachabilityGraph,
     imports: &[Import],
@@ -4075,6 +4631,11 @@ fn spill_fake_registers(
         }
     }
 
+    let (slot_size, slot_load_kind, slot_store_kind) = match bitness {
+        Bitness::B32 => (4, LoadKind::U32, StoreKind::U32),
+        Bitness::B64 => (8, LoadKind::U64, StoreKind::U64),
+    };
+
     let fake_mask = RegMask::fake();
     for current in used_blocks {
         let block = &mut all_blocks[current.index()];
@@ -4233,10 +4794,10 @@ fn spill_fake_registers(
                     (Some(dst_reg), None) => {
                         let dst_reg = Reg::from_usize(dst_reg.hw_enc()).unwrap();
                         let src_slot = src.as_stack().unwrap();
-                        let offset = src_slot.index() * 4;
-                        *regspill_size = core::cmp::max(*regspill_size, offset + 4);
+                        let offset = src_slot.index() * slot_size;
+                        *regspill_size = core::cmp::max(*regspill_size, offset + slot_size);
                         BasicInst::LoadAbsolute {
-                            kind: LoadKind::U32,
+                            kind: slot_load_kind,
                             dst: dst_reg,
                             target: SectionTarget {
                                 section_index: section_regspill,
@@ -4247,10 +4808,10 @@ fn spill_fake_registers(
                     (None, Some(src_reg)) => {
                         let src_reg = Reg::from_usize(src_reg.hw_enc()).unwrap();
                         let dst_slot = dst.as_stack().unwrap();
-                        let offset = dst_slot.index() * 4;
-                        *regspill_size = core::cmp::max(*regspill_size, offset + 4);
+                        let offset = dst_slot.index() * slot_size;
+                        *regspill_size = core::cmp::max(*regspill_size, offset + slot_size);
                         BasicInst::StoreAbsolute {
-                            kind: StoreKind::U32,
+                            kind: slot_store_kind,
                             src: src_reg.into(),
                             target: SectionTarget {
                                 section_index: section_regspill,
@@ -4984,6 +5545,7 @@ fn emit_code(
     used_imports: &HashSet<u32>,
     jump_target_for_block: &[Option<JumpTarget>],
     is_optimized: bool,
+    bitness: Bitness,
 ) -> Result<Vec<(SourceStack, Instruction)>, ProgramFromElfError> {
     use polkavm_common::program::Reg as PReg;
     fn conv_reg(reg: Reg) -> PReg {
@@ -5076,7 +5638,9 @@ fn emit_code(
                         {
                             LoadKind::I8 => load_i8,
                             LoadKind::I16 => load_i16,
+                            LoadKind::I32 => load_i32,
                             LoadKind::U32 => load_u32,
+                            LoadKind::U64 => load_u64,
                             LoadKind::U8 => load_u8,
                             LoadKind::U16 => load_u16,
                         }
@@ -5090,6 +5654,7 @@ fn emit_code(
                                 args = (conv_reg(src), target),
                                 kind = kind,
                                 {
+                                    StoreKind::U64 => store_u64,
                                     StoreKind::U32 => store_u32,
                                     StoreKind::U16 => store_u16,
                                     StoreKind::U8 => store_u8,
@@ -5101,6 +5666,7 @@ fn emit_code(
                                 args = (value, target),
                                 kind = kind,
                                 {
+                                    StoreKind::U64 => store_imm_u64,
                                     StoreKind::U32 => store_imm_u32,
                                     StoreKind::U16 => store_imm_u16,
                                     StoreKind::U8 => store_imm_u8,
@@ -5116,7 +5682,9 @@ fn emit_code(
                         {
                             LoadKind::I8 => load_indirect_i8,
                             LoadKind::I16 => load_indirect_i16,
+                            LoadKind::I32 => load_indirect_i32,
                             LoadKind::U32 => load_indirect_u32,
+                            LoadKind::U64 => load_indirect_u64,
                             LoadKind::U8 => load_indirect_u8,
                             LoadKind::U16 => load_indirect_u16,
                         }
@@ -5128,6 +5696,7 @@ fn emit_code(
                             args = (conv_reg(src), conv_reg(base), offset as u32),
                             kind = kind,
                             {
+                                StoreKind::U64 => store_indirect_u64,
                                 StoreKind::U32 => store_indirect_u32,
                                 StoreKind::U16 => store_indirect_u16,
                                 StoreKind::U8 => store_indirect_u8,
@@ -5139,6 +5708,7 @@ fn emit_code(
                             args = (conv_reg(base), offset as u32, value),
                             kind = kind,
                             {
+                                StoreKind::U64 => store_imm_indirect_u64,
                                 StoreKind::U32 => store_imm_indirect_u32,
                                 StoreKind::U16 => store_imm_indirect_u16,
                                 StoreKind::U8 => store_imm_indirect_u8,
@@ -5186,6 +5756,19 @@ fn emit_code(
                             K::DivUnsigned => div_unsigned,
                             K::Rem => rem_signed,
                             K::RemUnsigned => rem_unsigned,
+                            K::AndInverted => and_inverted,
+                            K::OrInverted => or_inverted,
+                            K::Xnor => xnor,
+                            K::Maximum => maximum,
+                            K::MaximumUnsigned => maximum_unsigned,
+                            K::Minimum => minimum,
+                            K::MinimumUnsigned => minimum_unsigned,
+                            K::Sub32 => sub_32,
+                            K::Mul32 => mul_32,
+                            K::Div32 => div_signed_32,
+                            K::DivUnsigned32 => div_unsigned_32,
+                            K::Rem32 => rem_signed_32,
+                            K::RemUnsigned32 => rem_unsigned_32,
                         }
                     }
                 }
@@ -5207,16 +5790,29 @@ fn emit_code(
                                     K::Xor => xor,
                                     K::ShiftLogicalRight => shift_logical_right,
                                     K::ShiftArithmeticRight => shift_arithmetic_right,
+                                    K::RotateLeft => rotate_left,
+                                    K::RotateRight => rotate_right,
                                     K::Or => or,
                                     K::And => and,
                                     K::Mul => mul,
                                     K::MulUpperSignedSigned => mul_upper_signed_signed,
                                     K::MulUpperUnsignedUnsigned => mul_upper_unsigned_unsigned,
+                                    K::Add32 => add_32,
+                                    K::ShiftLogicalLeft32 => shift_logical_left_32,
+                                    K::ShiftLogicalRight32 => shift_logical_right_32,
+                                    K::ShiftArithmeticRight32 => shift_arithmetic_right_32,
+                                    K::RotateLeft32 => rotate_left_32,
+                                    K::RotateRight32 => rotate_right_32,
                                 }
                             }
                         }
                         (RegImm::Reg(src1), RegImm::Imm(src2)) => {
                             let src1 = conv_reg(src1);
+                            let shift_mask = match bitness {
+                                Bitness::B32 => 31,
+                                Bitness::B64 => 63,
+                            };
+
                             match kind {
                                 K::Add if src2 == 0 => I::move_reg(dst, src1),
                                 K::Add => I::add_imm(dst, src1, src2),
@@ -5227,11 +5823,19 @@ fn emit_code(
                                 K::Xor => I::xor_imm(dst, src1, src2),
                                 K::ShiftLogicalRight => I::shift_logical_right_imm(dst, src1, src2),
                                 K::ShiftArithmeticRight => I::shift_arithmetic_right_imm(dst, src1, src2),
+                                K::RotateLeft => I::rotate_right_imm(dst, src1, src2.wrapping_neg() & shift_mask),
+                                K::RotateRight => I::rotate_right_imm(dst, src1, src2),
                                 K::Or => I::or_imm(dst, src1, src2),
                                 K::And => I::and_imm(dst, src1, src2),
                                 K::Mul => I::mul_imm(dst, src1, src2),
                                 K::MulUpperSignedSigned => I::mul_upper_signed_signed_imm(dst, src1, src2),
                                 K::MulUpperUnsignedUnsigned => I::mul_upper_unsigned_unsigned_imm(dst, src1, src2),
+                                K::Add32 => I::add_imm_32(dst, src1, src2),
+                                K::ShiftLogicalLeft32 => I::shift_logical_left_imm_32(dst, src1, src2),
+                                K::ShiftLogicalRight32 => I::shift_logical_right_imm_32(dst, src1, src2),
+                                K::ShiftArithmeticRight32 => I::shift_arithmetic_right_imm_32(dst, src1, src2),
+                                K::RotateLeft32 => I::rotate_right_imm_32(dst, src1, src2.wrapping_neg() & 31),
+                                K::RotateRight32 => I::rotate_right_imm_32(dst, src1, src2),
                             }
                         }
                         (RegImm::Imm(src1), RegImm::Reg(src2)) => {
@@ -5251,17 +5855,54 @@ fn emit_code(
                                 K::SetLessThanUnsigned => I::set_greater_than_unsigned_imm(dst, src2, src1),
                                 K::ShiftLogicalRight => I::shift_logical_right_imm_alt(dst, src2, src1),
                                 K::ShiftArithmeticRight => I::shift_arithmetic_right_imm_alt(dst, src2, src1),
+                                K::RotateLeft => I::rotate_left_imm_alt(dst, src2, src1),
+                                K::RotateRight => I::rotate_right_imm_alt(dst, src2, src1),
+
+                                K::Add32 => I::add_imm_32(dst, src2, src1),
+                                K::ShiftLogicalLeft32
+                                | K::ShiftLogicalRight32
+                                | K::ShiftArithmeticRight32
+                                | K::RotateLeft32
+                                | K::RotateRight32 => {
+                                    return Err(ProgramFromElfError::other(format!(
+                                        "internal error: word-sized shift with an immediate as its first operand: {op:?}"
+                                    )));
+                                }
                             }
                         }
                         (RegImm::Imm(src1), RegImm::Imm(src2)) => {
                             if is_optimized {
                                 unreachable!("internal error: instruction with only constant operands: {op:?}")
                             } else {
-                                I::load_imm(dst, OperationKind::from(kind).apply_const(src1 as i32, src2 as i32) as u32)
+                                let Some(value) = OperationKind::from(kind).apply_const(bitness, src1 as i32, src2 as i32) else {
+                                    return Err(ProgramFromElfError::other(format!(
+                                        "internal error: failed to evaluate an instruction with only constant operands: {op:?}"
+                                    )));
+                                };
+
+                                I::load_imm(dst, value as u32)
                             }
                         }
                     }
                 }
+                BasicInst::Unary { kind, dst, src } => {
+                    codegen! {
+                        args = (conv_reg(dst), conv_reg(src)),
+                        kind = kind,
+                        {
+                            UnaryKind::CountLeadingZeroBits => count_leading_zero_bits,
+                            UnaryKind::CountTrailingZeroBits => count_trailing_zero_bits,
+                            UnaryKind::CountSetBits => count_set_bits,
+                            UnaryKind::SignExtend8 => sign_extend_8,
+                            UnaryKind::SignExtend16 => sign_extend_16,
+                            UnaryKind::ZeroExtend16 => zero_extend_16,
+                            UnaryKind::ReverseByte => reverse_byte,
+                            UnaryKind::CountLeadingZeroBits32 => count_leading_zero_bits_32,
+                            UnaryKind::CountTrailingZeroBits32 => count_trailing_zero_bits_32,
+                            UnaryKind::CountSetBits32 => count_set_bits_32,
+                        }
+                    }
+                }
                 BasicInst::Cmov { kind, dst, src, cond } => {
                     codegen! {
                         args = (conv_reg(dst), conv_reg(src), conv_reg(cond)),
@@ -5276,6 +5917,9 @@ fn emit_code(
                     assert!(used_imports.contains(&syscall));
                     Instruction::ecalli(syscall)
                 }
+                BasicInst::Sbrk { dst, size } => Instruction::sbrk(conv_reg(dst), conv_reg(size)),
+                BasicInst::Memcpy { dst, src, count } => Instruction::memcpy(conv_reg(dst), conv_reg(src), conv_reg(count)),
+                BasicInst::Memset { dst, value, count } => Instruction::memset(conv_reg(dst), conv_reg(value), conv_reg(count)),
                 BasicInst::Nop => {
                     if is_optimized {
                         unreachable!("internal error: a nop instruction was not removed")
@@ -5385,9 +6029,9 @@ fn emit_code(
                         if is_optimized {
                             unreachable!("internal error: branch with only constant operands")
                         } else {
-                            match OperationKind::from(kind).apply_const(src1 as i32, src2 as i32) {
-                                1 => unconditional_jump(target_true),
-                                0 => {
+                            match OperationKind::from(kind).apply_const(bitness, src1 as i32, src2 as i32) {
+                                Some(1) => unconditional_jump(target_true),
+                                Some(0) => {
                                     assert!(can_fallthrough_to_next_block.contains(block_target));
                                     Instruction::fallthrough
                                 }
@@ -5408,15 +6052,11 @@ fn emit_code(
     Ok(code)
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
-enum Bitness {
-    B32,
-}
-
 impl From<Bitness> for u64 {
     fn from(value: Bitness) -> Self {
         match value {
             Bitness::B32 => 4,
+            Bitness::B64 => 8,
         }
     }
 }
@@ -5425,6 +6065,7 @@ impl From<Bitness> for RelocationSize {
     fn from(value: Bitness) -> Self {
         match value {
             Bitness::B32 => RelocationSize::U32,
+            Bitness::B64 => RelocationSize::U64,
         }
     }
 }
@@ -5434,6 +6075,7 @@ pub(crate) enum RelocationSize {
     U8,
     U16,
     U32,
+    U64,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -5532,6 +6174,15 @@ fn harvest_data_relocations(
                     }),
                 )
             }
+            object::RelocationKind::Absolute if relocation.encoding() == object::RelocationEncoding::Generic && relocation.size() == 64 => {
+                (
+                    "R_RISCV_64",
+                    Kind::Set(RelocationKind::Abs {
+                        target,
+                        size: RelocationSize::U64,
+                    }),
+                )
+            }
             object::RelocationKind::Elf(reloc_kind) => match reloc_kind {
                 object::elf::R_RISCV_SET6 => ("R_RISCV_SET6", Kind::Set6 { target }),
                 object::elf::R_RISCV_SUB6 => ("R_RISCV_SUB6", Kind::Sub6 { target }),
@@ -5555,6 +6206,8 @@ fn harvest_data_relocations(
                 object::elf::R_RISCV_SUB16 => ("R_RISCV_SUB16", Kind::Mut(MutOp::Sub, RelocationSize::U16, target)),
                 object::elf::R_RISCV_ADD32 => ("R_RISCV_ADD32", Kind::Mut(MutOp::Add, RelocationSize::U32, target)),
                 object::elf::R_RISCV_SUB32 => ("R_RISCV_SUB32", Kind::Mut(MutOp::Sub, RelocationSize::U32, target)),
+                object::elf::R_RISCV_ADD64 => ("R_RISCV_ADD64", Kind::Mut(MutOp::Add, RelocationSize::U64, target)),
+                object::elf::R_RISCV_SUB64 => ("R_RISCV_SUB64", Kind::Mut(MutOp::Sub, RelocationSize::U64, target)),
                 _ => {
                     return Err(ProgramFromElfError::other(format!(
                         "unsupported relocation in data section '{section_name}': {relocation:?}"
@@ -5668,6 +6321,14 @@ fn read_u32(data: &[u8], relative_address: u64) -> Result<u32, ProgramFromElfErr
     Ok(u32::from_le_bytes([value[0], value[1], value[2], value[3]]))
 }
 
+fn read_u16(data: &[u8], relative_address: u64) -> Result<u16, ProgramFromElfError> {
+    let target_range = relative_address as usize..relative_address as usize + 2;
+    let value = data
+        .get(target_range)
+        .ok_or(ProgramFromElfError::other("out of range relocation"))?;
+    Ok(u16::from_le_bytes([value[0], value[1]]))
+}
+
 fn read_u8(data: &[u8], relative_address: u64) -> Result<u8, ProgramFromElfError> {
     data.get(relative_address as usize)
         .ok_or(ProgramFromElfError::other("out of range relocation"))
@@ -5683,6 +6344,12 @@ fn write_u32(data: &mut [u8], relative_address: u64, value: u32) -> Result<(), P
     Ok(())
 }
 
+fn write_u64(data: &mut [u8], relative_address: u64, value: u64) -> Result<(), ProgramFromElfError> {
+    let value = value.to_le_bytes();
+    data[relative_address as usize..relative_address as usize + 8].copy_from_slice(&value);
+    Ok(())
+}
+
 fn write_u16(data: &mut [u8], relative_address: u64, value: u16) -> Result<(), ProgramFromElfError> {
     let value = value.to_le_bytes();
     data[relative_address as usize + 1] = value[1];
@@ -5692,6 +6359,7 @@ fn write_u16(data: &mut [u8], relative_address: u64, value: u16) -> Result<(), P
 
 fn harvest_code_relocations(
     elf: &Elf,
+    bitness: Bitness,
     section: &Section,
     instruction_overrides: &mut HashMap<SectionTarget, InstExt<SectionTarget, SectionTarget>>,
     data_relocations: &mut BTreeMap<SectionTarget, RelocationKind>,
@@ -5767,6 +6435,15 @@ fn harvest_code_relocations(
                     },
                 );
             }
+            object::RelocationKind::Absolute if relocation.encoding() == object::RelocationEncoding::Generic && relocation.size() == 64 => {
+                data_relocations.insert(
+                    current_location,
+                    RelocationKind::Abs {
+                        target,
+                        size: RelocationSize::U64,
+                    },
+                );
+            }
             object::RelocationKind::Elf(reloc_kind) => {
                 // https://github.com/riscv-non-isa/riscv-elf-psabi-doc/releases
                 match reloc_kind {
@@ -5777,14 +6454,14 @@ fn harvest_code_relocations(
                         };
 
                         let hi_inst_raw = u32::from_le_bytes([xs[0], xs[1], xs[2], xs[3]]);
-                        let Some(hi_inst) = Inst::decode(hi_inst_raw) else {
+                        let Some(hi_inst) = Inst::decode(bitness, hi_inst_raw) else {
                             return Err(ProgramFromElfError::other(format!(
                                 "R_RISCV_CALL_PLT for an unsupported instruction (1st): 0x{hi_inst_raw:08}"
                             )));
                         };
 
                         let lo_inst_raw = u32::from_le_bytes([xs[4], xs[5], xs[6], xs[7]]);
-                        let Some(lo_inst) = Inst::decode(lo_inst_raw) else {
+                        let Some(lo_inst) = Inst::decode(bitness, lo_inst_raw) else {
                             return Err(ProgramFromElfError::other(format!(
                                 "R_RISCV_CALL_PLT for an unsupported instruction (2nd): 0x{lo_inst_raw:08}"
                             )));
@@ -5881,7 +6558,7 @@ fn harvest_code_relocations(
                     }
                     object::elf::R_RISCV_JAL => {
                         let inst_raw = read_u32(section_data, relative_address)?;
-                        let Some(inst) = Inst::decode(inst_raw) else {
+                        let Some(inst) = Inst::decode(bitness, inst_raw) else {
                             return Err(ProgramFromElfError::other(format!(
                                 "R_RISCV_JAL for an unsupported instruction: 0x{inst_raw:08}"
                             )));
@@ -5904,7 +6581,7 @@ fn harvest_code_relocations(
                     }
                     object::elf::R_RISCV_BRANCH => {
                         let inst_raw = read_u32(section_data, relative_address)?;
-                        let Some(inst) = Inst::decode(inst_raw) else {
+                        let Some(inst) = Inst::decode(bitness, inst_raw) else {
                             return Err(ProgramFromElfError::other(format!(
                                 "R_RISCV_BRANCH for an unsupported instruction: 0x{inst_raw:08}"
                             )));
@@ -5934,10 +6611,94 @@ fn harvest_code_relocations(
                             target
                         );
                     }
+                    object::elf::R_RISCV_RVC_JUMP => {
+                        let inst_raw = read_u16(section_data, relative_address)?;
+                        let Some(inst) = Inst::decode_compressed(bitness, inst_raw.into()) else {
+                            return Err(ProgramFromElfError::other(format!(
+                                "R_RISCV_RVC_JUMP for an unsupported instruction: 0x{inst_raw:04x}"
+                            )));
+                        };
+
+                        let Inst::JumpAndLink { dst, .. } = inst else {
+                            return Err(ProgramFromElfError::other(format!(
+                                "R_RISCV_RVC_JUMP for an unsupported instruction: 0x{inst_raw:04x} ({inst:?})"
+                            )));
+                        };
+
+                        let target_return = current_location.add(2);
+                        instruction_overrides.insert(current_location, InstExt::Control(jump_or_call(dst, target, target_return)?));
+
+                        log::trace!(
+                            "  R_RISCV_RVC_JUMP: {}[0x{relative_address:x}] (0x{absolute_address:x} -> {}",
+                            section.name(),
+                            target
+                        );
+                    }
+                    object::elf::R_RISCV_RVC_BRANCH => {
+                        let inst_raw = read_u16(section_data, relative_address)?;
+                        let Some(inst) = Inst::decode_compressed(bitness, inst_raw.into()) else {
+                            return Err(ProgramFromElfError::other(format!(
+                                "R_RISCV_RVC_BRANCH for an unsupported instruction: 0x{inst_raw:04x}"
+                            )));
+                        };
+
+                        let Inst::Branch { kind, src1, src2, .. } = inst else {
+                            return Err(ProgramFromElfError::other(format!(
+                                "R_RISCV_RVC_BRANCH for an unsupported instruction: 0x{inst_raw:04x} ({inst:?})"
+                            )));
+                        };
+
+                        let target_false = current_location.add(2);
+                        instruction_overrides.insert(
+                            current_location,
+                            InstExt::Control(ControlInst::Branch {
+                                kind,
+                                src1: cast_reg_any(src1)?,
+                                src2: cast_reg_any(src2)?,
+                                target_true: target,
+                                target_false,
+                            }),
+                        );
+
+                        log::trace!(
+                            "  R_RISCV_RVC_BRANCH: {}[0x{relative_address:x}] (0x{absolute_address:x} -> {}",
+                            section.name(),
+                            target
+                        );
+                    }
+                    object::elf::R_RISCV_RVC_LUI => {
+                        // This relocation is for a C.LUI.
+                        let inst_raw = read_u16(section_data, relative_address)?;
+                        let Some(inst) = Inst::decode_compressed(bitness, inst_raw.into()) else {
+                            return Err(ProgramFromElfError::other(format!(
+                                "R_RISCV_RVC_LUI for an unsupported instruction: 0x{inst_raw:04x}"
+                            )));
+                        };
+
+                        let Inst::LoadUpperImmediate { dst, value: _ } = inst else {
+                            return Err(ProgramFromElfError::other(format!(
+                                "R_RISCV_RVC_LUI for an unsupported instruction: 0x{inst_raw:04x} ({inst:?})"
+                            )));
+                        };
+
+                        let Some(dst) = cast_reg_non_zero(dst)? else {
+                            return Err(ProgramFromElfError::other("R_RISCV_RVC_LUI with a zero destination register"));
+                        };
+
+                        instruction_overrides.insert(current_location, InstExt::Basic(BasicInst::LoadAddress { dst, target }));
+
+                        log::trace!(
+                            "  R_RISCV_RVC_LUI: {}[0x{relative_address:x}] (0x{absolute_address:x}): -> {}",
+                            section.name(),
+                            target
+                        );
+
+                        continue;
+                    }
                     object::elf::R_RISCV_HI20 => {
                         // This relocation is for a LUI.
                         let inst_raw = read_u32(section_data, relative_address)?;
-                        let Some(inst) = Inst::decode(inst_raw) else {
+                        let Some(inst) = Inst::decode(bitness, inst_raw) else {
                             return Err(ProgramFromElfError::other(format!(
                                 "R_RISCV_HI20 for an unsupported instruction: 0x{inst_raw:08}"
                             )));
@@ -5965,7 +6726,7 @@ fn harvest_code_relocations(
                     }
                     object::elf::R_RISCV_LO12_I => {
                         let inst_raw = read_u32(section_data, relative_address)?;
-                        let Some(inst) = Inst::decode(inst_raw) else {
+                        let Some(inst) = Inst::decode(bitness, inst_raw) else {
                             return Err(ProgramFromElfError::other(format!(
                                 "R_RISCV_LO12_I for an unsupported instruction: 0x{inst_raw:08}"
                             )));
@@ -6014,7 +6775,7 @@ fn harvest_code_relocations(
                     }
                     object::elf::R_RISCV_LO12_S => {
                         let inst_raw = read_u32(section_data, relative_address)?;
-                        let Some(inst) = Inst::decode(inst_raw) else {
+                        let Some(inst) = Inst::decode(bitness, inst_raw) else {
                             return Err(ProgramFromElfError::other(format!(
                                 "R_RISCV_LO12_S for an unsupported instruction: 0x{inst_raw:08}"
                             )));
@@ -6070,10 +6831,10 @@ fn harvest_code_relocations(
     for (relative_lo, (lo_rel_name, relative_hi)) in pcrel_relocations.reloc_pcrel_lo12 {
         let lo_inst_raw = &section_data[relative_lo as usize..][..4];
         let lo_inst_raw = u32::from_le_bytes([lo_inst_raw[0], lo_inst_raw[1], lo_inst_raw[2], lo_inst_raw[3]]);
-        let lo_inst = Inst::decode(lo_inst_raw);
+        let lo_inst = Inst::decode(bitness, lo_inst_raw);
         let hi_inst_raw = &section_data[relative_hi as usize..][..4];
         let hi_inst_raw = u32::from_le_bytes([hi_inst_raw[0], hi_inst_raw[1], hi_inst_raw[2], hi_inst_raw[3]]);
-        let hi_inst = Inst::decode(hi_inst_raw);
+        let hi_inst = Inst::decode(bitness, hi_inst_raw);
 
         let Some((hi_kind, target)) = pcrel_relocations.reloc_pcrel_hi20.get(&relative_hi).copied() else {
             return Err(ProgramFromElfError::other(format!("{lo_rel_name} relocation at '{section_name}'0x{relative_lo:x} targets '{section_name}'0x{relative_hi:x} which doesn't have a R_RISCV_PCREL_HI20 or R_RISCV_GOT_HI20 relocation")));
@@ -6104,7 +6865,7 @@ fn harvest_code_relocations(
 
             match lo_inst {
                 Inst::Load {
-                    kind: LoadKind::U32,
+                    kind: LoadKind::U32 | LoadKind::U64,
                     base,
                     dst,
                     ..
@@ -6268,8 +7029,7 @@ pub fn program_from_elf(config: Config, data: &[u8]) -> Result<ProgramBlob, Prog
         elf.add_empty_data_section(".got");
     }
 
-    // TODO: 64-bit support.
-    let bitness = Bitness::B32;
+    let bitness = if elf.is_64() { Bitness::B64 } else { Bitness::B32 };
 
     let mut sections_ro_data = Vec::new();
     let mut sections_rw_data = Vec::new();
@@ -6362,10 +7122,10 @@ pub fn program_from_elf(config: Config, data: &[u8]) -> Result<ProgramBlob, Prog
     let mut instruction_overrides = HashMap::new();
     for &section_index in &sections_code {
         let section = elf.section_by_index(section_index);
-        harvest_code_relocations(&elf, section, &mut instruction_overrides, &mut relocations)?;
+        harvest_code_relocations(&elf, bitness, section, &mut instruction_overrides, &mut relocations)?;
     }
 
-    let import_metadata = extract_import_metadata(&elf, &sections_import_metadata)?;
+    let import_metadata = extract_import_metadata(&elf, bitness, &sections_import_metadata)?;
     let export_metadata = if let Some(section_index) = section_export_metadata {
         let section = elf.section_by_index(section_index);
         extract_export_metadata(&relocations, section)?
@@ -6389,6 +7149,7 @@ pub fn program_from_elf(config: Config, data: &[u8]) -> Result<ProgramBlob, Prog
                 &relocations,
                 &mut instruction_overrides,
                 &mut instructions,
+                bitness,
             )?;
 
             if instructions.len() > initial_instruction_count {
@@ -6433,16 +7194,18 @@ pub fn program_from_elf(config: Config, data: &[u8]) -> Result<ProgramBlob, Prog
 
     let mut section_to_block = build_section_to_block_map(&all_blocks)?;
     let mut all_blocks = resolve_basic_block_references(&data_sections_set, &section_to_block, &all_blocks)?;
+    lower_bulk_memory_calls(&elf, &section_to_block, &mut all_blocks)?;
     let mut reachability_graph;
     let mut used_blocks;
 
     let mut regspill_size = 0;
     if config.optimize {
         reachability_graph = calculate_reachability(&section_to_block, &all_blocks, &data_sections_set, &export_metadata, &relocations)?;
-        optimize_program(&config, &elf, &import_metadata, &mut all_blocks, &mut reachability_graph);
+        optimize_program(&config, &elf, bitness, &import_metadata, &mut all_blocks, &mut reachability_graph);
         used_blocks = collect_used_blocks(&all_blocks, &reachability_graph);
         spill_fake_registers(
             section_regspill,
+            bitness,
             &mut all_blocks,
             &mut reachability_graph,
             &import_metadata,
@@ -6477,6 +7240,7 @@ pub fn program_from_elf(config: Config, data: &[u8]) -> Result<ProgramBlob, Prog
         used_blocks = (0..all_blocks.len()).map(BlockTarget::from_raw).collect();
         spill_fake_registers(
             section_regspill,
+            bitness,
             &mut all_blocks,
             &mut reachability_graph,
             &import_metadata,
@@ -6600,6 +7364,7 @@ pub fn program_from_elf(config: Config, data: &[u8]) -> Result<ProgramBlob, Prog
         &used_imports,
         &jump_target_for_block,
         config.optimize,
+        bitness,
     )?;
 
     {
@@ -6628,6 +7393,7 @@ pub fn program_from_elf(config: Config, data: &[u8]) -> Result<ProgramBlob, Prog
 
         fn write_generic(size: RelocationSize, data: &mut [u8], relative_address: u64, value: u64) -> Result<(), ProgramFromElfError> {
             match size {
+                RelocationSize::U64 => write_u64(data, relative_address, value),
                 RelocationSize::U32 => {
                     let Ok(value) = u32::try_from(value) else {
                         return Err(ProgramFromElfError::other(
@@ -6824,7 +7590,10 @@ pub fn program_from_elf(config: Config, data: &[u8]) -> Result<ProgramBlob, Prog
 
     log::trace!("Instruction count: {}", code.len());
 
-    let mut builder = ProgramBlobBuilder::new();
+    let mut builder = match bitness {
+        Bitness::B32 => ProgramBlobBuilder::new(),
+        Bitness::B64 => ProgramBlobBuilder::new_64bit(),
+    };
 
     builder.set_bss_size(memory_config.bss_size);
     builder.set_stack_size(memory_config.stack_size);
@@ -6895,7 +7664,7 @@ pub fn program_from_elf(config: Config, data: &[u8]) -> Result<ProgramBlob, Prog
             // TODO: Use a smallvec.
             let mut list = Vec::new();
             for source in source_stack.as_slice() {
-                for offset in (source.offset_range.start..source.offset_range.end).step_by(4) {
+                for offset in (source.offset_range.start..source.offset_range.end).step_by(2) {
                     let target = SectionTarget {
                         section_index: source.section_index,
                         offset,
@@ -7373,3 +8142,30 @@ fn emit_debug_info(builder: &mut ProgramBlobBuilder, locations_for_instruction:
     builder.add_custom_section(program::SECTION_OPT_DEBUG_LINE_PROGRAMS, section_line_programs);
     builder.add_custom_section(program::SECTION_OPT_DEBUG_LINE_PROGRAM_RANGES, section_line_program_ranges);
 }
+
+#[test]
+fn test_link_compressed_program() {
+    use std::io::Read;
+
+    let mut elf = Vec::new();
+    ruzstd::streaming_decoder::StreamingDecoder::new(&mut &include_bytes!("../../../test-data/test-blob-rv32emac.elf.zst")[..])
+        .unwrap()
+        .read_to_end(&mut elf)
+        .unwrap();
+
+    let blob = program_from_elf(Config::default(), &elf).unwrap();
+    let exports: Vec<_> = blob.exports().map(|export| export.unwrap().prototype().name().to_owned()).collect();
+    assert_eq!(exports, ["test_compressed", "increment_counter"]);
+
+    let imports: Vec<_> = blob.imports().map(|import| import.unwrap().prototype().name().to_owned()).collect();
+    assert_eq!(imports, ["multiply_by_2"]);
+
+    // The return address after `c.jalr` and the function pointer both start at code offsets which are
+    // only 2-byte aligned in the ELF file, but they're still encoded as jump table indexes in the blob.
+    assert!(blob.jump_table().all(|target| target.is_ok()));
+    assert_eq!(blob.jump_table().count(), 2);
+
+    // This is the second jump table entry; the encoding is part of the blob format, so it's hardcoded here.
+    let function_pointer = u32::from_le_bytes(blob.rw_data()[..4].try_into().unwrap());
+    assert_eq!(function_pointer, 8);
+}
@@ -81,27 +81,52 @@ impl BranchKind {
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
-#[repr(u8)]
+pub enum Bitness {
+    B32,
+    B64,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
 pub enum LoadKind {
-    I8 = 0b000,
-    I16 = 0b001,
-    U32 = 0b010,
-    U8 = 0b100,
-    U16 = 0b101,
+    I8,
+    I16,
+    I32,
+    U8,
+    U16,
+    U32,
+    U64,
 }
 
 impl LoadKind {
     #[inline(always)]
-    const fn decode(value: u32) -> Option<Self> {
-        match value & 0b111 {
-            0b000 => Some(LoadKind::I8),
-            0b001 => Some(LoadKind::I16),
-            0b010 => Some(LoadKind::U32),
-            0b100 => Some(LoadKind::U8),
-            0b101 => Some(LoadKind::U16),
+    const fn decode(bitness: Bitness, value: u32) -> Option<Self> {
+        match (bitness, value & 0b111) {
+            (_, 0b000) => Some(LoadKind::I8),
+            (_, 0b001) => Some(LoadKind::I16),
+            // On 32-bit there's no difference between a signed and an unsigned 32-bit load.
+            (Bitness::B32, 0b010) => Some(LoadKind::U32),
+            (Bitness::B64, 0b010) => Some(LoadKind::I32),
+            (Bitness::B64, 0b011) => Some(LoadKind::U64),
+            (_, 0b100) => Some(LoadKind::U8),
+            (_, 0b101) => Some(LoadKind::U16),
+            (Bitness::B64, 0b110) => Some(LoadKind::U32),
             _ => None,
         }
     }
+
+    #[cfg(test)]
+    const fn encode(self, bitness: Bitness) -> Option<u32> {
+        match (bitness, self) {
+            (_, LoadKind::I8) => Some(0b000),
+            (_, LoadKind::I16) => Some(0b001),
+            (Bitness::B32, LoadKind::U32) | (Bitness::B64, LoadKind::I32) => Some(0b010),
+            (Bitness::B64, LoadKind::U64) => Some(0b011),
+            (_, LoadKind::U8) => Some(0b100),
+            (_, LoadKind::U16) => Some(0b101),
+            (Bitness::B64, LoadKind::U32) => Some(0b110),
+            (Bitness::B32, LoadKind::I32 | LoadKind::U64) => None,
+        }
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
@@ -110,15 +135,17 @@ pub enum StoreKind {
     U8 = 0b000,
     U16 = 0b001,
     U32 = 0b010,
+    U64 = 0b011,
 }
 
 impl StoreKind {
     #[inline(always)]
-    const fn decode(value: u32) -> Option<Self> {
-        match value & 0b111 {
-            0b000 => Some(StoreKind::U8),
-            0b001 => Some(StoreKind::U16),
-            0b010 => Some(StoreKind::U32),
+    const fn decode(bitness: Bitness, value: u32) -> Option<Self> {
+        match (bitness, value & 0b111) {
+            (_, 0b000) => Some(StoreKind::U8),
+            (_, 0b001) => Some(StoreKind::U16),
+            (_, 0b010) => Some(StoreKind::U32),
+            (Bitness::B64, 0b011) => Some(StoreKind::U64),
             _ => None,
         }
     }
@@ -137,6 +164,14 @@ pub enum RegImmKind {
     ShiftLogicalLeft,
     ShiftLogicalRight,
     ShiftArithmeticRight,
+    RotateRight,
+
+    // These are only available on 64-bit.
+    Add32,
+    ShiftLogicalLeft32,
+    ShiftLogicalRight32,
+    ShiftArithmeticRight32,
+    RotateRight32,
 }
 
 impl RegImmKind {
@@ -175,6 +210,46 @@ pub enum RegRegKind {
     DivUnsigned = 0b01101,
     Rem = 0b01110,
     RemUnsigned = 0b01111,
+
+    AndInverted = 0b0010111,
+    OrInverted = 0b0010110,
+    Xnor = 0b0010100,
+    Maximum = 0b0101110,
+    MaximumUnsigned = 0b0101111,
+    Minimum = 0b0101100,
+    MinimumUnsigned = 0b0101101,
+    RotateLeft = 0b1010001,
+    RotateRight = 0b1010101,
+
+    // These are only available on 64-bit.
+    Add32 = 0b10000000,
+    Sub32 = 0b10010000,
+    ShiftLogicalLeft32 = 0b10000001,
+    ShiftLogicalRight32 = 0b10000101,
+    ShiftArithmeticRight32 = 0b10010101,
+    Mul32 = 0b10001000,
+    Div32 = 0b10001100,
+    DivUnsigned32 = 0b10001101,
+    Rem32 = 0b10001110,
+    RemUnsigned32 = 0b10001111,
+    RotateLeft32 = 0b11010001,
+    RotateRight32 = 0b11010101,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum UnaryKind {
+    CountLeadingZeroBits,
+    CountTrailingZeroBits,
+    CountSetBits,
+    SignExtend8,
+    SignExtend16,
+    ZeroExtend16,
+    ReverseByte,
+
+    // These are only available on 64-bit.
+    CountLeadingZeroBits32,
+    CountTrailingZeroBits32,
+    CountSetBits32,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
@@ -227,17 +302,24 @@ pub enum Inst {
         src1: Reg,
         src2: Reg,
     },
+    Unary {
+        kind: UnaryKind,
+        dst: Reg,
+        src: Reg,
+    },
     Ecall,
     Unimplemented,
     LoadReserved {
         acquire: bool,
         release: bool,
+        is_64_bit: bool,
         dst: Reg,
         src: Reg,
     },
     StoreConditional {
         acquire: bool,
         release: bool,
+        is_64_bit: bool,
         addr: Reg,
         dst: Reg,
         src: Reg,
@@ -245,6 +327,7 @@ pub enum Inst {
     Atomic {
         acquire: bool,
         release: bool,
+        is_64_bit: bool,
         kind: AtomicKind,
         dst: Reg,
         addr: Reg,
@@ -387,14 +470,36 @@ fn test_bits() {
     assert_eq!(unbits(5, 10, 2048, 25), 0);
 }
 
+/// Decodes the jump target of C.J and C.JAL.
+#[inline(always)]
+const fn decode_compressed_jump_target(op: u32) -> u32 {
+    sign_ext(
+        bits(1, 3, op, 3)
+            | bits(4, 4, op, 11)
+            | bits(5, 5, op, 2)
+            | bits(6, 6, op, 7)
+            | bits(7, 7, op, 6)
+            | bits(8, 9, op, 9)
+            | bits(10, 10, op, 8)
+            | bits(11, 11, op, 12),
+        12,
+    ) as u32
+}
+
 impl Inst {
-    pub fn decode(op: u32) -> Option<Self> {
-        // This is mostly unofficial, but it's a defacto standard used by both LLVM and GCC.
+    pub fn decode(bitness: Bitness, op: u32) -> Option<Self> {
+        // This is supposed to be unimplemented.
         // https://github.com/riscv-non-isa/riscv-asm-manual/blob/master/riscv-asm.md#instruction-aliases
         if op == 0xc0001073 {
             return Some(Inst::Unimplemented);
         }
 
+        // The width of the shift amount for the shift-by-immediate instructions.
+        let shamt_bits = match bitness {
+            Bitness::B32 => 5,
+            Bitness::B64 => 6,
+        };
+
         match op & 0b1111111 {
             0b0110111 => {
                 // LUI
@@ -441,34 +546,63 @@ impl Inst {
                 ) as u32,
             }),
             0b0000011 => Some(Inst::Load {
-                kind: LoadKind::decode(op >> 12)?,
+                kind: LoadKind::decode(bitness, op >> 12)?,
                 dst: Reg::decode(op >> 7),
                 base: Reg::decode(op >> 15),
                 offset: sign_ext(bits(0, 11, op, 20), 12),
             }),
             0b0100011 => Some(Inst::Store {
-                kind: StoreKind::decode(op >> 12)?,
+                kind: StoreKind::decode(bitness, op >> 12)?,
                 base: Reg::decode(op >> 15),
                 src: Reg::decode(op >> 20),
                 offset: sign_ext(bits(0, 4, op, 7) | bits(5, 11, op, 25), 12),
             }),
             0b0010011 => match (op >> 12) & 0b111 {
                 0b001 => {
-                    if op & 0xfe000000 != 0 {
-                        return None;
-                    }
+                    let kind = match op >> 20 {
+                        0b0110000_00000 => UnaryKind::CountLeadingZeroBits,
+                        0b0110000_00001 => UnaryKind::CountTrailingZeroBits,
+                        0b0110000_00010 => UnaryKind::CountSetBits,
+                        0b0110000_00100 => UnaryKind::SignExtend8,
+                        0b0110000_00101 => UnaryKind::SignExtend16,
+                        _ => {
+                            if op >> (20 + shamt_bits) != 0 {
+                                return None;
+                            }
 
-                    Some(Inst::RegImm {
-                        kind: RegImmKind::ShiftLogicalLeft,
+                            return Some(Inst::RegImm {
+                                kind: RegImmKind::ShiftLogicalLeft,
+                                dst: Reg::decode(op >> 7),
+                                src: Reg::decode(op >> 15),
+                                imm: bits(0, shamt_bits - 1, op, 20) as i32,
+                            });
+                        }
+                    };
+
+                    Some(Inst::Unary {
+                        kind,
                         dst: Reg::decode(op >> 7),
                         src: Reg::decode(op >> 15),
-                        imm: bits(0, 4, op, 20) as i32,
                     })
                 }
                 0b101 => {
-                    let kind = match (op & 0xfe000000) >> 24 {
-                        0b00000000 => RegImmKind::ShiftLogicalRight,
-                        0b01000000 => RegImmKind::ShiftArithmeticRight,
+                    let rev8 = match bitness {
+                        Bitness::B32 => 0b0110100_11000,
+                        Bitness::B64 => 0b0110101_11000,
+                    };
+
+                    if op >> 20 == rev8 {
+                        return Some(Inst::Unary {
+                            kind: UnaryKind::ReverseByte,
+                            dst: Reg::decode(op >> 7),
+                            src: Reg::decode(op >> 15),
+                        });
+                    }
+
+                    let kind = match (op >> (20 + shamt_bits)) << shamt_bits {
+                        0b0000000_00000 => RegImmKind::ShiftLogicalRight,
+                        0b0100000_00000 => RegImmKind::ShiftArithmeticRight,
+                        0b0110000_00000 => RegImmKind::RotateRight,
                         _ => return None,
                     };
 
@@ -476,7 +610,7 @@ impl Inst {
                         kind,
                         dst: Reg::decode(op >> 7),
                         src: Reg::decode(op >> 15),
-                        imm: bits(0, 4, op, 20) as i32,
+                        imm: bits(0, shamt_bits - 1, op, 20) as i32,
                     })
                 }
                 _ => Some(Inst::RegImm {
@@ -486,6 +620,55 @@ impl Inst {
                     imm: sign_ext(op >> 20, 12),
                 }),
             },
+            0b0011011 if bitness == Bitness::B64 => {
+                let dst = Reg::decode(op >> 7);
+                let src = Reg::decode(op >> 15);
+                match (op >> 12) & 0b111 {
+                    0b000 => Some(Inst::RegImm {
+                        kind: RegImmKind::Add32,
+                        dst,
+                        src,
+                        imm: sign_ext(op >> 20, 12),
+                    }),
+                    0b001 => {
+                        let kind = match op >> 20 {
+                            0b0110000_00000 => UnaryKind::CountLeadingZeroBits32,
+                            0b0110000_00001 => UnaryKind::CountTrailingZeroBits32,
+                            0b0110000_00010 => UnaryKind::CountSetBits32,
+                            _ => {
+                                if op >> 25 != 0 {
+                                    return None;
+                                }
+
+                                return Some(Inst::RegImm {
+                                    kind: RegImmKind::ShiftLogicalLeft32,
+                                    dst,
+                                    src,
+                                    imm: bits(0, 4, op, 20) as i32,
+                                });
+                            }
+                        };
+
+                        Some(Inst::Unary { kind, dst, src })
+                    }
+                    0b101 => {
+                        let kind = match op >> 25 {
+                            0b0000000 => RegImmKind::ShiftLogicalRight32,
+                            0b0100000 => RegImmKind::ShiftArithmeticRight32,
+                            0b0110000 => RegImmKind::RotateRight32,
+                            _ => return None,
+                        };
+
+                        Some(Inst::RegImm {
+                            kind,
+                            dst,
+                            src,
+                            imm: bits(0, 4, op, 20) as i32,
+                        })
+                    }
+                    _ => None,
+                }
+            }
             0b0110011 => {
                 let dst = Reg::decode(op >> 7);
                 let src1 = Reg::decode(op >> 15);
@@ -511,6 +694,24 @@ impl Inst {
                     0b0000001_00000_00000_110_00000_0000000 => RegRegKind::Rem,
                     0b0000001_00000_00000_111_00000_0000000 => RegRegKind::RemUnsigned,
 
+                    0b0100000_00000_00000_111_00000_0000000 => RegRegKind::AndInverted,
+                    0b0100000_00000_00000_110_00000_0000000 => RegRegKind::OrInverted,
+                    0b0100000_00000_00000_100_00000_0000000 => RegRegKind::Xnor,
+                    0b0000101_00000_00000_110_00000_0000000 => RegRegKind::Maximum,
+                    0b0000101_00000_00000_111_00000_0000000 => RegRegKind::MaximumUnsigned,
+                    0b0000101_00000_00000_100_00000_0000000 => RegRegKind::Minimum,
+                    0b0000101_00000_00000_101_00000_0000000 => RegRegKind::MinimumUnsigned,
+                    0b0110000_00000_00000_001_00000_0000000 => RegRegKind::RotateLeft,
+                    0b0110000_00000_00000_101_00000_0000000 => RegRegKind::RotateRight,
+
+                    0b0000100_00000_00000_100_00000_0000000 if bitness == Bitness::B32 && src2 == Reg::Zero => {
+                        return Some(Inst::Unary {
+                            kind: UnaryKind::ZeroExtend16,
+                            dst,
+                            src: src1,
+                        });
+                    }
+
                     0b0000111_00000_00000_101_00000_0000000 => {
                         return Some(Inst::Cmov {
                             kind: CmovKind::EqZero,
@@ -533,6 +734,39 @@ impl Inst {
 
                 Some(Inst::RegReg { kind, dst, src1, src2 })
             }
+            0b0111011 if bitness == Bitness::B64 => {
+                let dst = Reg::decode(op >> 7);
+                let src1 = Reg::decode(op >> 15);
+                let src2 = Reg::decode(op >> 20);
+                let kind = match op & 0b1111111_00000_00000_111_00000_0000000 {
+                    0b0000000_00000_00000_000_00000_0000000 => RegRegKind::Add32,
+                    0b0100000_00000_00000_000_00000_0000000 => RegRegKind::Sub32,
+                    0b0000000_00000_00000_001_00000_0000000 => RegRegKind::ShiftLogicalLeft32,
+                    0b0000000_00000_00000_101_00000_0000000 => RegRegKind::ShiftLogicalRight32,
+                    0b0100000_00000_00000_101_00000_0000000 => RegRegKind::ShiftArithmeticRight32,
+
+                    0b0000001_00000_00000_000_00000_0000000 => RegRegKind::Mul32,
+                    0b0000001_00000_00000_100_00000_0000000 => RegRegKind::Div32,
+                    0b0000001_00000_00000_101_00000_0000000 => RegRegKind::DivUnsigned32,
+                    0b0000001_00000_00000_110_00000_0000000 => RegRegKind::Rem32,
+                    0b0000001_00000_00000_111_00000_0000000 => RegRegKind::RemUnsigned32,
+
+                    0b0110000_00000_00000_001_00000_0000000 => RegRegKind::RotateLeft32,
+                    0b0110000_00000_00000_101_00000_0000000 => RegRegKind::RotateRight32,
+
+                    0b0000100_00000_00000_100_00000_0000000 if src2 == Reg::Zero => {
+                        return Some(Inst::Unary {
+                            kind: UnaryKind::ZeroExtend16,
+                            dst,
+                            src: src1,
+                        });
+                    }
+
+                    _ => return None,
+                };
+
+                Some(Inst::RegReg { kind, dst, src1, src2 })
+            }
             0b1110011 => {
                 if op == 0b000000000000_00000_000_00000_1110011 {
                     Some(Inst::Ecall)
@@ -540,7 +774,13 @@ impl Inst {
                     None
                 }
             }
-            0b0101111 if (op >> 12) & 0b111 == 0b010 => {
+            0b0101111 => {
+                let is_64_bit = match (bitness, (op >> 12) & 0b111) {
+                    (_, 0b010) => false,
+                    (Bitness::B64, 0b011) => true,
+                    _ => return None,
+                };
+
                 let dst = Reg::decode(op >> 7);
                 let src1 = Reg::decode(op >> 15);
                 let src2 = Reg::decode(op >> 20);
@@ -551,12 +791,14 @@ impl Inst {
                     0b00010 if src2 == Reg::Zero => Some(Inst::LoadReserved {
                         acquire,
                         release,
+                        is_64_bit,
                         dst,
                         src: src1,
                     }),
                     0b00011 => Some(Inst::StoreConditional {
                         acquire,
                         release,
+                        is_64_bit,
                         addr: src1,
                         dst,
                         src: src2,
@@ -578,6 +820,7 @@ impl Inst {
                         Some(Inst::Atomic {
                             acquire,
                             release,
+                            is_64_bit,
                             kind,
                             dst,
                             addr: src1,
@@ -590,8 +833,272 @@ impl Inst {
         }
     }
 
+    /// Returns whether the instruction starting with the given halfword is a 16-bit compressed instruction.
+    #[inline(always)]
+    pub const fn is_compressed(op: u32) -> bool {
+        op & 0b11 != 0b11
+    }
+
+    /// Decodes a 16-bit instruction from the C extension into its 32-bit equivalent.
+    pub fn decode_compressed(bitness: Bitness, op: u32) -> Option<Self> {
+        let op = op & 0xffff;
+        if op == 0 {
+            // C.UNIMP
+            return Some(Inst::Unimplemented);
+        }
+
+        // Some of the instructions can only access registers x8-x15, which are encoded with only 3 bits.
+        let reg_compressed = |value: u32| Reg::decode(8 + (value & 0b111));
+
+        let imm6_raw = bits(0, 4, op, 2) | bits(5, 5, op, 12);
+        let imm6 = sign_ext(imm6_raw, 6);
+        let shamt = || -> Option<i32> {
+            // On 32-bit the shift amount must fit in 5 bits.
+            if bitness == Bitness::B32 && imm6_raw & 0b100000 != 0 {
+                None
+            } else {
+                Some(imm6_raw as i32)
+            }
+        };
+
+        let rd = Reg::decode(op >> 7);
+        let rs2 = Reg::decode(op >> 2);
+        let rd_or_rs1_compressed = reg_compressed(op >> 7);
+        let rd_or_rs2_compressed = reg_compressed(op >> 2);
+
+        match (op & 0b11, op >> 13) {
+            (0b00, 0b000) => {
+                // C.ADDI4SPN
+                let imm = bits(2, 2, op, 6) | bits(3, 3, op, 5) | bits(4, 5, op, 11) | bits(6, 9, op, 7);
+                if imm == 0 {
+                    return None;
+                }
+
+                Some(Inst::RegImm {
+                    kind: RegImmKind::Add,
+                    dst: rd_or_rs2_compressed,
+                    src: Reg::SP,
+                    imm: imm as i32,
+                })
+            }
+            (0b00, 0b010) => Some(Inst::Load {
+                // C.LW
+                kind: LoadKind::decode(bitness, 0b010)?,
+                dst: rd_or_rs2_compressed,
+                base: rd_or_rs1_compressed,
+                offset: (bits(2, 2, op, 6) | bits(3, 5, op, 10) | bits(6, 6, op, 5)) as i32,
+            }),
+            (0b00, 0b011) if bitness == Bitness::B64 => Some(Inst::Load {
+                // C.LD
+                kind: LoadKind::U64,
+                dst: rd_or_rs2_compressed,
+                base: rd_or_rs1_compressed,
+                offset: (bits(3, 5, op, 10) | bits(6, 7, op, 5)) as i32,
+            }),
+            (0b00, 0b110) => Some(Inst::Store {
+                // C.SW
+                kind: StoreKind::U32,
+                src: rd_or_rs2_compressed,
+                base: rd_or_rs1_compressed,
+                offset: (bits(2, 2, op, 6) | bits(3, 5, op, 10) | bits(6, 6, op, 5)) as i32,
+            }),
+            (0b00, 0b111) if bitness == Bitness::B64 => Some(Inst::Store {
+                // C.SD
+                kind: StoreKind::U64,
+                src: rd_or_rs2_compressed,
+                base: rd_or_rs1_compressed,
+                offset: (bits(3, 5, op, 10) | bits(6, 7, op, 5)) as i32,
+            }),
+            (0b01, 0b000) => Some(Inst::RegImm {
+                // C.ADDI
+                kind: RegImmKind::Add,
+                dst: rd,
+                src: rd,
+                imm: imm6,
+            }),
+            (0b01, 0b001) => match bitness {
+                // C.JAL
+                Bitness::B32 => Some(Inst::JumpAndLink {
+                    dst: Reg::RA,
+                    target: decode_compressed_jump_target(op),
+                }),
+                // C.ADDIW
+                Bitness::B64 if rd != Reg::Zero => Some(Inst::RegImm {
+                    kind: RegImmKind::Add32,
+                    dst: rd,
+                    src: rd,
+                    imm: imm6,
+                }),
+                Bitness::B64 => None,
+            },
+            (0b01, 0b010) => Some(Inst::RegImm {
+                // C.LI
+                kind: RegImmKind::Add,
+                dst: rd,
+                src: Reg::Zero,
+                imm: imm6,
+            }),
+            (0b01, 0b011) if rd == Reg::SP => {
+                // C.ADDI16SP
+                let imm = bits(4, 4, op, 6) | bits(5, 5, op, 2) | bits(6, 6, op, 5) | bits(7, 8, op, 3) | bits(9, 9, op, 12);
+                if imm == 0 {
+                    return None;
+                }
+
+                Some(Inst::RegImm {
+                    kind: RegImmKind::Add,
+                    dst: Reg::SP,
+                    src: Reg::SP,
+                    imm: sign_ext(imm, 10),
+                })
+            }
+            (0b01, 0b011) => {
+                // C.LUI
+                if imm6_raw == 0 {
+                    return None;
+                }
+
+                Some(Inst::LoadUpperImmediate {
+                    dst: rd,
+                    value: (imm6 << 12) as u32,
+                })
+            }
+            (0b01, 0b100) => {
+                let dst = rd_or_rs1_compressed;
+                match (op >> 10) & 0b11 {
+                    0b00 => Some(Inst::RegImm {
+                        // C.SRLI
+                        kind: RegImmKind::ShiftLogicalRight,
+                        dst,
+                        src: dst,
+                        imm: shamt()?,
+                    }),
+                    0b01 => Some(Inst::RegImm {
+                        // C.SRAI
+                        kind: RegImmKind::ShiftArithmeticRight,
+                        dst,
+                        src: dst,
+                        imm: shamt()?,
+                    }),
+                    0b10 => Some(Inst::RegImm {
+                        // C.ANDI
+                        kind: RegImmKind::And,
+                        dst,
+                        src: dst,
+                        imm: imm6,
+                    }),
+                    _ => {
+                        let kind = match (bitness, (op >> 12) & 1, (op >> 5) & 0b11) {
+                            (_, 0, 0b00) => RegRegKind::Sub,
+                            (_, 0, 0b01) => RegRegKind::Xor,
+                            (_, 0, 0b10) => RegRegKind::Or,
+                            (_, 0, 0b11) => RegRegKind::And,
+                            (Bitness::B64, 1, 0b00) => RegRegKind::Sub32,
+                            (Bitness::B64, 1, 0b01) => RegRegKind::Add32,
+                            _ => return None,
+                        };
+
+                        Some(Inst::RegReg {
+                            kind,
+                            dst,
+                            src1: dst,
+                            src2: rd_or_rs2_compressed,
+                        })
+                    }
+                }
+            }
+            (0b01, 0b101) => Some(Inst::JumpAndLink {
+                // C.J
+                dst: Reg::Zero,
+                target: decode_compressed_jump_target(op),
+            }),
+            (0b01, 0b110 | 0b111) => Some(Inst::Branch {
+                // C.BEQZ, C.BNEZ
+                kind: if op >> 13 == 0b110 { BranchKind::Eq } else { BranchKind::NotEq },
+                src1: rd_or_rs1_compressed,
+                src2: Reg::Zero,
+                target: sign_ext(
+                    bits(1, 2, op, 3) | bits(3, 4, op, 10) | bits(5, 5, op, 2) | bits(6, 7, op, 5) | bits(8, 8, op, 12),
+                    9,
+                ) as u32,
+            }),
+            (0b10, 0b000) => Some(Inst::RegImm {
+                // C.SLLI
+                kind: RegImmKind::ShiftLogicalLeft,
+                dst: rd,
+                src: rd,
+                imm: shamt()?,
+            }),
+            (0b10, 0b010) if rd != Reg::Zero => Some(Inst::Load {
+                // C.LWSP
+                kind: LoadKind::decode(bitness, 0b010)?,
+                dst: rd,
+                base: Reg::SP,
+                offset: (bits(2, 4, op, 4) | bits(5, 5, op, 12) | bits(6, 7, op, 2)) as i32,
+            }),
+            (0b10, 0b011) if bitness == Bitness::B64 && rd != Reg::Zero => Some(Inst::Load {
+                // C.LDSP
+                kind: LoadKind::U64,
+                dst: rd,
+                base: Reg::SP,
+                offset: (bits(3, 4, op, 5) | bits(5, 5, op, 12) | bits(6, 8, op, 2)) as i32,
+            }),
+            (0b10, 0b100) => match ((op >> 12) & 1, rd, rs2) {
+                // C.JR
+                (0, Reg::Zero, Reg::Zero) => None,
+                (0, base, Reg::Zero) => Some(Inst::JumpAndLinkRegister {
+                    dst: Reg::Zero,
+                    base,
+                    value: 0,
+                }),
+                // C.MV
+                (0, dst, src2) => Some(Inst::RegReg {
+                    kind: RegRegKind::Add,
+                    dst,
+                    src1: Reg::Zero,
+                    src2,
+                }),
+                // C.EBREAK
+                (_, Reg::Zero, Reg::Zero) => None,
+                // C.JALR
+                (_, base, Reg::Zero) => Some(Inst::JumpAndLinkRegister {
+                    dst: Reg::RA,
+                    base,
+                    value: 0,
+                }),
+                // C.ADD
+                (_, dst, src2) => Some(Inst::RegReg {
+                    kind: RegRegKind::Add,
+                    dst,
+                    src1: dst,
+                    src2,
+                }),
+            },
+            (0b10, 0b110) => Some(Inst::Store {
+                // C.SWSP
+                kind: StoreKind::U32,
+                src: rs2,
+                base: Reg::SP,
+                offset: (bits(2, 5, op, 9) | bits(6, 7, op, 7)) as i32,
+            }),
+            (0b10, 0b111) if bitness == Bitness::B64 => Some(Inst::Store {
+                // C.SDSP
+                kind: StoreKind::U64,
+                src: rs2,
+                base: Reg::SP,
+                offset: (bits(3, 5, op, 10) | bits(6, 8, op, 7)) as i32,
+            }),
+            _ => None,
+        }
+    }
+
     #[cfg(test)]
-    pub fn encode(self) -> Option<u32> {
+    pub fn encode(self, bitness: Bitness) -> Option<u32> {
+        let shamt_bits = match bitness {
+            Bitness::B32 => 5,
+            Bitness::B64 => 6,
+        };
+
         match self {
             Inst::LoadUpperImmediate { dst, value } => {
                 if value & 0xfff != 0 {
@@ -621,10 +1128,18 @@ impl Inst {
             Inst::JumpAndLinkRegister { dst, base, value } => {
                 Some(0b1100111 | ((dst as u32) << 7) | ((base as u32) << 15) | (sign_unext(value as u32, 12)? << 20))
             }
-            Inst::Load { kind, dst, base, offset } => {
-                Some(0b0000011 | ((kind as u32) << 12) | ((dst as u32) << 7) | ((base as u32) << 15) | sign_unext(offset as u32, 12)? << 20)
-            }
+            Inst::Load { kind, dst, base, offset } => Some(
+                0b0000011
+                    | (kind.encode(bitness)? << 12)
+                    | ((dst as u32) << 7)
+                    | ((base as u32) << 15)
+                    | sign_unext(offset as u32, 12)? << 20,
+            ),
             Inst::Store { kind, src, base, offset } => {
+                if kind == StoreKind::U64 && bitness != Bitness::B64 {
+                    return None;
+                }
+
                 let imm = sign_unext(offset as u32, 12)?;
                 Some(
                     0b0100011
@@ -649,9 +1164,12 @@ impl Inst {
                 )
             }
             Inst::RegImm { kind, dst, src, mut imm } => match kind {
-                RegImmKind::ShiftLogicalLeft | RegImmKind::ShiftLogicalRight | RegImmKind::ShiftArithmeticRight => {
-                    if imm > 32 {
-                        imm = 32;
+                RegImmKind::ShiftLogicalLeft
+                | RegImmKind::ShiftLogicalRight
+                | RegImmKind::ShiftArithmeticRight
+                | RegImmKind::RotateRight => {
+                    if imm > 1 << shamt_bits {
+                        imm = 1 << shamt_bits;
                     } else if imm < 0 {
                         imm = 0;
                     }
@@ -662,6 +1180,29 @@ impl Inst {
                                 RegImmKind::ShiftLogicalLeft => 0b001 << 12,
                                 RegImmKind::ShiftLogicalRight => 0b101 << 12,
                                 RegImmKind::ShiftArithmeticRight => (0b101 << 12) | (1 << 30),
+                                RegImmKind::RotateRight => (0b101 << 12) | (0b11 << 29),
+                                _ => unreachable!(),
+                            }
+                            | ((dst as u32) << 7)
+                            | ((src as u32) << 15)
+                            | unbits(0, shamt_bits - 1, imm as u32, 20),
+                    )
+                }
+                RegImmKind::ShiftLogicalLeft32
+                | RegImmKind::ShiftLogicalRight32
+                | RegImmKind::ShiftArithmeticRight32
+                | RegImmKind::RotateRight32 => {
+                    if bitness != Bitness::B64 || !(0..32).contains(&imm) {
+                        return None;
+                    }
+
+                    Some(
+                        0b0011011
+                            | match kind {
+                                RegImmKind::ShiftLogicalLeft32 => 0b001 << 12,
+                                RegImmKind::ShiftLogicalRight32 => 0b101 << 12,
+                                RegImmKind::ShiftArithmeticRight32 => (0b101 << 12) | (1 << 30),
+                                RegImmKind::RotateRight32 => (0b101 << 12) | (0b11 << 29),
                                 _ => unreachable!(),
                             }
                             | ((dst as u32) << 7)
@@ -669,30 +1210,80 @@ impl Inst {
                             | unbits(0, 4, imm as u32, 20),
                     )
                 }
+                RegImmKind::Add32 => {
+                    if bitness != Bitness::B64 {
+                        return None;
+                    }
+
+                    Some(0b0011011 | ((dst as u32) << 7) | ((src as u32) << 15) | sign_unext(imm as u32, 12)? << 20)
+                }
                 _ => {
                     Some(0b0010011 | ((kind as u32) << 12) | ((dst as u32) << 7) | ((src as u32) << 15) | sign_unext(imm as u32, 12)? << 20)
                 }
             },
 
-            Inst::RegReg { kind, dst, src1, src2 } => Some(
-                0b0110011
-                    | ((kind as u32 & 0b00111) << 12)
-                    | ((kind as u32 & 0b01000) << 22)
-                    | ((kind as u32 & 0b10000) << 26)
-                    | ((dst as u32) << 7)
-                    | ((src1 as u32) << 15)
-                    | ((src2 as u32) << 20),
-            ),
+            Inst::RegReg { kind, dst, src1, src2 } => {
+                let opcode = if kind as u32 & 0b10000000 != 0 {
+                    if bitness != Bitness::B64 {
+                        return None;
+                    }
+
+                    0b0111011
+                } else {
+                    0b0110011
+                };
+
+                Some(
+                    opcode
+                        | ((kind as u32 & 0b0000111) << 12)
+                        | ((kind as u32 & 0b0001000) << 22)
+                        | ((kind as u32 & 0b0010000) << 26)
+                        | ((kind as u32 & 0b0100000) << 22)
+                        | ((kind as u32 & 0b1000000) << 23)
+                        | ((dst as u32) << 7)
+                        | ((src1 as u32) << 15)
+                        | ((src2 as u32) << 20),
+                )
+            }
+            Inst::Unary { kind, dst, src } => {
+                let is_64_bit_only = matches!(
+                    kind,
+                    UnaryKind::CountLeadingZeroBits32 | UnaryKind::CountTrailingZeroBits32 | UnaryKind::CountSetBits32
+                );
+
+                if is_64_bit_only && bitness != Bitness::B64 {
+                    return None;
+                }
+
+                Some(
+                    match (bitness, kind) {
+                        (_, UnaryKind::CountLeadingZeroBits) => 0b0010011 | (0b001 << 12) | (0b0110000_00000 << 20),
+                        (_, UnaryKind::CountTrailingZeroBits) => 0b0010011 | (0b001 << 12) | (0b0110000_00001 << 20),
+                        (_, UnaryKind::CountSetBits) => 0b0010011 | (0b001 << 12) | (0b0110000_00010 << 20),
+                        (_, UnaryKind::SignExtend8) => 0b0010011 | (0b001 << 12) | (0b0110000_00100 << 20),
+                        (_, UnaryKind::SignExtend16) => 0b0010011 | (0b001 << 12) | (0b0110000_00101 << 20),
+                        (Bitness::B32, UnaryKind::ReverseByte) => 0b0010011 | (0b101 << 12) | (0b0110100_11000 << 20),
+                        (Bitness::B64, UnaryKind::ReverseByte) => 0b0010011 | (0b101 << 12) | (0b0110101_11000 << 20),
+                        (Bitness::B32, UnaryKind::ZeroExtend16) => 0b0110011 | (0b100 << 12) | (0b0000100_00000 << 20),
+                        (Bitness::B64, UnaryKind::ZeroExtend16) => 0b0111011 | (0b100 << 12) | (0b0000100_00000 << 20),
+                        (_, UnaryKind::CountLeadingZeroBits32) => 0b0011011 | (0b001 << 12) | (0b0110000_00000 << 20),
+                        (_, UnaryKind::CountTrailingZeroBits32) => 0b0011011 | (0b001 << 12) | (0b0110000_00001 << 20),
+                        (_, UnaryKind::CountSetBits32) => 0b0011011 | (0b001 << 12) | (0b0110000_00010 << 20),
+                    } | ((dst as u32) << 7)
+                        | ((src as u32) << 15),
+                )
+            }
             Inst::Ecall => Some(0x00000073),
             Inst::Unimplemented => Some(0xc0001073),
             Inst::LoadReserved {
                 acquire,
                 release,
+                is_64_bit,
                 dst,
                 src,
             } => Some(
                 0b0101111
-                    | (0b010 << 12)
+                    | (encode_atomic_width(bitness, is_64_bit)? << 12)
                     | ((dst as u32) << 7)
                     | ((src as u32) << 15)
                     | (u32::from(release) << 25)
@@ -702,12 +1293,13 @@ impl Inst {
             Inst::StoreConditional {
                 acquire,
                 release,
+                is_64_bit,
                 addr,
                 dst,
                 src,
             } => Some(
                 0b0101111
-                    | (0b010 << 12)
+                    | (encode_atomic_width(bitness, is_64_bit)? << 12)
                     | ((dst as u32) << 7)
                     | ((addr as u32) << 15)
                     | ((src as u32) << 20)
@@ -718,13 +1310,14 @@ impl Inst {
             Inst::Atomic {
                 acquire,
                 release,
+                is_64_bit,
                 kind,
                 dst,
                 addr,
                 src,
             } => Some(
                 0b0101111
-                    | (0b010 << 12)
+                    | (encode_atomic_width(bitness, is_64_bit)? << 12)
                     | ((dst as u32) << 7)
                     | ((addr as u32) << 15)
                     | ((src as u32) << 20)
@@ -739,10 +1332,19 @@ impl Inst {
     }
 }
 
+#[cfg(test)]
+fn encode_atomic_width(bitness: Bitness, is_64_bit: bool) -> Option<u32> {
+    match (bitness, is_64_bit) {
+        (_, false) => Some(0b010),
+        (Bitness::B64, true) => Some(0b011),
+        (Bitness::B32, true) => None,
+    }
+}
+
 #[test]
 fn test_decode_jump_and_link() {
     assert_eq!(
-        Inst::decode(0xd6dff06f).unwrap(),
+        Inst::decode(Bitness::B32, 0xd6dff06f).unwrap(),
         Inst::JumpAndLink {
             dst: Reg::Zero,
             target: 0x9f40_u32.wrapping_sub(0xa1d4)
@@ -753,7 +1355,7 @@ fn test_decode_jump_and_link() {
 #[test]
 fn test_decode_branch() {
     assert_eq!(
-        Inst::decode(0x00c5fe63).unwrap(),
+        Inst::decode(Bitness::B32, 0x00c5fe63).unwrap(),
         Inst::Branch {
             kind: BranchKind::GreaterOrEqualUnsigned,
             src1: Reg::A1,
@@ -763,7 +1365,7 @@ fn test_decode_branch() {
     );
 
     assert_eq!(
-        Inst::decode(0xfeb96ce3).unwrap(),
+        Inst::decode(Bitness::B32, 0xfeb96ce3).unwrap(),
         Inst::Branch {
             kind: BranchKind::LessUnsigned,
             src1: Reg::S2,
@@ -777,7 +1379,7 @@ fn test_decode_branch() {
 fn test_decode_multiply() {
     assert_eq!(
         // 02f333b3                mulhu   t2,t1,a5
-        Inst::decode(0x02f333b3).unwrap(),
+        Inst::decode(Bitness::B32, 0x02f333b3).unwrap(),
         Inst::RegReg {
             kind: RegRegKind::MulUpperUnsignedUnsigned,
             dst: Reg::T2,
@@ -788,7 +1390,7 @@ fn test_decode_multiply() {
 
     assert_eq!(
         // 029426b3                mulhsu  a3,s0,s1
-        Inst::decode(0x029426b3).unwrap(),
+        Inst::decode(Bitness::B32, 0x029426b3).unwrap(),
         Inst::RegReg {
             kind: RegRegKind::MulUpperSignedUnsigned,
             dst: Reg::A3,
@@ -799,7 +1401,7 @@ fn test_decode_multiply() {
 
     assert_eq!(
         // 02941633                mulh    a2,s0,s1
-        Inst::decode(0x02941633).unwrap(),
+        Inst::decode(Bitness::B32, 0x02941633).unwrap(),
         Inst::RegReg {
             kind: RegRegKind::MulUpperSignedSigned,
             dst: Reg::A2,
@@ -809,10 +1411,97 @@ fn test_decode_multiply() {
     );
 }
 
+#[test]
+fn test_decode_bit_manipulation() {
+    assert_eq!(
+        // 60051513                clz     a0,a0
+        Inst::decode(Bitness::B32, 0x60051513).unwrap(),
+        Inst::Unary {
+            kind: UnaryKind::CountLeadingZeroBits,
+            dst: Reg::A0,
+            src: Reg::A0,
+        }
+    );
+
+    assert_eq!(
+        // 60259593                cpop    a1,a1
+        Inst::decode(Bitness::B32, 0x60259593).unwrap(),
+        Inst::Unary {
+            kind: UnaryKind::CountSetBits,
+            dst: Reg::A1,
+            src: Reg::A1,
+        }
+    );
+
+    assert_eq!(
+        // 69855513                rev8    a0,a0
+        Inst::decode(Bitness::B32, 0x69855513).unwrap(),
+        Inst::Unary {
+            kind: UnaryKind::ReverseByte,
+            dst: Reg::A0,
+            src: Reg::A0,
+        }
+    );
+
+    assert_eq!(
+        // 0805c533                zext.h  a0,a1
+        Inst::decode(Bitness::B32, 0x0805c533).unwrap(),
+        Inst::Unary {
+            kind: UnaryKind::ZeroExtend16,
+            dst: Reg::A0,
+            src: Reg::A1,
+        }
+    );
+
+    assert_eq!(
+        // 40b57533                andn    a0,a0,a1
+        Inst::decode(Bitness::B32, 0x40b57533).unwrap(),
+        Inst::RegReg {
+            kind: RegRegKind::AndInverted,
+            dst: Reg::A0,
+            src1: Reg::A0,
+            src2: Reg::A1,
+        }
+    );
+
+    assert_eq!(
+        // 0ac5e533                max     a0,a1,a2
+        Inst::decode(Bitness::B32, 0x0ac5e533).unwrap(),
+        Inst::RegReg {
+            kind: RegRegKind::Maximum,
+            dst: Reg::A0,
+            src1: Reg::A1,
+            src2: Reg::A2,
+        }
+    );
+
+    assert_eq!(
+        // 60b51533                rol     a0,a0,a1
+        Inst::decode(Bitness::B32, 0x60b51533).unwrap(),
+        Inst::RegReg {
+            kind: RegRegKind::RotateLeft,
+            dst: Reg::A0,
+            src1: Reg::A0,
+            src2: Reg::A1,
+        }
+    );
+
+    assert_eq!(
+        // 6075d513                rori    a0,a1,7
+        Inst::decode(Bitness::B32, 0x6075d513).unwrap(),
+        Inst::RegImm {
+            kind: RegImmKind::RotateRight,
+            dst: Reg::A0,
+            src: Reg::A1,
+            imm: 7,
+        }
+    );
+}
+
 #[test]
 fn test_decode_cmov() {
     assert_eq!(
-        Inst::decode(0xec5f5b3).unwrap(),
+        Inst::decode(Bitness::B32, 0xec5f5b3).unwrap(),
         Inst::Cmov {
             kind: CmovKind::NotEqZero,
             dst: Reg::A1,
@@ -822,7 +1511,7 @@ fn test_decode_cmov() {
     );
 
     assert_eq!(
-        Inst::decode(0xec55533).unwrap(),
+        Inst::decode(Bitness::B32, 0xec55533).unwrap(),
         Inst::Cmov {
             kind: CmovKind::EqZero,
             dst: Reg::A0,
@@ -832,21 +1521,471 @@ fn test_decode_cmov() {
     );
 }
 
+#[test]
+fn test_decode_64bit() {
+    assert_eq!(
+        // 00053503                ld      a0,0(a0)
+        Inst::decode(Bitness::B64, 0x00053503).unwrap(),
+        Inst::Load {
+            kind: LoadKind::U64,
+            dst: Reg::A0,
+            base: Reg::A0,
+            offset: 0,
+        }
+    );
+
+    assert_eq!(
+        // 00a5b423                sd      a0,8(a1)
+        Inst::decode(Bitness::B64, 0x00a5b423).unwrap(),
+        Inst::Store {
+            kind: StoreKind::U64,
+            src: Reg::A0,
+            base: Reg::A1,
+            offset: 8,
+        }
+    );
+
+    assert_eq!(
+        // 0005a503                lw      a0,0(a1)
+        Inst::decode(Bitness::B64, 0x0005a503).unwrap(),
+        Inst::Load {
+            kind: LoadKind::I32,
+            dst: Reg::A0,
+            base: Reg::A1,
+            offset: 0,
+        }
+    );
+
+    assert_eq!(
+        // 0005e503                lwu     a0,0(a1)
+        Inst::decode(Bitness::B64, 0x0005e503).unwrap(),
+        Inst::Load {
+            kind: LoadKind::U32,
+            dst: Reg::A0,
+            base: Reg::A1,
+            offset: 0,
+        }
+    );
+
+    assert_eq!(
+        // 0005051b                sext.w  a0,a0
+        Inst::decode(Bitness::B64, 0x0005051b).unwrap(),
+        Inst::RegImm {
+            kind: RegImmKind::Add32,
+            dst: Reg::A0,
+            src: Reg::A0,
+            imm: 0,
+        }
+    );
+
+    assert_eq!(
+        // 02051513                slli    a0,a0,0x20
+        Inst::decode(Bitness::B64, 0x02051513).unwrap(),
+        Inst::RegImm {
+            kind: RegImmKind::ShiftLogicalLeft,
+            dst: Reg::A0,
+            src: Reg::A0,
+            imm: 32,
+        }
+    );
+
+    assert_eq!(
+        // 02b5053b                mulw    a0,a0,a1
+        Inst::decode(Bitness::B64, 0x02b5053b).unwrap(),
+        Inst::RegReg {
+            kind: RegRegKind::Mul32,
+            dst: Reg::A0,
+            src1: Reg::A0,
+            src2: Reg::A1,
+        }
+    );
+
+    assert_eq!(
+        // 6b855513                rev8    a0,a0
+        Inst::decode(Bitness::B64, 0x6b855513).unwrap(),
+        Inst::Unary {
+            kind: UnaryKind::ReverseByte,
+            dst: Reg::A0,
+            src: Reg::A0,
+        }
+    );
+
+    assert_eq!(
+        // 6005151b                clzw    a0,a0
+        Inst::decode(Bitness::B64, 0x6005151b).unwrap(),
+        Inst::Unary {
+            kind: UnaryKind::CountLeadingZeroBits32,
+            dst: Reg::A0,
+            src: Reg::A0,
+        }
+    );
+
+    // None of these are valid on 32-bit.
+    assert_eq!(Inst::decode(Bitness::B32, 0x00053503), None);
+    assert_eq!(Inst::decode(Bitness::B32, 0x00a5b423), None);
+    assert_eq!(Inst::decode(Bitness::B32, 0x0005051b), None);
+    assert_eq!(Inst::decode(Bitness::B32, 0x02051513), None);
+}
+
+#[test]
+fn test_decode_compressed() {
+    assert_eq!(
+        // 0808                    c.addi4spn a0,sp,16
+        Inst::decode_compressed(Bitness::B32, 0x0808).unwrap(),
+        Inst::RegImm {
+            kind: RegImmKind::Add,
+            dst: Reg::A0,
+            src: Reg::SP,
+            imm: 16
+        }
+    );
+
+    assert_eq!(
+        // 424c                    c.lw      a1,4(a2)
+        Inst::decode_compressed(Bitness::B32, 0x424c).unwrap(),
+        Inst::Load {
+            kind: LoadKind::U32,
+            dst: Reg::A1,
+            base: Reg::A2,
+            offset: 4
+        }
+    );
+
+    assert_eq!(
+        // c714                    c.sw      a3,8(a4)
+        Inst::decode_compressed(Bitness::B32, 0xc714).unwrap(),
+        Inst::Store {
+            kind: StoreKind::U32,
+            src: Reg::A3,
+            base: Reg::A4,
+            offset: 8
+        }
+    );
+
+    assert_eq!(
+        // 1475                    c.addi    s0,-3
+        Inst::decode_compressed(Bitness::B32, 0x1475).unwrap(),
+        Inst::RegImm {
+            kind: RegImmKind::Add,
+            dst: Reg::S0,
+            src: Reg::S0,
+            imm: -3
+        }
+    );
+
+    assert_eq!(
+        // 47fd                    c.li      a5,31
+        Inst::decode_compressed(Bitness::B32, 0x47fd).unwrap(),
+        Inst::RegImm {
+            kind: RegImmKind::Add,
+            dst: Reg::A5,
+            src: Reg::Zero,
+            imm: 31
+        }
+    );
+
+    assert_eq!(
+        // 7285                    c.lui     t0,0xfffe1
+        Inst::decode_compressed(Bitness::B32, 0x7285).unwrap(),
+        Inst::LoadUpperImmediate {
+            dst: Reg::T0,
+            value: 0xfffe1000
+        }
+    );
+
+    assert_eq!(
+        // 7139                    c.addi16sp sp,-64
+        Inst::decode_compressed(Bitness::B32, 0x7139).unwrap(),
+        Inst::RegImm {
+            kind: RegImmKind::Add,
+            dst: Reg::SP,
+            src: Reg::SP,
+            imm: -64
+        }
+    );
+
+    assert_eq!(
+        // 8505                    c.srai    a0,1
+        Inst::decode_compressed(Bitness::B32, 0x8505).unwrap(),
+        Inst::RegImm {
+            kind: RegImmKind::ShiftArithmeticRight,
+            dst: Reg::A0,
+            src: Reg::A0,
+            imm: 1
+        }
+    );
+
+    assert_eq!(
+        // 99fd                    c.andi    a1,-1
+        Inst::decode_compressed(Bitness::B32, 0x99fd).unwrap(),
+        Inst::RegImm {
+            kind: RegImmKind::And,
+            dst: Reg::A1,
+            src: Reg::A1,
+            imm: -1
+        }
+    );
+
+    assert_eq!(
+        // 8c05                    c.sub     s0,s1
+        Inst::decode_compressed(Bitness::B32, 0x8c05).unwrap(),
+        Inst::RegReg {
+            kind: RegRegKind::Sub,
+            dst: Reg::S0,
+            src1: Reg::S0,
+            src2: Reg::S1
+        }
+    );
+
+    assert_eq!(
+        // bfed                    c.j       -6
+        Inst::decode_compressed(Bitness::B32, 0xbfed).unwrap(),
+        Inst::JumpAndLink {
+            dst: Reg::Zero,
+            target: (-6_i32) as u32
+        }
+    );
+
+    assert_eq!(
+        // 2095                    c.jal     100
+        Inst::decode_compressed(Bitness::B32, 0x2095).unwrap(),
+        Inst::JumpAndLink { dst: Reg::RA, target: 100 }
+    );
+
+    assert_eq!(
+        // c911                    c.beqz    a0,20
+        Inst::decode_compressed(Bitness::B32, 0xc911).unwrap(),
+        Inst::Branch {
+            kind: BranchKind::Eq,
+            src1: Reg::A0,
+            src2: Reg::Zero,
+            target: 20
+        }
+    );
+
+    assert_eq!(
+        // fcfd                    c.bnez    s1,-2
+        Inst::decode_compressed(Bitness::B32, 0xfcfd).unwrap(),
+        Inst::Branch {
+            kind: BranchKind::NotEq,
+            src1: Reg::S1,
+            src2: Reg::Zero,
+            target: (-2_i32) as u32
+        }
+    );
+
+    assert_eq!(
+        // 039e                    c.slli    t2,7
+        Inst::decode_compressed(Bitness::B32, 0x039e).unwrap(),
+        Inst::RegImm {
+            kind: RegImmKind::ShiftLogicalLeft,
+            dst: Reg::T2,
+            src: Reg::T2,
+            imm: 7
+        }
+    );
+
+    assert_eq!(
+        // 40b2                    c.lwsp    ra,12(sp)
+        Inst::decode_compressed(Bitness::B32, 0x40b2).unwrap(),
+        Inst::Load {
+            kind: LoadKind::U32,
+            dst: Reg::RA,
+            base: Reg::SP,
+            offset: 12
+        }
+    );
+
+    assert_eq!(
+        // dfbe                    c.swsp    a5,252(sp)
+        Inst::decode_compressed(Bitness::B32, 0xdfbe).unwrap(),
+        Inst::Store {
+            kind: StoreKind::U32,
+            src: Reg::A5,
+            base: Reg::SP,
+            offset: 252
+        }
+    );
+
+    assert_eq!(
+        // 8082                    c.jr      ra
+        Inst::decode_compressed(Bitness::B32, 0x8082).unwrap(),
+        Inst::JumpAndLinkRegister {
+            dst: Reg::Zero,
+            base: Reg::RA,
+            value: 0
+        }
+    );
+
+    assert_eq!(
+        // 851a                    c.mv      a0,t1
+        Inst::decode_compressed(Bitness::B32, 0x851a).unwrap(),
+        Inst::RegReg {
+            kind: RegRegKind::Add,
+            dst: Reg::A0,
+            src1: Reg::Zero,
+            src2: Reg::T1
+        }
+    );
+
+    assert_eq!(
+        // 9282                    c.jalr    t0
+        Inst::decode_compressed(Bitness::B32, 0x9282).unwrap(),
+        Inst::JumpAndLinkRegister {
+            dst: Reg::RA,
+            base: Reg::T0,
+            value: 0
+        }
+    );
+
+    assert_eq!(
+        // 95b2                    c.add     a1,a2
+        Inst::decode_compressed(Bitness::B32, 0x95b2).unwrap(),
+        Inst::RegReg {
+            kind: RegRegKind::Add,
+            dst: Reg::A1,
+            src1: Reg::A1,
+            src2: Reg::A2
+        }
+    );
+
+    assert_eq!(
+        // 0000                    c.unimp
+        Inst::decode_compressed(Bitness::B32, 0x0000).unwrap(),
+        Inst::Unimplemented
+    );
+
+    assert_eq!(
+        // 6588                    c.ld      a0,8(a1)
+        Inst::decode_compressed(Bitness::B64, 0x6588).unwrap(),
+        Inst::Load {
+            kind: LoadKind::U64,
+            dst: Reg::A0,
+            base: Reg::A1,
+            offset: 8
+        }
+    );
+
+    assert_eq!(
+        // fef0                    c.sd      a2,248(a3)
+        Inst::decode_compressed(Bitness::B64, 0xfef0).unwrap(),
+        Inst::Store {
+            kind: StoreKind::U64,
+            src: Reg::A2,
+            base: Reg::A3,
+            offset: 248
+        }
+    );
+
+    assert_eq!(
+        // 377d                    c.addiw   a4,-1
+        Inst::decode_compressed(Bitness::B64, 0x377d).unwrap(),
+        Inst::RegImm {
+            kind: RegImmKind::Add32,
+            dst: Reg::A4,
+            src: Reg::A4,
+            imm: -1
+        }
+    );
+
+    assert_eq!(
+        // 9c05                    c.subw    s0,s1
+        Inst::decode_compressed(Bitness::B64, 0x9c05).unwrap(),
+        Inst::RegReg {
+            kind: RegRegKind::Sub32,
+            dst: Reg::S0,
+            src1: Reg::S0,
+            src2: Reg::S1
+        }
+    );
+
+    assert_eq!(
+        // 72fe                    c.ldsp    t0,504(sp)
+        Inst::decode_compressed(Bitness::B64, 0x72fe).unwrap(),
+        Inst::Load {
+            kind: LoadKind::U64,
+            dst: Reg::T0,
+            base: Reg::SP,
+            offset: 504
+        }
+    );
+
+    assert_eq!(
+        // e426                    c.sdsp    s1,8(sp)
+        Inst::decode_compressed(Bitness::B64, 0xe426).unwrap(),
+        Inst::Store {
+            kind: StoreKind::U64,
+            src: Reg::S1,
+            base: Reg::SP,
+            offset: 8
+        }
+    );
+
+    assert_eq!(
+        // 1506                    c.slli    a0,33
+        Inst::decode_compressed(Bitness::B64, 0x1506).unwrap(),
+        Inst::RegImm {
+            kind: RegImmKind::ShiftLogicalLeft,
+            dst: Reg::A0,
+            src: Reg::A0,
+            imm: 33
+        }
+    );
+
+    assert_eq!(
+        // 424c                    c.lw      a1,4(a2)
+        Inst::decode_compressed(Bitness::B64, 0x424c).unwrap(),
+        Inst::Load {
+            kind: LoadKind::I32,
+            dst: Reg::A1,
+            base: Reg::A2,
+            offset: 4
+        }
+    );
+
+    // None of these are valid on 32-bit.
+    assert_eq!(Inst::decode_compressed(Bitness::B32, 0x6588), None);
+    assert_eq!(Inst::decode_compressed(Bitness::B32, 0xfef0), None);
+    assert_eq!(Inst::decode_compressed(Bitness::B32, 0x1506), None);
+    assert_eq!(Inst::decode_compressed(Bitness::B32, 0x95fd), None);
+}
+
+#[test]
+fn test_decode_compressed_expands_to_valid_instructions() {
+    for bitness in [Bitness::B32, Bitness::B64] {
+        for op in 0..=0xffff_u32 {
+            if !Inst::is_compressed(op) {
+                continue;
+            }
+
+            if let Some(inst) = Inst::decode_compressed(bitness, op) {
+                let Some(encoded) = inst.encode(bitness) else {
+                    panic!("failed to expand compressed instruction ({bitness:?}): 0x{op:04x} -> {inst:?}");
+                };
+
+                assert_eq!(Inst::decode(bitness, encoded), Some(inst), "compressed instruction: 0x{op:04x}");
+            }
+        }
+    }
+}
+
 #[cfg_attr(debug_assertions, ignore)]
 #[test]
 fn test_encode() {
-    for op in 0..=0xFFFFFFFF_u32 {
-        if let Some(inst) = Inst::decode(op) {
-            let encoded = inst.encode();
-            if encoded != Some(op) {
-                panic!(
-                    "failed to encode instruction: {inst:?}, expected = 0x{expected:08x} (0b{expected:b}, {expected}), actual = {actual} ({actual_binary}, {actual_dec})",
-                    inst = inst,
-                    expected = op,
-                    actual = encoded.map_or_else(|| "None".to_owned(), |encoded| format!("0x{:08x}", encoded)),
-                    actual_binary = encoded.map_or_else(|| "None".to_owned(), |encoded| format!("{:b}", encoded)),
-                    actual_dec = encoded.map_or_else(|| "None".to_owned(), |encoded| format!("{}", encoded)),
-                );
+    for bitness in [Bitness::B32, Bitness::B64] {
+        for op in 0..=0xFFFFFFFF_u32 {
+            if let Some(inst) = Inst::decode(bitness, op) {
+                let encoded = inst.encode(bitness);
+                if encoded != Some(op) {
+                    panic!(
+                        "failed to encode instruction ({bitness:?}): {inst:?}, expected = 0x{expected:08x} (0b{expected:b}, {expected}), actual = {actual} ({actual_binary}, {actual_dec})",
+                        inst = inst,
+                        expected = op,
+                        actual = encoded.map_or_else(|| "None".to_owned(), |encoded| format!("0x{:08x}", encoded)),
+                        actual_binary = encoded.map_or_else(|| "None".to_owned(), |encoded| format!("{:b}", encoded)),
+                        actual_dec = encoded.map_or_else(|| "None".to_owned(), |encoded| format!("{}", encoded)),
+                    );
+                }
             }
         }
     }
@@ -208,6 +208,7 @@ pub use crate::arch_amd64_bindings::{
     iovec,
     linux_dirent64,
     MADV_DONTNEED,
+    MADV_REMOVE,
     MAP_ANONYMOUS,
     MAP_FIXED,
     MAP_POPULATE,
@@ -251,6 +252,7 @@ pub use crate::arch_amd64_bindings::{
     rusage,
     SA_NODEFER,
     SA_ONSTACK,
+    SA_RESTART,
     SA_RESTORER,
     SA_SIGINFO,
     SECCOMP_RET_ALLOW,
@@ -278,6 +280,8 @@ pub use crate::arch_amd64_bindings::{
     SIGSYS,
     SIGTERM,
     SIGTRAP,
+    SIGURG,
+    SIGUSR1,
     timespec,
     WEXITED,
     WNOHANG,
@@ -299,6 +303,10 @@ impl siginfo_t {
     pub unsafe fn si_status(&self) -> c_int {
         self.__bindgen_anon_1.__bindgen_anon_1._sifields._sigchld._status
     }
+
+    pub unsafe fn si_addr(&self) -> *mut c_void {
+        self.__bindgen_anon_1.__bindgen_anon_1._sifields._sigfault._addr
+    }
 }
 
 #[allow(non_snake_case)]
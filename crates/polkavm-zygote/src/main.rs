@@ -2,17 +2,18 @@
 #![no_main]
 #![allow(clippy::missing_safety_doc)]
 
-use core::ptr::addr_of_mut;
+use core::ptr::{addr_of, addr_of_mut};
 use core::sync::atomic::Ordering;
-use core::sync::atomic::{AtomicBool, AtomicUsize};
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize};
 use polkavm_common::{
-    abi::{VM_ADDR_USER_MEMORY, VM_ADDR_USER_STACK_HIGH, VM_MAXIMUM_MEMORY_SIZE},
-    utils::align_to_next_page_usize,
+    abi::{VM_ADDR_USER_MEMORY, VM_MAXIMUM_MEMORY_SIZE, VM_PAGE_SIZE},
+    utils::{align_to_next_page_u64, align_to_next_page_usize},
     zygote::{
         AddressTableRaw, VmCtx as VmCtxInner, SANDBOX_EMPTY_NATIVE_PROGRAM_COUNTER, SANDBOX_EMPTY_NTH_INSTRUCTION, VMCTX_FUTEX_BUSY,
         VMCTX_FUTEX_HOSTCALL, VMCTX_FUTEX_IDLE, VMCTX_FUTEX_INIT, VMCTX_FUTEX_TRAP, VM_ADDR_JUMP_TABLE, VM_ADDR_JUMP_TABLE_RETURN_TO_HOST,
         VM_ADDR_NATIVE_CODE, VM_ADDR_SIGSTACK, VM_RPC_FLAG_CLEAR_PROGRAM_AFTER_EXECUTION, VM_RPC_FLAG_RECONFIGURE,
-        VM_RPC_FLAG_RESET_MEMORY_AFTER_EXECUTION, VM_SANDBOX_MAXIMUM_JUMP_TABLE_VIRTUAL_SIZE, VM_SANDBOX_MAXIMUM_NATIVE_CODE_SIZE,
+        VM_RPC_FLAG_RESET_MEMORY_AFTER_EXECUTION, VM_RPC_FLAG_SET_HEAP_TOP, VM_SANDBOX_MAXIMUM_JUMP_TABLE_VIRTUAL_SIZE,
+        VM_SANDBOX_MAXIMUM_NATIVE_CODE_SIZE,
     },
 };
 use polkavm_linux_raw as linux_raw;
@@ -223,15 +224,55 @@ unsafe extern "C" fn entry_point(stack: *mut usize) -> ! {
 static IN_SIGNAL_HANDLER: AtomicBool = AtomicBool::new(false);
 static NATIVE_PAGE_SIZE: AtomicUsize = AtomicUsize::new(!0);
 
-unsafe extern "C" fn signal_handler(signal: u32, _info: &linux_raw::siginfo_t, context: &linux_raw::ucontext) {
-    if IN_SIGNAL_HANDLER.load(Ordering::Relaxed) || signal == linux_raw::SIGIO {
+/// The memfd which backs the guest's BSS, the dynamically allocated heap and the stack; it's also mapped by the host.
+static MEMORY_MEMFD: AtomicI32 = AtomicI32::new(-1);
+
+#[allow(clippy::needless_borrow)]
+unsafe fn is_in_guest_code(rip: u64) -> bool {
+    let user_code = VM_ADDR_NATIVE_CODE;
+    rip >= user_code && rip < user_code + (&*VMCTX.memory_config.get()).code_size() as u64
+}
+
+unsafe extern "C" fn signal_handler(signal: u32, info: &linux_raw::siginfo_t, context: &linux_raw::ucontext) {
+    let rip = context.uc_mcontext.rip;
+    if signal == linux_raw::SIGUSR1 {
+        // The host wants to interrupt the execution. If we're not currently running the guest program
+        // then just ignore it; the host will notice that on its own the next time we talk to it.
+        if !is_in_guest_code(rip) || IN_SIGNAL_HANDLER.swap(true, Ordering::Relaxed) {
+            return;
+        }
+    } else if IN_SIGNAL_HANDLER.load(Ordering::Relaxed) || signal == linux_raw::SIGIO {
         graceful_abort();
     }
 
     IN_SIGNAL_HANDLER.store(true, Ordering::Relaxed);
 
-    let rip = context.uc_mcontext.rip;
     *VMCTX.rip().get() = rip;
+    *VMCTX.trap_signal.get() = signal;
+    *VMCTX.trap_address.get() = info.si_addr() as u64;
+
+    // Bit 1 of the page fault error code is set if the access was a write.
+    *VMCTX.trap_is_write.get() = u32::from(context.uc_mcontext.err & (1 << 1) != 0);
+
+    let mcontext = &context.uc_mcontext;
+    *VMCTX.trap_native_regs.get() = [
+        mcontext.rax,
+        mcontext.rcx,
+        mcontext.rdx,
+        mcontext.rbx,
+        mcontext.rsp,
+        mcontext.rbp,
+        mcontext.rsi,
+        mcontext.rdi,
+        mcontext.r8,
+        mcontext.r9,
+        mcontext.r10,
+        mcontext.r11,
+        mcontext.r12,
+        mcontext.r13,
+        mcontext.r14,
+        mcontext.r15,
+    ];
 
     trace!(
         "signal triggered from ",
@@ -273,14 +314,10 @@ unsafe extern "C" fn signal_handler(signal: u32, _info: &linux_raw::siginfo_t, c
         Hex(context.uc_mcontext.r15)
     );
 
-    let user_code = VM_ADDR_NATIVE_CODE;
-
-    #[allow(clippy::needless_borrow)]
-    if rip >= user_code && rip < user_code + (&*VMCTX.memory_config.get()).code_size() as u64 {
+    if is_in_guest_code(rip) {
         signal_host(VMCTX_FUTEX_TRAP, SignalHostKind::Normal)
             .unwrap_or_else(|error| abort_with_error("failed to wait for the host process (trap)", error));
 
-        *VMCTX.rip().get() = SANDBOX_EMPTY_NATIVE_PROGRAM_COUNTER;
         longjmp(addr_of_mut!(RESUME_IDLE_LOOP_JMPBUF), 1);
     } else {
         abort_with_message("segmentation fault")
@@ -426,6 +463,10 @@ unsafe fn initialize(mut stack: *mut usize) -> linux_raw::Fd {
 
     lifetime_pipe.leak();
 
+    let memory_memfd = linux_raw::recvfd(socket.borrow()).unwrap_or_else(|error| abort_with_error("failed to read memory fd", error));
+    MEMORY_MEMFD.store(memory_memfd.raw(), Ordering::Relaxed);
+    memory_memfd.leak();
+
     // Wait for the host to fill out vmctx.
     signal_host(VMCTX_FUTEX_INIT, SignalHostKind::Normal)
         .unwrap_or_else(|error| abort_with_error("failed to wait for the host process (init)", error));
@@ -520,6 +561,12 @@ unsafe fn initialize(mut stack: *mut usize) -> linux_raw::Fd {
     linux_raw::sys_rt_sigaction(linux_raw::SIGIO, &sa, None)
         .unwrap_or_else(|error| abort_with_error("failed to set up a signal handler for SIGIO", error));
 
+    // This is used by the host to interrupt the execution, so make sure it doesn't
+    // spuriously fail any syscalls we might be in the middle of when it arrives.
+    sa.sa_flags |= linux_raw::SA_RESTART as u64;
+    linux_raw::sys_rt_sigaction(linux_raw::SIGUSR1, &sa, None)
+        .unwrap_or_else(|error| abort_with_error("failed to set up a signal handler for SIGUSR1", error));
+
     // Set up the sysreturn jump table.
     linux_raw::sys_mmap(
         VM_ADDR_JUMP_TABLE_RETURN_TO_HOST as *mut core::ffi::c_void,
@@ -574,7 +621,8 @@ unsafe fn initialize(mut stack: *mut usize) -> linux_raw::Fd {
 
         // SYS_madvise
         ([4]: a = syscall_arg[2]),
-        (if a != linux_raw::MADV_DONTNEED => jump @0),
+        (if a == linux_raw::MADV_DONTNEED => jump @1),
+        (if a != linux_raw::MADV_REMOVE => jump @0),
         (seccomp_allow),
 
         // SYS_mmap
@@ -648,6 +696,10 @@ unsafe fn main_loop(socket: linux_raw::Fd) -> ! {
         reconfigure(socket.borrow());
     }
 
+    if rpc_flags & VM_RPC_FLAG_SET_HEAP_TOP != 0 {
+        resize_heap(*VMCTX.new_heap_top.get());
+    }
+
     if let Some(rpc_address) = rpc_address {
         trace!("jumping to: ", Hex(rpc_address as usize));
         rpc_address();
@@ -660,39 +712,137 @@ unsafe fn main_loop(socket: linux_raw::Fd) -> ! {
 unsafe fn reset_memory() {
     trace!("resetting memory...");
     let current = &mut *VMCTX.memory_config.get();
-    let heap_size = current.heap_size();
-    if heap_size > 0 {
+    let rw_data_size = current.rw_data_size();
+    if rw_data_size > 0 {
+        // The read-write data is a private mapping of the program's memfd, so this brings back its original contents.
         linux_raw::sys_madvise(
-            current.heap_address() as *mut core::ffi::c_void,
-            heap_size as usize,
+            current.rw_data_address() as *mut core::ffi::c_void,
+            rw_data_size as usize,
             linux_raw::MADV_DONTNEED,
         )
-        .unwrap_or_else(|error| abort_with_error("failed to clear user heap", error));
+        .unwrap_or_else(|error| abort_with_error("failed to clear user rw data", error));
+    }
+
+    resize_heap(current.heap_range().end.into());
+    clear_shared_memory();
+}
+
+/// Maps the given part of the guest's address space from the memory memfd which is shared with the host.
+unsafe fn mmap_shared_memory(address: u64, length: usize) -> Result<(), linux_raw::Error> {
+    let memfd = linux_raw::FdRef::from_raw_unchecked(MEMORY_MEMFD.load(Ordering::Relaxed));
+    linux_raw::sys_mmap(
+        address as *mut core::ffi::c_void,
+        length,
+        linux_raw::PROT_READ | linux_raw::PROT_WRITE,
+        linux_raw::MAP_FIXED | linux_raw::MAP_SHARED,
+        Some(memfd),
+        address,
+    )?;
+
+    Ok(())
+}
+
+/// Zeroes the given part of the memory memfd, and frees its pages.
+///
+/// Unlike with private mappings `MADV_DONTNEED` doesn't discard the contents of a shared mapping, so this is necessary
+/// before anything which is mapped from the memory memfd is unmapped or reused.
+unsafe fn remove_shared_memory(address: u64, length: usize) -> Result<(), linux_raw::Error> {
+    linux_raw::sys_madvise(address as *mut core::ffi::c_void, length, linux_raw::MADV_REMOVE)
+}
+
+/// Zeroes the BSS, the dynamically allocated heap and the stack.
+unsafe fn clear_shared_memory() {
+    let current = &*VMCTX.memory_config.get();
+    let heap_start = u64::from(current.bss_address());
+    let Some(heap_end) = align_to_next_page_u64(u64::from(VM_PAGE_SIZE), *VMCTX.heap_top.get()) else {
+        unreachable!()
+    };
+
+    if heap_end > heap_start {
+        remove_shared_memory(heap_start, (heap_end - heap_start) as usize)
+            .unwrap_or_else(|error| abort_with_error("failed to clear user heap", error));
     }
 
     let stack_size = current.stack_size() as usize;
     if stack_size > 0 {
-        linux_raw::sys_madvise(
-            (VM_ADDR_USER_STACK_HIGH as usize - stack_size) as *mut core::ffi::c_void,
-            stack_size,
-            linux_raw::MADV_DONTNEED,
-        )
-        .unwrap_or_else(|error| abort_with_error("failed to clear user stack", error));
+        remove_shared_memory(current.stack_address_low().into(), stack_size)
+            .unwrap_or_else(|error| abort_with_error("failed to clear user stack", error));
     }
 }
 
+/// Maps or unmaps the memory past the initial heap so that the heap ends at `new_heap_top`.
+unsafe fn resize_heap(new_heap_top: u64) {
+    let heap_top = *VMCTX.heap_top.get();
+    let Some(mapped_end) = align_to_next_page_u64(u64::from(VM_PAGE_SIZE), heap_top) else {
+        unreachable!()
+    };
+    let Some(new_mapped_end) = align_to_next_page_u64(u64::from(VM_PAGE_SIZE), new_heap_top) else {
+        unreachable!()
+    };
+
+    match new_mapped_end.cmp(&mapped_end) {
+        core::cmp::Ordering::Greater => {
+            mmap_shared_memory(mapped_end, (new_mapped_end - mapped_end) as usize)
+                .unwrap_or_else(|error| abort_with_error("failed to mmap user memory (heap)", error));
+        }
+        core::cmp::Ordering::Less => {
+            remove_shared_memory(new_mapped_end, (mapped_end - new_mapped_end) as usize)
+                .unwrap_or_else(|error| abort_with_error("failed to clear user memory (heap)", error));
+            linux_raw::sys_munmap(new_mapped_end as *mut core::ffi::c_void, (mapped_end - new_mapped_end) as usize)
+                .unwrap_or_else(|error| abort_with_error("failed to unmap user memory (heap)", error));
+        }
+        core::cmp::Ordering::Equal => {}
+    }
+
+    *VMCTX.heap_top.get() = new_heap_top;
+}
+
 #[inline(never)]
 #[no_mangle]
-pub unsafe extern "C" fn syscall_hostcall(hostcall: u32) {
+pub unsafe extern "C" fn syscall_hostcall(hostcall: u32, rip: u64) {
     trace!("syscall: hostcall triggered");
 
     *VMCTX.hostcall().get() = hostcall;
+    *VMCTX.rip().get() = rip;
     signal_host(VMCTX_FUTEX_HOSTCALL, SignalHostKind::Normal)
         .unwrap_or_else(|error| abort_with_error("failed to wait for the host process (hostcall)", error));
 
-    if *VMCTX.hostcall().get() == polkavm_common::zygote::HOSTCALL_ABORT_EXECUTION {
-        longjmp(addr_of_mut!(RESUME_IDLE_LOOP_JMPBUF), 1);
+    loop {
+        match *VMCTX.hostcall().get() {
+            polkavm_common::zygote::HOSTCALL_ABORT_EXECUTION => longjmp(addr_of_mut!(RESUME_IDLE_LOOP_JMPBUF), 1),
+            polkavm_common::zygote::HOSTCALL_NESTED_CALL => nested_call(),
+            _ => break,
+        }
+    }
+}
+
+/// Calls into the guest program from within a hostcall, on behalf of the host function which is currently running.
+///
+/// Once the nested call finishes (either normally or by trapping) we go back to waiting for the host,
+/// which will then either resume the original hostcall or request yet another nested call.
+#[cold]
+#[inline(never)]
+unsafe fn nested_call() {
+    trace!("syscall: nested call requested");
+
+    // Any trap or return from the nested call will longjmp back to the idle loop, so temporarily redirect it here.
+    let outer_jmpbuf = core::ptr::read(addr_of!(RESUME_IDLE_LOOP_JMPBUF));
+    if setjmp(addr_of_mut!(RESUME_IDLE_LOOP_JMPBUF)) == 0 {
+        let rpc_address = *VMCTX.rpc_address.get().cast::<Option<extern "C" fn() -> !>>();
+        let Some(rpc_address) = rpc_address else {
+            abort_with_message("nested call requested without an address to jump to");
+        };
+
+        trace!("jumping to: ", Hex(rpc_address as usize));
+        rpc_address();
     }
+
+    IN_SIGNAL_HANDLER.store(false, Ordering::Relaxed);
+    core::ptr::write(addr_of_mut!(RESUME_IDLE_LOOP_JMPBUF), outer_jmpbuf);
+
+    trace!("returning from nested call...");
+    signal_host(VMCTX_FUTEX_IDLE, SignalHostKind::Normal)
+        .unwrap_or_else(|error| abort_with_error("failed to wait for the host process (nested call)", error));
 }
 
 #[inline(never)]
@@ -712,6 +862,21 @@ pub unsafe extern "C" fn syscall_return() -> ! {
     longjmp(addr_of_mut!(RESUME_IDLE_LOOP_JMPBUF), 1);
 }
 
+#[inline(never)]
+#[no_mangle]
+pub unsafe extern "C" fn syscall_sbrk(size: u32) -> u32 {
+    trace!("syscall: sbrk triggered");
+
+    let heap_top = *VMCTX.heap_top.get();
+    let new_heap_top = heap_top + u64::from(size);
+    if new_heap_top > (*VMCTX.memory_config.get()).max_heap_top() {
+        return 0;
+    }
+
+    resize_heap(new_heap_top);
+    heap_top as u32
+}
+
 // Just for debugging. Normally should never be used.
 #[inline(never)]
 #[no_mangle]
@@ -738,6 +903,7 @@ pub static ADDRESS_TABLE: AddressTableRaw = AddressTableRaw {
     syscall_trap,
     syscall_return,
     syscall_trace,
+    syscall_sbrk,
 };
 
 enum SignalHostKind {
@@ -917,15 +1083,8 @@ unsafe fn reconfigure(socket: linux_raw::FdRef) {
         .unwrap_or_else(|error| abort_with_error("failed to close user memory fd", error));
 
     if new.bss_size() > 0 {
-        linux_raw::sys_mmap(
-            new.bss_address() as *mut core::ffi::c_void,
-            new.bss_size() as usize,
-            linux_raw::PROT_READ | linux_raw::PROT_WRITE,
-            linux_raw::MAP_FIXED | linux_raw::MAP_PRIVATE | linux_raw::MAP_ANONYMOUS,
-            None,
-            0,
-        )
-        .unwrap_or_else(|error| abort_with_error("failed to mmap user memory (bss)", error));
+        mmap_shared_memory(new.bss_address().into(), new.bss_size() as usize)
+            .unwrap_or_else(|error| abort_with_error("failed to mmap user memory (bss)", error));
 
         trace!(
             "new bss range: ",
@@ -942,15 +1101,8 @@ unsafe fn reconfigure(socket: linux_raw::FdRef) {
     }
 
     if new.stack_size() > 0 {
-        linux_raw::sys_mmap(
-            new.stack_address_low() as *mut core::ffi::c_void,
-            new.stack_size() as usize,
-            linux_raw::PROT_READ | linux_raw::PROT_WRITE,
-            linux_raw::MAP_FIXED | linux_raw::MAP_PRIVATE | linux_raw::MAP_ANONYMOUS,
-            None,
-            0,
-        )
-        .unwrap_or_else(|error| abort_with_error("failed to mmap user memory (stack)", error));
+        mmap_shared_memory(new.stack_address_low().into(), new.stack_size() as usize)
+            .unwrap_or_else(|error| abort_with_error("failed to mmap user memory (stack)", error));
 
         trace!(
             "new stack range: ",
@@ -966,6 +1118,9 @@ unsafe fn reconfigure(socket: linux_raw::FdRef) {
         }
     }
 
+    current.set_max_heap_size(new.max_heap_size());
+    *VMCTX.heap_top.get() = current.heap_range().end.into();
+
     if *current != new {
         // This should never happen, but let's check it just in case.
         abort_with_message("internal error: failed to fully update memory configuration");
@@ -984,6 +1139,8 @@ unsafe fn reconfigure(socket: linux_raw::FdRef) {
 
 #[inline(never)]
 unsafe fn clear_program() {
+    clear_shared_memory();
+
     let current = &mut *VMCTX.memory_config.get();
     if current.user_memory_region_size() > 0 || current.stack_size() > 0 || current.code_size() > 0 {
         polkavm_common::static_assert!(VM_ADDR_NATIVE_CODE + (VM_SANDBOX_MAXIMUM_NATIVE_CODE_SIZE as u64) < 0x200000000);
@@ -995,6 +1152,9 @@ unsafe fn clear_program() {
         current.clear_code_size();
     }
 
+    current.set_max_heap_size(0);
+    *VMCTX.heap_top.get() = current.heap_range().end.into();
+
     if current.jump_table_size() > 0 {
         linux_raw::sys_munmap(VM_ADDR_JUMP_TABLE as *mut core::ffi::c_void, current.jump_table_size())
             .unwrap_or_else(|error| abort_with_error("failed to unmap jump table", error));
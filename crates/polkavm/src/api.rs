@@ -2,22 +2,28 @@ use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
+use core::future::Future;
 use core::marker::PhantomData;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
 
 use polkavm_common::abi::{
     GuestMemoryConfig, VM_MAXIMUM_EXPORT_COUNT, VM_MAXIMUM_EXTERN_ARG_COUNT, VM_MAXIMUM_IMPORT_COUNT, VM_MAXIMUM_INSTRUCTION_COUNT,
 };
-use polkavm_common::abi::{VM_ADDR_RETURN_TO_HOST, VM_ADDR_USER_STACK_HIGH};
-use polkavm_common::error::Trap;
+use polkavm_common::abi::{VM_ADDR_RETURN_TO_HOST, VM_ADDR_USER_STACK_HIGH, VM_CODE_ADDRESS_ALIGNMENT, VM_MAX_PAGE_SIZE, VM_PAGE_SIZE};
+use polkavm_common::error::{Backtrace, BacktraceFrame, BacktraceSymbol, Trap, TrapKind};
 use polkavm_common::init::GuestProgramInit;
 use polkavm_common::program::{ExternFnPrototype, ExternTy, ProgramBlob, ProgramExport, ProgramImport};
-use polkavm_common::program::{FrameKind, Instruction, InstructionVisitor, Reg};
-use polkavm_common::utils::{Access, AsUninitSliceMut, Gas};
+use polkavm_common::program::{FrameKind, Instruction, InstructionVisitor, Opcode, Reg};
+use polkavm_common::utils::{align_to_next_page_u32, align_to_next_page_usize, Access, AsUninitSliceMut, Gas};
 
 use crate::caller::{Caller, CallerRaw};
 use crate::config::{BackendKind, Config, GasMeteringKind, ModuleConfig, SandboxKind};
 use crate::error::{bail, bail_static, Error, ExecutionError};
+use crate::guest_ptr::GuestPtr;
 use crate::interpreter::{InterpretedAccess, InterpretedInstance, InterpretedModule};
 use crate::tracer::Tracer;
 
@@ -30,15 +36,16 @@ if_compiler_is_supported! {
     use crate::sandbox::linux::Sandbox as SandboxLinux;
 }
 
-struct DisplayFn<'a, Args> {
+struct DisplayFn<'a, Args, Returns> {
     name: &'a str,
     args: Args,
-    return_ty: Option<ExternTy>,
+    returns: Returns,
 }
 
-impl<'a, Args> core::fmt::Display for DisplayFn<'a, Args>
+impl<'a, Args, Returns> core::fmt::Display for DisplayFn<'a, Args, Returns>
 where
     Args: Clone + Iterator<Item = ExternTy>,
+    Returns: Clone + ExactSizeIterator<Item = ExternTy>,
 {
     fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
         fmt.write_str(self.name)?;
@@ -50,20 +57,45 @@ where
             ty.fmt(fmt)?;
         }
         fmt.write_str(")")?;
-        if let Some(return_ty) = self.return_ty {
-            fmt.write_str(" -> ")?;
-            return_ty.fmt(fmt)?;
+        match self.returns.len() {
+            0 => {}
+            1 => {
+                fmt.write_str(" -> ")?;
+                for ty in self.returns.clone() {
+                    ty.fmt(fmt)?;
+                }
+            }
+            _ => {
+                fmt.write_str(" -> (")?;
+                for (index, ty) in self.returns.clone().enumerate() {
+                    if index > 0 {
+                        fmt.write_str(", ")?;
+                    }
+                    ty.fmt(fmt)?;
+                }
+                fmt.write_str(")")?;
+            }
         }
 
         Ok(())
     }
 }
 
-pub(crate) type OnHostcall<'a> = &'a mut dyn for<'r> FnMut(u32, BackendAccess<'r>) -> Result<(), Trap>;
+/// What should happen with the execution after a hostcall handler returns.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum HostcallOutcome {
+    /// Continue executing the guest program.
+    Continue,
+
+    /// Suspend the execution and return to the host; it can be resumed later.
+    Suspend,
+}
+
+pub(crate) type OnHostcall<'a> = &'a mut dyn for<'r> FnMut(u32, BackendAccess<'r>) -> Result<HostcallOutcome, Trap>;
 
 if_compiler_is_supported! {
     {
-        use core::sync::atomic::{AtomicUsize, Ordering};
+        use core::sync::atomic::AtomicUsize;
 
         pub(crate) trait SandboxExt: Sandbox {
             fn as_compiled_module(module: &Module) -> &CompiledModule<Self>;
@@ -215,6 +247,7 @@ if_compiler_is_supported! {
 pub(crate) struct EngineState {
     #[allow(dead_code)]
     sandbox_cache: Option<SandboxCache>,
+    max_nested_call_depth: u32,
 }
 
 pub struct Engine {
@@ -303,7 +336,10 @@ impl Engine {
             selected_sandbox,
             interpreter_enabled: debug_trace_execution || selected_backend == BackendKind::Interpreter,
             debug_trace_execution,
-            state: Arc::new(EngineState { sandbox_cache }),
+            state: Arc::new(EngineState {
+                sandbox_cache,
+                max_nested_call_depth: config.max_nested_call_depth,
+            }),
         })
     }
 }
@@ -327,6 +363,38 @@ impl CompiledModuleKind {
     pub fn is_some(&self) -> bool {
         !matches!(self, CompiledModuleKind::Unavailable)
     }
+
+    fn with_initial_heap(&self, heap: &[u8]) -> Result<Self, Error> {
+        if_compiler_is_supported! {
+            {
+                Ok(match self {
+                    #[cfg(target_os = "linux")]
+                    CompiledModuleKind::Linux(module) => CompiledModuleKind::Linux(module.with_initial_heap(heap)?),
+                    CompiledModuleKind::Generic(module) => CompiledModuleKind::Generic(module.with_initial_heap(heap)?),
+                    CompiledModuleKind::Unavailable => CompiledModuleKind::Unavailable,
+                })
+            } else {{
+                let _ = heap;
+                Ok(CompiledModuleKind::Unavailable)
+            }}
+        }
+    }
+
+    pub(crate) fn program_counter_by_native_address(&self, native_address: u64) -> Option<u32> {
+        if_compiler_is_supported! {
+            {
+                match self {
+                    #[cfg(target_os = "linux")]
+                    CompiledModuleKind::Linux(module) => module.program_counter_by_native_address(native_address),
+                    CompiledModuleKind::Generic(module) => module.program_counter_by_native_address(native_address),
+                    CompiledModuleKind::Unavailable => None,
+                }
+            } else {{
+                let _ = native_address;
+                None
+            }}
+        }
+    }
 }
 
 struct ModulePrivate {
@@ -338,18 +406,62 @@ struct ModulePrivate {
     instruction_by_basic_block: Vec<u32>,
     jump_table_index_by_basic_block: Vec<u32>,
     basic_block_by_jump_table_index: Vec<u32>,
+    call_return_basic_blocks: Vec<u32>,
 
     blob: ProgramBlob<'static>,
+    /// A hash of the program blob, used to make sure that snapshots are only restored into instances of the same program.
+    fingerprint: u64,
     compiled_module: CompiledModuleKind,
     interpreted_module: Option<InterpretedModule>,
     memory_config: GuestMemoryConfig,
+    max_heap_size: u32,
     gas_metering: Option<GasMeteringKind>,
+    bulk_memory_byte_cost: u32,
+    sbrk_byte_cost: u32,
+    count_basic_blocks: bool,
 }
 
 /// A compiled PolkaVM program module.
 #[derive(Clone)]
 pub struct Module(Arc<ModulePrivate>);
 
+/// The layout of a module's memory inside of the VM.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct MemoryMap(GuestMemoryConfig);
+
+impl core::fmt::Debug for MemoryMap {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("MemoryMap")
+            .field("ro_data_range", &self.ro_data_range())
+            .field("rw_data_range", &self.rw_data_range())
+            .field("heap_range", &self.heap_range())
+            .field("stack_range", &self.stack_range())
+            .finish()
+    }
+}
+
+impl MemoryMap {
+    /// The range of addresses where the read-only data is.
+    pub fn ro_data_range(&self) -> core::ops::Range<u32> {
+        self.0.ro_data_range()
+    }
+
+    /// The range of addresses where the initialized read-write data is.
+    pub fn rw_data_range(&self) -> core::ops::Range<u32> {
+        self.0.rw_data_address()..self.0.rw_data_address() + self.0.rw_data_size()
+    }
+
+    /// The range of addresses where the read-write memory is, including both the read-write data and the BSS.
+    pub fn heap_range(&self) -> core::ops::Range<u32> {
+        self.0.heap_range()
+    }
+
+    /// The range of addresses where the stack is.
+    pub fn stack_range(&self) -> core::ops::Range<u32> {
+        self.0.stack_range()
+    }
+}
+
 pub(crate) trait BackendModule: Sized {
     type BackendVisitor<'a>;
     type Aux;
@@ -363,6 +475,7 @@ pub(crate) trait BackendModule: Sized {
         init: GuestProgramInit<'a>,
         instruction_count: usize,
         basic_block_count: usize,
+        is_64_bit: bool,
         debug_trace_execution: bool,
     ) -> Result<(Self::BackendVisitor<'a>, Self::Aux), Error>;
 
@@ -387,6 +500,7 @@ pub(crate) struct Common<'a> {
     pub(crate) imports: &'a BTreeMap<u32, ProgramImport<'a>>,
     pub(crate) jump_table_index_by_basic_block: &'a Vec<u32>,
     pub(crate) instruction_by_basic_block: Vec<u32>,
+    pub(crate) call_return_basic_blocks: Vec<u32>,
     pub(crate) gas_cost_for_basic_block: Vec<u32>,
     pub(crate) maximum_seen_jump_target: u32,
     pub(crate) nth_instruction: usize,
@@ -394,6 +508,7 @@ pub(crate) struct Common<'a> {
     pub(crate) basic_block_count: usize,
     pub(crate) block_in_progress: bool,
     pub(crate) current_instruction_offset: usize,
+    pub(crate) is_64_bit: bool,
 }
 
 impl<'a> Common<'a> {
@@ -475,10 +590,36 @@ where
     VisitorWrapper<'a, T>: BackendVisitor,
 {
     #[cfg_attr(not(debug_assertions), inline)]
-    fn on_pre_visit(&mut self, offset: usize, _opcode: u8) -> Self::ReturnTy {
+    fn on_pre_visit(&mut self, offset: usize, opcode: u8) -> Self::ReturnTy {
+        if !self.is_64_bit && Opcode::from_u8(opcode).is_some_and(Opcode::is_64_bit_only) {
+            #[cold]
+            fn error_64_bit_only_instruction(opcode: u8) -> Error {
+                Error::from(format!(
+                    "found an instruction which is only supported by the 64-bit variant of the instruction set: opcode {opcode}"
+                ))
+            }
+
+            return Err(error_64_bit_only_instruction(opcode));
+        }
+
         if self.config.gas_metering.is_some() {
-            // TODO: Come up with a better cost model.
-            *self.gas_cost_for_basic_block.last_mut().unwrap() += 1;
+            let nth_basic_block = self.instruction_by_basic_block.len() - 1;
+            let opcode_cost = self.config.gas_cost_model.opcode_cost[usize::from(opcode)];
+            let cost = &mut self.gas_cost_for_basic_block[nth_basic_block];
+
+            // The compiler subtracts the cost as a sign-extended 32-bit immediate, so it can't be any bigger than this.
+            let Some(new_cost) = cost.checked_add(opcode_cost).filter(|&new_cost| new_cost <= i32::MAX as u32) else {
+                #[cold]
+                fn error_basic_block_too_expensive(nth_basic_block: usize) -> Error {
+                    Error::from(format!(
+                        "the gas cost of basic block #{nth_basic_block} doesn't fit into a 32-bit signed integer"
+                    ))
+                }
+
+                return Err(error_basic_block_too_expensive(nth_basic_block));
+            };
+
+            *cost = new_cost;
         }
 
         self.current_instruction_offset = offset;
@@ -777,6 +918,146 @@ where
         Ok(())
     }
 
+    #[inline(always)]
+    fn sbrk(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.sbrk(d, s);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn and_inverted(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.and_inverted(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn or_inverted(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.or_inverted(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn xnor(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.xnor(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn maximum(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.maximum(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn maximum_unsigned(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.maximum_unsigned(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn minimum(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.minimum(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn minimum_unsigned(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.minimum_unsigned(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn rotate_left(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.rotate_left(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn rotate_right(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.rotate_right(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn rotate_right_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.rotate_right_imm(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn rotate_right_imm_alt(&mut self, d: Reg, s2: Reg, s1: u32) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.rotate_right_imm_alt(d, s2, s1);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn rotate_left_imm_alt(&mut self, d: Reg, s2: Reg, s1: u32) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.rotate_left_imm_alt(d, s2, s1);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn count_leading_zero_bits(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.count_leading_zero_bits(d, s);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn count_trailing_zero_bits(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.count_trailing_zero_bits(d, s);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn count_set_bits(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.count_set_bits(d, s);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn sign_extend_8(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.sign_extend_8(d, s);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn sign_extend_16(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.sign_extend_16(d, s);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn zero_extend_16(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.zero_extend_16(d, s);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn reverse_byte(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.reverse_byte(d, s);
+        Ok(())
+    }
+
     #[inline(always)]
     fn cmov_if_zero(&mut self, d: Reg, s: Reg, c: Reg) -> Self::ReturnTy {
         self.0.before_instruction();
@@ -791,6 +1072,20 @@ where
         Ok(())
     }
 
+    #[inline(always)]
+    fn memcpy(&mut self, dst: Reg, src: Reg, count: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.memcpy(dst, src, count);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn memset(&mut self, dst: Reg, value: Reg, count: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.memset(dst, value, count);
+        Ok(())
+    }
+
     #[inline(always)]
     fn add_imm(&mut self, d: Reg, s: Reg, imm: u32) -> Self::ReturnTy {
         self.0.before_instruction();
@@ -1059,6 +1354,8 @@ where
             bail_static!("found a call instruction where the next basic block is not part of the jump table");
         }
 
+        self.call_return_basic_blocks.push(return_basic_block);
+
         self.maximum_seen_jump_target = core::cmp::max(self.maximum_seen_jump_target, target);
 
         self.start_new_basic_block()?;
@@ -1080,6 +1377,8 @@ where
             bail_static!("found a call instruction where the next basic block is not part of the jump table");
         }
 
+        self.call_return_basic_blocks.push(return_basic_block);
+
         self.start_new_basic_block()?;
         self.0.before_instruction();
         self.0.call_indirect(ra, base, offset);
@@ -1102,81 +1401,364 @@ where
         self.0.jump_indirect(base, offset);
         Ok(())
     }
-}
 
-impl Module {
-    pub(crate) fn is_debug_trace_execution_enabled(&self) -> bool {
-        self.0.debug_trace_execution
+    #[inline(always)]
+    fn load_i32(&mut self, dst: Reg, offset: u32) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.load_i32(dst, offset);
+        Ok(())
     }
 
-    pub(crate) fn instructions(&self) -> &[Instruction] {
-        &self.interpreted_module().unwrap().instructions
+    #[inline(always)]
+    fn load_u64(&mut self, dst: Reg, offset: u32) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.load_u64(dst, offset);
+        Ok(())
     }
 
-    pub(crate) fn compiled_module(&self) -> &CompiledModuleKind {
-        &self.0.compiled_module
+    #[inline(always)]
+    fn store_u64(&mut self, src: Reg, offset: u32) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.store_u64(src, offset);
+        Ok(())
     }
 
-    pub(crate) fn interpreted_module(&self) -> Option<&InterpretedModule> {
-        self.0.interpreted_module.as_ref()
+    #[inline(always)]
+    fn store_imm_indirect_u64(&mut self, base: Reg, offset: u32, value: u32) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.store_imm_indirect_u64(base, offset, value);
+        Ok(())
     }
 
-    pub(crate) fn blob(&self) -> &ProgramBlob<'static> {
-        &self.0.blob
+    #[inline(always)]
+    fn load_indirect_i32(&mut self, dst: Reg, base: Reg, offset: u32) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.load_indirect_i32(dst, base, offset);
+        Ok(())
     }
 
-    pub(crate) fn get_export(&self, export_index: usize) -> Option<&ProgramExport> {
-        self.0.exports.get(export_index)
+    #[inline(always)]
+    fn load_indirect_u64(&mut self, dst: Reg, base: Reg, offset: u32) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.load_indirect_u64(dst, base, offset);
+        Ok(())
     }
 
-    pub(crate) fn instruction_by_basic_block(&self, nth_basic_block: u32) -> Option<u32> {
-        self.0.instruction_by_basic_block.get(nth_basic_block as usize).copied()
+    #[inline(always)]
+    fn store_indirect_u64(&mut self, src: Reg, base: Reg, offset: u32) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.store_indirect_u64(src, base, offset);
+        Ok(())
     }
 
-    pub(crate) fn jump_table_index_by_basic_block(&self, nth_basic_block: u32) -> Option<u32> {
-        let index = self
-            .0
-            .jump_table_index_by_basic_block
-            .get(nth_basic_block as usize)
-            .copied()
-            .unwrap_or(0);
-        if index == 0 {
-            None
-        } else {
-            Some(index)
-        }
+    #[inline(always)]
+    fn add_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.add_imm_32(d, s1, s2);
+        Ok(())
     }
 
-    pub(crate) fn basic_block_by_jump_table_index(&self, jump_table_index: u32) -> Option<u32> {
-        self.0.basic_block_by_jump_table_index.get(jump_table_index as usize).copied()
+    #[inline(always)]
+    fn shift_logical_left_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.shift_logical_left_imm_32(d, s1, s2);
+        Ok(())
     }
 
-    pub(crate) fn memory_config(&self) -> &GuestMemoryConfig {
-        &self.0.memory_config
+    #[inline(always)]
+    fn shift_logical_right_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.shift_logical_right_imm_32(d, s1, s2);
+        Ok(())
     }
 
-    pub(crate) fn gas_metering(&self) -> Option<GasMeteringKind> {
-        self.0.gas_metering
+    #[inline(always)]
+    fn shift_arithmetic_right_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.shift_arithmetic_right_imm_32(d, s1, s2);
+        Ok(())
     }
 
-    /// Creates a new module by deserializing the program from the given `bytes`.
-    pub fn new(engine: &Engine, config: &ModuleConfig, bytes: impl AsRef<[u8]>) -> Result<Self, Error> {
-        let blob = match ProgramBlob::parse(bytes.as_ref()) {
-            Ok(blob) => blob,
-            Err(error) => {
-                bail!("failed to parse blob: {}", error);
-            }
-        };
+    #[inline(always)]
+    fn rotate_right_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.rotate_right_imm_32(d, s1, s2);
+        Ok(())
+    }
 
-        Self::from_blob(engine, config, &blob)
+    #[inline(always)]
+    fn add_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.add_32(d, s1, s2);
+        Ok(())
     }
 
-    /// Creates a new module from a deserialized program `blob`.
-    pub fn from_blob(engine: &Engine, config: &ModuleConfig, blob: &ProgramBlob) -> Result<Self, Error> {
-        // Do an early check for memory config validity.
-        GuestMemoryConfig::new(
-            blob.ro_data().len() as u64,
-            blob.rw_data().len() as u64,
+    #[inline(always)]
+    fn sub_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.sub_32(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn mul_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.mul_32(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn shift_logical_left_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.shift_logical_left_32(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn shift_logical_right_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.shift_logical_right_32(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn shift_arithmetic_right_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.shift_arithmetic_right_32(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn div_unsigned_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.div_unsigned_32(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn div_signed_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.div_signed_32(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn rem_unsigned_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.rem_unsigned_32(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn rem_signed_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.rem_signed_32(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn rotate_left_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.rotate_left_32(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn rotate_right_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.rotate_right_32(d, s1, s2);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn store_imm_u64(&mut self, value: u32, offset: u32) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.store_imm_u64(value, offset);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn count_leading_zero_bits_32(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.count_leading_zero_bits_32(d, s);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn count_trailing_zero_bits_32(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.count_trailing_zero_bits_32(d, s);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn count_set_bits_32(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.0.before_instruction();
+        self.0.count_set_bits_32(d, s);
+        Ok(())
+    }
+}
+
+impl Module {
+    pub(crate) fn is_debug_trace_execution_enabled(&self) -> bool {
+        self.0.debug_trace_execution
+    }
+
+    pub(crate) fn instructions(&self) -> &[Instruction] {
+        &self.interpreted_module().unwrap().instructions
+    }
+
+    pub(crate) fn compiled_module(&self) -> &CompiledModuleKind {
+        &self.0.compiled_module
+    }
+
+    pub(crate) fn interpreted_module(&self) -> Option<&InterpretedModule> {
+        self.0.interpreted_module.as_ref()
+    }
+
+    pub(crate) fn blob(&self) -> &ProgramBlob<'static> {
+        &self.0.blob
+    }
+
+    pub(crate) fn get_export(&self, export_index: usize) -> Option<&ProgramExport> {
+        self.0.exports.get(export_index)
+    }
+
+    pub(crate) fn instruction_by_basic_block(&self, nth_basic_block: u32) -> Option<u32> {
+        self.0.instruction_by_basic_block.get(nth_basic_block as usize).copied()
+    }
+
+    pub(crate) fn jump_table_index_by_basic_block(&self, nth_basic_block: u32) -> Option<u32> {
+        let index = self
+            .0
+            .jump_table_index_by_basic_block
+            .get(nth_basic_block as usize)
+            .copied()
+            .unwrap_or(0);
+        if index == 0 {
+            None
+        } else {
+            Some(index)
+        }
+    }
+
+    pub(crate) fn basic_block_by_jump_table_index(&self, jump_table_index: u32) -> Option<u32> {
+        self.0.basic_block_by_jump_table_index.get(jump_table_index as usize).copied()
+    }
+
+    pub(crate) fn memory_config(&self) -> &GuestMemoryConfig {
+        &self.0.memory_config
+    }
+
+    /// The address past which the heap cannot grow any further.
+    pub(crate) fn max_heap_top(&self) -> u32 {
+        self.0.memory_config.heap_address() + self.0.max_heap_size
+    }
+
+    pub(crate) fn gas_metering(&self) -> Option<GasMeteringKind> {
+        self.0.gas_metering
+    }
+
+    /// The extra gas charged for every byte touched by `memcpy` and `memset`.
+    pub(crate) fn bulk_memory_byte_cost(&self) -> u32 {
+        self.0.bulk_memory_byte_cost
+    }
+
+    /// The extra gas charged for every byte by which `sbrk` tries to grow the heap.
+    pub(crate) fn sbrk_byte_cost(&self) -> u32 {
+        self.0.sbrk_byte_cost
+    }
+
+    /// Returns the functions exported by this module.
+    pub fn exports(&self) -> &[ProgramExport<'static>] {
+        &self.0.exports
+    }
+
+    /// Returns the functions imported by this module, ordered by their import index.
+    pub fn imports(&self) -> impl ExactSizeIterator<Item = &ProgramImport<'static>> + '_ {
+        self.0.imports.values()
+    }
+
+    /// Returns where the module's memory regions are located inside of the VM.
+    pub fn memory_map(&self) -> MemoryMap {
+        MemoryMap(self.0.memory_config)
+    }
+
+    /// Returns the number of instructions in this module's code.
+    pub fn instruction_count(&self) -> u32 {
+        self.0.blob.instruction_count()
+    }
+
+    /// Returns the number of basic blocks in this module's code.
+    pub fn basic_block_count(&self) -> u32 {
+        self.0.blob.basic_block_count()
+    }
+
+    /// Returns whether this module contains any debug info.
+    pub fn has_debug_info(&self) -> bool {
+        self.0.blob.has_debug_info()
+    }
+
+    /// Creates a copy of this module whose instances start with the given heap contents instead of the blob's initial data.
+    fn with_initial_heap(&self, mut heap: Vec<u8>) -> Result<Self, Error> {
+        let mut memory_config = self.0.memory_config;
+        assert!(heap.len() >= memory_config.heap_size() as usize);
+
+        if heap.len() > memory_config.heap_size() as usize {
+            // The heap was grown with `sbrk`, so the new module starts out with a bigger BSS section.
+            let heap_size = align_to_next_page_usize(VM_PAGE_SIZE as usize, heap.len()).unwrap();
+            heap.resize(heap_size, 0);
+
+            let bss_size = memory_config.bss_size() + (heap_size as u32 - memory_config.heap_size());
+            memory_config.set_bss_size(bss_size).map_err(Error::from_static_str)?;
+        }
+
+        Ok(Module(Arc::new(ModulePrivate {
+            debug_trace_execution: self.0.debug_trace_execution,
+            exports: self.0.exports.clone(),
+            imports: self.0.imports.clone(),
+            export_index_by_name: self.0.export_index_by_name.clone(),
+
+            instruction_by_basic_block: self.0.instruction_by_basic_block.clone(),
+            jump_table_index_by_basic_block: self.0.jump_table_index_by_basic_block.clone(),
+            basic_block_by_jump_table_index: self.0.basic_block_by_jump_table_index.clone(),
+            call_return_basic_blocks: self.0.call_return_basic_blocks.clone(),
+
+            blob: self.0.blob.clone(),
+            fingerprint: self.0.fingerprint,
+            compiled_module: self.0.compiled_module.with_initial_heap(&heap)?,
+            interpreted_module: self
+                .0
+                .interpreted_module
+                .as_ref()
+                .map(|interpreted_module| interpreted_module.with_initial_heap(heap)),
+            memory_config,
+            max_heap_size: self.0.max_heap_size,
+            gas_metering: self.0.gas_metering,
+            bulk_memory_byte_cost: self.0.bulk_memory_byte_cost,
+            sbrk_byte_cost: self.0.sbrk_byte_cost,
+            count_basic_blocks: self.0.count_basic_blocks,
+        })))
+    }
+
+    /// Creates a new module by deserializing the program from the given `bytes`.
+    pub fn new(engine: &Engine, config: &ModuleConfig, bytes: impl AsRef<[u8]>) -> Result<Self, Error> {
+        let blob = match ProgramBlob::parse(bytes.as_ref()) {
+            Ok(blob) => blob,
+            Err(error) => {
+                bail!("failed to parse blob: {}", error);
+            }
+        };
+
+        Self::from_blob(engine, config, &blob)
+    }
+
+    /// Creates a new module from a deserialized program `blob`.
+    pub fn from_blob(engine: &Engine, config: &ModuleConfig, blob: &ProgramBlob) -> Result<Self, Error> {
+        // Do an early check for memory config validity.
+        GuestMemoryConfig::new(
+            blob.ro_data().len() as u64,
+            blob.rw_data().len() as u64,
             u64::from(blob.bss_size()),
             u64::from(blob.stack_size()),
         )
@@ -1261,6 +1843,27 @@ impl Module {
             .with_bss(blob.bss_size())
             .with_stack(blob.stack_size());
 
+        let max_heap_size = {
+            let memory_config = init.memory_config().map_err(Error::from_static_str)?;
+            let Some(max_heap_size) = align_to_next_page_u32(VM_PAGE_SIZE, config.max_heap_size) else {
+                bail!("the maximum heap size is too big");
+            };
+
+            if max_heap_size > memory_config.heap_size() {
+                // Make sure there's still a guard page left between the heap and the stack.
+                let max_heap_top = u64::from(memory_config.heap_address()) + u64::from(max_heap_size);
+                if max_heap_top + u64::from(VM_MAX_PAGE_SIZE) > u64::from(memory_config.stack_address_low()) {
+                    bail!("the maximum heap size is too big; the heap would overlap with the stack");
+                }
+
+                max_heap_size
+            } else {
+                memory_config.heap_size()
+            }
+        };
+
+        let init = init.with_max_heap_size(max_heap_size);
+
         macro_rules! new_common {
             () => {{
                 let mut common = Common {
@@ -1269,6 +1872,7 @@ impl Module {
                     imports: &imports,
                     jump_table_index_by_basic_block: &jump_table_index_by_basic_block,
                     instruction_by_basic_block: Vec::new(),
+                    call_return_basic_blocks: Vec::new(),
                     gas_cost_for_basic_block: Vec::new(),
                     maximum_seen_jump_target: initial_maximum_seen_jump_target,
                     nth_instruction: 0,
@@ -1276,12 +1880,15 @@ impl Module {
                     basic_block_count: blob.basic_block_count() as usize,
                     block_in_progress: false,
                     current_instruction_offset: 0,
+                    is_64_bit: blob.is_64_bit(),
                 };
 
                 common.instruction_by_basic_block.reserve(common.basic_block_count + 1);
                 common.instruction_by_basic_block.push(0);
                 if config.gas_metering.is_some() {
-                    common.gas_cost_for_basic_block.resize(common.basic_block_count, 0);
+                    common
+                        .gas_cost_for_basic_block
+                        .resize(common.basic_block_count, config.gas_cost_model.basic_block_cost);
                 }
 
                 common
@@ -1299,6 +1906,7 @@ impl Module {
                     init,
                     blob.instruction_count() as usize,
                     blob.basic_block_count() as usize,
+                    blob.is_64_bit(),
                     engine.debug_trace_execution,
                 )?;
 
@@ -1447,6 +2055,12 @@ impl Module {
             vec
         };
 
+        let call_return_basic_blocks = {
+            let mut vec = common.call_return_basic_blocks;
+            vec.shrink_to_fit();
+            vec
+        };
+
         log::trace!("Processing finished!");
 
         assert!(compiled_module.is_some() || interpreted_module.is_some());
@@ -1491,16 +2105,27 @@ impl Module {
             instruction_by_basic_block,
             jump_table_index_by_basic_block,
             basic_block_by_jump_table_index,
+            call_return_basic_blocks,
 
             // TODO: Remove the clone.
             blob: blob.clone().into_owned(),
+            fingerprint: crate::utils::fnv1a_hash(blob.as_bytes()),
             compiled_module,
             interpreted_module,
             memory_config,
+            max_heap_size,
             gas_metering: config.gas_metering,
+            bulk_memory_byte_cost: config.gas_cost_model.bulk_memory_byte_cost,
+            sbrk_byte_cost: config.gas_cost_model.sbrk_byte_cost,
+            count_basic_blocks: config.count_basic_blocks,
         })))
     }
 
+    /// Returns whether the module targets the 64-bit variant of the instruction set.
+    pub fn is_64_bit(&self) -> bool {
+        self.0.blob.is_64_bit()
+    }
+
     /// The address at where the program's stack starts inside of the VM.
     pub fn stack_address_low(&self) -> u32 {
         self.0.memory_config.stack_address_low()
@@ -1609,6 +2234,119 @@ impl Module {
             }
         }
     }
+
+    fn symbolicate(&self, pc: u32) -> Vec<BacktraceSymbol> {
+        let mut symbols = Vec::new();
+        let Ok(Some(mut line_program)) = self.blob().get_debug_line_program_at(pc) else {
+            return symbols;
+        };
+
+        for _ in 0..128 {
+            // Have an upper bound on the number of iterations, just in case.
+            let Ok(Some(region_info)) = line_program.run() else { break };
+
+            if !region_info.instruction_range().contains(&pc) {
+                continue;
+            }
+
+            for frame in region_info.frames() {
+                let function_name = frame.full_name().ok().map(|name| name.to_string()).filter(|name| !name.is_empty());
+                let path = frame.path().ok().flatten().map(|path| path.to_owned());
+                symbols.push(BacktraceSymbol::new(function_name, path, frame.line(), frame.column()));
+            }
+
+            // The frames are ordered from the outermost one, but we want the innermost one first.
+            symbols.reverse();
+
+            break;
+        }
+
+        symbols
+    }
+
+    /// Returns the index of the call instruction which corresponds to the given return address.
+    fn call_site_by_return_address(&self, return_address: u32) -> Option<u32> {
+        if return_address % VM_CODE_ADDRESS_ALIGNMENT != 0 {
+            return None;
+        }
+
+        let nth_basic_block = self.basic_block_by_jump_table_index(return_address / VM_CODE_ADDRESS_ALIGNMENT)?;
+        self.0.call_return_basic_blocks.binary_search(&nth_basic_block).ok()?;
+        self.instruction_by_basic_block(nth_basic_block)?.checked_sub(1)
+    }
+
+    /// Captures a backtrace of a trapped guest program and attaches it to the trap.
+    pub(crate) fn attach_backtrace<'a>(&self, trap: Trap, access: &impl Access<'a>) -> Trap {
+        // Put an upper limit on how many frames we'll gather, just in case.
+        const MAXIMUM_FRAME_COUNT: usize = 256;
+
+        let mut frames = Vec::new();
+        let mut function_name = None;
+        if let Some(pc) = trap.program_counter() {
+            let symbols = self.symbolicate(pc);
+            function_name = symbols.last().and_then(|symbol| symbol.function_name().map(|name| name.to_owned()));
+            frames.push(BacktraceFrame::new(pc, symbols));
+        }
+
+        // Addresses are always 32-bit, even in the 64-bit instruction set.
+        let ra = access.get_reg(Reg::RA);
+        if ra == VM_ADDR_RETURN_TO_HOST {
+            // We're still in the very first function which was called.
+            return trap.with_backtrace(Backtrace::new(frames));
+        }
+
+        let mut last_return_address = None;
+        if let Some(pc) = self.call_site_by_return_address(ra) {
+            let symbols = self.symbolicate(pc);
+
+            // If the return address points into the same function then it's most likely
+            // a leftover from a call which already returned; for a recursive call the real
+            // return address will be found on the stack anyway.
+            let is_stale = function_name.is_some() && symbols.last().and_then(|symbol| symbol.function_name()) == function_name.as_deref();
+            if !is_stale {
+                frames.push(BacktraceFrame::new(pc, symbols));
+                last_return_address = Some(ra);
+            }
+        }
+
+        let stack_range = self.memory_config().stack_range();
+        let sp = access.get_reg(Reg::SP).clamp(stack_range.start, stack_range.end) & !3;
+
+        // Walk the stack a chunk at a time so that we don't have to copy all of it if we can stop early.
+        let mut buffer = [core::mem::MaybeUninit::<u8>::uninit(); 1024];
+        let mut address = sp;
+        'walk: while address < stack_range.end {
+            let length = core::cmp::min(buffer.len() as u32, stack_range.end - address);
+            let Ok(chunk) = access.read_memory_into_slice(address, &mut buffer[..length as usize]) else {
+                break;
+            };
+
+            address += length;
+            for word in chunk.chunks_exact(4) {
+                if frames.len() >= MAXIMUM_FRAME_COUNT {
+                    break 'walk;
+                }
+
+                let return_address = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+                if return_address == VM_ADDR_RETURN_TO_HOST {
+                    break 'walk;
+                }
+
+                let Some(pc) = self.call_site_by_return_address(return_address) else {
+                    continue;
+                };
+
+                // The return address was most likely spilled on the stack while still being in the register.
+                if last_return_address.take() == Some(return_address) {
+                    continue;
+                }
+
+                frames.push(BacktraceFrame::new(pc, self.symbolicate(pc)));
+            }
+        }
+
+        trap.with_backtrace(Backtrace::new(frames))
+    }
 }
 
 #[derive(Clone)]
@@ -1704,9 +2442,20 @@ impl FuncType {
     }
 }
 
+/// A future which finishes an asynchronous hostcall, producing the values for the return registers.
+type AsyncHostcallFuture = Pin<Box<dyn Future<Output = Result<Vec<u64>, Trap>> + Send>>;
+
 trait ExternFn<T>: Send + Sync {
     fn call(&self, user_data: &mut T, access: BackendAccess, raw: &mut CallerRaw) -> Result<(), Trap>;
-    fn typecheck(&self, prototype: &ExternFnPrototype) -> Result<(), Error>;
+    fn typecheck(&self, prototype: &ExternFnPrototype, is_64_bit: bool) -> Result<(), Error>;
+
+    fn is_async(&self) -> bool {
+        false
+    }
+
+    fn call_async(&self, _user_data: &mut T, _access: BackendAccess, _raw: &mut CallerRaw) -> Result<AsyncHostcallFuture, Trap> {
+        unreachable!("internal error: tried to asynchronously call a synchronous host function");
+    }
 }
 
 #[repr(transparent)]
@@ -1723,106 +2472,163 @@ pub trait IntoExternFn<T, Params, Result>: Send + Sync + 'static {
     fn _into_extern_fn(self) -> ExternFnArc<T>;
 }
 
+pub trait IntoExternFnAsync<T, Params, Result>: Send + Sync + 'static {
+    #[doc(hidden)]
+    fn _into_extern_fn(self) -> ExternFnArc<T>;
+}
+
 /// A type which can be marshalled through the VM's FFI boundary.
+///
+/// On the 64-bit variant of the instruction set every value fits in a single register,
+/// while on the 32-bit variant the 64-bit values are split into two registers.
 pub trait AbiTy: Sized + Send + 'static {
     #[doc(hidden)]
     const _PRIVATE_EXTERN_TY: ExternTy;
 
     #[doc(hidden)]
-    const _REGS_REQUIRED: usize;
+    const _REGS_REQUIRED_32: usize;
 
     #[doc(hidden)]
-    fn _get(get_reg: impl FnMut() -> u32) -> Self;
+    const _REGS_REQUIRED_64: usize;
 
     #[doc(hidden)]
-    fn _set(self, set_reg: impl FnMut(u32));
+    fn _get(is_64_bit: bool, get_reg: impl FnMut() -> u64) -> Self;
+
+    #[doc(hidden)]
+    fn _set(self, is_64_bit: bool, set_reg: impl FnMut(u64));
+
+    #[doc(hidden)]
+    fn _regs_required(is_64_bit: bool) -> usize {
+        if is_64_bit {
+            Self::_REGS_REQUIRED_64
+        } else {
+            Self::_REGS_REQUIRED_32
+        }
+    }
 }
 
 impl AbiTy for u32 {
     const _PRIVATE_EXTERN_TY: ExternTy = ExternTy::I32;
-    const _REGS_REQUIRED: usize = 1;
+    const _REGS_REQUIRED_32: usize = 1;
+    const _REGS_REQUIRED_64: usize = 1;
 
-    fn _get(mut get_reg: impl FnMut() -> u32) -> Self {
-        get_reg()
+    fn _get(_is_64_bit: bool, mut get_reg: impl FnMut() -> u64) -> Self {
+        get_reg() as u32
     }
 
-    fn _set(self, mut set_reg: impl FnMut(u32)) {
-        set_reg(self)
-    }
+    fn _set(self, is_64_bit: bool, mut set_reg: impl FnMut(u64)) {
+        if is_64_bit {
+            // 32-bit values are always kept sign extended in 64-bit registers.
+            set_reg(i64::from(self as i32) as u64)
+        } else {
+            set_reg(u64::from(self))
+        }
+    }
 }
 
 impl AbiTy for i32 {
     const _PRIVATE_EXTERN_TY: ExternTy = <u32 as AbiTy>::_PRIVATE_EXTERN_TY;
-    const _REGS_REQUIRED: usize = <u32 as AbiTy>::_REGS_REQUIRED;
+    const _REGS_REQUIRED_32: usize = <u32 as AbiTy>::_REGS_REQUIRED_32;
+    const _REGS_REQUIRED_64: usize = <u32 as AbiTy>::_REGS_REQUIRED_64;
 
-    fn _get(get_reg: impl FnMut() -> u32) -> Self {
-        <u32 as AbiTy>::_get(get_reg) as i32
+    fn _get(is_64_bit: bool, get_reg: impl FnMut() -> u64) -> Self {
+        <u32 as AbiTy>::_get(is_64_bit, get_reg) as i32
     }
 
-    fn _set(self, set_reg: impl FnMut(u32)) {
-        (self as u32)._set(set_reg)
+    fn _set(self, is_64_bit: bool, set_reg: impl FnMut(u64)) {
+        (self as u32)._set(is_64_bit, set_reg)
     }
 }
 
 impl AbiTy for u64 {
     const _PRIVATE_EXTERN_TY: ExternTy = ExternTy::I64;
-    const _REGS_REQUIRED: usize = 2;
+    const _REGS_REQUIRED_32: usize = 2;
+    const _REGS_REQUIRED_64: usize = 1;
 
-    fn _get(mut get_reg: impl FnMut() -> u32) -> Self {
-        let value_lo = get_reg();
-        let value_hi = get_reg();
-        u64::from(value_lo) | (u64::from(value_hi) << 32)
+    fn _get(is_64_bit: bool, mut get_reg: impl FnMut() -> u64) -> Self {
+        if is_64_bit {
+            get_reg()
+        } else {
+            let value_lo = get_reg() as u32;
+            let value_hi = get_reg() as u32;
+            u64::from(value_lo) | (u64::from(value_hi) << 32)
+        }
     }
 
-    fn _set(self, mut set_reg: impl FnMut(u32)) {
-        set_reg(self as u32);
-        set_reg((self >> 32) as u32);
+    fn _set(self, is_64_bit: bool, mut set_reg: impl FnMut(u64)) {
+        if is_64_bit {
+            set_reg(self);
+        } else {
+            set_reg(u64::from(self as u32));
+            set_reg(self >> 32);
+        }
     }
 }
 
 impl AbiTy for i64 {
     const _PRIVATE_EXTERN_TY: ExternTy = <u64 as AbiTy>::_PRIVATE_EXTERN_TY;
-    const _REGS_REQUIRED: usize = <u64 as AbiTy>::_REGS_REQUIRED;
+    const _REGS_REQUIRED_32: usize = <u64 as AbiTy>::_REGS_REQUIRED_32;
+    const _REGS_REQUIRED_64: usize = <u64 as AbiTy>::_REGS_REQUIRED_64;
+
+    fn _get(is_64_bit: bool, get_reg: impl FnMut() -> u64) -> Self {
+        <u64 as AbiTy>::_get(is_64_bit, get_reg) as i64
+    }
+
+    fn _set(self, is_64_bit: bool, set_reg: impl FnMut(u64)) {
+        (self as u64)._set(is_64_bit, set_reg)
+    }
+}
+
+impl<T> AbiTy for GuestPtr<T>
+where
+    T: 'static,
+{
+    const _PRIVATE_EXTERN_TY: ExternTy = <u32 as AbiTy>::_PRIVATE_EXTERN_TY;
+    const _REGS_REQUIRED_32: usize = <u32 as AbiTy>::_REGS_REQUIRED_32;
+    const _REGS_REQUIRED_64: usize = <u32 as AbiTy>::_REGS_REQUIRED_64;
 
-    fn _get(get_reg: impl FnMut() -> u32) -> Self {
-        <u64 as AbiTy>::_get(get_reg) as i64
+    fn _get(is_64_bit: bool, get_reg: impl FnMut() -> u64) -> Self {
+        GuestPtr::new(<u32 as AbiTy>::_get(is_64_bit, get_reg))
     }
 
-    fn _set(self, set_reg: impl FnMut(u32)) {
-        (self as u64)._set(set_reg)
+    fn _set(self, is_64_bit: bool, set_reg: impl FnMut(u64)) {
+        self.address()._set(is_64_bit, set_reg)
     }
 }
 
 // `AbiTy` is deliberately not implemented for `usize`.
 
 /// A type which can be returned from a host function.
+///
+/// Besides a single value this can also be a tuple of up to six values, which are returned in
+/// consecutive argument registers starting with `A0`.
 pub trait ReturnTy: Sized + Send + 'static {
     #[doc(hidden)]
-    const _PRIVATE_EXTERN_TY: Option<ExternTy>;
-    fn _handle_return(self, set_reg: impl FnMut(u32)) -> Result<(), Trap>;
+    const _PRIVATE_EXTERN_TY: &'static [ExternTy];
+    fn _handle_return(self, is_64_bit: bool, set_reg: impl FnMut(u64)) -> Result<(), Trap>;
 }
 
 impl<T> ReturnTy for T
 where
     T: AbiTy,
 {
-    const _PRIVATE_EXTERN_TY: Option<ExternTy> = Some(T::_PRIVATE_EXTERN_TY);
-    fn _handle_return(self, set_reg: impl FnMut(u32)) -> Result<(), Trap> {
-        self._set(set_reg);
+    const _PRIVATE_EXTERN_TY: &'static [ExternTy] = &[T::_PRIVATE_EXTERN_TY];
+    fn _handle_return(self, is_64_bit: bool, set_reg: impl FnMut(u64)) -> Result<(), Trap> {
+        self._set(is_64_bit, set_reg);
         Ok(())
     }
 }
 
 impl ReturnTy for () {
-    const _PRIVATE_EXTERN_TY: Option<ExternTy> = None;
-    fn _handle_return(self, _set_reg: impl FnMut(u32)) -> Result<(), Trap> {
+    const _PRIVATE_EXTERN_TY: &'static [ExternTy] = &[];
+    fn _handle_return(self, _is_64_bit: bool, _set_reg: impl FnMut(u64)) -> Result<(), Trap> {
         Ok(())
     }
 }
 
 impl ReturnTy for Result<(), Trap> {
-    const _PRIVATE_EXTERN_TY: Option<ExternTy> = None;
-    fn _handle_return(self, _set_reg: impl FnMut(u32)) -> Result<(), Trap> {
+    const _PRIVATE_EXTERN_TY: &'static [ExternTy] = &[];
+    fn _handle_return(self, _is_64_bit: bool, _set_reg: impl FnMut(u64)) -> Result<(), Trap> {
         self
     }
 }
@@ -1831,9 +2637,9 @@ impl<T> ReturnTy for Result<T, Trap>
 where
     T: AbiTy,
 {
-    const _PRIVATE_EXTERN_TY: Option<ExternTy> = Some(T::_PRIVATE_EXTERN_TY);
-    fn _handle_return(self, set_reg: impl FnMut(u32)) -> Result<(), Trap> {
-        self?._set(set_reg);
+    const _PRIVATE_EXTERN_TY: &'static [ExternTy] = &[T::_PRIVATE_EXTERN_TY];
+    fn _handle_return(self, is_64_bit: bool, set_reg: impl FnMut(u64)) -> Result<(), Trap> {
+        self?._set(is_64_bit, set_reg);
         Ok(())
     }
 }
@@ -1843,34 +2649,86 @@ pub trait FuncArgs: Send {
     const _PRIVATE_EXTERN_TY: &'static [ExternTy];
 
     #[doc(hidden)]
-    fn _set(self, set_reg: impl FnMut(u32));
+    fn _set(self, is_64_bit: bool, set_reg: impl FnMut(u64));
 }
 
+/// A type which can be returned from a function exported by the guest program.
+///
+/// Just as with [`ReturnTy`] this can also be a tuple of up to six values.
 pub trait FuncResult: Send {
     #[doc(hidden)]
-    const _PRIVATE_EXTERN_TY: Option<ExternTy>;
+    const _PRIVATE_EXTERN_TY: &'static [ExternTy];
 
     #[doc(hidden)]
-    fn _get(get_reg: impl FnMut() -> u32) -> Self;
+    fn _get(is_64_bit: bool, get_reg: impl FnMut() -> u64) -> Self;
 }
 
 impl FuncResult for () {
-    const _PRIVATE_EXTERN_TY: Option<ExternTy> = None;
+    const _PRIVATE_EXTERN_TY: &'static [ExternTy] = &[];
 
-    fn _get(_: impl FnMut() -> u32) -> Self {}
+    fn _get(_: bool, _: impl FnMut() -> u64) -> Self {}
 }
 
 impl<T> FuncResult for T
 where
     T: AbiTy,
 {
-    const _PRIVATE_EXTERN_TY: Option<ExternTy> = Some(<T as AbiTy>::_PRIVATE_EXTERN_TY);
+    const _PRIVATE_EXTERN_TY: &'static [ExternTy] = &[<T as AbiTy>::_PRIVATE_EXTERN_TY];
 
-    fn _get(get_reg: impl FnMut() -> u32) -> Self {
-        <T as AbiTy>::_get(get_reg)
+    fn _get(is_64_bit: bool, get_reg: impl FnMut() -> u64) -> Self {
+        <T as AbiTy>::_get(is_64_bit, get_reg)
     }
 }
 
+macro_rules! impl_multiple_return_values {
+    ($($args:ident)+) => {
+        impl<$($args: AbiTy,)+> ReturnTy for ($($args,)+) {
+            const _PRIVATE_EXTERN_TY: &'static [ExternTy] = &[
+                $(<$args as AbiTy>::_PRIVATE_EXTERN_TY,)+
+            ];
+
+            #[allow(non_snake_case)]
+            fn _handle_return(self, is_64_bit: bool, mut set_reg: impl FnMut(u64)) -> Result<(), Trap> {
+                let ($($args,)+) = self;
+                $($args._set(is_64_bit, &mut set_reg);)+
+                Ok(())
+            }
+        }
+
+        impl<$($args: AbiTy,)+> ReturnTy for Result<($($args,)+), Trap> {
+            const _PRIVATE_EXTERN_TY: &'static [ExternTy] = <($($args,)+) as ReturnTy>::_PRIVATE_EXTERN_TY;
+
+            fn _handle_return(self, is_64_bit: bool, set_reg: impl FnMut(u64)) -> Result<(), Trap> {
+                self?._handle_return(is_64_bit, set_reg)
+            }
+        }
+
+        impl<$($args: AbiTy,)+> FuncResult for ($($args,)+) {
+            const _PRIVATE_EXTERN_TY: &'static [ExternTy] = <($($args,)+) as ReturnTy>::_PRIVATE_EXTERN_TY;
+
+            fn _get(is_64_bit: bool, mut get_reg: impl FnMut() -> u64) -> Self {
+                ($(<$args as AbiTy>::_get(is_64_bit, &mut get_reg),)+)
+            }
+        }
+    };
+}
+
+impl_multiple_return_values!(A0 A1);
+impl_multiple_return_values!(A0 A1 A2);
+impl_multiple_return_values!(A0 A1 A2 A3);
+impl_multiple_return_values!(A0 A1 A2 A3 A4);
+impl_multiple_return_values!(A0 A1 A2 A3 A4 A5);
+
+fn regs_required(is_64_bit: bool, types: impl IntoIterator<Item = ExternTy>) -> usize {
+    types
+        .into_iter()
+        .map(|ty| match ty {
+            ExternTy::I32 => <u32 as AbiTy>::_regs_required(is_64_bit),
+            ExternTy::I64 => <u64 as AbiTy>::_regs_required(is_64_bit),
+        })
+        .sum()
+}
+
 macro_rules! impl_into_extern_fn {
     (@check_reg_count $regs_required:expr) => {
         if $regs_required > Reg::ARG_REGS.len() {
@@ -1887,73 +2745,79 @@ macro_rules! impl_into_extern_fn {
     (@get_reg $caller:expr) => {{
         let mut reg_index = 0;
         let caller = &mut $caller;
-        move || -> u32 {
-            let value = caller.get_reg(Reg::ARG_REGS[reg_index]);
+        move || -> u64 {
+            let value = caller.get_reg64(Reg::ARG_REGS[reg_index]);
             reg_index += 1;
             value
         }
     }};
 
     (@call $caller:expr, $callback:expr, $a0:ident) => {{
-        impl_into_extern_fn!(@check_reg_count $a0::_REGS_REQUIRED);
+        let is_64_bit = $caller.is_64_bit();
+        impl_into_extern_fn!(@check_reg_count $a0::_regs_required(is_64_bit));
 
         let cb = impl_into_extern_fn!(@get_reg $caller);
-        let a0 = $a0::_get(cb);
+        let a0 = $a0::_get(is_64_bit, cb);
         catch_hostcall_panic(|| ($callback)($caller, a0))
     }};
 
     (@call $caller:expr, $callback:expr, $a0:ident, $a1:ident) => {{
-        impl_into_extern_fn!(@check_reg_count $a0::_REGS_REQUIRED + $a1::_REGS_REQUIRED);
+        let is_64_bit = $caller.is_64_bit();
+        impl_into_extern_fn!(@check_reg_count $a0::_regs_required(is_64_bit) + $a1::_regs_required(is_64_bit));
 
         let mut cb = impl_into_extern_fn!(@get_reg $caller);
-        let a0 = $a0::_get(&mut cb);
-        let a1 = $a1::_get(cb);
+        let a0 = $a0::_get(is_64_bit, &mut cb);
+        let a1 = $a1::_get(is_64_bit, cb);
         catch_hostcall_panic(|| ($callback)($caller, a0, a1))
     }};
 
     (@call $caller:expr, $callback:expr, $a0:ident, $a1:ident, $a2:ident) => {{
-        impl_into_extern_fn!(@check_reg_count $a0::_REGS_REQUIRED + $a1::_REGS_REQUIRED + $a2::_REGS_REQUIRED);
+        let is_64_bit = $caller.is_64_bit();
+        impl_into_extern_fn!(@check_reg_count $a0::_regs_required(is_64_bit) + $a1::_regs_required(is_64_bit) + $a2::_regs_required(is_64_bit));
 
         let mut cb = impl_into_extern_fn!(@get_reg $caller);
-        let a0 = $a0::_get(&mut cb);
-        let a1 = $a1::_get(&mut cb);
-        let a2 = $a2::_get(cb);
+        let a0 = $a0::_get(is_64_bit, &mut cb);
+        let a1 = $a1::_get(is_64_bit, &mut cb);
+        let a2 = $a2::_get(is_64_bit, cb);
         catch_hostcall_panic(|| ($callback)($caller, a0, a1, a2))
     }};
 
     (@call $caller:expr, $callback:expr, $a0:ident, $a1:ident, $a2:ident, $a3:ident) => {{
-        impl_into_extern_fn!(@check_reg_count $a0::_REGS_REQUIRED + $a1::_REGS_REQUIRED + $a2::_REGS_REQUIRED + $a3::_REGS_REQUIRED);
+        let is_64_bit = $caller.is_64_bit();
+        impl_into_extern_fn!(@check_reg_count $a0::_regs_required(is_64_bit) + $a1::_regs_required(is_64_bit) + $a2::_regs_required(is_64_bit) + $a3::_regs_required(is_64_bit));
 
         let mut cb = impl_into_extern_fn!(@get_reg $caller);
-        let a0 = $a0::_get(&mut cb);
-        let a1 = $a1::_get(&mut cb);
-        let a2 = $a2::_get(&mut cb);
-        let a3 = $a3::_get(cb);
+        let a0 = $a0::_get(is_64_bit, &mut cb);
+        let a1 = $a1::_get(is_64_bit, &mut cb);
+        let a2 = $a2::_get(is_64_bit, &mut cb);
+        let a3 = $a3::_get(is_64_bit, cb);
         catch_hostcall_panic(|| ($callback)($caller, a0, a1, a2, a3))
     }};
 
     (@call $caller:expr, $callback:expr, $a0:ident, $a1:ident, $a2:ident, $a3:ident, $a4:ident) => {{
-        impl_into_extern_fn!(@check_reg_count $a0::_REGS_REQUIRED + $a1::_REGS_REQUIRED + $a2::_REGS_REQUIRED + $a3::_REGS_REQUIRED + $a4::_REGS_REQUIRED);
+        let is_64_bit = $caller.is_64_bit();
+        impl_into_extern_fn!(@check_reg_count $a0::_regs_required(is_64_bit) + $a1::_regs_required(is_64_bit) + $a2::_regs_required(is_64_bit) + $a3::_regs_required(is_64_bit) + $a4::_regs_required(is_64_bit));
 
         let mut cb = impl_into_extern_fn!(@get_reg $caller);
-        let a0 = $a0::_get(&mut cb);
-        let a1 = $a1::_get(&mut cb);
-        let a2 = $a2::_get(&mut cb);
-        let a3 = $a3::_get(&mut cb);
-        let a4 = $a4::_get(cb);
+        let a0 = $a0::_get(is_64_bit, &mut cb);
+        let a1 = $a1::_get(is_64_bit, &mut cb);
+        let a2 = $a2::_get(is_64_bit, &mut cb);
+        let a3 = $a3::_get(is_64_bit, &mut cb);
+        let a4 = $a4::_get(is_64_bit, cb);
         catch_hostcall_panic(|| ($callback)($caller, a0, a1, a2, a3, a4))
     }};
 
     (@call $caller:expr, $callback:expr, $a0:ident, $a1:ident, $a2:ident, $a3:ident, $a4:ident, $a5:ident) => {{
-        impl_into_extern_fn!(@check_reg_count $a0::_REGS_REQUIRED + $a1::_REGS_REQUIRED + $a2::_REGS_REQUIRED + $a3::_REGS_REQUIRED + $a4::_REGS_REQUIRED + $a5::_REGS_REQUIRED);
+        let is_64_bit = $caller.is_64_bit();
+        impl_into_extern_fn!(@check_reg_count $a0::_regs_required(is_64_bit) + $a1::_regs_required(is_64_bit) + $a2::_regs_required(is_64_bit) + $a3::_regs_required(is_64_bit) + $a4::_regs_required(is_64_bit) + $a5::_regs_required(is_64_bit));
 
         let mut cb = impl_into_extern_fn!(@get_reg $caller);
-        let a0 = $a0::_get(&mut cb);
-        let a1 = $a1::_get(&mut cb);
-        let a2 = $a2::_get(&mut cb);
-        let a3 = $a3::_get(&mut cb);
-        let a4 = $a4::_get(&mut cb);
-        let a5 = $a5::_get(cb);
+        let a0 = $a0::_get(is_64_bit, &mut cb);
+        let a1 = $a1::_get(is_64_bit, &mut cb);
+        let a2 = $a2::_get(is_64_bit, &mut cb);
+        let a3 = $a3::_get(is_64_bit, &mut cb);
+        let a4 = $a4::_get(is_64_bit, &mut cb);
+        let a5 = $a5::_get(is_64_bit, cb);
         catch_hostcall_panic(|| ($callback)($caller, a0, a1, a2, a3, a4, a5))
     }};
 
@@ -1970,33 +2834,88 @@ macro_rules! impl_into_extern_fn {
                     impl_into_extern_fn!(@call caller, self.0, $($args),*)
                 })?;
 
+                let is_64_bit = raw.is_64_bit();
                 let set_reg = {
                     let mut reg_index = 0;
-                    move |value: u32| {
+                    move |value: u64| {
                         let reg = Reg::ARG_REGS[reg_index];
-                        access.set_reg(reg, value);
+                        access.set_reg64(reg, value);
 
                         if let Some(ref mut tracer) = raw.tracer() {
-                            tracer.on_set_reg_in_hostcall(reg, value as u32);
+                            tracer.on_set_reg_in_hostcall(reg, value);
                         }
 
                         reg_index += 1;
                     }
                 };
-                result._handle_return(set_reg)
+                result._handle_return(is_64_bit, set_reg)
             }
 
-            fn typecheck(&self, prototype: &ExternFnPrototype) -> Result<(), Error> {
-                let args: [ExternTy; $arg_count] = [$($args::_PRIVATE_EXTERN_TY,)*];
-                if args.len() != prototype.args().len() || args.into_iter().zip(prototype.args()).any(|(lhs, rhs)| lhs != rhs) || R::_PRIVATE_EXTERN_TY != prototype.return_ty() {
-                    bail!(
-                        "failed to instantiate module: the module wanted to import function '{}', while the function that was registered was '{}'",
-                        DisplayFn { name: prototype.name(), args: prototype.args(), return_ty: prototype.return_ty() },
-                        DisplayFn { name: prototype.name(), args: args.into_iter(), return_ty: R::_PRIVATE_EXTERN_TY },
-                    );
-                }
+            fn typecheck(&self, prototype: &ExternFnPrototype, is_64_bit: bool) -> Result<(), Error> {
+                typecheck_extern_fn(prototype, is_64_bit, &[$($args::_PRIVATE_EXTERN_TY,)*], R::_PRIVATE_EXTERN_TY)
+            }
+        }
 
-                Ok(())
+        impl<T, F, Fut, $($args,)* R> ExternFn<T> for AsyncFn<F, (Fut, R, $($args),*)>
+            where
+            F: Fn(Caller<'_, T>, $($args),*) -> Fut + Send + Sync + 'static,
+            Fut: Future<Output = R> + Send + 'static,
+            $($args: AbiTy,)*
+            R: ReturnTy,
+        {
+            fn call(&self, _user_data: &mut T, _access: BackendAccess, _raw: &mut CallerRaw) -> Result<(), Trap> {
+                log::error!("Asynchronous host function called from a synchronous call");
+                Err(Trap::from_error("asynchronous host functions can only be called from an asynchronous call"))
+            }
+
+            fn typecheck(&self, prototype: &ExternFnPrototype, is_64_bit: bool) -> Result<(), Error> {
+                typecheck_extern_fn(prototype, is_64_bit, &[$($args::_PRIVATE_EXTERN_TY,)*], R::_PRIVATE_EXTERN_TY)
+            }
+
+            fn is_async(&self) -> bool {
+                true
+            }
+
+            fn call_async(&self, user_data: &mut T, mut access: BackendAccess, raw: &mut CallerRaw) -> Result<AsyncHostcallFuture, Trap> {
+                #[allow(unused_mut)]
+                let future = Caller::wrap(user_data, &mut access, raw, move |mut caller| {
+                    impl_into_extern_fn!(@call caller, self.callback, $($args),*)
+                })?;
+
+                let is_64_bit = raw.is_64_bit();
+                Ok(Box::pin(async move {
+                    let mut return_values = Vec::new();
+                    future.await._handle_return(is_64_bit, |value| return_values.push(value))?;
+                    Ok(return_values)
+                }))
+            }
+        }
+
+        impl<T, F, Fut, $($args,)* R> IntoExternFnAsync<T, ($($args,)*), R> for F
+        where
+            F: Fn($($args),*) -> Fut + Send + Sync + 'static,
+            Fut: Future<Output = R> + Send + 'static,
+            $($args: AbiTy,)*
+            R: ReturnTy,
+        {
+            fn _into_extern_fn(self) -> ExternFnArc<T> {
+                #[allow(non_snake_case)]
+                let callback = move |_caller: Caller<T>, $($args: $args),*| -> Fut {
+                    self($($args),*)
+                };
+                ExternFnArc(Arc::new(AsyncFn { callback, _phantom: UnsafePhantomData(PhantomData::<(Fut, R, $($args),*)>) }))
+            }
+        }
+
+        impl<T, F, Fut, $($args,)* R> IntoExternFnAsync<T, (Caller<'_, T>, $($args,)*), R> for F
+        where
+            F: Fn(Caller<'_, T>, $($args),*) -> Fut + Send + Sync + 'static,
+            Fut: Future<Output = R> + Send + 'static,
+            $($args: AbiTy,)*
+            R: ReturnTy,
+        {
+            fn _into_extern_fn(self) -> ExternFnArc<T> {
+                ExternFnArc(Arc::new(AsyncFn { callback: self, _phantom: UnsafePhantomData(PhantomData::<(Fut, R, $($args),*)>) }))
             }
         }
 
@@ -2034,9 +2953,9 @@ macro_rules! impl_into_extern_fn {
             #[allow(unused_mut)]
             #[allow(unused_variables)]
             #[allow(non_snake_case)]
-            fn _set(self, mut set_reg: impl FnMut(u32)) {
+            fn _set(self, is_64_bit: bool, mut set_reg: impl FnMut(u64)) {
                 let ($($args,)*) = self;
-                $($args._set(&mut set_reg);)*
+                $($args._set(is_64_bit, &mut set_reg);)*
             }
         }
     };
@@ -2050,6 +2969,36 @@ impl_into_extern_fn!(4 A0 A1 A2 A3);
 impl_into_extern_fn!(5 A0 A1 A2 A3 A4);
 impl_into_extern_fn!(6 A0 A1 A2 A3 A4 A5);
 
+fn typecheck_extern_fn(prototype: &ExternFnPrototype, is_64_bit: bool, args: &[ExternTy], returns: &[ExternTy]) -> Result<(), Error> {
+    if args.len() != prototype.args().len()
+        || args.iter().copied().zip(prototype.args()).any(|(lhs, rhs)| lhs != rhs)
+        || !returns.iter().copied().eq(prototype.returns())
+    {
+        bail!(
+            "failed to instantiate module: the module wanted to import function '{}', while the function that was registered was '{}'",
+            DisplayFn {
+                name: prototype.name(),
+                args: prototype.args(),
+                returns: prototype.returns()
+            },
+            DisplayFn {
+                name: prototype.name(),
+                args: args.iter().copied(),
+                returns: returns.iter().copied()
+            },
+        );
+    }
+
+    if regs_required(is_64_bit, returns.iter().copied()) > Reg::ARG_REGS.len() {
+        bail!(
+            "failed to instantiate module: the imported function '{}' returns more values than can fit in the registers",
+            prototype.name()
+        );
+    }
+
+    Ok(())
+}
+
 #[repr(transparent)]
 struct UnsafePhantomData<T>(PhantomData<T>);
 
@@ -2059,6 +3008,11 @@ unsafe impl<T> Send for UnsafePhantomData<T> {}
 // SAFETY: This is only used to hold a type used exclusively at compile time, so regardless of whether it implements `Sync` this will be safe.
 unsafe impl<T> Sync for UnsafePhantomData<T> {}
 
+struct AsyncFn<F, P> {
+    callback: F,
+    _phantom: UnsafePhantomData<P>,
+}
+
 struct DynamicFn<T, F> {
     args: Vec<ExternTy>,
     return_ty: Option<ExternTy>,
@@ -2092,6 +3046,7 @@ where
         let mut args = [DEFAULT; VM_MAXIMUM_EXTERN_ARG_COUNT];
         let args = &mut args[..self.args.len()];
 
+        let is_64_bit = raw.is_64_bit();
         let mut arg_regs = Reg::ARG_REGS.into_iter();
         for (&arg_ty, arg) in self.args.iter().zip(args.iter_mut()) {
             match arg_ty {
@@ -2101,7 +3056,15 @@ where
                         return Err(Trap::default());
                     };
 
-                    *arg = Val::I32(access.get_reg(reg) as i32);
+                    *arg = Val::I32(access.get_reg64(reg) as i32);
+                }
+                ExternTy::I64 if is_64_bit => {
+                    let Some(reg) = arg_regs.next() else {
+                        log::error!("dynamic host call called with too many arguments");
+                        return Err(Trap::default());
+                    };
+
+                    *arg = Val::I64(access.get_reg64(reg) as i64);
                 }
                 ExternTy::I64 => {
                     let Some(reg_1) = arg_regs.next() else {
@@ -2114,8 +3077,8 @@ where
                         return Err(Trap::default());
                     };
 
-                    let lo = access.get_reg(reg_1);
-                    let hi = access.get_reg(reg_2);
+                    let lo = access.get_reg64(reg_1) as u32;
+                    let hi = access.get_reg64(reg_2) as u32;
                     *arg = Val::I64((u64::from(lo) | (u64::from(hi) << 32)) as i64);
                 }
             }
@@ -2145,10 +3108,18 @@ where
                         return Err(Trap::default());
                     }
 
-                    access.set_reg(Reg::A0, value as u32);
-                    if let Some(tracer) = raw.tracer() {
-                        tracer.on_set_reg_in_hostcall(Reg::A0, value as u32);
-                    }
+                    let mut set_reg = |reg, value| {
+                        access.set_reg64(reg, value);
+                        if let Some(tracer) = raw.tracer() {
+                            tracer.on_set_reg_in_hostcall(reg, value);
+                        }
+                    };
+
+                    let mut reg_index = 0;
+                    value._set(is_64_bit, |value| {
+                        set_reg(Reg::ARG_REGS[reg_index], value);
+                        reg_index += 1;
+                    });
                 }
                 Val::I64(value) => {
                     if return_ty != ExternTy::I64 {
@@ -2159,14 +3130,18 @@ where
                         return Err(Trap::default());
                     }
 
-                    let value = value as u64;
-                    access.set_reg(Reg::A0, value as u32);
-                    access.set_reg(Reg::A1, (value >> 32) as u32);
+                    let mut set_reg = |reg, value| {
+                        access.set_reg64(reg, value);
+                        if let Some(tracer) = raw.tracer() {
+                            tracer.on_set_reg_in_hostcall(reg, value);
+                        }
+                    };
 
-                    if let Some(tracer) = raw.tracer() {
-                        tracer.on_set_reg_in_hostcall(Reg::A0, value as u32);
-                        tracer.on_set_reg_in_hostcall(Reg::A1, (value >> 32) as u32);
-                    }
+                    let mut reg_index = 0;
+                    value._set(is_64_bit, |value| {
+                        set_reg(Reg::ARG_REGS[reg_index], value);
+                        reg_index += 1;
+                    });
                 }
             }
         }
@@ -2174,22 +3149,138 @@ where
         Ok(())
     }
 
-    fn typecheck(&self, prototype: &ExternFnPrototype) -> Result<(), Error> {
+    fn typecheck(&self, prototype: &ExternFnPrototype, _is_64_bit: bool) -> Result<(), Error> {
         if self.args.len() != prototype.args().len()
             || self.args.iter().zip(prototype.args()).any(|(lhs, rhs)| *lhs != rhs)
-            || self.return_ty != prototype.return_ty()
+            || !self.return_ty.into_iter().eq(prototype.returns())
         {
             bail!(
                 "failed to instantiate module: the module wanted to import function '{}', while the function that was registered was '{}'",
                 DisplayFn {
                     name: prototype.name(),
                     args: prototype.args(),
-                    return_ty: prototype.return_ty()
+                    returns: prototype.returns()
                 },
                 DisplayFn {
                     name: prototype.name(),
                     args: self.args.iter().copied(),
-                    return_ty: self.return_ty
+                    returns: self.return_ty.into_iter()
+                },
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// A host function which forwards the call to a function exported by another instance.
+struct InstanceExportFn<T> {
+    instance: Instance<T>,
+    export_index: usize,
+}
+
+impl<T> InstanceExportFn<T> {
+    fn prototype(&self) -> &ExternFnPrototype<'_> {
+        self.instance.0.instance_pre.0.module.0.exports[self.export_index].prototype()
+    }
+}
+
+impl<T> ExternFn<T> for InstanceExportFn<T>
+where
+    T: Send + Sync,
+{
+    fn call(&self, user_data: &mut T, mut access: BackendAccess, raw: &mut CallerRaw) -> Result<(), Trap> {
+        let prototype = self.prototype();
+        let mut config = ExecutionConfig::default();
+        let input_count = regs_required(raw.is_64_bit(), prototype.args());
+        for (index, reg) in Reg::ARG_REGS.into_iter().take(input_count).enumerate() {
+            config.initial_regs[Reg::A0 as usize + index] = access.get_reg64(reg);
+        }
+
+        // The callee can only spend as much gas as the caller has left; whatever it uses is then charged back to the caller.
+        let gas_before = access.gas_remaining();
+        if let Some(gas) = gas_before {
+            config.set_gas(gas);
+        }
+
+        // The instance is locked while it's running, so calling back into an instance which is already busy would deadlock.
+        let mut mutable = match self.instance.0.mutable.try_lock() {
+            Ok(mutable) => mutable,
+            Err(std::sync::TryLockError::Poisoned(poison)) => poison.into_inner(),
+            Err(std::sync::TryLockError::WouldBlock) => {
+                log::debug!("hostcall into another instance failed: the instance is busy");
+                return Err(Trap::from_error(format!(
+                    "failed to call function '{}' of another instance: the instance is busy",
+                    prototype.name()
+                )));
+            }
+        };
+
+        // Making a new call would silently discard the suspended one, so refuse to do that.
+        if mutable.has_suspended_call() {
+            log::debug!("hostcall into another instance failed: the instance has a suspended call");
+            return Err(Trap::from_error(format!(
+                "failed to call function '{}' of another instance: the instance has a suspended call",
+                prototype.name()
+            )));
+        }
+
+        let result = self
+            .instance
+            .call_export_locked(&mut mutable, user_data, self.export_index, &config, None);
+        if let Some(gas_before) = gas_before {
+            let gas_used = if matches!(result, Err(ExecutionError::OutOfGas)) {
+                // Make sure the caller runs out of gas too.
+                gas_before.get() + 1
+            } else {
+                mutable
+                    .backend
+                    .access()
+                    .gas_remaining()
+                    .map_or(0, |gas_after| gas_before.get().saturating_sub(gas_after.get()))
+            };
+
+            access.consume_gas(gas_used);
+        }
+
+        if let Err(error) = result {
+            log::debug!("hostcall into another instance failed: {}", error);
+            return Err(Trap::from_error(error));
+        }
+
+        let output_count = regs_required(raw.is_64_bit(), prototype.returns());
+        for reg in Reg::ARG_REGS.into_iter().take(output_count) {
+            let value = mutable.backend.access().get_reg64(reg);
+            access.set_reg64(reg, value);
+            if let Some(tracer) = raw.tracer() {
+                tracer.on_set_reg_in_hostcall(reg, value);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn typecheck(&self, prototype: &ExternFnPrototype, is_64_bit: bool) -> Result<(), Error> {
+        if self.instance.0.instance_pre.0.module.is_64_bit() != is_64_bit {
+            bail!(
+                "failed to instantiate module: the imported function '{}' is exported by a module which targets a different variant of the instruction set",
+                prototype.name()
+            );
+        }
+
+        let export_prototype = self.prototype();
+        if !prototype.args().eq(export_prototype.args()) || !prototype.returns().eq(export_prototype.returns()) {
+            bail!(
+                "failed to instantiate module: the module wanted to import function '{}', while the function that was linked was '{}'",
+                DisplayFn {
+                    name: prototype.name(),
+                    args: prototype.args(),
+                    returns: prototype.returns()
+                },
+                DisplayFn {
+                    name: prototype.name(),
+                    args: export_prototype.args(),
+                    returns: export_prototype.returns()
                 },
             );
         }
@@ -2199,12 +3290,15 @@ where
 }
 
 type FallbackHandlerArc<T> = Arc<dyn Fn(Caller<'_, T>, u32) -> Result<(), Trap> + Send + Sync + 'static>;
+type OutOfGasHandlerArc<T> = Arc<dyn Fn(Caller<'_, T>) -> Result<Gas, Trap> + Send + Sync + 'static>;
 
 pub struct Linker<T> {
     engine_state: Arc<EngineState>,
     host_functions: HashMap<String, ExternFnArc<T>>,
     #[allow(clippy::type_complexity)]
     fallback_handler: Option<FallbackHandlerArc<T>>,
+    out_of_gas_handler: Option<OutOfGasHandlerArc<T>>,
+    allow_shadowing: bool,
     phantom: PhantomData<T>,
 }
 
@@ -2214,15 +3308,47 @@ impl<T> Linker<T> {
             engine_state: Arc::clone(&engine.state),
             host_functions: Default::default(),
             fallback_handler: None,
+            out_of_gas_handler: None,
+            allow_shadowing: false,
             phantom: PhantomData,
         }
     }
 
+    /// Sets whether a host function can be redefined under a name which is already registered.
+    ///
+    /// When enabled the new definition replaces the old one; otherwise trying to do so is an error.
+    ///
+    /// Default: `false`
+    pub fn allow_shadowing(&mut self, value: bool) -> &mut Self {
+        self.allow_shadowing = value;
+        self
+    }
+
+    fn define(&mut self, name: String, func: ExternFnArc<T>) -> Result<&mut Self, Error> {
+        if !self.allow_shadowing && self.host_functions.contains_key(&name) {
+            bail!("cannot register host function: host function was already registered: '{}'", name);
+        }
+
+        self.host_functions.insert(name, func);
+        Ok(self)
+    }
+
     /// Defines a fallback external call handler, in case no other registered functions match.
     pub fn func_fallback(&mut self, func: impl Fn(Caller<'_, T>, u32) -> Result<(), Trap> + Send + Sync + 'static) {
         self.fallback_handler = Some(Arc::new(func));
     }
 
+    /// Defines a handler which will be called when the program runs out of gas, instead of immediately aborting the execution.
+    ///
+    /// The gas returned by the handler is added to the gas remaining and the execution continues from where it left off.
+    /// If that's still not enough then the execution ends with [`ExecutionError::OutOfGas`](crate::ExecutionError::OutOfGas)
+    /// as usual, and if the handler returns an error then the execution is aborted with that trap.
+    ///
+    /// This is only supported with [`GasMeteringKind::Sync`].
+    pub fn on_out_of_gas(&mut self, func: impl Fn(Caller<'_, T>) -> Result<Gas, Trap> + Send + Sync + 'static) {
+        self.out_of_gas_handler = Some(Arc::new(func));
+    }
+
     /// Defines a new dynamically typed handler for external calls with a given name.
     pub fn func_new(
         &mut self,
@@ -2233,11 +3359,7 @@ impl<T> Linker<T> {
     where
         T: 'static,
     {
-        if self.host_functions.contains_key(name) {
-            bail!("cannot register host function: host function was already registered: '{}'", name);
-        }
-
-        self.host_functions.insert(
+        self.define(
             name.to_owned(),
             ExternFnArc(Arc::new(DynamicFn {
                 args: ty.args,
@@ -2245,19 +3367,75 @@ impl<T> Linker<T> {
                 callback: func,
                 _phantom: UnsafePhantomData(PhantomData),
             })),
-        );
-
-        Ok(self)
+        )
     }
 
     /// Defines a new statically typed handler for external calls with a given name.
     pub fn func_wrap<Params, Args>(&mut self, name: &str, func: impl IntoExternFn<T, Params, Args>) -> Result<&mut Self, Error> {
-        if self.host_functions.contains_key(name) {
-            bail!("cannot register host function: host function was already registered: '{}'", name);
-        }
+        self.define(name.to_owned(), func._into_extern_fn())
+    }
 
-        self.host_functions.insert(name.to_owned(), func._into_extern_fn());
-        Ok(self)
+    /// Defines a new statically typed handler for external calls with a given name inside of a given namespace.
+    ///
+    /// The guest imports such a function as `namespace::name`, e.g. with `#[polkavm_import(module = "namespace")]`.
+    /// This is equivalent to calling [`Linker::func_wrap`] with a `"namespace::name"` name.
+    pub fn func_wrap_in<Params, Args>(
+        &mut self,
+        namespace: &str,
+        name: &str,
+        func: impl IntoExternFn<T, Params, Args>,
+    ) -> Result<&mut Self, Error> {
+        let name = qualified_import_name(namespace, name)?;
+        self.define(name, func._into_extern_fn())
+    }
+
+    /// Makes an already registered host function also available under another name.
+    ///
+    /// Both of the names can be namespaced (e.g. `"env_v2::get"`), which can be used to expose the same function
+    /// from multiple versions of an API.
+    pub fn alias(&mut self, name: &str, existing_name: &str) -> Result<&mut Self, Error> {
+        let Some(func) = self.host_functions.get(existing_name) else {
+            bail!("cannot alias host function '{name}': no such host function was registered: '{existing_name}'");
+        };
+
+        let func = func.clone();
+        self.define(name.to_owned(), func)
+    }
+
+    /// Defines a new statically typed handler for external calls with a given name which returns a future.
+    ///
+    /// Such a function can only be called through [`Func::call_async`] or [`TypedFunc::call_async`]; while its future
+    /// is pending the call yields to the executor. Calling it through a synchronous call will trap.
+    ///
+    /// The returned future is `'static` and runs while the instance is unlocked, so it can't hold on to the [`Caller`]
+    /// nor access the guest's memory; read anything it needs before returning it.
+    pub fn func_wrap_async<Params, Args>(&mut self, name: &str, func: impl IntoExternFnAsync<T, Params, Args>) -> Result<&mut Self, Error> {
+        self.define(name.to_owned(), func._into_extern_fn())
+    }
+
+    /// Links an import with a given name to a function exported by another instance.
+    ///
+    /// Calls to such an import go through the VM into the other instance, with the arguments and the return values
+    /// passed in registers; the memories of both instances stay isolated. The other instance runs with a default
+    /// [`ExecutionConfig`] and gets the same user data as the instance which called it.
+    ///
+    /// An instance can't be called into while it's already running, so calling back into the instance
+    /// which made the call (whether directly or indirectly) will trap.
+    pub fn instance_export(&mut self, name: &str, instance: &Instance<T>, export_name: &str) -> Result<&mut Self, Error>
+    where
+        T: Send + Sync + 'static,
+    {
+        let Some(&export_index) = instance.0.instance_pre.0.module.0.export_index_by_name.get(export_name) else {
+            bail!("cannot link host function '{name}': no such function is exported: '{export_name}'");
+        };
+
+        self.define(
+            name.to_owned(),
+            ExternFnArc(Arc::new(InstanceExportFn {
+                instance: instance.clone(),
+                export_index,
+            })),
+        )
     }
 
     /// Pre-instantiates a new module, linking it with the external functions previously defined on this object.
@@ -2275,7 +3453,7 @@ impl<T> Linker<T> {
                 bail!("failed to instantiate module: missing host function: '{}'", prototype.name());
             };
 
-            host_fn.0.typecheck(prototype)?;
+            host_fn.0.typecheck(prototype, module.is_64_bit())?;
             host_functions.insert(*index, host_fn.clone());
         }
 
@@ -2284,17 +3462,26 @@ impl<T> Linker<T> {
             module: module.clone(),
             host_functions,
             fallback_handler: self.fallback_handler.clone(),
+            out_of_gas_handler: self.out_of_gas_handler.clone(),
             _private: PhantomData,
         })))
     }
 }
 
+fn qualified_import_name(namespace: &str, name: &str) -> Result<String, Error> {
+    if namespace.is_empty() || namespace.contains("::") {
+        bail!("cannot register host function '{name}': invalid namespace: '{namespace}'");
+    }
+
+    Ok(format!("{namespace}::{name}"))
+}
+
 struct InstancePrePrivate<T> {
-    #[allow(dead_code)]
     engine_state: Arc<EngineState>,
     module: Module,
     host_functions: HashMap<u32, ExternFnArc<T>>,
     fallback_handler: Option<FallbackHandlerArc<T>>,
+    out_of_gas_handler: Option<OutOfGasHandlerArc<T>>,
     _private: PhantomData<T>,
 }
 
@@ -2309,17 +3496,18 @@ impl<T> Clone for InstancePre<T> {
 impl<T> InstancePre<T> {
     /// Instantiates a new module.
     pub fn instantiate(&self) -> Result<Instance<T>, Error> {
+        let interrupt = Arc::new(InterruptState::default());
         let compiled_module = &self.0.module.0.compiled_module;
         let backend = if_compiler_is_supported! {
             {
                 match compiled_module {
                     #[cfg(target_os = "linux")]
                     CompiledModuleKind::Linux(..) => {
-                        let compiled_instance = CompiledInstance::new(Arc::clone(&self.0.engine_state), self.0.module.clone())?;
+                        let compiled_instance = CompiledInstance::new(Arc::clone(&self.0.engine_state), self.0.module.clone(), Arc::clone(&interrupt))?;
                         Some(InstanceBackend::CompiledLinux(compiled_instance))
                     },
                     CompiledModuleKind::Generic(..) => {
-                        let compiled_instance = CompiledInstance::new(Arc::clone(&self.0.engine_state), self.0.module.clone())?;
+                        let compiled_instance = CompiledInstance::new(Arc::clone(&self.0.engine_state), self.0.module.clone(), Arc::clone(&interrupt))?;
                         Some(InstanceBackend::CompiledGeneric(compiled_instance))
                     },
                     CompiledModuleKind::Unavailable => None
@@ -2334,7 +3522,7 @@ impl<T> InstancePre<T> {
         let backend = match backend {
             Some(backend) => backend,
             None => {
-                let interpreted_instance = InterpretedInstance::new(self.0.module.clone())?;
+                let interpreted_instance = InterpretedInstance::new(self.0.module.clone(), Some(Arc::clone(&interrupt)))?;
                 InstanceBackend::Interpreted(interpreted_instance)
             }
         };
@@ -2347,9 +3535,13 @@ impl<T> InstancePre<T> {
 
         Ok(Instance(Arc::new(InstancePrivate {
             instance_pre: self.clone(),
+            interrupt,
             mutable: Mutex::new(InstancePrivateMut {
                 backend,
-                raw: CallerRaw::new(tracer),
+                raw: CallerRaw::new(tracer, self.0.module.is_64_bit(), *self.0.module.memory_config()),
+                suspended_call: None,
+                async_call_token: None,
+                next_async_call_token: 0,
             }),
         })))
     }
@@ -2388,6 +3580,46 @@ impl InstanceBackend {
         }
     }
 
+    fn resume(&mut self, on_hostcall: OnHostcall) -> Result<(), ExecutionError> {
+        if_compiler_is_supported! {
+            {
+                match self {
+                    #[cfg(target_os = "linux")]
+                    InstanceBackend::CompiledLinux(ref mut backend) => backend.resume(on_hostcall),
+                    InstanceBackend::CompiledGeneric(ref mut backend) => backend.resume(on_hostcall),
+                    InstanceBackend::Interpreted(ref mut backend) => backend.resume(on_hostcall),
+                }
+            } else {
+                match self {
+                    InstanceBackend::Interpreted(ref mut backend) => backend.resume(on_hostcall),
+                }
+            }
+        }
+    }
+
+    fn resize_heap(&mut self, heap_top: u32) -> Result<(), Error> {
+        if_compiler_is_supported! {
+            {
+                match self {
+                    #[cfg(target_os = "linux")]
+                    InstanceBackend::CompiledLinux(ref mut backend) => backend.resize_heap(heap_top),
+                    InstanceBackend::CompiledGeneric(ref mut backend) => backend.resize_heap(heap_top),
+                    InstanceBackend::Interpreted(ref mut backend) => {
+                        backend.resize_heap(heap_top);
+                        Ok(())
+                    }
+                }
+            } else {
+                match self {
+                    InstanceBackend::Interpreted(ref mut backend) => {
+                        backend.resize_heap(heap_top);
+                        Ok(())
+                    }
+                }
+            }
+        }
+    }
+
     fn access(&mut self) -> BackendAccess {
         if_compiler_is_supported! {
             {
@@ -2405,34 +3637,86 @@ impl InstanceBackend {
         }
     }
 
-    fn pid(&self) -> Option<u32> {
+    fn instructions_executed(&self) -> Option<u64> {
         if_compiler_is_supported! {
             {
                 match self {
                     #[cfg(target_os = "linux")]
-                    InstanceBackend::CompiledLinux(ref backend) => backend.sandbox().pid(),
-                    InstanceBackend::CompiledGeneric(ref backend) => backend.sandbox().pid(),
-                    InstanceBackend::Interpreted(..) => None,
+                    InstanceBackend::CompiledLinux(..) => None,
+                    InstanceBackend::CompiledGeneric(..) => None,
+                    InstanceBackend::Interpreted(ref backend) => Some(backend.cycle_counter()),
                 }
             } else {
                 match self {
-                    InstanceBackend::Interpreted(..) => None,
+                    InstanceBackend::Interpreted(ref backend) => Some(backend.cycle_counter()),
                 }
             }
         }
     }
-}
-
-#[derive(Debug)]
-pub struct MemoryAccessError<T> {
-    pub address: u32,
-    pub length: u64,
-    pub error: T,
-}
 
-impl<T> core::fmt::Display for MemoryAccessError<T>
-where
-    T: core::fmt::Display,
+    fn basic_blocks_executed(&self, module: &Module) -> Option<u64> {
+        if_compiler_is_supported! {
+            {
+                match self {
+                    #[cfg(target_os = "linux")]
+                    InstanceBackend::CompiledLinux(ref backend) => module.0.count_basic_blocks.then(|| backend.sandbox().basic_blocks_executed()),
+                    InstanceBackend::CompiledGeneric(ref backend) => module.0.count_basic_blocks.then(|| backend.sandbox().basic_blocks_executed()),
+                    InstanceBackend::Interpreted(ref backend) => Some(backend.basic_blocks_executed()),
+                }
+            } else {
+                let _ = module;
+                match self {
+                    InstanceBackend::Interpreted(ref backend) => Some(backend.basic_blocks_executed()),
+                }
+            }
+        }
+    }
+
+    fn wait_counters(&self) -> Option<(u64, u64)> {
+        if_compiler_is_supported! {
+            {
+                match self {
+                    #[cfg(target_os = "linux")]
+                    InstanceBackend::CompiledLinux(ref backend) => Some(backend.sandbox().wait_counters()),
+                    InstanceBackend::CompiledGeneric(..) => None,
+                    InstanceBackend::Interpreted(..) => None,
+                }
+            } else {
+                match self {
+                    InstanceBackend::Interpreted(..) => None,
+                }
+            }
+        }
+    }
+
+    fn pid(&self) -> Option<u32> {
+        if_compiler_is_supported! {
+            {
+                match self {
+                    #[cfg(target_os = "linux")]
+                    InstanceBackend::CompiledLinux(ref backend) => backend.sandbox().pid(),
+                    InstanceBackend::CompiledGeneric(ref backend) => backend.sandbox().pid(),
+                    InstanceBackend::Interpreted(..) => None,
+                }
+            } else {
+                match self {
+                    InstanceBackend::Interpreted(..) => None,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MemoryAccessError<T> {
+    pub address: u32,
+    pub length: u64,
+    pub error: T,
+}
+
+impl<T> core::fmt::Display for MemoryAccessError<T>
+where
+    T: core::fmt::Display,
 {
     fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
         write!(
@@ -2492,15 +3776,50 @@ if_compiler_is_supported! {
     }
 }
 
+impl<'a> BackendAccess<'a> {
+    /// Calls the given export from within the hostcall which is currently being handled through this access.
+    pub(crate) fn call_nested(
+        &mut self,
+        module: &Module,
+        export_index: usize,
+        regs: &mut [u64; Reg::ALL.len()],
+        on_hostcall: OnHostcall,
+    ) -> Result<(), ExecutionError> {
+        if_compiler_is_supported! {
+            {
+                match self {
+                    #[cfg(target_os = "linux")]
+                    BackendAccess::CompiledLinux(access) => crate::compiler::call_nested::<SandboxLinux>(module, access, export_index, regs, on_hostcall),
+                    BackendAccess::CompiledGeneric(access) => crate::compiler::call_nested::<SandboxGeneric>(module, access, export_index, regs, on_hostcall),
+                    BackendAccess::Interpreted(access) => access.call_nested(export_index, regs, on_hostcall),
+                }
+            } else {{
+                let _ = module;
+                match self {
+                    BackendAccess::Interpreted(access) => access.call_nested(export_index, regs, on_hostcall),
+                }
+            }}
+        }
+    }
+}
+
 impl<'a> Access<'a> for BackendAccess<'a> {
     type Error = Trap;
 
     fn get_reg(&self, reg: Reg) -> u32 {
-        access_backend!(self, |access| access.get_reg(reg))
+        self.get_reg64(reg) as u32
     }
 
     fn set_reg(&mut self, reg: Reg, value: u32) {
-        access_backend!(self, |access| access.set_reg(reg, value))
+        self.set_reg64(reg, u64::from(value));
+    }
+
+    fn get_reg64(&self, reg: Reg) -> u64 {
+        access_backend!(self, |access| access.get_reg64(reg))
+    }
+
+    fn set_reg64(&mut self, reg: Reg, value: u64) {
+        access_backend!(self, |access| access.set_reg64(reg, value))
     }
 
     fn read_memory_into_slice<'slice, B>(&self, address: u32, buffer: &'slice mut B) -> Result<&'slice mut [u8], Self::Error>
@@ -2516,6 +3835,18 @@ impl<'a> Access<'a> for BackendAccess<'a> {
         access_backend!(self, |access| Ok(access.write_memory(address, data).map_err(map_access_error)?))
     }
 
+    fn with_memory<R>(&self, address: u32, length: u32, callback: impl FnOnce(&[u8]) -> R) -> Result<R, Self::Error> {
+        access_backend!(self, |access| Ok(access
+            .with_memory(address, length, callback)
+            .map_err(map_access_error)?))
+    }
+
+    fn with_memory_mut<R>(&mut self, address: u32, length: u32, callback: impl FnOnce(&mut [u8]) -> R) -> Result<R, Self::Error> {
+        access_backend!(self, |access| Ok(access
+            .with_memory_mut(address, length, callback)
+            .map_err(map_access_error)?))
+    }
+
     fn program_counter(&self) -> Option<u32> {
         access_backend!(self, |access| access.program_counter())
     }
@@ -2524,6 +3855,10 @@ impl<'a> Access<'a> for BackendAccess<'a> {
         access_backend!(self, |access| access.native_program_counter())
     }
 
+    fn heap_size(&self) -> u32 {
+        access_backend!(self, |access| access.heap_size())
+    }
+
     fn gas_remaining(&self) -> Option<Gas> {
         access_backend!(self, |access| access.gas_remaining())
     }
@@ -2531,21 +3866,139 @@ impl<'a> Access<'a> for BackendAccess<'a> {
     fn consume_gas(&mut self, gas: u64) {
         access_backend!(self, |access| access.consume_gas(gas))
     }
+
+    fn add_gas(&mut self, gas: u64) {
+        access_backend!(self, |access| access.add_gas(gas))
+    }
+
+    fn set_gas(&mut self, gas: Gas) {
+        access_backend!(self, |access| access.set_gas(gas))
+    }
+}
+
+/// The state shared between an instance and its interrupt handles.
+#[derive(Default)]
+pub(crate) struct InterruptState {
+    is_interrupted: AtomicBool,
+
+    /// The worker process which is currently running the program, if any.
+    #[cfg(all(not(miri), target_arch = "x86_64", target_os = "linux"))]
+    worker: Mutex<Option<crate::sandbox::linux::InterruptTarget>>,
+}
+
+impl InterruptState {
+    pub fn interrupt(&self) {
+        self.is_interrupted.store(true, Ordering::Relaxed);
+
+        #[cfg(all(not(miri), target_arch = "x86_64", target_os = "linux"))]
+        {
+            let worker = match self.worker.lock() {
+                Ok(worker) => worker,
+                Err(poison) => poison.into_inner(),
+            };
+
+            if let Some(ref worker) = *worker {
+                worker.interrupt();
+            }
+        }
+    }
+
+    #[inline]
+    pub fn is_interrupted(&self) -> bool {
+        self.is_interrupted.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn clear(&self) {
+        self.is_interrupted.store(false, Ordering::Relaxed);
+    }
+
+    #[cfg(all(not(miri), target_arch = "x86_64", target_os = "linux"))]
+    pub fn set_worker(&self, target: Option<crate::sandbox::linux::InterruptTarget>) {
+        let mut worker = match self.worker.lock() {
+            Ok(worker) => worker,
+            Err(poison) => poison.into_inner(),
+        };
+
+        *worker = target;
+    }
+}
+
+/// A handle which can be used to interrupt the execution of an [`Instance`], possibly from another thread.
+///
+/// The interrupted call will return [`ExecutionError::Interrupted`](crate::ExecutionError::Interrupted).
+#[derive(Clone)]
+pub struct InterruptHandle(Arc<InterruptState>);
+
+impl InterruptHandle {
+    /// Interrupts the call which is currently running in the instance this handle was created from.
+    ///
+    /// Does nothing if no call is running; every new call starts out uninterrupted.
+    pub fn interrupt(&self) {
+        self.0.interrupt();
+    }
+}
+
+/// A call which was suspended in the middle of a hostcall.
+struct SuspendedCall {
+    export_index: usize,
+    hostcall: u32,
 }
 
 struct InstancePrivateMut {
     backend: InstanceBackend,
     raw: CallerRaw,
+    suspended_call: Option<SuspendedCall>,
+    /// The token of the asynchronous call which is currently in progress, if any.
+    ///
+    /// The instance isn't locked while an asynchronous call is waiting for a host function,
+    /// so this is what keeps any other call from touching the execution in the meantime.
+    async_call_token: Option<u64>,
+    next_async_call_token: u64,
 }
 
 impl InstancePrivateMut {
     fn tracer(&mut self) -> Option<&mut Tracer> {
         self.raw.tracer()
     }
+
+    fn has_suspended_call(&self) -> bool {
+        self.suspended_call.is_some() || self.async_call_token.is_some()
+    }
+}
+
+#[cold]
+fn error_async_call_in_progress(export: &ProgramExport) -> ExecutionError {
+    ExecutionError::Error(Error::from(format!(
+        "failed to call function '{}': an asynchronous call into this instance is in progress",
+        export.prototype().name()
+    )))
+}
+
+/// Makes sure an asynchronous call doesn't keep the instance blocked if its future gets dropped while it's waiting.
+struct AsyncCallGuard<'a> {
+    mutable: &'a Mutex<InstancePrivateMut>,
+    token: Option<u64>,
+}
+
+impl<'a> Drop for AsyncCallGuard<'a> {
+    fn drop(&mut self) {
+        let Some(token) = self.token else { return };
+        let mut mutable = match self.mutable.lock() {
+            Ok(mutable) => mutable,
+            Err(poison) => poison.into_inner(),
+        };
+
+        // The suspended execution will be simply abandoned by the next call.
+        if mutable.async_call_token == Some(token) {
+            mutable.async_call_token = None;
+        }
+    }
 }
 
 struct InstancePrivate<T> {
     instance_pre: InstancePre<T>,
+    interrupt: Arc<InterruptState>,
     mutable: Mutex<InstancePrivateMut>,
 }
 
@@ -2583,30 +4036,37 @@ impl<T> Instance<T> {
         let export = &self.0.instance_pre.0.module.0.exports[export_index];
         let prototype = export.prototype();
 
-        let return_ty = FnResult::_PRIVATE_EXTERN_TY;
+        let returns = FnResult::_PRIVATE_EXTERN_TY;
         let args = FnArgs::_PRIVATE_EXTERN_TY;
 
         if args.len() != prototype.args().len()
             || args.iter().copied().zip(prototype.args()).any(|(lhs, rhs)| lhs != rhs)
-            || return_ty != prototype.return_ty()
+            || !returns.iter().copied().eq(prototype.returns())
         {
             let error = format!(
                 "failed to get function: wanted to get function '{}', while the function that was exported was '{}'",
                 DisplayFn {
                     name: prototype.name(),
                     args: args.iter().copied(),
-                    return_ty
+                    returns: returns.iter().copied()
                 },
                 DisplayFn {
                     name: prototype.name(),
                     args: prototype.args(),
-                    return_ty: prototype.return_ty()
+                    returns: prototype.returns()
                 },
             );
 
             return Err(error.into());
         }
 
+        if regs_required(self.0.instance_pre.0.module.is_64_bit(), returns.iter().copied()) > Reg::ARG_REGS.len() {
+            return Err(Error::from(format!(
+                "failed to get function '{}': it returns more values than can fit in the registers",
+                name
+            )));
+        }
+
         Ok(TypedFunc {
             instance: self.clone(),
             export_index,
@@ -2644,13 +4104,47 @@ impl<T> Instance<T> {
         mutable.backend.access().write_memory(address, data)
     }
 
+    /// Calls the given closure with a view of the instance's memory at the given address.
+    ///
+    /// See [`Caller::with_memory`] for more details.
+    pub fn with_memory<R>(&self, address: u32, length: u32, callback: impl FnOnce(&[u8]) -> R) -> Result<R, Trap> {
+        let mut mutable = match self.0.mutable.lock() {
+            Ok(mutable) => mutable,
+            Err(poison) => poison.into_inner(),
+        };
+
+        mutable.backend.access().with_memory(address, length, callback)
+    }
+
+    /// Calls the given closure with a mutable view of the instance's memory at the given address.
+    ///
+    /// See [`Caller::with_memory_mut`] for more details.
+    pub fn with_memory_mut<R>(&self, address: u32, length: u32, callback: impl FnOnce(&mut [u8]) -> R) -> Result<R, Trap> {
+        let mut mutable = match self.0.mutable.lock() {
+            Ok(mutable) => mutable,
+            Err(poison) => poison.into_inner(),
+        };
+
+        mutable.backend.access().with_memory_mut(address, length, callback)
+    }
+
     pub fn get_reg(&self, reg: Reg) -> u32 {
+        self.get_reg64(reg) as u32
+    }
+
+    /// Gets the full value of a register, for programs which target the 64-bit variant of the instruction set.
+    pub fn get_reg64(&self, reg: Reg) -> u64 {
         let mut mutable = match self.0.mutable.lock() {
             Ok(mutable) => mutable,
             Err(poison) => poison.into_inner(),
         };
 
-        mutable.backend.access().get_reg(reg)
+        mutable.backend.access().get_reg64(reg)
+    }
+
+    /// Returns a handle which can be used to interrupt calls into this instance.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle(Arc::clone(&self.0.interrupt))
     }
 
     /// Gets the amount of gas remaining, or `None` if gas metering is not enabled for this instance.
@@ -2666,126 +4160,1155 @@ impl<T> Instance<T> {
         mutable.backend.access().gas_remaining()
     }
 
-    /// Returns the PID of the sandbox corresponding to this instance.
+    /// Takes a snapshot of the instance's registers, writable memory and remaining gas.
     ///
-    /// Will be `None` if the instance doesn't run in a separate process.
-    /// Mostly only useful for debugging.
-    pub fn pid(&self) -> Option<u32> {
-        let mutable = match self.0.mutable.lock() {
+    /// The read-only data is not included, since the program can't modify it anyway.
+    pub fn snapshot(&self) -> InstanceSnapshot {
+        let mut mutable = match self.0.mutable.lock() {
             Ok(mutable) => mutable,
             Err(poison) => poison.into_inner(),
         };
 
-        mutable.backend.pid()
-    }
-}
-
-pub struct ExecutionConfig {
-    pub(crate) reset_memory_after_execution: bool,
-    pub(crate) clear_program_after_execution: bool,
-    pub(crate) initial_regs: [u32; Reg::ALL.len()],
-    pub(crate) gas: Option<Gas>,
-}
+        let memory_config = self.0.instance_pre.0.module.memory_config();
+        let access = mutable.backend.access();
+        let read_memory = |range: core::ops::Range<u32>| {
+            if range.is_empty() {
+                return Vec::new();
+            }
 
-impl Default for ExecutionConfig {
-    fn default() -> Self {
-        let mut initial_regs = [0; Reg::ALL.len()];
-        initial_regs[Reg::SP as usize] = VM_ADDR_USER_STACK_HIGH;
-        initial_regs[Reg::RA as usize] = VM_ADDR_RETURN_TO_HOST;
+            access
+                .read_memory_into_new_vec(range.start, range.end - range.start)
+                .expect("internal error: failed to read the instance's memory")
+        };
 
-        ExecutionConfig {
-            reset_memory_after_execution: false,
-            clear_program_after_execution: false,
-            initial_regs,
-            gas: None,
+        InstanceSnapshot {
+            module_fingerprint: self.0.instance_pre.0.module.0.fingerprint,
+            regs: Reg::ALL.map(|reg| access.get_reg64(reg)),
+            gas: access.gas_remaining(),
+            heap: read_memory(memory_config.heap_address()..memory_config.heap_address() + access.heap_size()),
+            stack: read_memory(memory_config.stack_range()),
         }
     }
-}
 
-impl ExecutionConfig {
-    pub fn set_reset_memory_after_execution(&mut self, value: bool) -> &mut Self {
-        self.reset_memory_after_execution = value;
-        self
-    }
+    /// Restores the instance's registers, writable memory and remaining gas from a snapshot.
+    ///
+    /// The snapshot must have been taken from an instance of a module created from the same program blob,
+    /// although not necessarily from this instance. This fails if the program doesn't match,
+    /// or if there's a call which is currently suspended.
+    pub fn restore(&self, snapshot: &InstanceSnapshot) -> Result<(), Error> {
+        let mut mutable = match self.0.mutable.lock() {
+            Ok(mutable) => mutable,
+            Err(poison) => poison.into_inner(),
+        };
 
-    pub fn set_clear_program_after_execution(&mut self, value: bool) -> &mut Self {
-        self.clear_program_after_execution = value;
-        self
-    }
+        if mutable.has_suspended_call() {
+            bail_static!("failed to restore a snapshot: there is a suspended call");
+        }
 
-    pub fn set_reg(&mut self, reg: Reg, value: u32) -> &mut Self {
-        self.initial_regs[reg as usize] = value;
-        self
-    }
+        let module = &self.0.instance_pre.0.module;
+        if snapshot.module_fingerprint != module.0.fingerprint {
+            bail_static!("failed to restore a snapshot: the snapshot was taken from an instance of a different module");
+        }
 
-    pub fn set_gas(&mut self, gas: Gas) -> &mut Self {
-        self.gas = Some(gas);
-        self
-    }
-}
+        let memory_config = module.memory_config();
+        let heap_top = u64::from(memory_config.heap_address()) + snapshot.heap.len() as u64;
+        if heap_top < u64::from(memory_config.heap_range().end)
+            || heap_top > u64::from(module.max_heap_top())
+            || snapshot.stack.len() != memory_config.stack_size() as usize
+        {
+            bail_static!("failed to restore a snapshot: the memory layout doesn't match");
+        }
 
-pub struct Func<T> {
-    instance: Instance<T>,
-    export_index: usize,
-}
+        if snapshot.gas.is_some() != module.gas_metering().is_some() {
+            bail_static!("failed to restore a snapshot: the gas metering configuration doesn't match");
+        }
 
-impl<T> Clone for Func<T> {
-    fn clone(&self) -> Self {
-        Self {
-            instance: self.instance.clone(),
-            export_index: self.export_index,
+        mutable.backend.resize_heap(heap_top as u32)?;
+        snapshot.write_into(&mut mutable.backend.access(), memory_config)?;
+        if let Some(tracer) = mutable.tracer() {
+            tracer.on_restore(snapshot);
         }
-    }
-}
 
-fn on_hostcall<'a, T>(
-    user_data: &'a mut T,
-    host_functions: &'a HashMap<u32, ExternFnArc<T>>,
-    fallback_handler: Option<&'a FallbackHandlerArc<T>>,
-    raw: &'a mut CallerRaw,
-) -> impl for<'r> FnMut(u32, BackendAccess<'r>) -> Result<(), Trap> + 'a {
-    move |hostcall: u32, mut access: BackendAccess| -> Result<(), Trap> {
-        if hostcall & (1 << 31) != 0 {
-            if hostcall == polkavm_common::HOSTCALL_TRACE {
-                if let Some(tracer) = raw.tracer() {
-                    return tracer.on_trace(&mut access);
-                }
+        Ok(())
+    }
 
-                log::error!("trace hostcall called but no tracer is set");
-                return Err(Trap::default());
-            }
+    /// Creates a new [`InstancePre`] whose instances will start with this instance's current heap contents.
+    ///
+    /// This can be used to run a program's initialization code only once, and then cheaply spawn any number
+    /// of already initialized instances. The new instances share the frozen memory image and only get their
+    /// own private copy of it when they write to it, so the cost of instantiation doesn't grow with the size of the heap.
+    ///
+    /// Only the heap is frozen; the new instances start with a zeroed stack and don't inherit any registers nor gas.
+    /// This fails if there's a call which is currently suspended.
+    pub fn freeze(&self) -> Result<InstancePre<T>, Error> {
+        let mut mutable = match self.0.mutable.lock() {
+            Ok(mutable) => mutable,
+            Err(poison) => poison.into_inner(),
+        };
 
-            log::error!("unknown special hostcall triggered: {}", hostcall);
-            return Err(Trap::default());
+        if mutable.has_suspended_call() {
+            bail_static!("failed to freeze the instance: there is a suspended call");
         }
 
-        let Some(host_fn) = host_functions.get(&hostcall) else {
-            if let Some(fallback_handler) = fallback_handler {
-                return Caller::wrap(user_data, &mut access, raw, move |caller| fallback_handler(caller, hostcall));
-            }
-
-            // This should never happen.
-            log::error!("hostcall to a function which doesn't exist: {}", hostcall);
-            return Err(Trap::default());
+        let instance_pre = &self.0.instance_pre.0;
+        let access = mutable.backend.access();
+        let heap_size = access.heap_size();
+        let heap = if heap_size == 0 {
+            Vec::new()
+        } else {
+            access
+                .read_memory_into_new_vec(instance_pre.module.memory_config().heap_address(), heap_size)
+                .expect("internal error: failed to read the instance's memory")
         };
 
-        if let Err(trap) = host_fn.0.call(user_data, access, raw) {
-            log::debug!("hostcall failed: {}", trap);
-            return Err(trap);
-        }
+        Ok(InstancePre(Arc::new(InstancePrePrivate {
+            engine_state: Arc::clone(&instance_pre.engine_state),
+            module: instance_pre.module.with_initial_heap(heap)?,
+            host_functions: instance_pre.host_functions.clone(),
+            fallback_handler: instance_pre.fallback_handler.clone(),
+            out_of_gas_handler: instance_pre.out_of_gas_handler.clone(),
+            _private: PhantomData,
+        })))
+    }
 
-        Ok(())
+    /// Resumes a call which was previously suspended in a hostcall.
+    ///
+    /// The `result` is the value returned by the hostcall, and must match the return type of the import which was called.
+    /// If the import returns multiple values use [`Instance::resume_with_values`] instead.
+    pub fn resume(&self, result: Option<Val>) -> Result<CallState, ExecutionError> {
+        self.resume_with_values(result.as_ref().map_or(&[][..], core::slice::from_ref))
     }
-}
 
-impl<T> Func<T> {
-    /// Calls the function.
+    /// Resumes a call which was previously suspended in a hostcall.
+    ///
+    /// The `results` are the values returned by the hostcall, and must match the return types of the import which was called.
+    pub fn resume_with_values(&self, results: &[Val]) -> Result<CallState, ExecutionError> {
+        self.resume_impl(results, None)
+    }
+
+    /// Resumes a call which was previously suspended in a hostcall, and returns an [`ExecutionReport`]
+    /// covering the execution from this point until the call finishes or gets suspended again.
+    pub fn resume_with_values_and_report(&self, results: &[Val]) -> (Result<CallState, ExecutionError>, ExecutionReport) {
+        let mut report = ExecutionReport::default();
+        let result = self.resume_impl(results, Some(&mut report));
+        (result, report)
+    }
+
+    fn resume_impl(&self, results: &[Val], report_out: Option<&mut ExecutionReport>) -> Result<CallState, ExecutionError> {
+        let mut mutable = match self.0.mutable.lock() {
+            Ok(mutable) => mutable,
+            Err(poison) => poison.into_inner(),
+        };
+
+        let mutable = &mut *mutable;
+        if mutable.async_call_token.is_some() {
+            return Err(ExecutionError::Error(Error::from_static_str(
+                "failed to resume: an asynchronous call into this instance is in progress",
+            )));
+        }
+
+        let Some(suspended_call) = mutable.suspended_call.take() else {
+            return Err(ExecutionError::Error(Error::from_static_str(
+                "failed to resume: there is no suspended call",
+            )));
+        };
+
+        let module = &self.0.instance_pre.0.module;
+        let import = &module.0.imports[&suspended_call.hostcall];
+        let is_64_bit = module.is_64_bit();
+        let prototype = import.prototype();
+        if !results.iter().map(|value| value.extern_ty()).eq(prototype.returns()) {
+            let error = format!(
+                "failed to resume: the result doesn't match the return type of the import '{}'",
+                DisplayFn {
+                    name: prototype.name(),
+                    args: prototype.args(),
+                    returns: prototype.returns()
+                },
+            );
+
+            mutable.suspended_call = Some(suspended_call);
+            return Err(ExecutionError::Error(error.into()));
+        }
+
+        if regs_required(is_64_bit, prototype.returns()) > Reg::ARG_REGS.len() {
+            let error = format!(
+                "failed to resume: the import '{}' returns more values than can fit in the registers",
+                prototype.name()
+            );
+
+            mutable.suspended_call = Some(suspended_call);
+            return Err(ExecutionError::Error(error.into()));
+        }
+
+        let mut output_count = 0;
+        let mut set_reg = |value: u64| {
+            let reg = Reg::ARG_REGS[output_count];
+            mutable.backend.access().set_reg64(reg, value);
+            if let Some(tracer) = mutable.raw.tracer() {
+                tracer.on_set_reg_in_hostcall(reg, value);
+            }
+            output_count += 1;
+        };
+
+        for result in results {
+            match *result {
+                Val::I32(value) => i32::_set(value, is_64_bit, &mut set_reg),
+                Val::I64(value) => i64::_set(value, is_64_bit, &mut set_reg),
+            }
+        }
+
+        let mut report = report_out
+            .is_some()
+            .then(|| ExecutionReportCollector::new(module, &mut mutable.backend, None));
+
+        let mut suspended_hostcall = None;
+        let mut on_hostcall = on_hostcall_suspending(&mut mutable.raw, report.as_mut(), &mut suspended_hostcall);
+        let result = mutable.backend.resume(&mut on_hostcall);
+        core::mem::drop(on_hostcall);
+
+        if let (Some(report), Some(report_out)) = (report, report_out) {
+            *report_out = report.finish(module, &mut mutable.backend);
+        }
+
+        let export = &module.0.exports[suspended_call.export_index];
+        finish_resumable_call(mutable, suspended_call.export_index, export, result, suspended_hostcall)
+    }
+
+    fn call_export_locked(
+        &self,
+        mutable: &mut InstancePrivateMut,
+        user_data: &mut T,
+        export_index: usize,
+        config: &ExecutionConfig,
+        report_out: Option<&mut ExecutionReport>,
+    ) -> Result<(), ExecutionError> {
+        let instance_pre = &self.0.instance_pre;
+        let module = &instance_pre.0.module;
+        let export = &module.0.exports[export_index];
+        if mutable.async_call_token.is_some() {
+            return Err(error_async_call_in_progress(export));
+        }
+
+        mutable.suspended_call = None;
+        if let Some(ref mut tracer) = mutable.tracer() {
+            tracer.on_before_call(export_index, export, config);
+        }
+
+        let mut report = report_out
+            .is_some()
+            .then(|| ExecutionReportCollector::new(module, &mut mutable.backend, config.gas));
+
+        let mut on_hostcall = on_hostcall(user_data, &instance_pre.0, &mut mutable.raw, report.as_mut(), None);
+        let mut result = mutable.backend.call(export_index, &mut on_hostcall, config);
+        core::mem::drop(on_hostcall);
+
+        if let (Some(report), Some(report_out)) = (report, report_out) {
+            *report_out = report.finish(module, &mut mutable.backend);
+        }
+
+        if let Some(ref mut tracer) = mutable.tracer() {
+            result = tracer.on_after_call(result);
+        }
+
+        translate_call_result(export, result)
+    }
+
+    /// Calls the given export asynchronously and fetches its return values with `get_result`.
+    ///
+    /// The return values are fetched while the instance is still locked, so that another call can't clobber them.
+    async fn call_export_async<R>(
+        &self,
+        user_data: &mut T,
+        export_index: usize,
+        config: ExecutionConfig,
+        mut report_out: Option<&mut ExecutionReport>,
+        get_result: impl FnOnce(&mut InstanceBackend) -> R,
+    ) -> Result<R, ExecutionError> {
+        let instance_pre = &self.0.instance_pre;
+        let export = &instance_pre.0.module.0.exports[export_index];
+
+        let mut pending_hostcall = None;
+        let mut report = None;
+        let mut hostcall_result: Option<Result<Vec<u64>, Trap>> = None;
+        let mut guard = AsyncCallGuard {
+            mutable: &self.0.mutable,
+            token: None,
+        };
+
+        loop {
+            // The instance mustn't be locked while we're waiting, so any other call is refused until this one finishes.
+            let future = {
+                let mut mutable = match self.0.mutable.lock() {
+                    Ok(mutable) => mutable,
+                    Err(poison) => poison.into_inner(),
+                };
+
+                let mutable = &mut *mutable;
+                let mut result = match hostcall_result.take() {
+                    None => {
+                        if mutable.async_call_token.is_some() {
+                            return Err(error_async_call_in_progress(export));
+                        }
+
+                        let token = mutable.next_async_call_token;
+                        mutable.next_async_call_token = token.wrapping_add(1);
+                        mutable.async_call_token = Some(token);
+                        guard.token = Some(token);
+
+                        mutable.suspended_call = None;
+                        if let Some(ref mut tracer) = mutable.tracer() {
+                            tracer.on_before_call(export_index, export, &config);
+                        }
+
+                        if report_out.is_some() {
+                            report = Some(ExecutionReportCollector::new(
+                                &instance_pre.0.module,
+                                &mut mutable.backend,
+                                config.gas,
+                            ));
+                        }
+
+                        let mut on_hostcall = on_hostcall(
+                            user_data,
+                            &instance_pre.0,
+                            &mut mutable.raw,
+                            report.as_mut(),
+                            Some(&mut pending_hostcall),
+                        );
+
+                        mutable.backend.call(export_index, &mut on_hostcall, &config)
+                    }
+                    Some(_) if mutable.async_call_token != guard.token => {
+                        // This should never happen, since every other call is refused while we're waiting.
+                        guard.token = None;
+                        return Err(ExecutionError::Error(Error::from(format!(
+                            "failed to call function '{}': the suspended execution was taken over by another call",
+                            export.prototype().name()
+                        ))));
+                    }
+                    Some(Ok(return_values)) => {
+                        for (reg, value) in Reg::ARG_REGS.into_iter().zip(return_values) {
+                            mutable.backend.access().set_reg64(reg, value);
+                            if let Some(tracer) = mutable.raw.tracer() {
+                                tracer.on_set_reg_in_hostcall(reg, value);
+                            }
+                        }
+
+                        let mut on_hostcall = on_hostcall(
+                            user_data,
+                            &instance_pre.0,
+                            &mut mutable.raw,
+                            report.as_mut(),
+                            Some(&mut pending_hostcall),
+                        );
+
+                        mutable.backend.resume(&mut on_hostcall)
+                    }
+                    Some(Err(trap)) => {
+                        log::debug!("hostcall failed: {}", trap);
+                        let program_counter = mutable.backend.access().program_counter();
+                        Err(ExecutionError::Trap(
+                            trap.with_kind(TrapKind::HostFunctionError).with_program_counter(program_counter),
+                        ))
+                    }
+                };
+
+                match pending_hostcall.take() {
+                    Some(future) if result.is_ok() => future,
+                    _ => {
+                        mutable.async_call_token = None;
+                        guard.token = None;
+
+                        if let (Some(report), Some(report_out)) = (report.take(), report_out.take()) {
+                            *report_out = report.finish(&instance_pre.0.module, &mut mutable.backend);
+                        }
+
+                        if let Some(ref mut tracer) = mutable.tracer() {
+                            result = tracer.on_after_call(result);
+                        }
+
+                        translate_call_result(export, result)?;
+                        return Ok(get_result(&mut mutable.backend));
+                    }
+                }
+            };
+
+            hostcall_result = Some(future.await);
+        }
+    }
+
+    /// Returns the PID of the sandbox corresponding to this instance.
+    ///
+    /// Will be `None` if the instance doesn't run in a separate process.
+    /// Mostly only useful for debugging.
+    pub fn pid(&self) -> Option<u32> {
+        let mutable = match self.0.mutable.lock() {
+            Ok(mutable) => mutable,
+            Err(poison) => poison.into_inner(),
+        };
+
+        mutable.backend.pid()
+    }
+}
+
+/// The state of an [`Instance`] at a given point in time.
+///
+/// Created with [`Instance::snapshot`], and can be applied back with [`Instance::restore`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct InstanceSnapshot {
+    module_fingerprint: u64,
+    regs: [u64; Reg::ALL.len()],
+    gas: Option<Gas>,
+    heap: Vec<u8>,
+    stack: Vec<u8>,
+}
+
+impl InstanceSnapshot {
+    const MAGIC: [u8; 4] = *b"PVMS";
+    const VERSION: u8 = 3;
+
+    /// Serializes the snapshot into bytes, e.g. to store it on disk.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(64 + self.heap.len() + self.stack.len());
+        output.extend_from_slice(&Self::MAGIC);
+        output.push(Self::VERSION);
+        output.extend_from_slice(&self.module_fingerprint.to_le_bytes());
+        for value in self.regs {
+            output.extend_from_slice(&value.to_le_bytes());
+        }
+
+        match self.gas {
+            Some(gas) => {
+                output.push(1);
+                output.extend_from_slice(&gas.get().to_le_bytes());
+            }
+            None => output.push(0),
+        }
+
+        for memory in [&self.heap, &self.stack] {
+            output.extend_from_slice(&(memory.len() as u32).to_le_bytes());
+            output.extend_from_slice(memory);
+        }
+
+        output
+    }
+
+    /// Deserializes a snapshot previously serialized with [`InstanceSnapshot::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        fn take<'a>(bytes: &mut &'a [u8], length: usize) -> Result<&'a [u8], Error> {
+            if bytes.len() < length {
+                bail_static!("failed to parse a snapshot: unexpected end of input");
+            }
+
+            let (chunk, rest) = bytes.split_at(length);
+            *bytes = rest;
+            Ok(chunk)
+        }
+
+        fn take_u32(bytes: &mut &[u8]) -> Result<u32, Error> {
+            let chunk = take(bytes, 4)?;
+            Ok(u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        }
+
+        let mut bytes = bytes;
+        if take(&mut bytes, Self::MAGIC.len())? != Self::MAGIC {
+            bail_static!("failed to parse a snapshot: invalid magic bytes");
+        }
+
+        let version = take(&mut bytes, 1)?[0];
+        if version != Self::VERSION {
+            bail!("failed to parse a snapshot: unsupported version {version}");
+        }
+
+        let module_fingerprint = u64::from_le_bytes(take(&mut bytes, 8)?.try_into().unwrap());
+        let mut regs = [0; Reg::ALL.len()];
+        for value in &mut regs {
+            *value = u64::from_le_bytes(take(&mut bytes, 8)?.try_into().unwrap());
+        }
+
+        let gas = match take(&mut bytes, 1)?[0] {
+            0 => None,
+            1 => {
+                let gas = u64::from_le_bytes(take(&mut bytes, 8)?.try_into().unwrap());
+                let Some(gas) = Gas::new(gas) else {
+                    bail_static!("failed to parse a snapshot: invalid gas");
+                };
+
+                Some(gas)
+            }
+            _ => bail_static!("failed to parse a snapshot: invalid gas"),
+        };
+
+        let heap_length = take_u32(&mut bytes)? as usize;
+        let heap = take(&mut bytes, heap_length)?.to_vec();
+        let stack_length = take_u32(&mut bytes)? as usize;
+        let stack = take(&mut bytes, stack_length)?.to_vec();
+
+        if !bytes.is_empty() {
+            bail_static!("failed to parse a snapshot: trailing data");
+        }
+
+        Ok(InstanceSnapshot {
+            module_fingerprint,
+            regs,
+            gas,
+            heap,
+            stack,
+        })
+    }
+
+    pub(crate) fn heap_size(&self) -> u32 {
+        self.heap.len() as u32
+    }
+
+    pub(crate) fn write_into<'a>(&self, access: &mut impl Access<'a>, memory_config: &GuestMemoryConfig) -> Result<(), Error> {
+        for (range, data) in [(memory_config.heap_range(), &self.heap), (memory_config.stack_range(), &self.stack)] {
+            if !data.is_empty() {
+                access.write_memory(range.start, data).map_err(Error::from_display)?;
+            }
+        }
+
+        for (reg, value) in Reg::ALL.into_iter().zip(self.regs) {
+            access.set_reg64(reg, value);
+        }
+
+        if let Some(gas) = self.gas {
+            access.set_gas(gas);
+        }
+
+        Ok(())
+    }
+}
+
+pub struct ExecutionConfig {
+    pub(crate) reset_memory_after_execution: bool,
+    pub(crate) clear_program_after_execution: bool,
+    pub(crate) initial_regs: [u64; Reg::ALL.len()],
+    pub(crate) gas: Option<Gas>,
+    pub(crate) deadline: Option<Instant>,
+}
+
+impl Default for ExecutionConfig {
+    fn default() -> Self {
+        let mut initial_regs = [0; Reg::ALL.len()];
+        initial_regs[Reg::SP as usize] = u64::from(VM_ADDR_USER_STACK_HIGH);
+        initial_regs[Reg::RA as usize] = u64::from(VM_ADDR_RETURN_TO_HOST);
+
+        ExecutionConfig {
+            reset_memory_after_execution: false,
+            clear_program_after_execution: false,
+            initial_regs,
+            gas: None,
+            deadline: None,
+        }
+    }
+}
+
+impl ExecutionConfig {
+    pub fn set_reset_memory_after_execution(&mut self, value: bool) -> &mut Self {
+        self.reset_memory_after_execution = value;
+        self
+    }
+
+    pub fn set_clear_program_after_execution(&mut self, value: bool) -> &mut Self {
+        self.clear_program_after_execution = value;
+        self
+    }
+
+    pub fn set_reg(&mut self, reg: Reg, value: u32) -> &mut Self {
+        self.set_reg64(reg, u64::from(value))
+    }
+
+    /// Sets the full initial value of a register, for programs which target the 64-bit variant of the instruction set.
+    pub fn set_reg64(&mut self, reg: Reg, value: u64) -> &mut Self {
+        self.initial_regs[reg as usize] = value;
+        self
+    }
+
+    pub fn set_gas(&mut self, gas: Gas) -> &mut Self {
+        self.gas = Some(gas);
+        self
+    }
+
+    /// Sets the point in time after which the call will be interrupted.
+    ///
+    /// Once the deadline passes the call will return [`ExecutionError::Interrupted`](crate::ExecutionError::Interrupted).
+    pub fn set_deadline(&mut self, deadline: Instant) -> &mut Self {
+        self.deadline = Some(deadline);
+        self
+    }
+}
+
+/// Statistics about a single call into an instance.
+///
+/// Returned by the `*_with_report` variants of the call methods, e.g. [`Func::call_ex_with_report`].
+/// For a resumable call each segment of the execution, from when it's started or resumed until it
+/// finishes or gets suspended, gets its own report.
+///
+/// If the call couldn't be started at all, e.g. because the arguments didn't match the function's prototype,
+/// then the report is empty.
+#[derive(Clone, Debug, Default)]
+pub struct ExecutionReport {
+    gas_consumed: Option<u64>,
+    basic_blocks_executed: Option<u64>,
+    instructions_executed: Option<u64>,
+    hostcall_counts: BTreeMap<String, u64>,
+    wall_time: Duration,
+    wait_loop_start_count: Option<u64>,
+    futex_wait_count: Option<u64>,
+}
+
+impl ExecutionReport {
+    /// The amount of gas consumed by the call, or `None` if gas metering is not enabled.
+    ///
+    /// This includes any gas which was added by the out-of-gas handler during the call.
+    pub fn gas_consumed(&self) -> Option<u64> {
+        self.gas_consumed
+    }
+
+    /// The number of basic blocks executed during the call.
+    ///
+    /// Always available when running on the interpreter; the compiled backends only count the basic blocks
+    /// if [`ModuleConfig::set_count_basic_blocks`] was enabled for the module.
+    pub fn basic_blocks_executed(&self) -> Option<u64> {
+        self.basic_blocks_executed
+    }
+
+    /// The number of instructions executed during the call.
+    ///
+    /// Only available when running on the interpreter; this is always `None` for the compiled backends.
+    pub fn instructions_executed(&self) -> Option<u64> {
+        self.instructions_executed
+    }
+
+    /// The number of times the import with the given name was called.
+    pub fn hostcall_count(&self, import_name: &str) -> u64 {
+        self.hostcall_counts.get(import_name).copied().unwrap_or(0)
+    }
+
+    /// The number of times each import was called, sorted by the name of the import.
+    ///
+    /// Imports which weren't called at all are not included.
+    pub fn hostcall_counts(&self) -> impl Iterator<Item = (&str, u64)> + '_ {
+        self.hostcall_counts.iter().map(|(name, &count)| (name.as_str(), count))
+    }
+
+    /// The wall-clock time the call took, including any time spent in host functions.
+    pub fn wall_time(&self) -> Duration {
+        self.wall_time
+    }
+
+    /// The number of times the host started waiting for the sandbox during the call.
+    ///
+    /// Only available when running in the Linux sandbox.
+    pub fn wait_loop_start_count(&self) -> Option<u64> {
+        self.wait_loop_start_count
+    }
+
+    /// The number of times the host had to call into the kernel to wait for the sandbox during the call.
+    ///
+    /// Only available when running in the Linux sandbox.
+    pub fn futex_wait_count(&self) -> Option<u64> {
+        self.futex_wait_count
+    }
+}
+
+struct ExecutionReportCollector {
+    started_at: Instant,
+    gas_before: Option<u64>,
+    gas_added: u64,
+    basic_blocks_before: Option<u64>,
+    instructions_before: Option<u64>,
+    wait_counters_before: Option<(u64, u64)>,
+    hostcall_counts: HashMap<u32, u64>,
+}
+
+impl ExecutionReportCollector {
+    /// Starts collecting a report; `initial_gas` is the gas the call will start with, if it's different from what's currently remaining.
+    fn new(module: &Module, backend: &mut InstanceBackend, initial_gas: Option<Gas>) -> Self {
+        ExecutionReportCollector {
+            started_at: Instant::now(),
+            gas_before: backend.access().gas_remaining().map(|gas| initial_gas.unwrap_or(gas).get()),
+            gas_added: 0,
+            basic_blocks_before: backend.basic_blocks_executed(module),
+            instructions_before: backend.instructions_executed(),
+            wait_counters_before: backend.wait_counters(),
+            hostcall_counts: HashMap::new(),
+        }
+    }
+
+    fn finish(self, module: &Module, backend: &mut InstanceBackend) -> ExecutionReport {
+        let wall_time = self.started_at.elapsed();
+        let gas_after = backend.access().gas_remaining().map(|gas| gas.get());
+        let basic_blocks_after = backend.basic_blocks_executed(module);
+        let instructions_after = backend.instructions_executed();
+        let wait_counters_after = backend.wait_counters();
+
+        let hostcall_counts = self
+            .hostcall_counts
+            .into_iter()
+            .filter_map(|(hostcall, count)| {
+                let import = module.0.imports.get(&hostcall)?;
+                Some((import.prototype().name().to_owned(), count))
+            })
+            .collect();
+
+        ExecutionReport {
+            gas_consumed: self
+                .gas_before
+                .zip(gas_after)
+                .map(|(before, after)| before.saturating_add(self.gas_added).saturating_sub(after)),
+            basic_blocks_executed: self
+                .basic_blocks_before
+                .zip(basic_blocks_after)
+                .map(|(before, after)| after - before),
+            instructions_executed: self
+                .instructions_before
+                .zip(instructions_after)
+                .map(|(before, after)| after - before),
+            hostcall_counts,
+            wall_time,
+            wait_loop_start_count: self
+                .wait_counters_before
+                .zip(wait_counters_after)
+                .map(|(before, after)| after.0 - before.0),
+            futex_wait_count: self
+                .wait_counters_before
+                .zip(wait_counters_after)
+                .map(|(before, after)| after.1 - before.1),
+        }
+    }
+}
+
+/// The state in which a resumable call returned control to the host.
+#[derive(Debug)]
+pub enum CallState {
+    /// The call has finished and returned the given value.
+    Finished(Option<Val>),
+
+    /// The guest program has called into the host and its execution was suspended.
+    ///
+    /// The registers and the memory are kept in the instance, and the execution
+    /// can be continued with [`Instance::resume`].
+    Suspended {
+        /// The index of the import which was called.
+        hostcall: u32,
+    },
+}
+
+pub struct Func<T> {
+    instance: Instance<T>,
+    export_index: usize,
+}
+
+impl<T> Clone for Func<T> {
+    fn clone(&self) -> Self {
+        Self {
+            instance: self.instance.clone(),
+            export_index: self.export_index,
+        }
+    }
+}
+
+fn on_hostcall<'a, T>(
+    user_data: &'a mut T,
+    instance_pre: &'a InstancePrePrivate<T>,
+    raw: &'a mut CallerRaw,
+    mut report: Option<&'a mut ExecutionReportCollector>,
+    mut pending_hostcall: Option<&'a mut Option<AsyncHostcallFuture>>,
+) -> impl for<'r> FnMut(u32, BackendAccess<'r>) -> Result<HostcallOutcome, Trap> + 'a {
+    move |hostcall: u32, mut access: BackendAccess| -> Result<HostcallOutcome, Trap> {
+        raw.set_instance((instance_pre as *const InstancePrePrivate<T>).cast(), call_export_nested::<T>);
+
+        if hostcall == polkavm_common::HOSTCALL_OUT_OF_GAS {
+            if let Some(ref out_of_gas_handler) = instance_pre.out_of_gas_handler {
+                let gas = Caller::wrap(user_data, &mut access, raw, move |caller| out_of_gas_handler(caller))
+                    .map_err(|trap| trap.with_kind(TrapKind::HostFunctionError))?;
+                access.add_gas(gas.get());
+                if let Some(ref mut report) = report {
+                    report.gas_added = report.gas_added.saturating_add(gas.get());
+                }
+            }
+
+            return Ok(HostcallOutcome::Continue);
+        }
+
+        if hostcall & (1 << 31) != 0 {
+            on_special_hostcall(hostcall, raw, &mut access)?;
+            return Ok(HostcallOutcome::Continue);
+        }
+
+        if let Some(ref mut report) = report {
+            *report.hostcall_counts.entry(hostcall).or_insert(0) += 1;
+        }
+
+        let Some(host_fn) = instance_pre.host_functions.get(&hostcall) else {
+            if let Some(ref fallback_handler) = instance_pre.fallback_handler {
+                return Caller::wrap(user_data, &mut access, raw, move |caller| fallback_handler(caller, hostcall))
+                    .map(|()| HostcallOutcome::Continue)
+                    .map_err(|trap| trap.with_kind(TrapKind::HostFunctionError));
+            }
+
+            // This should never happen.
+            log::error!("hostcall to a function which doesn't exist: {}", hostcall);
+            return Err(Trap::default());
+        };
+
+        if host_fn.0.is_async() {
+            if let Some(ref mut pending_hostcall) = pending_hostcall {
+                // Suspend the execution; it will be resumed once the future finishes.
+                match host_fn.0.call_async(user_data, access, raw) {
+                    Ok(future) => {
+                        **pending_hostcall = Some(future);
+                        return Ok(HostcallOutcome::Suspend);
+                    }
+                    Err(trap) => {
+                        log::debug!("hostcall failed: {}", trap);
+                        return Err(trap.with_kind(TrapKind::HostFunctionError));
+                    }
+                }
+            }
+        }
+
+        if let Err(trap) = host_fn.0.call(user_data, access, raw) {
+            log::debug!("hostcall failed: {}", trap);
+            return Err(trap.with_kind(TrapKind::HostFunctionError));
+        }
+
+        Ok(HostcallOutcome::Continue)
+    }
+}
+
+/// Calls an export of the instance from within one of its host functions.
+///
+/// # Safety
+///
+/// Can only be called through a `CallerRaw` which is currently being used by a hostcall handler created with `on_hostcall::<T>`.
+unsafe fn call_export_nested<T>(
+    raw: &mut CallerRaw,
+    name: &str,
+    args: &[ExternTy],
+    returns: &[ExternTy],
+    regs: &mut [u64; Reg::ALL.len()],
+) -> Result<(), ExecutionError> {
+    // SAFETY: The caller will make sure that the instance is of the right type and that it's still alive.
+    let instance_pre = unsafe { &*raw.instance().cast::<InstancePrePrivate<T>>() };
+    let Some(&export_index) = instance_pre.module.0.export_index_by_name.get(name) else {
+        return Err(ExecutionError::Error(Error::from(format!(
+            "failed to call function '{}': no such function is exported",
+            name
+        ))));
+    };
+
+    let export = &instance_pre.module.0.exports[export_index];
+    let prototype = export.prototype();
+    if args.len() != prototype.args().len()
+        || args.iter().copied().zip(prototype.args()).any(|(lhs, rhs)| lhs != rhs)
+        || !returns.iter().copied().eq(prototype.returns())
+    {
+        let error = format!(
+            "failed to call function: wanted to call function '{}', while the function that was exported was '{}'",
+            DisplayFn {
+                name: prototype.name(),
+                args: args.iter().copied(),
+                returns: returns.iter().copied()
+            },
+            DisplayFn {
+                name: prototype.name(),
+                args: prototype.args(),
+                returns: prototype.returns()
+            },
+        );
+
+        return Err(ExecutionError::Error(error.into()));
+    }
+
+    let is_64_bit = instance_pre.module.is_64_bit();
+    if regs_required(is_64_bit, args.iter().copied()) > Reg::ARG_REGS.len()
+        || regs_required(is_64_bit, returns.iter().copied()) > Reg::ARG_REGS.len()
+    {
+        return Err(ExecutionError::Error(Error::from(format!(
+            "failed to call function '{}': its arguments or return values don't fit in the registers",
+            name
+        ))));
+    }
+
+    if raw.call_depth() >= instance_pre.engine_state.max_nested_call_depth {
+        return Err(ExecutionError::Error(Error::from(format!(
+            "failed to call function '{}': maximum nested call depth exceeded",
+            name
+        ))));
+    }
+
+    let result = raw.nested(|raw, user_data, access| {
+        // SAFETY: These point to the user data and the access of the hostcall which is currently being handled,
+        //         and they will stay alive until that hostcall returns.
+        let (user_data, access) = unsafe { (&mut *user_data.cast::<T>(), &mut *access.cast::<BackendAccess>()) };
+        if let Some(tracer) = raw.tracer() {
+            tracer.on_before_nested_call(export_index, export, regs);
+        }
+
+        let mut on_hostcall = on_hostcall(user_data, instance_pre, raw, None, None);
+        let result = access.call_nested(&instance_pre.module, export_index, regs, &mut on_hostcall);
+        core::mem::drop(on_hostcall);
+
+        if let Some(tracer) = raw.tracer() {
+            tracer.on_after_nested_call();
+        }
+
+        result
+    });
+
+    translate_call_result(export, result)
+}
+
+fn on_hostcall_suspending<'a>(
+    raw: &'a mut CallerRaw,
+    mut report: Option<&'a mut ExecutionReportCollector>,
+    suspended_hostcall: &'a mut Option<u32>,
+) -> impl for<'r> FnMut(u32, BackendAccess<'r>) -> Result<HostcallOutcome, Trap> + 'a {
+    move |hostcall: u32, mut access: BackendAccess| -> Result<HostcallOutcome, Trap> {
+        if hostcall & (1 << 31) != 0 {
+            on_special_hostcall(hostcall, raw, &mut access)?;
+            return Ok(HostcallOutcome::Continue);
+        }
+
+        if let Some(ref mut report) = report {
+            *report.hostcall_counts.entry(hostcall).or_insert(0) += 1;
+        }
+
+        *suspended_hostcall = Some(hostcall);
+        Ok(HostcallOutcome::Suspend)
+    }
+}
+
+fn on_special_hostcall(hostcall: u32, raw: &mut CallerRaw, access: &mut BackendAccess) -> Result<(), Trap> {
+    if hostcall == polkavm_common::HOSTCALL_TRACE {
+        if let Some(tracer) = raw.tracer() {
+            return tracer.on_trace(access);
+        }
+
+        log::error!("trace hostcall called but no tracer is set");
+        return Err(Trap::default());
+    }
+
+    if hostcall == polkavm_common::HOSTCALL_OUT_OF_GAS {
+        // There's no out-of-gas handler to call, so the execution will just end once we return.
+        return Ok(());
+    }
+
+    log::error!("unknown special hostcall triggered: {}", hostcall);
+    Err(Trap::default())
+}
+
+fn translate_call_result(export: &ProgramExport, result: Result<(), ExecutionError>) -> Result<(), ExecutionError> {
+    match result {
+        Ok(()) => Ok(()),
+        Err(ExecutionError::Error(error)) => Err(ExecutionError::Error(
+            format!("failed to call function '{}': {}", export.prototype().name(), error).into(),
+        )),
+        Err(ExecutionError::Trap(trap)) => Err(ExecutionError::Trap(trap)),
+        Err(ExecutionError::OutOfGas) => Err(ExecutionError::OutOfGas),
+        Err(ExecutionError::Interrupted) => Err(ExecutionError::Interrupted),
+    }
+}
+
+fn get_return_value(export: &ProgramExport, is_64_bit: bool, backend: &mut InstanceBackend) -> Option<Val> {
+    let return_ty = export.prototype().return_ty()?;
+    let mut output_count = 0;
+    let get = || {
+        let value = backend.access().get_reg64(Reg::ARG_REGS[output_count]);
+        output_count += 1;
+        value
+    };
+
+    match return_ty {
+        ExternTy::I32 => Some(Val::I32(<i32 as AbiTy>::_get(is_64_bit, get))),
+        ExternTy::I64 => Some(Val::I64(<i64 as AbiTy>::_get(is_64_bit, get))),
+    }
+}
+
+fn finish_resumable_call(
+    mutable: &mut InstancePrivateMut,
+    export_index: usize,
+    export: &ProgramExport,
+    mut result: Result<(), ExecutionError>,
+    suspended_hostcall: Option<u32>,
+) -> Result<CallState, ExecutionError> {
+    if let (Ok(()), Some(hostcall)) = (&result, suspended_hostcall) {
+        mutable.suspended_call = Some(SuspendedCall { export_index, hostcall });
+        return Ok(CallState::Suspended { hostcall });
+    }
+
+    if let Some(ref mut tracer) = mutable.tracer() {
+        result = tracer.on_after_call(result);
+    }
+
+    translate_call_result(export, result)?;
+    let is_64_bit = mutable.raw.is_64_bit();
+    Ok(CallState::Finished(get_return_value(export, is_64_bit, &mut mutable.backend)))
+}
+
+impl<T> Func<T> {
+    /// Calls the function.
     pub fn call(&self, user_data: &mut T, args: &[Val]) -> Result<Option<Val>, ExecutionError> {
         self.call_ex(user_data, args, ExecutionConfig::default())
     }
 
     /// Calls the function with the given configuration.
-    pub fn call_ex(&self, user_data: &mut T, args: &[Val], mut config: ExecutionConfig) -> Result<Option<Val>, ExecutionError> {
+    pub fn call_ex(&self, user_data: &mut T, args: &[Val], config: ExecutionConfig) -> Result<Option<Val>, ExecutionError> {
+        self.call_ex_impl(user_data, args, config, None)
+    }
+
+    /// Calls the function with the given configuration, and returns an [`ExecutionReport`] alongside the result.
+    pub fn call_ex_with_report(
+        &self,
+        user_data: &mut T,
+        args: &[Val],
+        config: ExecutionConfig,
+    ) -> (Result<Option<Val>, ExecutionError>, ExecutionReport) {
+        let mut report = ExecutionReport::default();
+        let result = self.call_ex_impl(user_data, args, config, Some(&mut report));
+        (result, report)
+    }
+
+    fn call_ex_impl(
+        &self,
+        user_data: &mut T,
+        args: &[Val],
+        mut config: ExecutionConfig,
+        report: Option<&mut ExecutionReport>,
+    ) -> Result<Option<Val>, ExecutionError> {
+        self.set_args(args, &mut config)?;
+
+        let instance = &self.instance;
+        let mut mutable = match instance.0.mutable.lock() {
+            Ok(mutable) => mutable,
+            Err(poison) => poison.into_inner(),
+        };
+
+        instance.call_export_locked(&mut mutable, user_data, self.export_index, &config, report)?;
+
+        let module = &instance.0.instance_pre.0.module;
+        Ok(get_return_value(
+            &module.0.exports[self.export_index],
+            module.is_64_bit(),
+            &mut mutable.backend,
+        ))
+    }
+
+    /// Calls the function asynchronously.
+    ///
+    /// The call yields whenever the guest program calls an asynchronous host function
+    /// (defined with [`Linker::func_wrap_async`]) whose future is still pending.
+    /// The instance isn't locked while the call is waiting, but any other call into it is refused until this one finishes
+    /// or its future gets dropped.
+    pub async fn call_async(&self, user_data: &mut T, args: &[Val]) -> Result<Option<Val>, ExecutionError> {
+        self.call_async_ex(user_data, args, ExecutionConfig::default()).await
+    }
+
+    /// Calls the function asynchronously with the given configuration.
+    pub async fn call_async_ex(&self, user_data: &mut T, args: &[Val], config: ExecutionConfig) -> Result<Option<Val>, ExecutionError> {
+        self.call_async_ex_impl(user_data, args, config, None).await
+    }
+
+    /// Calls the function asynchronously with the given configuration, and returns an [`ExecutionReport`] alongside the result.
+    ///
+    /// The report covers the whole call, including the time spent waiting for the asynchronous host functions.
+    pub async fn call_async_ex_with_report(
+        &self,
+        user_data: &mut T,
+        args: &[Val],
+        config: ExecutionConfig,
+    ) -> (Result<Option<Val>, ExecutionError>, ExecutionReport) {
+        let mut report = ExecutionReport::default();
+        let result = self.call_async_ex_impl(user_data, args, config, Some(&mut report)).await;
+        (result, report)
+    }
+
+    async fn call_async_ex_impl(
+        &self,
+        user_data: &mut T,
+        args: &[Val],
+        mut config: ExecutionConfig,
+        report: Option<&mut ExecutionReport>,
+    ) -> Result<Option<Val>, ExecutionError> {
+        self.set_args(args, &mut config)?;
+
+        let module = &self.instance.0.instance_pre.0.module;
+        self.instance
+            .call_export_async(user_data, self.export_index, config, report, |backend| {
+                get_return_value(&module.0.exports[self.export_index], module.is_64_bit(), backend)
+            })
+            .await
+    }
+
+    /// Calls the function, suspending the execution every time the guest program calls into the host.
+    ///
+    /// When suspended the host is expected to handle the hostcall itself and continue the execution
+    /// with [`Instance::resume`]. The host functions defined on the [`Linker`] are not called.
+    pub fn call_resumable(&self, args: &[Val], config: ExecutionConfig) -> Result<CallState, ExecutionError> {
+        self.call_resumable_impl(args, config, None)
+    }
+
+    /// Calls the function like [`Func::call_resumable`], and returns an [`ExecutionReport`] covering the execution
+    /// until the call finishes or gets suspended for the first time.
+    ///
+    /// Use [`Instance::resume_with_values_and_report`] to get a report for the rest of the call.
+    pub fn call_resumable_with_report(
+        &self,
+        args: &[Val],
+        config: ExecutionConfig,
+    ) -> (Result<CallState, ExecutionError>, ExecutionReport) {
+        let mut report = ExecutionReport::default();
+        let result = self.call_resumable_impl(args, config, Some(&mut report));
+        (result, report)
+    }
+
+    fn call_resumable_impl(
+        &self,
+        args: &[Val],
+        mut config: ExecutionConfig,
+        report_out: Option<&mut ExecutionReport>,
+    ) -> Result<CallState, ExecutionError> {
+        self.set_args(args, &mut config)?;
+
+        let instance_pre = &self.instance.0.instance_pre;
+        let export = &instance_pre.0.module.0.exports[self.export_index];
+        let mutable = &self.instance.0.mutable;
+        let mut mutable = match mutable.lock() {
+            Ok(mutable) => mutable,
+            Err(poison) => poison.into_inner(),
+        };
+
+        let mutable = &mut *mutable;
+        if mutable.async_call_token.is_some() {
+            return Err(error_async_call_in_progress(export));
+        }
+
+        mutable.suspended_call = None;
+        if let Some(ref mut tracer) = mutable.tracer() {
+            tracer.on_before_call(self.export_index, export, &config);
+        }
+
+        let mut report = report_out
+            .is_some()
+            .then(|| ExecutionReportCollector::new(&instance_pre.0.module, &mut mutable.backend, config.gas));
+
+        let mut suspended_hostcall = None;
+        let mut on_hostcall = on_hostcall_suspending(&mut mutable.raw, report.as_mut(), &mut suspended_hostcall);
+        let result = mutable.backend.call(self.export_index, &mut on_hostcall, &config);
+        core::mem::drop(on_hostcall);
+
+        if let (Some(report), Some(report_out)) = (report, report_out) {
+            *report_out = report.finish(&instance_pre.0.module, &mut mutable.backend);
+        }
+
+        finish_resumable_call(mutable, self.export_index, export, result, suspended_hostcall)
+    }
+
+    fn set_args(&self, args: &[Val], config: &mut ExecutionConfig) -> Result<(), ExecutionError> {
         let instance_pre = &self.instance.0.instance_pre;
         let export = &instance_pre.0.module.0.exports[self.export_index];
         let prototype = export.prototype();
@@ -2802,25 +5325,36 @@ impl<T> Func<T> {
                 DisplayFn {
                     name: prototype.name(),
                     args: args.iter().map(|value| value.extern_ty()),
-                    return_ty: prototype.return_ty()
+                    returns: prototype.returns()
                 },
                 DisplayFn {
                     name: prototype.name(),
                     args: prototype.args(),
-                    return_ty: prototype.return_ty()
+                    returns: prototype.returns()
                 },
             );
 
             return Err(ExecutionError::Error(error.into()));
         }
 
+        if prototype.returns().len() > 1 {
+            return Err(ExecutionError::Error(
+                format!(
+                    "failed to call function '{}': functions which return multiple values can only be called through a 'TypedFunc'",
+                    prototype.name()
+                )
+                .into(),
+            ));
+        }
+
         let mut input_count = 0;
         if prototype.args().len() > 0 {
+            let is_64_bit = self.instance.0.instance_pre.0.module.is_64_bit();
             let required_count = args
                 .iter()
                 .map(|arg| match arg {
-                    Val::I32(..) => i32::_REGS_REQUIRED,
-                    Val::I64(..) => i64::_REGS_REQUIRED,
+                    Val::I32(..) => i32::_regs_required(is_64_bit),
+                    Val::I64(..) => i64::_regs_required(is_64_bit),
                 })
                 .sum::<usize>();
 
@@ -2830,7 +5364,7 @@ impl<T> Func<T> {
                 ));
             }
 
-            let mut cb = |value: u32| {
+            let mut cb = |value: u64| {
                 assert!(input_count <= VM_MAXIMUM_EXTERN_ARG_COUNT);
                 config.initial_regs[Reg::A0 as usize + input_count] = value;
                 input_count += 1;
@@ -2838,80 +5372,21 @@ impl<T> Func<T> {
 
             for arg in args {
                 match arg {
-                    Val::I32(value) => i32::_set(*value, &mut cb),
-                    Val::I64(value) => i64::_set(*value, &mut cb),
+                    Val::I32(value) => i32::_set(*value, is_64_bit, &mut cb),
+                    Val::I64(value) => i64::_set(*value, is_64_bit, &mut cb),
                 }
             }
         }
 
-        let mutable = &self.instance.0.mutable;
-        let mut mutable = match mutable.lock() {
-            Ok(mutable) => mutable,
-            Err(poison) => poison.into_inner(),
-        };
-
-        let mutable = &mut *mutable;
-        if let Some(ref mut tracer) = mutable.tracer() {
-            tracer.on_before_call(self.export_index, export, &config);
-        }
-
-        let mut on_hostcall = on_hostcall(
-            user_data,
-            &instance_pre.0.host_functions,
-            instance_pre.0.fallback_handler.as_ref(),
-            &mut mutable.raw,
-        );
-
-        let result = mutable.backend.call(self.export_index, &mut on_hostcall, &config);
-        core::mem::drop(on_hostcall);
-
-        if let Some(ref mut tracer) = mutable.tracer() {
-            tracer.on_after_call();
-        }
-
-        match result {
-            Ok(()) => {}
-            Err(ExecutionError::Error(error)) => {
-                return Err(ExecutionError::Error(
-                    format!("failed to call function '{}': {}", export.prototype().name(), error).into(),
-                ));
-            }
-            Err(ExecutionError::Trap(trap)) => {
-                return Err(ExecutionError::Trap(trap));
-            }
-            Err(ExecutionError::OutOfGas) => {
-                return Err(ExecutionError::OutOfGas);
-            }
-        }
-
-        if let Some(return_ty) = prototype.return_ty() {
-            let mut output_count = 0;
-            let get = || {
-                let value = mutable.backend.access().get_reg(Reg::ARG_REGS[output_count]);
-                output_count += 1;
-                value
-            };
-
-            match return_ty {
-                ExternTy::I32 => {
-                    let value = <i32 as AbiTy>::_get(get);
-                    Ok(Some(Val::I32(value)))
-                }
-                ExternTy::I64 => {
-                    let value = <i64 as AbiTy>::_get(get);
-                    Ok(Some(Val::I64(value)))
-                }
-            }
-        } else {
-            Ok(None)
-        }
+        Ok(())
     }
 }
 
 pub struct TypedFunc<T, FnArgs, FnResult> {
     instance: Instance<T>,
     export_index: usize,
-    _phantom: PhantomData<(FnArgs, FnResult)>,
+    // A function pointer so that this is `Send` and `Sync` regardless of the argument and result types.
+    _phantom: PhantomData<fn(FnArgs) -> FnResult>,
 }
 
 impl<T, FnArgs, FnResult> TypedFunc<T, FnArgs, FnResult>
@@ -2925,65 +5400,130 @@ where
     }
 
     /// Calls the function with the given configuration.
-    pub fn call_ex(&self, user_data: &mut T, args: FnArgs, mut config: ExecutionConfig) -> Result<FnResult, ExecutionError> {
-        let instance_pre = &self.instance.0.instance_pre;
-        let export = &instance_pre.0.module.0.exports[self.export_index];
-
+    pub fn call_ex(&self, user_data: &mut T, args: FnArgs, config: ExecutionConfig) -> Result<FnResult, ExecutionError> {
+        self.call_ex_impl(user_data, args, config, None)
+    }
+
+    /// Calls the function with the given configuration, and returns an [`ExecutionReport`] alongside the result.
+    pub fn call_ex_with_report(
+        &self,
+        user_data: &mut T,
+        args: FnArgs,
+        config: ExecutionConfig,
+    ) -> (Result<FnResult, ExecutionError>, ExecutionReport) {
+        let mut report = ExecutionReport::default();
+        let result = self.call_ex_impl(user_data, args, config, Some(&mut report));
+        (result, report)
+    }
+
+    fn call_ex_impl(
+        &self,
+        user_data: &mut T,
+        args: FnArgs,
+        mut config: ExecutionConfig,
+        report: Option<&mut ExecutionReport>,
+    ) -> Result<FnResult, ExecutionError> {
+        let is_64_bit = self.instance.0.instance_pre.0.module.is_64_bit();
         let mut input_count = 0;
-        args._set(|value| {
+        args._set(is_64_bit, |value| {
             assert!(input_count <= VM_MAXIMUM_EXTERN_ARG_COUNT);
             config.initial_regs[Reg::A0 as usize + input_count] = value;
             input_count += 1;
         });
 
-        let mutable = &self.instance.0.mutable;
-        let mut mutable = match mutable.lock() {
+        let instance = &self.instance;
+        let mut mutable = match instance.0.mutable.lock() {
             Ok(mutable) => mutable,
             Err(poison) => poison.into_inner(),
         };
 
-        let mutable = &mut *mutable;
-        if let Some(ref mut tracer) = mutable.tracer() {
-            tracer.on_before_call(self.export_index, export, &config);
-        }
-
-        let mut on_hostcall = on_hostcall(
-            user_data,
-            &instance_pre.0.host_functions,
-            instance_pre.0.fallback_handler.as_ref(),
-            &mut mutable.raw,
-        );
-
-        let result = mutable.backend.call(self.export_index, &mut on_hostcall, &config);
-        core::mem::drop(on_hostcall);
-
-        if let Some(ref mut tracer) = mutable.tracer() {
-            tracer.on_after_call();
-        }
-
-        match result {
-            Ok(()) => {}
-            Err(ExecutionError::Error(error)) => {
-                return Err(ExecutionError::Error(
-                    format!("failed to call function '{}': {}", export.prototype().name(), error).into(),
-                ));
-            }
-            Err(ExecutionError::Trap(trap)) => {
-                return Err(ExecutionError::Trap(trap));
-            }
-            Err(ExecutionError::OutOfGas) => {
-                return Err(ExecutionError::OutOfGas);
-            }
-        }
+        instance.call_export_locked(&mut mutable, user_data, self.export_index, &config, report)?;
 
         let mut output_count = 0;
-        let result = FnResult::_get(|| {
+        let result = FnResult::_get(is_64_bit, || {
             let access = mutable.backend.access();
-            let value = access.get_reg(Reg::ARG_REGS[output_count]);
+            let value = access.get_reg64(Reg::ARG_REGS[output_count]);
             output_count += 1;
             value
         });
 
         Ok(result)
     }
+
+    /// Calls the function asynchronously.
+    ///
+    /// The call yields whenever the guest program calls an asynchronous host function
+    /// (defined with [`Linker::func_wrap_async`]) whose future is still pending.
+    /// The instance isn't locked while the call is waiting, but any other call into it is refused until this one finishes
+    /// or its future gets dropped.
+    pub async fn call_async(&self, user_data: &mut T, args: FnArgs) -> Result<FnResult, ExecutionError> {
+        self.call_async_ex(user_data, args, ExecutionConfig::default()).await
+    }
+
+    /// Calls the function asynchronously with the given configuration.
+    pub async fn call_async_ex(&self, user_data: &mut T, args: FnArgs, config: ExecutionConfig) -> Result<FnResult, ExecutionError> {
+        self.call_async_ex_impl(user_data, args, config, None).await
+    }
+
+    /// Calls the function asynchronously with the given configuration, and returns an [`ExecutionReport`] alongside the result.
+    ///
+    /// The report covers the whole call, including the time spent waiting for the asynchronous host functions.
+    pub async fn call_async_ex_with_report(
+        &self,
+        user_data: &mut T,
+        args: FnArgs,
+        config: ExecutionConfig,
+    ) -> (Result<FnResult, ExecutionError>, ExecutionReport) {
+        let mut report = ExecutionReport::default();
+        let result = self.call_async_ex_impl(user_data, args, config, Some(&mut report)).await;
+        (result, report)
+    }
+
+    async fn call_async_ex_impl(
+        &self,
+        user_data: &mut T,
+        args: FnArgs,
+        mut config: ExecutionConfig,
+        report: Option<&mut ExecutionReport>,
+    ) -> Result<FnResult, ExecutionError> {
+        let is_64_bit = self.instance.0.instance_pre.0.module.is_64_bit();
+        let mut input_count = 0;
+        args._set(is_64_bit, |value| {
+            assert!(input_count <= VM_MAXIMUM_EXTERN_ARG_COUNT);
+            config.initial_regs[Reg::A0 as usize + input_count] = value;
+            input_count += 1;
+        });
+
+        self.instance
+            .call_export_async(user_data, self.export_index, config, report, |backend| {
+                let mut output_count = 0;
+                FnResult::_get(is_64_bit, || {
+                    let value = backend.access().get_reg64(Reg::ARG_REGS[output_count]);
+                    output_count += 1;
+                    value
+                })
+            })
+            .await
+    }
 }
+
+// Make sure the futures returned by `call_async` can be moved across threads, e.g. by a work-stealing executor.
+const _: () = {
+    fn assert_send<F: Send>(_: &F) {}
+
+    #[allow(dead_code)]
+    fn call_async_futures_are_send<T, FnArgs, FnResult>(
+        user_data: &mut T,
+        func: &Func<T>,
+        typed_func: &TypedFunc<T, FnArgs, FnResult>,
+        args: FnArgs,
+    ) where
+        T: Send + Sync,
+        FnArgs: FuncArgs + Send,
+        FnResult: FuncResult,
+    {
+        assert_send(&func.call_async(user_data, &[]));
+        assert_send(&func.call_async_ex_with_report(user_data, &[], ExecutionConfig::default()));
+        assert_send(&typed_func.call_async(user_data, args));
+    }
+};
@@ -1,16 +1,28 @@
-use crate::api::BackendAccess;
+use crate::api::{BackendAccess, FuncArgs, FuncResult};
+use crate::error::{Error, ExecutionError};
+use crate::guest_ptr::{pod_as_bytes, pod_as_uninit_bytes_mut, GuestPtr, GuestSlice, Pod};
 use crate::tracer::Tracer;
 use crate::Gas;
 use core::mem::MaybeUninit;
-use polkavm_common::error::Trap;
-use polkavm_common::program::Reg;
-use polkavm_common::utils::{Access, AsUninitSliceMut};
+use polkavm_common::abi::{GuestMemoryConfig, VM_ADDR_RETURN_TO_HOST, VM_PAGE_SIZE};
+use polkavm_common::error::{Trap, TrapKind};
+use polkavm_common::program::{ExternTy, Reg};
+use polkavm_common::utils::{align_to_next_page_u64, Access, AsUninitSliceMut};
 use std::rc::{Rc, Weak};
 
+/// Calls an export of the instance which is currently in the middle of a hostcall.
+pub(crate) type CallExportFn =
+    unsafe fn(&mut CallerRaw, &str, &[ExternTy], &[ExternTy], &mut [u64; Reg::ALL.len()]) -> Result<(), ExecutionError>;
+
 pub(crate) struct CallerRaw {
     user_data: *mut core::ffi::c_void,
     access: *mut core::ffi::c_void,
+    instance: *const core::ffi::c_void,
+    call_export: Option<CallExportFn>,
+    call_depth: u32,
     tracer: Option<Tracer>,
+    is_64_bit: bool,
+    memory_config: GuestMemoryConfig,
 }
 
 // SAFETY: Most of the methods of this struct are `unsafe` and the callers will uphold the invariants to ensure that this is safe.
@@ -20,14 +32,52 @@ unsafe impl Send for CallerRaw {}
 unsafe impl Sync for CallerRaw {}
 
 impl CallerRaw {
-    pub(crate) fn new(tracer: Option<Tracer>) -> Self {
+    pub(crate) fn new(tracer: Option<Tracer>, is_64_bit: bool, memory_config: GuestMemoryConfig) -> Self {
         CallerRaw {
             user_data: core::ptr::null_mut(),
             access: core::ptr::null_mut(),
+            instance: core::ptr::null(),
+            call_export: None,
+            call_depth: 0,
             tracer,
+            is_64_bit,
+            memory_config,
         }
     }
 
+    pub(crate) fn set_instance(&mut self, instance: *const core::ffi::c_void, call_export: CallExportFn) {
+        self.instance = instance;
+        self.call_export = Some(call_export);
+    }
+
+    pub(crate) fn instance(&self) -> *const core::ffi::c_void {
+        self.instance
+    }
+
+    pub(crate) fn call_depth(&self) -> u32 {
+        self.call_depth
+    }
+
+    /// Whether the instance targets the 64-bit variant of the instruction set.
+    pub(crate) fn is_64_bit(&self) -> bool {
+        self.is_64_bit
+    }
+
+    /// Runs a nested call, and restores the state of the hostcall which made it once it's finished.
+    ///
+    /// The callback gets the pointers to the user data and to the `BackendAccess` of the outer hostcall.
+    pub(crate) fn nested<R>(&mut self, callback: impl FnOnce(&mut Self, *mut core::ffi::c_void, *mut core::ffi::c_void) -> R) -> R {
+        let (user_data, access, instance, call_export) = (self.user_data, self.access, self.instance, self.call_export);
+        self.call_depth += 1;
+        let result = callback(self, user_data, access);
+        self.call_depth -= 1;
+        self.user_data = user_data;
+        self.access = access;
+        self.instance = instance;
+        self.call_export = call_export;
+        result
+    }
+
     unsafe fn data<T>(&self) -> &T {
         // SAFETY: The caller will make sure that the invariants hold.
         unsafe { &*(self.user_data as *const T) }
@@ -52,24 +102,41 @@ impl CallerRaw {
         self.tracer.as_mut()
     }
 
-    unsafe fn get_reg(&self, reg: Reg) -> u32 {
+    unsafe fn get_reg64(&self, reg: Reg) -> u64 {
         // SAFETY: The caller will make sure that the invariants hold.
-        let value = unsafe { self.access() }.get_reg(reg);
+        let value = unsafe { self.access() }.get_reg64(reg);
         log::trace!("Getting register (during hostcall): {reg} = 0x{value:x}");
         value
     }
 
-    unsafe fn set_reg(&mut self, reg: Reg, value: u32) {
+    unsafe fn set_reg64(&mut self, reg: Reg, value: u64) {
+        let value = if self.is_64_bit { value } else { u64::from(value as u32) };
         log::trace!("Setting register (during hostcall): {reg} = 0x{value:x}");
 
         // SAFETY: The caller will make sure that the invariants hold.
-        unsafe { self.access_mut() }.set_reg(reg, value);
+        unsafe { self.access_mut() }.set_reg64(reg, value);
 
         if let Some(ref mut tracer) = self.tracer() {
             tracer.on_set_reg_in_hostcall(reg, value);
         }
     }
 
+    /// Checks whether the given range lies fully within one of the guest's memory regions.
+    unsafe fn is_memory_accessible(&self, address: u32, length: u32) -> bool {
+        let config = &self.memory_config;
+
+        // SAFETY: The caller will make sure that the invariants hold.
+        let heap_end = u64::from(config.heap_address()) + u64::from(unsafe { self.access() }.heap_size());
+        let heap_end = align_to_next_page_u64(u64::from(VM_PAGE_SIZE), heap_end).unwrap_or(u64::MAX);
+
+        let start = u64::from(address);
+        let end = start + u64::from(length);
+        let is_within = |range_start: u32, range_end: u64| start >= u64::from(range_start) && end <= range_end;
+        is_within(config.ro_data_address(), u64::from(config.ro_data_range().end))
+            || is_within(config.heap_address(), heap_end)
+            || is_within(config.stack_address_low(), u64::from(config.stack_address_high()))
+    }
+
     unsafe fn read_memory_into_slice<'slice, B>(&self, address: u32, buffer: &'slice mut B) -> Result<&'slice mut [u8], Trap>
     where
         B: ?Sized + AsUninitSliceMut,
@@ -107,6 +174,78 @@ impl CallerRaw {
         Ok(value)
     }
 
+    unsafe fn read_pod<P: Pod>(&self, address: u32) -> Result<P, Trap> {
+        let mut value: MaybeUninit<P> = MaybeUninit::uninit();
+
+        // SAFETY: The caller will make sure that the invariants hold.
+        unsafe { self.read_memory_into_slice(address, pod_as_uninit_bytes_mut(core::slice::from_mut(&mut value))) }?;
+
+        // SAFETY: The value was fully initialized by `read_memory_into_slice`, and every bit pattern is valid for `Pod` types.
+        Ok(unsafe { value.assume_init() })
+    }
+
+    unsafe fn read_slice<P: Pod>(&self, slice: GuestSlice<P>) -> Result<Vec<P>, Trap> {
+        let address = slice.as_ptr().address();
+
+        // Make sure the length is sane before we allocate anything, since it comes from the guest.
+        let Some(byte_length) = slice.byte_length() else {
+            return Err(TrapKind::InvalidLoad { address }.into());
+        };
+
+        // SAFETY: The caller will make sure that the invariants hold.
+        if !unsafe { self.is_memory_accessible(address, byte_length) } {
+            return Err(TrapKind::InvalidLoad { address }.into());
+        }
+
+        let length = slice.len() as usize;
+        let mut buffer = Vec::with_capacity(length);
+
+        // SAFETY: The caller will make sure that the invariants hold.
+        unsafe { self.read_memory_into_slice(address, pod_as_uninit_bytes_mut(&mut buffer.spare_capacity_mut()[..length])) }?;
+
+        // SAFETY: The first `length` elements were fully initialized by `read_memory_into_slice`.
+        unsafe {
+            buffer.set_len(length);
+        }
+
+        Ok(buffer)
+    }
+
+    unsafe fn read_str(&self, mut address: u32, max_length: u32) -> Result<String, Trap> {
+        const CHUNK_SIZE: u32 = 256;
+
+        // The string is read in small chunks which never cross a page boundary, so that we don't
+        // trigger a fault by reading past its end if it's placed right at the end of a memory region.
+        let mut buffer: MaybeUninit<[u8; CHUNK_SIZE as usize]> = MaybeUninit::uninit();
+        let mut output = Vec::new();
+        loop {
+            let remaining = u64::from(max_length) + 1 - output.len() as u64;
+            if remaining == 0 {
+                return Err(Trap::from_error(format!(
+                    "string at 0x{:x} is longer than {} bytes",
+                    address as usize - output.len(),
+                    max_length
+                )));
+            }
+
+            let chunk_length = core::cmp::min(remaining, u64::from(CHUNK_SIZE - address % CHUNK_SIZE)) as usize;
+
+            // SAFETY: The caller will make sure that the invariants hold.
+            let chunk = unsafe { self.read_memory_into_slice(address, &mut buffer.as_uninit_slice_mut()[..chunk_length]) }?;
+            if let Some(nul_position) = chunk.iter().position(|&byte| byte == 0) {
+                output.extend_from_slice(&chunk[..nul_position]);
+                break;
+            }
+
+            output.extend_from_slice(chunk);
+            address = address
+                .checked_add(chunk_length as u32)
+                .ok_or(Trap::new(TrapKind::InvalidLoad { address: u32::MAX }))?;
+        }
+
+        String::from_utf8(output).map_err(Trap::from_error)
+    }
+
     unsafe fn write_memory(&mut self, address: u32, data: &[u8]) -> Result<(), Trap> {
         log::trace!(
             "Writing memory (during hostcall): 0x{:x}-0x{:x} ({} bytes)",
@@ -125,6 +264,86 @@ impl CallerRaw {
         result
     }
 
+    unsafe fn write_slice<P: Pod>(&mut self, address: u32, values: &[P]) -> Result<(), Trap> {
+        // SAFETY: The caller will make sure that the invariants hold.
+        unsafe { self.write_memory(address, pod_as_bytes(values)) }
+    }
+
+    unsafe fn with_memory<R>(&self, address: u32, length: u32, callback: impl FnOnce(&[u8]) -> R) -> Result<R, Trap> {
+        log::trace!(
+            "Accessing memory (during hostcall): 0x{:x}-0x{:x} ({} bytes)",
+            address,
+            address.wrapping_add(length),
+            length
+        );
+
+        // SAFETY: The caller will make sure that the invariants hold.
+        unsafe { self.access() }.with_memory(address, length, callback)
+    }
+
+    unsafe fn with_memory_mut<R>(&mut self, address: u32, length: u32, callback: impl FnOnce(&mut [u8]) -> R) -> Result<R, Trap> {
+        log::trace!(
+            "Accessing memory mutably (during hostcall): 0x{:x}-0x{:x} ({} bytes)",
+            address,
+            address.wrapping_add(length),
+            length
+        );
+
+        // The tracer needs to see what was written, so only make a copy if it's actually enabled.
+        let is_tracing = self.tracer.is_some();
+        let mut written_data = None;
+
+        // SAFETY: The caller will make sure that the invariants hold.
+        let result = unsafe { self.access_mut() }.with_memory_mut(address, length, |slice| {
+            let result = callback(slice);
+            if is_tracing {
+                written_data = Some(slice.to_vec());
+            }
+
+            result
+        });
+
+        if let (Some(tracer), Some(data)) = (self.tracer(), written_data) {
+            tracer.on_memory_write_in_hostcall(address, &data, true)?;
+        }
+
+        result
+    }
+
+    unsafe fn call_export<FnArgs, FnResult>(&mut self, name: &str, args: FnArgs) -> Result<FnResult, ExecutionError>
+    where
+        FnArgs: FuncArgs,
+        FnResult: FuncResult,
+    {
+        let Some(call_export) = self.call_export else {
+            return Err(ExecutionError::Error(Error::from_static_str(
+                "failed to call an export: calling exports is not possible through this caller",
+            )));
+        };
+
+        // The nested call uses the same stack, right below where the outer call currently is.
+        let mut regs = [0; Reg::ALL.len()];
+        // SAFETY: The caller will make sure that the invariants hold.
+        regs[Reg::SP as usize] = unsafe { self.get_reg64(Reg::SP) };
+        regs[Reg::RA as usize] = u64::from(VM_ADDR_RETURN_TO_HOST);
+
+        let mut input_count = 0;
+        args._set(self.is_64_bit, |value| {
+            regs[Reg::A0 as usize + input_count] = value;
+            input_count += 1;
+        });
+
+        // SAFETY: The caller will make sure that the invariants hold.
+        unsafe { call_export(self, name, FnArgs::_PRIVATE_EXTERN_TY, FnResult::_PRIVATE_EXTERN_TY, &mut regs) }?;
+
+        let mut output_count = 0;
+        Ok(FnResult::_get(self.is_64_bit, || {
+            let value = regs[Reg::ARG_REGS[output_count] as usize];
+            output_count += 1;
+            value
+        }))
+    }
+
     unsafe fn gas_remaining(&self) -> Option<Gas> {
         // SAFETY: The caller will make sure that the invariants hold.
         unsafe { self.access() }.gas_remaining()
@@ -186,6 +405,10 @@ impl<'a, T> Caller<'a, T> {
         }
     }
 
+    pub(crate) fn is_64_bit(&self) -> bool {
+        self.raw.is_64_bit()
+    }
+
     pub fn split(self) -> (Caller<'a, ()>, &'a mut T) {
         let Caller { raw, lifetime, _phantom } = self;
         let dummy: *mut () = &mut () as *mut ();
@@ -193,6 +416,9 @@ impl<'a, T> Caller<'a, T> {
         let user_data: *mut core::ffi::c_void = core::mem::replace(&mut raw.user_data, dummy);
         let user_data: *mut T = user_data.cast();
 
+        // The user data is now borrowed separately, so it can't be passed into a nested call anymore.
+        raw.call_export = None;
+
         // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
         let user_data = unsafe { &mut *user_data };
         let caller = Caller {
@@ -215,13 +441,23 @@ impl<'a, T> Caller<'a, T> {
     }
 
     pub fn get_reg(&self, reg: Reg) -> u32 {
-        // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
-        unsafe { self.raw.get_reg(reg) }
+        self.get_reg64(reg) as u32
     }
 
     pub fn set_reg(&mut self, reg: Reg, value: u32) {
+        self.set_reg64(reg, u64::from(value));
+    }
+
+    /// Gets the full value of a register, for programs which target the 64-bit variant of the instruction set.
+    pub fn get_reg64(&self, reg: Reg) -> u64 {
+        // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
+        unsafe { self.raw.get_reg64(reg) }
+    }
+
+    /// Sets the full value of a register, for programs which target the 64-bit variant of the instruction set.
+    pub fn set_reg64(&mut self, reg: Reg, value: u64) {
         // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
-        unsafe { self.raw.set_reg(reg, value) }
+        unsafe { self.raw.set_reg64(reg, value) }
     }
 
     pub fn read_memory_into_slice<'slice, B>(&self, address: u32, buffer: &'slice mut B) -> Result<&'slice mut [u8], Trap>
@@ -242,11 +478,126 @@ impl<'a, T> Caller<'a, T> {
         unsafe { self.raw.read_u32(address) }
     }
 
+    /// Reads a `u8` from the guest's memory.
+    pub fn read_u8(&self, address: u32) -> Result<u8, Trap> {
+        // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
+        unsafe { self.raw.read_pod::<u8>(address) }
+    }
+
+    /// Reads a little-endian `u16` from the guest's memory.
+    pub fn read_u16(&self, address: u32) -> Result<u16, Trap> {
+        // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
+        unsafe { self.raw.read_pod::<u16>(address).map(u16::from_le) }
+    }
+
+    /// Reads a little-endian `u64` from the guest's memory.
+    pub fn read_u64(&self, address: u32) -> Result<u64, Trap> {
+        // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
+        unsafe { self.raw.read_pod::<u64>(address).map(u64::from_le) }
+    }
+
+    /// Reads a value of the given type from the guest's memory.
+    ///
+    /// The address can be given either as a plain `u32` or as a [`GuestPtr`].
+    pub fn read_pod<P: Pod>(&self, address: impl Into<GuestPtr<P>>) -> Result<P, Trap> {
+        // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
+        unsafe { self.raw.read_pod(address.into().address()) }
+    }
+
+    /// Reads all of the elements of the given slice from the guest's memory.
+    pub fn read_slice<P: Pod>(&self, slice: GuestSlice<P>) -> Result<Vec<P>, Trap> {
+        // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
+        unsafe { self.raw.read_slice(slice) }
+    }
+
+    /// Reads a NUL-terminated UTF-8 string from the guest's memory.
+    ///
+    /// Will trap if the string is not valid UTF-8, or if no NUL terminator was found within the first `max_length` bytes.
+    pub fn read_str(&self, address: impl Into<GuestPtr<u8>>, max_length: u32) -> Result<String, Trap> {
+        // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
+        unsafe { self.raw.read_str(address.into().address(), max_length) }
+    }
+
     pub fn write_memory(&mut self, address: u32, data: &[u8]) -> Result<(), Trap> {
         // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
         unsafe { self.raw.write_memory(address, data) }
     }
 
+    /// Writes a `u8` into the guest's memory.
+    pub fn write_u8(&mut self, address: u32, value: u8) -> Result<(), Trap> {
+        // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
+        unsafe { self.raw.write_slice(address, &[value]) }
+    }
+
+    /// Writes a little-endian `u16` into the guest's memory.
+    pub fn write_u16(&mut self, address: u32, value: u16) -> Result<(), Trap> {
+        // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
+        unsafe { self.raw.write_slice(address, &[value.to_le()]) }
+    }
+
+    /// Writes a little-endian `u32` into the guest's memory.
+    pub fn write_u32(&mut self, address: u32, value: u32) -> Result<(), Trap> {
+        // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
+        unsafe { self.raw.write_slice(address, &[value.to_le()]) }
+    }
+
+    /// Writes a little-endian `u64` into the guest's memory.
+    pub fn write_u64(&mut self, address: u32, value: u64) -> Result<(), Trap> {
+        // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
+        unsafe { self.raw.write_slice(address, &[value.to_le()]) }
+    }
+
+    /// Writes a value of the given type into the guest's memory.
+    ///
+    /// The address can be given either as a plain `u32` or as a [`GuestPtr`].
+    pub fn write_pod<P: Pod>(&mut self, address: impl Into<GuestPtr<P>>, value: P) -> Result<(), Trap> {
+        // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
+        unsafe { self.raw.write_slice(address.into().address(), &[value]) }
+    }
+
+    /// Writes a slice of values into the guest's memory.
+    pub fn write_slice<P: Pod>(&mut self, address: impl Into<GuestPtr<P>>, values: &[P]) -> Result<(), Trap> {
+        // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
+        unsafe { self.raw.write_slice(address.into().address(), values) }
+    }
+
+    /// Calls the given closure with a view of the guest memory at the given address.
+    ///
+    /// Unlike [`Caller::read_memory_into_slice`] this gives direct access to the memory without copying it,
+    /// as long as the backend supports it. Both the interpreter and the generic sandbox always do; the Linux
+    /// sandbox shares the BSS, the heap and the stack with the host, but the read-write data is private
+    /// to the sandboxed process and still has to be copied.
+    pub fn with_memory<R>(&self, address: u32, length: u32, callback: impl FnOnce(&[u8]) -> R) -> Result<R, Trap> {
+        // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
+        unsafe { self.raw.with_memory(address, length, callback) }
+    }
+
+    /// Calls the given closure with a mutable view of the guest memory at the given address.
+    ///
+    /// Just as with [`Caller::with_memory`] the memory is only copied if the backend can't give direct access to it.
+    pub fn with_memory_mut<R>(&mut self, address: u32, length: u32, callback: impl FnOnce(&mut [u8]) -> R) -> Result<R, Trap> {
+        // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
+        unsafe { self.raw.with_memory_mut(address, length, callback) }
+    }
+
+    /// Calls a function exported by the program from within the current host function.
+    ///
+    /// The state of the current call is saved and restored once the nested call finishes, so the program
+    /// will continue as usual once the host function returns. The nested call runs on the same stack
+    /// right below the current stack pointer, and it shares the gas with the current call.
+    ///
+    /// This isn't available on a caller which was [split](Caller::split) from its user data, and
+    /// fails if the maximum nesting depth set with [`Config::set_max_nested_call_depth`](crate::Config::set_max_nested_call_depth) is exceeded.
+    /// To call into a different instance just call its functions directly.
+    pub fn call_export<FnArgs, FnResult>(&mut self, name: &str, args: FnArgs) -> Result<FnResult, ExecutionError>
+    where
+        FnArgs: FuncArgs,
+        FnResult: FuncResult,
+    {
+        // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
+        unsafe { self.raw.call_export(name, args) }
+    }
+
     pub fn gas_remaining(&self) -> Option<Gas> {
         // SAFETY: This can only be called from inside of `Caller::wrap` so this is always valid.
         unsafe { self.raw.gas_remaining() }
@@ -288,17 +639,27 @@ impl<T> CallerRef<T> {
     }
 
     pub fn get_reg(&self, reg: Reg) -> u32 {
+        self.get_reg64(reg) as u32
+    }
+
+    pub fn set_reg(&mut self, reg: Reg, value: u32) {
+        self.set_reg64(reg, u64::from(value));
+    }
+
+    /// Gets the full value of a register, for programs which target the 64-bit variant of the instruction set.
+    pub fn get_reg64(&self, reg: Reg) -> u64 {
         self.check_lifetime_or_panic();
 
         // SAFETY: We've made sure the lifetime is valid.
-        unsafe { (*self.raw).get_reg(reg) }
+        unsafe { (*self.raw).get_reg64(reg) }
     }
 
-    pub fn set_reg(&mut self, reg: Reg, value: u32) {
+    /// Sets the full value of a register, for programs which target the 64-bit variant of the instruction set.
+    pub fn set_reg64(&mut self, reg: Reg, value: u64) {
         self.check_lifetime_or_panic();
 
         // SAFETY: We've made sure the lifetime is valid.
-        unsafe { (*self.raw).set_reg(reg, value) }
+        unsafe { (*self.raw).set_reg64(reg, value) }
     }
 
     pub fn read_memory_into_slice<'slice, B>(&self, address: u32, buffer: &'slice mut B) -> Result<&'slice mut [u8], Trap>
@@ -325,6 +686,48 @@ impl<T> CallerRef<T> {
         unsafe { (*self.raw).read_u32(address) }
     }
 
+    pub fn read_u8(&self, address: u32) -> Result<u8, Trap> {
+        self.check_lifetime_or_panic();
+
+        // SAFETY: We've made sure the lifetime is valid.
+        unsafe { (*self.raw).read_pod::<u8>(address) }
+    }
+
+    pub fn read_u16(&self, address: u32) -> Result<u16, Trap> {
+        self.check_lifetime_or_panic();
+
+        // SAFETY: We've made sure the lifetime is valid.
+        unsafe { (*self.raw).read_pod::<u16>(address).map(u16::from_le) }
+    }
+
+    pub fn read_u64(&self, address: u32) -> Result<u64, Trap> {
+        self.check_lifetime_or_panic();
+
+        // SAFETY: We've made sure the lifetime is valid.
+        unsafe { (*self.raw).read_pod::<u64>(address).map(u64::from_le) }
+    }
+
+    pub fn read_pod<P: Pod>(&self, address: impl Into<GuestPtr<P>>) -> Result<P, Trap> {
+        self.check_lifetime_or_panic();
+
+        // SAFETY: We've made sure the lifetime is valid.
+        unsafe { (*self.raw).read_pod(address.into().address()) }
+    }
+
+    pub fn read_slice<P: Pod>(&self, slice: GuestSlice<P>) -> Result<Vec<P>, Trap> {
+        self.check_lifetime_or_panic();
+
+        // SAFETY: We've made sure the lifetime is valid.
+        unsafe { (*self.raw).read_slice(slice) }
+    }
+
+    pub fn read_str(&self, address: impl Into<GuestPtr<u8>>, max_length: u32) -> Result<String, Trap> {
+        self.check_lifetime_or_panic();
+
+        // SAFETY: We've made sure the lifetime is valid.
+        unsafe { (*self.raw).read_str(address.into().address(), max_length) }
+    }
+
     pub fn write_memory(&mut self, address: u32, data: &[u8]) -> Result<(), Trap> {
         self.check_lifetime_or_panic();
 
@@ -332,6 +735,73 @@ impl<T> CallerRef<T> {
         unsafe { (*self.raw).write_memory(address, data) }
     }
 
+    pub fn write_u8(&mut self, address: u32, value: u8) -> Result<(), Trap> {
+        self.check_lifetime_or_panic();
+
+        // SAFETY: We've made sure the lifetime is valid.
+        unsafe { (*self.raw).write_slice(address, &[value]) }
+    }
+
+    pub fn write_u16(&mut self, address: u32, value: u16) -> Result<(), Trap> {
+        self.check_lifetime_or_panic();
+
+        // SAFETY: We've made sure the lifetime is valid.
+        unsafe { (*self.raw).write_slice(address, &[value.to_le()]) }
+    }
+
+    pub fn write_u32(&mut self, address: u32, value: u32) -> Result<(), Trap> {
+        self.check_lifetime_or_panic();
+
+        // SAFETY: We've made sure the lifetime is valid.
+        unsafe { (*self.raw).write_slice(address, &[value.to_le()]) }
+    }
+
+    pub fn write_u64(&mut self, address: u32, value: u64) -> Result<(), Trap> {
+        self.check_lifetime_or_panic();
+
+        // SAFETY: We've made sure the lifetime is valid.
+        unsafe { (*self.raw).write_slice(address, &[value.to_le()]) }
+    }
+
+    pub fn write_pod<P: Pod>(&mut self, address: impl Into<GuestPtr<P>>, value: P) -> Result<(), Trap> {
+        self.check_lifetime_or_panic();
+
+        // SAFETY: We've made sure the lifetime is valid.
+        unsafe { (*self.raw).write_slice(address.into().address(), &[value]) }
+    }
+
+    pub fn write_slice<P: Pod>(&mut self, address: impl Into<GuestPtr<P>>, values: &[P]) -> Result<(), Trap> {
+        self.check_lifetime_or_panic();
+
+        // SAFETY: We've made sure the lifetime is valid.
+        unsafe { (*self.raw).write_slice(address.into().address(), values) }
+    }
+
+    pub fn with_memory<R>(&self, address: u32, length: u32, callback: impl FnOnce(&[u8]) -> R) -> Result<R, Trap> {
+        self.check_lifetime_or_panic();
+
+        // SAFETY: We've made sure the lifetime is valid.
+        unsafe { (*self.raw).with_memory(address, length, callback) }
+    }
+
+    pub fn with_memory_mut<R>(&mut self, address: u32, length: u32, callback: impl FnOnce(&mut [u8]) -> R) -> Result<R, Trap> {
+        self.check_lifetime_or_panic();
+
+        // SAFETY: We've made sure the lifetime is valid.
+        unsafe { (*self.raw).with_memory_mut(address, length, callback) }
+    }
+
+    pub fn call_export<FnArgs, FnResult>(&mut self, name: &str, args: FnArgs) -> Result<FnResult, ExecutionError>
+    where
+        FnArgs: FuncArgs,
+        FnResult: FuncResult,
+    {
+        self.check_lifetime_or_panic();
+
+        // SAFETY: We've made sure the lifetime is valid.
+        unsafe { (*self.raw).call_export(name, args) }
+    }
+
     pub fn gas_remaining(&self) -> Option<Gas> {
         self.check_lifetime_or_panic();
 
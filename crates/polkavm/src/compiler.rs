@@ -1,17 +1,19 @@
+use core::cell::Cell;
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::sync::Arc;
 
 use polkavm_assembler::{Assembler, Label};
-use polkavm_common::error::{ExecutionError, Trap};
+use polkavm_common::error::{ExecutionError, Trap, TrapKind};
 use polkavm_common::init::GuestProgramInit;
-use polkavm_common::program::{ProgramExport, Instruction};
+use polkavm_common::program::{ProgramExport, Instruction, Reg};
+use polkavm_common::utils::Access;
 use polkavm_common::zygote::{
     AddressTable, VM_COMPILER_MAXIMUM_EPILOGUE_LENGTH, VM_COMPILER_MAXIMUM_INSTRUCTION_LENGTH,
 };
 use polkavm_common::abi::VM_CODE_ADDRESS_ALIGNMENT;
 
-use crate::api::{BackendAccess, EngineState, ExecutionConfig, Module, OnHostcall, SandboxExt, VisitorWrapper};
+use crate::api::{BackendAccess, EngineState, ExecutionConfig, HostcallOutcome, InterruptState, Module, OnHostcall, SandboxExt, VisitorWrapper};
 use crate::error::{bail, Error};
 
 use crate::sandbox::{Sandbox, SandboxProgram, SandboxProgramInit, ExecuteArgs};
@@ -32,20 +34,34 @@ pub(crate) struct Compiler<'a> {
     jump_table: Vec<u8>,
     export_to_label: HashMap<u32, Label>,
     export_trampolines: Vec<u64>,
+    is_64_bit: bool,
     debug_trace_execution: bool,
     ecall_label: Label,
     trap_label: Label,
+    out_of_gas_label: Label,
     trace_label: Label,
+    sbrk_label: Label,
+    memcpy_label: Label,
+    memset_label: Label,
+    invalid_jump_label: Label,
     jump_table_label: Label,
     sandbox_kind: SandboxKind,
     gas_metering: Option<GasMeteringKind>,
+    bulk_memory_byte_cost: u32,
+    sbrk_byte_cost: u32,
     native_code_address: u64,
     address_table: AddressTable,
     vmctx_regs_offset: usize,
     vmctx_gas_offset: usize,
+    vmctx_heap_top_offset: usize,
+    vmctx_basic_blocks_executed_offset: usize,
+    count_basic_blocks: bool,
     nth_instruction_to_code_offset_map: Vec<u32>,
     init: GuestProgramInit<'a>,
     is_last_instruction: bool,
+    has_lzcnt: bool,
+    has_bmi1: bool,
+    has_popcnt: bool,
 }
 
 struct CompilationResult<'a> {
@@ -53,6 +69,8 @@ struct CompilationResult<'a> {
     jump_table: Vec<u8>,
     export_trampolines: Vec<u64>,
     sysreturn_address: u64,
+    invalid_jump_address: u64,
+    hostcall_resume_address: u64,
     nth_instruction_to_code_offset_map: Vec<u32>,
     init: GuestProgramInit<'a>,
 }
@@ -68,6 +86,9 @@ impl<'a> Compiler<'a> {
         address_table: AddressTable,
         vmctx_regs_offset: usize,
         vmctx_gas_offset: usize,
+        vmctx_heap_top_offset: usize,
+        vmctx_basic_blocks_executed_offset: usize,
+        is_64_bit: bool,
         debug_trace_execution: bool,
         native_code_address: u64,
         instruction_count: usize,
@@ -77,7 +98,12 @@ impl<'a> Compiler<'a> {
         let mut asm = Assembler::new();
         let ecall_label = asm.forward_declare_label();
         let trap_label = asm.forward_declare_label();
+        let out_of_gas_label = asm.forward_declare_label();
         let trace_label = asm.forward_declare_label();
+        let sbrk_label = asm.forward_declare_label();
+        let memcpy_label = asm.forward_declare_label();
+        let memset_label = asm.forward_declare_label();
+        let invalid_jump_label = asm.forward_declare_label();
         let jump_table_label = asm.forward_declare_label();
 
         let nth_basic_block_to_label = Vec::with_capacity(basic_block_count);
@@ -111,18 +137,32 @@ impl<'a> Compiler<'a> {
             export_trampolines: Default::default(),
             ecall_label,
             trap_label,
+            out_of_gas_label,
             trace_label,
+            sbrk_label,
+            memcpy_label,
+            memset_label,
+            invalid_jump_label,
             jump_table_label,
             sandbox_kind,
             gas_metering: config.gas_metering,
+            bulk_memory_byte_cost: config.gas_cost_model.bulk_memory_byte_cost,
+            sbrk_byte_cost: config.gas_cost_model.sbrk_byte_cost,
             native_code_address,
+            is_64_bit,
             debug_trace_execution,
             address_table,
             vmctx_regs_offset,
             vmctx_gas_offset,
+            vmctx_heap_top_offset,
+            vmctx_basic_blocks_executed_offset,
+            count_basic_blocks: config.count_basic_blocks,
             nth_instruction_to_code_offset_map,
             init,
             is_last_instruction: instruction_count == 0,
+            has_lzcnt: !config.force_bit_manipulation_fallbacks && std::is_x86_feature_detected!("lzcnt"),
+            has_bmi1: !config.force_bit_manipulation_fallbacks && std::is_x86_feature_detected!("bmi1"),
+            has_popcnt: !config.force_bit_manipulation_fallbacks && std::is_x86_feature_detected!("popcnt"),
         };
 
         compiler.start_new_basic_block();
@@ -149,7 +189,17 @@ impl<'a> Compiler<'a> {
         }
 
         self.emit_trap_trampoline();
-        self.emit_ecall_trampoline();
+        if matches!(self.gas_metering, Some(GasMeteringKind::Sync)) {
+            self.emit_out_of_gas_trampoline();
+        }
+
+        self.emit_invalid_jump_trampoline();
+        self.emit_sbrk_trampoline();
+
+        let memory_config = self.init.memory_config().map_err(Error::from_static_str)?;
+        self.emit_memcpy_trampoline(&memory_config);
+        self.emit_memset_trampoline(&memory_config);
+        let label_hostcall_resume = self.emit_ecall_trampoline();
         self.emit_export_trampolines();
 
         let label_sysreturn = self.emit_sysreturn();
@@ -160,7 +210,21 @@ impl<'a> Compiler<'a> {
 
         let native_pointer_size = core::mem::size_of::<usize>();
         let jump_table_entry_size = native_pointer_size * VM_CODE_ADDRESS_ALIGNMENT as usize;
-        self.jump_table.resize(self.basic_block_by_jump_table_index.len() * jump_table_entry_size, 0);
+        let jump_table_length = polkavm_common::utils::align_to_next_page_usize(
+            crate::sandbox::get_native_page_size(),
+            self.basic_block_by_jump_table_index.len() * jump_table_entry_size,
+        )
+        .unwrap();
+
+        // Any indirect jump which doesn't land on a valid basic block (including misaligned ones) will end up here.
+        let invalid_jump_address = self.native_code_address
+            .checked_add_signed(self.asm.get_label_origin_offset_or_panic(self.invalid_jump_label) as i64)
+            .expect("overflow");
+
+        self.jump_table.reserve_exact(jump_table_length);
+        while self.jump_table.len() < jump_table_length {
+            self.jump_table.extend_from_slice(&invalid_jump_address.to_ne_bytes());
+        }
 
         // The very first entry is always invalid.
         assert_eq!(self.basic_block_by_jump_table_index[0], u32::MAX);
@@ -198,6 +262,10 @@ impl<'a> Compiler<'a> {
             .checked_add_signed(self.asm.get_label_origin_offset_or_panic(label_sysreturn) as i64)
             .expect("overflow");
 
+        let hostcall_resume_address = self.native_code_address
+            .checked_add_signed(self.asm.get_label_origin_offset_or_panic(label_hostcall_resume) as i64)
+            .expect("overflow");
+
         match self.sandbox_kind {
             SandboxKind::Linux => {},
             SandboxKind::Generic => {
@@ -214,6 +282,8 @@ impl<'a> Compiler<'a> {
             jump_table: self.jump_table,
             export_trampolines: self.export_trampolines,
             sysreturn_address,
+            invalid_jump_address,
+            hostcall_resume_address,
             nth_instruction_to_code_offset_map: self.nth_instruction_to_code_offset_map,
             init: self.init,
         })
@@ -275,6 +345,11 @@ impl<'a> Compiler<'a> {
             self.nth_basic_block_to_machine_code_offset.push(offset);
             self.emit_gas_metering_stub(gas_metering);
         }
+
+        if self.count_basic_blocks {
+            // This has to go after the gas metering stub, since the stub's cost gets patched in later at a fixed offset.
+            self.emit_basic_block_counter();
+        }
     }
 }
 
@@ -340,6 +415,7 @@ impl<S> crate::api::BackendModule for CompiledModule<S> where S: Sandbox {
         init: GuestProgramInit<'a>,
         instruction_count: usize,
         basic_block_count: usize,
+        is_64_bit: bool,
         debug_trace_execution: bool,
     ) -> Result<(Self::BackendVisitor<'a>, Self::Aux), Error> {
         crate::sandbox::assert_native_page_size();
@@ -355,6 +431,9 @@ impl<S> crate::api::BackendModule for CompiledModule<S> where S: Sandbox {
             S::address_table(),
             S::vmctx_regs_offset(),
             S::vmctx_gas_offset(),
+            S::vmctx_heap_top_offset(),
+            S::vmctx_basic_blocks_executed_offset(),
+            is_64_bit,
             debug_trace_execution,
             native_code_address,
             instruction_count,
@@ -367,6 +446,7 @@ impl<S> crate::api::BackendModule for CompiledModule<S> where S: Sandbox {
 
     fn finish_compilation<'a>(wrapper: VisitorWrapper<'a, Self::BackendVisitor<'a>>, address_space: Self::Aux) -> Result<(crate::api::Common<'a>, Self), Error> {
         let gas_metering = wrapper.visitor.gas_metering;
+        let native_code_address = wrapper.visitor.native_code_address;
         let result = wrapper.visitor.finalize(&wrapper.common.gas_cost_for_basic_block)?;
 
         let init = SandboxProgramInit::new(result.init)
@@ -380,6 +460,9 @@ impl<S> crate::api::BackendModule for CompiledModule<S> where S: Sandbox {
         let module = CompiledModule {
             sandbox_program,
             export_trampolines,
+            native_code_address,
+            invalid_jump_address: result.invalid_jump_address,
+            hostcall_resume_address: result.hostcall_resume_address,
             nth_instruction_to_code_offset_map: result.nth_instruction_to_code_offset_map,
         };
 
@@ -390,6 +473,9 @@ impl<S> crate::api::BackendModule for CompiledModule<S> where S: Sandbox {
 pub(crate) struct CompiledModule<S> where S: Sandbox {
     sandbox_program: S::Program,
     export_trampolines: Vec<u64>,
+    native_code_address: u64,
+    invalid_jump_address: u64,
+    hostcall_resume_address: u64,
     nth_instruction_to_code_offset_map: Vec<u32>,
 }
 
@@ -398,19 +484,141 @@ impl<S> CompiledModule<S> where S: Sandbox {
         self.sandbox_program.machine_code()
     }
 
+    /// Creates a copy of this module whose instances start with the given heap contents.
+    pub fn with_initial_heap(&self, heap: &[u8]) -> Result<Self, Error> {
+        Ok(CompiledModule {
+            sandbox_program: S::prepare_program_with_initial_heap(&self.sandbox_program, heap).map_err(Error::from_display)?,
+            export_trampolines: self.export_trampolines.clone(),
+            native_code_address: self.native_code_address,
+            invalid_jump_address: self.invalid_jump_address,
+            hostcall_resume_address: self.hostcall_resume_address,
+            nth_instruction_to_code_offset_map: self.nth_instruction_to_code_offset_map.clone(),
+        })
+    }
+
     pub fn nth_instruction_to_code_offset_map(&self) -> &[u32] {
         &self.nth_instruction_to_code_offset_map
     }
+
+    pub(crate) fn program_counter_by_native_address(&self, native_address: u64) -> Option<u32> {
+        let offset = native_address.checked_sub(self.native_code_address)?;
+        let offset = u32::try_from(offset).ok()?;
+
+        // The very last entry marks the start of the epilogue.
+        let (&epilogue_start, map) = self.nth_instruction_to_code_offset_map.split_last()?;
+        if offset >= epilogue_start {
+            return None;
+        }
+
+        // Instructions which don't emit any code share their offset with the next one, so pick the last one.
+        let nth_instruction = map.partition_point(|&instruction_offset| instruction_offset <= offset).checked_sub(1)?;
+        Some(nth_instruction as u32)
+    }
+
+    fn resolve_trap(&self, trap: Trap, native_program_counter: Option<u64>) -> Trap {
+        let Some(native_program_counter) = native_program_counter else {
+            return trap;
+        };
+
+        if native_program_counter == self.invalid_jump_address {
+            return trap.with_kind(TrapKind::InvalidJumpTarget);
+        }
+
+        if trap.program_counter().is_some() {
+            return trap;
+        }
+
+        let program_counter = self.program_counter_by_native_address(native_program_counter);
+        trap.with_program_counter(program_counter)
+    }
+}
+
+/// Wraps the hostcall handler, recording in `out_of_gas_handler_failed` whether the out-of-gas handler has returned an error.
+fn wrap_on_hostcall<'a, S>(
+    on_hostcall: OnHostcall<'a>,
+    out_of_gas_handler_failed: &'a Cell<bool>,
+) -> impl for <'r> FnMut(u32, S::Access<'r>) -> Result<HostcallOutcome, Trap> + 'a where S: Sandbox {
+    move |hostcall, access| {
+        let access: BackendAccess = access.into();
+        let result = on_hostcall(hostcall, access);
+        if hostcall == polkavm_common::HOSTCALL_OUT_OF_GAS && result.is_err() {
+            out_of_gas_handler_failed.set(true);
+        }
+
+        result
+    }
+}
+
+fn finish_execution<S>(
+    module: &Module,
+    sandbox: &mut S,
+    result: Result<(), ExecutionError<S::Error>>,
+    out_of_gas_handler_failed: bool,
+) -> Result<(), ExecutionError<Error>>
+    where S: SandboxExt
+{
+    let result = match result {
+        Ok(()) => Ok(()),
+        Err(ExecutionError::Trap(trap)) => {
+            if let Some(native_regs) = sandbox.trap_native_regs() {
+                // The registers were never saved if we've trapped in the middle of the guest program, so restore them here.
+                let mut access = sandbox.access();
+                for (reg, value) in Reg::ALL.into_iter().zip(amd64::guest_regs_from_native_regs(&native_regs, module.is_64_bit())) {
+                    access.set_reg64(reg, value);
+                }
+            }
+
+            let access = sandbox.access();
+            let trap = S::as_compiled_module(module).resolve_trap(trap, access.native_program_counter());
+            Err(ExecutionError::Trap(module.attach_backtrace(trap, &access)))
+        },
+        Err(ExecutionError::Error(error)) => return Err(ExecutionError::Error(Error::from_display(error))),
+        Err(ExecutionError::OutOfGas) => return Err(ExecutionError::OutOfGas),
+        // If we've run out of gas before being interrupted then that takes precedence.
+        Err(ExecutionError::Interrupted) => Err(ExecutionError::Interrupted),
+    };
+
+    // An error returned from the out-of-gas handler should be propagated as-is.
+    if module.gas_metering().is_some() && sandbox.gas_remaining_impl().is_err() && !out_of_gas_handler_failed {
+        return Err(ExecutionError::OutOfGas);
+    }
+
+    result
+}
+
+/// Calls the given export from within a hostcall which is currently being handled by the sandbox behind `access`.
+pub(crate) fn call_nested<S>(
+    module: &Module,
+    access: &mut S::Access<'_>,
+    export_index: usize,
+    regs: &mut [u64; Reg::ALL.len()],
+    on_hostcall: OnHostcall,
+) -> Result<(), ExecutionError<Error>> where S: SandboxExt {
+    let address = S::as_compiled_module(module).export_trampolines[export_index];
+    let mut initial_regs = *regs;
+    crate::utils::truncate_regs(&mut initial_regs, module.is_64_bit());
+    let out_of_gas_handler_failed = Cell::new(false);
+    let mut on_hostcall = wrap_on_hostcall::<S>(on_hostcall, &out_of_gas_handler_failed);
+    S::call_nested(access, address, &initial_regs, &mut on_hostcall, &mut |sandbox, result| {
+        let result = finish_execution(module, sandbox, result, out_of_gas_handler_failed.get());
+        let access = sandbox.access();
+        for (reg, value) in Reg::ALL.into_iter().zip(regs.iter_mut()) {
+            *value = access.get_reg64(reg);
+        }
+
+        result
+    })
 }
 
 pub(crate) struct CompiledInstance<S> where S: SandboxExt {
     engine_state: Arc<EngineState>,
     module: Module,
+    interrupt: Arc<InterruptState>,
     sandbox: Option<S>,
 }
 
 impl<S> CompiledInstance<S> where S: SandboxExt {
-    pub fn new(engine_state: Arc<EngineState>, module: Module) -> Result<CompiledInstance<S>, Error> {
+    pub fn new(engine_state: Arc<EngineState>, module: Module, interrupt: Arc<InterruptState>) -> Result<CompiledInstance<S>, Error> {
         let mut args = ExecuteArgs::new();
         args.set_program(&S::as_compiled_module(&module).sandbox_program);
 
@@ -420,11 +628,14 @@ impl<S> CompiledInstance<S> where S: SandboxExt {
             .map_err(Error::from_display)
             .map_err(|error| error.context("instantiation failed: failed to upload the program into the sandbox"))?;
 
-        Ok(CompiledInstance { engine_state, module, sandbox: Some(sandbox) })
+        Ok(CompiledInstance { engine_state, module, interrupt, sandbox: Some(sandbox) })
     }
 
     pub fn call(&mut self, export_index: usize, on_hostcall: OnHostcall, config: &ExecutionConfig) -> Result<(), ExecutionError<Error>> {
         let address = S::as_compiled_module(&self.module).export_trampolines[export_index];
+        let mut initial_regs = config.initial_regs;
+        crate::utils::truncate_regs(&mut initial_regs, self.module.is_64_bit());
+
         let mut exec_args = ExecuteArgs::<S>::new();
 
         if config.reset_memory_after_execution {
@@ -435,43 +646,53 @@ impl<S> CompiledInstance<S> where S: SandboxExt {
             exec_args.set_clear_program_after_execution();
         }
 
+        self.interrupt.clear();
+        exec_args.set_interrupt(Arc::clone(&self.interrupt));
+        if let Some(deadline) = config.deadline {
+            exec_args.set_deadline(deadline);
+        }
+
         exec_args.set_call(address);
-        exec_args.set_initial_regs(&config.initial_regs);
+        exec_args.set_initial_regs(&initial_regs);
         if self.module.gas_metering().is_some() {
             if let Some(gas) = config.gas {
                 exec_args.set_gas(gas);
             }
         }
 
-        fn wrap_on_hostcall<S>(on_hostcall: OnHostcall<'_>) -> impl for <'r> FnMut(u32, S::Access<'r>) -> Result<(), Trap> + '_ where S: Sandbox {
-            move |hostcall, access| {
-                let access: BackendAccess = access.into();
-                on_hostcall(hostcall, access)
-            }
-        }
-
-        let mut on_hostcall = wrap_on_hostcall::<S>(on_hostcall);
+        let out_of_gas_handler_failed = Cell::new(false);
+        let mut on_hostcall = wrap_on_hostcall::<S>(on_hostcall, &out_of_gas_handler_failed);
         exec_args.set_on_hostcall(&mut on_hostcall);
+        self.execute(exec_args, &out_of_gas_handler_failed)
+    }
 
-        let sandbox = self.sandbox.as_mut().unwrap();
-        let result = match sandbox.execute(exec_args) {
-            Ok(()) => Ok(()),
-            Err(ExecutionError::Trap(trap)) => Err(ExecutionError::Trap(trap)),
-            Err(ExecutionError::Error(error)) => return Err(ExecutionError::Error(Error::from_display(error))),
-            Err(ExecutionError::OutOfGas) => return Err(ExecutionError::OutOfGas),
-        };
+    pub fn resume(&mut self, on_hostcall: OnHostcall) -> Result<(), ExecutionError<Error>> {
+        let mut exec_args = ExecuteArgs::<S>::new();
+        exec_args.set_resume(S::as_compiled_module(&self.module).hostcall_resume_address);
 
-        if self.module.gas_metering().is_some() && sandbox.gas_remaining_impl().is_err() {
-            return Err(ExecutionError::OutOfGas);
-        }
+        let out_of_gas_handler_failed = Cell::new(false);
+        let mut on_hostcall = wrap_on_hostcall::<S>(on_hostcall, &out_of_gas_handler_failed);
+        exec_args.set_on_hostcall(&mut on_hostcall);
+        self.execute(exec_args, &out_of_gas_handler_failed)
+    }
 
-        result
+    fn execute(&mut self, exec_args: ExecuteArgs<S>, out_of_gas_handler_failed: &Cell<bool>) -> Result<(), ExecutionError<Error>> {
+        let sandbox = self.sandbox.as_mut().unwrap();
+        let result = sandbox.execute(exec_args);
+        finish_execution(&self.module, sandbox, result, out_of_gas_handler_failed.get())
     }
 
     pub fn access(&'_ mut self) -> S::Access<'_> {
         self.sandbox.as_mut().unwrap().access()
     }
 
+    /// Grows or shrinks the heap so that it ends at `heap_top`.
+    pub fn resize_heap(&mut self, heap_top: u32) -> Result<(), Error> {
+        let mut exec_args = ExecuteArgs::<S>::new();
+        exec_args.set_heap_top(heap_top);
+        self.sandbox.as_mut().unwrap().execute(exec_args).map_err(Error::from_display)
+    }
+
     pub fn sandbox(&self) -> &S {
         self.sandbox.as_ref().unwrap()
     }
@@ -1,13 +1,15 @@
+use core::ops::Range;
+
 use polkavm_assembler::amd64::addr::*;
 use polkavm_assembler::amd64::inst::*;
 use polkavm_assembler::amd64::RegIndex as NativeReg;
 use polkavm_assembler::amd64::RegIndex::*;
 use polkavm_assembler::amd64::Reg::rsp;
-use polkavm_assembler::amd64::{Condition, LoadKind, RegSize, Size, MemOp};
+use polkavm_assembler::amd64::{Condition, ImmKind, LoadKind, RegSize, Size, MemOp};
 use polkavm_assembler::Label;
 
 use polkavm_common::program::{InstructionVisitor, Reg};
-use polkavm_common::abi::VM_CODE_ADDRESS_ALIGNMENT;
+use polkavm_common::abi::{GuestMemoryConfig, VM_CODE_ADDRESS_ALIGNMENT, VM_PAGE_SIZE};
 use polkavm_common::zygote::VM_ADDR_VMCTX;
 
 use crate::api::VisitorWrapper;
@@ -59,6 +61,16 @@ fn conv_reg(reg: Reg) -> NativeReg {
     REG_MAP[reg as usize]
 }
 
+/// Extracts the guest registers from the native registers captured when the guest program trapped.
+pub(crate) fn guest_regs_from_native_regs(native_regs: &[u64; 16], is_64_bit: bool) -> [u64; Reg::ALL.len()] {
+    let mut regs = [0; Reg::ALL.len()];
+    for reg in Reg::ALL {
+        let value = native_regs[conv_reg(reg) as usize];
+        regs[reg as usize] = if is_64_bit { value } else { u64::from(value as u32) };
+    }
+    regs
+}
+
 #[test]
 fn test_conv_reg() {
     for reg in Reg::ALL {
@@ -115,7 +127,7 @@ macro_rules! load_store_operand {
                         },
 
                         // [base] = ..
-                        (Some($base), 0) => {
+                        (Some($base), 0) if !$self.is_64_bit => {
                             // NOTE: This assumes that `base` has its upper 32-bits clear.
                             let $op = base_index(RegSize::R64, GENERIC_SANDBOX_MEMORY_REG, conv_reg($base));
                             $body
@@ -148,20 +160,60 @@ enum ShiftKind {
     LogicalLeft,
     LogicalRight,
     ArithmeticRight,
+    RotateLeft,
+    RotateRight,
+}
+
+enum BitwiseInvertedKind {
+    And,
+    Or,
+}
+
+enum MinMax {
+    MinSigned,
+    MinUnsigned,
+    MaxSigned,
+    MaxUnsigned,
+}
+
+/// A region of guest memory which can be accessed by `memcpy` and `memset`.
+#[derive(Clone)]
+enum BulkMemoryRegion {
+    Fixed(Range<u32>),
+
+    /// The heap, which starts at `address` and ends at the current heap top as stored in the VM context.
+    Heap { address: u32 },
 }
 
 impl<'a> Compiler<'a> {
     pub const PADDING_BYTE: u8 = 0x90; // NOP
 
-    #[allow(clippy::unused_self)]
     #[cfg_attr(not(debug_assertions), inline(always))]
     fn reg_size(&self) -> RegSize {
-        RegSize::R32
+        if self.is_64_bit {
+            RegSize::R64
+        } else {
+            RegSize::R32
+        }
+    }
+
+    /// Returns an immediate operand for an instruction which operates on the whole register.
+    #[cfg_attr(not(debug_assertions), inline(always))]
+    fn imm(&self, value: u32) -> ImmKind {
+        match self.reg_size() {
+            RegSize::R32 => imm32(value),
+            // Immediates are sign extended in the 64-bit instruction set.
+            RegSize::R64 => imm64(value as i32),
+        }
     }
 
     #[cfg_attr(not(debug_assertions), inline(always))]
     fn load_immediate(&mut self, dst: Reg, value: u32) {
-        self.push(mov_imm(conv_reg(dst), imm32(value)));
+        if self.is_64_bit && (value as i32) < 0 {
+            self.push(mov_imm(conv_reg(dst), imm64(value as i32)));
+        } else {
+            self.push(mov_imm(conv_reg(dst), imm32(value)));
+        }
     }
 
     #[cfg_attr(not(debug_assertions), inline(always))]
@@ -175,7 +227,7 @@ impl<'a> Compiler<'a> {
                         Size::U8 => self.push(mov_imm(dst, imm8(value as u8))),
                         Size::U16 => self.push(mov_imm(dst, imm16(value as u16))),
                         Size::U32 => self.push(mov_imm(dst, imm32(value))),
-                        Size::U64 => unreachable!(),
+                        Size::U64 => self.push(mov_imm(dst, imm64(value as i32))),
                     }
                 },
             }
@@ -236,14 +288,7 @@ impl<'a> Compiler<'a> {
             self.push(test((self.reg_size(), conv_reg(s1), conv_reg(s1))));
             self.push(setcc(Condition::NotEqual, conv_reg(d)));
         } else {
-            match self.reg_size() {
-                RegSize::R32 => {
-                    self.push(cmp((conv_reg(s1), imm32(s2))));
-                },
-                RegSize::R64 => {
-                    self.push(cmp((conv_reg(s1), imm64(s2 as i32))));
-                }
-            }
+            self.push(cmp((conv_reg(s1), self.imm(s2))));
             self.push(setcc(condition, conv_reg(d)));
         }
 
@@ -253,12 +298,24 @@ impl<'a> Compiler<'a> {
     }
 
     #[cfg_attr(not(debug_assertions), inline(always))]
-    fn shift_imm(&mut self, d: Reg, s1: Reg, s2: u32, kind: ShiftKind) {
-        if s2 >= 32 {
-            // d = s << 32+
-            self.clear_reg(d);
-            return;
-        }
+    fn shift_imm(&mut self, reg_size: RegSize, d: Reg, s1: Reg, s2: u32, kind: ShiftKind) {
+        let bits = match reg_size {
+            RegSize::R32 => 32,
+            RegSize::R64 => 64,
+        };
+
+        let s2 = match kind {
+            // Rotations only look at the lower bits of the amount.
+            ShiftKind::RotateLeft | ShiftKind::RotateRight => s2 & (bits - 1),
+            // The word-sized and the 64-bit shifts also only look at the lower bits of the amount.
+            _ if self.is_64_bit => s2 & (bits - 1),
+            _ if s2 >= 32 => {
+                // d = s << 32+
+                self.clear_reg(d);
+                return;
+            }
+            _ => s2,
+        };
 
         if d != s1 {
             self.mov(d, s1);
@@ -266,14 +323,16 @@ impl<'a> Compiler<'a> {
 
         // d = d << s2
         match kind {
-            ShiftKind::LogicalLeft => self.push(shl_imm(self.reg_size(), conv_reg(d), s2 as u8)),
-            ShiftKind::LogicalRight => self.push(shr_imm(self.reg_size(), conv_reg(d), s2 as u8)),
-            ShiftKind::ArithmeticRight => self.push(sar_imm(self.reg_size(), conv_reg(d), s2 as u8)),
+            ShiftKind::LogicalLeft => self.push(shl_imm(reg_size, conv_reg(d), s2 as u8)),
+            ShiftKind::LogicalRight => self.push(shr_imm(reg_size, conv_reg(d), s2 as u8)),
+            ShiftKind::ArithmeticRight => self.push(sar_imm(reg_size, conv_reg(d), s2 as u8)),
+            ShiftKind::RotateLeft => self.push(ror_imm(reg_size, conv_reg(d), ((bits - s2) & (bits - 1)) as u8)),
+            ShiftKind::RotateRight => self.push(ror_imm(reg_size, conv_reg(d), s2 as u8)),
         }
     }
 
     #[cfg_attr(not(debug_assertions), inline(always))]
-    fn shift(&mut self, d: Reg, s1: impl Into<RegImm>, s2: Reg, kind: ShiftKind) {
+    fn shift(&mut self, reg_size: RegSize, d: Reg, s1: impl Into<RegImm>, s2: Reg, kind: ShiftKind) {
         // TODO: Consider using shlx/shrx/sarx when BMI2 is available.
         self.push(mov(self.reg_size(), rcx, conv_reg(s2)));
 
@@ -290,12 +349,236 @@ impl<'a> Compiler<'a> {
 
         // d = d << s2
         match kind {
-            ShiftKind::LogicalLeft => self.push(shl_cl(self.reg_size(), conv_reg(d))),
-            ShiftKind::LogicalRight => self.push(shr_cl(self.reg_size(), conv_reg(d))),
-            ShiftKind::ArithmeticRight => self.push(sar_cl(self.reg_size(), conv_reg(d))),
+            ShiftKind::LogicalLeft => self.push(shl_cl(reg_size, conv_reg(d))),
+            ShiftKind::LogicalRight => self.push(shr_cl(reg_size, conv_reg(d))),
+            ShiftKind::ArithmeticRight => self.push(sar_cl(reg_size, conv_reg(d))),
+            ShiftKind::RotateLeft => self.push(rol_cl(reg_size, conv_reg(d))),
+            ShiftKind::RotateRight => self.push(ror_cl(reg_size, conv_reg(d))),
+        }
+    }
+
+    #[cfg_attr(not(debug_assertions), inline(always))]
+    fn min_max(&mut self, d: Reg, s1: Reg, s2: Reg, kind: MinMax) {
+        // The first condition is when `s2` should be picked, the second one when `s1` should be picked.
+        let (condition_s2, condition_s1) = match kind {
+            MinMax::MinSigned => (Condition::Greater, Condition::Less),
+            MinMax::MinUnsigned => (Condition::Above, Condition::Below),
+            MinMax::MaxSigned => (Condition::Less, Condition::Greater),
+            MinMax::MaxUnsigned => (Condition::Below, Condition::Above),
+        };
+
+        self.push(cmp((self.reg_size(), conv_reg(s1), conv_reg(s2))));
+        if d == s2 {
+            self.push(cmov(condition_s1, self.reg_size(), conv_reg(d), conv_reg(s1)));
+        } else {
+            if d != s1 {
+                self.mov(d, s1);
+            }
+            self.push(cmov(condition_s2, self.reg_size(), conv_reg(d), conv_reg(s2)));
+        }
+    }
+
+    #[cfg_attr(not(debug_assertions), inline(always))]
+    fn count_leading_zeros(&mut self, reg_size: RegSize, d: Reg, s: Reg) {
+        if self.has_lzcnt {
+            self.push(lzcnt(reg_size, conv_reg(d), conv_reg(s)));
+        } else {
+            let bits = match reg_size {
+                RegSize::R32 => 32,
+                RegSize::R64 => 64,
+            };
+
+            // The result of `bsr` is undefined when the source is zero, so pick the result manually in that case.
+            self.push(bsr(reg_size, TMP_REG, conv_reg(s)));
+            self.push(mov_imm(conv_reg(d), imm32(bits * 2 - 1)));
+            self.push(cmov(Condition::NotEqual, reg_size, conv_reg(d), TMP_REG));
+
+            // d = (bits - 1) - d, or `bits` if the source was zero
+            self.push(xor((conv_reg(d), imm32(bits - 1))));
+        }
+    }
+
+    #[cfg_attr(not(debug_assertions), inline(always))]
+    fn count_trailing_zeros(&mut self, reg_size: RegSize, d: Reg, s: Reg) {
+        if self.has_bmi1 {
+            self.push(tzcnt(reg_size, conv_reg(d), conv_reg(s)));
+        } else {
+            let bits = match reg_size {
+                RegSize::R32 => 32,
+                RegSize::R64 => 64,
+            };
+
+            // The result of `bsf` is undefined when the source is zero, so pick the result manually in that case.
+            self.push(bsf(reg_size, TMP_REG, conv_reg(s)));
+            self.push(mov_imm(conv_reg(d), imm32(bits)));
+            self.push(cmov(Condition::NotEqual, reg_size, conv_reg(d), TMP_REG));
+        }
+    }
+
+    #[cfg_attr(not(debug_assertions), inline(always))]
+    fn count_ones(&mut self, reg_size: RegSize, d: Reg, s: Reg) {
+        if self.has_popcnt {
+            self.push(popcnt(reg_size, conv_reg(d), conv_reg(s)));
+        } else {
+            self.count_set_bits_fallback(reg_size, d, s);
         }
     }
 
+    #[cfg_attr(not(debug_assertions), inline(always))]
+    fn count_set_bits_fallback(&mut self, reg_size: RegSize, d: Reg, s: Reg) {
+        if matches!(reg_size, RegSize::R64) {
+            self.count_set_bits_fallback_64(d, s);
+            return;
+        }
+
+        let d = conv_reg(d);
+        if d != conv_reg(s) {
+            self.push(mov(reg_size, d, conv_reg(s)));
+        }
+
+        // d = d - ((d >> 1) & 0x55555555)
+        self.push(mov(reg_size, TMP_REG, d));
+        self.push(shr_imm(reg_size, TMP_REG, 1));
+        self.push(and((TMP_REG, imm32(0x55555555))));
+        self.push(sub((reg_size, d, TMP_REG)));
+
+        // d = (d & 0x33333333) + ((d >> 2) & 0x33333333)
+        self.push(mov(reg_size, TMP_REG, d));
+        self.push(shr_imm(reg_size, TMP_REG, 2));
+        self.push(and((TMP_REG, imm32(0x33333333))));
+        self.push(and((d, imm32(0x33333333))));
+        self.push(add((reg_size, d, TMP_REG)));
+
+        // d = (d + (d >> 4)) & 0x0f0f0f0f
+        self.push(mov(reg_size, TMP_REG, d));
+        self.push(shr_imm(reg_size, TMP_REG, 4));
+        self.push(add((reg_size, d, TMP_REG)));
+        self.push(and((d, imm32(0x0f0f0f0f))));
+
+        // d = (d * 0x01010101) >> 24
+        self.push(imul_imm(reg_size, d, d, 0x01010101));
+        self.push(shr_imm(reg_size, d, 24));
+    }
+
+    fn count_set_bits_fallback_64(&mut self, d: Reg, s: Reg) {
+        let d = conv_reg(d);
+        if d != conv_reg(s) {
+            self.push(mov(RegSize::R64, d, conv_reg(s)));
+        }
+
+        // The 64-bit masks don't fit in an immediate, so we need another scratch register.
+        let scratch = if d == rax { rdx } else { rax };
+        self.push(push(scratch));
+
+        // d = d - ((d >> 1) & 0x5555555555555555)
+        self.push(mov_imm64(scratch, 0x5555555555555555_u64));
+        self.push(mov(RegSize::R64, TMP_REG, d));
+        self.push(shr_imm(RegSize::R64, TMP_REG, 1));
+        self.push(and((RegSize::R64, TMP_REG, scratch)));
+        self.push(sub((RegSize::R64, d, TMP_REG)));
+
+        // d = (d & 0x3333333333333333) + ((d >> 2) & 0x3333333333333333)
+        self.push(mov_imm64(scratch, 0x3333333333333333_u64));
+        self.push(mov(RegSize::R64, TMP_REG, d));
+        self.push(shr_imm(RegSize::R64, TMP_REG, 2));
+        self.push(and((RegSize::R64, TMP_REG, scratch)));
+        self.push(and((RegSize::R64, d, scratch)));
+        self.push(add((RegSize::R64, d, TMP_REG)));
+
+        // d = (d + (d >> 4)) & 0x0f0f0f0f0f0f0f0f
+        self.push(mov_imm64(scratch, 0x0f0f0f0f0f0f0f0f_u64));
+        self.push(mov(RegSize::R64, TMP_REG, d));
+        self.push(shr_imm(RegSize::R64, TMP_REG, 4));
+        self.push(add((RegSize::R64, d, TMP_REG)));
+        self.push(and((RegSize::R64, d, scratch)));
+
+        // d = (d * 0x0101010101010101) >> 56
+        self.push(mov_imm64(scratch, 0x0101010101010101_u64));
+        self.push(imul(RegSize::R64, d, scratch));
+        self.push(shr_imm(RegSize::R64, d, 56));
+
+        self.push(pop(scratch));
+    }
+
+    #[cfg_attr(not(debug_assertions), inline(always))]
+    fn bitwise_inverted(&mut self, d: Reg, s1: Reg, s2: Reg, kind: BitwiseInvertedKind) {
+        let reg_size = self.reg_size();
+
+        // tmp = !s2
+        self.push(mov(reg_size, TMP_REG, conv_reg(s2)));
+        self.push(not(reg_size, TMP_REG));
+
+        match kind {
+            BitwiseInvertedKind::And if d == s1 => self.push(and((reg_size, conv_reg(d), TMP_REG))),
+            BitwiseInvertedKind::Or if d == s1 => self.push(or((reg_size, conv_reg(d), TMP_REG))),
+            BitwiseInvertedKind::And => {
+                self.push(and((reg_size, TMP_REG, conv_reg(s1))));
+                self.push(mov(reg_size, conv_reg(d), TMP_REG));
+            },
+            BitwiseInvertedKind::Or => {
+                self.push(or((reg_size, TMP_REG, conv_reg(s1))));
+                self.push(mov(reg_size, conv_reg(d), TMP_REG));
+            },
+        }
+    }
+
+    /// Calculates the upper 64-bits of a 128-bit multiplication.
+    fn mul_upper_64(&mut self, d: Reg, s1: Reg, s2: impl Into<RegImm>, s1_kind: Signedness, s2_kind: Signedness) {
+        // The widening multiplication always returns its result in rdx:rax, so just as with the division
+        // we need to make sure that those registers won't be accidentally overwritten.
+        self.push(push(rdx));
+        self.push(push(rax));
+
+        // Push the operands.
+        match s2.into() {
+            RegImm::Reg(s2) => self.push(push(conv_reg(s2))),
+            RegImm::Imm(s2) => {
+                self.push(mov_imm(TMP_REG, imm64(s2 as i32)));
+                self.push(push(TMP_REG));
+            }
+        }
+        self.push(push(conv_reg(s1)));
+
+        // rdx = (s1 * s2) >> 64
+        self.push(load(LoadKind::U64, rax, reg_indirect(RegSize::R64, rsp)));
+        self.push(load(LoadKind::U64, TMP_REG, reg_indirect(RegSize::R64, rsp + 8)));
+        self.push(mul(RegSize::R64, TMP_REG));
+
+        // The signed result only differs from the unsigned one when the operands are negative:
+        //   mulh(s1, s2) = mulhu(s1, s2) - (s1 < 0 ? s2 : 0) - (s2 < 0 ? s1 : 0)
+        self.push(load(LoadKind::U64, rax, reg_indirect(RegSize::R64, rsp)));
+        if matches!(s1_kind, Signedness::Signed) {
+            let label_next = self.asm.forward_declare_label();
+            self.push(test((RegSize::R64, rax, rax)));
+            self.push(jcc_label8(Condition::NotSign, label_next));
+            self.push(sub((RegSize::R64, rdx, TMP_REG)));
+            self.define_label(label_next);
+        }
+
+        if matches!(s2_kind, Signedness::Signed) {
+            let label_next = self.asm.forward_declare_label();
+            self.push(test((RegSize::R64, TMP_REG, TMP_REG)));
+            self.push(jcc_label8(Condition::NotSign, label_next));
+            self.push(sub((RegSize::R64, rdx, rax)));
+            self.define_label(label_next);
+        }
+
+        self.push(mov(RegSize::R64, TMP_REG, rdx));
+
+        // Drop the operands and restore the original registers.
+        self.push(lea(RegSize::R64, rsp, reg_indirect(RegSize::R64, rsp + 16)));
+        self.push(pop(rax));
+        self.push(pop(rdx));
+
+        self.push(mov(RegSize::R64, conv_reg(d), TMP_REG));
+    }
+
+    /// Sign extends the lower 32-bits of a register; used for the word-sized instructions of the 64-bit instruction set.
+    #[cfg_attr(not(debug_assertions), inline(always))]
+    fn sign_extend_32(&mut self, reg: Reg) {
+        self.push(movsxd_32_to_64(conv_reg(reg), conv_reg(reg)));
+    }
+
     #[cfg_attr(not(debug_assertions), inline(always))]
     fn mov(&mut self, dst: Reg, src: Reg) {
         self.push(mov(self.reg_size(), conv_reg(dst), conv_reg(src)))
@@ -334,7 +617,7 @@ impl<'a> Compiler<'a> {
     fn branch(&mut self, s1: Reg, s2: impl Into<RegImm>, target: u32, condition: Condition) {
         match s2.into() {
             RegImm::Reg(s2) => self.push(cmp((self.reg_size(), conv_reg(s1), conv_reg(s2)))),
-            RegImm::Imm(s2) => self.push(cmp((conv_reg(s1), imm32(s2)))),
+            RegImm::Imm(s2) => self.push(cmp((conv_reg(s1), self.imm(s2)))),
         }
 
         let label = self.get_or_forward_declare_label(target);
@@ -370,33 +653,39 @@ impl<'a> Compiler<'a> {
         }
     }
 
-    fn div_rem(&mut self, d: Reg, s1: Reg, s2: Reg, div_rem: DivRem, kind: Signedness) {
+    fn div_rem(&mut self, reg_size: RegSize, d: Reg, s1: Reg, s2: Reg, div_rem: DivRem, kind: Signedness) {
         // Unlike most other architectures RISC-V doesn't trap on division by zero
         // nor on division with overflow, and has well defined results in such cases.
 
         let label_divisor_is_zero = self.asm.forward_declare_label();
         let label_next = self.asm.forward_declare_label();
 
-        self.push(test((self.reg_size(), conv_reg(s2), conv_reg(s2))));
+        self.push(test((reg_size, conv_reg(s2), conv_reg(s2))));
         self.push(jcc_label8(Condition::Equal, label_divisor_is_zero));
 
         if matches!(kind, Signedness::Signed) {
             let label_normal = self.asm.forward_declare_label();
-            match self.reg_size() {
+            match reg_size {
                 RegSize::R32 => {
                     self.push(cmp((conv_reg(s1), imm32(i32::MIN as u32))));
                     self.push(jcc_label8(Condition::NotEqual, label_normal));
                     self.push(cmp((conv_reg(s2), imm32(-1_i32 as u32))));
+                }
+                RegSize::R64 => {
+                    self.push(mov_imm64(TMP_REG, i64::MIN as u64));
+                    self.push(cmp((RegSize::R64, conv_reg(s1), TMP_REG)));
                     self.push(jcc_label8(Condition::NotEqual, label_normal));
-                    match div_rem {
-                        DivRem::Div => self.mov(d, s1),
-                        DivRem::Rem => self.clear_reg(d),
-                    }
-                    self.push(jmp_label8(label_next));
+                    self.push(cmp((conv_reg(s2), imm64(-1))));
                 }
-                RegSize::R64 => todo!(),
             }
 
+            self.push(jcc_label8(Condition::NotEqual, label_normal));
+            match div_rem {
+                DivRem::Div => self.mov(d, s1),
+                DivRem::Rem => self.clear_reg(d),
+            }
+            self.push(jmp_label8(label_next));
+
             self.define_label(label_normal);
         }
 
@@ -425,17 +714,20 @@ impl<'a> Compiler<'a> {
         self.push(pop(rax));
 
         match kind {
-            Signedness::Unsigned => self.push(div(self.reg_size(), TMP_REG)),
+            Signedness::Unsigned => self.push(div(reg_size, TMP_REG)),
             Signedness::Signed => {
-                self.push(cdq());
-                self.push(idiv(self.reg_size(), TMP_REG))
+                match reg_size {
+                    RegSize::R32 => self.push(cdq()),
+                    RegSize::R64 => self.push(cqo()),
+                }
+                self.push(idiv(reg_size, TMP_REG))
             }
         }
 
         // Move the result to the temporary register.
         match div_rem {
-            DivRem::Div => self.push(mov(self.reg_size(), TMP_REG, rax)),
-            DivRem::Rem => self.push(mov(self.reg_size(), TMP_REG, rdx)),
+            DivRem::Div => self.push(mov(reg_size, TMP_REG, rax)),
+            DivRem::Rem => self.push(mov(reg_size, TMP_REG, rdx)),
         }
 
         // Restore the original registers.
@@ -443,7 +735,7 @@ impl<'a> Compiler<'a> {
         self.push(pop(rdx));
 
         // Move the output into the destination registers.
-        self.push(mov(self.reg_size(), conv_reg(d), TMP_REG));
+        self.push(mov(reg_size, conv_reg(d), TMP_REG));
 
         // Go to the next instruction.
         self.push(jmp_label8(label_next));
@@ -482,15 +774,21 @@ impl<'a> Compiler<'a> {
 
     fn save_registers_to_vmctx(&mut self) {
         let regs_base = self.load_vmctx_field_address(self.vmctx_regs_offset);
+        let size = Size::from(self.reg_size());
         for (nth, reg) in Reg::ALL.iter().copied().enumerate() {
-            self.push(store(Size::U32, reg_indirect(RegSize::R64, regs_base + nth as i32 * 4), conv_reg(reg)));
+            self.push(store(size, reg_indirect(RegSize::R64, regs_base + nth as i32 * 8), conv_reg(reg)));
         }
     }
 
     fn restore_registers_from_vmctx(&mut self) {
         let regs_base = self.load_vmctx_field_address(self.vmctx_regs_offset);
+        let kind = match self.reg_size() {
+            RegSize::R32 => LoadKind::U32,
+            RegSize::R64 => LoadKind::U64,
+        };
+
         for (nth, reg) in Reg::ALL.iter().copied().enumerate() {
-            self.push(load(LoadKind::U32, conv_reg(reg), reg_indirect(RegSize::R64, regs_base + nth as i32 * 4)));
+            self.push(load(kind, conv_reg(reg), reg_indirect(RegSize::R64, regs_base + nth as i32 * 8)));
         }
     }
 
@@ -528,7 +826,7 @@ impl<'a> Compiler<'a> {
         label
     }
 
-    pub(crate) fn emit_ecall_trampoline(&mut self) {
+    pub(crate) fn emit_ecall_trampoline(&mut self) -> Label {
         log::trace!("Emitting trampoline: ecall");
         self.define_label(self.ecall_label);
 
@@ -536,10 +834,23 @@ impl<'a> Compiler<'a> {
         self.save_registers_to_vmctx();
         self.push(mov_imm64(TMP_REG, self.address_table.syscall_hostcall));
         self.push(pop(rdi)); // Pop the ecall number as an argument.
+        self.push(load(LoadKind::U64, rsi, reg_indirect(RegSize::R64, rsp))); // Grab the return address.
+        self.push(lea(RegSize::R64, rsi, reg_indirect(RegSize::R64, rsi - 1))); // Make it point into the `ecalli` instruction.
         self.push(call(TMP_REG));
+
+        // A suspended hostcall is resumed from here, with the return address into the guest program pushed on the stack.
+        let resume_label = self.asm.create_label();
         self.restore_registers_from_vmctx();
+
+        if matches!(self.gas_metering, Some(GasMeteringKind::Sync)) {
+            // The host function might have consumed gas, so check whether we're still good to continue.
+            self.push(cmp((self.vmctx_field(self.vmctx_gas_offset), imm64(0))));
+            self.push(jcc_label32(Condition::Sign, self.out_of_gas_label));
+        }
+
         self.push(ret());
 
+        resume_label
     }
 
     pub(crate) fn emit_trace_trampoline(&mut self) {
@@ -550,12 +861,177 @@ impl<'a> Compiler<'a> {
         self.save_registers_to_vmctx();
         self.push(mov_imm64(TMP_REG, self.address_table.syscall_trace));
         self.push(pop(rdi)); // Pop the instruction number as an argument.
-        self.push(load(LoadKind::U64, rsi, reg_indirect(RegSize::R64, rsp - 8))); // Grab the return address.
+        self.push(load(LoadKind::U64, rsi, reg_indirect(RegSize::R64, rsp))); // Grab the return address.
         self.push(call(TMP_REG));
         self.restore_registers_from_vmctx();
         self.push(ret());
     }
 
+    pub(crate) fn emit_sbrk_trampoline(&mut self) {
+        log::trace!("Emitting trampoline: sbrk");
+        self.define_label(self.sbrk_label);
+
+        self.push(push(TMP_REG)); // Save the size.
+        self.save_registers_to_vmctx();
+        self.push(mov_imm64(TMP_REG, self.address_table.syscall_sbrk));
+        self.push(pop(rdi)); // Pop the size as an argument.
+        self.push(call(TMP_REG));
+        self.push(push(rax)); // Save the result.
+        self.restore_registers_from_vmctx();
+        self.push(pop(TMP_REG)); // Return the result in the temporary register.
+        self.push(ret());
+    }
+
+    /// Jumps to `label_invalid` unless the `count` bytes (in the temporary register) starting at `address`
+    /// fit entirely within one of the given memory `regions`.
+    fn emit_memory_range_check(&mut self, address: NativeReg, scratch: NativeReg, regions: &[BulkMemoryRegion], label_invalid: Label) {
+        let label_valid = self.asm.forward_declare_label();
+        for region in regions {
+            let label_next = self.asm.forward_declare_label();
+            match *region {
+                BulkMemoryRegion::Fixed(ref range) => {
+                    if range.is_empty() {
+                        continue;
+                    }
+
+                    self.push(cmp((address, imm32(range.start))));
+                    self.push(jcc_label8(Condition::Below, label_next));
+                    self.push(mov_imm(scratch, imm32(range.end)));
+                }
+                BulkMemoryRegion::Heap { address: heap_address } => {
+                    // The heap can be grown with `sbrk`, so it ends at the current heap top rounded up to the next page.
+                    self.push(cmp((address, imm32(heap_address))));
+                    self.push(jcc_label8(Condition::Below, label_next));
+                    self.push(load(LoadKind::U32, scratch, self.vmctx_field(self.vmctx_heap_top_offset)));
+                    self.push(add((scratch, imm32(VM_PAGE_SIZE - 1))));
+                    self.push(and((scratch, imm32(!(VM_PAGE_SIZE - 1)))));
+                }
+            }
+
+            self.push(sub((RegSize::R32, scratch, address)));
+            self.push(jcc_label8(Condition::BelowOrEqual, label_next));
+            self.push(cmp((RegSize::R32, TMP_REG, scratch)));
+            self.push(jcc_label32(Condition::BelowOrEqual, label_valid));
+            self.define_label(label_next);
+        }
+
+        self.push(jmp_label32(label_invalid));
+        self.define_label(label_valid);
+    }
+
+    /// Turns a guest address into a native address which can be used by the string instructions.
+    fn emit_guest_address_to_native(&mut self, reg: NativeReg) {
+        match self.sandbox_kind {
+            // The guest memory is mapped at the very beginning of the address space.
+            SandboxKind::Linux => {},
+            SandboxKind::Generic => {
+                self.push(lea(RegSize::R64, reg, base_index(RegSize::R64, GENERIC_SANDBOX_MEMORY_REG, reg)));
+            }
+        }
+    }
+
+    pub(crate) fn emit_memcpy_trampoline(&mut self, memory_config: &GuestMemoryConfig) {
+        log::trace!("Emitting trampoline: memcpy");
+        self.define_label(self.memcpy_label);
+
+        let heap = BulkMemoryRegion::Heap { address: memory_config.heap_address() };
+        let readable_regions = [BulkMemoryRegion::Fixed(memory_config.ro_data_range()), heap.clone(), BulkMemoryRegion::Fixed(memory_config.stack_range())];
+        let writable_regions = [heap, BulkMemoryRegion::Fixed(memory_config.stack_range())];
+
+        let label_invalid = self.asm.forward_declare_label();
+        let label_done = self.asm.forward_declare_label();
+        let label_forward = self.asm.forward_declare_label();
+
+        // The arguments were passed on the stack, so every guest register has to be preserved.
+        self.push(push(rdi));
+        self.push(push(rsi));
+        self.push(push(rdx));
+        self.push(load(LoadKind::U32, TMP_REG, reg_indirect(RegSize::R64, rsp + 48))); // Grab the count.
+        self.push(test((RegSize::R32, TMP_REG, TMP_REG)));
+        self.push(jcc_label32(Condition::Equal, label_done));
+        self.push(load(LoadKind::U32, rdi, reg_indirect(RegSize::R64, rsp + 32))); // Grab the destination.
+        self.push(load(LoadKind::U32, rsi, reg_indirect(RegSize::R64, rsp + 40))); // Grab the source.
+        self.emit_memory_range_check(rdi, rdx, &writable_regions, label_invalid);
+        self.emit_memory_range_check(rsi, rdx, &readable_regions, label_invalid);
+
+        // If the destination starts inside of the source then copy backwards, so that the source isn't clobbered before it's read.
+        self.push(mov(RegSize::R32, rdx, rdi));
+        self.push(sub((RegSize::R32, rdx, rsi)));
+        self.push(cmp((RegSize::R32, rdx, TMP_REG)));
+        self.emit_guest_address_to_native(rdi);
+        self.emit_guest_address_to_native(rsi);
+        self.push(jcc_label8(Condition::AboveOrEqual, label_forward));
+        self.push(lea(RegSize::R64, rdi, base_index(RegSize::R64, rdi, TMP_REG)));
+        self.push(lea(RegSize::R64, rdi, reg_indirect(RegSize::R64, rdi - 1)));
+        self.push(lea(RegSize::R64, rsi, base_index(RegSize::R64, rsi, TMP_REG)));
+        self.push(lea(RegSize::R64, rsi, reg_indirect(RegSize::R64, rsi - 1)));
+        self.push(std());
+        self.push(rep_movsb());
+        self.push(cld());
+        self.push(jmp_label8(label_done));
+
+        self.define_label(label_forward);
+        self.push(rep_movsb());
+
+        self.define_label(label_done);
+        self.push(pop(rdx));
+        self.push(pop(rsi));
+        self.push(pop(rdi));
+        self.push(ret());
+
+        self.define_label(label_invalid);
+        self.push(pop(rdx));
+        self.push(pop(rsi));
+        self.push(pop(rdi));
+        self.push(lea(RegSize::R64, rsp, reg_indirect(RegSize::R64, rsp + 32))); // Drop the return address and the arguments so that the stack is aligned as if we've trapped directly.
+        self.push(jmp_label32(self.trap_label));
+    }
+
+    pub(crate) fn emit_memset_trampoline(&mut self, memory_config: &GuestMemoryConfig) {
+        log::trace!("Emitting trampoline: memset");
+        self.define_label(self.memset_label);
+
+        let heap = BulkMemoryRegion::Heap { address: memory_config.heap_address() };
+        let writable_regions = [heap, BulkMemoryRegion::Fixed(memory_config.stack_range())];
+
+        let label_invalid = self.asm.forward_declare_label();
+        let label_done = self.asm.forward_declare_label();
+
+        // The arguments were passed on the stack, so every guest register has to be preserved.
+        self.push(push(rdi));
+        self.push(push(rax));
+        self.push(push(rdx));
+        self.push(load(LoadKind::U32, TMP_REG, reg_indirect(RegSize::R64, rsp + 48))); // Grab the count.
+        self.push(test((RegSize::R32, TMP_REG, TMP_REG)));
+        self.push(jcc_label32(Condition::Equal, label_done));
+        self.push(load(LoadKind::U32, rdi, reg_indirect(RegSize::R64, rsp + 32))); // Grab the destination.
+        self.emit_memory_range_check(rdi, rdx, &writable_regions, label_invalid);
+        self.push(load(LoadKind::U32, rax, reg_indirect(RegSize::R64, rsp + 40))); // Grab the value.
+        self.emit_guest_address_to_native(rdi);
+        self.push(rep_stosb());
+
+        self.define_label(label_done);
+        self.push(pop(rdx));
+        self.push(pop(rax));
+        self.push(pop(rdi));
+        self.push(ret());
+
+        self.define_label(label_invalid);
+        self.push(pop(rdx));
+        self.push(pop(rax));
+        self.push(pop(rdi));
+        self.push(lea(RegSize::R64, rsp, reg_indirect(RegSize::R64, rsp + 32))); // Drop the return address and the arguments so that the stack is aligned as if we've trapped directly.
+        self.push(jmp_label32(self.trap_label));
+    }
+
+    pub(crate) fn emit_invalid_jump_trampoline(&mut self) {
+        log::trace!("Emitting trampoline: invalid jump");
+        self.define_label(self.invalid_jump_label);
+
+        // Every jump table entry which doesn't point to a valid basic block points here.
+        self.push(ud2());
+    }
+
     pub(crate) fn emit_trap_trampoline(&mut self) {
         log::trace!("Emitting trampoline: trap");
         self.define_label(self.trap_label);
@@ -565,6 +1041,29 @@ impl<'a> Compiler<'a> {
         self.push(jmp(TMP_REG));
     }
 
+    pub(crate) fn emit_out_of_gas_trampoline(&mut self) {
+        log::trace!("Emitting trampoline: out of gas");
+        self.define_label(self.out_of_gas_label);
+
+        // Give the host a chance to refill the gas.
+        self.save_registers_to_vmctx();
+        self.push(mov_imm64(TMP_REG, self.address_table.syscall_hostcall));
+        self.push(mov_imm(rdi, imm32(polkavm_common::HOSTCALL_OUT_OF_GAS)));
+        self.push(load(LoadKind::U64, rsi, reg_indirect(RegSize::R64, rsp))); // Grab the return address.
+        self.push(lea(RegSize::R64, rsi, reg_indirect(RegSize::R64, rsi - 1))); // Make it point into the gas metering stub.
+        self.push(call(TMP_REG));
+        self.restore_registers_from_vmctx();
+
+        // If we still don't have enough gas then there's nothing more we can do.
+        let label_refilled = self.asm.forward_declare_label();
+        self.push(cmp((self.vmctx_field(self.vmctx_gas_offset), imm64(0))));
+        self.push(jcc_label8(Condition::NotSign, label_refilled));
+        self.push(lea(RegSize::R64, rsp, reg_indirect(RegSize::R64, rsp + 8))); // Drop the return address so that the stack is aligned as if we've trapped directly.
+        self.push(jmp_label32(self.trap_label));
+        self.define_label(label_refilled);
+        self.push(ret());
+    }
+
     #[cold]
     pub(crate) fn trace_execution(&mut self, nth_instruction: usize) {
         self.push(mov_imm(TMP_REG, imm32(nth_instruction as u32)));
@@ -574,11 +1073,50 @@ impl<'a> Compiler<'a> {
     pub(crate) fn emit_gas_metering_stub(&mut self, kind: GasMeteringKind) {
         self.push(sub((self.vmctx_field(self.vmctx_gas_offset), imm64(i32::MAX))));
         if matches!(kind, GasMeteringKind::Sync) {
+            let label_continue = self.asm.forward_declare_label();
             self.push(cmp((self.vmctx_field(self.vmctx_gas_offset), imm64(0))));
-            self.push(jcc_label32(Condition::Sign, self.trap_label));
+            self.push(jcc_label8(Condition::NotSign, label_continue));
+            self.push(call_label32(self.out_of_gas_label));
+            self.define_label(label_continue);
         }
     }
 
+    pub(crate) fn emit_basic_block_counter(&mut self) {
+        self.push(inc(Size::U64, self.vmctx_field(self.vmctx_basic_blocks_executed_offset)));
+    }
+
+    /// Charges the per-byte gas cost of a `memcpy`, a `memset` or an `sbrk`.
+    fn emit_per_byte_gas_metering(&mut self, count: Reg, byte_cost: u32) {
+        let Some(kind) = self.gas_metering else { return };
+        if byte_cost == 0 {
+            return;
+        }
+
+        self.push(mov(RegSize::R32, TMP_REG, conv_reg(count)));
+        if byte_cost != 1 {
+            self.push(imul_imm(RegSize::R64, TMP_REG, TMP_REG, byte_cost as i32));
+        }
+
+        self.push(sub((RegSize::R64, self.vmctx_field(self.vmctx_gas_offset), TMP_REG)));
+        if matches!(kind, GasMeteringKind::Sync) {
+            let label_continue = self.asm.forward_declare_label();
+            self.push(jcc_label8(Condition::NotSign, label_continue));
+            self.push(call_label32(self.out_of_gas_label));
+            self.define_label(label_continue);
+        }
+    }
+
+    fn bulk_memory_operation(&mut self, dst: Reg, src: Reg, count: Reg, label: Label) {
+        self.emit_per_byte_gas_metering(count, self.bulk_memory_byte_cost);
+
+        // Pass the arguments on the stack so that the trampoline doesn't have to clobber any registers.
+        self.push(push(conv_reg(count)));
+        self.push(push(conv_reg(src)));
+        self.push(push(conv_reg(dst)));
+        self.push(call_label32(label));
+        self.push(lea(RegSize::R64, rsp, reg_indirect(RegSize::R64, rsp + 24)));
+    }
+
     pub(crate) fn emit_weight(&mut self, offset: usize, cost: u32) {
         let length = sub((self.vmctx_field(self.vmctx_gas_offset), imm64(i32::MAX))).len();
         let xs = cost.to_le_bytes();
@@ -601,7 +1139,8 @@ impl<'a> Compiler<'a> {
             SandboxKind::Linux => {
                 use polkavm_assembler::amd64::{SegReg, Scale};
 
-                let target = if offset != 0 || ra == Some(base) {
+                // The target address is always truncated to 32-bits.
+                let target = if offset != 0 || ra == Some(base) || self.is_64_bit {
                     self.push(lea(RegSize::R32, TMP_REG, reg_indirect(RegSize::R32, conv_reg(base) + offset as i32)));
                     TMP_REG
                 } else {
@@ -618,6 +1157,10 @@ impl<'a> Compiler<'a> {
                 // TODO: This also could be more efficient.
                 self.push(lea_rip_label(TMP_REG, self.jump_table_label));
                 self.push(push(conv_reg(base)));
+                if self.is_64_bit {
+                    // Clear the upper 32-bits of the address.
+                    self.push(mov(RegSize::R32, conv_reg(base), conv_reg(base)));
+                }
                 self.push(shl_imm(RegSize::R64, conv_reg(base), 3));
                 if offset > 0 {
                     let offset = offset.wrapping_mul(8);
@@ -644,8 +1187,8 @@ impl<'a> InstructionVisitor for VisitorWrapper<'a, Compiler<'a>> {
 
     #[inline(always)]
     fn trap(&mut self) -> Self::ReturnTy {
-        let trap_label = self.trap_label;
-        self.push(jmp_label32(trap_label));
+        // This will trigger a SIGILL, which will be caught by the sandbox's signal handler.
+        self.push(ud2());
         self.start_new_basic_block();
     }
 
@@ -693,32 +1236,38 @@ impl<'a> InstructionVisitor for VisitorWrapper<'a, Compiler<'a>> {
 
     #[inline(always)]
     fn shift_logical_right(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.shift(d, s1, s2, ShiftKind::LogicalRight);
+        let reg_size = self.reg_size();
+        self.shift(reg_size, d, s1, s2, ShiftKind::LogicalRight);
     }
 
     #[inline(always)]
     fn shift_arithmetic_right(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.shift(d, s1, s2, ShiftKind::ArithmeticRight);
+        let reg_size = self.reg_size();
+        self.shift(reg_size, d, s1, s2, ShiftKind::ArithmeticRight);
     }
 
     #[inline(always)]
     fn shift_logical_left(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.shift(d, s1, s2, ShiftKind::LogicalLeft);
+        let reg_size = self.reg_size();
+        self.shift(reg_size, d, s1, s2, ShiftKind::LogicalLeft);
     }
 
     #[inline(always)]
     fn shift_logical_right_imm_alt(&mut self, d: Reg, s2: Reg, s1: u32) -> Self::ReturnTy {
-        self.shift(d, s1, s2, ShiftKind::LogicalRight);
+        let reg_size = self.reg_size();
+        self.shift(reg_size, d, s1, s2, ShiftKind::LogicalRight);
     }
 
     #[inline(always)]
     fn shift_arithmetic_right_imm_alt(&mut self, d: Reg, s2: Reg, s1: u32) -> Self::ReturnTy  {
-        self.shift(d, s1, s2, ShiftKind::ArithmeticRight);
+        let reg_size = self.reg_size();
+        self.shift(reg_size, d, s1, s2, ShiftKind::ArithmeticRight);
     }
 
     #[inline(always)]
     fn shift_logical_left_imm_alt(&mut self, d: Reg, s2: Reg, s1: u32) -> Self::ReturnTy  {
-        self.shift(d, s1, s2, ShiftKind::LogicalLeft);
+        let reg_size = self.reg_size();
+        self.shift(reg_size, d, s1, s2, ShiftKind::LogicalLeft);
     }
 
     #[inline(always)]
@@ -808,20 +1357,22 @@ impl<'a> InstructionVisitor for VisitorWrapper<'a, Compiler<'a>> {
 
     #[inline(always)]
     fn negate_and_add_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        let reg_size = self.reg_size();
         if d == s1 {
             // d = -d + s2
-            self.push(neg(RegSize::R32, conv_reg(d)));
+            self.push(neg(reg_size, conv_reg(d)));
             if s2 != 0 {
-                self.push(add((conv_reg(d), imm32(s2))));
+                let s2 = self.imm(s2);
+                self.push(add((conv_reg(d), s2)));
             }
         } else {
             // d = -s1 + s2  =>  d = s2 - s1
             if s2 == 0 {
                 self.mov(d, s1);
-                self.push(neg(RegSize::R32, conv_reg(d)));
+                self.push(neg(reg_size, conv_reg(d)));
             } else {
-                self.push(mov_imm(conv_reg(d), imm32(s2)));
-                self.push(sub((RegSize::R32, conv_reg(d), conv_reg(s1))));
+                self.load_immediate(d, s2);
+                self.push(sub((reg_size, conv_reg(d), conv_reg(s1))));
             }
         }
     }
@@ -844,11 +1395,17 @@ impl<'a> InstructionVisitor for VisitorWrapper<'a, Compiler<'a>> {
 
     #[inline(always)]
     fn mul_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
-        self.push(imul_imm(RegSize::R32, conv_reg(d), conv_reg(s1), s2 as i32));
+        let reg_size = self.reg_size();
+        self.push(imul_imm(reg_size, conv_reg(d), conv_reg(s1), s2 as i32));
     }
 
     #[inline(always)]
     fn mul_upper_signed_signed(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        if self.is_64_bit {
+            self.mul_upper_64(d, s1, s2, Signedness::Signed, Signedness::Signed);
+            return;
+        }
+
         self.push(movsxd_32_to_64(TMP_REG, conv_reg(s2)));
         self.push(movsxd_32_to_64(conv_reg(d), conv_reg(s1)));
         self.push(imul(RegSize::R64, conv_reg(d), TMP_REG));
@@ -857,6 +1414,11 @@ impl<'a> InstructionVisitor for VisitorWrapper<'a, Compiler<'a>> {
 
     #[inline(always)]
     fn mul_upper_signed_signed_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        if self.is_64_bit {
+            self.mul_upper_64(d, s1, s2, Signedness::Signed, Signedness::Signed);
+            return;
+        }
+
         self.push(mov_imm(TMP_REG, imm64(s2 as i32)));
         self.push(movsxd_32_to_64(conv_reg(d), conv_reg(s1)));
         self.push(imul(RegSize::R64, conv_reg(d), TMP_REG));
@@ -865,6 +1427,11 @@ impl<'a> InstructionVisitor for VisitorWrapper<'a, Compiler<'a>> {
 
     #[inline(always)]
     fn mul_upper_unsigned_unsigned(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        if self.is_64_bit {
+            self.mul_upper_64(d, s1, s2, Signedness::Unsigned, Signedness::Unsigned);
+            return;
+        }
+
         if d == s1 {
             // d = d * s2
             self.push(imul(RegSize::R64, conv_reg(d), conv_reg(s2)));
@@ -882,6 +1449,11 @@ impl<'a> InstructionVisitor for VisitorWrapper<'a, Compiler<'a>> {
 
     #[inline(always)]
     fn mul_upper_unsigned_unsigned_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        if self.is_64_bit {
+            self.mul_upper_64(d, s1, s2, Signedness::Unsigned, Signedness::Unsigned);
+            return;
+        }
+
         self.push(mov_imm(TMP_REG, imm32(s2)));
         if d != s1 {
             self.push(mov(RegSize::R32, conv_reg(d), conv_reg(s1)));
@@ -906,6 +1478,11 @@ impl<'a> InstructionVisitor for VisitorWrapper<'a, Compiler<'a>> {
         //   3) multiply,
         //   4) return the upper 32-bits.
 
+        if self.is_64_bit {
+            self.mul_upper_64(d, s1, s2, Signedness::Signed, Signedness::Unsigned);
+            return;
+        }
+
         if d == s2 {
             // d = s1 * d
             self.push(mov(RegSize::R32, TMP_REG, conv_reg(s2)));
@@ -922,37 +1499,112 @@ impl<'a> InstructionVisitor for VisitorWrapper<'a, Compiler<'a>> {
 
     #[inline(always)]
     fn div_unsigned(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.div_rem(d, s1, s2, DivRem::Div, Signedness::Unsigned);
+        let reg_size = self.reg_size();
+        self.div_rem(reg_size, d, s1, s2, DivRem::Div, Signedness::Unsigned);
     }
 
     #[inline(always)]
     fn div_signed(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.div_rem(d, s1, s2, DivRem::Div, Signedness::Signed);
+        let reg_size = self.reg_size();
+        self.div_rem(reg_size, d, s1, s2, DivRem::Div, Signedness::Signed);
     }
 
     #[inline(always)]
     fn rem_unsigned(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.div_rem(d, s1, s2, DivRem::Rem, Signedness::Unsigned);
+        let reg_size = self.reg_size();
+        self.div_rem(reg_size, d, s1, s2, DivRem::Rem, Signedness::Unsigned);
     }
 
     #[inline(always)]
     fn rem_signed(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.div_rem(d, s1, s2, DivRem::Rem, Signedness::Signed);
+        let reg_size = self.reg_size();
+        self.div_rem(reg_size, d, s1, s2, DivRem::Rem, Signedness::Signed);
+    }
+
+    #[inline(always)]
+    fn and_inverted(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.bitwise_inverted(d, s1, s2, BitwiseInvertedKind::And);
+    }
+
+    #[inline(always)]
+    fn or_inverted(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.bitwise_inverted(d, s1, s2, BitwiseInvertedKind::Or);
+    }
+
+    #[inline(always)]
+    fn xnor(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        // d = !(s1 ^ s2)
+        let reg_size = self.reg_size();
+        self.xor(d, s1, s2);
+        self.push(not(reg_size, conv_reg(d)));
+    }
+
+    #[inline(always)]
+    fn maximum(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.min_max(d, s1, s2, MinMax::MaxSigned);
+    }
+
+    #[inline(always)]
+    fn maximum_unsigned(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.min_max(d, s1, s2, MinMax::MaxUnsigned);
+    }
+
+    #[inline(always)]
+    fn minimum(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.min_max(d, s1, s2, MinMax::MinSigned);
+    }
+
+    #[inline(always)]
+    fn minimum_unsigned(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.min_max(d, s1, s2, MinMax::MinUnsigned);
+    }
+
+    #[inline(always)]
+    fn rotate_left(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        let reg_size = self.reg_size();
+        self.shift(reg_size, d, s1, s2, ShiftKind::RotateLeft);
+    }
+
+    #[inline(always)]
+    fn rotate_right(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        let reg_size = self.reg_size();
+        self.shift(reg_size, d, s1, s2, ShiftKind::RotateRight);
     }
 
     #[inline(always)]
     fn shift_logical_right_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
-        self.shift_imm(d, s1, s2, ShiftKind::LogicalRight);
+        let reg_size = self.reg_size();
+        self.shift_imm(reg_size, d, s1, s2, ShiftKind::LogicalRight);
     }
 
     #[inline(always)]
     fn shift_arithmetic_right_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
-        self.shift_imm(d, s1, s2, ShiftKind::ArithmeticRight);
+        let reg_size = self.reg_size();
+        self.shift_imm(reg_size, d, s1, s2, ShiftKind::ArithmeticRight);
     }
 
     #[inline(always)]
     fn shift_logical_left_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
-        self.shift_imm(d, s1, s2, ShiftKind::LogicalLeft);
+        let reg_size = self.reg_size();
+        self.shift_imm(reg_size, d, s1, s2, ShiftKind::LogicalLeft);
+    }
+
+    #[inline(always)]
+    fn rotate_right_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        let reg_size = self.reg_size();
+        self.shift_imm(reg_size, d, s1, s2, ShiftKind::RotateRight);
+    }
+
+    #[inline(always)]
+    fn rotate_right_imm_alt(&mut self, d: Reg, s2: Reg, s1: u32) -> Self::ReturnTy {
+        let reg_size = self.reg_size();
+        self.shift(reg_size, d, s1, s2, ShiftKind::RotateRight);
+    }
+
+    #[inline(always)]
+    fn rotate_left_imm_alt(&mut self, d: Reg, s2: Reg, s1: u32) -> Self::ReturnTy {
+        let reg_size = self.reg_size();
+        self.shift(reg_size, d, s1, s2, ShiftKind::RotateLeft);
     }
 
     #[inline(always)]
@@ -962,7 +1614,8 @@ impl<'a> InstructionVisitor for VisitorWrapper<'a, Compiler<'a>> {
         }
 
         // d = s1 | s2
-        self.push(or((conv_reg(d), imm32(s2))));
+        let s2 = self.imm(s2);
+        self.push(or((conv_reg(d), s2)));
     }
 
     #[inline(always)]
@@ -972,7 +1625,8 @@ impl<'a> InstructionVisitor for VisitorWrapper<'a, Compiler<'a>> {
         }
 
         // d = s1 & s2
-        self.push(and((conv_reg(d), imm32(s2))));
+        let s2 = self.imm(s2);
+        self.push(and((conv_reg(d), s2)));
     }
 
     #[inline(always)]
@@ -984,7 +1638,8 @@ impl<'a> InstructionVisitor for VisitorWrapper<'a, Compiler<'a>> {
 
         if s2 != !0 {
             // d = s1 ^ s2
-            self.push(xor((conv_reg(d), imm32(s2))));
+            let s2 = self.imm(s2);
+            self.push(xor((conv_reg(d), s2)));
         } else {
             // d = s1 ^ 0xfffffff
             self.push(not(reg_size, conv_reg(d)));
@@ -1001,6 +1656,75 @@ impl<'a> InstructionVisitor for VisitorWrapper<'a, Compiler<'a>> {
         self.mov(d, s);
     }
 
+    #[inline(always)]
+    fn count_leading_zero_bits(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        let reg_size = self.reg_size();
+        self.count_leading_zeros(reg_size, d, s);
+    }
+
+    #[inline(always)]
+    fn count_trailing_zero_bits(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        let reg_size = self.reg_size();
+        self.count_trailing_zeros(reg_size, d, s);
+    }
+
+    #[inline(always)]
+    fn count_set_bits(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        let reg_size = self.reg_size();
+        self.count_ones(reg_size, d, s);
+    }
+
+    #[inline(always)]
+    fn sign_extend_8(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        let reg_size = self.reg_size();
+        self.push(movsx_8(reg_size, conv_reg(d), conv_reg(s)));
+    }
+
+    #[inline(always)]
+    fn sign_extend_16(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        let reg_size = self.reg_size();
+        self.push(movsx_16(reg_size, conv_reg(d), conv_reg(s)));
+    }
+
+    #[inline(always)]
+    fn zero_extend_16(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        let reg_size = self.reg_size();
+        self.push(movzx_16(reg_size, conv_reg(d), conv_reg(s)));
+    }
+
+    #[inline(always)]
+    fn reverse_byte(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        let reg_size = self.reg_size();
+        if d != s {
+            self.mov(d, s);
+        }
+
+        self.push(bswap(reg_size, conv_reg(d)));
+    }
+
+    #[inline(always)]
+    fn sbrk(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        let sbrk_label = self.sbrk_label;
+        let sbrk_byte_cost = self.sbrk_byte_cost;
+        let label_next = self.asm.forward_declare_label();
+        if self.is_64_bit {
+            // The heap can't grow by more than 4GB, so this always fails.
+            let label_in_range = self.asm.forward_declare_label();
+            self.push(mov(RegSize::R64, TMP_REG, conv_reg(s)));
+            self.push(shr_imm(RegSize::R64, TMP_REG, 32));
+            self.push(jcc_label8(Condition::Equal, label_in_range));
+            self.clear_reg(d);
+            self.push(jmp_label8(label_next));
+            self.define_label(label_in_range);
+        }
+
+        self.emit_per_byte_gas_metering(s, sbrk_byte_cost);
+        self.push(mov(RegSize::R32, TMP_REG, conv_reg(s)));
+        self.push(call_label32(sbrk_label));
+        self.push(mov(RegSize::R32, conv_reg(d), TMP_REG));
+        self.define_label(label_next);
+    }
+
     #[inline(always)]
     fn cmov_if_zero(&mut self, d: Reg, s: Reg, c: Reg) -> Self::ReturnTy {
         self.cmov(d, s, c, Condition::Equal);
@@ -1011,6 +1735,18 @@ impl<'a> InstructionVisitor for VisitorWrapper<'a, Compiler<'a>> {
         self.cmov(d, s, c, Condition::NotEqual);
     }
 
+    #[inline(always)]
+    fn memcpy(&mut self, dst: Reg, src: Reg, count: Reg) -> Self::ReturnTy {
+        let memcpy_label = self.memcpy_label;
+        self.bulk_memory_operation(dst, src, count, memcpy_label);
+    }
+
+    #[inline(always)]
+    fn memset(&mut self, dst: Reg, value: Reg, count: Reg) -> Self::ReturnTy {
+        let memset_label = self.memset_label;
+        self.bulk_memory_operation(dst, value, count, memset_label);
+    }
+
     #[inline(always)]
     fn add_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
         let reg_size = self.reg_size();
@@ -1020,7 +1756,10 @@ impl<'a> InstructionVisitor for VisitorWrapper<'a, Compiler<'a>> {
             // d = d + 1
             (_, _, 1) if d == s1 => self.push(inc(reg_size, d)),
             // d = d + s2
-            (_, _, _) if d == s1 => self.push(add((d, imm32(s2)))),
+            (_, _, _) if d == s1 => {
+                let s2 = self.imm(s2);
+                self.push(add((d, s2)));
+            },
             // d = s1 + s2
             (_, _, _) => {
                 self.push(lea(reg_size, d, reg_indirect(reg_size, s1 + s2 as i32)));
@@ -1243,4 +1982,197 @@ impl<'a> InstructionVisitor for VisitorWrapper<'a, Compiler<'a>> {
     fn call_indirect(&mut self, ra: Reg, base: Reg, offset: u32) -> Self::ReturnTy {
         self.indirect_jump_or_call(Some(ra), base, offset)
     }
+
+    #[inline(always)]
+    fn load_i32(&mut self, dst: Reg, offset: u32) -> Self::ReturnTy {
+        self.load(dst, None, offset, LoadKind::I32);
+    }
+
+    #[inline(always)]
+    fn load_u64(&mut self, dst: Reg, offset: u32) -> Self::ReturnTy {
+        self.load(dst, None, offset, LoadKind::U64);
+    }
+
+    #[inline(always)]
+    fn store_u64(&mut self, src: Reg, offset: u32) -> Self::ReturnTy {
+        self.store(src, None, offset, Size::U64);
+    }
+
+    #[inline(always)]
+    fn store_imm_u64(&mut self, value: u32, offset: u32) -> Self::ReturnTy {
+        self.store(value, None, offset, Size::U64);
+    }
+
+    #[inline(always)]
+    fn store_imm_indirect_u64(&mut self, base: Reg, offset: u32, value: u32) -> Self::ReturnTy {
+        self.store(value, Some(base), offset, Size::U64);
+    }
+
+    #[inline(always)]
+    fn load_indirect_i32(&mut self, dst: Reg, base: Reg, offset: u32) -> Self::ReturnTy {
+        self.load(dst, Some(base), offset, LoadKind::I32);
+    }
+
+    #[inline(always)]
+    fn load_indirect_u64(&mut self, dst: Reg, base: Reg, offset: u32) -> Self::ReturnTy {
+        self.load(dst, Some(base), offset, LoadKind::U64);
+    }
+
+    #[inline(always)]
+    fn store_indirect_u64(&mut self, src: Reg, base: Reg, offset: u32) -> Self::ReturnTy {
+        self.store(src, Some(base), offset, Size::U64);
+    }
+
+    #[inline(always)]
+    fn add_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        if d == s1 {
+            self.push(add((conv_reg(d), imm32(s2))));
+        } else {
+            self.push(lea(RegSize::R32, conv_reg(d), reg_indirect(RegSize::R32, conv_reg(s1) + s2 as i32)));
+        }
+
+        self.sign_extend_32(d);
+    }
+
+    #[inline(always)]
+    fn shift_logical_left_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        self.shift_imm(RegSize::R32, d, s1, s2, ShiftKind::LogicalLeft);
+        self.sign_extend_32(d);
+    }
+
+    #[inline(always)]
+    fn shift_logical_right_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        self.shift_imm(RegSize::R32, d, s1, s2, ShiftKind::LogicalRight);
+        self.sign_extend_32(d);
+    }
+
+    #[inline(always)]
+    fn shift_arithmetic_right_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        self.shift_imm(RegSize::R32, d, s1, s2, ShiftKind::ArithmeticRight);
+        self.sign_extend_32(d);
+    }
+
+    #[inline(always)]
+    fn rotate_right_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        self.shift_imm(RegSize::R32, d, s1, s2, ShiftKind::RotateRight);
+        self.sign_extend_32(d);
+    }
+
+    #[inline(always)]
+    fn add_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        if d == s2 {
+            // d = s1 + d
+            self.push(add((RegSize::R32, conv_reg(d), conv_reg(s1))));
+        } else {
+            // d = s1 + s2
+            if d != s1 {
+                self.mov(d, s1);
+            }
+            self.push(add((RegSize::R32, conv_reg(d), conv_reg(s2))));
+        }
+
+        self.sign_extend_32(d);
+    }
+
+    #[inline(always)]
+    fn sub_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        if d == s2 {
+            // d = s1 - d
+            self.push(neg(RegSize::R32, conv_reg(d)));
+            self.push(add((RegSize::R32, conv_reg(d), conv_reg(s1))));
+        } else {
+            // d = s1 - s2
+            if d != s1 {
+                self.mov(d, s1);
+            }
+            self.push(sub((RegSize::R32, conv_reg(d), conv_reg(s2))));
+        }
+
+        self.sign_extend_32(d);
+    }
+
+    #[inline(always)]
+    fn mul_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        if d == s2 {
+            // d = s1 * d
+            self.push(imul(RegSize::R32, conv_reg(d), conv_reg(s1)));
+        } else {
+            // d = s1 * s2
+            if d != s1 {
+                self.mov(d, s1);
+            }
+            self.push(imul(RegSize::R32, conv_reg(d), conv_reg(s2)));
+        }
+
+        self.sign_extend_32(d);
+    }
+
+    #[inline(always)]
+    fn shift_logical_left_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.shift(RegSize::R32, d, s1, s2, ShiftKind::LogicalLeft);
+        self.sign_extend_32(d);
+    }
+
+    #[inline(always)]
+    fn shift_logical_right_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.shift(RegSize::R32, d, s1, s2, ShiftKind::LogicalRight);
+        self.sign_extend_32(d);
+    }
+
+    #[inline(always)]
+    fn shift_arithmetic_right_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.shift(RegSize::R32, d, s1, s2, ShiftKind::ArithmeticRight);
+        self.sign_extend_32(d);
+    }
+
+    #[inline(always)]
+    fn div_unsigned_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.div_rem(RegSize::R32, d, s1, s2, DivRem::Div, Signedness::Unsigned);
+        self.sign_extend_32(d);
+    }
+
+    #[inline(always)]
+    fn div_signed_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.div_rem(RegSize::R32, d, s1, s2, DivRem::Div, Signedness::Signed);
+        self.sign_extend_32(d);
+    }
+
+    #[inline(always)]
+    fn rem_unsigned_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.div_rem(RegSize::R32, d, s1, s2, DivRem::Rem, Signedness::Unsigned);
+        self.sign_extend_32(d);
+    }
+
+    #[inline(always)]
+    fn rem_signed_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.div_rem(RegSize::R32, d, s1, s2, DivRem::Rem, Signedness::Signed);
+        self.sign_extend_32(d);
+    }
+
+    #[inline(always)]
+    fn rotate_left_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.shift(RegSize::R32, d, s1, s2, ShiftKind::RotateLeft);
+        self.sign_extend_32(d);
+    }
+
+    #[inline(always)]
+    fn rotate_right_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.shift(RegSize::R32, d, s1, s2, ShiftKind::RotateRight);
+        self.sign_extend_32(d);
+    }
+
+    #[inline(always)]
+    fn count_leading_zero_bits_32(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.count_leading_zeros(RegSize::R32, d, s);
+    }
+
+    #[inline(always)]
+    fn count_trailing_zero_bits_32(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.count_trailing_zeros(RegSize::R32, d, s);
+    }
+
+    #[inline(always)]
+    fn count_set_bits_32(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.count_ones(RegSize::R32, d, s);
+    }
 }
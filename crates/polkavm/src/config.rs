@@ -1,4 +1,5 @@
 use crate::error::{bail, Error};
+use polkavm_common::program::Opcode;
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum BackendKind {
@@ -99,6 +100,7 @@ pub struct Config {
     pub(crate) trace_execution: bool,
     pub(crate) allow_insecure: bool,
     pub(crate) worker_count: usize,
+    pub(crate) max_nested_call_depth: u32,
 }
 
 impl Default for Config {
@@ -130,6 +132,7 @@ impl Config {
             trace_execution: false,
             allow_insecure: false,
             worker_count: 2,
+            max_nested_call_depth: 16,
         }
     }
 
@@ -229,6 +232,21 @@ impl Config {
         self.worker_count = value;
         self
     }
+
+    /// Sets how deeply host functions can recursively call back into the program with [`Caller::call_export`](crate::Caller::call_export).
+    ///
+    /// Setting this to `0` disallows such calls completely.
+    ///
+    /// Default: `16`
+    pub fn set_max_nested_call_depth(&mut self, value: u32) -> &mut Self {
+        self.max_nested_call_depth = value;
+        self
+    }
+
+    /// Returns the maximum nesting depth of calls made from within host functions.
+    pub fn max_nested_call_depth(&self) -> u32 {
+        self.max_nested_call_depth
+    }
 }
 
 /// The type of gas metering.
@@ -249,10 +267,123 @@ pub enum GasMeteringKind {
     Async,
 }
 
+/// The model used to calculate how much gas the program consumes.
+///
+/// The cost of a basic block is the sum of the costs of all of its instructions
+/// plus the cost of entering a basic block, and is charged once the block is entered.
+///
+/// Instructions which operate on a variable amount of memory (`memcpy` and `memset`)
+/// are additionally charged for every byte they touch when they're executed, and `sbrk`
+/// is additionally charged for every byte by which it tries to grow the heap.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct GasCostModel {
+    pub(crate) opcode_cost: [u32; 256],
+    pub(crate) basic_block_cost: u32,
+    pub(crate) bulk_memory_byte_cost: u32,
+    pub(crate) sbrk_byte_cost: u32,
+}
+
+impl Default for GasCostModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GasCostModel {
+    /// Creates a new default gas cost model, where every instruction costs `1`, entering a basic block is free,
+    /// and every byte touched by `memcpy` and `memset` or requested from `sbrk` costs `1`.
+    pub fn new() -> Self {
+        GasCostModel {
+            opcode_cost: [1; 256],
+            basic_block_cost: 0,
+            bulk_memory_byte_cost: 1,
+            sbrk_byte_cost: 1,
+        }
+    }
+
+    /// Returns the cost of a single instruction with the given opcode.
+    pub fn opcode_cost(&self, opcode: Opcode) -> u32 {
+        self.opcode_cost[opcode as usize]
+    }
+
+    /// Sets the cost of a single instruction with the given opcode.
+    ///
+    /// The cost can't be bigger than `i32::MAX`. The total cost of a basic block can't be bigger than that either;
+    /// modules which contain a basic block that's more expensive will fail to compile.
+    ///
+    /// Default: `1`
+    pub fn set_opcode_cost(&mut self, opcode: Opcode, cost: u32) -> Result<&mut Self, Error> {
+        check_gas_cost(cost)?;
+        self.opcode_cost[opcode as usize] = cost;
+        Ok(self)
+    }
+
+    /// Returns the extra cost which is charged every time a basic block is entered.
+    pub fn basic_block_cost(&self) -> u32 {
+        self.basic_block_cost
+    }
+
+    /// Sets the extra cost which is charged every time a basic block is entered.
+    ///
+    /// The cost can't be bigger than `i32::MAX`.
+    ///
+    /// Default: `0`
+    pub fn set_basic_block_cost(&mut self, cost: u32) -> Result<&mut Self, Error> {
+        check_gas_cost(cost)?;
+        self.basic_block_cost = cost;
+        Ok(self)
+    }
+
+    /// Returns the extra cost which `memcpy` and `memset` are charged for every byte they copy or fill.
+    pub fn bulk_memory_byte_cost(&self) -> u32 {
+        self.bulk_memory_byte_cost
+    }
+
+    /// Sets the extra cost which `memcpy` and `memset` are charged for every byte they copy or fill.
+    ///
+    /// The cost can't be bigger than `i32::MAX`.
+    ///
+    /// Default: `1`
+    pub fn set_bulk_memory_byte_cost(&mut self, cost: u32) -> Result<&mut Self, Error> {
+        check_gas_cost(cost)?;
+        self.bulk_memory_byte_cost = cost;
+        Ok(self)
+    }
+
+    /// Returns the extra cost which `sbrk` is charged for every byte by which it tries to grow the heap.
+    pub fn sbrk_byte_cost(&self) -> u32 {
+        self.sbrk_byte_cost
+    }
+
+    /// Sets the extra cost which `sbrk` is charged for every byte by which it tries to grow the heap.
+    ///
+    /// The cost can't be bigger than `i32::MAX`.
+    ///
+    /// Default: `1`
+    pub fn set_sbrk_byte_cost(&mut self, cost: u32) -> Result<&mut Self, Error> {
+        check_gas_cost(cost)?;
+        self.sbrk_byte_cost = cost;
+        Ok(self)
+    }
+}
+
+fn check_gas_cost(cost: u32) -> Result<(), Error> {
+    // The compiler subtracts the costs as sign-extended 32-bit immediates.
+    if cost > i32::MAX as u32 {
+        bail!("invalid gas cost: {cost} is bigger than the maximum of {}", i32::MAX);
+    }
+
+    Ok(())
+}
+
 /// The configuration for a module.
 #[derive(Clone)]
 pub struct ModuleConfig {
     pub(crate) gas_metering: Option<GasMeteringKind>,
+    pub(crate) gas_cost_model: GasCostModel,
+    pub(crate) max_heap_size: u32,
+    pub(crate) count_basic_blocks: bool,
+    pub(crate) force_bit_manipulation_fallbacks: bool,
 }
 
 impl Default for ModuleConfig {
@@ -264,7 +395,13 @@ impl Default for ModuleConfig {
 impl ModuleConfig {
     /// Creates a new default module configuration.
     pub fn new() -> Self {
-        ModuleConfig { gas_metering: None }
+        ModuleConfig {
+            gas_metering: None,
+            gas_cost_model: GasCostModel::new(),
+            max_heap_size: 0,
+            count_basic_blocks: false,
+            force_bit_manipulation_fallbacks: false,
+        }
     }
 
     /// Sets the type of gas metering to enable for this module.
@@ -274,4 +411,45 @@ impl ModuleConfig {
         self.gas_metering = kind;
         self
     }
+
+    /// Sets the model used to calculate the gas cost of the program.
+    ///
+    /// This only matters if gas metering is enabled.
+    ///
+    /// Default: [`GasCostModel::new`]
+    pub fn set_gas_cost_model(&mut self, model: GasCostModel) -> &mut Self {
+        self.gas_cost_model = model;
+        self
+    }
+
+    /// Sets the maximum size to which the program's heap can grow through the `sbrk` instruction.
+    ///
+    /// This includes the program's read-write data and its BSS section; if it's smaller than
+    /// those then the heap will not be allowed to grow at all.
+    ///
+    /// Default: `0`
+    pub fn set_max_heap_size(&mut self, max_heap_size: u32) -> &mut Self {
+        self.max_heap_size = max_heap_size;
+        self
+    }
+
+    /// Sets whether the compiled code should count how many basic blocks it executes.
+    ///
+    /// The count is reported through [`ExecutionReport::basic_blocks_executed`](crate::ExecutionReport::basic_blocks_executed).
+    /// The interpreter always counts the basic blocks it executes, so this only matters for the compiled backends,
+    /// where it makes the code slightly slower.
+    ///
+    /// Default: `false`
+    pub fn set_count_basic_blocks(&mut self, value: bool) -> &mut Self {
+        self.count_basic_blocks = value;
+        self
+    }
+
+    /// Forces the compiler to emit the fallback sequences for `lzcnt`, `tzcnt` and `popcnt`,
+    /// so that they can be tested on CPUs which support those instructions.
+    #[cfg(test)]
+    pub(crate) fn set_force_bit_manipulation_fallbacks(&mut self, value: bool) -> &mut Self {
+        self.force_bit_manipulation_fallbacks = value;
+        self
+    }
 }
@@ -0,0 +1,171 @@
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+
+/// A marker trait for plain old data types which can be safely copied to and from the guest's memory.
+///
+/// Values are copied byte-for-byte, so multi-byte fields are expected to be in the guest's native
+/// (little-endian) byte order.
+///
+/// # Safety
+///
+/// The type must not contain any padding bytes, must be valid for any possible bit pattern,
+/// and must not contain any pointers or references. In practice this means a `#[repr(C)]` or
+/// `#[repr(transparent)]` struct made out of other `Pod` types without any implicit padding.
+pub unsafe trait Pod: Copy + Send + Sync + 'static {}
+
+macro_rules! impl_pod {
+    ($($ty:ty),+) => {
+        $(
+            // SAFETY: Primitive integers have no padding and are valid for any bit pattern.
+            unsafe impl Pod for $ty {}
+        )+
+    };
+}
+
+impl_pod!(u8, i8, u16, i16, u32, i32, u64, i64);
+
+// SAFETY: Arrays have no padding between their elements.
+unsafe impl<T, const N: usize> Pod for [T; N] where T: Pod {}
+
+pub(crate) fn pod_as_bytes<P: Pod>(values: &[P]) -> &[u8] {
+    // SAFETY: `Pod` types have no padding, so every byte is initialized.
+    unsafe { core::slice::from_raw_parts(values.as_ptr().cast::<u8>(), core::mem::size_of_val(values)) }
+}
+
+pub(crate) fn pod_as_uninit_bytes_mut<P: Pod>(values: &mut [MaybeUninit<P>]) -> &mut [MaybeUninit<u8>] {
+    // SAFETY: `MaybeUninit<u8>` has no validity requirements, and once the bytes are initialized
+    //         the value is guaranteed to be valid since `Pod` types are valid for any bit pattern.
+    unsafe { core::slice::from_raw_parts_mut(values.as_mut_ptr().cast::<MaybeUninit<u8>>(), core::mem::size_of_val(values)) }
+}
+
+/// A typed pointer into the guest's memory.
+///
+/// This is just a 32-bit address, so it can be directly used as an argument of a host function,
+/// and then passed to e.g. [`Caller::read_pod`](crate::Caller::read_pod) to read the value it points to.
+#[repr(transparent)]
+pub struct GuestPtr<T> {
+    address: u32,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> GuestPtr<T> {
+    /// Creates a new pointer to the given address.
+    pub const fn new(address: u32) -> Self {
+        GuestPtr {
+            address,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the address this pointer points to.
+    pub const fn address(self) -> u32 {
+        self.address
+    }
+
+    /// Returns whether this pointer is null.
+    pub const fn is_null(self) -> bool {
+        self.address == 0
+    }
+
+    /// Casts this pointer to a pointer of a different type.
+    pub const fn cast<U>(self) -> GuestPtr<U> {
+        GuestPtr::new(self.address)
+    }
+
+    /// Returns a pointer to the `count`-th element after this one, or `None` if it would overflow.
+    pub fn checked_add(self, count: u32) -> Option<Self> {
+        let offset = count.checked_mul(u32::try_from(core::mem::size_of::<T>()).ok()?)?;
+        Some(GuestPtr::new(self.address.checked_add(offset)?))
+    }
+
+    /// Returns a slice of `length` elements starting at this pointer.
+    pub const fn slice(self, length: u32) -> GuestSlice<T> {
+        GuestSlice { pointer: self, length }
+    }
+}
+
+impl<T> Copy for GuestPtr<T> {}
+
+impl<T> Clone for GuestPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> PartialEq for GuestPtr<T> {
+    fn eq(&self, rhs: &Self) -> bool {
+        self.address == rhs.address
+    }
+}
+
+impl<T> Eq for GuestPtr<T> {}
+
+impl<T> core::fmt::Debug for GuestPtr<T> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(fmt, "GuestPtr(0x{:x})", self.address)
+    }
+}
+
+impl<T> From<u32> for GuestPtr<T> {
+    fn from(address: u32) -> Self {
+        GuestPtr::new(address)
+    }
+}
+
+/// A typed `(pointer, length)` pair pointing into the guest's memory.
+///
+/// Since this spans two registers it can't be used directly as an argument of a host function;
+/// take a [`GuestPtr`] and a `u32` instead, and combine them with [`GuestPtr::slice`].
+pub struct GuestSlice<T> {
+    pointer: GuestPtr<T>,
+    length: u32,
+}
+
+impl<T> GuestSlice<T> {
+    /// Creates a new slice of `length` elements starting at the given address.
+    pub const fn new(address: u32, length: u32) -> Self {
+        GuestPtr::new(address).slice(length)
+    }
+
+    /// Returns a pointer to the first element of this slice.
+    pub const fn as_ptr(self) -> GuestPtr<T> {
+        self.pointer
+    }
+
+    /// Returns the number of elements in this slice.
+    pub const fn len(self) -> u32 {
+        self.length
+    }
+
+    /// Returns whether this slice is empty.
+    pub const fn is_empty(self) -> bool {
+        self.length == 0
+    }
+
+    /// Returns the size of this slice in bytes, or `None` if it doesn't fit in the address space.
+    pub fn byte_length(self) -> Option<u32> {
+        self.length.checked_mul(u32::try_from(core::mem::size_of::<T>()).ok()?)
+    }
+}
+
+impl<T> Copy for GuestSlice<T> {}
+
+impl<T> Clone for GuestSlice<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> PartialEq for GuestSlice<T> {
+    fn eq(&self, rhs: &Self) -> bool {
+        self.pointer == rhs.pointer && self.length == rhs.length
+    }
+}
+
+impl<T> Eq for GuestSlice<T> {}
+
+impl<T> core::fmt::Debug for GuestSlice<T> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(fmt, "GuestSlice(0x{:x}, {})", self.pointer.address, self.length)
+    }
+}
@@ -1,20 +1,130 @@
-use crate::api::{BackendAccess, ExecutionConfig, MemoryAccessError, Module, OnHostcall};
+use crate::api::{BackendAccess, ExecutionConfig, HostcallOutcome, InterruptState, MemoryAccessError, Module, OnHostcall};
+use crate::config::GasMeteringKind;
 use crate::error::{bail, Error};
 use crate::utils::RegImm;
 use core::mem::MaybeUninit;
-use polkavm_common::abi::{VM_ADDR_RETURN_TO_HOST, VM_CODE_ADDRESS_ALIGNMENT};
-use polkavm_common::error::Trap;
+use polkavm_common::abi::{VM_ADDR_RETURN_TO_HOST, VM_CODE_ADDRESS_ALIGNMENT, VM_PAGE_SIZE};
+use polkavm_common::error::{Trap, TrapKind};
 use polkavm_common::init::GuestProgramInit;
 use polkavm_common::operation::*;
 use polkavm_common::program::{Instruction, InstructionVisitor, Reg};
-use polkavm_common::utils::{byte_slice_init, Access, AsUninitSliceMut, Gas};
+use polkavm_common::utils::{align_to_next_page_u32, byte_slice_init, Access, AsUninitSliceMut, Gas};
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Instant;
 
 type ExecutionError<E = core::convert::Infallible> = polkavm_common::error::ExecutionError<E>;
 
+/// How many basic blocks to execute between checking whether the deadline has passed.
+const BASIC_BLOCKS_PER_DEADLINE_CHECK: u32 = 4096;
+
+const PAGE_SIZE: usize = VM_PAGE_SIZE as usize;
+
+type HeapPage = Arc<[u8; PAGE_SIZE]>;
+
+/// The interpreter's heap.
+///
+/// The pages are shared with the module, and hence with every other instance, until they're written to,
+/// so only the pages which are actually touched have to be copied.
+#[derive(Clone)]
+struct Heap {
+    pages: Vec<HeapPage>,
+    zero_page: HeapPage,
+}
+
+impl Heap {
+    fn new(data: &[u8]) -> Self {
+        let zero_page: HeapPage = Arc::new([0; PAGE_SIZE]);
+        let pages = data
+            .chunks(PAGE_SIZE)
+            .map(|chunk| {
+                if chunk.iter().all(|&byte| byte == 0) {
+                    Arc::clone(&zero_page)
+                } else {
+                    let mut page = [0; PAGE_SIZE];
+                    page[..chunk.len()].copy_from_slice(chunk);
+                    Arc::new(page)
+                }
+            })
+            .collect();
+
+        Heap { pages, zero_page }
+    }
+
+    fn len(&self) -> usize {
+        self.pages.len() * PAGE_SIZE
+    }
+
+    /// Grows or shrinks the heap; `length` must be a multiple of the page size.
+    fn resize(&mut self, length: usize) {
+        debug_assert_eq!(length % PAGE_SIZE, 0);
+        self.pages.resize(length / PAGE_SIZE, Arc::clone(&self.zero_page));
+    }
+
+    /// Calls `callback` for each page the given range touches, along with the part of the range within that page.
+    fn for_each_chunk(offset: usize, length: usize, mut callback: impl FnMut(usize, core::ops::Range<usize>, core::ops::Range<usize>)) {
+        let mut position = offset;
+        while position < offset + length {
+            let nth_page = position / PAGE_SIZE;
+            let offset_in_page = position % PAGE_SIZE;
+            let chunk_length = core::cmp::min(PAGE_SIZE - offset_in_page, offset + length - position);
+            let offset_in_data = position - offset;
+            callback(
+                nth_page,
+                offset_in_page..offset_in_page + chunk_length,
+                offset_in_data..offset_in_data + chunk_length,
+            );
+            position += chunk_length;
+        }
+    }
+
+    /// Returns the given range if it doesn't cross a page boundary.
+    fn slice(&self, offset: usize, length: usize) -> Option<&[u8]> {
+        let offset_in_page = offset % PAGE_SIZE;
+        self.pages[offset / PAGE_SIZE].get(offset_in_page..offset_in_page + length)
+    }
+
+    /// Returns the given range if it doesn't cross a page boundary, copying the page if it's shared.
+    fn slice_mut(&mut self, offset: usize, length: usize) -> Option<&mut [u8]> {
+        let offset_in_page = offset % PAGE_SIZE;
+        if offset_in_page + length > PAGE_SIZE {
+            return None;
+        }
+
+        Some(&mut Arc::make_mut(&mut self.pages[offset / PAGE_SIZE])[offset_in_page..offset_in_page + length])
+    }
+
+    fn read(&self, offset: usize, buffer: &mut [u8]) {
+        Self::for_each_chunk(offset, buffer.len(), |nth_page, page_range, buffer_range| {
+            buffer[buffer_range].copy_from_slice(&self.pages[nth_page][page_range]);
+        });
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) {
+        let pages = &mut self.pages;
+        Self::for_each_chunk(offset, data.len(), |nth_page, page_range, data_range| {
+            Arc::make_mut(&mut pages[nth_page])[page_range].copy_from_slice(&data[data_range]);
+        });
+    }
+
+    fn fill(&mut self, offset: usize, length: usize, value: u8) {
+        let pages = &mut self.pages;
+        Self::for_each_chunk(offset, length, |nth_page, page_range, _| {
+            Arc::make_mut(&mut pages[nth_page])[page_range].fill(value);
+        });
+    }
+
+    fn copy_within(&mut self, src_offset: usize, dst_offset: usize, length: usize) {
+        let mut buffer = vec![0; length];
+        self.read(src_offset, &mut buffer);
+        self.write(dst_offset, &buffer);
+    }
+}
+
 pub(crate) struct InterpretedModule {
     pub(crate) instructions: Vec<Instruction>,
     ro_data: Vec<u8>,
-    rw_data: Vec<u8>,
+    initial_heap: Heap,
     gas_cost_for_basic_block: Vec<u32>,
 }
 
@@ -24,16 +134,28 @@ impl InterpretedModule {
         let mut ro_data: Vec<_> = init.ro_data().into();
         ro_data.resize(memory_config.ro_data_size() as usize, 0);
 
+        let mut initial_heap: Vec<_> = init.rw_data().into();
+        initial_heap.resize(memory_config.heap_size() as usize, 0);
+
         Ok(InterpretedModule {
             instructions,
             ro_data,
-            rw_data: init.rw_data().into(),
+            initial_heap: Heap::new(&initial_heap),
             gas_cost_for_basic_block,
         })
     }
+
+    pub fn with_initial_heap(&self, heap: Vec<u8>) -> Self {
+        InterpretedModule {
+            instructions: self.instructions.clone(),
+            ro_data: self.ro_data.clone(),
+            initial_heap: Heap::new(&heap),
+            gas_cost_for_basic_block: self.gas_cost_for_basic_block.clone(),
+        }
+    }
 }
 
-pub(crate) type OnSetReg<'a> = &'a mut dyn FnMut(Reg, u32) -> Result<(), Trap>;
+pub(crate) type OnSetReg<'a> = &'a mut dyn FnMut(Reg, u64) -> Result<(), Trap>;
 pub(crate) type OnStore<'a> = &'a mut dyn for<'r> FnMut(u32, &'r [u8]) -> Result<(), Trap>;
 
 #[derive(Default)]
@@ -57,42 +179,76 @@ impl<'a> InterpreterContext<'a> {
     }
 }
 
+pub(crate) struct SavedCallState {
+    regs: [u64; Reg::ALL.len()],
+    nth_instruction: u32,
+    nth_basic_block: u32,
+    return_to_host: bool,
+    in_new_execution: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum MemoryRegion {
+    RoData,
+    Heap,
+    Stack,
+}
+
 pub(crate) struct InterpretedInstance {
     module: Module,
-    heap: Vec<u8>,
+    /// The heap's pages are shared with the module until they're written to.
+    heap: Heap,
+    /// The current end of the heap, as moved by `sbrk`.
+    heap_top: u32,
     stack: Vec<u8>,
-    regs: [u32; Reg::ALL.len()],
+    regs: [u64; Reg::ALL.len()],
+    is_64_bit: bool,
     nth_instruction: u32,
     nth_basic_block: u32,
     return_to_host: bool,
     cycle_counter: u64,
+    basic_blocks_executed: u64,
     gas_remaining: Option<i64>,
     in_new_execution: bool,
+    is_suspended: bool,
+    /// Whether the execution was just resumed after being suspended, in which case the gas has to be checked
+    /// as the host could have consumed some of it while handling the hostcall.
+    in_resumed_execution: bool,
+    reset_memory_after_execution: bool,
+    interrupt: Option<Arc<InterruptState>>,
+    deadline: Option<Instant>,
+    basic_blocks_until_deadline_check: u32,
 }
 
 impl InterpretedInstance {
-    pub fn new(module: Module) -> Result<Self, Error> {
+    pub fn new(module: Module, interrupt: Option<Arc<InterruptState>>) -> Result<Self, Error> {
         if module.interpreted_module().is_none() {
             bail!("an interpreter cannot be created from the given module")
         }
 
-        let mut heap = Vec::new();
         let mut stack = Vec::new();
-
-        heap.reserve_exact(module.memory_config().heap_size() as usize);
         stack.reserve_exact(module.memory_config().stack_size() as usize);
 
         let mut interpreter = Self {
-            heap,
+            heap: Heap::new(&[]),
+            heap_top: 0,
             stack,
+            is_64_bit: module.is_64_bit(),
             module,
             regs: [0; Reg::ALL.len()],
             nth_instruction: VM_ADDR_RETURN_TO_HOST,
             nth_basic_block: 0,
             return_to_host: true,
             cycle_counter: 0,
+            basic_blocks_executed: 0,
             gas_remaining: None,
             in_new_execution: false,
+            is_suspended: false,
+            in_resumed_execution: false,
+            reset_memory_after_execution: false,
+            interrupt,
+            deadline: None,
+            basic_blocks_until_deadline_check: 0,
         };
 
         if interpreter.module.gas_metering().is_some() {
@@ -104,12 +260,59 @@ impl InterpretedInstance {
     }
 
     pub fn call(&mut self, export_index: usize, on_hostcall: OnHostcall, config: &ExecutionConfig) -> Result<(), ExecutionError<Error>> {
+        if self.is_suspended {
+            // The suspended execution was abandoned.
+            self.is_suspended = false;
+            if self.reset_memory_after_execution {
+                self.reset_memory();
+            }
+        }
+
+        if let Some(ref interrupt) = self.interrupt {
+            interrupt.clear();
+        }
+
         let mut ctx = InterpreterContext::default();
         ctx.set_on_hostcall(on_hostcall);
         self.prepare_for_call(export_index, config);
+        self.reset_memory_after_execution = config.reset_memory_after_execution;
+        self.deadline = config.deadline;
+        self.basic_blocks_until_deadline_check = 0;
+        self.execute(ctx)
+    }
+
+    pub fn resume(&mut self, on_hostcall: OnHostcall) -> Result<(), ExecutionError<Error>> {
+        if !self.is_suspended {
+            return Err(ExecutionError::Error(Error::from_static_str(
+                "tried to resume an execution which wasn't suspended",
+            )));
+        }
 
-        let result = self.run(ctx);
-        if config.reset_memory_after_execution {
+        // Skip over the `ecalli` instruction we were suspended at.
+        self.is_suspended = false;
+        self.in_resumed_execution = true;
+        self.return_to_host = false;
+        self.nth_instruction += 1;
+
+        let mut ctx = InterpreterContext::default();
+        ctx.set_on_hostcall(on_hostcall);
+        self.execute(ctx)
+    }
+
+    fn execute(&mut self, ctx: InterpreterContext) -> Result<(), ExecutionError<Error>> {
+        let result = match self.run(ctx) {
+            Err(ExecutionError::Trap(trap)) => {
+                let module = self.module.clone();
+                Err(ExecutionError::Trap(module.attach_backtrace(trap, &self.access())))
+            }
+            result => result,
+        };
+
+        if self.is_suspended {
+            return result;
+        }
+
+        if self.reset_memory_after_execution {
             self.reset_memory();
         }
 
@@ -121,16 +324,22 @@ impl InterpretedInstance {
             error.map_err(|error| match error {
                 ExecutionError::Trap(trap) => ExecutionError::Trap(trap),
                 ExecutionError::OutOfGas => ExecutionError::OutOfGas,
+                ExecutionError::Interrupted => ExecutionError::Interrupted,
                 ExecutionError::Error(_) => unreachable!(),
             })
         }
 
-        if self.in_new_execution {
-            self.in_new_execution = false;
-            translate_error(self.on_start_new_basic_block())?;
+        let mut visitor = Visitor { inner: self, ctx };
+        if visitor.inner.in_new_execution {
+            visitor.inner.in_new_execution = false;
+            translate_error(visitor.on_start_new_basic_block())?;
+        }
+
+        if visitor.inner.in_resumed_execution {
+            visitor.inner.in_resumed_execution = false;
+            translate_error(visitor.check_gas())?;
         }
 
-        let mut visitor = Visitor { inner: self, ctx };
         loop {
             visitor.inner.cycle_counter += 1;
             let Some(instruction) = visitor
@@ -154,14 +363,29 @@ impl InterpretedInstance {
 
     pub fn reset_memory(&mut self) {
         let interpreted_module = self.module.interpreted_module().unwrap();
-        self.heap.clear();
-        self.heap.extend_from_slice(&interpreted_module.rw_data);
-        self.heap.resize(self.module.memory_config().heap_size() as usize, 0);
+        self.heap = interpreted_module.initial_heap.clone();
+        self.heap_top = self.module.memory_config().heap_range().end;
         self.stack.clear();
         self.stack.resize(self.module.memory_config().stack_size() as usize, 0);
     }
 
     pub fn prepare_for_call(&mut self, export_index: usize, config: &ExecutionConfig) {
+        self.jump_to_export(export_index, &config.initial_regs);
+        if self.module.gas_metering().is_some() {
+            if let Some(gas) = config.gas {
+                self.gas_remaining = Some(gas.get() as i64);
+            }
+        } else {
+            self.gas_remaining = None;
+        }
+    }
+
+    /// Prepares a call from within a hostcall; the gas is shared with the outer call.
+    pub fn prepare_for_nested_call(&mut self, export_index: usize, regs: &[u64; Reg::ALL.len()]) {
+        self.jump_to_export(export_index, regs);
+    }
+
+    fn jump_to_export(&mut self, export_index: usize, regs: &[u64; Reg::ALL.len()]) {
         // TODO: If this function becomes public then this needs to return an error.
         let nth_basic_block = self
             .module
@@ -175,72 +399,228 @@ impl InterpretedInstance {
             .expect("internal error: invalid export address");
 
         self.return_to_host = false;
-        self.regs.copy_from_slice(&config.initial_regs);
+        self.regs.copy_from_slice(regs);
+        crate::utils::truncate_regs(&mut self.regs, self.is_64_bit);
         self.nth_instruction = nth_instruction;
         self.nth_basic_block = nth_basic_block;
-        if self.module.gas_metering().is_some() {
-            if let Some(gas) = config.gas {
-                self.gas_remaining = Some(gas.get() as i64);
-            }
-        } else {
-            self.gas_remaining = None;
+        self.in_new_execution = true;
+    }
+
+    /// Saves the state of the call which is currently in the middle of a hostcall, so that a nested call can be made.
+    pub fn save_call_state(&self) -> SavedCallState {
+        SavedCallState {
+            regs: self.regs,
+            nth_instruction: self.nth_instruction,
+            nth_basic_block: self.nth_basic_block,
+            return_to_host: self.return_to_host,
+            in_new_execution: self.in_new_execution,
         }
+    }
 
-        self.in_new_execution = true;
+    pub fn restore_call_state(&mut self, state: SavedCallState) {
+        self.regs = state.regs;
+        self.nth_instruction = state.nth_instruction;
+        self.nth_basic_block = state.nth_basic_block;
+        self.return_to_host = state.return_to_host;
+        self.in_new_execution = state.in_new_execution;
     }
 
     pub fn step_once(&mut self, ctx: InterpreterContext) -> Result<(), ExecutionError> {
-        if self.in_new_execution {
-            self.in_new_execution = false;
-            self.on_start_new_basic_block()?;
+        let mut visitor = Visitor { inner: self, ctx };
+        if visitor.inner.in_new_execution {
+            visitor.inner.in_new_execution = false;
+            visitor.on_start_new_basic_block()?;
         }
 
-        self.cycle_counter += 1;
-        let Some(instruction) = self.module.instructions().get(self.nth_instruction as usize).copied() else {
+        visitor.inner.cycle_counter += 1;
+        let Some(instruction) = visitor
+            .inner
+            .module
+            .instructions()
+            .get(visitor.inner.nth_instruction as usize)
+            .copied()
+        else {
             return Err(ExecutionError::Trap(Default::default()));
         };
 
-        let mut visitor = Visitor { inner: self, ctx };
-
         instruction.visit(&mut visitor)
     }
 
+    /// Grows or shrinks the heap so that it ends at `new_heap_top`.
+    pub fn resize_heap(&mut self, new_heap_top: u32) {
+        let heap_address = self.module.memory_config().heap_address();
+        let mapped_end = align_to_next_page_u32(VM_PAGE_SIZE, new_heap_top).unwrap();
+        let new_length = (mapped_end - heap_address) as usize;
+        if new_length != self.heap.len() {
+            self.heap.resize(new_length);
+        }
+
+        self.heap_top = new_heap_top;
+    }
+
+    /// Grows the heap by `size` bytes and returns where it previously ended, or `None` if it can't grow that much.
+    fn sbrk(&mut self, size: u32) -> Option<u32> {
+        let heap_top = self.heap_top;
+        let new_heap_top = heap_top.checked_add(size)?;
+        if new_heap_top > self.module.max_heap_top() {
+            return None;
+        }
+
+        self.resize_heap(new_heap_top);
+        Some(heap_top)
+    }
+
+    pub fn cycle_counter(&self) -> u64 {
+        self.cycle_counter
+    }
+
+    pub fn basic_blocks_executed(&self) -> u64 {
+        self.basic_blocks_executed
+    }
+
     pub fn access(&mut self) -> InterpretedAccess {
         InterpretedAccess { instance: self }
     }
 
-    fn get_memory_slice(&self, address: u32, length: u32) -> Option<&[u8]> {
-        let memory_config = self.module.memory_config();
-        let (range, memory) = if memory_config.ro_data_range().contains(&address) {
-            let module = self.module.interpreted_module().unwrap();
-            (memory_config.ro_data_range(), &module.ro_data)
-        } else if memory_config.heap_range().contains(&address) {
-            (memory_config.heap_range(), &self.heap)
-        } else if memory_config.stack_range().contains(&address) {
-            (memory_config.stack_range(), &self.stack)
-        } else {
-            return None;
+    /// The range of addresses where the heap is, including the part which was allocated with `sbrk`.
+    fn heap_range(&self) -> core::ops::Range<u32> {
+        let heap_address = self.module.memory_config().heap_address();
+        heap_address..heap_address + self.heap.len() as u32
+    }
+
+    /// Returns the given range of memory without copying it, unless it's in the heap and crosses a page boundary.
+    fn get_memory_slice(&self, address: u32, length: u32) -> Option<Cow<[u8]>> {
+        let (region, offset) = self.memory_region(address, length)?;
+        let length = length as usize;
+        let slice = match region {
+            MemoryRegion::RoData => &self.module.interpreted_module().unwrap().ro_data[offset..offset + length],
+            MemoryRegion::Stack => &self.stack[offset..offset + length],
+            MemoryRegion::Heap => {
+                if let Some(slice) = self.heap.slice(offset, length) {
+                    slice
+                } else {
+                    let mut buffer = vec![0; length];
+                    self.heap.read(offset, &mut buffer);
+                    return Some(Cow::Owned(buffer));
+                }
+            }
         };
 
-        let offset = address - range.start;
-        memory.get(offset as usize..offset as usize + length as usize)
+        Some(Cow::Borrowed(slice))
+    }
+
+    /// Returns the given range of writable memory, unless it's in the heap and crosses a page boundary.
+    ///
+    /// The outer `Option` is `None` if the range is not writable.
+    fn get_memory_slice_mut(&mut self, address: u32, length: u32) -> Option<Option<&mut [u8]>> {
+        let (region, offset) = self.writable_memory_region(address, length)?;
+        let length = length as usize;
+        Some(match region {
+            MemoryRegion::RoData => unreachable!(),
+            MemoryRegion::Stack => Some(&mut self.stack[offset..offset + length]),
+            MemoryRegion::Heap => self.heap.slice_mut(offset, length),
+        })
+    }
+
+    fn write_memory(&mut self, address: u32, data: &[u8]) -> Option<()> {
+        let (region, offset) = self.writable_memory_region(address, data.len() as u32)?;
+        match region {
+            MemoryRegion::RoData => unreachable!(),
+            MemoryRegion::Stack => self.stack[offset..offset + data.len()].copy_from_slice(data),
+            MemoryRegion::Heap => self.heap.write(offset, data),
+        }
+
+        Some(())
+    }
+
+    fn fill_memory(&mut self, address: u32, length: u32, value: u8) -> Option<()> {
+        let (region, offset) = self.writable_memory_region(address, length)?;
+        let length = length as usize;
+        match region {
+            MemoryRegion::RoData => unreachable!(),
+            MemoryRegion::Stack => self.stack[offset..offset + length].fill(value),
+            MemoryRegion::Heap => self.heap.fill(offset, length, value),
+        }
+
+        Some(())
     }
 
-    fn get_memory_slice_mut(&mut self, address: u32, length: u32) -> Option<&mut [u8]> {
+    /// Finds the memory region which fully contains `length` bytes starting at `address`, along with the offset into that region.
+    fn memory_region(&self, address: u32, length: u32) -> Option<(MemoryRegion, usize)> {
         let memory_config = self.module.memory_config();
-        let (range, memory_slice) = if memory_config.heap_range().contains(&address) {
-            (memory_config.heap_range(), &mut self.heap)
+        let (region, range) = if memory_config.ro_data_range().contains(&address) {
+            (MemoryRegion::RoData, memory_config.ro_data_range())
+        } else if self.heap_range().contains(&address) {
+            (MemoryRegion::Heap, self.heap_range())
         } else if memory_config.stack_range().contains(&address) {
-            (memory_config.stack_range(), &mut self.stack)
+            (MemoryRegion::Stack, memory_config.stack_range())
         } else {
             return None;
         };
 
-        let offset = (address - range.start) as usize;
-        memory_slice.get_mut(offset..offset + length as usize)
+        if length > range.end - address {
+            return None;
+        }
+
+        Some((region, (address - range.start) as usize))
+    }
+
+    fn writable_memory_region(&self, address: u32, length: u32) -> Option<(MemoryRegion, usize)> {
+        self.memory_region(address, length)
+            .filter(|&(region, _)| region != MemoryRegion::RoData)
+    }
+
+    /// Copies `length` bytes from `src` to `dst`. The source and the destination are allowed to overlap.
+    fn copy_memory(&mut self, dst: u32, src: u32, length: u32) -> Result<(), TrapKind> {
+        let Some((src_region, src_offset)) = self.memory_region(src, length) else {
+            return Err(TrapKind::InvalidLoad { address: src });
+        };
+
+        let Some((dst_region, dst_offset)) = self.writable_memory_region(dst, length) else {
+            return Err(TrapKind::InvalidStore { address: dst });
+        };
+
+        let src_range = src_offset..src_offset + length as usize;
+        let dst_range = dst_offset..dst_offset + length as usize;
+        match (src_region, dst_region) {
+            (_, MemoryRegion::RoData) => unreachable!(),
+            (MemoryRegion::Heap, MemoryRegion::Heap) => self.heap.copy_within(src_offset, dst_offset, length as usize),
+            (MemoryRegion::Stack, MemoryRegion::Stack) => self.stack.copy_within(src_range, dst_offset),
+            (MemoryRegion::Heap, MemoryRegion::Stack) => self.heap.read(src_offset, &mut self.stack[dst_range]),
+            (MemoryRegion::Stack, MemoryRegion::Heap) => self.heap.write(dst_offset, &self.stack[src_range]),
+            (MemoryRegion::RoData, MemoryRegion::Heap) => {
+                let ro_data = &self.module.interpreted_module().unwrap().ro_data;
+                self.heap.write(dst_offset, &ro_data[src_range]);
+            }
+            (MemoryRegion::RoData, MemoryRegion::Stack) => {
+                let ro_data = &self.module.interpreted_module().unwrap().ro_data;
+                self.stack[dst_range].copy_from_slice(&ro_data[src_range]);
+            }
+        }
+
+        Ok(())
     }
 
     fn on_start_new_basic_block(&mut self) -> Result<(), ExecutionError> {
+        if let Some(ref interrupt) = self.interrupt {
+            if interrupt.is_interrupted() {
+                return Err(ExecutionError::Interrupted);
+            }
+        }
+
+        if let Some(deadline) = self.deadline {
+            // Getting the current time is relatively expensive, so don't do it for every basic block.
+            if self.basic_blocks_until_deadline_check == 0 {
+                if Instant::now() >= deadline {
+                    return Err(ExecutionError::Interrupted);
+                }
+
+                self.basic_blocks_until_deadline_check = BASIC_BLOCKS_PER_DEADLINE_CHECK;
+            }
+
+            self.basic_blocks_until_deadline_check -= 1;
+        }
+
         if let Some(ref mut gas_remaining) = self.gas_remaining {
             let module = self.module.interpreted_module().unwrap();
             let gas_cost = i64::from(module.gas_cost_for_basic_block[self.nth_basic_block as usize]);
@@ -262,21 +642,70 @@ impl InterpretedInstance {
 
         Ok(())
     }
+
+    /// Charges the per-byte gas cost of a `memcpy`, a `memset` or an `sbrk` which touches `length` bytes.
+    fn consume_per_byte_gas(&mut self, length: u32, byte_cost: u32) -> Result<(), ExecutionError> {
+        if let Some(ref mut gas_remaining) = self.gas_remaining {
+            let gas_cost = i64::from(length) * i64::from(byte_cost);
+            *gas_remaining = gas_remaining.saturating_sub(gas_cost);
+        }
+
+        self.check_gas()
+    }
 }
 
 pub struct InterpretedAccess<'a> {
     instance: &'a mut InterpretedInstance,
 }
 
+impl<'a> InterpretedAccess<'a> {
+    pub(crate) fn call_nested(
+        &mut self,
+        export_index: usize,
+        regs: &mut [u64; Reg::ALL.len()],
+        on_hostcall: OnHostcall,
+    ) -> Result<(), ExecutionError<Error>> {
+        let instance = &mut *self.instance;
+        let outer_state = instance.save_call_state();
+        instance.prepare_for_nested_call(export_index, regs);
+
+        let mut ctx = InterpreterContext::default();
+        ctx.set_on_hostcall(on_hostcall);
+        let mut result = match instance.run(ctx) {
+            Err(ExecutionError::Trap(trap)) => {
+                let module = instance.module.clone();
+                Err(ExecutionError::Trap(module.attach_backtrace(trap, &instance.access())))
+            }
+            result => result,
+        };
+
+        if core::mem::take(&mut instance.is_suspended) {
+            result = Err(ExecutionError::Error(Error::from_static_str("a nested call cannot be suspended")));
+        }
+
+        regs.copy_from_slice(&instance.regs);
+        instance.restore_call_state(outer_state);
+        result
+    }
+}
+
 impl<'a> Access<'a> for InterpretedAccess<'a> {
     type Error = MemoryAccessError<&'static str>;
 
     fn get_reg(&self, reg: Reg) -> u32 {
-        self.instance.regs[reg as usize]
+        self.get_reg64(reg) as u32
     }
 
     fn set_reg(&mut self, reg: Reg, value: u32) {
-        self.instance.regs[reg as usize] = value;
+        self.set_reg64(reg, u64::from(value));
+    }
+
+    fn get_reg64(&self, reg: Reg) -> u64 {
+        self.instance.regs[reg as usize]
+    }
+
+    fn set_reg64(&mut self, reg: Reg, value: u64) {
+        self.instance.regs[reg as usize] = if self.instance.is_64_bit { value } else { u64::from(value as u32) };
     }
 
     fn read_memory_into_slice<'slice, T>(&self, address: u32, buffer: &'slice mut T) -> Result<&'slice mut [u8], Self::Error>
@@ -292,20 +721,47 @@ impl<'a> Access<'a> for InterpretedAccess<'a> {
             });
         };
 
-        Ok(byte_slice_init(buffer, slice))
+        Ok(byte_slice_init(buffer, &slice))
     }
 
     fn write_memory(&mut self, address: u32, data: &[u8]) -> Result<(), Self::Error> {
-        let Some(slice) = self.instance.get_memory_slice_mut(address, data.len() as u32) else {
+        self.instance.write_memory(address, data).ok_or(MemoryAccessError {
+            address,
+            length: data.len() as u64,
+            error: "out of range write",
+        })
+    }
+
+    fn with_memory<R>(&self, address: u32, length: u32, callback: impl FnOnce(&[u8]) -> R) -> Result<R, Self::Error> {
+        let Some(slice) = self.instance.get_memory_slice(address, length) else {
             return Err(MemoryAccessError {
                 address,
-                length: data.len() as u64,
+                length: u64::from(length),
+                error: "out of range read",
+            });
+        };
+
+        Ok(callback(&slice))
+    }
+
+    fn with_memory_mut<R>(&mut self, address: u32, length: u32, callback: impl FnOnce(&mut [u8]) -> R) -> Result<R, Self::Error> {
+        let Some(slice) = self.instance.get_memory_slice_mut(address, length) else {
+            return Err(MemoryAccessError {
+                address,
+                length: u64::from(length),
                 error: "out of range write",
             });
         };
 
-        slice.copy_from_slice(data);
-        Ok(())
+        if let Some(slice) = slice {
+            return Ok(callback(slice));
+        }
+
+        // The range crosses a page boundary in the heap, so it has to be copied out and back in.
+        let mut buffer = self.instance.get_memory_slice(address, length).unwrap().into_owned();
+        let result = callback(&mut buffer);
+        self.instance.write_memory(address, &buffer).unwrap();
+        Ok(result)
     }
 
     fn program_counter(&self) -> Option<u32> {
@@ -316,6 +772,10 @@ impl<'a> Access<'a> for InterpretedAccess<'a> {
         None
     }
 
+    fn heap_size(&self) -> u32 {
+        self.instance.heap_top - self.instance.module.memory_config().heap_address()
+    }
+
     fn gas_remaining(&self) -> Option<Gas> {
         let gas = self.instance.gas_remaining?;
         Some(Gas::new(gas as u64).unwrap_or(Gas::MIN))
@@ -326,6 +786,18 @@ impl<'a> Access<'a> for InterpretedAccess<'a> {
             *gas_remaining = gas_remaining.checked_sub_unsigned(gas).unwrap_or(-1);
         }
     }
+
+    fn add_gas(&mut self, gas: u64) {
+        if let Some(ref mut gas_remaining) = self.instance.gas_remaining {
+            *gas_remaining = gas_remaining.saturating_add_unsigned(gas);
+        }
+    }
+
+    fn set_gas(&mut self, gas: Gas) {
+        if let Some(ref mut gas_remaining) = self.instance.gas_remaining {
+            *gas_remaining = gas.get() as i64;
+        }
+    }
 }
 
 struct Visitor<'a, 'b> {
@@ -334,16 +806,69 @@ struct Visitor<'a, 'b> {
 }
 
 impl<'a, 'b> Visitor<'a, 'b> {
+    fn on_start_new_basic_block(&mut self) -> Result<(), ExecutionError> {
+        match self.inner.on_start_new_basic_block() {
+            Err(ExecutionError::OutOfGas) => self.on_out_of_gas(),
+            result => result,
+        }?;
+
+        self.inner.basic_blocks_executed += 1;
+        Ok(())
+    }
+
+    fn check_gas(&mut self) -> Result<(), ExecutionError> {
+        match self.inner.check_gas() {
+            Err(ExecutionError::OutOfGas) => self.on_out_of_gas(),
+            result => result,
+        }
+    }
+
+    fn consume_per_byte_gas(&mut self, length: u32, byte_cost: u32) -> Result<(), ExecutionError> {
+        match self.inner.consume_per_byte_gas(length, byte_cost) {
+            Err(ExecutionError::OutOfGas) => self.on_out_of_gas(),
+            result => result,
+        }
+    }
+
+    #[cold]
+    fn on_out_of_gas(&mut self) -> Result<(), ExecutionError> {
+        // With synchronous gas metering the host gets a chance to refill the gas before we give up.
+        if self.inner.module.gas_metering() == Some(GasMeteringKind::Sync) {
+            if let Some(on_hostcall) = self.ctx.on_hostcall.as_mut() {
+                let nth_instruction = self.inner.nth_instruction;
+                let access = BackendAccess::Interpreted(self.inner.access());
+                (on_hostcall)(polkavm_common::HOSTCALL_OUT_OF_GAS, access)
+                    .map_err(|trap| ExecutionError::Trap(trap.with_program_counter(Some(nth_instruction))))?;
+
+                return self.inner.check_gas();
+            }
+        }
+
+        Err(ExecutionError::OutOfGas)
+    }
+
+    /// Gets the low 32 bits of a register, or a 32-bit immediate.
     #[inline(always)]
-    fn get(&self, regimm: impl Into<RegImm>) -> u32 {
+    fn get32(&self, regimm: impl Into<RegImm>) -> u32 {
         match regimm.into() {
-            RegImm::Reg(reg) => self.inner.regs[reg as usize],
+            RegImm::Reg(reg) => self.inner.regs[reg as usize] as u32,
             RegImm::Imm(value) => value,
         }
     }
 
+    /// Gets the full value of a register, or a sign extended immediate.
     #[inline(always)]
-    fn set(&mut self, dst: Reg, value: u32) -> Result<(), ExecutionError> {
+    fn get64(&self, regimm: impl Into<RegImm>) -> u64 {
+        match regimm.into() {
+            RegImm::Reg(reg) => self.inner.regs[reg as usize],
+            RegImm::Imm(value) => i64::from(value as i32) as u64,
+        }
+    }
+
+    #[inline(always)]
+    fn set(&mut self, dst: Reg, value: u64) -> Result<(), ExecutionError> {
+        // On 32-bit the upper bits of a register are always zero.
+        let value = if self.inner.is_64_bit { value } else { u64::from(value as u32) };
         self.inner.regs[dst as usize] = value;
         log::trace!("{dst} = 0x{value:x}");
 
@@ -355,17 +880,66 @@ impl<'a, 'b> Visitor<'a, 'b> {
         }
     }
 
+    #[inline(always)]
+    fn set2(
+        &mut self,
+        dst: Reg,
+        src: Reg,
+        callback_32: impl Fn(u32) -> u32,
+        callback_64: impl Fn(u64) -> u64,
+    ) -> Result<(), ExecutionError> {
+        let value = if self.inner.is_64_bit {
+            callback_64(self.get64(src))
+        } else {
+            u64::from(callback_32(self.get32(src)))
+        };
+
+        self.set(dst, value)?;
+        self.inner.nth_instruction += 1;
+        Ok(())
+    }
+
+    /// Sets a register to the sign extended result of a 32-bit operation; only used by the 64-bit variant of the instruction set.
+    #[inline(always)]
+    fn set2_32(&mut self, dst: Reg, src: Reg, callback: impl Fn(u32) -> u32) -> Result<(), ExecutionError> {
+        let value = callback(self.get32(src));
+        self.set(dst, i64::from(value as i32) as u64)?;
+        self.inner.nth_instruction += 1;
+        Ok(())
+    }
+
     #[inline(always)]
     fn set3(
+        &mut self,
+        dst: Reg,
+        s1: impl Into<RegImm>,
+        s2: impl Into<RegImm>,
+        callback_32: impl Fn(u32, u32) -> u32,
+        callback_64: impl Fn(u64, u64) -> u64,
+    ) -> Result<(), ExecutionError> {
+        let (s1, s2) = (s1.into(), s2.into());
+        let value = if self.inner.is_64_bit {
+            callback_64(self.get64(s1), self.get64(s2))
+        } else {
+            u64::from(callback_32(self.get32(s1), self.get32(s2)))
+        };
+
+        self.set(dst, value)?;
+        self.inner.nth_instruction += 1;
+        Ok(())
+    }
+
+    /// Sets a register to the sign extended result of a 32-bit operation; only used by the 64-bit variant of the instruction set.
+    #[inline(always)]
+    fn set3_32(
         &mut self,
         dst: Reg,
         s1: impl Into<RegImm>,
         s2: impl Into<RegImm>,
         callback: impl Fn(u32, u32) -> u32,
     ) -> Result<(), ExecutionError> {
-        let s1 = self.get(s1);
-        let s2 = self.get(s2);
-        self.set(dst, callback(s1, s2))?;
+        let value = callback(self.get32(s1), self.get32(s2));
+        self.set(dst, i64::from(value as i32) as u64)?;
         self.inner.nth_instruction += 1;
         Ok(())
     }
@@ -375,11 +949,17 @@ impl<'a, 'b> Visitor<'a, 'b> {
         s1: impl Into<RegImm>,
         s2: impl Into<RegImm>,
         target: u32,
-        callback: impl Fn(u32, u32) -> bool,
+        callback_32: impl Fn(u32, u32) -> bool,
+        callback_64: impl Fn(u64, u64) -> bool,
     ) -> Result<(), ExecutionError> {
-        let s1 = self.get(s1);
-        let s2 = self.get(s2);
-        if callback(s1, s2) {
+        let (s1, s2) = (s1.into(), s2.into());
+        let condition = if self.inner.is_64_bit {
+            callback_64(self.get64(s1), self.get64(s2))
+        } else {
+            callback_32(self.get32(s1), self.get32(s2))
+        };
+
+        if condition {
             self.inner.nth_instruction = self
                 .inner
                 .module
@@ -391,13 +971,17 @@ impl<'a, 'b> Visitor<'a, 'b> {
             self.inner.nth_basic_block += 1;
         }
 
-        self.inner.on_start_new_basic_block()
+        self.on_start_new_basic_block()
+    }
+
+    fn trap_with_kind(&self, kind: TrapKind) -> ExecutionError {
+        ExecutionError::Trap(Trap::new(kind).with_program_counter(Some(self.inner.nth_instruction)))
     }
 
     fn load<T: LoadTy>(&mut self, dst: Reg, base: Option<Reg>, offset: u32) -> Result<(), ExecutionError> {
         assert!(core::mem::size_of::<T>() >= 1);
 
-        let address = base.map_or(0, |base| self.inner.regs[base as usize]).wrapping_add(offset);
+        let address = base.map_or(0, |base| self.get32(base)).wrapping_add(offset);
         let length = core::mem::size_of::<T>() as u32;
         let Some(slice) = self.inner.get_memory_slice(address, length) else {
             log::debug!(
@@ -408,12 +992,12 @@ impl<'a, 'b> Visitor<'a, 'b> {
             self.inner
                 .module
                 .debug_print_location(log::Level::Debug, self.inner.nth_instruction);
-            return Err(ExecutionError::Trap(Default::default()));
+            return Err(self.trap_with_kind(TrapKind::InvalidLoad { address }));
         };
 
         log::trace!("{dst} = {kind} [0x{address:x}]", kind = core::any::type_name::<T>());
 
-        let value = T::from_slice(slice);
+        let value = T::from_slice(&slice);
         self.set(dst, value)?;
         self.inner.nth_instruction += 1;
         Ok(())
@@ -422,21 +1006,23 @@ impl<'a, 'b> Visitor<'a, 'b> {
     fn store<T: StoreTy>(&mut self, src: impl Into<RegImm>, base: Option<Reg>, offset: u32) -> Result<(), ExecutionError> {
         assert!(core::mem::size_of::<T>() >= 1);
 
-        let address = base.map_or(0, |base| self.inner.regs[base as usize]).wrapping_add(offset);
+        let address = base.map_or(0, |base| self.get32(base)).wrapping_add(offset);
         let value = match src.into() {
             RegImm::Reg(src) => {
-                let value = self.inner.regs[src as usize];
+                let value = self.get64(src);
                 log::trace!("{kind} [0x{address:x}] = {src} = 0x{value:x}", kind = core::any::type_name::<T>());
                 value
             }
             RegImm::Imm(value) => {
+                let value = self.get64(value);
                 log::trace!("{kind} [0x{address:x}] = 0x{value:x}", kind = core::any::type_name::<T>());
                 value
             }
         };
 
         let length = core::mem::size_of::<T>() as u32;
-        let Some(slice) = self.inner.get_memory_slice_mut(address, length) else {
+        let value = T::into_bytes(value);
+        if self.inner.write_memory(address, value.as_ref()).is_none() {
             log::debug!(
                 "Store of {length} bytes to 0x{address:x} failed! (pc = #{pc}, cycle = {cycle})",
                 pc = self.inner.nth_instruction,
@@ -445,11 +1031,8 @@ impl<'a, 'b> Visitor<'a, 'b> {
             self.inner
                 .module
                 .debug_print_location(log::Level::Debug, self.inner.nth_instruction);
-            return Err(ExecutionError::Trap(Default::default()));
-        };
-
-        let value = T::into_bytes(value);
-        slice.copy_from_slice(value.as_ref());
+            return Err(self.trap_with_kind(TrapKind::InvalidStore { address }));
+        }
 
         if let Some(on_store) = self.ctx.on_store.as_mut() {
             (on_store)(address, value.as_ref()).map_err(ExecutionError::Trap)?;
@@ -474,13 +1057,13 @@ impl<'a, 'b> Visitor<'a, 'b> {
             self.inner.nth_basic_block + 1
         );
 
-        self.set(ra, return_address)
+        self.set(ra, u64::from(return_address))
     }
 
     fn dynamic_jump(&mut self, call: Option<(Reg, u32)>, base: Reg, offset: u32) -> Result<(), ExecutionError> {
-        let target = self.inner.regs[base as usize].wrapping_add(offset);
+        let target = self.get32(base).wrapping_add(offset);
         if let Some((ra, return_address)) = call {
-            self.set(ra, return_address)?;
+            self.set(ra, u64::from(return_address))?;
         }
 
         if target == VM_ADDR_RETURN_TO_HOST {
@@ -489,12 +1072,12 @@ impl<'a, 'b> Visitor<'a, 'b> {
         }
 
         if target == 0 {
-            return Err(ExecutionError::Trap(Default::default()));
+            return Err(self.trap_with_kind(TrapKind::InvalidJumpTarget));
         }
 
         if target % VM_CODE_ADDRESS_ALIGNMENT != 0 {
             log::error!("Found a dynamic jump with a misaligned target: target = {target}");
-            return Err(ExecutionError::Trap(Default::default()));
+            return Err(self.trap_with_kind(TrapKind::InvalidJumpTarget));
         }
 
         let Some(nth_basic_block) = self
@@ -502,7 +1085,7 @@ impl<'a, 'b> Visitor<'a, 'b> {
             .module
             .basic_block_by_jump_table_index(target / VM_CODE_ADDRESS_ALIGNMENT)
         else {
-            return Err(ExecutionError::Trap(Default::default()));
+            return Err(self.trap_with_kind(TrapKind::InvalidJumpTarget));
         };
 
         let nth_instruction = self
@@ -514,66 +1097,86 @@ impl<'a, 'b> Visitor<'a, 'b> {
         log::trace!("Dynamic jump to: #{nth_instruction}: @{nth_basic_block:x}");
         self.inner.nth_basic_block = nth_basic_block;
         self.inner.nth_instruction = nth_instruction;
-        self.inner.on_start_new_basic_block()
+        self.on_start_new_basic_block()
     }
 }
 
 trait LoadTy {
-    fn from_slice(xs: &[u8]) -> u32;
+    /// Reads the value and sign or zero extends it to the full width of a register.
+    fn from_slice(xs: &[u8]) -> u64;
 }
 
 impl LoadTy for u8 {
-    fn from_slice(xs: &[u8]) -> u32 {
-        u32::from(xs[0])
+    fn from_slice(xs: &[u8]) -> u64 {
+        u64::from(xs[0])
     }
 }
 
 impl LoadTy for i8 {
-    fn from_slice(xs: &[u8]) -> u32 {
-        i32::from(xs[0] as i8) as u32
+    fn from_slice(xs: &[u8]) -> u64 {
+        i64::from(xs[0] as i8) as u64
     }
 }
 
 impl LoadTy for u16 {
-    fn from_slice(xs: &[u8]) -> u32 {
-        u32::from(u16::from_le_bytes([xs[0], xs[1]]))
+    fn from_slice(xs: &[u8]) -> u64 {
+        u64::from(u16::from_le_bytes([xs[0], xs[1]]))
     }
 }
 
 impl LoadTy for i16 {
-    fn from_slice(xs: &[u8]) -> u32 {
-        i32::from(i16::from_le_bytes([xs[0], xs[1]])) as u32
+    fn from_slice(xs: &[u8]) -> u64 {
+        i64::from(i16::from_le_bytes([xs[0], xs[1]])) as u64
     }
 }
 
 impl LoadTy for u32 {
-    fn from_slice(xs: &[u8]) -> u32 {
-        u32::from_le_bytes([xs[0], xs[1], xs[2], xs[3]])
+    fn from_slice(xs: &[u8]) -> u64 {
+        u64::from(u32::from_le_bytes([xs[0], xs[1], xs[2], xs[3]]))
+    }
+}
+
+impl LoadTy for i32 {
+    fn from_slice(xs: &[u8]) -> u64 {
+        i64::from(i32::from_le_bytes([xs[0], xs[1], xs[2], xs[3]])) as u64
+    }
+}
+
+impl LoadTy for u64 {
+    fn from_slice(xs: &[u8]) -> u64 {
+        u64::from_le_bytes([xs[0], xs[1], xs[2], xs[3], xs[4], xs[5], xs[6], xs[7]])
     }
 }
 
 trait StoreTy: Sized {
     type Array: AsRef<[u8]>;
-    fn into_bytes(value: u32) -> Self::Array;
+    fn into_bytes(value: u64) -> Self::Array;
 }
 
 impl StoreTy for u8 {
     type Array = [u8; 1];
-    fn into_bytes(value: u32) -> Self::Array {
+    fn into_bytes(value: u64) -> Self::Array {
         (value as u8).to_le_bytes()
     }
 }
 
 impl StoreTy for u16 {
     type Array = [u8; 2];
-    fn into_bytes(value: u32) -> Self::Array {
+    fn into_bytes(value: u64) -> Self::Array {
         (value as u16).to_le_bytes()
     }
 }
 
 impl StoreTy for u32 {
     type Array = [u8; 4];
-    fn into_bytes(value: u32) -> Self::Array {
+    fn into_bytes(value: u64) -> Self::Array {
+        (value as u32).to_le_bytes()
+    }
+}
+
+impl StoreTy for u64 {
+    type Array = [u8; 8];
+    fn into_bytes(value: u64) -> Self::Array {
         value.to_le_bytes()
     }
 }
@@ -587,191 +1190,426 @@ impl<'a, 'b> InstructionVisitor for Visitor<'a, 'b> {
             self.inner.nth_instruction,
             self.inner.nth_basic_block
         );
-        Err(ExecutionError::Trap(Default::default()))
+        Err(self.trap_with_kind(TrapKind::TrapInstruction))
     }
 
     fn fallthrough(&mut self) -> Self::ReturnTy {
         self.inner.nth_instruction += 1;
         self.inner.nth_basic_block += 1;
-        self.inner.on_start_new_basic_block()
+        self.on_start_new_basic_block()
     }
 
     fn ecalli(&mut self, imm: u32) -> Self::ReturnTy {
         if let Some(on_hostcall) = self.ctx.on_hostcall.as_mut() {
+            let nth_instruction = self.inner.nth_instruction;
             let access = BackendAccess::Interpreted(self.inner.access());
-            (on_hostcall)(imm, access).map_err(ExecutionError::Trap)?;
+            let outcome = match (on_hostcall)(imm, access) {
+                Ok(outcome) => outcome,
+                Err(trap) => {
+                    // Same as with the compiled backends running out of gas takes precedence over the host function's error.
+                    self.inner.check_gas()?;
+                    return Err(ExecutionError::Trap(trap.with_program_counter(Some(nth_instruction))));
+                }
+            };
+
+            if outcome == HostcallOutcome::Suspend {
+                // Stay at the `ecalli` instruction; we'll skip over it when resumed.
+                self.inner.is_suspended = true;
+                self.inner.return_to_host = true;
+                return Ok(());
+            }
+
             self.inner.nth_instruction += 1;
-            self.inner.check_gas()?;
+            self.check_gas()?;
             Ok(())
         } else {
             log::debug!("Hostcall called without any hostcall handler set!");
-            Err(ExecutionError::Trap(Default::default()))
+            Err(self.trap_with_kind(TrapKind::Unknown))
         }
     }
 
     fn set_less_than_unsigned(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| u32::from(s1 < s2))
+        self.set3(d, s1, s2, |s1, s2| u32::from(s1 < s2), |s1, s2| u64::from(s1 < s2))
     }
 
     fn set_less_than_signed(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| u32::from((s1 as i32) < (s2 as i32)))
+        self.set3(
+            d,
+            s1,
+            s2,
+            |s1, s2| u32::from((s1 as i32) < (s2 as i32)),
+            |s1, s2| u64::from((s1 as i64) < (s2 as i64)),
+        )
     }
 
     fn shift_logical_right(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.set3(d, s1, s2, u32::wrapping_shr)
+        self.set3(d, s1, s2, u32::wrapping_shr, |s1, s2| s1.wrapping_shr(s2 as u32))
     }
 
     fn shift_arithmetic_right(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| ((s1 as i32).wrapping_shr(s2)) as u32)
+        self.set3(
+            d,
+            s1,
+            s2,
+            |s1, s2| ((s1 as i32).wrapping_shr(s2)) as u32,
+            |s1, s2| ((s1 as i64).wrapping_shr(s2 as u32)) as u64,
+        )
     }
 
     fn shift_logical_left(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.set3(d, s1, s2, u32::wrapping_shl)
+        self.set3(d, s1, s2, u32::wrapping_shl, |s1, s2| s1.wrapping_shl(s2 as u32))
     }
 
     fn xor(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| s1 ^ s2)
+        self.set3(d, s1, s2, |s1, s2| s1 ^ s2, |s1, s2| s1 ^ s2)
     }
 
     fn and(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| s1 & s2)
+        self.set3(d, s1, s2, |s1, s2| s1 & s2, |s1, s2| s1 & s2)
     }
 
     fn or(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| s1 | s2)
+        self.set3(d, s1, s2, |s1, s2| s1 | s2, |s1, s2| s1 | s2)
     }
 
     fn add(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.set3(d, s1, s2, u32::wrapping_add)
+        self.set3(d, s1, s2, u32::wrapping_add, u64::wrapping_add)
     }
 
     fn sub(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.set3(d, s1, s2, u32::wrapping_sub)
+        self.set3(d, s1, s2, u32::wrapping_sub, u64::wrapping_sub)
     }
 
     fn negate_and_add_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| s2.wrapping_sub(s1))
+        self.set3(d, s1, s2, |s1, s2| s2.wrapping_sub(s1), |s1, s2| s2.wrapping_sub(s1))
     }
 
     fn mul(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.set3(d, s1, s2, u32::wrapping_mul)
+        self.set3(d, s1, s2, u32::wrapping_mul, u64::wrapping_mul)
     }
 
     fn mul_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
-        self.set3(d, s1, s2, u32::wrapping_mul)
+        self.set3(d, s1, s2, u32::wrapping_mul, u64::wrapping_mul)
     }
 
     fn mul_upper_signed_signed(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| mulh(s1 as i32, s2 as i32) as u32)
+        self.set3(
+            d,
+            s1,
+            s2,
+            |s1, s2| mulh(s1 as i32, s2 as i32) as u32,
+            |s1, s2| mulh64(s1 as i64, s2 as i64) as u64,
+        )
     }
 
     fn mul_upper_signed_signed_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| mulh(s1 as i32, s2 as i32) as u32)
+        self.set3(
+            d,
+            s1,
+            s2,
+            |s1, s2| mulh(s1 as i32, s2 as i32) as u32,
+            |s1, s2| mulh64(s1 as i64, s2 as i64) as u64,
+        )
     }
 
     fn mul_upper_unsigned_unsigned(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.set3(d, s1, s2, mulhu)
+        self.set3(d, s1, s2, mulhu, mulhu64)
     }
 
     fn mul_upper_unsigned_unsigned_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
-        self.set3(d, s1, s2, mulhu)
+        self.set3(d, s1, s2, mulhu, mulhu64)
     }
 
     fn mul_upper_signed_unsigned(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| mulhsu(s1 as i32, s2) as u32)
+        self.set3(
+            d,
+            s1,
+            s2,
+            |s1, s2| mulhsu(s1 as i32, s2) as u32,
+            |s1, s2| mulhsu64(s1 as i64, s2) as u64,
+        )
     }
 
     fn div_unsigned(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.set3(d, s1, s2, divu)
+        self.set3(d, s1, s2, divu, divu64)
     }
 
     fn div_signed(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| div(s1 as i32, s2 as i32) as u32)
+        self.set3(
+            d,
+            s1,
+            s2,
+            |s1, s2| div(s1 as i32, s2 as i32) as u32,
+            |s1, s2| div64(s1 as i64, s2 as i64) as u64,
+        )
     }
 
     fn rem_unsigned(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.set3(d, s1, s2, remu)
+        self.set3(d, s1, s2, remu, remu64)
     }
 
     fn rem_signed(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| rem(s1 as i32, s2 as i32) as u32)
+        self.set3(
+            d,
+            s1,
+            s2,
+            |s1, s2| rem(s1 as i32, s2 as i32) as u32,
+            |s1, s2| rem64(s1 as i64, s2 as i64) as u64,
+        )
+    }
+
+    fn and_inverted(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3(d, s1, s2, |s1, s2| s1 & !s2, |s1, s2| s1 & !s2)
+    }
+
+    fn or_inverted(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3(d, s1, s2, |s1, s2| s1 | !s2, |s1, s2| s1 | !s2)
+    }
+
+    fn xnor(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3(d, s1, s2, |s1, s2| !(s1 ^ s2), |s1, s2| !(s1 ^ s2))
+    }
+
+    fn maximum(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3(
+            d,
+            s1,
+            s2,
+            |s1, s2| (s1 as i32).max(s2 as i32) as u32,
+            |s1, s2| (s1 as i64).max(s2 as i64) as u64,
+        )
+    }
+
+    fn maximum_unsigned(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3(d, s1, s2, u32::max, u64::max)
+    }
+
+    fn minimum(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3(
+            d,
+            s1,
+            s2,
+            |s1, s2| (s1 as i32).min(s2 as i32) as u32,
+            |s1, s2| (s1 as i64).min(s2 as i64) as u64,
+        )
+    }
+
+    fn minimum_unsigned(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3(d, s1, s2, u32::min, u64::min)
+    }
+
+    fn rotate_left(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3(d, s1, s2, u32::rotate_left, |s1, s2| s1.rotate_left(s2 as u32))
+    }
+
+    fn rotate_right(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3(d, s1, s2, u32::rotate_right, |s1, s2| s1.rotate_right(s2 as u32))
     }
 
     fn set_less_than_unsigned_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| u32::from(s1 < s2))
+        self.set3(d, s1, s2, |s1, s2| u32::from(s1 < s2), |s1, s2| u64::from(s1 < s2))
     }
 
     fn set_greater_than_unsigned_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| u32::from(s1 > s2))
+        self.set3(d, s1, s2, |s1, s2| u32::from(s1 > s2), |s1, s2| u64::from(s1 > s2))
     }
 
     fn set_less_than_signed_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| u32::from((s1 as i32) < (s2 as i32)))
+        self.set3(
+            d,
+            s1,
+            s2,
+            |s1, s2| u32::from((s1 as i32) < (s2 as i32)),
+            |s1, s2| u64::from((s1 as i64) < (s2 as i64)),
+        )
     }
 
     fn set_greater_than_signed_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| u32::from((s1 as i32) > (s2 as i32)))
+        self.set3(
+            d,
+            s1,
+            s2,
+            |s1, s2| u32::from((s1 as i32) > (s2 as i32)),
+            |s1, s2| u64::from((s1 as i64) > (s2 as i64)),
+        )
     }
 
     fn shift_logical_right_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
-        self.set3(d, s1, s2, u32::wrapping_shr)
+        self.set3(d, s1, s2, u32::wrapping_shr, |s1, s2| s1.wrapping_shr(s2 as u32))
     }
 
     fn shift_logical_right_imm_alt(&mut self, d: Reg, s2: Reg, s1: u32) -> Self::ReturnTy {
-        self.set3(d, s1, s2, u32::wrapping_shr)
+        self.set3(d, s1, s2, u32::wrapping_shr, |s1, s2| s1.wrapping_shr(s2 as u32))
     }
 
     fn shift_arithmetic_right_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| ((s1 as i32) >> s2) as u32)
+        self.set3(
+            d,
+            s1,
+            s2,
+            |s1, s2| ((s1 as i32) >> s2) as u32,
+            |s1, s2| ((s1 as i64).wrapping_shr(s2 as u32)) as u64,
+        )
     }
 
     fn shift_arithmetic_right_imm_alt(&mut self, d: Reg, s2: Reg, s1: u32) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| ((s1 as i32) >> s2) as u32)
+        self.set3(
+            d,
+            s1,
+            s2,
+            |s1, s2| ((s1 as i32) >> s2) as u32,
+            |s1, s2| ((s1 as i64).wrapping_shr(s2 as u32)) as u64,
+        )
     }
 
     fn shift_logical_left_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
-        self.set3(d, s1, s2, u32::wrapping_shl)
+        self.set3(d, s1, s2, u32::wrapping_shl, |s1, s2| s1.wrapping_shl(s2 as u32))
     }
 
     fn shift_logical_left_imm_alt(&mut self, d: Reg, s2: Reg, s1: u32) -> Self::ReturnTy {
-        self.set3(d, s1, s2, u32::wrapping_shl)
+        self.set3(d, s1, s2, u32::wrapping_shl, |s1, s2| s1.wrapping_shl(s2 as u32))
+    }
+
+    fn rotate_right_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        self.set3(d, s1, s2, u32::rotate_right, |s1, s2| s1.rotate_right(s2 as u32))
+    }
+
+    fn rotate_right_imm_alt(&mut self, d: Reg, s2: Reg, s1: u32) -> Self::ReturnTy {
+        self.set3(d, s1, s2, u32::rotate_right, |s1, s2| s1.rotate_right(s2 as u32))
+    }
+
+    fn rotate_left_imm_alt(&mut self, d: Reg, s2: Reg, s1: u32) -> Self::ReturnTy {
+        self.set3(d, s1, s2, u32::rotate_left, |s1, s2| s1.rotate_left(s2 as u32))
     }
 
     fn or_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| s1 | s2)
+        self.set3(d, s1, s2, |s1, s2| s1 | s2, |s1, s2| s1 | s2)
     }
 
     fn and_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| s1 & s2)
+        self.set3(d, s1, s2, |s1, s2| s1 & s2, |s1, s2| s1 & s2)
     }
 
     fn xor_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
-        self.set3(d, s1, s2, |s1, s2| s1 ^ s2)
+        self.set3(d, s1, s2, |s1, s2| s1 ^ s2, |s1, s2| s1 ^ s2)
     }
 
     fn load_imm(&mut self, dst: Reg, imm: u32) -> Self::ReturnTy {
-        self.set(dst, imm)?;
+        self.set(dst, self.get64(imm))?;
         self.inner.nth_instruction += 1;
         Ok(())
     }
 
     fn move_reg(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
-        let imm = self.get(s);
-        self.set(d, imm)?;
+        let value = self.get64(s);
+        self.set(d, value)?;
+        self.inner.nth_instruction += 1;
+        Ok(())
+    }
+
+    fn count_leading_zero_bits(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.set2(d, s, u32::leading_zeros, |s| u64::from(s.leading_zeros()))
+    }
+
+    fn count_trailing_zero_bits(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.set2(d, s, u32::trailing_zeros, |s| u64::from(s.trailing_zeros()))
+    }
+
+    fn count_set_bits(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.set2(d, s, u32::count_ones, |s| u64::from(s.count_ones()))
+    }
+
+    fn sign_extend_8(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.set2(d, s, |s| i32::from(s as i8) as u32, |s| i64::from(s as i8) as u64)
+    }
+
+    fn sign_extend_16(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.set2(d, s, |s| i32::from(s as i16) as u32, |s| i64::from(s as i16) as u64)
+    }
+
+    fn zero_extend_16(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.set2(d, s, |s| s & 0xffff, |s| s & 0xffff)
+    }
+
+    fn reverse_byte(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.set2(d, s, u32::swap_bytes, u64::swap_bytes)
+    }
+
+    fn sbrk(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        let size = self.get64(s);
+        let result = match u32::try_from(size) {
+            Ok(size) => {
+                self.consume_per_byte_gas(size, self.inner.module.sbrk_byte_cost())?;
+                self.inner.sbrk(size).unwrap_or(0)
+            }
+            // The heap can't grow by more than 4GB, so this always fails.
+            Err(_) => 0,
+        };
+        self.set(d, u64::from(result))?;
         self.inner.nth_instruction += 1;
         Ok(())
     }
 
     fn cmov_if_zero(&mut self, d: Reg, s: Reg, c: Reg) -> Self::ReturnTy {
-        self.set3(d, s, c, |s, c| if c == 0 { s } else { 0 })
+        self.set3(d, s, c, |s, c| if c == 0 { s } else { 0 }, |s, c| if c == 0 { s } else { 0 })
     }
 
     fn cmov_if_not_zero(&mut self, d: Reg, s: Reg, c: Reg) -> Self::ReturnTy {
-        self.set3(d, s, c, |s, c| if c != 0 { s } else { 0 })
+        self.set3(d, s, c, |s, c| if c != 0 { s } else { 0 }, |s, c| if c != 0 { s } else { 0 })
+    }
+
+    fn memcpy(&mut self, dst: Reg, src: Reg, count: Reg) -> Self::ReturnTy {
+        let dst_address = self.get32(dst);
+        let src_address = self.get32(src);
+        let count = self.get32(count);
+        self.consume_per_byte_gas(count, self.inner.module.bulk_memory_byte_cost())?;
+
+        if count != 0 {
+            log::trace!("memcpy [0x{dst_address:x}], [0x{src_address:x}], 0x{count:x}");
+            if let Err(kind) = self.inner.copy_memory(dst_address, src_address, count) {
+                log::debug!(
+                    "Copy of {count} bytes from 0x{src_address:x} to 0x{dst_address:x} failed! (pc = #{pc}, cycle = {cycle})",
+                    pc = self.inner.nth_instruction,
+                    cycle = self.inner.cycle_counter
+                );
+                self.inner
+                    .module
+                    .debug_print_location(log::Level::Debug, self.inner.nth_instruction);
+                return Err(self.trap_with_kind(kind));
+            }
+        }
+
+        self.inner.nth_instruction += 1;
+        Ok(())
+    }
+
+    fn memset(&mut self, dst: Reg, value: Reg, count: Reg) -> Self::ReturnTy {
+        let dst_address = self.get32(dst);
+        let value = self.get32(value) as u8;
+        let count = self.get32(count);
+        self.consume_per_byte_gas(count, self.inner.module.bulk_memory_byte_cost())?;
+
+        if count != 0 {
+            log::trace!("memset [0x{dst_address:x}], 0x{value:x}, 0x{count:x}");
+            if self.inner.fill_memory(dst_address, count, value).is_none() {
+                log::debug!(
+                    "Fill of {count} bytes at 0x{dst_address:x} failed! (pc = #{pc}, cycle = {cycle})",
+                    pc = self.inner.nth_instruction,
+                    cycle = self.inner.cycle_counter
+                );
+                self.inner
+                    .module
+                    .debug_print_location(log::Level::Debug, self.inner.nth_instruction);
+                return Err(self.trap_with_kind(TrapKind::InvalidStore { address: dst_address }));
+            }
+        }
+
+        self.inner.nth_instruction += 1;
+        Ok(())
     }
 
     fn add_imm(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
-        self.set3(d, s1, s2, u32::wrapping_add)
+        self.set3(d, s1, s2, u32::wrapping_add, u64::wrapping_add)
     }
 
     fn store_imm_u8(&mut self, value: u32, offset: u32) -> Self::ReturnTy {
@@ -863,67 +1701,67 @@ impl<'a, 'b> InstructionVisitor for Visitor<'a, 'b> {
     }
 
     fn branch_less_unsigned(&mut self, s1: Reg, s2: Reg, i: u32) -> Self::ReturnTy {
-        self.branch(s1, s2, i, |s1, s2| s1 < s2)
+        self.branch(s1, s2, i, |s1, s2| s1 < s2, |s1, s2| s1 < s2)
     }
 
     fn branch_less_unsigned_imm(&mut self, s1: Reg, s2: u32, i: u32) -> Self::ReturnTy {
-        self.branch(s1, s2, i, |s1, s2| s1 < s2)
+        self.branch(s1, s2, i, |s1, s2| s1 < s2, |s1, s2| s1 < s2)
     }
 
     fn branch_less_signed(&mut self, s1: Reg, s2: Reg, i: u32) -> Self::ReturnTy {
-        self.branch(s1, s2, i, |s1, s2| (s1 as i32) < (s2 as i32))
+        self.branch(s1, s2, i, |s1, s2| (s1 as i32) < (s2 as i32), |s1, s2| (s1 as i64) < (s2 as i64))
     }
 
     fn branch_less_signed_imm(&mut self, s1: Reg, s2: u32, i: u32) -> Self::ReturnTy {
-        self.branch(s1, s2, i, |s1, s2| (s1 as i32) < (s2 as i32))
+        self.branch(s1, s2, i, |s1, s2| (s1 as i32) < (s2 as i32), |s1, s2| (s1 as i64) < (s2 as i64))
     }
 
     fn branch_eq(&mut self, s1: Reg, s2: Reg, i: u32) -> Self::ReturnTy {
-        self.branch(s1, s2, i, |s1, s2| s1 == s2)
+        self.branch(s1, s2, i, |s1, s2| s1 == s2, |s1, s2| s1 == s2)
     }
 
     fn branch_eq_imm(&mut self, s1: Reg, s2: u32, i: u32) -> Self::ReturnTy {
-        self.branch(s1, s2, i, |s1, s2| s1 == s2)
+        self.branch(s1, s2, i, |s1, s2| s1 == s2, |s1, s2| s1 == s2)
     }
 
     fn branch_not_eq(&mut self, s1: Reg, s2: Reg, i: u32) -> Self::ReturnTy {
-        self.branch(s1, s2, i, |s1, s2| s1 != s2)
+        self.branch(s1, s2, i, |s1, s2| s1 != s2, |s1, s2| s1 != s2)
     }
 
     fn branch_not_eq_imm(&mut self, s1: Reg, s2: u32, i: u32) -> Self::ReturnTy {
-        self.branch(s1, s2, i, |s1, s2| s1 != s2)
+        self.branch(s1, s2, i, |s1, s2| s1 != s2, |s1, s2| s1 != s2)
     }
 
     fn branch_greater_or_equal_unsigned(&mut self, s1: Reg, s2: Reg, i: u32) -> Self::ReturnTy {
-        self.branch(s1, s2, i, |s1, s2| s1 >= s2)
+        self.branch(s1, s2, i, |s1, s2| s1 >= s2, |s1, s2| s1 >= s2)
     }
 
     fn branch_greater_or_equal_unsigned_imm(&mut self, s1: Reg, s2: u32, i: u32) -> Self::ReturnTy {
-        self.branch(s1, s2, i, |s1, s2| s1 >= s2)
+        self.branch(s1, s2, i, |s1, s2| s1 >= s2, |s1, s2| s1 >= s2)
     }
 
     fn branch_greater_or_equal_signed(&mut self, s1: Reg, s2: Reg, i: u32) -> Self::ReturnTy {
-        self.branch(s1, s2, i, |s1, s2| (s1 as i32) >= (s2 as i32))
+        self.branch(s1, s2, i, |s1, s2| (s1 as i32) >= (s2 as i32), |s1, s2| (s1 as i64) >= (s2 as i64))
     }
 
     fn branch_greater_or_equal_signed_imm(&mut self, s1: Reg, s2: u32, i: u32) -> Self::ReturnTy {
-        self.branch(s1, s2, i, |s1, s2| (s1 as i32) >= (s2 as i32))
+        self.branch(s1, s2, i, |s1, s2| (s1 as i32) >= (s2 as i32), |s1, s2| (s1 as i64) >= (s2 as i64))
     }
 
     fn branch_less_or_equal_unsigned_imm(&mut self, s1: Reg, s2: u32, i: u32) -> Self::ReturnTy {
-        self.branch(s1, s2, i, |s1, s2| s1 <= s2)
+        self.branch(s1, s2, i, |s1, s2| s1 <= s2, |s1, s2| s1 <= s2)
     }
 
     fn branch_less_or_equal_signed_imm(&mut self, s1: Reg, s2: u32, i: u32) -> Self::ReturnTy {
-        self.branch(s1, s2, i, |s1, s2| (s1 as i32) <= (s2 as i32))
+        self.branch(s1, s2, i, |s1, s2| (s1 as i32) <= (s2 as i32), |s1, s2| (s1 as i64) <= (s2 as i64))
     }
 
     fn branch_greater_unsigned_imm(&mut self, s1: Reg, s2: u32, i: u32) -> Self::ReturnTy {
-        self.branch(s1, s2, i, |s1, s2| s1 > s2)
+        self.branch(s1, s2, i, |s1, s2| s1 > s2, |s1, s2| s1 > s2)
     }
 
     fn branch_greater_signed_imm(&mut self, s1: Reg, s2: u32, i: u32) -> Self::ReturnTy {
-        self.branch(s1, s2, i, |s1, s2| (s1 as i32) > (s2 as i32))
+        self.branch(s1, s2, i, |s1, s2| (s1 as i32) > (s2 as i32), |s1, s2| (s1 as i64) > (s2 as i64))
     }
 
     fn jump(&mut self, target: u32) -> Self::ReturnTy {
@@ -936,7 +1774,7 @@ impl<'a, 'b> InstructionVisitor for Visitor<'a, 'b> {
         log::trace!("Static jump to: #{nth_instruction}: @{target:x}");
         self.inner.nth_basic_block = target;
         self.inner.nth_instruction = nth_instruction;
-        self.inner.on_start_new_basic_block()
+        self.on_start_new_basic_block()
     }
 
     fn jump_indirect(&mut self, base: Reg, offset: u32) -> Self::ReturnTy {
@@ -953,4 +1791,184 @@ impl<'a, 'b> InstructionVisitor for Visitor<'a, 'b> {
         let return_address = self.get_return_address();
         self.dynamic_jump(Some((ra, return_address)), base, offset)
     }
+
+    fn load_i32(&mut self, dst: Reg, offset: u32) -> Self::ReturnTy {
+        self.load::<i32>(dst, None, offset)
+    }
+
+    fn load_u64(&mut self, dst: Reg, offset: u32) -> Self::ReturnTy {
+        self.load::<u64>(dst, None, offset)
+    }
+
+    fn store_u64(&mut self, src: Reg, offset: u32) -> Self::ReturnTy {
+        self.store::<u64>(src, None, offset)
+    }
+
+    fn store_imm_u64(&mut self, value: u32, offset: u32) -> Self::ReturnTy {
+        self.store::<u64>(value, None, offset)
+    }
+
+    fn store_imm_indirect_u64(&mut self, base: Reg, offset: u32, value: u32) -> Self::ReturnTy {
+        self.store::<u64>(value, Some(base), offset)
+    }
+
+    fn load_indirect_i32(&mut self, dst: Reg, base: Reg, offset: u32) -> Self::ReturnTy {
+        self.load::<i32>(dst, Some(base), offset)
+    }
+
+    fn load_indirect_u64(&mut self, dst: Reg, base: Reg, offset: u32) -> Self::ReturnTy {
+        self.load::<u64>(dst, Some(base), offset)
+    }
+
+    fn store_indirect_u64(&mut self, src: Reg, base: Reg, offset: u32) -> Self::ReturnTy {
+        self.store::<u64>(src, Some(base), offset)
+    }
+
+    fn add_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        self.set3_32(d, s1, s2, u32::wrapping_add)
+    }
+
+    fn shift_logical_left_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        self.set3_32(d, s1, s2, u32::wrapping_shl)
+    }
+
+    fn shift_logical_right_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        self.set3_32(d, s1, s2, u32::wrapping_shr)
+    }
+
+    fn shift_arithmetic_right_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        self.set3_32(d, s1, s2, |s1, s2| ((s1 as i32).wrapping_shr(s2)) as u32)
+    }
+
+    fn rotate_right_imm_32(&mut self, d: Reg, s1: Reg, s2: u32) -> Self::ReturnTy {
+        self.set3_32(d, s1, s2, u32::rotate_right)
+    }
+
+    fn add_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3_32(d, s1, s2, u32::wrapping_add)
+    }
+
+    fn sub_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3_32(d, s1, s2, u32::wrapping_sub)
+    }
+
+    fn mul_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3_32(d, s1, s2, u32::wrapping_mul)
+    }
+
+    fn shift_logical_left_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3_32(d, s1, s2, u32::wrapping_shl)
+    }
+
+    fn shift_logical_right_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3_32(d, s1, s2, u32::wrapping_shr)
+    }
+
+    fn shift_arithmetic_right_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3_32(d, s1, s2, |s1, s2| ((s1 as i32).wrapping_shr(s2)) as u32)
+    }
+
+    fn div_unsigned_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3_32(d, s1, s2, divu)
+    }
+
+    fn div_signed_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3_32(d, s1, s2, |s1, s2| div(s1 as i32, s2 as i32) as u32)
+    }
+
+    fn rem_unsigned_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3_32(d, s1, s2, remu)
+    }
+
+    fn rem_signed_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3_32(d, s1, s2, |s1, s2| rem(s1 as i32, s2 as i32) as u32)
+    }
+
+    fn rotate_left_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3_32(d, s1, s2, u32::rotate_left)
+    }
+
+    fn rotate_right_32(&mut self, d: Reg, s1: Reg, s2: Reg) -> Self::ReturnTy {
+        self.set3_32(d, s1, s2, u32::rotate_right)
+    }
+
+    fn count_leading_zero_bits_32(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.set2_32(d, s, u32::leading_zeros)
+    }
+
+    fn count_trailing_zero_bits_32(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.set2_32(d, s, u32::trailing_zeros)
+    }
+
+    fn count_set_bits_32(&mut self, d: Reg, s: Reg) -> Self::ReturnTy {
+        self.set2_32(d, s, u32::count_ones)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BackendKind, Config, Engine, ModuleConfig};
+    use polkavm_common::elf::FnMetadata;
+    use polkavm_common::program::{asm, ProgramBlob};
+    use polkavm_common::writer::ProgramBlobBuilder;
+
+    #[test]
+    fn writes_only_copy_the_touched_heap_pages() {
+        let mut builder = ProgramBlobBuilder::new();
+        builder.add_export(0, &FnMetadata::new("main", &[], None));
+        builder.set_code(&[asm::ret()]);
+        builder.set_rw_data(vec![1; PAGE_SIZE * 3]);
+        builder.set_bss_size(VM_PAGE_SIZE);
+
+        let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+        let mut config = Config::default();
+        config.set_backend(Some(BackendKind::Interpreter));
+        let engine = Engine::new(&config).unwrap();
+        let module = Module::from_blob(&engine, &ModuleConfig::default(), &blob).unwrap();
+        let heap_address = module.memory_config().heap_address();
+        let initial_heap = &module.interpreted_module().unwrap().initial_heap;
+
+        let mut instance_1 = InterpretedInstance::new(module.clone(), None).unwrap();
+        let mut instance_2 = InterpretedInstance::new(module.clone(), None).unwrap();
+        let is_shared =
+            |instance: &InterpretedInstance, nth_page: usize| Arc::ptr_eq(&instance.heap.pages[nth_page], &initial_heap.pages[nth_page]);
+
+        assert_eq!(instance_1.heap.pages.len(), 4);
+        assert!((0..4).all(|nth_page| is_shared(&instance_1, nth_page)));
+
+        instance_1.access().write_memory(heap_address + VM_PAGE_SIZE + 16, &[2; 4]).unwrap();
+        assert!(is_shared(&instance_1, 0));
+        assert!(!is_shared(&instance_1, 1));
+        assert!(is_shared(&instance_1, 2));
+        assert!(is_shared(&instance_1, 3));
+        assert!((0..4).all(|nth_page| is_shared(&instance_2, nth_page)));
+
+        // A write which crosses a page boundary only copies the two pages it touches.
+        instance_1
+            .access()
+            .write_memory(heap_address + VM_PAGE_SIZE * 3 - 2, &[3; 4])
+            .unwrap();
+        assert!(is_shared(&instance_1, 0));
+        assert!(!is_shared(&instance_1, 2));
+        assert!(!is_shared(&instance_1, 3));
+
+        let access = instance_1.access();
+        assert_eq!(
+            access.read_memory_into_new_vec(heap_address + VM_PAGE_SIZE + 14, 8).unwrap(),
+            [1, 1, 2, 2, 2, 2, 1, 1]
+        );
+        assert_eq!(
+            access.read_memory_into_new_vec(heap_address + VM_PAGE_SIZE * 3 - 4, 8).unwrap(),
+            [1, 1, 3, 3, 3, 3, 0, 0]
+        );
+
+        assert_eq!(
+            instance_2
+                .access()
+                .read_memory_into_new_vec(heap_address + VM_PAGE_SIZE + 14, 8)
+                .unwrap(),
+            [1; 8]
+        );
+    }
 }
@@ -44,6 +44,7 @@ mod error;
 mod api;
 mod caller;
 mod config;
+mod guest_ptr;
 mod interpreter;
 mod source_cache;
 mod tracer;
@@ -55,17 +56,19 @@ if_compiler_is_supported! {
 }
 
 pub use polkavm_common::{
-    error::{ExecutionError, Trap},
-    program::{ProgramBlob, ProgramParseError, Reg},
+    error::{Backtrace, BacktraceFrame, BacktraceSymbol, ExecutionError, Trap, TrapKind},
+    program::{ExternFnPrototype, ExternTy, Opcode, ProgramBlob, ProgramExport, ProgramImport, ProgramParseError, Reg},
     utils::{AsUninitSliceMut, Gas},
 };
 
 pub use crate::api::{
-    Engine, ExecutionConfig, Func, FuncType, Instance, InstancePre, IntoExternFn, Linker, Module, TypedFunc, Val, ValType,
+    CallState, Engine, ExecutionConfig, ExecutionReport, Func, FuncType, Instance, InstancePre, InstanceSnapshot, InterruptHandle,
+    IntoExternFn, IntoExternFnAsync, Linker, MemoryMap, Module, TypedFunc, Val, ValType,
 };
 pub use crate::caller::{Caller, CallerRef};
-pub use crate::config::{BackendKind, Config, GasMeteringKind, ModuleConfig, SandboxKind};
+pub use crate::config::{BackendKind, Config, GasCostModel, GasMeteringKind, ModuleConfig, SandboxKind};
 pub use crate::error::Error;
+pub use crate::guest_ptr::{GuestPtr, GuestSlice, Pod};
 
 #[cfg(test)]
 mod tests;
@@ -1,4 +1,6 @@
 use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Instant;
 
 use polkavm_common::{
     abi::VM_PAGE_SIZE,
@@ -10,12 +12,14 @@ use polkavm_common::{
         SandboxMemoryConfig,
         VM_RPC_FLAG_CLEAR_PROGRAM_AFTER_EXECUTION,
         VM_RPC_FLAG_RECONFIGURE, VM_RPC_FLAG_RESET_MEMORY_AFTER_EXECUTION,
+        VM_RPC_FLAG_SET_HEAP_TOP,
     },
     utils::{Access, Gas}
 };
 
-use crate::api::BackendAccess;
+use crate::api::{BackendAccess, HostcallOutcome, InterruptState};
 use crate::config::{GasMeteringKind, SandboxKind};
+use crate::Error;
 
 macro_rules! get_field_offset {
     ($struct:expr, |$struct_ident:ident| $get_field:expr) => {{
@@ -65,6 +69,9 @@ pub(crate) fn assert_native_page_size() {
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub(crate) struct OutOfGas;
 
+/// How often the sandboxes check whether a program running with asynchronous gas metering has run out of gas.
+pub(crate) const ASYNC_GAS_METERING_CHECK_INTERVAL: core::time::Duration = core::time::Duration::from_millis(10);
+
 pub trait SandboxConfig: Default {
     fn enable_logger(&mut self, value: bool);
 }
@@ -88,6 +95,9 @@ pub(crate) trait Sandbox: Sized {
 
     fn reserve_address_space() -> Result<Self::AddressSpace, Self::Error>;
     fn prepare_program(init: SandboxProgramInit, address_space: Self::AddressSpace, gas_metering: Option<GasMeteringKind>) -> Result<Self::Program, Self::Error>;
+
+    /// Prepares a copy of an already prepared program which starts with the given heap contents instead of its original read-write data.
+    fn prepare_program_with_initial_heap(program: &Self::Program, heap: &[u8]) -> Result<Self::Program, Self::Error>;
     fn spawn(config: &Self::Config) -> Result<Self, Self::Error>;
     fn execute(&mut self, args: ExecuteArgs<Self>) -> Result<(), ExecutionError<Self::Error>>;
     fn access(&'_ mut self) -> Self::Access<'_>;
@@ -95,11 +105,38 @@ pub(crate) trait Sandbox: Sized {
     fn address_table() -> AddressTable;
     fn vmctx_regs_offset() -> usize;
     fn vmctx_gas_offset() -> usize;
+
+    /// The offset of the current end of the heap within the VM context; only its lower 32 bits are read.
+    fn vmctx_heap_top_offset() -> usize;
+
+    /// The offset of the 64-bit counter of executed basic blocks within the VM context.
+    fn vmctx_basic_blocks_executed_offset() -> usize;
+
+    /// Returns the total number of basic blocks executed by this sandbox; only counted if enabled in the `ModuleConfig`.
+    fn basic_blocks_executed(&self) -> u64;
     fn gas_remaining_impl(&self) -> Result<Option<Gas>, OutOfGas>;
     fn sync(&mut self) -> Result<(), Self::Error>;
+
+    /// Returns the native registers at the time of the last trap, if it was triggered by a signal.
+    fn trap_native_regs(&self) -> Option<[u64; 16]>;
+
+    /// Calls into the guest program at the given address from within a hostcall.
+    ///
+    /// The state of the execution which is in the middle of the hostcall is restored once the nested call finishes,
+    /// right after `on_finish` gets a chance to look at the state in which the nested call has ended.
+    fn call_nested<'a>(
+        access: &mut Self::Access<'_>,
+        address: u64,
+        regs: &[u64],
+        on_hostcall: OnHostcall<'a, Self>,
+        on_finish: OnNestedCallFinished<'a, Self>,
+    ) -> Result<(), ExecutionError<Error>>
+    where
+        Self: 'a;
 }
 
-pub(crate) type OnHostcall<'a, T> = &'a mut dyn for<'r> FnMut(u32, <T as Sandbox>::Access<'r>) -> Result<(), Trap>;
+pub(crate) type OnHostcall<'a, T> = &'a mut dyn for<'r> FnMut(u32, <T as Sandbox>::Access<'r>) -> Result<HostcallOutcome, Trap>;
+pub(crate) type OnNestedCallFinished<'a, T> = &'a mut dyn FnMut(&mut T, Result<(), ExecutionError<<T as Sandbox>::Error>>) -> Result<(), ExecutionError<Error>>;
 
 #[derive(Copy, Clone)]
 pub struct SandboxProgramInit<'a> {
@@ -152,19 +189,41 @@ impl<'a> SandboxProgramInit<'a> {
         config.set_guest_config(self.guest_init.memory_config()?);
         config.set_code_size(native_page_size, self.code.len())?;
         config.set_jump_table_size(native_page_size, self.jump_table.len())?;
+        config.set_max_heap_size(self.guest_init.max_heap_size());
 
         Ok(config)
     }
 }
 
+/// Returns the memory configuration under which the given heap contents can be used as the program's read-write data.
+///
+/// The trailing zeros of the heap are moved into the BSS section, so they don't have to be explicitly stored anywhere.
+/// The heap can be bigger than the original one if it was grown with `sbrk`.
+fn memory_config_for_initial_heap(mut config: SandboxMemoryConfig, heap: &[u8]) -> Result<(SandboxMemoryConfig, &[u8]), &'static str> {
+    assert!(heap.len() >= config.heap_size() as usize);
+    assert_eq!(heap.len() % VM_PAGE_SIZE as usize, 0);
+
+    let rw_data_length = heap.iter().rposition(|&byte| byte != 0).map_or(0, |position| position + 1);
+    config.set_bss_size(0)?;
+    config.set_rw_data_size(rw_data_length as u32)?;
+    let bss_size = heap.len() as u32 - config.rw_data_size();
+    config.set_bss_size(bss_size)?;
+
+    Ok((config, &heap[..rw_data_length]))
+}
+
 pub(crate) struct ExecuteArgs<'a, T> where T: Sandbox + 'a {
     rpc_address: u64,
     rpc_flags: u32,
     program: Option<&'a T::Program>,
     on_hostcall: Option<OnHostcall<'a, T>>,
-    initial_regs: &'a [u32],
+    initial_regs: &'a [u64],
     gas: Option<Gas>,
+    heap_top: u32,
     is_async: bool,
+    is_resume: bool,
+    interrupt: Option<Arc<InterruptState>>,
+    deadline: Option<Instant>,
 }
 
 impl<'a, T> Default for ExecuteArgs<'a, T> where T: Sandbox {
@@ -176,7 +235,7 @@ impl<'a, T> Default for ExecuteArgs<'a, T> where T: Sandbox {
 impl<'a, T> ExecuteArgs<'a, T> where T: Sandbox {
     #[inline]
     pub fn new() -> Self {
-        static EMPTY_REGS: &[u32; Reg::ALL.len()] = &[0; Reg::ALL.len()];
+        static EMPTY_REGS: &[u64; Reg::ALL.len()] = &[0; Reg::ALL.len()];
         ExecuteArgs {
             rpc_address: 0,
             rpc_flags: 0,
@@ -184,7 +243,11 @@ impl<'a, T> ExecuteArgs<'a, T> where T: Sandbox {
             on_hostcall: None,
             initial_regs: EMPTY_REGS,
             gas: None,
+            heap_top: 0,
             is_async: false,
+            is_resume: false,
+            interrupt: None,
+            deadline: None,
         }
     }
 
@@ -204,18 +267,35 @@ impl<'a, T> ExecuteArgs<'a, T> where T: Sandbox {
         self.rpc_flags |= VM_RPC_FLAG_CLEAR_PROGRAM_AFTER_EXECUTION;
     }
 
+    /// Grows or shrinks the heap so that it ends at the given address.
+    #[inline]
+    pub fn set_heap_top(&mut self, heap_top: u32) {
+        self.rpc_flags |= VM_RPC_FLAG_SET_HEAP_TOP;
+        self.heap_top = heap_top;
+    }
+
     #[inline]
     pub fn set_call(&mut self, address: u64) {
         self.rpc_address = address;
     }
 
+    /// Resumes an execution which was previously suspended in a hostcall.
+    ///
+    /// The `address` is the native address from which the execution should be continued
+    /// if the sandbox needs to reenter the guest program.
+    #[inline]
+    pub fn set_resume(&mut self, address: u64) {
+        self.rpc_address = address;
+        self.is_resume = true;
+    }
+
     #[inline]
     pub fn set_on_hostcall(&mut self, callback: OnHostcall<'a, T>) {
         self.on_hostcall = Some(callback);
     }
 
     #[inline]
-    pub fn set_initial_regs(&mut self, regs: &'a [u32]) {
+    pub fn set_initial_regs(&mut self, regs: &'a [u64]) {
         assert_eq!(regs.len(), Reg::ALL.len());
         self.initial_regs = regs;
     }
@@ -230,6 +310,16 @@ impl<'a, T> ExecuteArgs<'a, T> where T: Sandbox {
         self.is_async = value;
     }
 
+    #[inline]
+    pub fn set_interrupt(&mut self, interrupt: Arc<InterruptState>) {
+        self.interrupt = Some(interrupt);
+    }
+
+    #[inline]
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        self.deadline = Some(deadline);
+    }
+
     fn get_gas(&self, gas_metering: Option<GasMeteringKind>) -> Option<i64> {
         if self.program.is_none() && self.gas.is_none() && gas_metering.is_some() {
             // Keep whatever value was set there previously.
@@ -260,7 +350,7 @@ macro_rules! sandbox_tests {
             use polkavm_assembler::Assembler;
             use polkavm_common::init::GuestProgramInit;
             use polkavm_common::utils::Access;
-            use polkavm_common::error::ExecutionError;
+            use polkavm_common::error::{ExecutionError, TrapKind};
 
             use crate::sandbox::$sandbox_kind::{Sandbox, SandboxConfig};
 
@@ -471,7 +561,7 @@ macro_rules! sandbox_tests {
                     args.set_program(&program);
                     args.set_call(native_code_address);
                     match sandbox.execute(args) {
-                        Err(ExecutionError::Trap(_)) => {}
+                        Err(ExecutionError::Trap(trap)) => assert_eq!(trap.kind(), TrapKind::InvalidLoad { address: 0 }),
                         _ => panic!(),
                     }
 
@@ -486,7 +576,7 @@ macro_rules! sandbox_tests {
                     let mut args = ExecuteArgs::new();
                     args.set_call(native_code_address);
                     match sandbox.execute(args) {
-                        Err(ExecutionError::Trap(_)) => {}
+                        Err(ExecutionError::Trap(trap)) => assert_eq!(trap.kind(), TrapKind::InvalidLoad { address: 0 }),
                         _ => panic!(),
                     }
 
@@ -531,7 +621,7 @@ macro_rules! sandbox_tests {
                     args.set_program(&program);
                     args.set_call(native_code_address);
                     match sandbox.execute(args) {
-                        Err(ExecutionError::Trap(_)) => {}
+                        Err(ExecutionError::Trap(trap)) => assert_eq!(trap.kind(), TrapKind::DivisionFault),
                         _ => panic!(),
                     }
 
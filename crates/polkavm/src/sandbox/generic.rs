@@ -1,9 +1,10 @@
 #![allow(clippy::manual_range_contains)]
 
 use polkavm_common::{
-    error::{ExecutionError, Trap},
+    abi::VM_PAGE_SIZE,
+    error::{ExecutionError, Trap, TrapKind},
     program::Reg,
-    utils::{byte_slice_init, Access, AsUninitSliceMut, Gas},
+    utils::{align_to_next_page_u32, byte_slice_init, Access, AsUninitSliceMut, Gas},
     zygote::{
         AddressTable,
         AddressTableRaw,
@@ -11,6 +12,7 @@ use polkavm_common::{
         SandboxMemoryConfig,
         VM_RPC_FLAG_CLEAR_PROGRAM_AFTER_EXECUTION,
         VM_RPC_FLAG_RESET_MEMORY_AFTER_EXECUTION,
+        VM_RPC_FLAG_SET_HEAP_TOP,
         VM_ADDR_JUMP_TABLE,
         VM_ADDR_JUMP_TABLE_RETURN_TO_HOST,
         VM_SANDBOX_MAXIMUM_NATIVE_CODE_SIZE,
@@ -25,10 +27,11 @@ use core::cell::UnsafeCell;
 use core::sync::atomic::{AtomicUsize, Ordering};
 use core::mem::MaybeUninit;
 use std::borrow::Cow;
-use std::sync::Arc;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock};
+use std::time::Instant;
 
-use super::{OnHostcall, SandboxKind, SandboxProgramInit, get_native_page_size};
-use crate::api::{BackendAccess, MemoryAccessError};
+use super::{OnHostcall, OnNestedCallFinished, SandboxKind, SandboxProgramInit, get_native_page_size};
+use crate::api::{BackendAccess, HostcallOutcome, InterruptState, MemoryAccessError};
 use crate::config::GasMeteringKind;
 
 // On Linux don't depend on the `libc` crate to lower the number of dependencies.
@@ -38,6 +41,7 @@ mod sys {
     pub use polkavm_linux_raw::{c_void, c_int, size_t, siginfo_t, SIG_IGN, SIG_DFL, ucontext as ucontext_t};
     pub const SIGSEGV: c_int = polkavm_linux_raw::SIGSEGV as c_int;
     pub const SIGILL: c_int = polkavm_linux_raw::SIGILL as c_int;
+    pub const SIGURG: c_int = polkavm_linux_raw::SIGURG as c_int;
     pub const PROT_READ: c_int = polkavm_linux_raw::PROT_READ as c_int;
     pub const PROT_WRITE: c_int = polkavm_linux_raw::PROT_WRITE as c_int;
     pub const PROT_EXEC: c_int = polkavm_linux_raw::PROT_EXEC as c_int;
@@ -47,8 +51,10 @@ mod sys {
     pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
     pub const SA_SIGINFO: c_int = polkavm_linux_raw::SA_SIGINFO as c_int;
     pub const SA_NODEFER: c_int = polkavm_linux_raw::SA_NODEFER as c_int;
+    pub const SA_RESTART: c_int = polkavm_linux_raw::SA_RESTART as c_int;
 
     pub type sighandler_t = size_t;
+    pub type pthread_t = core::ffi::c_ulong;
 
     #[repr(C)]
     pub struct sigset_t {
@@ -94,6 +100,13 @@ mod sys {
         ) -> c_int;
 
         pub fn sigemptyset(set: *mut sigset_t) -> c_int;
+
+        pub fn pthread_self() -> pthread_t;
+
+        pub fn pthread_kill(
+            thread: pthread_t,
+            sig: c_int
+        ) -> c_int;
     }
 }
 
@@ -276,6 +289,7 @@ impl Drop for Mmap {
 
 static mut OLD_SIGSEGV: MaybeUninit<sys::sigaction> = MaybeUninit::uninit();
 static mut OLD_SIGILL: MaybeUninit<sys::sigaction> = MaybeUninit::uninit();
+static mut OLD_SIGURG: MaybeUninit<sys::sigaction> = MaybeUninit::uninit();
 
 #[cfg(any(target_os = "macos", target_os = "freebsd"))]
 static mut OLD_SIGBUS: MaybeUninit<sys::sigaction> = MaybeUninit::uninit();
@@ -284,6 +298,7 @@ unsafe extern "C" fn signal_handler(signal: c_int, info: &sys::siginfo_t, contex
     let old = match signal {
         sys::SIGSEGV => &OLD_SIGSEGV,
         sys::SIGILL => &OLD_SIGILL,
+        sys::SIGURG => &*core::ptr::addr_of!(OLD_SIGURG),
         #[cfg(any(target_os = "macos", target_os = "freebsd"))]
         sys::SIGBUS => &OLD_SIGBUS,
         _ => unreachable!("received unknown signal")
@@ -306,11 +321,19 @@ unsafe extern "C" fn signal_handler(signal: c_int, info: &sys::siginfo_t, contex
         }
 
         let vmctx = &mut *vmctx;
-        if vmctx.program_range.contains(&rip) {
+        if signal == sys::SIGURG {
+            // We were poked by the gas watchdog. Just stop the execution if we're inside of the guest program;
+            // the gas check after the execution is finished will then report that we've run out of gas.
+            if vmctx.program_range.contains(&rip) && core::ptr::read_volatile(&vmctx.gas) < 0 {
+                sysreturn(vmctx);
+            }
+        } else if vmctx.program_range.contains(&rip) {
             vmctx.native_program_counter = Some(rip);
 
             log::trace!("Trap triggered at 0x{rip:x}");
-            trigger_trap(vmctx);
+            vmctx.trap_native_regs = Some(get_native_regs(context));
+            let kind = get_trap_kind(vmctx, signal, info, context);
+            trigger_trap(vmctx, Trap::new(kind));
         }
     }
 
@@ -318,7 +341,10 @@ unsafe extern "C" fn signal_handler(signal: c_int, info: &sys::siginfo_t, contex
 
     let old = &*old.as_ptr();
     if old.sa_sigaction == sys::SIG_IGN || old.sa_sigaction == sys::SIG_DFL {
-        sys::sigaction(signal, old, core::ptr::null_mut());
+        // SIGURG is ignored by default, and we still need our handler for the gas watchdog.
+        if signal != sys::SIGURG {
+            sys::sigaction(signal, old, core::ptr::null_mut());
+        }
         return;
     }
 
@@ -331,12 +357,80 @@ unsafe extern "C" fn signal_handler(signal: c_int, info: &sys::siginfo_t, contex
     }
 }
 
+unsafe fn get_native_regs(context: &sys::ucontext_t) -> [u64; 16] {
+    #[cfg(target_os = "linux")]
+    {
+        let m = &context.uc_mcontext;
+        [m.rax, m.rcx, m.rdx, m.rbx, m.rsp, m.rbp, m.rsi, m.rdi, m.r8, m.r9, m.r10, m.r11, m.r12, m.r13, m.r14, m.r15]
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let m = &(*context.uc_mcontext).__ss;
+        [
+            m.__rax, m.__rcx, m.__rdx, m.__rbx, m.__rsp, m.__rbp, m.__rsi, m.__rdi,
+            m.__r8, m.__r9, m.__r10, m.__r11, m.__r12, m.__r13, m.__r14, m.__r15,
+        ]
+    }
+    #[cfg(target_os = "freebsd")]
+    {
+        let m = &context.uc_mcontext;
+        [
+            m.mc_rax, m.mc_rcx, m.mc_rdx, m.mc_rbx, m.mc_rsp, m.mc_rbp, m.mc_rsi, m.mc_rdi,
+            m.mc_r8, m.mc_r9, m.mc_r10, m.mc_r11, m.mc_r12, m.mc_r13, m.mc_r14, m.mc_r15,
+        ].map(|value| value as u64)
+    }
+}
+
+unsafe fn get_trap_kind(vmctx: &VmCtx, signal: c_int, info: &sys::siginfo_t, context: &sys::ucontext_t) -> TrapKind {
+    if signal == sys::SIGILL {
+        return TrapKind::TrapInstruction;
+    }
+
+    let fault_address;
+    let is_write;
+    #[cfg(target_os = "linux")]
+    {
+        fault_address = info.si_addr() as u64;
+        // Bit 1 of the page fault error code is set if the access was a write.
+        is_write = context.uc_mcontext.err & (1 << 1) != 0;
+    }
+    #[cfg(target_os = "macos")]
+    {
+        fault_address = info.si_addr as u64;
+        is_write = (*context.uc_mcontext).__es.__err & (1 << 1) != 0;
+    }
+    #[cfg(target_os = "freebsd")]
+    {
+        fault_address = info.si_addr as u64;
+        is_write = context.uc_mcontext.mc_err & (1 << 1) != 0;
+    }
+
+    let guest_memory = (vmctx as *const VmCtx).cast::<u8>().offset(-GUEST_MEMORY_TO_VMCTX_OFFSET) as u64;
+    if let Some(address) = fault_address.checked_sub(guest_memory).and_then(|address| u32::try_from(address).ok()) {
+        if is_write {
+            TrapKind::InvalidStore { address }
+        } else {
+            TrapKind::InvalidLoad { address }
+        }
+    } else if vmctx.program_range.contains(&fault_address) {
+        // The only memory accesses we do within the program's range are jump table lookups.
+        TrapKind::InvalidJumpTarget
+    } else {
+        TrapKind::Unknown
+    }
+}
+
 #[allow(clippy::fn_to_numeric_cast_any)]
 unsafe fn register_signal_handler_for_signal(signal: c_int, old_sa: &mut MaybeUninit<sys::sigaction>) -> Result<(), Error> {
     let mut sa: sys::sigaction = core::mem::zeroed();
     let old_sa = old_sa.write(core::mem::zeroed());
 
     sa.sa_flags = sys::SA_SIGINFO | sys::SA_NODEFER;
+    if signal == sys::SIGURG {
+        // The gas watchdog can poke the thread while it's running a host function, so don't make its syscalls fail.
+        sa.sa_flags |= sys::SA_RESTART;
+    }
+
     sa.sa_sigaction = signal_handler as usize;
     sys::sigemptyset(&mut sa.sa_mask);
     if sys::sigaction(signal, &sa, old_sa) < 0 {
@@ -390,6 +484,78 @@ fn register_signal_handlers_if_necessary() -> Result<(), Error> {
     }
 }
 
+/// An execution of a program with asynchronous gas metering which is being watched by the gas watchdog.
+struct WatchedExecution {
+    thread: sys::pthread_t,
+    gas: *const i64,
+}
+
+// SAFETY: The gas is only ever read by the watchdog while the execution is registered, and
+//         the execution is always unregistered by the thread which is running it when it finishes.
+unsafe impl Send for WatchedExecution {}
+
+static WATCHED_EXECUTIONS: Mutex<Vec<WatchedExecution>> = Mutex::new(Vec::new());
+static WATCHED_EXECUTIONS_CHANGED: Condvar = Condvar::new();
+
+fn lock_watched_executions() -> MutexGuard<'static, Vec<WatchedExecution>> {
+    match WATCHED_EXECUTIONS.lock() {
+        Ok(executions) => executions,
+        Err(poison) => poison.into_inner(),
+    }
+}
+
+/// The guest program doesn't check its own gas when using asynchronous gas metering, so instead
+/// we periodically check it here in the background and interrupt the program once it runs out.
+fn run_gas_watchdog() {
+    let mut executions = lock_watched_executions();
+    loop {
+        while executions.is_empty() {
+            executions = match WATCHED_EXECUTIONS_CHANGED.wait(executions) {
+                Ok(executions) => executions,
+                Err(poison) => poison.into_inner(),
+            };
+        }
+
+        for execution in executions.iter() {
+            // SAFETY: The execution is still registered, so its VM context must still be alive.
+            if unsafe { core::ptr::read_volatile(execution.gas) } < 0 {
+                // SAFETY: The execution is still registered, so its thread must still be alive.
+                unsafe {
+                    sys::pthread_kill(execution.thread, sys::SIGURG);
+                }
+            }
+        }
+
+        core::mem::drop(executions);
+        std::thread::sleep(super::ASYNC_GAS_METERING_CHECK_INTERVAL);
+        executions = lock_watched_executions();
+    }
+}
+
+fn start_gas_watchdog_if_necessary() -> Result<(), Error> {
+    static IS_RUNNING: OnceLock<bool> = OnceLock::new();
+    let is_running = *IS_RUNNING.get_or_init(|| {
+        // SAFETY: This can only run once, so calling this is safe.
+        if let Err(error) = unsafe { register_signal_handler_for_signal(sys::SIGURG, &mut *core::ptr::addr_of_mut!(OLD_SIGURG)) } {
+            log::error!("Failed to set up a signal handler for the gas watchdog: {error}");
+            return false;
+        }
+
+        if let Err(error) = std::thread::Builder::new().name("polkavm-gas-watchdog".into()).spawn(run_gas_watchdog) {
+            log::error!("Failed to spawn the gas watchdog: {error}");
+            return false;
+        }
+
+        true
+    });
+
+    if is_running {
+        Ok(())
+    } else {
+        Err("failed to start the gas watchdog".into())
+    }
+}
+
 thread_local! {
     static THREAD_VMCTX: UnsafeCell<*mut VmCtx> = const { UnsafeCell::new(core::ptr::null_mut()) };
 }
@@ -413,8 +579,68 @@ unsafe fn sysreturn(vmctx: &mut VmCtx) -> ! {
     }
 }
 
-unsafe fn trigger_trap(vmctx: &mut VmCtx) -> ! {
-    vmctx.trap_triggered = true;
+/// Jumps into the guest program, and returns once the guest program returns to the host (or traps).
+///
+/// If `resume_return_address` is non-zero then it's used as the address to which the guest program will return from a hostcall.
+unsafe fn call_into_guest(vmctx: *mut VmCtx, guest_memory: *const u8, entry_point: u64, resume_return_address: u64) {
+    // SAFETY: The caller will make sure that `vmctx` and `guest_memory` point to the sandbox which is being executed.
+    unsafe {
+        core::arch::asm!(r#"
+            push rbp
+            push rbx
+
+            // Fill in the return address.
+            lea rbx, [rip+1f]
+            mov [r14], rbx
+
+            // Fill in the return stack pointer.
+            mov [r14 + 8], rsp
+
+            // Align the stack.
+            sub rsp, 8
+
+            // If we're resuming then push the address to which
+            // the guest program should return from the hostcall.
+            test {resume_return_address}, {resume_return_address}
+            jz 2f
+            push {resume_return_address}
+            2:
+
+            // Call into the guest program.
+            jmp {entry_point}
+
+            // We will jump here on exit.
+            1:
+
+            pop rbx
+            pop rbp
+        "#,
+            entry_point = in(reg) entry_point,
+            resume_return_address = in(reg) resume_return_address,
+            // Mark all of the clobbered registers.
+            //
+            // We need to save and restore rbp and rbx manually since
+            // the inline assembly doesn't support using them as operands.
+            clobber_abi("C"),
+            lateout("rax") _,
+            lateout("rcx") _,
+            lateout("rdx") _,
+            lateout("rsi") _,
+            lateout("rdi") _,
+            lateout("r8") _,
+            lateout("r9") _,
+            lateout("r10") _,
+            lateout("r11") _,
+            lateout("r12") _,
+            lateout("r13") _,
+            inlateout("r14") vmctx => _,
+            in("r15") guest_memory,
+        );
+    }
+}
+
+unsafe fn trigger_trap(vmctx: &mut VmCtx, trap: Trap) -> ! {
+    vmctx.trap = Some(trap);
     sysreturn(vmctx);
 }
 
@@ -427,15 +653,20 @@ struct VmCtx {
     return_stack_pointer: usize,
 
     gas: i64,
+    heap_top: u32,
+    basic_blocks_executed: u64,
 
     program_range: Range<u64>,
-    trap_triggered: bool,
+    trap: Option<Trap>,
+    trap_native_regs: Option<[u64; 16]>,
 
-    regs: CacheAligned<[u32; REG_COUNT]>,
+    regs: CacheAligned<[u64; REG_COUNT]>,
     on_hostcall: Option<OnHostcall<'static, Sandbox>>,
     sandbox: *mut Sandbox,
     instruction_number: Option<u32>,
     native_program_counter: Option<u64>,
+    suspended_return_address: Option<u64>,
+    is_interrupted: bool,
 }
 
 impl VmCtx {
@@ -444,20 +675,25 @@ impl VmCtx {
         VmCtx {
             return_address: 0,
             return_stack_pointer: 0,
-            trap_triggered: false,
+            trap: None,
+            trap_native_regs: None,
             program_range: 0..0,
 
             gas: 0,
+            heap_top: 0,
+            basic_blocks_executed: 0,
             regs: CacheAligned([0; REG_COUNT]),
             on_hostcall: None,
             sandbox: core::ptr::null_mut(),
             instruction_number: None,
             native_program_counter: None,
+            suspended_return_address: None,
+            is_interrupted: false,
         }
     }
 
     #[inline(always)]
-    pub const fn regs(&self) -> &[u32; REG_COUNT] {
+    pub const fn regs(&self) -> &[u64; REG_COUNT] {
         &self.regs.0
     }
 }
@@ -487,12 +723,14 @@ unsafe fn conjure_vmctx<'a>() -> &'a mut VmCtx {
     &mut *THREAD_VMCTX.with(|thread_ctx| *thread_ctx.get())
 }
 
-unsafe extern "C" fn syscall_hostcall(hostcall: u32) {
+unsafe extern "C" fn syscall_hostcall(hostcall: u32, rip: u64) {
     // SAFETY: We were called from the inside of the guest program, so vmctx must be valid.
     let vmctx = unsafe { conjure_vmctx() };
 
+    vmctx.native_program_counter = Some(rip);
+
     let Some(on_hostcall) = vmctx.on_hostcall.as_mut().take() else {
-        trigger_trap(vmctx);
+        trigger_trap(vmctx, Trap::default());
     };
 
     // SAFETY: We were called from the inside of the guest program, so no other
@@ -501,9 +739,26 @@ unsafe extern "C" fn syscall_hostcall(hostcall: u32) {
         &mut *vmctx.sandbox
     };
 
+    // With asynchronous gas metering the program could have already run out of gas,
+    // in which case it must not be allowed to call into the host anymore.
+    if sandbox.is_out_of_gas_async() {
+        sysreturn(vmctx);
+    }
+
+    // We can't interrupt the guest program while it's running, so at least check whenever it calls into the host.
+    if sandbox.is_interrupt_requested() {
+        vmctx.is_interrupted = true;
+        sysreturn(vmctx);
+    }
+
     match on_hostcall(hostcall, super::Sandbox::access(sandbox)) {
-        Ok(()) => {}
-        Err(_) => trigger_trap(vmctx)
+        Ok(HostcallOutcome::Continue) => {}
+        Ok(HostcallOutcome::Suspend) => {
+            // The guest program will continue from right after the call into the ecall trampoline when resumed.
+            vmctx.suspended_return_address = Some(rip + 1);
+            sysreturn(vmctx);
+        }
+        Err(trap) => trigger_trap(vmctx, trap)
     }
 }
 
@@ -524,9 +779,18 @@ unsafe extern "C" fn syscall_trace(instruction_number: u32, rip: u64) {
         &mut *vmctx.sandbox
     };
 
+    if sandbox.is_out_of_gas_async() {
+        sysreturn(vmctx);
+    }
+
+    if sandbox.is_interrupt_requested() {
+        vmctx.is_interrupted = true;
+        sysreturn(vmctx);
+    }
+
     match on_hostcall(polkavm_common::HOSTCALL_TRACE, super::Sandbox::access(sandbox)) {
-        Ok(()) => {}
-        Err(_) => trigger_trap(vmctx)
+        Ok(_) => {}
+        Err(trap) => trigger_trap(vmctx, trap)
     }
 }
 
@@ -535,7 +799,7 @@ unsafe extern "C" fn syscall_trap() -> ! {
     let vmctx = unsafe { conjure_vmctx() };
 
     // SAFETY: We were called from the inside of the guest program, so it's safe to trap.
-    trigger_trap(vmctx);
+    trigger_trap(vmctx, Trap::default());
 }
 
 unsafe extern "C" fn syscall_return() -> ! {
@@ -546,6 +810,25 @@ unsafe extern "C" fn syscall_return() -> ! {
     sysreturn(vmctx);
 }
 
+unsafe extern "C" fn syscall_sbrk(size: u32) -> u32 {
+    // SAFETY: We were called from the inside of the guest program, so vmctx must be valid.
+    let vmctx = unsafe { conjure_vmctx() };
+
+    // SAFETY: We were called from the inside of the guest program, so no other
+    // mutable references to the sandbox can be concurrently alive.
+    let sandbox = unsafe {
+        &mut *vmctx.sandbox
+    };
+
+    match sandbox.sbrk(size) {
+        Ok(heap_top) => heap_top.unwrap_or(0),
+        Err(error) => {
+            log::error!("Failed to grow the heap: {error}");
+            trigger_trap(vmctx, Trap::default());
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct SandboxProgram(Arc<SandboxProgramInner>);
 
@@ -554,7 +837,7 @@ struct SandboxProgramInner {
     ro_data: Vec<u8>,
     rw_data: Vec<u8>,
 
-    code_memory: Mmap,
+    code_memory: Arc<Mmap>,
     code_length: usize,
 
     gas_metering: Option<GasMeteringKind>,
@@ -572,12 +855,24 @@ enum Poison {
     Poisoned,
 }
 
+/// An execution which was suspended in the middle of a hostcall.
+struct SuspendedExecution {
+    /// The native address to which the guest program should return from the hostcall.
+    return_address: u64,
+
+    /// The flags of the original call, applied once the execution finishes.
+    rpc_flags: u32,
+}
+
 pub struct Sandbox {
     poison: Poison,
+    suspended: Option<SuspendedExecution>,
     program: Option<SandboxProgram>,
     memory: Mmap,
     memory_config: SandboxMemoryConfig,
     guest_memory_offset: usize,
+    interrupt: Option<Arc<InterruptState>>,
+    deadline: Option<Instant>,
 }
 
 impl Drop for Sandbox {
@@ -603,6 +898,8 @@ impl Sandbox {
     }
 
     fn clear_program(&mut self) -> Result<(), ExecutionError<Error>> {
+        self.resize_heap(self.memory_config.heap_range().end)?;
+
         let user_memory_region_size = self.memory_config.user_memory_region_size();
         if user_memory_region_size > 0 {
             self.memory.mmap_within(
@@ -612,6 +909,7 @@ impl Sandbox {
             )?;
 
             self.memory_config.clear_user_memory_sizes();
+            self.vmctx_mut().heap_top = self.memory_config.heap_range().end;
         }
 
         if self.memory_config.stack_size() > 0 {
@@ -626,9 +924,10 @@ impl Sandbox {
 
         self.memory_config.clear_code_size();
         self.memory_config.clear_jump_table_size();
+        self.memory_config.set_max_heap_size(0);
         if let Some(program) = self.program.take() {
-            if let Some(program) = Arc::into_inner(program.0) {
-                program.code_memory.unmap()?;
+            if let Some(code_memory) = Arc::into_inner(program.0).and_then(|program| Arc::into_inner(program.code_memory)) {
+                code_memory.unmap()?;
             }
         }
 
@@ -636,6 +935,10 @@ impl Sandbox {
     }
 
     fn reset_memory(&mut self) -> Result<(), ExecutionError<Error>> {
+        if self.program.is_some() {
+            self.resize_heap(self.memory_config.heap_range().end)?;
+        }
+
         if let Some(ref program) = self.program {
             let program = &program.0;
             let rw_data_size = self.memory_config.rw_data_size() as usize;
@@ -673,6 +976,52 @@ impl Sandbox {
         Ok(())
     }
 
+    /// Maps or unmaps the memory past the initial heap so that the heap ends at `new_heap_top`.
+    fn resize_heap(&mut self, new_heap_top: u32) -> Result<(), Error> {
+        let mapped_end = align_to_next_page_u32(VM_PAGE_SIZE, self.vmctx().heap_top).unwrap();
+        let new_mapped_end = align_to_next_page_u32(VM_PAGE_SIZE, new_heap_top).unwrap();
+        match new_mapped_end.cmp(&mapped_end) {
+            core::cmp::Ordering::Greater => {
+                self.memory.mmap_within(
+                    self.guest_memory_offset + mapped_end as usize,
+                    (new_mapped_end - mapped_end) as usize,
+                    PROT_READ | PROT_WRITE
+                )?;
+            }
+            core::cmp::Ordering::Less => {
+                self.memory.mmap_within(
+                    self.guest_memory_offset + new_mapped_end as usize,
+                    (mapped_end - new_mapped_end) as usize,
+                    0
+                )?;
+            }
+            core::cmp::Ordering::Equal => {}
+        }
+
+        self.vmctx_mut().heap_top = new_heap_top;
+        Ok(())
+    }
+
+    /// Grows the heap by `size` bytes and returns where it previously ended, or `None` if it can't grow that much.
+    fn sbrk(&mut self, size: u32) -> Result<Option<u32>, Error> {
+        let heap_top = self.vmctx().heap_top;
+        let Some(new_heap_top) = heap_top.checked_add(size) else {
+            return Ok(None);
+        };
+
+        if u64::from(new_heap_top) > self.memory_config.max_heap_top() {
+            return Ok(None);
+        }
+
+        self.resize_heap(new_heap_top)?;
+        Ok(Some(heap_top))
+    }
+
+    /// The range of addresses where the heap is, including the part which was allocated with `sbrk`.
+    fn heap_range(&self) -> Range<u32> {
+        self.memory_config.heap_address()..align_to_next_page_u32(VM_PAGE_SIZE, self.vmctx().heap_top).unwrap()
+    }
+
     fn bound_check_access(&self, address: u32, length: u32) -> Result<(), ()> {
         use core::ops::Range;
 
@@ -693,7 +1042,7 @@ impl Sandbox {
         }
 
         let range = u64::from(address)..u64::from(address) + u64::from(length);
-        if check(self.memory_config.ro_data_range(), range.clone())? || check(self.memory_config.heap_range(), range.clone())? || check(self.memory_config.stack_range(), range)? {
+        if check(self.memory_config.ro_data_range(), range.clone())? || check(self.heap_range(), range.clone())? || check(self.memory_config.stack_range(), range)? {
             Ok(())
         } else {
             Err(())
@@ -712,7 +1061,40 @@ impl Sandbox {
         Some(&mut self.memory.as_slice_mut()[range])
     }
 
+    fn is_interrupt_requested(&self) -> bool {
+        self.interrupt.as_ref().is_some_and(|interrupt| interrupt.is_interrupted())
+            || self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    fn is_async_gas_metering_enabled(&self) -> bool {
+        self.program.as_ref().and_then(|program| program.0.gas_metering) == Some(GasMeteringKind::Async)
+    }
+
+    fn is_out_of_gas_async(&self) -> bool {
+        self.is_async_gas_metering_enabled() && self.vmctx().gas < 0
+    }
+
     fn execute_impl(&mut self, mut args: ExecuteArgs<Self>) -> Result<(), ExecutionError<Error>> {
+        if !args.is_resume {
+            self.interrupt = args.interrupt.take();
+            self.deadline = args.deadline;
+        }
+
+        let suspended = self.suspended.take();
+        let mut rpc_flags = args.rpc_flags;
+        let mut resume_return_address = 0;
+        if args.is_resume {
+            let Some(suspended) = suspended else {
+                return Err(ExecutionError::Error("tried to resume an execution which wasn't suspended".into()));
+            };
+
+            rpc_flags = suspended.rpc_flags;
+            resume_return_address = suspended.return_address;
+        } else if let Some(suspended) = suspended {
+            // The suspended execution is abandoned; we've already left the guest program, so just finish it up.
+            self.finish_execution(suspended.rpc_flags)?;
+        }
+
         if let Some(SandboxProgram(program)) = args.program {
             log::trace!("Reconfiguring sandbox...");
             self.clear_program()?;
@@ -804,19 +1186,30 @@ impl Sandbox {
             let native_page_size = get_native_page_size();
             current.set_code_size(native_page_size, new.code_size()).unwrap();
             current.set_jump_table_size(native_page_size, new.jump_table_size()).unwrap();
+            current.set_max_heap_size(new.max_heap_size());
             self.program = Some(SandboxProgram(Arc::clone(program)));
 
             if *current != new {
                 panic!("internal error: failed to fully update memory configuration");
             }
+
+            let heap_top = current.heap_range().end;
+            self.vmctx_mut().heap_top = heap_top;
+        }
+
+        if args.rpc_flags & VM_RPC_FLAG_SET_HEAP_TOP != 0 {
+            self.resize_heap(args.heap_top)?;
+        }
+
+        if !args.is_resume {
+            self.vmctx_mut().regs.copy_from_slice(args.initial_regs);
         }
 
-        self.vmctx_mut().regs.copy_from_slice(args.initial_regs);
         if let Some(gas) = args.get_gas(self.program.as_ref().and_then(|program| program.0.gas_metering)) {
             self.vmctx_mut().gas = gas;
         }
 
-        let mut trap_triggered = false;
+        let mut trap = None;
         if args.rpc_address != 0 {
             {
                 let Some(program) = self.program.as_ref() else {
@@ -835,63 +1228,45 @@ impl Sandbox {
             let on_hostcall: Option<OnHostcall<'static, Sandbox>> = unsafe { core::mem::transmute(on_hostcall) };
             self.vmctx_mut().on_hostcall = on_hostcall;
             self.vmctx_mut().sandbox = self;
-            self.vmctx_mut().trap_triggered = false;
+            self.vmctx_mut().trap = None;
+            self.vmctx_mut().native_program_counter = None;
+            self.vmctx_mut().trap_native_regs = None;
+            self.vmctx_mut().suspended_return_address = None;
+            self.vmctx_mut().is_interrupted = false;
+
+            let is_watched = self.is_async_gas_metering_enabled();
 
             #[allow(clippy::undocumented_unsafe_blocks)]
             unsafe {
                 let vmctx = vmctx_mut_ptr(&mut self.memory);
-                THREAD_VMCTX.with(|thread_ctx| core::ptr::write(thread_ctx.get(), vmctx));
+                // A host function might call into another instance, so make sure to restore the previous context afterwards.
+                let outer_vmctx = THREAD_VMCTX.with(|thread_ctx| core::ptr::replace(thread_ctx.get(), vmctx));
+
+                if is_watched {
+                    let mut executions = lock_watched_executions();
+                    executions.push(WatchedExecution {
+                        thread: sys::pthread_self(),
+                        gas: core::ptr::addr_of!((*vmctx).gas),
+                    });
+                    WATCHED_EXECUTIONS_CHANGED.notify_one();
+                }
 
                 let guest_memory = self.memory.as_ptr().cast::<u8>().add(self.guest_memory_offset);
+                call_into_guest(vmctx, guest_memory, args.rpc_address, resume_return_address);
 
-                core::arch::asm!(r#"
-                    push rbp
-                    push rbx
-
-                    // Fill in the return address.
-                    lea rbx, [rip+1f]
-                    mov [r14], rbx
-
-                    // Fill in the return stack pointer.
-                    mov [r14 + 8], rsp
-
-                    // Align the stack.
-                    sub rsp, 8
-
-                    // Call into the guest program.
-                    jmp {entry_point}
-
-                    // We will jump here on exit.
-                    1:
-
-                    pop rbx
-                    pop rbp
-                "#,
-                    entry_point = in(reg) args.rpc_address,
-                    // Mark all of the clobbered registers.
-                    //
-                    // We need to save and restore rbp and rbx manually since
-                    // the inline assembly doesn't support using them as operands.
-                    clobber_abi("C"),
-                    lateout("rax") _,
-                    lateout("rcx") _,
-                    lateout("rdx") _,
-                    lateout("rsi") _,
-                    lateout("rdi") _,
-                    lateout("r8") _,
-                    lateout("r9") _,
-                    lateout("r10") _,
-                    lateout("r11") _,
-                    lateout("r12") _,
-                    lateout("r13") _,
-                    inlateout("r14") vmctx => _,
-                    in("r15") guest_memory,
-                );
+                if is_watched {
+                    let gas = core::ptr::addr_of!((*vmctx).gas);
+                    lock_watched_executions().retain(|execution| execution.gas != gas);
+                }
 
-                THREAD_VMCTX.with(|thread_ctx| core::ptr::write(thread_ctx.get(), core::ptr::null_mut()));
+                THREAD_VMCTX.with(|thread_ctx| core::ptr::write(thread_ctx.get(), outer_vmctx));
+            }
+
+            trap = self.vmctx_mut().trap.take();
+            if let Some(return_address) = self.vmctx_mut().suspended_return_address.take() {
+                self.suspended = Some(SuspendedExecution { return_address, rpc_flags });
             }
 
-            trap_triggered = core::mem::replace(&mut self.vmctx_mut().trap_triggered, false);
             self.vmctx_mut().sandbox = core::ptr::null_mut();
             self.vmctx_mut().on_hostcall = None;
             self.vmctx_mut().return_address = 0;
@@ -899,14 +1274,28 @@ impl Sandbox {
             self.vmctx_mut().program_range = 0..0;
         };
 
-        if args.rpc_flags & VM_RPC_FLAG_CLEAR_PROGRAM_AFTER_EXECUTION != 0 {
-            self.clear_program()?;
-        } else if args.rpc_flags & VM_RPC_FLAG_RESET_MEMORY_AFTER_EXECUTION != 0 {
-            self.reset_memory()?;
+        if self.suspended.is_some() {
+            // The execution isn't finished yet, so leave the memory as-is.
+            return Ok(());
         }
 
-        if trap_triggered {
-            return Err(ExecutionError::Trap(Trap::default()));
+        self.finish_execution(rpc_flags)?;
+        if core::mem::take(&mut self.vmctx_mut().is_interrupted) {
+            return Err(ExecutionError::Interrupted);
+        }
+
+        if let Some(trap) = trap {
+            return Err(ExecutionError::Trap(trap));
+        }
+
+        Ok(())
+    }
+
+    fn finish_execution(&mut self, rpc_flags: u32) -> Result<(), ExecutionError<Error>> {
+        if rpc_flags & VM_RPC_FLAG_CLEAR_PROGRAM_AFTER_EXECUTION != 0 {
+            self.clear_program()?;
+        } else if rpc_flags & VM_RPC_FLAG_RESET_MEMORY_AFTER_EXECUTION != 0 {
+            self.reset_memory()?;
         }
 
         Ok(())
@@ -942,6 +1331,10 @@ impl super::Sandbox for Sandbox {
         let jump_table_offset = cfg.code_size();
         let sysreturn_offset = jump_table_offset + (VM_ADDR_JUMP_TABLE_RETURN_TO_HOST - VM_ADDR_JUMP_TABLE) as usize;
 
+        if gas_metering == Some(GasMeteringKind::Async) {
+            start_gas_watchdog_if_necessary()?;
+        }
+
         map.modify_and_protect(0, cfg.code_size(), PROT_EXEC, |slice| {
             slice[..init.code.len()].copy_from_slice(init.code);
         })?;
@@ -978,16 +1371,31 @@ impl super::Sandbox for Sandbox {
             memory_config: cfg,
             ro_data: init.ro_data().to_vec(),
             rw_data: init.rw_data().to_vec(),
-            code_memory: map,
+            code_memory: Arc::new(map),
             code_length: init.code.len(),
             gas_metering,
         })))
     }
 
+    fn prepare_program_with_initial_heap(program: &Self::Program, heap: &[u8]) -> Result<Self::Program, Self::Error> {
+        let (cfg, rw_data) = super::memory_config_for_initial_heap(program.0.memory_config, heap)?;
+
+        // The code doesn't change, so it can be shared with the original program.
+        Ok(SandboxProgram(Arc::new(SandboxProgramInner {
+            memory_config: cfg,
+            ro_data: program.0.ro_data.clone(),
+            rw_data: rw_data.to_vec(),
+            code_memory: Arc::clone(&program.0.code_memory),
+            code_length: program.0.code_length,
+            gas_metering: program.0.gas_metering,
+        })))
+    }
+
     fn spawn(_config: &SandboxConfig) -> Result<Self, Error> {
         register_signal_handlers_if_necessary()?;
 
         let guest_memory_offset = get_guest_memory_offset();
+        let memory_config = SandboxMemoryConfig::empty();
         let mut memory = Mmap::reserve_address_space(guest_memory_offset + 0x100000000)?;
 
         // Make the space for VmCtx read-write.
@@ -996,15 +1404,20 @@ impl super::Sandbox for Sandbox {
 
         // SAFETY: We just mmaped this and made it read-write.
         unsafe {
-            core::ptr::write(vmctx_mut_ptr(&mut memory), VmCtx::new());
+            let mut vmctx = VmCtx::new();
+            vmctx.heap_top = memory_config.heap_range().end;
+            core::ptr::write(vmctx_mut_ptr(&mut memory), vmctx);
         }
 
         Ok(Sandbox {
             poison: Poison::None,
+            suspended: None,
             program: None,
             memory,
-            memory_config: SandboxMemoryConfig::empty(),
+            memory_config,
             guest_memory_offset,
+            interrupt: None,
+            deadline: None,
         })
     }
 
@@ -1019,7 +1432,7 @@ impl super::Sandbox for Sandbox {
                 self.poison = Poison::Poisoned;
                 result
             }
-            result @ (Ok(()) | Err(ExecutionError::Trap(_) | ExecutionError::OutOfGas)) => {
+            result @ (Ok(()) | Err(ExecutionError::Trap(_) | ExecutionError::OutOfGas | ExecutionError::Interrupted)) => {
                 self.poison = Poison::None;
                 result
             }
@@ -1041,6 +1454,7 @@ impl super::Sandbox for Sandbox {
             syscall_trap,
             syscall_return,
             syscall_trace,
+            syscall_sbrk,
         })
     }
 
@@ -1052,6 +1466,18 @@ impl super::Sandbox for Sandbox {
         get_field_offset!(VmCtx::new(), |base| &base.gas)
     }
 
+    fn vmctx_heap_top_offset() -> usize {
+        get_field_offset!(VmCtx::new(), |base| &base.heap_top)
+    }
+
+    fn vmctx_basic_blocks_executed_offset() -> usize {
+        get_field_offset!(VmCtx::new(), |base| &base.basic_blocks_executed)
+    }
+
+    fn basic_blocks_executed(&self) -> u64 {
+        self.vmctx().basic_blocks_executed
+    }
+
     fn gas_remaining_impl(&self) -> Result<Option<Gas>, super::OutOfGas> {
         let Some(program) = self.program.as_ref() else { return Ok(None) };
         if program.0.gas_metering.is_none() { return Ok(None) };
@@ -1062,6 +1488,75 @@ impl super::Sandbox for Sandbox {
     fn sync(&mut self) -> Result<(), Self::Error> {
         Ok(())
     }
+
+    fn trap_native_regs(&self) -> Option<[u64; 16]> {
+        self.vmctx().trap_native_regs
+    }
+
+    fn call_nested<'a>(
+        access: &mut SandboxAccess,
+        address: u64,
+        regs: &[u64],
+        on_hostcall: OnHostcall<'a, Self>,
+        on_finish: OnNestedCallFinished<'a, Self>,
+    ) -> Result<(), ExecutionError<crate::Error>>
+    where
+        Self: 'a,
+    {
+        let sandbox = &mut *access.sandbox;
+        log::trace!("Jumping to: 0x{:x} (nested call)", address);
+
+        // We're in the middle of a hostcall, so the guest program's registers are already saved in the VM context,
+        // and the native stack which is currently in use is below the one used by the outer execution.
+        let vmctx = sandbox.vmctx_mut();
+        let outer_return_address = vmctx.return_address;
+        let outer_return_stack_pointer = vmctx.return_stack_pointer;
+        let outer_regs = vmctx.regs.0;
+        let outer_instruction_number = vmctx.instruction_number;
+        let outer_native_program_counter = vmctx.native_program_counter;
+
+        // SAFETY: Transmuting an arbitrary lifetime into a 'static lifetime is safe as long as the invariants
+        // that the shorter lifetime requires are still upheld.
+        let on_hostcall: OnHostcall<'static, Sandbox> = unsafe { core::mem::transmute(on_hostcall) };
+        let outer_on_hostcall = vmctx.on_hostcall.replace(on_hostcall);
+
+        vmctx.regs.copy_from_slice(regs);
+        vmctx.trap = None;
+        vmctx.native_program_counter = None;
+        vmctx.trap_native_regs = None;
+
+        // SAFETY: The sandbox is currently executing on this thread, so the pointers are valid.
+        unsafe {
+            let vmctx = vmctx_mut_ptr(&mut sandbox.memory);
+            let guest_memory = sandbox.memory.as_ptr().cast::<u8>().add(sandbox.guest_memory_offset);
+            call_into_guest(vmctx, guest_memory, address, 0);
+        }
+
+        let vmctx = sandbox.vmctx_mut();
+        let trap = vmctx.trap.take();
+        let result = if vmctx.suspended_return_address.take().is_some() {
+            Err(ExecutionError::Error("a nested call cannot be suspended".into()))
+        } else if core::mem::take(&mut vmctx.is_interrupted) {
+            Err(ExecutionError::Interrupted)
+        } else if let Some(trap) = trap {
+            Err(ExecutionError::Trap(trap))
+        } else {
+            Ok(())
+        };
+
+        let result = on_finish(sandbox, result);
+
+        let vmctx = sandbox.vmctx_mut();
+        vmctx.return_address = outer_return_address;
+        vmctx.return_stack_pointer = outer_return_stack_pointer;
+        vmctx.regs.0 = outer_regs;
+        vmctx.instruction_number = outer_instruction_number;
+        vmctx.native_program_counter = outer_native_program_counter;
+        vmctx.trap_native_regs = None;
+        vmctx.on_hostcall = outer_on_hostcall;
+
+        result
+    }
 }
 
 pub struct SandboxAccess<'a> {
@@ -1078,11 +1573,19 @@ impl<'a> Access<'a> for SandboxAccess<'a> {
     type Error = MemoryAccessError<&'static str>;
 
     fn get_reg(&self, reg: Reg) -> u32 {
+        self.get_reg64(reg) as u32
+    }
+
+    fn set_reg(&mut self, reg: Reg, value: u32) {
+        self.set_reg64(reg, u64::from(value));
+    }
+
+    fn get_reg64(&self, reg: Reg) -> u64 {
         assert!(!matches!(self.sandbox.poison, Poison::Poisoned), "sandbox has been poisoned");
         self.sandbox.vmctx().regs[reg as usize]
     }
 
-    fn set_reg(&mut self, reg: Reg, value: u32) {
+    fn set_reg64(&mut self, reg: Reg, value: u64) {
         assert!(!matches!(self.sandbox.poison, Poison::Poisoned), "sandbox has been poisoned");
         self.sandbox.vmctx_mut().regs[reg as usize] = value;
     }
@@ -1146,6 +1649,60 @@ impl<'a> Access<'a> for SandboxAccess<'a> {
         Ok(())
     }
 
+    fn with_memory<R>(&self, address: u32, length: u32, callback: impl FnOnce(&[u8]) -> R) -> Result<R, Self::Error> {
+        log::trace!(
+            "Accessing memory: 0x{:x}-0x{:x} ({} bytes)",
+            address,
+            address as usize + length as usize,
+            length
+        );
+
+        if matches!(self.sandbox.poison, Poison::Poisoned) {
+            return Err(MemoryAccessError {
+                address,
+                length: u64::from(length),
+                error: "read failed: sandbox has been poisoned",
+            });
+        }
+
+        let Some(slice) = self.sandbox.get_memory_slice(address, length) else {
+            return Err(MemoryAccessError {
+                address,
+                length: u64::from(length),
+                error: "out of range read",
+            });
+        };
+
+        Ok(callback(slice))
+    }
+
+    fn with_memory_mut<R>(&mut self, address: u32, length: u32, callback: impl FnOnce(&mut [u8]) -> R) -> Result<R, Self::Error> {
+        log::trace!(
+            "Accessing memory mutably: 0x{:x}-0x{:x} ({} bytes)",
+            address,
+            address as usize + length as usize,
+            length
+        );
+
+        if matches!(self.sandbox.poison, Poison::Poisoned) {
+            return Err(MemoryAccessError {
+                address,
+                length: u64::from(length),
+                error: "write failed: sandbox has been poisoned",
+            });
+        }
+
+        let Some(slice) = self.sandbox.get_memory_slice_mut(address, length) else {
+            return Err(MemoryAccessError {
+                address,
+                length: u64::from(length),
+                error: "out of range write",
+            });
+        };
+
+        Ok(callback(slice))
+    }
+
     fn program_counter(&self) -> Option<u32> {
         self.sandbox.vmctx().instruction_number
     }
@@ -1154,6 +1711,10 @@ impl<'a> Access<'a> for SandboxAccess<'a> {
         self.sandbox.vmctx().native_program_counter
     }
 
+    fn heap_size(&self) -> u32 {
+        self.sandbox.vmctx().heap_top - self.sandbox.memory_config.heap_address()
+    }
+
     fn gas_remaining(&self) -> Option<Gas> {
         use super::Sandbox;
         self.sandbox.gas_remaining_impl().ok().unwrap_or(Some(Gas::MIN))
@@ -1167,4 +1728,21 @@ impl<'a> Access<'a> for SandboxAccess<'a> {
         let gas_remaining = &mut self.sandbox.vmctx_mut().gas;
         *gas_remaining = gas_remaining.checked_sub_unsigned(gas).unwrap_or(-1);
     }
+
+    fn add_gas(&mut self, gas: u64) {
+        if self.sandbox.program.as_ref().and_then(|program| program.0.gas_metering).is_none() {
+            return;
+        }
+
+        let gas_remaining = &mut self.sandbox.vmctx_mut().gas;
+        *gas_remaining = gas_remaining.saturating_add_unsigned(gas);
+    }
+
+    fn set_gas(&mut self, gas: Gas) {
+        if self.sandbox.program.as_ref().and_then(|program| program.0.gas_metering).is_none() {
+            return;
+        }
+
+        self.sandbox.vmctx_mut().gas = gas.get() as i64;
+    }
 }
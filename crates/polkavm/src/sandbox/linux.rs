@@ -5,13 +5,14 @@ extern crate polkavm_linux_raw as linux_raw;
 
 use polkavm_common::{
     abi::VM_PAGE_SIZE,
-    error::{ExecutionError, Trap},
+    error::{ExecutionError, Trap, TrapKind},
     program::Reg,
     utils::{align_to_next_page_usize, slice_assume_init_mut, Access, AsUninitSliceMut, Gas},
     zygote::{
         AddressTable, AddressTablePacked,
         SandboxMemoryConfig, VmCtx, SANDBOX_EMPTY_NATIVE_PROGRAM_COUNTER, SANDBOX_EMPTY_NTH_INSTRUCTION, VMCTX_FUTEX_BUSY,
-        VMCTX_FUTEX_HOSTCALL, VMCTX_FUTEX_IDLE, VMCTX_FUTEX_INIT, VMCTX_FUTEX_TRAP, VM_ADDR_NATIVE_CODE,
+        VMCTX_FUTEX_HOSTCALL, VMCTX_FUTEX_IDLE, VMCTX_FUTEX_INIT, VMCTX_FUTEX_TRAP, VM_ADDR_JUMP_TABLE, VM_ADDR_NATIVE_CODE,
+        VM_SANDBOX_MAXIMUM_JUMP_TABLE_VIRTUAL_SIZE,
     },
 };
 
@@ -22,13 +23,13 @@ pub use linux_raw::Error;
 use core::ffi::{c_int, c_uint};
 use core::ops::Range;
 use core::sync::atomic::Ordering;
-use linux_raw::{abort, cstr, syscall_readonly, Fd, Mmap, STDERR_FILENO, STDIN_FILENO};
+use linux_raw::{abort, cstr, syscall_readonly, Fd, FdRef, Mmap, STDERR_FILENO, STDIN_FILENO};
 use std::borrow::Cow;
 use std::time::Instant;
 use std::sync::Arc;
 
-use super::{OnHostcall, SandboxKind, SandboxProgramInit, get_native_page_size};
-use crate::api::{BackendAccess, MemoryAccessError};
+use super::{OnHostcall, OnNestedCallFinished, SandboxKind, SandboxProgramInit, get_native_page_size};
+use crate::api::{BackendAccess, HostcallOutcome, InterruptState, MemoryAccessError};
 use crate::config::GasMeteringKind;
 
 pub struct SandboxConfig {
@@ -300,6 +301,30 @@ impl ChildProcess {
     }
 }
 
+/// The worker process which should be signalled to interrupt the execution.
+pub(crate) struct InterruptTarget {
+    pid: c_int,
+    pidfd: Option<c_int>,
+}
+
+impl InterruptTarget {
+    pub(crate) fn interrupt(&self) {
+        // The worker will ignore this if it's not currently running the guest program.
+        let result = unsafe {
+            if let Some(pidfd) = self.pidfd {
+                let errcode = syscall_readonly!(linux_raw::SYS_pidfd_send_signal, pidfd, linux_raw::SIGUSR1, 0, 0);
+                Error::from_syscall("pidfd_send_signal", errcode)
+            } else {
+                linux_raw::sys_kill(self.pid, linux_raw::SIGUSR1)
+            }
+        };
+
+        if let Err(error) = result {
+            log::warn!("Failed to interrupt the worker process: {error}");
+        }
+    }
+}
+
 impl Drop for ChildProcess {
     fn drop(&mut self) {
         #[cfg(polkavm_dev_debug_zygote)]
@@ -457,6 +482,8 @@ const ZYGOTE_ADDRESS_TABLE: AddressTable = {
     address_table
 };
 
+static PADDING: [u8; VM_PAGE_SIZE as usize] = [0; VM_PAGE_SIZE as usize];
+
 fn create_empty_memfd(name: &core::ffi::CStr) -> Result<Fd, Error> {
     linux_raw::sys_memfd_create(name, linux_raw::MFD_CLOEXEC | linux_raw::MFD_ALLOW_SEALING)
 }
@@ -575,6 +602,34 @@ fn prepare_vmctx() -> Result<(Fd, Mmap), Error> {
     Ok((memfd, vmctx))
 }
 
+/// Creates the memfd which backs the guest's BSS, the dynamically allocated heap and the stack.
+///
+/// The offsets in the memfd are the same as the guest addresses, and it's also mapped into the host
+/// so that the guest's memory can be accessed directly without copying it around.
+fn prepare_memory() -> Result<(Fd, Mmap), Error> {
+    let length = 1_usize << 32;
+    let memfd = create_empty_memfd(cstr!("polkavm_memory"))?;
+    linux_raw::sys_ftruncate(memfd.borrow(), length as linux_raw::c_ulong)?;
+    linux_raw::sys_fcntl(
+        memfd.borrow(),
+        linux_raw::F_ADD_SEALS,
+        linux_raw::F_SEAL_SEAL | linux_raw::F_SEAL_SHRINK | linux_raw::F_SEAL_GROW,
+    )?;
+
+    let memory = unsafe {
+        linux_raw::Mmap::map(
+            core::ptr::null_mut(),
+            length,
+            linux_raw::PROT_READ | linux_raw::PROT_WRITE,
+            linux_raw::MAP_SHARED,
+            Some(memfd.borrow()),
+            0,
+        )?
+    };
+
+    Ok((memfd, memory))
+}
+
 unsafe fn child_main(zygote_memfd: Fd, child_socket: Fd, uid_map: &str, gid_map: &str, logging_pipe: Option<Fd>) -> Result<(), Error> {
     // Change the name of the process.
     linux_raw::sys_prctl_set_name(b"polkavm-sandbox\0")?;
@@ -602,10 +657,11 @@ unsafe fn child_main(zygote_memfd: Fd, child_socket: Fd, uid_map: &str, gid_map:
         proc_self.close()?;
     }
 
+    // One extra fd is needed for the memory memfd, which the zygote keeps open.
     let fd_limit = if logging_pipe.is_some() {
-        4
+        5
     } else {
-        3
+        4
     };
 
     // This should never happen in practice, but can in theory if the user closes stdin or stderr manually.
@@ -734,23 +790,33 @@ impl super::SandboxProgram for SandboxProgram {
     fn machine_code(&self) -> Cow<[u8]> {
         // The code is kept inside of the memfd and we don't have it readily accessible.
         // So if necessary just read it back from the memfd.
-        let mut buffer = vec![0; self.0.code_range.len()];
-        linux_raw::sys_lseek(self.0.memfd.borrow(), self.0.code_range.start as i64, linux_raw::SEEK_SET).expect("failed to get machine code of the program: seek failed");
-
-        let mut position = 0;
-        while position < self.0.code_range.len() {
-            let count = match linux_raw::sys_read(self.0.memfd.borrow(), &mut buffer[position..]) {
-                Ok(count) => count,
-                Err(error) if error.errno() == linux_raw::EINTR => continue,
-                Err(error) => panic!("failed to get machine code of the program: read failed: {error}")
-            };
+        let code = read_memfd(self.0.memfd.borrow(), self.0.code_range.clone())
+            .unwrap_or_else(|error| panic!("failed to get machine code of the program: {error}"));
+
+        Cow::Owned(code)
+    }
+}
+
+fn read_memfd(memfd: FdRef, range: Range<usize>) -> Result<Vec<u8>, Error> {
+    let mut buffer = vec![0; range.len()];
+    linux_raw::sys_lseek(memfd, range.start as i64, linux_raw::SEEK_SET)?;
+
+    let mut position = 0;
+    while position < range.len() {
+        let count = match linux_raw::sys_read(memfd, &mut buffer[position..]) {
+            Ok(count) => count,
+            Err(error) if error.errno() == linux_raw::EINTR => continue,
+            Err(error) => return Err(error)
+        };
 
-            assert_ne!(count, 0);
-            position += count as usize;
+        if count == 0 {
+            return Err(Error::from_str("failed to read memfd: unexpected end of file"));
         }
 
-        Cow::Owned(buffer)
+        position += count as usize;
     }
+
+    Ok(buffer)
 }
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
@@ -884,6 +950,7 @@ unsafe fn set_message(vmctx: &VmCtx, message: core::fmt::Arguments) {
 pub struct Sandbox {
     _lifetime_pipe: Fd,
     vmctx_mmap: Mmap,
+    memory_mmap: Mmap,
     child: ChildProcess,
     socket: Fd,
 
@@ -891,6 +958,13 @@ pub struct Sandbox {
     count_futex_wait: u64,
 
     gas_metering: Option<GasMeteringKind>,
+
+    /// Whether the worker is waiting in a hostcall for the execution to be resumed.
+    is_suspended: bool,
+
+    /// Used to check whether the current execution should be interrupted.
+    interrupt: Option<Arc<InterruptState>>,
+    deadline: Option<Instant>,
 }
 
 impl Drop for Sandbox {
@@ -933,8 +1007,6 @@ impl super::Sandbox for Sandbox {
     }
 
     fn prepare_program(init: SandboxProgramInit, (): Self::AddressSpace, gas_metering: Option<GasMeteringKind>) -> Result<Self::Program, Self::Error> {
-        static PADDING: [u8; VM_PAGE_SIZE as usize] = [0; VM_PAGE_SIZE as usize];
-
         let native_page_size = get_native_page_size();
         let cfg = init.memory_config(native_page_size)?;
         let ro_data_padding = &PADDING[..cfg.ro_data_size() as usize - init.ro_data().len()];
@@ -967,10 +1039,46 @@ impl super::Sandbox for Sandbox {
         })))
     }
 
+    fn prepare_program_with_initial_heap(program: &Self::Program, heap: &[u8]) -> Result<Self::Program, Self::Error> {
+        let old_cfg = program.0.memory_config;
+        let (cfg, rw_data) = super::memory_config_for_initial_heap(old_cfg, heap)?;
+        let rw_data_padding = &PADDING[..cfg.rw_data_size() as usize - rw_data.len()];
+
+        // Everything besides the read-write data stays the same, so just copy it from the original memfd.
+        let ro_data_size = old_cfg.ro_data_size() as usize;
+        let old_length = ro_data_size + old_cfg.rw_data_size() as usize + old_cfg.code_size() + old_cfg.jump_table_size();
+        let ro_data = read_memfd(program.0.memfd.borrow(), 0..ro_data_size)?;
+        let code_and_jump_table = read_memfd(program.0.memfd.borrow(), ro_data_size + old_cfg.rw_data_size() as usize..old_length)?;
+
+        // The program's read-write data is mapped as private, so every instance will get its own copy-on-write view of the new heap.
+        let memfd = prepare_sealed_memfd(
+            create_program_memfd()?,
+            ro_data.len() + cfg.rw_data_size() as usize + code_and_jump_table.len(),
+            [
+                &ro_data,
+                rw_data,
+                rw_data_padding,
+                &code_and_jump_table
+            ]
+        )?;
+
+        let offset = cfg.ro_data_size() as usize + cfg.rw_data_size() as usize;
+        let code_range = offset..offset + program.0.code_range.len();
+
+        Ok(SandboxProgram(Arc::new(SandboxProgramInner {
+            memfd,
+            memory_config: cfg,
+            sysreturn_address: program.0.sysreturn_address,
+            code_range,
+            gas_metering: program.0.gas_metering,
+        })))
+    }
+
     fn spawn(config: &SandboxConfig) -> Result<Self, Error> {
         let sigset = Sigmask::block_all_signals()?;
         let zygote_memfd = prepare_zygote()?;
         let (vmctx_memfd, vmctx_mmap) = prepare_vmctx()?;
+        let (memory_memfd, memory_mmap) = prepare_memory()?;
         let (socket, child_socket) = linux_raw::sys_socketpair(linux_raw::AF_UNIX, linux_raw::SOCK_SEQPACKET | linux_raw::SOCK_CLOEXEC, 0)?;
         let (lifetime_pipe_host, lifetime_pipe_child) = linux_raw::sys_pipe2(linux_raw::O_CLOEXEC)?;
 
@@ -1218,6 +1326,9 @@ impl super::Sandbox for Sandbox {
         linux_raw::sendfd(socket.borrow(), lifetime_pipe_child.borrow())?;
         lifetime_pipe_child.close()?;
 
+        linux_raw::sendfd(socket.borrow(), memory_memfd.borrow())?;
+        memory_memfd.close()?;
+
         // Wait until the child process receives the vmctx memfd.
         wait_for_futex(vmctx, &mut child, VMCTX_FUTEX_BUSY, VMCTX_FUTEX_INIT)?;
 
@@ -1265,6 +1376,7 @@ impl super::Sandbox for Sandbox {
         Ok(Sandbox {
             _lifetime_pipe: lifetime_pipe_host,
             vmctx_mmap,
+            memory_mmap,
             child,
             socket,
 
@@ -1272,48 +1384,30 @@ impl super::Sandbox for Sandbox {
             count_futex_wait: 0,
 
             gas_metering: None,
+            is_suspended: false,
+            interrupt: None,
+            deadline: None,
         })
     }
 
     fn execute(&mut self, mut args: ExecuteArgs<Self>) -> Result<(), ExecutionError<Error>> {
-        self.wait_if_necessary(match args.on_hostcall {
-            Some(ref mut on_hostcall) => Some(&mut *on_hostcall),
-            None => None,
-        }, true)?;
-
-        if args.is_async && args.on_hostcall.is_some() {
-            return Err(Error::from_str("requested asynchronous execution with a borrowed hostcall handler").into());
+        if !args.is_resume {
+            self.interrupt = args.interrupt.take();
+            self.deadline = args.deadline;
         }
 
-        unsafe {
-            if let Some(program) = args.program {
-                *self.vmctx().new_memory_config.get() = program.0.memory_config;
-                *self.vmctx().new_sysreturn_address.get() = program.0.sysreturn_address;
-                self.gas_metering = program.0.gas_metering;
-            }
-
-            if let Some(gas) = args.get_gas(self.gas_metering) {
-                *self.vmctx().gas().get() = gas;
-            }
-
-            *self.vmctx().rpc_address.get() = args.rpc_address;
-            *self.vmctx().rpc_flags.get() = args.rpc_flags;
-
-            (*self.vmctx().regs().get()).copy_from_slice(args.initial_regs);
-            self.vmctx().futex.store(VMCTX_FUTEX_BUSY, Ordering::Release);
-            linux_raw::sys_futex_wake_one(&self.vmctx().futex)?;
-
-            if let Some(program) = args.program {
-                // TODO: This can block forever.
-                linux_raw::sendfd(self.socket.borrow(), program.0.memfd.borrow())?;
-            }
-        }
+        let Some(interrupt) = self.interrupt.clone() else {
+            return self.execute_impl(args);
+        };
 
-        if !args.is_async {
-            self.wait_if_necessary(args.on_hostcall, args.rpc_address == 0)?;
-        }
+        interrupt.set_worker(Some(InterruptTarget {
+            pid: self.child.pid,
+            pidfd: self.child.pidfd.as_ref().map(|pidfd| pidfd.raw()),
+        }));
 
-        Ok(())
+        let result = self.execute_impl(args);
+        interrupt.set_worker(None);
+        result
     }
 
     #[inline]
@@ -1337,6 +1431,18 @@ impl super::Sandbox for Sandbox {
         get_field_offset!(VmCtx::new(), |base| base.gas().get())
     }
 
+    fn vmctx_heap_top_offset() -> usize {
+        get_field_offset!(VmCtx::new(), |base| base.heap_top.get())
+    }
+
+    fn vmctx_basic_blocks_executed_offset() -> usize {
+        get_field_offset!(VmCtx::new(), |base| base.counters.basic_blocks_executed.get())
+    }
+
+    fn basic_blocks_executed(&self) -> u64 {
+        unsafe { *self.vmctx().counters.basic_blocks_executed.get() }
+    }
+
     fn gas_remaining_impl(&self) -> Result<Option<Gas>, super::OutOfGas> {
         if self.gas_metering.is_none() { return Ok(None) };
         let raw_gas = unsafe { *self.vmctx().gas().get() };
@@ -1344,22 +1450,184 @@ impl super::Sandbox for Sandbox {
     }
 
     fn sync(&mut self) -> Result<(), Self::Error> {
+        if self.is_suspended {
+            return Ok(());
+        }
+
         self.wait_if_necessary(None, true).map_err(|error| {
             match error {
                 ExecutionError::Trap(..) => Error::from_str("unexpected trap"),
                 ExecutionError::OutOfGas => Error::from_str("unexpected out of gas"),
+                ExecutionError::Interrupted => Error::from_str("unexpected interruption"),
                 ExecutionError::Error(error) => error,
             }
         })
     }
+
+    fn trap_native_regs(&self) -> Option<[u64; 16]> {
+        unsafe {
+            if *self.vmctx().trap_signal.get() == 0 {
+                return None;
+            }
+
+            Some(*self.vmctx().trap_native_regs.get())
+        }
+    }
+
+    fn call_nested<'a>(
+        access: &mut SandboxAccess,
+        address: u64,
+        regs: &[u64],
+        on_hostcall: OnHostcall<'a, Self>,
+        on_finish: OnNestedCallFinished<'a, Self>,
+    ) -> Result<(), ExecutionError<crate::Error>>
+    where
+        Self: 'a,
+    {
+        let sandbox = &mut *access.sandbox;
+
+        // The worker is waiting for us inside of the hostcall, so save the state of the outer execution
+        // and then ask the worker to make the nested call.
+        let (outer_regs, outer_hostcall, outer_rip, outer_nth_instruction, outer_rpc_address) = unsafe {
+            let vmctx = sandbox.vmctx();
+            let outer_state = (
+                *vmctx.regs().get(),
+                *vmctx.hostcall().get(),
+                *vmctx.rip().get(),
+                *vmctx.nth_instruction().get(),
+                *vmctx.rpc_address.get(),
+            );
+
+            (*vmctx.regs().get()).copy_from_slice(regs);
+            *vmctx.hostcall().get() = polkavm_common::zygote::HOSTCALL_NESTED_CALL;
+            *vmctx.rpc_address.get() = address;
+            *vmctx.rip().get() = SANDBOX_EMPTY_NATIVE_PROGRAM_COUNTER;
+            *vmctx.nth_instruction().get() = SANDBOX_EMPTY_NTH_INSTRUCTION;
+            *vmctx.trap_signal.get() = 0;
+            outer_state
+        };
+
+        sandbox.vmctx().futex.store(VMCTX_FUTEX_BUSY, Ordering::Release);
+        let mut result = match linux_raw::sys_futex_wake_one(&sandbox.vmctx().futex) {
+            Ok(_) => sandbox.wait(Some(on_hostcall), true),
+            Err(error) => Err(error.into()),
+        };
+
+        if core::mem::take(&mut sandbox.is_suspended) {
+            unsafe {
+                *sandbox.vmctx().hostcall().get() = polkavm_common::zygote::HOSTCALL_ABORT_EXECUTION;
+            }
+            sandbox.vmctx().futex.store(VMCTX_FUTEX_BUSY, Ordering::Release);
+            result = match linux_raw::sys_futex_wake_one(&sandbox.vmctx().futex) {
+                Ok(_) => Err(Error::from_str("a nested call cannot be suspended").into()),
+                Err(error) => Err(error.into()),
+            };
+        }
+
+        if matches!(result, Err(ExecutionError::Trap(..) | ExecutionError::OutOfGas | ExecutionError::Interrupted | ExecutionError::Error(..))) {
+            // The worker was told to abandon the nested call, so wait until it goes back to waiting for the original hostcall.
+            if let Err(error) = sandbox.wait_if_necessary(None, true) {
+                result = Err(error);
+            }
+        }
+
+        let result = on_finish(sandbox, result);
+
+        unsafe {
+            let vmctx = sandbox.vmctx();
+            *vmctx.regs().get() = outer_regs;
+            *vmctx.hostcall().get() = outer_hostcall;
+            *vmctx.rip().get() = outer_rip;
+            *vmctx.nth_instruction().get() = outer_nth_instruction;
+            *vmctx.rpc_address.get() = outer_rpc_address;
+            *vmctx.trap_signal.get() = 0;
+        }
+
+        result
+    }
 }
 
 impl Sandbox {
+    /// Returns how many times the host has started waiting for the worker, and how many times it had to call `futex_wait`.
+    pub(crate) fn wait_counters(&self) -> (u64, u64) {
+        (self.count_wait_loop_start, self.count_futex_wait)
+    }
+
     #[inline]
     fn vmctx(&self) -> &VmCtx {
         unsafe { &*self.vmctx_mmap.as_ptr().cast::<VmCtx>() }
     }
 
+    fn execute_impl(&mut self, mut args: ExecuteArgs<Self>) -> Result<(), ExecutionError<Error>> {
+        if args.is_resume {
+            if !core::mem::take(&mut self.is_suspended) {
+                return Err(Error::from_str("tried to resume an execution which wasn't suspended").into());
+            }
+
+            if let Some(gas) = args.get_gas(self.gas_metering) {
+                unsafe {
+                    *self.vmctx().gas().get() = gas;
+                }
+            }
+
+            // The worker is still waiting for the hostcall to finish, so just wake it up.
+            self.vmctx().futex.store(VMCTX_FUTEX_BUSY, Ordering::Release);
+            linux_raw::sys_futex_wake_one(&self.vmctx().futex)?;
+            return self.wait_if_necessary(args.on_hostcall, true);
+        }
+
+        if core::mem::take(&mut self.is_suspended) {
+            // The suspended execution was abandoned, so tell the worker to abort it.
+            unsafe {
+                *self.vmctx().hostcall().get() = polkavm_common::zygote::HOSTCALL_ABORT_EXECUTION;
+            }
+            self.vmctx().futex.store(VMCTX_FUTEX_BUSY, Ordering::Release);
+            linux_raw::sys_futex_wake_one(&self.vmctx().futex)?;
+        }
+
+        self.wait_if_necessary(match args.on_hostcall {
+            Some(ref mut on_hostcall) => Some(&mut *on_hostcall),
+            None => None,
+        }, true)?;
+
+        if args.is_async && args.on_hostcall.is_some() {
+            return Err(Error::from_str("requested asynchronous execution with a borrowed hostcall handler").into());
+        }
+
+        unsafe {
+            if let Some(program) = args.program {
+                *self.vmctx().new_memory_config.get() = program.0.memory_config;
+                *self.vmctx().new_sysreturn_address.get() = program.0.sysreturn_address;
+                self.gas_metering = program.0.gas_metering;
+            }
+
+            if let Some(gas) = args.get_gas(self.gas_metering) {
+                *self.vmctx().gas().get() = gas;
+            }
+
+            *self.vmctx().rpc_address.get() = args.rpc_address;
+            *self.vmctx().rpc_flags.get() = args.rpc_flags;
+            *self.vmctx().new_heap_top.get() = u64::from(args.heap_top);
+            *self.vmctx().rip().get() = SANDBOX_EMPTY_NATIVE_PROGRAM_COUNTER;
+            *self.vmctx().trap_signal.get() = 0;
+
+            (*self.vmctx().regs().get()).copy_from_slice(args.initial_regs);
+            self.vmctx().futex.store(VMCTX_FUTEX_BUSY, Ordering::Release);
+            linux_raw::sys_futex_wake_one(&self.vmctx().futex)?;
+
+            if let Some(program) = args.program {
+                // TODO: This can block forever.
+                linux_raw::sendfd(self.socket.borrow(), program.0.memfd.borrow())?;
+            }
+        }
+
+        if !args.is_async {
+            self.wait_if_necessary(args.on_hostcall, args.rpc_address == 0)?;
+        }
+
+        Ok(())
+    }
+
     #[inline(never)]
     #[cold]
     fn wait(&mut self, mut on_hostcall: Option<OnHostcall<Self>>, low_latency: bool) -> Result<(), ExecutionError<Error>> {
@@ -1381,10 +1649,20 @@ impl Sandbox {
             if state == VMCTX_FUTEX_TRAP {
                 core::sync::atomic::fence(Ordering::Acquire);
 
+                let is_interrupted = unsafe { *self.vmctx().trap_signal.get() } == linux_raw::SIGUSR1;
+                let kind = get_trap_kind(self.vmctx());
                 self.vmctx().futex.store(VMCTX_FUTEX_BUSY, Ordering::Release);
                 linux_raw::sys_futex_wake_one(&self.vmctx().futex)?;
 
-                return Err(ExecutionError::Trap(Trap::default()));
+                if is_interrupted {
+                    if self.is_out_of_gas_async() {
+                        return Err(ExecutionError::OutOfGas);
+                    }
+
+                    return Err(ExecutionError::Interrupted);
+                }
+
+                return Err(ExecutionError::Trap(Trap::new(kind)));
             }
 
             if state == VMCTX_FUTEX_HOSTCALL {
@@ -1403,6 +1681,23 @@ impl Sandbox {
                     }
                 };
 
+                // With asynchronous gas metering the program could have already run out of gas,
+                // in which case it must not be allowed to call into the host anymore.
+                let is_out_of_gas = self.is_out_of_gas_async();
+                if is_out_of_gas || self.is_interrupt_requested() {
+                    unsafe {
+                        *self.vmctx().hostcall().get() = polkavm_common::zygote::HOSTCALL_ABORT_EXECUTION;
+                    }
+                    self.vmctx().futex.store(VMCTX_FUTEX_BUSY, Ordering::Release);
+                    linux_raw::sys_futex_wake_one(&self.vmctx().futex)?;
+
+                    if is_out_of_gas {
+                        return Err(ExecutionError::OutOfGas);
+                    }
+
+                    return Err(ExecutionError::Interrupted);
+                }
+
                 let hostcall = unsafe { *self.vmctx().hostcall().get() };
                 if hostcall == polkavm_common::HOSTCALL_TRACE {
                     // When tracing aggressively spin to avoid having to call into the kernel.
@@ -1410,11 +1705,16 @@ impl Sandbox {
                 }
 
                 match on_hostcall(hostcall, super::Sandbox::access(self)) {
-                    Ok(()) => {
+                    Ok(HostcallOutcome::Continue) => {
                         self.vmctx().futex.store(VMCTX_FUTEX_BUSY, Ordering::Release);
                         linux_raw::sys_futex_wake_one(&self.vmctx().futex)?;
                         continue;
                     }
+                    Ok(HostcallOutcome::Suspend) => {
+                        // Leave the worker waiting; it will be woken up once the execution is resumed.
+                        self.is_suspended = true;
+                        return Ok(());
+                    }
                     Err(trap) => {
                         unsafe {
                             *self.vmctx().hostcall().get() = polkavm_common::zygote::HOSTCALL_ABORT_EXECUTION;
@@ -1448,8 +1748,25 @@ impl Sandbox {
                 }
             }
 
+            let mut timeout = core::time::Duration::from_millis(100);
+            if self.is_interrupt_requested() || self.is_out_of_gas_async() {
+                // The worker ignores the signal if it isn't running the guest program at the time
+                // it receives it, so keep poking it until it stops.
+                self.child.send_signal(linux_raw::SIGUSR1)?;
+                timeout = core::time::Duration::from_millis(1);
+            } else {
+                if let Some(deadline) = self.deadline {
+                    timeout = timeout.min(deadline.saturating_duration_since(Instant::now()));
+                }
+
+                if self.gas_metering == Some(GasMeteringKind::Async) {
+                    // The program doesn't check its own gas, so we have to periodically do it for it.
+                    timeout = timeout.min(super::ASYNC_GAS_METERING_CHECK_INTERVAL);
+                }
+            }
+
             self.count_futex_wait += 1;
-            match linux_raw::sys_futex_wait(&self.vmctx().futex, VMCTX_FUTEX_BUSY, Some(core::time::Duration::from_millis(100))) {
+            match linux_raw::sys_futex_wait(&self.vmctx().futex, VMCTX_FUTEX_BUSY, Some(timeout)) {
                 Ok(()) => continue,
                 Err(error) if error.errno() == linux_raw::EAGAIN || error.errno() == linux_raw::EINTR => continue,
                 Err(error) if error.errno() == linux_raw::ETIMEDOUT => {
@@ -1459,10 +1776,12 @@ impl Sandbox {
                         log::trace!("Child #{} is not running anymore: {status}", self.child.pid);
                         let message = get_message(self.vmctx());
                         if let Some(message) = message {
-                            return Err(Error::from(format!("{status}: {message}")).into());
+                            log::error!("Worker process crashed: {status}: {message}");
                         } else {
-                            return Err(Error::from(format!("worker process unexpectedly quit: {status}")).into());
+                            log::error!("Worker process unexpectedly quit: {status}");
                         }
+
+                        return Err(ExecutionError::Trap(Trap::new(TrapKind::WorkerCrashed)));
                     }
                 }
                 Err(error) => return Err(error.into()),
@@ -1470,6 +1789,15 @@ impl Sandbox {
         }
     }
 
+    fn is_interrupt_requested(&self) -> bool {
+        self.interrupt.as_ref().is_some_and(|interrupt| interrupt.is_interrupted())
+            || self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    fn is_out_of_gas_async(&self) -> bool {
+        self.gas_metering == Some(GasMeteringKind::Async) && unsafe { core::ptr::read_volatile(self.vmctx().gas().get()) } < 0
+    }
+
     #[inline]
     fn wait_if_necessary(&mut self, on_hostcall: Option<OnHostcall<Self>>, low_latency: bool) -> Result<(), ExecutionError<Error>> {
         if self.vmctx().futex.load(Ordering::Relaxed) != VMCTX_FUTEX_IDLE {
@@ -1480,10 +1808,62 @@ impl Sandbox {
     }
 }
 
+fn get_trap_kind(vmctx: &VmCtx) -> TrapKind {
+    let signal = unsafe { *vmctx.trap_signal.get() };
+    match signal {
+        0 => TrapKind::Unknown,
+        linux_raw::SIGILL => TrapKind::TrapInstruction,
+        linux_raw::SIGFPE => TrapKind::DivisionFault,
+        linux_raw::SIGSEGV | linux_raw::SIGBUS => {
+            let fault_address = unsafe { *vmctx.trap_address.get() };
+            let is_write = unsafe { *vmctx.trap_is_write.get() } != 0;
+
+            // The guest's memory is mapped at the very beginning of the address space.
+            if let Ok(address) = u32::try_from(fault_address) {
+                if is_write {
+                    TrapKind::InvalidStore { address }
+                } else {
+                    TrapKind::InvalidLoad { address }
+                }
+            } else if fault_address >= VM_ADDR_JUMP_TABLE && fault_address < VM_ADDR_JUMP_TABLE + VM_SANDBOX_MAXIMUM_JUMP_TABLE_VIRTUAL_SIZE {
+                TrapKind::InvalidJumpTarget
+            } else {
+                TrapKind::Unknown
+            }
+        }
+        _ => TrapKind::Unknown,
+    }
+}
+
 pub struct SandboxAccess<'a> {
     sandbox: &'a mut Sandbox,
 }
 
+impl<'a> SandboxAccess<'a> {
+    /// Returns the given part of the guest's memory if it's fully backed by the memfd shared with the host.
+    ///
+    /// Only the BSS, the dynamically allocated heap and the stack are shared; the read-write data is a private
+    /// copy-on-write mapping of the program inside of the sandbox, so it's not accessible from here.
+    fn shared_memory(&self, address: u32, length: u32) -> Option<*mut u8> {
+        let (memory_config, heap_top) = unsafe { (&*self.sandbox.vmctx().memory_config.get(), *self.sandbox.vmctx().heap_top.get()) };
+
+        let address = u64::from(address);
+        let end = address + u64::from(length);
+        let heap_start = u64::from(memory_config.bss_address());
+        let heap_end = polkavm_common::utils::align_to_next_page_u64(u64::from(VM_PAGE_SIZE), heap_top)?;
+        let stack_start = u64::from(memory_config.stack_address_low());
+        let stack_end = stack_start + u64::from(memory_config.stack_size());
+
+        let is_in_heap = memory_config.bss_size() > 0 && address >= heap_start && end <= heap_end;
+        let is_in_stack = address >= stack_start && end <= stack_end;
+        if !is_in_heap && !is_in_stack {
+            return None;
+        }
+
+        Some(unsafe { self.sandbox.memory_mmap.as_mut_ptr().cast::<u8>().add(address as usize) })
+    }
+}
+
 impl<'a> From<SandboxAccess<'a>> for BackendAccess<'a> {
     fn from(access: SandboxAccess<'a>) -> Self {
         BackendAccess::CompiledLinux(access)
@@ -1494,11 +1874,19 @@ impl<'a> Access<'a> for SandboxAccess<'a> {
     type Error = MemoryAccessError<linux_raw::Error>;
 
     fn get_reg(&self, reg: Reg) -> u32 {
+        self.get_reg64(reg) as u32
+    }
+
+    fn set_reg(&mut self, reg: Reg, value: u32) {
+        self.set_reg64(reg, u64::from(value));
+    }
+
+    fn get_reg64(&self, reg: Reg) -> u64 {
         let regs = unsafe { &*self.sandbox.vmctx().regs().get() };
         regs[reg as usize]
     }
 
-    fn set_reg(&mut self, reg: Reg, value: u32) {
+    fn set_reg64(&mut self, reg: Reg, value: u64) {
         unsafe {
             (*self.sandbox.vmctx().regs().get())[reg as usize] = value;
         }
@@ -1584,6 +1972,28 @@ impl<'a> Access<'a> for SandboxAccess<'a> {
         }
     }
 
+    fn with_memory<R>(&self, address: u32, length: u32, callback: impl FnOnce(&[u8]) -> R) -> Result<R, Self::Error> {
+        let Some(pointer) = self.shared_memory(address, length) else {
+            let buffer = self.read_memory_into_new_vec(address, length)?;
+            return Ok(callback(&buffer));
+        };
+
+        // SAFETY: The guest is not running, and the memory stays mapped for as long as we're borrowing the sandbox.
+        Ok(callback(unsafe { core::slice::from_raw_parts(pointer, length as usize) }))
+    }
+
+    fn with_memory_mut<R>(&mut self, address: u32, length: u32, callback: impl FnOnce(&mut [u8]) -> R) -> Result<R, Self::Error> {
+        let Some(pointer) = self.shared_memory(address, length) else {
+            let mut buffer = self.read_memory_into_new_vec(address, length)?;
+            let result = callback(&mut buffer);
+            self.write_memory(address, &buffer)?;
+            return Ok(result);
+        };
+
+        // SAFETY: The guest is not running, and the memory stays mapped for as long as we're borrowing the sandbox.
+        Ok(callback(unsafe { core::slice::from_raw_parts_mut(pointer, length as usize) }))
+    }
+
     fn program_counter(&self) -> Option<u32> {
         let value = unsafe { *self.sandbox.vmctx().nth_instruction().get() };
 
@@ -1604,6 +2014,14 @@ impl<'a> Access<'a> for SandboxAccess<'a> {
         }
     }
 
+    fn heap_size(&self) -> u32 {
+        let (heap_top, heap_address) = unsafe {
+            (*self.sandbox.vmctx().heap_top.get(), (*self.sandbox.vmctx().memory_config.get()).heap_address())
+        };
+
+        (heap_top as u32).saturating_sub(heap_address)
+    }
+
     fn gas_remaining(&self) -> Option<Gas> {
         use super::Sandbox;
         self.sandbox.gas_remaining_impl().ok().unwrap_or(Some(Gas::MIN))
@@ -1614,4 +2032,16 @@ impl<'a> Access<'a> for SandboxAccess<'a> {
         let gas_remaining = unsafe { &mut *self.sandbox.vmctx().gas().get() };
         *gas_remaining = gas_remaining.checked_sub_unsigned(gas).unwrap_or(-1);
     }
+
+    fn add_gas(&mut self, gas: u64) {
+        if self.sandbox.gas_metering.is_none() { return }
+        let gas_remaining = unsafe { &mut *self.sandbox.vmctx().gas().get() };
+        *gas_remaining = gas_remaining.saturating_add_unsigned(gas);
+    }
+
+    fn set_gas(&mut self, gas: Gas) {
+        if self.sandbox.gas_metering.is_none() { return }
+        let gas_remaining = unsafe { &mut *self.sandbox.vmctx().gas().get() };
+        *gas_remaining = gas.get() as i64;
+    }
 }
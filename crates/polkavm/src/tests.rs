@@ -1,16 +1,18 @@
 use crate::{
-    Caller, CallerRef, Config, Engine, ExecutionConfig, ExecutionError, Gas, GasMeteringKind, Linker, Module, ModuleConfig, ProgramBlob,
-    Reg, Trap, Val,
+    BackendKind, CallState, Caller, CallerRef, Config, Engine, ExecutionConfig, ExecutionError, Gas, GasCostModel, GasMeteringKind,
+    GuestPtr, GuestSlice, Instance, InstanceSnapshot, Linker, Module, ModuleConfig, Opcode, Pod, ProgramBlob, Reg, SandboxKind, Trap,
+    TrapKind, Val,
 };
 use core::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 use std::sync::Mutex;
 
-use polkavm_common::abi::{VM_ADDR_USER_MEMORY, VM_PAGE_SIZE};
+use polkavm_common::abi::{VM_ADDR_USER_MEMORY, VM_ADDR_USER_STACK_HIGH, VM_PAGE_SIZE};
 use polkavm_common::elf::FnMetadata;
 use polkavm_common::program::asm;
 use polkavm_common::program::ExternTy::*;
+use polkavm_common::program::Instruction;
 use polkavm_common::program::Reg::*;
 use polkavm_common::writer::ProgramBlobBuilder;
 
@@ -190,496 +192,2983 @@ fn caller_split_works(config: Config) {
     assert_eq!(state.value, 0x12345678);
 }
 
-fn trapping_from_hostcall_handler_works(config: Config) {
+fn caller_with_memory_works(config: Config) {
     let _ = env_logger::try_init();
     let blob = basic_test_blob();
     let engine = Engine::new(&config).unwrap();
     let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
     let mut linker = Linker::new(&engine);
 
-    enum Kind {
-        Ok,
-        Trap,
-    }
-
     linker
-        .func_wrap("hostcall", move |caller: Caller<Kind>| -> Result<u32, Trap> {
-            match *caller.data() {
-                Kind::Ok => Ok(100),
-                Kind::Trap => Err(Trap::default()),
-            }
+        .func_wrap("hostcall", move |mut caller: Caller<()>| -> Result<u32, Trap> {
+            let value = caller.with_memory(VM_ADDR_USER_MEMORY, 4, |slice| u32::from_le_bytes(slice.try_into().unwrap()))?;
+            assert_eq!(value, 0x12345678);
+
+            caller.with_memory_mut(VM_ADDR_USER_MEMORY, 4, |slice| slice.copy_from_slice(&0xdeadbeef_u32.to_le_bytes()))?;
+            assert_eq!(caller.read_u32(VM_ADDR_USER_MEMORY)?, 0xdeadbeef);
+
+            assert!(caller.with_memory(0, 4, |_| ()).is_err());
+            assert!(caller.with_memory_mut(VM_ADDR_USER_MEMORY + VM_PAGE_SIZE - 2, 4, |_| ()).is_err());
+
+            Ok(100)
         })
         .unwrap();
 
     let instance_pre = linker.instantiate_pre(&module).unwrap();
     let instance = instance_pre.instantiate().unwrap();
-
     let result = instance
         .get_typed_func::<(u32, u32), u32>("main")
         .unwrap()
-        .call(&mut Kind::Ok, (1, 10));
-    assert!(matches!(result, Ok(111)));
-
-    let result = instance
-        .get_typed_func::<(u32, u32), u32>("main")
-        .unwrap()
-        .call(&mut Kind::Trap, (1, 10));
-    assert!(matches!(result, Err(ExecutionError::Trap(..))));
-
-    let result = instance
-        .get_func("main")
-        .unwrap()
-        .call(&mut Kind::Ok, &[Val::from(1), Val::from(10)]);
-    assert!(matches!(result, Ok(Some(Val::I32(111)))));
+        .call(&mut (), (1, 10))
+        .unwrap();
+    assert_eq!(result, 111);
 
-    let result = instance
-        .get_func("main")
-        .unwrap()
-        .call(&mut Kind::Trap, &[Val::from(1), Val::from(10)]);
-    assert!(matches!(result, Err(ExecutionError::Trap(..))));
+    assert_eq!(
+        instance.with_memory(VM_ADDR_USER_MEMORY, 4, |slice| slice.to_vec()).unwrap(),
+        0xdeadbeef_u32.to_le_bytes()
+    );
+    instance.with_memory_mut(VM_ADDR_USER_MEMORY, 2, |slice| slice.fill(0)).unwrap();
+    assert_eq!(
+        instance.read_memory_into_new_vec(VM_ADDR_USER_MEMORY, 4).unwrap(),
+        [0, 0, 0xad, 0xde]
+    );
 }
 
-fn decompress_zstd(mut bytes: &[u8]) -> Vec<u8> {
-    use std::io::Read;
-    let mut output = Vec::new();
-    ruzstd::streaming_decoder::StreamingDecoder::new(&mut bytes)
-        .unwrap()
-        .read_to_end(&mut output)
-        .unwrap();
-    output
-}
+fn caller_with_memory_works_on_heap_and_stack(config: Config) {
+    let _ = env_logger::try_init();
+    let stack_address = VM_ADDR_USER_STACK_HIGH - VM_PAGE_SIZE;
+    let mut builder = ProgramBlobBuilder::new();
+    builder.set_bss_size(VM_PAGE_SIZE);
+    builder.set_stack_size(VM_PAGE_SIZE);
+    builder.add_export(0, &FnMetadata::new("main", &[], Some(I32)));
+    builder.add_import(0, &FnMetadata::new("hostcall", &[], Some(I32)));
+    builder.set_code(&[
+        asm::store_imm_u32(0x12345678, VM_ADDR_USER_MEMORY),
+        asm::store_imm_u32(0xaabbccdd, stack_address),
+        asm::ecalli(0),
+        asm::load_u32(Reg::A0, VM_ADDR_USER_MEMORY + 4),
+        asm::ret(),
+    ]);
 
-static BLOB_MAP: Mutex<Option<HashMap<&'static [u8], ProgramBlob>>> = Mutex::new(None);
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    let mut linker = Linker::new(&engine);
 
-fn get_blob(elf: &'static [u8]) -> ProgramBlob {
-    let mut blob_map = match BLOB_MAP.lock() {
-        Ok(blob_map) => blob_map,
-        Err(error) => error.into_inner(),
-    };
+    linker
+        .func_wrap("hostcall", move |mut caller: Caller<()>| -> Result<u32, Trap> {
+            let value = caller.with_memory(VM_ADDR_USER_MEMORY, 4, |slice| u32::from_le_bytes(slice.try_into().unwrap()))?;
+            assert_eq!(value, 0x12345678);
+            caller.with_memory_mut(VM_ADDR_USER_MEMORY + 4, 4, |slice| {
+                slice.copy_from_slice(&0xdeadbeef_u32.to_le_bytes())
+            })?;
 
-    let blob_map = blob_map.get_or_insert_with(HashMap::new);
-    blob_map
-        .entry(elf)
-        .or_insert_with(|| {
-            // This is slow, so cache it.
-            let elf = decompress_zstd(elf);
-            let blob = polkavm_linker::program_from_elf(Default::default(), &elf).unwrap();
-            blob.into_owned()
-        })
-        .clone()
-}
+            let value = caller.with_memory(stack_address, 4, |slice| u32::from_le_bytes(slice.try_into().unwrap()))?;
+            assert_eq!(value, 0xaabbccdd);
+            caller.with_memory_mut(stack_address, 4, |slice| slice.fill(0))?;
 
-fn doom(config: Config, elf: &'static [u8]) {
-    if config.backend() == Some(crate::BackendKind::Interpreter) || config.trace_execution() {
-        // The interpreter is currently too slow to run doom.
-        return;
-    }
+            // This straddles the end of the heap.
+            assert!(caller.with_memory(VM_ADDR_USER_MEMORY + VM_PAGE_SIZE - 2, 4, |_| ()).is_err());
 
-    if cfg!(debug_assertions) {
-        // The linker is currently very slow in debug mode.
-        return;
-    }
+            Ok(0)
+        })
+        .unwrap();
 
-    const DOOM_WAD: &[u8] = include_bytes!("../../../examples/doom/roms/doom1.wad");
+    let instance_pre = linker.instantiate_pre(&module).unwrap();
+    let instance = instance_pre.instantiate().unwrap();
+    let result = instance.get_typed_func::<(), u32>("main").unwrap().call(&mut (), ()).unwrap();
+    assert_eq!(result, 0xdeadbeef);
+    assert_eq!(instance.read_memory_into_new_vec(stack_address, 4).unwrap(), [0, 0, 0, 0]);
+}
 
+fn caller_typed_memory_access_works(config: Config) {
     let _ = env_logger::try_init();
-    let blob = get_blob(elf);
+    let mut builder = ProgramBlobBuilder::new();
+    builder.set_bss_size(VM_PAGE_SIZE);
+    builder.add_export(0, &FnMetadata::new("main", &[I32, I32], Some(I32)));
+    builder.add_import(0, &FnMetadata::new("hostcall", &[I32, I32], Some(I32)));
+    builder.set_code(&[asm::store_imm_u32(0x12345678, VM_ADDR_USER_MEMORY), asm::ecalli(0), asm::ret()]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
     let engine = Engine::new(&config).unwrap();
     let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
     let mut linker = Linker::new(&engine);
 
-    struct State {
-        frame: Vec<u8>,
-        frame_width: u32,
-        frame_height: u32,
+    #[derive(Copy, Clone, PartialEq, Debug)]
+    #[repr(C)]
+    struct Pair {
+        a: u32,
+        b: u16,
+        c: [u8; 2],
     }
 
+    // SAFETY: The struct has no padding and consists only of `Pod` fields.
+    unsafe impl Pod for Pair {}
+
     linker
         .func_wrap(
-            "ext_output_video",
-            |caller: Caller<State>, address: u32, width: u32, height: u32| -> Result<(), Trap> {
-                let (caller, state) = caller.split();
-                let length = width * height * 4;
-                state.frame.clear();
-                state.frame.reserve(length as usize);
-                caller.read_memory_into_slice(address, &mut state.frame.spare_capacity_mut()[..length as usize])?;
-                // SAFETY: We've successfully read this many bytes into this Vec.
-                unsafe {
-                    state.frame.set_len(length as usize);
-                }
-                state.frame_width = width;
-                state.frame_height = height;
-                Ok(())
+            "hostcall",
+            move |mut caller: Caller<()>, pointer: GuestPtr<u32>, length: u32| -> Result<u32, Trap> {
+                assert_eq!(pointer.address(), VM_ADDR_USER_MEMORY);
+                assert_eq!(caller.read_pod(pointer)?, 0x12345678);
+
+                caller.write_u64(VM_ADDR_USER_MEMORY + 4, 0x1122334455667788)?;
+                assert_eq!(caller.read_u64(VM_ADDR_USER_MEMORY + 4)?, 0x1122334455667788);
+                assert_eq!(caller.read_u16(VM_ADDR_USER_MEMORY + 4)?, 0x7788);
+                assert_eq!(caller.read_u8(VM_ADDR_USER_MEMORY + 4)?, 0x88);
+                assert_eq!(caller.read_slice(pointer.slice(length))?, [0x12345678, 0x55667788, 0x11223344]);
+
+                let pair = Pair { a: 1, b: 2, c: [3, 4] };
+                caller.write_pod(VM_ADDR_USER_MEMORY + 16, pair)?;
+                assert_eq!(caller.read_pod::<Pair>(VM_ADDR_USER_MEMORY + 16)?, pair);
+                assert_eq!(caller.read_u32(VM_ADDR_USER_MEMORY + 20)?, 0x04030002);
+
+                caller.write_slice(VM_ADDR_USER_MEMORY + 32, b"hello, world\0")?;
+                assert_eq!(caller.read_str(VM_ADDR_USER_MEMORY + 32, 64)?, "hello, world");
+                assert!(caller.read_str(VM_ADDR_USER_MEMORY + 32, 5).is_err());
+
+                // This is right at the end of the memory, so reading too far would fault.
+                caller.write_slice(VM_ADDR_USER_MEMORY + VM_PAGE_SIZE - 4, b"abc\0")?;
+                assert_eq!(caller.read_str(VM_ADDR_USER_MEMORY + VM_PAGE_SIZE - 4, 1024)?, "abc");
+
+                caller.write_slice(VM_ADDR_USER_MEMORY + 48, &[0xff, 0x00])?;
+                assert!(caller.read_str(VM_ADDR_USER_MEMORY + 48, 64).is_err());
+
+                assert!(caller.read_pod::<u32>(0).is_err());
+                assert!(caller.write_u32(0, 0).is_err());
+                assert!(caller.read_slice(GuestSlice::<u32>::new(VM_ADDR_USER_MEMORY, u32::MAX)).is_err());
+                assert!(caller
+                    .read_slice(GuestSlice::<u8>::new(VM_ADDR_USER_MEMORY, u32::MAX - VM_ADDR_USER_MEMORY))
+                    .is_err());
+                assert!(caller
+                    .read_slice(GuestSlice::<u8>::new(VM_ADDR_USER_MEMORY + VM_PAGE_SIZE - 4, 5))
+                    .is_err());
+
+                Ok(length)
             },
         )
         .unwrap();
 
-    linker
-        .func_wrap(
-            "ext_output_audio",
-            |_caller: Caller<State>, _address: u32, _samples: u32| -> Result<(), Trap> { Ok(()) },
-        )
+    let instance_pre = linker.instantiate_pre(&module).unwrap();
+    let instance = instance_pre.instantiate().unwrap();
+    let result = instance
+        .get_typed_func::<(u32, u32), u32>("main")
+        .unwrap()
+        .call(&mut (), (VM_ADDR_USER_MEMORY, 3))
         .unwrap();
+    assert_eq!(result, 3);
+}
 
-    linker
-        .func_wrap("ext_rom_size", |_caller: Caller<State>| -> u32 { DOOM_WAD.len() as u32 })
-        .unwrap();
+fn multiple_return_values_work(config: Config) {
+    let _ = env_logger::try_init();
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new_with_returns("main", &[I32], &[I32, I64]));
+    builder.add_export(1, &FnMetadata::new("sum", &[I32], Some(I32)));
+    builder.add_import(0, &FnMetadata::new_with_returns("hostcall", &[I32], &[I32, I64]));
+    builder.set_code(&[
+        asm::ecalli(0),
+        asm::add_imm(A0, A0, 1),
+        asm::ret(),
+        asm::ecalli(0),
+        asm::add(A0, A0, A1),
+        asm::ret(),
+    ]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
 
+    let mut linker = Linker::new(&engine);
     linker
-        .func_wrap(
-            "ext_rom_read",
-            |mut caller: Caller<State>, pointer: u32, offset: u32, length: u32| -> Result<(), Trap> {
-                let chunk = DOOM_WAD
-                    .get(offset as usize..offset as usize + length as usize)
-                    .ok_or_else(Trap::default)?;
+        .func_wrap("hostcall", |value: u32| -> (u32, u64) { (value + 10, 0x1122334455667788) })
+        .unwrap();
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
 
-                caller.write_memory(pointer, chunk)
-            },
-        )
+    let result = instance
+        .get_typed_func::<(u32,), (u32, u64)>("main")
+        .unwrap()
+        .call(&mut (), (5,))
         .unwrap();
+    assert_eq!(result, (16, 0x1122334455667788));
+
+    assert!(instance.get_typed_func::<(u32,), u32>("main").is_err());
+    assert!(instance.get_func("main").unwrap().call(&mut (), &[Val::from(5)]).is_err());
 
+    let mut linker = Linker::new(&engine);
     linker
-        .func_wrap(
-            "ext_stdout",
-            |_caller: Caller<State>, _buffer: u32, length: u32| -> Result<i32, Trap> { Ok(length as i32) },
-        )
+        .func_wrap("hostcall", |value: u32| -> Result<(u32, u64), Trap> { Ok((value, u64::MAX)) })
+        .unwrap();
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+    let result = instance
+        .get_typed_func::<(u32,), (u32, u64)>("main")
+        .unwrap()
+        .call(&mut (), (5,))
         .unwrap();
+    assert_eq!(result, (6, u64::MAX));
 
-    let instance_pre = linker.instantiate_pre(&module).unwrap();
-    let instance = instance_pre.instantiate().unwrap();
-    let ext_initialize = instance.get_typed_func::<(), ()>("ext_initialize").unwrap();
-    let ext_tick = instance.get_typed_func::<(), ()>("ext_tick").unwrap();
+    let state = instance
+        .get_func("sum")
+        .unwrap()
+        .call_resumable(&[Val::from(5)], ExecutionConfig::default())
+        .unwrap();
+    assert!(matches!(state, CallState::Suspended { hostcall: 0 }), "unexpected state: {state:?}");
 
-    let mut state = State {
-        frame: Vec::new(),
-        frame_width: 0,
-        frame_height: 0,
-    };
+    let result = instance.resume(Some(Val::from(10)));
+    assert!(matches!(result, Err(ExecutionError::Error(..))), "unexpected result: {result:?}");
 
-    ext_initialize.call(&mut state, ()).unwrap();
-    for nth_frame in 0..=10440 {
-        ext_tick.call(&mut state, ()).unwrap();
+    let state = instance.resume_with_values(&[Val::from(10), Val::I64(20)]).unwrap();
+    assert!(
+        matches!(state, CallState::Finished(Some(Val::I32(30)))),
+        "unexpected state: {state:?}"
+    );
 
-        let expected_frame_raw = match nth_frame {
-            120 => decompress_zstd(include_bytes!("../../../test-data/doom_00120.tga.zst")),
-            1320 => decompress_zstd(include_bytes!("../../../test-data/doom_01320.tga.zst")),
-            9000 => decompress_zstd(include_bytes!("../../../test-data/doom_09000.tga.zst")),
-            10440 => decompress_zstd(include_bytes!("../../../test-data/doom_10440.tga.zst")),
-            _ => continue,
-        };
+    let mut linker: Linker<()> = Linker::new(&engine);
+    linker.func_wrap("hostcall", |value: u32| -> u32 { value }).unwrap();
+    assert!(linker.instantiate_pre(&module).is_err());
+}
 
-        for pixel in state.frame.chunks_exact_mut(4) {
-            pixel.swap(0, 2);
-            pixel[3] = 0xff;
-        }
+fn exports_can_be_called_from_host_functions(config: Config) {
+    let _ = env_logger::try_init();
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("main", &[I32], Some(I32)));
+    builder.add_export(1, &FnMetadata::new("double", &[I32], Some(I32)));
+    builder.add_export(2, &FnMetadata::new("trap", &[], None));
+    builder.add_import(0, &FnMetadata::new("callback", &[I32], Some(I32)));
+    builder.set_code(&[
+        asm::move_reg(S0, A0),
+        asm::ecalli(0),
+        asm::add(A0, A0, S0),
+        asm::ret(),
+        asm::add(A0, A0, A0),
+        asm::load_imm(S0, 1000),
+        asm::ret(),
+        asm::trap(),
+    ]);
 
-        let expected_frame = image::load_from_memory_with_format(&expected_frame_raw, image::ImageFormat::Tga)
-            .unwrap()
-            .to_rgba8();
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let mut config = config;
+    config.set_max_nested_call_depth(4);
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
 
-        if state.frame != *expected_frame.as_raw() {
-            panic!("frame {nth_frame:05} doesn't match!");
+    let instantiate = |callback: fn(Caller<()>, u32) -> Result<u32, Trap>| -> Instance<()> {
+        let mut linker = Linker::new(&engine);
+        linker.func_wrap("callback", callback).unwrap();
+        linker.instantiate_pre(&module).unwrap().instantiate().unwrap()
+    };
+
+    // The nested call clobbers `S0`, which must be restored once we return to the outer call.
+    let instance = instantiate(|mut caller, value| {
+        assert!(matches!(
+            caller.call_export::<(u32,), u32>("missing", (value,)),
+            Err(ExecutionError::Error(..))
+        ));
+        assert!(matches!(
+            caller.call_export::<(), u32>("double", ()),
+            Err(ExecutionError::Error(..))
+        ));
+        caller.call_export::<(u32,), u32>("double", (value,)).map_err(Trap::from_error)
+    });
+    let main = instance.get_typed_func::<(u32,), u32>("main").unwrap();
+    assert_eq!(main.call(&mut (), (5,)).unwrap(), 15);
+    assert_eq!(main.call(&mut (), (7,)).unwrap(), 21);
+
+    // The outer call can continue even if the nested call traps.
+    let instance = instantiate(|mut caller, _| match caller.call_export::<(), ()>("trap", ()) {
+        Err(ExecutionError::Trap(trap)) if trap.kind() == TrapKind::TrapInstruction => Ok(123),
+        result => panic!("unexpected result: {result:?}"),
+    });
+    let main = instance.get_typed_func::<(u32,), u32>("main").unwrap();
+    assert_eq!(main.call(&mut (), (1,)).unwrap(), 124);
+    assert_eq!(main.call(&mut (), (2,)).unwrap(), 125);
+
+    // Recursively call back into `main` until we hit the nesting limit.
+    let instance = instantiate(|mut caller, value| {
+        if value == 0 {
+            return Ok(0);
+        }
+
+        caller.call_export::<(u32,), u32>("main", (value - 1,)).map_err(Trap::from_error)
+    });
+    let main = instance.get_typed_func::<(u32,), u32>("main").unwrap();
+    assert_eq!(main.call(&mut (), (4,)).unwrap(), 4 + 3 + 2 + 1);
+    match main.call(&mut (), (5,)) {
+        Err(ExecutionError::Trap(trap)) => {
+            assert_eq!(trap.kind(), TrapKind::HostFunctionError);
+            assert!(trap.to_string().contains("maximum nested call depth exceeded"), "{trap}");
         }
+        result => panic!("unexpected result: {result:?}"),
     }
 
-    // Generate frames to pick:
-    // for nth_frame in 0..20000 {
-    //     ext_tick.call(&mut state, ()).unwrap();
-    //     if nth_frame % 120 == 0 {
-    //         for pixel in state.frame.chunks_exact_mut(4) {
-    //             pixel.swap(0, 2);
-    //             pixel[3] = 0xff;
-    //         }
-    //         let filename = format!("/tmp/doom-frames/doom_{:05}.tga", nth_frame);
-    //         image::save_buffer(filename, &state.frame, state.frame_width, state.frame_height, image::ColorType::Rgba8).unwrap();
-    //     }
-    // }
+    assert_eq!(main.call(&mut (), (3,)).unwrap(), 3 + 2 + 1);
 }
 
-fn doom_o3_dwarf5(config: Config) {
-    doom(config, include_bytes!("../../../test-data/doom_O3_dwarf5.elf.zst"));
-}
+fn imports_can_be_linked_to_exports_of_other_instances(config: Config) {
+    let _ = env_logger::try_init();
+    let engine = Engine::new(&config).unwrap();
 
-fn doom_o1_dwarf5(config: Config) {
-    doom(config, include_bytes!("../../../test-data/doom_O1_dwarf5.elf.zst"));
-}
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("hash", &[I32, I32], Some(I32)));
+    builder.add_export(1, &FnMetadata::new("trap", &[], None));
+    builder.set_code(&[asm::mul_imm(A0, A0, 31), asm::add(A0, A0, A1), asm::ret(), asm::trap()]);
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let lib_module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    let lib = Linker::new(&engine).instantiate_pre(&lib_module).unwrap().instantiate().unwrap();
 
-fn doom_o3_dwarf2(config: Config) {
-    doom(config, include_bytes!("../../../test-data/doom_O3_dwarf2.elf.zst"));
-}
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("main", &[I32, I32], Some(I32)));
+    builder.add_export(1, &FnMetadata::new("call_trap", &[], None));
+    builder.add_import(0, &FnMetadata::new("lib_hash", &[I32, I32], Some(I32)));
+    builder.add_import(1, &FnMetadata::new("lib_trap", &[], None));
+    builder.set_code(&[asm::ecalli(0), asm::add_imm(A0, A0, 1), asm::ret(), asm::ecalli(1), asm::ret()]);
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
 
-fn pinky(config: Config) {
-    if config.backend() == Some(crate::BackendKind::Interpreter) || config.trace_execution() {
-        // The interpreter is currently too slow to run this.
-        return;
+    let mut linker = Linker::new(&engine);
+    linker.instance_export("lib_hash", &lib, "hash").unwrap();
+    linker.instance_export("lib_trap", &lib, "trap").unwrap();
+    assert!(linker.instance_export("lib_missing", &lib, "missing").is_err());
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+
+    let main = instance.get_typed_func::<(u32, u32), u32>("main").unwrap();
+    assert_eq!(main.call(&mut (), (2, 3)).unwrap(), 2 * 31 + 3 + 1);
+
+    match instance.get_typed_func::<(), ()>("call_trap").unwrap().call(&mut (), ()) {
+        Err(ExecutionError::Trap(trap)) => assert_eq!(trap.kind(), TrapKind::HostFunctionError),
+        result => panic!("unexpected result: {result:?}"),
     }
 
-    let _ = env_logger::try_init();
-    let blob = get_blob(include_bytes!("../../../test-data/bench-pinky.elf.zst"));
+    // Both instances are still usable after the other one has trapped.
+    assert_eq!(main.call(&mut (), (4, 5)).unwrap(), 4 * 31 + 5 + 1);
+    let hash = lib.get_typed_func::<(u32, u32), u32>("hash").unwrap();
+    assert_eq!(hash.call(&mut (), (1, 1)).unwrap(), 32);
+
+    let mut linker = Linker::new(&engine);
+    linker.instance_export("lib_hash", &lib, "trap").unwrap();
+    linker.instance_export("lib_trap", &lib, "trap").unwrap();
+    assert!(linker.instantiate_pre(&module).is_err());
+}
 
+fn calls_into_other_instances_are_gas_metered(config: Config) {
+    let _ = env_logger::try_init();
     let engine = Engine::new(&config).unwrap();
-    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    let mut module_config = ModuleConfig::default();
+    module_config.set_gas_metering(Some(GasMeteringKind::Sync));
+    let with_gas = |gas| {
+        let mut execution_config = ExecutionConfig::default();
+        execution_config.set_gas(Gas::new(gas).unwrap());
+        execution_config
+    };
+
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("hash", &[I32, I32], Some(I32)));
+    builder.add_export(1, &FnMetadata::new("suspend", &[], None));
+    builder.add_import(0, &FnMetadata::new("wait", &[], None));
+    builder.set_code(&[
+        asm::mul_imm(A0, A0, 31),
+        asm::add(A0, A0, A1),
+        asm::ret(),
+        asm::ecalli(0),
+        asm::ret(),
+    ]);
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let lib_module = Module::from_blob(&engine, &module_config, &blob).unwrap();
+    let mut lib_linker = Linker::new(&engine);
+    lib_linker
+        .func_wrap("wait", || unreachable!("host functions are not called during a resumable call"))
+        .unwrap();
+    let lib = lib_linker.instantiate_pre(&lib_module).unwrap().instantiate().unwrap();
+
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("main", &[I32, I32], Some(I32)));
+    builder.add_import(0, &FnMetadata::new("lib_hash", &[I32, I32], Some(I32)));
+    builder.set_code(&[asm::ecalli(0), asm::add_imm(A0, A0, 1), asm::ret()]);
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let module = Module::from_blob(&engine, &module_config, &blob).unwrap();
+
+    let mut linker = Linker::new(&engine);
+    linker.instance_export("lib_hash", &lib, "hash").unwrap();
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+    let main = instance.get_typed_func::<(u32, u32), u32>("main").unwrap();
+
+    // The gas used by the other instance is charged to the caller.
+    let result = main.call_ex(&mut (), (2, 3), with_gas(100));
+    assert!(matches!(result, Ok(66)), "unexpected result: {result:?}");
+    assert_eq!(instance.gas_remaining().unwrap(), Gas::new(94).unwrap());
+
+    let result = main.call_ex(&mut (), (2, 3), with_gas(6));
+    assert!(matches!(result, Ok(66)), "unexpected result: {result:?}");
+    assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+
+    // The caller runs out of gas when the other instance does.
+    let result = main.call_ex(&mut (), (2, 3), with_gas(5));
+    assert!(matches!(result, Err(ExecutionError::OutOfGas)), "unexpected result: {result:?}");
+
+    // A suspended call of the other instance isn't clobbered.
+    let state = lib.get_func("suspend").unwrap().call_resumable(&[], with_gas(100)).unwrap();
+    assert!(matches!(state, CallState::Suspended { hostcall: 0 }), "unexpected state: {state:?}");
+
+    match main.call_ex(&mut (), (2, 3), with_gas(100)) {
+        Err(ExecutionError::Trap(trap)) => assert_eq!(trap.kind(), TrapKind::HostFunctionError),
+        result => panic!("unexpected result: {result:?}"),
+    }
+
+    let state = lib.resume(None).unwrap();
+    assert!(matches!(state, CallState::Finished(None)), "unexpected state: {state:?}");
+
+    let result = main.call_ex(&mut (), (2, 3), with_gas(100));
+    assert!(matches!(result, Ok(66)), "unexpected result: {result:?}");
+}
+
+fn namespaced_imports_work(config: Config) {
+    let _ = env_logger::try_init();
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("main", &[], Some(I32)));
+    builder.add_import(0, &FnMetadata::new("env_v1::get", &[], Some(I32)));
+    builder.add_import(1, &FnMetadata::new("env_v2::get", &[], Some(I32)));
+    builder.add_import(2, &FnMetadata::new("env_v2::get_old", &[], Some(I32)));
+    builder.set_code(&[
+        asm::ecalli(0),
+        asm::move_reg(S0, A0),
+        asm::ecalli(1),
+        asm::add(S0, S0, A0),
+        asm::ecalli(2),
+        asm::add(A0, A0, S0),
+        asm::ret(),
+    ]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+
+    let mut linker: Linker<()> = Linker::new(&engine);
+    linker.func_wrap_in("env_v1", "get", || -> u32 { 1 }).unwrap();
+    linker.func_wrap_in("env_v2", "get", || -> u32 { 20 }).unwrap();
+    linker.alias("env_v2::get_old", "env_v1::get").unwrap();
+    assert!(linker.alias("env_v2::get_older", "env_v0::get").is_err());
+    assert!(linker.func_wrap_in("", "get", || -> u32 { 0 }).is_err());
+    assert!(linker.func_wrap_in("env::v3", "get", || -> u32 { 0 }).is_err());
+
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+    let main = instance.get_typed_func::<(), u32>("main").unwrap();
+    assert_eq!(main.call(&mut (), ()).unwrap(), 1 + 20 + 1);
+
+    assert!(linker.func_wrap("env_v2::get", || -> u32 { 300 }).is_err());
+    linker.allow_shadowing(true);
+    linker.func_wrap("env_v2::get", || -> u32 { 300 }).unwrap();
+
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+    let main = instance.get_typed_func::<(), u32>("main").unwrap();
+    assert_eq!(main.call(&mut (), ()).unwrap(), 1 + 300 + 1);
+
+    let mut linker: Linker<()> = Linker::new(&engine);
+    linker.func_wrap("get", || -> u32 { 1 }).unwrap();
+    assert!(linker.instantiate_pre(&module).is_err());
+}
+
+fn module_can_be_introspected(config: Config) {
+    let _ = env_logger::try_init();
+    let blob = basic_test_blob();
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+
+    let exports: Vec<_> = module.exports().iter().map(|export| export.prototype().name()).collect();
+    assert_eq!(exports, ["main"]);
+    assert_eq!(module.exports()[0].prototype().args().collect::<Vec<_>>(), [I32, I32]);
+
+    let imports: Vec<_> = module.imports().map(|import| (import.index(), import.prototype().name())).collect();
+    assert_eq!(imports, [(0, "hostcall")]);
+    assert_eq!(module.imports().next().unwrap().prototype().return_ty(), Some(I32));
+
+    let memory_map = module.memory_map();
+    assert!(memory_map.ro_data_range().is_empty());
+    assert!(memory_map.rw_data_range().is_empty());
+    assert_eq!(memory_map.heap_range().start, VM_ADDR_USER_MEMORY);
+    assert_eq!(memory_map.heap_range().len(), VM_PAGE_SIZE as usize);
+    assert_eq!(memory_map.stack_range().end, VM_ADDR_USER_STACK_HIGH);
+
+    assert_eq!(module.instruction_count(), 5);
+    assert_eq!(module.basic_block_count(), 1);
+    assert!(!module.has_debug_info());
+
+    let blob = get_blob(include_bytes!("../../../test-data/test-blob.elf.zst"));
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    assert!(module.has_debug_info());
+    assert!(module.imports().any(|import| import.prototype().name() == "multiply_by_2"));
+    assert!(!module.memory_map().rw_data_range().is_empty());
+}
+
+fn user_errors_are_propagated_from_host_functions(config: Config) {
+    let _ = env_logger::try_init();
+    let blob = basic_test_blob();
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    let mut linker = Linker::new(&engine);
+
+    #[derive(Debug, PartialEq)]
+    struct QuotaExceeded(u32);
+
+    impl core::fmt::Display for QuotaExceeded {
+        fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+            write!(fmt, "storage quota exceeded by {} bytes", self.0)
+        }
+    }
+
+    impl std::error::Error for QuotaExceeded {}
+
+    linker
+        .func_wrap("hostcall", move |_caller: Caller<()>| -> Result<u32, Trap> {
+            Err(Trap::from_error(QuotaExceeded(123)))
+        })
+        .unwrap();
+
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+    let trap = match instance.get_typed_func::<(u32, u32), u32>("main").unwrap().call(&mut (), (1, 10)) {
+        Err(ExecutionError::Trap(trap)) => trap,
+        result => panic!("unexpected result: {result:?}"),
+    };
+
+    assert_eq!(trap.kind(), TrapKind::HostFunctionError);
+    assert_eq!(trap.downcast_ref::<QuotaExceeded>(), Some(&QuotaExceeded(123)));
+    assert!(trap.to_string().ends_with("storage quota exceeded by 123 bytes"));
+
+    let error = trap.into_error().unwrap();
+    assert_eq!(*error.downcast::<QuotaExceeded>().unwrap(), QuotaExceeded(123));
+}
+
+fn trap_kinds_are_reported(config: Config) {
+    let _ = env_logger::try_init();
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("trap", &[], None));
+    builder.add_export(1, &FnMetadata::new("invalid_load", &[], None));
+    builder.add_export(2, &FnMetadata::new("invalid_store", &[], None));
+    builder.add_export(3, &FnMetadata::new("invalid_jump", &[], None));
+    builder.set_code(&[
+        asm::trap(),
+        asm::load_u32(A0, 0x100),
+        asm::ret(),
+        asm::store_imm_u32(1, 0x200),
+        asm::ret(),
+        asm::load_imm(A0, 1),
+        asm::jump_indirect(A0, 0),
+    ]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    let linker: Linker<()> = Linker::new(&engine);
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+
+    let call = |name: &str| -> Trap {
+        match instance.get_typed_func::<(), ()>(name).unwrap().call(&mut (), ()) {
+            Err(ExecutionError::Trap(trap)) => trap,
+            result => panic!("unexpected result: {result:?}"),
+        }
+    };
+
+    let trap = call("trap");
+    assert_eq!(trap.kind(), TrapKind::TrapInstruction);
+    assert_eq!(trap.program_counter(), Some(0));
+
+    let trap = call("invalid_load");
+    assert_eq!(trap.kind(), TrapKind::InvalidLoad { address: 0x100 });
+    assert_eq!(trap.program_counter(), Some(1));
+
+    let trap = call("invalid_store");
+    assert_eq!(trap.kind(), TrapKind::InvalidStore { address: 0x200 });
+    assert_eq!(trap.program_counter(), Some(3));
+
+    // The compiled backend doesn't know where an invalid jump came from.
+    let trap = call("invalid_jump");
+    assert_eq!(trap.kind(), TrapKind::InvalidJumpTarget);
+    assert!(matches!(trap.program_counter(), None | Some(6)));
+}
+
+fn backtrace_is_captured_on_trap(config: Config) {
+    let _ = env_logger::try_init();
+    let mut builder = ProgramBlobBuilder::new();
+    builder.set_stack_size(VM_PAGE_SIZE);
+    builder.add_export(0, &FnMetadata::new("main", &[], None));
+    builder.set_jump_table(&[1, 3]);
+    builder.set_code(&[
+        // @0:
+        asm::call(RA, 2),
+        // @1:
+        asm::ret(),
+        // @2:
+        asm::add_imm(SP, SP, (-8_i32) as u32),
+        asm::store_indirect_u32(RA, SP, 4),
+        asm::call(RA, 4),
+        // @3:
+        asm::load_indirect_u32(RA, SP, 4),
+        asm::add_imm(SP, SP, 8),
+        asm::ret(),
+        // @4:
+        asm::trap(),
+    ]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    let linker: Linker<()> = Linker::new(&engine);
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+
+    let trap = match instance.get_typed_func::<(), ()>("main").unwrap().call(&mut (), ()) {
+        Err(ExecutionError::Trap(trap)) => trap,
+        result => panic!("unexpected result: {result:?}"),
+    };
+
+    let backtrace = trap.backtrace().unwrap();
+    let program_counters: Vec<u32> = backtrace.frames().iter().map(|frame| frame.program_counter()).collect();
+    assert_eq!(program_counters, [8, 4, 0]);
+    assert!(backtrace.frames().iter().all(|frame| frame.symbols().is_empty()));
+}
+
+fn backtrace_is_captured_on_trap_with_deep_stack(config: Config) {
+    let _ = env_logger::try_init();
+    let mut builder = ProgramBlobBuilder::new();
+    builder.set_stack_size(VM_PAGE_SIZE);
+    builder.add_export(0, &FnMetadata::new("main", &[I32], None));
+    builder.set_jump_table(&[1, 4]);
+    builder.set_code(&[
+        // @0:
+        asm::call(RA, 2),
+        // @1:
+        asm::ret(),
+        // @2:
+        asm::add_imm(SP, SP, (-8_i32) as u32),
+        asm::store_indirect_u32(RA, SP, 4),
+        asm::branch_eq_imm(A0, 0, 5),
+        // @3:
+        asm::add_imm(A0, A0, (-1_i32) as u32),
+        asm::call(RA, 2),
+        // @4:
+        asm::load_indirect_u32(RA, SP, 4),
+        asm::add_imm(SP, SP, 8),
+        asm::ret(),
+        // @5:
+        asm::trap(),
+    ]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    let linker: Linker<()> = Linker::new(&engine);
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+
+    // The stack is walked in chunks, so recurse deep enough to cross a few of them.
+    for (depth, expected_frame_count) in [(10, 12), (200, 202), (300, 256)] {
+        let trap = match instance.get_typed_func::<(u32,), ()>("main").unwrap().call(&mut (), (depth,)) {
+            Err(ExecutionError::Trap(trap)) => trap,
+            result => panic!("unexpected result: {result:?}"),
+        };
+
+        let frames = trap.backtrace().unwrap().frames();
+        assert_eq!(frames.len(), expected_frame_count);
+        assert_eq!(frames[0].program_counter(), 10);
+        assert!(frames[1..frames.len() - 1].iter().all(|frame| frame.program_counter() == 6));
+        if expected_frame_count == depth as usize + 2 {
+            assert_eq!(frames.last().unwrap().program_counter(), 0);
+        }
+    }
+}
+
+fn trapping_from_hostcall_handler_works(config: Config) {
+    let _ = env_logger::try_init();
+    let blob = basic_test_blob();
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    let mut linker = Linker::new(&engine);
+
+    enum Kind {
+        Ok,
+        Trap,
+    }
+
+    linker
+        .func_wrap("hostcall", move |caller: Caller<Kind>| -> Result<u32, Trap> {
+            match *caller.data() {
+                Kind::Ok => Ok(100),
+                Kind::Trap => Err(Trap::default()),
+            }
+        })
+        .unwrap();
+
+    let instance_pre = linker.instantiate_pre(&module).unwrap();
+    let instance = instance_pre.instantiate().unwrap();
+
+    let result = instance
+        .get_typed_func::<(u32, u32), u32>("main")
+        .unwrap()
+        .call(&mut Kind::Ok, (1, 10));
+    assert!(matches!(result, Ok(111)));
+
+    let result = instance
+        .get_typed_func::<(u32, u32), u32>("main")
+        .unwrap()
+        .call(&mut Kind::Trap, (1, 10));
+    match result {
+        Err(ExecutionError::Trap(trap)) => {
+            assert_eq!(trap.kind(), TrapKind::HostFunctionError);
+            assert_eq!(trap.program_counter(), Some(2));
+        }
+        _ => panic!("unexpected result: {result:?}"),
+    }
+
+    let result = instance
+        .get_func("main")
+        .unwrap()
+        .call(&mut Kind::Ok, &[Val::from(1), Val::from(10)]);
+    assert!(matches!(result, Ok(Some(Val::I32(111)))));
+
+    let result = instance
+        .get_func("main")
+        .unwrap()
+        .call(&mut Kind::Trap, &[Val::from(1), Val::from(10)]);
+    assert!(matches!(result, Err(ExecutionError::Trap(..))));
+}
+
+fn suspending_and_resuming_execution_works(config: Config) {
+    let _ = env_logger::try_init();
+    let blob = basic_test_blob();
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    let mut linker = Linker::new(&engine);
+    linker
+        .func_wrap("hostcall", move || -> u32 {
+            unreachable!("host functions are not called during a resumable call")
+        })
+        .unwrap();
+
+    let instance_pre = linker.instantiate_pre(&module).unwrap();
+    let instance: Instance<()> = instance_pre.instantiate().unwrap();
+    let func = instance.get_func("main").unwrap();
+
+    let state = func
+        .call_resumable(&[Val::from(1), Val::from(10)], ExecutionConfig::default())
+        .unwrap();
+    assert!(matches!(state, CallState::Suspended { hostcall: 0 }), "unexpected state: {state:?}");
+    assert_eq!(instance.get_reg(Reg::S0), 11);
+    assert_eq!(
+        instance.read_memory_into_new_vec(VM_ADDR_USER_MEMORY, 4).unwrap(),
+        0x12345678_u32.to_le_bytes()
+    );
+
+    let result = instance.resume(Some(Val::I64(100)));
+    assert!(matches!(result, Err(ExecutionError::Error(..))), "unexpected result: {result:?}");
+
+    let state = instance.resume(Some(Val::from(100))).unwrap();
+    assert!(
+        matches!(state, CallState::Finished(Some(Val::I32(111)))),
+        "unexpected state: {state:?}"
+    );
+
+    let result = instance.resume(Some(Val::from(100)));
+    assert!(matches!(result, Err(ExecutionError::Error(..))), "unexpected result: {result:?}");
+
+    // A new call abandons the one which is currently suspended.
+    let state = func
+        .call_resumable(&[Val::from(1), Val::from(10)], ExecutionConfig::default())
+        .unwrap();
+    assert!(matches!(state, CallState::Suspended { hostcall: 0 }), "unexpected state: {state:?}");
+    let state = func
+        .call_resumable(&[Val::from(2), Val::from(3)], ExecutionConfig::default())
+        .unwrap();
+    assert!(matches!(state, CallState::Suspended { hostcall: 0 }), "unexpected state: {state:?}");
+
+    let state = instance.resume(Some(Val::from(5))).unwrap();
+    assert!(
+        matches!(state, CallState::Finished(Some(Val::I32(10)))),
+        "unexpected state: {state:?}"
+    );
+}
+
+fn block_on<F: core::future::Future>(future: F) -> F::Output {
+    struct ThreadWaker(std::thread::Thread);
+    impl std::task::Wake for ThreadWaker {
+        fn wake(self: std::sync::Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = core::task::Waker::from(std::sync::Arc::new(ThreadWaker(std::thread::current())));
+    let mut context = core::task::Context::from_waker(&waker);
+    let mut future = core::pin::pin!(future);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            core::task::Poll::Ready(output) => return output,
+            core::task::Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+struct YieldOnce(bool);
+
+impl core::future::Future for YieldOnce {
+    type Output = ();
+    fn poll(mut self: core::pin::Pin<&mut Self>, context: &mut core::task::Context) -> core::task::Poll<()> {
+        if self.0 {
+            core::task::Poll::Ready(())
+        } else {
+            self.0 = true;
+            context.waker().wake_by_ref();
+            core::task::Poll::Pending
+        }
+    }
+}
+
+fn asynchronous_host_functions_work(config: Config) {
+    let _ = env_logger::try_init();
+    let blob = basic_test_blob();
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    let mut linker = Linker::new(&engine);
+    linker
+        .func_wrap_async("hostcall", |caller: Caller<()>| {
+            let value = caller.read_u32(VM_ADDR_USER_MEMORY);
+            async move {
+                YieldOnce(false).await;
+                assert_eq!(value.unwrap(), 0x12345678);
+                100_u32
+            }
+        })
+        .unwrap();
+
+    let instance_pre = linker.instantiate_pre(&module).unwrap();
+    let instance = instance_pre.instantiate().unwrap();
+
+    let func = instance.get_typed_func::<(u32, u32), u32>("main").unwrap();
+    assert_eq!(block_on(func.call_async(&mut (), (1, 10))).unwrap(), 111);
+
+    let func = instance.get_func("main").unwrap();
+    let result = block_on(func.call_async(&mut (), &[Val::from(2), Val::from(20)])).unwrap();
+    assert!(matches!(result, Some(Val::I32(122))), "unexpected result: {result:?}");
+
+    // Asynchronous host functions cannot be called from a synchronous call.
+    match func.call(&mut (), &[Val::from(1), Val::from(10)]) {
+        Err(ExecutionError::Trap(trap)) => assert_eq!(trap.kind(), TrapKind::HostFunctionError),
+        result => panic!("unexpected result: {result:?}"),
+    }
+}
+
+fn other_calls_are_refused_while_an_async_call_is_suspended(config: Config) {
+    let _ = env_logger::try_init();
+    let blob = basic_test_blob();
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    let mut linker = Linker::new(&engine);
+    linker
+        .func_wrap_async("hostcall", |_caller: Caller<u32>| async move {
+            YieldOnce(false).await;
+            100_u32
+        })
+        .unwrap();
+
+    let instance_pre = linker.instantiate_pre(&module).unwrap();
+    let instance = instance_pre.instantiate().unwrap();
+    let func = instance.get_typed_func::<(u32, u32), u32>("main").unwrap();
+
+    struct NoopWaker;
+    impl std::task::Wake for NoopWaker {
+        fn wake(self: std::sync::Arc<Self>) {}
+    }
+
+    let waker = core::task::Waker::from(std::sync::Arc::new(NoopWaker));
+    let mut context = core::task::Context::from_waker(&waker);
+
+    let assert_refused = |instance: &Instance<u32>| {
+        let func = instance.get_func("main").unwrap();
+        let args = [Val::from(2), Val::from(20)];
+        let result = func.call(&mut 0, &args);
+        assert!(matches!(result, Err(ExecutionError::Error(..))), "unexpected result: {result:?}");
+        let result = block_on(func.call_async(&mut 0, &args));
+        assert!(matches!(result, Err(ExecutionError::Error(..))), "unexpected result: {result:?}");
+        let result = func.call_resumable(&args, ExecutionConfig::default());
+        assert!(matches!(result, Err(ExecutionError::Error(..))), "unexpected result: {result:?}");
+        let result = instance.resume(Some(Val::from(0)));
+        assert!(matches!(result, Err(ExecutionError::Error(..))), "unexpected result: {result:?}");
+        assert!(instance.freeze().is_err());
+    };
+
+    {
+        let mut user_data = 0;
+        let mut future = core::pin::pin!(func.call_async(&mut user_data, (1, 10)));
+        assert!(core::future::Future::poll(future.as_mut(), &mut context).is_pending());
+
+        // The instance (and all of its clones) isn't locked while the host function is pending,
+        // but its execution still belongs to the suspended call.
+        assert_refused(&instance);
+
+        let result = loop {
+            if let core::task::Poll::Ready(result) = core::future::Future::poll(future.as_mut(), &mut context) {
+                break result;
+            }
+        };
+        assert_eq!(result.unwrap(), 111);
+    }
+
+    assert_eq!(block_on(func.call_async(&mut 0, (2, 20))).unwrap(), 122);
+
+    // Dropping the future abandons the suspended execution, so the instance can be used again.
+    {
+        let mut user_data = 0;
+        let mut future = Box::pin(func.call_async(&mut user_data, (1, 10)));
+        assert!(core::future::Future::poll(future.as_mut(), &mut context).is_pending());
+        assert_refused(&instance);
+    }
+
+    assert_eq!(block_on(func.call_async(&mut 0, (3, 30))).unwrap(), 133);
+}
+
+fn interrupting_execution_works(config: Config) {
+    let _ = env_logger::try_init();
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("infinite_loop", &[], None));
+    builder.add_export(1, &FnMetadata::new("infinite_loop_with_hostcall", &[], None));
+    builder.add_export(2, &FnMetadata::new("main", &[], Some(I32)));
+    builder.add_import(0, &FnMetadata::new("hostcall", &[], None));
+    builder.set_code(&[asm::jump(0), asm::ecalli(0), asm::jump(1), asm::load_imm(A0, 1), asm::ret()]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    let mut linker = Linker::new(&engine);
+    linker.func_wrap("hostcall", || {}).unwrap();
+    let instance: Instance<()> = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+
+    let handle = instance.interrupt_handle();
+    let thread = std::thread::spawn(move || {
+        std::thread::sleep(core::time::Duration::from_millis(50));
+        handle.interrupt();
+    });
+
+    let result = instance
+        .get_typed_func::<(), ()>("infinite_loop_with_hostcall")
+        .unwrap()
+        .call(&mut (), ());
+    assert!(matches!(result, Err(ExecutionError::Interrupted)), "unexpected result: {result:?}");
+    thread.join().unwrap();
+
+    let mut config_with_deadline = ExecutionConfig::default();
+    config_with_deadline.set_deadline(std::time::Instant::now() + core::time::Duration::from_millis(50));
+    let result = instance
+        .get_typed_func::<(), ()>("infinite_loop_with_hostcall")
+        .unwrap()
+        .call_ex(&mut (), (), config_with_deadline);
+    assert!(matches!(result, Err(ExecutionError::Interrupted)), "unexpected result: {result:?}");
+
+    // The generic sandbox can only be interrupted when the program calls into the host.
+    if config.sandbox() != Some(SandboxKind::Generic) || config.trace_execution() {
+        let handle = instance.interrupt_handle();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(core::time::Duration::from_millis(50));
+            handle.interrupt();
+        });
+
+        let result = instance.get_typed_func::<(), ()>("infinite_loop").unwrap().call(&mut (), ());
+        assert!(matches!(result, Err(ExecutionError::Interrupted)), "unexpected result: {result:?}");
+        thread.join().unwrap();
+
+        let mut config_with_deadline = ExecutionConfig::default();
+        config_with_deadline.set_deadline(std::time::Instant::now() + core::time::Duration::from_millis(50));
+        let result = instance
+            .get_typed_func::<(), ()>("infinite_loop")
+            .unwrap()
+            .call_ex(&mut (), (), config_with_deadline);
+        assert!(matches!(result, Err(ExecutionError::Interrupted)), "unexpected result: {result:?}");
+    }
+
+    // The instance can still be used after being interrupted.
+    assert_eq!(instance.get_typed_func::<(), u32>("main").unwrap().call(&mut (), ()).unwrap(), 1);
+}
+
+fn decompress_zstd(mut bytes: &[u8]) -> Vec<u8> {
+    use std::io::Read;
+    let mut output = Vec::new();
+    ruzstd::streaming_decoder::StreamingDecoder::new(&mut bytes)
+        .unwrap()
+        .read_to_end(&mut output)
+        .unwrap();
+    output
+}
+
+static BLOB_MAP: Mutex<Option<HashMap<&'static [u8], ProgramBlob>>> = Mutex::new(None);
+
+fn get_blob(elf: &'static [u8]) -> ProgramBlob {
+    let mut blob_map = match BLOB_MAP.lock() {
+        Ok(blob_map) => blob_map,
+        Err(error) => error.into_inner(),
+    };
+
+    let blob_map = blob_map.get_or_insert_with(HashMap::new);
+    blob_map
+        .entry(elf)
+        .or_insert_with(|| {
+            // This is slow, so cache it.
+            let elf = decompress_zstd(elf);
+            let blob = polkavm_linker::program_from_elf(Default::default(), &elf).unwrap();
+            blob.into_owned()
+        })
+        .clone()
+}
+
+fn doom(config: Config, elf: &'static [u8]) {
+    if config.backend() == Some(crate::BackendKind::Interpreter) || config.trace_execution() {
+        // The interpreter is currently too slow to run doom.
+        return;
+    }
+
+    if cfg!(debug_assertions) {
+        // The linker is currently very slow in debug mode.
+        return;
+    }
+
+    const DOOM_WAD: &[u8] = include_bytes!("../../../examples/doom/roms/doom1.wad");
+
+    let _ = env_logger::try_init();
+    let blob = get_blob(elf);
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    let mut linker = Linker::new(&engine);
+
+    struct State {
+        frame: Vec<u8>,
+        frame_width: u32,
+        frame_height: u32,
+    }
+
+    linker
+        .func_wrap(
+            "ext_output_video",
+            |caller: Caller<State>, address: u32, width: u32, height: u32| -> Result<(), Trap> {
+                let (caller, state) = caller.split();
+                let length = width * height * 4;
+                state.frame.clear();
+                state.frame.reserve(length as usize);
+                caller.read_memory_into_slice(address, &mut state.frame.spare_capacity_mut()[..length as usize])?;
+                // SAFETY: We've successfully read this many bytes into this Vec.
+                unsafe {
+                    state.frame.set_len(length as usize);
+                }
+                state.frame_width = width;
+                state.frame_height = height;
+                Ok(())
+            },
+        )
+        .unwrap();
+
+    linker
+        .func_wrap(
+            "ext_output_audio",
+            |_caller: Caller<State>, _address: u32, _samples: u32| -> Result<(), Trap> { Ok(()) },
+        )
+        .unwrap();
+
+    linker
+        .func_wrap("ext_rom_size", |_caller: Caller<State>| -> u32 { DOOM_WAD.len() as u32 })
+        .unwrap();
+
+    linker
+        .func_wrap(
+            "ext_rom_read",
+            |mut caller: Caller<State>, pointer: u32, offset: u32, length: u32| -> Result<(), Trap> {
+                let chunk = DOOM_WAD
+                    .get(offset as usize..offset as usize + length as usize)
+                    .ok_or_else(Trap::default)?;
+
+                caller.write_memory(pointer, chunk)
+            },
+        )
+        .unwrap();
+
+    linker
+        .func_wrap(
+            "ext_stdout",
+            |_caller: Caller<State>, _buffer: u32, length: u32| -> Result<i32, Trap> { Ok(length as i32) },
+        )
+        .unwrap();
+
+    let instance_pre = linker.instantiate_pre(&module).unwrap();
+    let instance = instance_pre.instantiate().unwrap();
+    let ext_initialize = instance.get_typed_func::<(), ()>("ext_initialize").unwrap();
+    let ext_tick = instance.get_typed_func::<(), ()>("ext_tick").unwrap();
+
+    let mut state = State {
+        frame: Vec::new(),
+        frame_width: 0,
+        frame_height: 0,
+    };
+
+    ext_initialize.call(&mut state, ()).unwrap();
+    for nth_frame in 0..=10440 {
+        ext_tick.call(&mut state, ()).unwrap();
+
+        let expected_frame_raw = match nth_frame {
+            120 => decompress_zstd(include_bytes!("../../../test-data/doom_00120.tga.zst")),
+            1320 => decompress_zstd(include_bytes!("../../../test-data/doom_01320.tga.zst")),
+            9000 => decompress_zstd(include_bytes!("../../../test-data/doom_09000.tga.zst")),
+            10440 => decompress_zstd(include_bytes!("../../../test-data/doom_10440.tga.zst")),
+            _ => continue,
+        };
+
+        for pixel in state.frame.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+            pixel[3] = 0xff;
+        }
+
+        let expected_frame = image::load_from_memory_with_format(&expected_frame_raw, image::ImageFormat::Tga)
+            .unwrap()
+            .to_rgba8();
+
+        if state.frame != *expected_frame.as_raw() {
+            panic!("frame {nth_frame:05} doesn't match!");
+        }
+    }
+
+    // Generate frames to pick:
+    // for nth_frame in 0..20000 {
+    //     ext_tick.call(&mut state, ()).unwrap();
+    //     if nth_frame % 120 == 0 {
+    //         for pixel in state.frame.chunks_exact_mut(4) {
+    //             pixel.swap(0, 2);
+    //             pixel[3] = 0xff;
+    //         }
+    //         let filename = format!("/tmp/doom-frames/doom_{:05}.tga", nth_frame);
+    //         image::save_buffer(filename, &state.frame, state.frame_width, state.frame_height, image::ColorType::Rgba8).unwrap();
+    //     }
+    // }
+}
+
+fn doom_o3_dwarf5(config: Config) {
+    doom(config, include_bytes!("../../../test-data/doom_O3_dwarf5.elf.zst"));
+}
+
+fn doom_o1_dwarf5(config: Config) {
+    doom(config, include_bytes!("../../../test-data/doom_O1_dwarf5.elf.zst"));
+}
+
+fn doom_o3_dwarf2(config: Config) {
+    doom(config, include_bytes!("../../../test-data/doom_O3_dwarf2.elf.zst"));
+}
+
+fn pinky(config: Config) {
+    if config.backend() == Some(crate::BackendKind::Interpreter) || config.trace_execution() {
+        // The interpreter is currently too slow to run this.
+        return;
+    }
+
+    let _ = env_logger::try_init();
+    let blob = get_blob(include_bytes!("../../../test-data/bench-pinky.elf.zst"));
+
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    let linker = Linker::new(&engine);
+    let instance_pre = linker.instantiate_pre(&module).unwrap();
+    let instance = instance_pre.instantiate().unwrap();
+    let ext_initialize = instance.get_typed_func::<(), ()>("initialize").unwrap();
+    let ext_run = instance.get_typed_func::<(), ()>("run").unwrap();
+    let ext_get_framebuffer = instance.get_typed_func::<(), u32>("get_framebuffer").unwrap();
+
+    ext_initialize.call(&mut (), ()).unwrap();
+    for _ in 0..256 {
+        ext_run.call(&mut (), ()).unwrap();
+    }
+
+    let address = ext_get_framebuffer.call(&mut (), ()).unwrap();
+    let framebuffer = instance.read_memory_into_new_vec(address, 256 * 240 * 4).unwrap();
+
+    let expected_frame_raw = decompress_zstd(include_bytes!("../../../test-data/pinky_00256.tga.zst"));
+    let expected_frame = image::load_from_memory_with_format(&expected_frame_raw, image::ImageFormat::Tga)
+        .unwrap()
+        .to_rgba8();
+
+    if framebuffer != *expected_frame.as_raw() {
+        panic!("frames doesn't match!");
+    }
+}
+
+struct TestInstance {
+    instance: crate::Instance<()>,
+}
+
+impl TestInstance {
+    fn new(config: &Config) -> Self {
+        let _ = env_logger::try_init();
+        let blob = get_blob(include_bytes!("../../../test-data/test-blob.elf.zst"));
+
+        let engine = Engine::new(config).unwrap();
+        let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+        let mut linker = Linker::new(&engine);
+        linker
+            .func_wrap("multiply_by_2", |_caller: Caller<()>, value: u32| -> Result<u32, Trap> {
+                Ok(value * 2)
+            })
+            .unwrap();
+
+        let instance_pre = linker.instantiate_pre(&module).unwrap();
+        let instance = instance_pre.instantiate().unwrap();
+
+        TestInstance { instance }
+    }
+
+    pub fn call<FnArgs, FnResult>(&self, name: &str, args: FnArgs) -> Result<FnResult, crate::ExecutionError<crate::Error>>
+    where
+        FnArgs: crate::api::FuncArgs,
+        FnResult: crate::api::FuncResult,
+    {
+        let function = self
+            .instance
+            .get_typed_func::<FnArgs, FnResult>(name)
+            .expect("function doesn't exist");
+        function.call(&mut (), args)
+    }
+}
+
+fn test_blob_basic_test(config: Config) {
+    let i = TestInstance::new(&config);
+    assert_eq!(i.call::<(), u32>("push_one_to_global_vec", ()).unwrap(), 1);
+    assert_eq!(i.call::<(), u32>("push_one_to_global_vec", ()).unwrap(), 2);
+    assert_eq!(i.call::<(), u32>("push_one_to_global_vec", ()).unwrap(), 3);
+}
+
+fn test_blob_atomic_fetch_add(config: Config) {
+    let i = TestInstance::new(&config);
+    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_add", (1,)).unwrap(), 0);
+    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_add", (1,)).unwrap(), 1);
+    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_add", (1,)).unwrap(), 2);
+    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_add", (0,)).unwrap(), 3);
+    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_add", (0,)).unwrap(), 3);
+    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_add", (2,)).unwrap(), 3);
+    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_add", (0,)).unwrap(), 5);
+}
+
+fn test_blob_atomic_fetch_swap(config: Config) {
+    let i = TestInstance::new(&config);
+    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_swap", (10,)).unwrap(), 0);
+    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_swap", (100,)).unwrap(), 10);
+    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_swap", (1000,)).unwrap(), 100);
+}
+
+fn test_blob_atomic_fetch_minmax(config: Config) {
+    use core::cmp::{max, min};
+
+    fn maxu(a: i32, b: i32) -> i32 {
+        max(a as u32, b as u32) as i32
+    }
+
+    fn minu(a: i32, b: i32) -> i32 {
+        min(a as u32, b as u32) as i32
+    }
+
+    #[allow(clippy::type_complexity)]
+    let list: [(&str, fn(i32, i32) -> i32); 4] = [
+        ("atomic_fetch_max_signed", max),
+        ("atomic_fetch_min_signed", min),
+        ("atomic_fetch_max_unsigned", maxu),
+        ("atomic_fetch_min_unsigned", minu),
+    ];
+
+    let i = TestInstance::new(&config);
+    for (name, cb) in list {
+        for a in [-10, 0, 10] {
+            for b in [-10, 0, 10] {
+                let new_value = cb(a, b);
+                i.call::<(i32,), ()>("set_atomic_global", (a,)).unwrap();
+                assert_eq!(i.call::<(i32,), i32>(name, (b,)).unwrap(), a);
+                assert_eq!(i.call::<(), i32>("get_atomic_global", ()).unwrap(), new_value);
+            }
+        }
+    }
+}
+
+fn test_blob_hostcall(config: Config) {
+    let i = TestInstance::new(&config);
+    assert_eq!(i.call::<(u32,), u32>("test_multiply_by_6", (10,)).unwrap(), 60);
+}
+
+fn test_blob_rv32emac(config: Config) {
+    let _ = env_logger::try_init();
+    let blob = get_blob(include_bytes!("../../../test-data/test-blob-rv32emac.elf.zst"));
+
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    let mut linker = Linker::new(&engine);
+    linker
+        .func_wrap("multiply_by_2", |_caller: Caller<()>, value: u32| -> Result<u32, Trap> {
+            Ok(value * 2)
+        })
+        .unwrap();
+
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+    let test_compressed = instance.get_typed_func::<(u32,), u32>("test_compressed").unwrap();
+    let increment_counter = instance.get_typed_func::<(), u32>("increment_counter").unwrap();
+
+    assert_eq!(test_compressed.call(&mut (), (5,)).unwrap(), ((((5 + 1) * 2) << 4) ^ 5) + 0x12000);
+    assert_eq!(test_compressed.call(&mut (), (0,)).unwrap(), 0x55);
+    assert_eq!(increment_counter.call(&mut (), ()).unwrap(), 101);
+    assert_eq!(increment_counter.call(&mut (), ()).unwrap(), 102);
+}
+
+fn sbrk_grows_the_heap(config: Config) {
+    let _ = env_logger::try_init();
+
+    let mut builder = ProgramBlobBuilder::new();
+    builder.set_bss_size(VM_PAGE_SIZE);
+    builder.add_export(0, &FnMetadata::new("sbrk", &[I32], Some(I32)));
+    builder.add_export(1, &FnMetadata::new("load", &[I32], Some(I32)));
+    builder.add_export(2, &FnMetadata::new("store", &[I32, I32], None));
+    builder.set_code(&[
+        asm::sbrk(A0, A0),
+        asm::ret(),
+        asm::load_indirect_u32(A0, A0, 0),
+        asm::ret(),
+        asm::store_indirect_u32(A1, A0, 0),
+        asm::ret(),
+    ]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let mut module_config = ModuleConfig::default();
+    module_config.set_max_heap_size(VM_PAGE_SIZE * 4);
+
+    let module = Module::from_blob(&engine, &module_config, &blob).unwrap();
+    let heap_range = module.memory_map().heap_range();
+    let linker = Linker::new(&engine);
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+    let sbrk = instance.get_typed_func::<(u32,), u32>("sbrk").unwrap();
+    let load = instance.get_typed_func::<(u32,), u32>("load").unwrap();
+    let store = instance.get_typed_func::<(u32, u32), ()>("store").unwrap();
+
+    // Memory past the end of the initial heap is not accessible.
+    assert!(load.call(&mut (), (heap_range.end,)).is_err());
+
+    assert_eq!(sbrk.call(&mut (), (0,)).unwrap(), heap_range.end);
+    assert_eq!(sbrk.call(&mut (), (16,)).unwrap(), heap_range.end);
+    assert_eq!(sbrk.call(&mut (), (VM_PAGE_SIZE,)).unwrap(), heap_range.end + 16);
+    assert_eq!(sbrk.call(&mut (), (0,)).unwrap(), heap_range.end + 16 + VM_PAGE_SIZE);
+
+    // The newly allocated memory is accessible both from within the guest and from the host.
+    store.call(&mut (), (heap_range.end, 0x12345678)).unwrap();
+    store.call(&mut (), (heap_range.end + VM_PAGE_SIZE + 12, 0xaabbccdd)).unwrap();
+    assert_eq!(load.call(&mut (), (heap_range.end,)).unwrap(), 0x12345678);
+    assert_eq!(
+        instance.read_memory_into_new_vec(heap_range.end + VM_PAGE_SIZE + 12, 4).unwrap(),
+        [0xdd, 0xcc, 0xbb, 0xaa]
+    );
+
+    // Growing the heap past the limit fails and leaves the heap untouched.
+    assert_eq!(sbrk.call(&mut (), (VM_PAGE_SIZE * 3,)).unwrap(), 0);
+    assert_eq!(sbrk.call(&mut (), (0,)).unwrap(), heap_range.end + 16 + VM_PAGE_SIZE);
+    assert_eq!(sbrk.call(&mut (), (u32::MAX,)).unwrap(), 0);
+
+    // Resetting the memory also shrinks the heap back to its initial size.
+    let mut execution_config = ExecutionConfig::default();
+    execution_config.set_reset_memory_after_execution(true);
+    assert_eq!(
+        sbrk.call_ex(&mut (), (0,), execution_config).unwrap(),
+        heap_range.end + 16 + VM_PAGE_SIZE
+    );
+    assert_eq!(sbrk.call(&mut (), (0,)).unwrap(), heap_range.end);
+    assert!(load.call(&mut (), (heap_range.end,)).is_err());
+
+    // The maximum size of the heap can't overlap with the stack.
+    module_config.set_max_heap_size(u32::MAX);
+    assert!(Module::from_blob(&engine, &module_config, &blob).is_err());
+}
+
+fn bit_manipulation_instructions(config: Config, force_fallbacks: bool) {
+    let _ = env_logger::try_init();
+
+    type UnaryOp = (&'static str, fn(Reg, Reg) -> Instruction, fn(u32) -> u32);
+    type BinaryOp = (&'static str, fn(Reg, Reg, Reg) -> Instruction, fn(u32, u32) -> u32);
+
+    let unary_ops: [UnaryOp; 7] = [
+        ("clz", asm::count_leading_zero_bits, u32::leading_zeros),
+        ("ctz", asm::count_trailing_zero_bits, u32::trailing_zeros),
+        ("cpop", asm::count_set_bits, u32::count_ones),
+        ("sext_b", asm::sign_extend_8, |s| s as i8 as u32),
+        ("sext_h", asm::sign_extend_16, |s| s as i16 as u32),
+        ("zext_h", asm::zero_extend_16, |s| s & 0xffff),
+        ("rev8", asm::reverse_byte, u32::swap_bytes),
+    ];
+
+    let binary_ops: [BinaryOp; 9] = [
+        ("andn", asm::and_inverted, |s1, s2| s1 & !s2),
+        ("orn", asm::or_inverted, |s1, s2| s1 | !s2),
+        ("xnor", asm::xnor, |s1, s2| !(s1 ^ s2)),
+        ("max", asm::maximum, |s1, s2| (s1 as i32).max(s2 as i32) as u32),
+        ("maxu", asm::maximum_unsigned, u32::max),
+        ("min", asm::minimum, |s1, s2| (s1 as i32).min(s2 as i32) as u32),
+        ("minu", asm::minimum_unsigned, u32::min),
+        ("rol", asm::rotate_left, u32::rotate_left),
+        ("ror", asm::rotate_right, u32::rotate_right),
+    ];
+
+    const ROTATE_AMOUNTS: [u32; 5] = [0, 1, 13, 31, 37];
+    const ROTATE_CONSTANT: u32 = 0x12345678;
+
+    // Every function gets its own basic block, and the native backends emit different code
+    // depending on whether the destination register aliases one of the sources, so cover all of those.
+    let mut builder = ProgramBlobBuilder::new();
+    let mut code = Vec::new();
+    let mut basic_block_count = 0;
+    let mut add_function = |name: String, arg_count: usize, instructions: &[Instruction]| {
+        builder.add_export(basic_block_count, &FnMetadata::new(name, &[I32, I32][..arg_count], Some(I32)));
+        code.extend_from_slice(instructions);
+        code.push(asm::ret());
+        basic_block_count += 1;
+    };
+
+    for &(name, op, _) in &unary_ops {
+        add_function(name.to_owned(), 2, &[op(A0, A1)]);
+        add_function(format!("{name}_in_place"), 1, &[op(A0, A0)]);
+    }
+
+    for &(name, op, _) in &binary_ops {
+        add_function(name.to_owned(), 2, &[op(A2, A0, A1), asm::move_reg(A0, A2)]);
+        add_function(format!("{name}_d_s1"), 2, &[op(A0, A0, A1)]);
+        add_function(format!("{name}_d_s2"), 2, &[op(A1, A0, A1), asm::move_reg(A0, A1)]);
+    }
+
+    for amount in ROTATE_AMOUNTS {
+        add_function(
+            format!("ror_imm_{amount}"),
+            1,
+            &[asm::rotate_right_imm(A1, A0, amount), asm::move_reg(A0, A1)],
+        );
+    }
+
+    add_function(
+        "ror_imm_alt".to_owned(),
+        1,
+        &[asm::rotate_right_imm_alt(A1, A0, ROTATE_CONSTANT), asm::move_reg(A0, A1)],
+    );
+    add_function("rol_imm_alt".to_owned(), 1, &[asm::rotate_left_imm_alt(A0, A0, ROTATE_CONSTANT)]);
+    builder.set_code(&code);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let mut module_config = ModuleConfig::default();
+    module_config.set_force_bit_manipulation_fallbacks(force_fallbacks);
+    let module = Module::from_blob(&engine, &module_config, &blob).unwrap();
+    let linker = Linker::new(&engine);
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+
+    let call1 = |name: &str, a0: u32| instance.get_typed_func::<(u32,), u32>(name).unwrap().call(&mut (), (a0,)).unwrap();
+    let call2 = |name: &str, a0: u32, a1: u32| {
+        instance
+            .get_typed_func::<(u32, u32), u32>(name)
+            .unwrap()
+            .call(&mut (), (a0, a1))
+            .unwrap()
+    };
+
+    let values = [
+        0, 1, 2, 0x80, 0xff, 0x7fff, 0x8000, 0xffff, 0x12345678, 0x7fffffff, 0x80000000, 0x80000001, 0xfffffffe, 0xffffffff,
+    ];
+
+    for &(name, _, expected) in &unary_ops {
+        for value in values {
+            assert_eq!(call2(name, 0xdeadbeef, value), expected(value), "{name}({value:#x})");
+            assert_eq!(call1(&format!("{name}_in_place"), value), expected(value), "{name}({value:#x})");
+        }
+    }
+
+    for &(name, _, expected) in &binary_ops {
+        for s1 in values {
+            for s2 in values.into_iter().chain(ROTATE_AMOUNTS) {
+                let expected = expected(s1, s2);
+                assert_eq!(call2(name, s1, s2), expected, "{name}({s1:#x}, {s2:#x})");
+                assert_eq!(call2(&format!("{name}_d_s1"), s1, s2), expected, "{name}({s1:#x}, {s2:#x})");
+                assert_eq!(call2(&format!("{name}_d_s2"), s1, s2), expected, "{name}({s1:#x}, {s2:#x})");
+            }
+        }
+    }
+
+    for value in values {
+        for amount in ROTATE_AMOUNTS {
+            assert_eq!(call1(&format!("ror_imm_{amount}"), value), value.rotate_right(amount));
+        }
+
+        assert_eq!(call1("ror_imm_alt", value), ROTATE_CONSTANT.rotate_right(value));
+        assert_eq!(call1("rol_imm_alt", value), ROTATE_CONSTANT.rotate_left(value));
+    }
+}
+
+fn bit_manipulation_instructions_work(config: Config) {
+    bit_manipulation_instructions(config, false);
+}
+
+fn bit_manipulation_instructions_work_with_fallbacks(config: Config) {
+    bit_manipulation_instructions(config, true);
+}
+
+fn sixty_four_bit_instructions_work(config: Config) {
+    let _ = env_logger::try_init();
+
+    type UnaryOp = (&'static str, fn(Reg, Reg) -> Instruction, fn(u64) -> u64);
+    type BinaryOp = (&'static str, fn(Reg, Reg, Reg) -> Instruction, fn(u64, u64) -> u64);
+    type ImmOp = (&'static str, Instruction, fn(u64) -> u64);
+
+    fn sext32(value: u32) -> u64 {
+        i64::from(value as i32) as u64
+    }
+
+    let unary_ops: [UnaryOp; 8] = [
+        ("clz", asm::count_leading_zero_bits, |s| u64::from(s.leading_zeros())),
+        ("ctz", asm::count_trailing_zero_bits, |s| u64::from(s.trailing_zeros())),
+        ("cpop", asm::count_set_bits, |s| u64::from(s.count_ones())),
+        ("sext_b", asm::sign_extend_8, |s| s as i8 as u64),
+        ("rev8", asm::reverse_byte, u64::swap_bytes),
+        ("clzw", asm::count_leading_zero_bits_32, |s| u64::from((s as u32).leading_zeros())),
+        ("ctzw", asm::count_trailing_zero_bits_32, |s| u64::from((s as u32).trailing_zeros())),
+        ("cpopw", asm::count_set_bits_32, |s| u64::from((s as u32).count_ones())),
+    ];
+
+    let binary_ops: [BinaryOp; 33] = [
+        ("add", asm::add, u64::wrapping_add),
+        ("sub", asm::sub, u64::wrapping_sub),
+        ("mul", asm::mul, u64::wrapping_mul),
+        ("mulh", asm::mul_upper_signed_signed, |s1, s2| {
+            ((i128::from(s1 as i64) * i128::from(s2 as i64)) >> 64) as u64
+        }),
+        ("mulhu", asm::mul_upper_unsigned_unsigned, |s1, s2| {
+            ((u128::from(s1) * u128::from(s2)) >> 64) as u64
+        }),
+        ("mulhsu", asm::mul_upper_signed_unsigned, |s1, s2| {
+            ((i128::from(s1 as i64) * i128::from(s2)) >> 64) as u64
+        }),
+        ("divu", asm::div_unsigned, |s1, s2| s1.checked_div(s2).unwrap_or(u64::MAX)),
+        ("div", asm::div_signed, |s1, s2| match (s1 as i64).checked_div(s2 as i64) {
+            Some(value) => value as u64,
+            None if s2 == 0 => u64::MAX,
+            None => s1,
+        }),
+        ("remu", asm::rem_unsigned, |s1, s2| s1.checked_rem(s2).unwrap_or(s1)),
+        ("rem", asm::rem_signed, |s1, s2| match (s1 as i64).checked_rem(s2 as i64) {
+            Some(value) => value as u64,
+            None if s2 == 0 => s1,
+            None => 0,
+        }),
+        ("sll", asm::shift_logical_left, |s1, s2| s1.wrapping_shl(s2 as u32)),
+        ("srl", asm::shift_logical_right, |s1, s2| s1.wrapping_shr(s2 as u32)),
+        ("sra", asm::shift_arithmetic_right, |s1, s2| {
+            (s1 as i64).wrapping_shr(s2 as u32) as u64
+        }),
+        ("rol", asm::rotate_left, |s1, s2| s1.rotate_left(s2 as u32)),
+        ("ror", asm::rotate_right, |s1, s2| s1.rotate_right(s2 as u32)),
+        ("and", asm::and, |s1, s2| s1 & s2),
+        ("xor", asm::xor, |s1, s2| s1 ^ s2),
+        ("andn", asm::and_inverted, |s1, s2| s1 & !s2),
+        ("slt", asm::set_less_than_signed, |s1, s2| u64::from((s1 as i64) < (s2 as i64))),
+        ("sltu", asm::set_less_than_unsigned, |s1, s2| u64::from(s1 < s2)),
+        ("max", asm::maximum, |s1, s2| (s1 as i64).max(s2 as i64) as u64),
+        ("minu", asm::minimum_unsigned, u64::min),
+        ("addw", asm::add_32, |s1, s2| sext32((s1 as u32).wrapping_add(s2 as u32))),
+        ("subw", asm::sub_32, |s1, s2| sext32((s1 as u32).wrapping_sub(s2 as u32))),
+        ("mulw", asm::mul_32, |s1, s2| sext32((s1 as u32).wrapping_mul(s2 as u32))),
+        ("sllw", asm::shift_logical_left_32, |s1, s2| {
+            sext32((s1 as u32).wrapping_shl(s2 as u32))
+        }),
+        ("srlw", asm::shift_logical_right_32, |s1, s2| {
+            sext32((s1 as u32).wrapping_shr(s2 as u32))
+        }),
+        ("sraw", asm::shift_arithmetic_right_32, |s1, s2| {
+            sext32((s1 as i32).wrapping_shr(s2 as u32) as u32)
+        }),
+        ("divuw", asm::div_unsigned_32, |s1, s2| {
+            sext32((s1 as u32).checked_div(s2 as u32).unwrap_or(u32::MAX))
+        }),
+        ("divw", asm::div_signed_32, |s1, s2| match (s1 as i32).checked_div(s2 as i32) {
+            Some(value) => sext32(value as u32),
+            None if s2 as u32 == 0 => u64::MAX,
+            None => sext32(s1 as u32),
+        }),
+        ("remuw", asm::rem_unsigned_32, |s1, s2| {
+            sext32((s1 as u32).checked_rem(s2 as u32).unwrap_or(s1 as u32))
+        }),
+        ("remw", asm::rem_signed_32, |s1, s2| match (s1 as i32).checked_rem(s2 as i32) {
+            Some(value) => sext32(value as u32),
+            None if s2 as u32 == 0 => sext32(s1 as u32),
+            None => 0,
+        }),
+        ("rolw", asm::rotate_left_32, |s1, s2| sext32((s1 as u32).rotate_left(s2 as u32))),
+    ];
+
+    // Immediates are sign extended to 64-bits.
+    let imm_ops: [ImmOp; 17] = [
+        ("load_imm", asm::load_imm(A0, 0x80000000), |_| 0xffffffff80000000),
+        ("add_imm", asm::add_imm(A0, A1, -2_i32 as u32), |s| s.wrapping_sub(2)),
+        ("and_imm", asm::and_imm(A0, A1, 0xffffff00), |s| s & 0xffffffffffffff00),
+        ("or_imm", asm::or_imm(A0, A1, 0x80000000), |s| s | 0xffffffff80000000),
+        ("xor_imm", asm::xor_imm(A0, A1, !0), |s| !s),
+        ("mul_imm", asm::mul_imm(A0, A1, -3_i32 as u32), |s| s.wrapping_mul(-3_i64 as u64)),
+        ("mulh_imm", asm::mul_upper_signed_signed_imm(A0, A1, -3_i32 as u32), |s| {
+            ((i128::from(s as i64) * -3) >> 64) as u64
+        }),
+        ("neg_add_imm", asm::negate_and_add_imm(A0, A1, -1_i32 as u32), |s| {
+            u64::MAX.wrapping_sub(s)
+        }),
+        ("sltu_imm", asm::set_less_than_unsigned_imm(A0, A1, !0), |s| u64::from(s < u64::MAX)),
+        ("sgt_imm", asm::set_greater_than_signed_imm(A0, A1, -1_i32 as u32), |s| {
+            u64::from(s as i64 > -1)
+        }),
+        ("sll_imm", asm::shift_logical_left_imm(A0, A1, 40), |s| s << 40),
+        ("srl_imm", asm::shift_logical_right_imm(A0, A1, 63), |s| s >> 63),
+        ("sra_imm", asm::shift_arithmetic_right_imm(A0, A1, 32), |s| {
+            ((s as i64) >> 32) as u64
+        }),
+        ("ror_imm", asm::rotate_right_imm(A0, A1, 48), |s| s.rotate_right(48)),
+        ("addiw", asm::add_imm_32(A0, A1, 0x7fffffff), |s| {
+            sext32((s as u32).wrapping_add(0x7fffffff))
+        }),
+        ("slliw", asm::shift_logical_left_imm_32(A0, A1, 31), |s| sext32((s as u32) << 31)),
+        ("sraiw", asm::shift_arithmetic_right_imm_32(A0, A1, 4), |s| {
+            sext32(((s as i32) >> 4) as u32)
+        }),
+    ];
+
+    let mut builder = ProgramBlobBuilder::new_64bit();
+    builder.set_bss_size(VM_PAGE_SIZE);
+    builder.set_stack_size(VM_PAGE_SIZE);
+
+    let mut code = Vec::new();
+    let mut basic_block_count = 0;
+    let mut add_function = |name: String, arg_count: usize, instructions: &[Instruction]| {
+        builder.add_export(basic_block_count, &FnMetadata::new(name, &[I64, I64][..arg_count], Some(I64)));
+        code.extend_from_slice(instructions);
+        code.push(asm::ret());
+        basic_block_count += 1;
+    };
+
+    for &(name, op, _) in &unary_ops {
+        add_function(name.to_owned(), 2, &[op(A0, A1)]);
+        add_function(format!("{name}_in_place"), 1, &[op(A0, A0)]);
+    }
+
+    for &(name, op, _) in &binary_ops {
+        add_function(name.to_owned(), 2, &[op(A2, A0, A1), asm::move_reg(A0, A2)]);
+        add_function(format!("{name}_d_s1"), 2, &[op(A0, A0, A1)]);
+        add_function(format!("{name}_d_s2"), 2, &[op(A1, A0, A1), asm::move_reg(A0, A1)]);
+    }
+
+    for &(name, op, _) in &imm_ops {
+        add_function(name.to_owned(), 2, &[op]);
+    }
+
+    let sp_offset = -16_i32 as u32;
+    add_function(
+        "store_load_u64".to_owned(),
+        1,
+        &[
+            asm::store_indirect_u64(A0, SP, sp_offset),
+            asm::load_indirect_u64(A0, SP, sp_offset),
+        ],
+    );
+    add_function(
+        "store_load_i32".to_owned(),
+        1,
+        &[
+            asm::store_indirect_u64(A0, SP, sp_offset),
+            asm::load_indirect_i32(A0, SP, sp_offset + 4),
+        ],
+    );
+    add_function(
+        "store_load_u32".to_owned(),
+        1,
+        &[
+            asm::store_indirect_u64(A0, SP, sp_offset),
+            asm::load_indirect_u32(A0, SP, sp_offset),
+        ],
+    );
+    add_function(
+        "store_imm_indirect_u64".to_owned(),
+        0,
+        &[
+            asm::store_imm_indirect_u64(SP, sp_offset, 0x80000000),
+            asm::load_indirect_u64(A0, SP, sp_offset),
+        ],
+    );
+    add_function(
+        "store_load_u64_abs".to_owned(),
+        1,
+        &[asm::store_u64(A0, VM_ADDR_USER_MEMORY), asm::load_u64(A0, VM_ADDR_USER_MEMORY)],
+    );
+    add_function(
+        "store_load_i32_abs".to_owned(),
+        1,
+        &[asm::store_u64(A0, VM_ADDR_USER_MEMORY), asm::load_i32(A0, VM_ADDR_USER_MEMORY)],
+    );
+    add_function(
+        "store_imm_u64".to_owned(),
+        0,
+        &[
+            asm::store_imm_u64(-2_i32 as u32, VM_ADDR_USER_MEMORY),
+            asm::load_u64(A0, VM_ADDR_USER_MEMORY),
+        ],
+    );
+    add_function("sbrk".to_owned(), 1, &[asm::sbrk(A0, A0)]);
+
+    // Branches compare all of the bits of a register against a sign extended immediate.
+    let branch_index = basic_block_count;
+    builder.add_export(branch_index, &FnMetadata::new("branch_ltu_imm", &[I64], Some(I64)));
+    code.extend_from_slice(&[
+        asm::branch_less_unsigned_imm(A0, !0, branch_index + 2),
+        asm::load_imm(A0, 0),
+        asm::ret(),
+        asm::load_imm(A0, 1),
+        asm::ret(),
+    ]);
+
+    builder.set_code(&code);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    assert!(blob.is_64_bit());
+
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    assert!(module.is_64_bit());
+
+    let linker = Linker::new(&engine);
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+
+    let call0 = |name: &str| instance.get_typed_func::<(), u64>(name).unwrap().call(&mut (), ()).unwrap();
+    let call1 = |name: &str, a0: u64| instance.get_typed_func::<(u64,), u64>(name).unwrap().call(&mut (), (a0,)).unwrap();
+    let call2 = |name: &str, a0: u64, a1: u64| {
+        instance
+            .get_typed_func::<(u64, u64), u64>(name)
+            .unwrap()
+            .call(&mut (), (a0, a1))
+            .unwrap()
+    };
+
+    let values = [
+        0,
+        1,
+        2,
+        3,
+        31,
+        32,
+        63,
+        64,
+        0x80,
+        0xffff,
+        0x12345678,
+        0x7fffffff,
+        0x80000000,
+        0xffffffff,
+        0x100000000,
+        0x123456789abcdef0,
+        0x7fffffffffffffff,
+        0x8000000000000000,
+        0xfedcba9876543210,
+        0xffffffff00000000,
+        0xffffffff80000000,
+        0xfffffffffffffffe,
+        0xffffffffffffffff,
+    ];
+
+    for &(name, _, expected) in &unary_ops {
+        for value in values {
+            assert_eq!(call2(name, 0xdeadbeefdeadbeef, value), expected(value), "{name}({value:#x})");
+            assert_eq!(call1(&format!("{name}_in_place"), value), expected(value), "{name}({value:#x})");
+        }
+    }
+
+    for &(name, _, expected) in &binary_ops {
+        for s1 in values {
+            for s2 in values {
+                let expected = expected(s1, s2);
+                assert_eq!(call2(name, s1, s2), expected, "{name}({s1:#x}, {s2:#x})");
+                assert_eq!(call2(&format!("{name}_d_s1"), s1, s2), expected, "{name}({s1:#x}, {s2:#x})");
+                assert_eq!(call2(&format!("{name}_d_s2"), s1, s2), expected, "{name}({s1:#x}, {s2:#x})");
+            }
+        }
+    }
+
+    for &(name, _, expected) in &imm_ops {
+        for value in values {
+            assert_eq!(call2(name, 0, value), expected(value), "{name}({value:#x})");
+        }
+    }
+
+    for value in values {
+        assert_eq!(call1("store_load_u64", value), value);
+        assert_eq!(call1("store_load_i32", value), sext32((value >> 32) as u32));
+        assert_eq!(call1("store_load_u32", value), value & 0xffffffff);
+        assert_eq!(call1("store_load_u64_abs", value), value);
+        assert_eq!(call1("store_load_i32_abs", value), sext32(value as u32));
+        assert_eq!(call1("branch_ltu_imm", value), u64::from(value != u64::MAX));
+    }
+
+    assert_eq!(call0("store_imm_indirect_u64"), 0xffffffff80000000);
+    assert_eq!(call0("store_imm_u64"), 0xfffffffffffffffe);
+
+    // The heap can't grow by more than 4GB.
+    let heap_end = call1("sbrk", 0);
+    assert_eq!(call1("sbrk", 0x100000000), 0);
+    assert_eq!(call1("sbrk", 0), heap_end);
+}
+
+fn sixty_four_bit_programs_pass_u64_in_a_single_register(config: Config) {
+    let _ = env_logger::try_init();
+
+    let mut builder = ProgramBlobBuilder::new_64bit();
+    builder.add_import(0, &FnMetadata::new("multiply", &[I64, I64], Some(I64)));
+    builder.add_export(0, &FnMetadata::new("main", &[I64, I64], Some(I64)));
+    builder.set_code(&[asm::ecalli(0), asm::add(A0, A0, A1), asm::ret()]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    let mut linker = Linker::new(&engine);
+    linker
+        .func_wrap("multiply", |caller: Caller<()>, a: u64, b: u64| -> u64 {
+            // Only a single register is used for each of the arguments.
+            assert_eq!(caller.get_reg64(Reg::A0), a);
+            assert_eq!(caller.get_reg64(Reg::A1), b);
+
+            // The 32-bit accessors only see the lower half.
+            assert_eq!(caller.get_reg(Reg::A0), a as u32);
+            a.wrapping_mul(b)
+        })
+        .unwrap();
+
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+    let main = instance.get_typed_func::<(u64, u64), u64>("main").unwrap();
+    assert_eq!(
+        main.call(&mut (), (0x100000001, 0x80000000)).unwrap(),
+        0x100000001 * 0x80000000 + 0x80000000
+    );
+    assert_eq!(main.call(&mut (), (u64::MAX, 2)).unwrap(), u64::MAX.wrapping_mul(2).wrapping_add(2));
+
+    // The function types have to match the bitness of the program.
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("main", &[I64], Some(I64)));
+    builder.set_code(&[asm::ret()]);
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    assert!(!module.is_64_bit());
+    let instance = Linker::new(&engine).instantiate_pre(&module).unwrap().instantiate().unwrap();
+    let main = instance.get_typed_func::<(u64,), u64>("main").unwrap();
+    assert_eq!(main.call(&mut (), (0x123456789abcdef0,)).unwrap(), 0x123456789abcdef0);
+    assert_eq!(instance.get_reg(Reg::A0), 0x9abcdef0);
+    assert_eq!(instance.get_reg(Reg::A1), 0x12345678);
+}
+
+fn sixty_four_bit_instructions_are_rejected_in_32_bit_programs(config: Config) {
+    let _ = env_logger::try_init();
+
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("main", &[], None));
+    builder.set_code(&[asm::add_32(A0, A0, A1), asm::ret()]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    assert!(!blob.is_64_bit());
+
+    let engine = Engine::new(&config).unwrap();
+    assert!(Module::from_blob(&engine, &Default::default(), &blob).is_err());
+}
+
+fn memcpy_and_memset_work(config: Config) {
+    let _ = env_logger::try_init();
+
+    let mut builder = ProgramBlobBuilder::new();
+    builder.set_ro_data(b"Hello world!".to_vec());
+    builder.set_bss_size(VM_PAGE_SIZE);
+    builder.set_stack_size(VM_PAGE_SIZE);
+    builder.add_export(0, &FnMetadata::new("memcpy", &[I32, I32, I32], Some(I32)));
+    builder.add_export(1, &FnMetadata::new("memset", &[I32, I32, I32], Some(I32)));
+    builder.set_code(&[
+        // Return the sum of the arguments to make sure that none of the registers are clobbered.
+        asm::memcpy(A0, A1, A2),
+        asm::add(A0, A0, A1),
+        asm::add(A0, A0, A2),
+        asm::ret(),
+        asm::memset(A0, A1, A2),
+        asm::add(A0, A0, A1),
+        asm::add(A0, A0, A2),
+        asm::ret(),
+    ]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
+    let memory_map = module.memory_map();
+    let ro_data = memory_map.ro_data_range().start;
+    let heap = memory_map.heap_range().start;
+    let stack = memory_map.stack_range().start;
+    let linker = Linker::new(&engine);
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+    let memcpy = instance.get_typed_func::<(u32, u32, u32), u32>("memcpy").unwrap();
+    let memset = instance.get_typed_func::<(u32, u32, u32), u32>("memset").unwrap();
+
+    let sum = |a: u32, b: u32, c: u32| a.wrapping_add(b).wrapping_add(c);
+    let call_memcpy = |dst: u32, src: u32, count: u32| {
+        let result = memcpy.call(&mut (), (dst, src, count));
+        if let Ok(value) = result {
+            assert_eq!(value, sum(dst, src, count));
+        }
+        result.is_ok()
+    };
+    let call_memset = |dst: u32, value: u32, count: u32| {
+        let result = memset.call(&mut (), (dst, value, count));
+        if let Ok(result) = result {
+            assert_eq!(result, sum(dst, value, count));
+        }
+        result.is_ok()
+    };
+
+    // Copying from the read-only data, and then between the heap and the stack.
+    assert!(call_memcpy(heap, ro_data, 12));
+    assert_eq!(instance.read_memory_into_new_vec(heap, 12).unwrap(), b"Hello world!");
+    assert!(call_memcpy(stack + 100, heap + 6, 5));
+    assert_eq!(instance.read_memory_into_new_vec(stack + 100, 5).unwrap(), b"world");
+    assert!(call_memcpy(heap + 20, stack + 100, 5));
+    assert_eq!(instance.read_memory_into_new_vec(heap + 20, 5).unwrap(), b"world");
+
+    // Overlapping copies behave as if the source was first copied into a temporary buffer.
+    instance.write_memory(heap, b"0123456789").unwrap();
+    assert!(call_memcpy(heap + 2, heap, 6));
+    assert_eq!(instance.read_memory_into_new_vec(heap, 10).unwrap(), b"0101234589");
+    instance.write_memory(heap, b"0123456789").unwrap();
+    assert!(call_memcpy(heap, heap + 2, 6));
+    assert_eq!(instance.read_memory_into_new_vec(heap, 10).unwrap(), b"2345676789");
+
+    // Only the lowest byte of the value is used.
+    assert!(call_memset(heap + 1, 0x1234, 8));
+    assert_eq!(instance.read_memory_into_new_vec(heap, 10).unwrap(), b"2444444449");
+    assert!(call_memset(stack, 0xff, VM_PAGE_SIZE));
+    assert_eq!(
+        instance.read_memory_into_new_vec(stack, VM_PAGE_SIZE).unwrap(),
+        vec![0xff; VM_PAGE_SIZE as usize]
+    );
+
+    // Nothing is accessed if the count is zero.
+    assert!(call_memcpy(0, 0, 0));
+    assert!(call_memset(0, 0, 0));
+
+    // Out of bounds accesses trap.
+    assert!(!call_memcpy(ro_data, heap, 1));
+    assert!(!call_memcpy(heap, 0, 1));
+    assert!(!call_memcpy(heap, VM_ADDR_USER_STACK_HIGH - 4, 5));
+    assert!(!call_memcpy(heap, heap + 4, u32::MAX));
+    assert!(!call_memcpy(VM_ADDR_USER_STACK_HIGH - 4, heap, 5));
+    assert!(!call_memset(ro_data, 0, 1));
+    assert!(!call_memset(VM_ADDR_USER_STACK_HIGH - 4, 0, 5));
+    assert!(!call_memset(heap, 0, u32::MAX));
+
+    // The instance is still usable after a trap.
+    assert!(call_memcpy(heap, ro_data, 5));
+    assert_eq!(instance.read_memory_into_new_vec(heap, 5).unwrap(), b"Hello");
+}
+
+fn memcpy_and_memset_across_the_heap_top(config: Config) {
+    let _ = env_logger::try_init();
+
+    let mut builder = ProgramBlobBuilder::new();
+    builder.set_bss_size(VM_PAGE_SIZE);
+    builder.add_export(0, &FnMetadata::new("memcpy", &[I32, I32, I32], None));
+    builder.add_export(1, &FnMetadata::new("memset", &[I32, I32, I32], None));
+    builder.add_export(2, &FnMetadata::new("sbrk", &[I32], Some(I32)));
+    builder.set_code(&[
+        asm::memcpy(A0, A1, A2),
+        asm::ret(),
+        asm::memset(A0, A1, A2),
+        asm::ret(),
+        asm::sbrk(A0, A0),
+        asm::ret(),
+    ]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let run = |config: &Config| {
+        let engine = Engine::new(config).unwrap();
+        let mut module_config = ModuleConfig::default();
+        module_config.set_max_heap_size(VM_PAGE_SIZE * 4);
+        let module = Module::from_blob(&engine, &module_config, &blob).unwrap();
+        let heap = module.memory_map().heap_range();
+        let linker = Linker::new(&engine);
+        let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+        let memcpy = instance.get_typed_func::<(u32, u32, u32), ()>("memcpy").unwrap();
+        let memset = instance.get_typed_func::<(u32, u32, u32), ()>("memset").unwrap();
+        let sbrk = instance.get_typed_func::<(u32,), u32>("sbrk").unwrap();
+
+        let pattern: Vec<u8> = (0..32).collect();
+        instance.write_memory(heap.start, &pattern).unwrap();
+
+        // The heap can still grow, but until it does the memory past the heap top isn't accessible,
+        // so these trap without writing anything.
+        let mut memory = Vec::new();
+        let dst = heap.end - 16;
+        let result = memcpy.call(&mut (), (dst, heap.start, 32));
+        assert!(matches!(result, Err(ExecutionError::Trap(..))), "unexpected result: {result:?}");
+        memory.push(instance.read_memory_into_new_vec(dst, 16).unwrap());
+
+        let result = memset.call(&mut (), (dst, 0xff, 32));
+        assert!(matches!(result, Err(ExecutionError::Trap(..))), "unexpected result: {result:?}");
+        memory.push(instance.read_memory_into_new_vec(dst, 16).unwrap());
+
+        let result = memcpy.call(&mut (), (heap.start, dst, 32));
+        assert!(matches!(result, Err(ExecutionError::Trap(..))), "unexpected result: {result:?}");
+        memory.push(instance.read_memory_into_new_vec(heap.start, 32).unwrap());
+
+        // Once the heap is grown the same operations succeed.
+        assert_eq!(sbrk.call(&mut (), (16,)).unwrap(), heap.end);
+        memcpy.call(&mut (), (dst, heap.start, 32)).unwrap();
+        memory.push(instance.read_memory_into_new_vec(dst, 32).unwrap());
+
+        memset.call(&mut (), (dst, 0xff, 32)).unwrap();
+        memory.push(instance.read_memory_into_new_vec(dst, 32).unwrap());
+
+        memory
+    };
+
+    let memory = run(&config);
+    assert_eq!(memory[0], vec![0; 16]);
+    assert_eq!(memory[1], vec![0; 16]);
+    assert_eq!(memory[2], (0..32).collect::<Vec<u8>>());
+    assert_eq!(memory[3], (0..32).collect::<Vec<u8>>());
+    assert_eq!(memory[4], vec![0xff; 32]);
+
+    let mut interpreter_config = Config::default();
+    interpreter_config.set_backend(Some(BackendKind::Interpreter));
+    assert_eq!(memory, run(&interpreter_config));
+}
+
+fn snapshot_and_restore_work(config: Config) {
+    let _ = env_logger::try_init();
+
+    let mut builder = ProgramBlobBuilder::new();
+    builder.set_bss_size(VM_PAGE_SIZE);
+    builder.add_export(0, &FnMetadata::new("increment", &[], Some(I32)));
+    builder.set_code(&[
+        asm::load_u32(A0, VM_ADDR_USER_MEMORY),
+        asm::add_imm(A0, A0, 1),
+        asm::store_u32(A0, VM_ADDR_USER_MEMORY),
+        asm::ret(),
+    ]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let mut module_config = ModuleConfig::default();
+    module_config.set_gas_metering(Some(GasMeteringKind::Sync));
+
+    let module = Module::from_blob(&engine, &module_config, &blob).unwrap();
+    let linker = Linker::new(&engine);
+    let instance_pre = linker.instantiate_pre(&module).unwrap();
+    let instance = instance_pre.instantiate().unwrap();
+    let increment = instance.get_typed_func::<(), i32>("increment").unwrap();
+
+    let mut execution_config = ExecutionConfig::default();
+    execution_config.set_gas(Gas::new(100).unwrap());
+    assert_eq!(increment.call_ex(&mut (), (), execution_config).unwrap(), 1);
+
+    let snapshot = instance.snapshot();
+    let gas_at_snapshot = instance.gas_remaining().unwrap();
+    assert_eq!(increment.call(&mut (), ()).unwrap(), 2);
+    assert_eq!(increment.call(&mut (), ()).unwrap(), 3);
+    assert_ne!(instance.gas_remaining().unwrap(), gas_at_snapshot);
+
+    instance.restore(&snapshot).unwrap();
+    assert_eq!(instance.read_memory_into_new_vec(VM_ADDR_USER_MEMORY, 4).unwrap(), [1, 0, 0, 0]);
+    assert_eq!(instance.get_reg(A0), 1);
+    assert_eq!(instance.gas_remaining().unwrap(), gas_at_snapshot);
+    assert_eq!(increment.call(&mut (), ()).unwrap(), 2);
+
+    // The snapshot can be serialized and restored into a fresh instance of the same module.
+    let snapshot = InstanceSnapshot::from_bytes(&snapshot.to_bytes()).unwrap();
+    let new_instance = instance_pre.instantiate().unwrap();
+    new_instance.restore(&snapshot).unwrap();
+    assert_eq!(new_instance.gas_remaining().unwrap(), gas_at_snapshot);
+    assert_eq!(
+        new_instance
+            .get_typed_func::<(), i32>("increment")
+            .unwrap()
+            .call(&mut (), ())
+            .unwrap(),
+        2
+    );
+
+    assert!(InstanceSnapshot::from_bytes(&[]).is_err());
+    assert!(InstanceSnapshot::from_bytes(&snapshot.to_bytes()[..16]).is_err());
+
+    // A snapshot can't be restored into an instance of a module with a different memory layout.
+    let mut builder = ProgramBlobBuilder::new();
+    builder.set_bss_size(VM_PAGE_SIZE * 2);
+    builder.add_export(0, &FnMetadata::new("main", &[], None));
+    builder.set_code(&[asm::ret()]);
+    let other_blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let other_module = Module::from_blob(&engine, &module_config, &other_blob).unwrap();
+    let other_instance = linker.instantiate_pre(&other_module).unwrap().instantiate().unwrap();
+    assert!(other_instance.restore(&snapshot).is_err());
+
+    // Nor into an instance of a different program, even if the memory layout is the same.
+    let mut builder = ProgramBlobBuilder::new();
+    builder.set_bss_size(VM_PAGE_SIZE);
+    builder.add_export(0, &FnMetadata::new("increment", &[], Some(I32)));
+    builder.set_code(&[asm::load_imm(A0, 1), asm::ret()]);
+    let other_blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let other_module = Module::from_blob(&engine, &module_config, &other_blob).unwrap();
+    let other_instance = linker.instantiate_pre(&other_module).unwrap().instantiate().unwrap();
+    let error = other_instance.restore(&snapshot).unwrap_err();
+    assert!(error.to_string().contains("different module"), "unexpected error: {error}");
+}
+
+fn freezing_an_instance_works(config: Config) {
+    let _ = env_logger::try_init();
+
+    let mut builder = ProgramBlobBuilder::new();
+    builder.set_rw_data(vec![1, 0, 0, 0]);
+    builder.set_bss_size(VM_PAGE_SIZE * 2);
+    builder.add_export(0, &FnMetadata::new("init", &[], None));
+    builder.add_export(1, &FnMetadata::new("get", &[], Some(I32)));
+    builder.add_export(2, &FnMetadata::new("increment", &[], None));
+    builder.set_code(&[
+        asm::load_imm(A0, 100),
+        asm::store_u32(A0, VM_ADDR_USER_MEMORY + VM_PAGE_SIZE),
+        asm::ret(),
+        asm::load_u32(A0, VM_ADDR_USER_MEMORY),
+        asm::load_u32(A1, VM_ADDR_USER_MEMORY + VM_PAGE_SIZE),
+        asm::add(A0, A0, A1),
+        asm::ret(),
+        asm::load_u32(A0, VM_ADDR_USER_MEMORY + VM_PAGE_SIZE),
+        asm::add_imm(A0, A0, 1),
+        asm::store_u32(A0, VM_ADDR_USER_MEMORY + VM_PAGE_SIZE),
+        asm::ret(),
+    ]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
     let linker = Linker::new(&engine);
     let instance_pre = linker.instantiate_pre(&module).unwrap();
+
+    let get = |instance: &Instance<()>| instance.get_typed_func::<(), i32>("get").unwrap().call(&mut (), ()).unwrap();
+    let increment = |instance: &Instance<()>| instance.get_typed_func::<(), ()>("increment").unwrap().call(&mut (), ()).unwrap();
+
     let instance = instance_pre.instantiate().unwrap();
-    let ext_initialize = instance.get_typed_func::<(), ()>("initialize").unwrap();
-    let ext_run = instance.get_typed_func::<(), ()>("run").unwrap();
-    let ext_get_framebuffer = instance.get_typed_func::<(), u32>("get_framebuffer").unwrap();
+    instance.get_typed_func::<(), ()>("init").unwrap().call(&mut (), ()).unwrap();
+    assert_eq!(get(&instance), 101);
+
+    let frozen_pre = instance.freeze().unwrap();
+    let instance_1 = frozen_pre.instantiate().unwrap();
+    let instance_2 = frozen_pre.instantiate().unwrap();
+    assert_eq!(get(&instance_1), 101);
+    assert_eq!(get(&instance_2), 101);
+
+    // Every instance gets its own copy of the frozen memory.
+    increment(&instance_1);
+    assert_eq!(get(&instance_1), 102);
+    assert_eq!(get(&instance_2), 101);
+    assert_eq!(get(&instance), 101);
+
+    increment(&instance);
+    assert_eq!(get(&instance), 102);
+    assert_eq!(get(&instance_2), 101);
+    assert_eq!(get(&frozen_pre.instantiate().unwrap()), 101);
+
+    // The original initial memory is still used for instances which weren't frozen.
+    assert_eq!(get(&instance_pre.instantiate().unwrap()), 1);
+
+    // Resetting the memory brings it back to the frozen state.
+    let mut execution_config = ExecutionConfig::default();
+    execution_config.set_reset_memory_after_execution(true);
+    instance_1
+        .get_typed_func::<(), ()>("increment")
+        .unwrap()
+        .call_ex(&mut (), (), execution_config)
+        .unwrap();
+    assert_eq!(get(&instance_1), 101);
 
-    ext_initialize.call(&mut (), ()).unwrap();
-    for _ in 0..256 {
-        ext_run.call(&mut (), ()).unwrap();
+    // A frozen instance can be frozen again.
+    increment(&instance_2);
+    let instance_3 = instance_2.freeze().unwrap().instantiate().unwrap();
+    assert_eq!(get(&instance_3), 102);
+}
+
+fn basic_gas_metering(config: Config, gas_metering_kind: GasMeteringKind) {
+    let _ = env_logger::try_init();
+
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("main", &[], Some(I32)));
+    builder.set_code(&[asm::add_imm(A0, A0, 666), asm::ret()]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let mut module_config = ModuleConfig::default();
+    module_config.set_gas_metering(Some(gas_metering_kind));
+
+    let module = Module::from_blob(&engine, &module_config, &blob).unwrap();
+    let linker = Linker::new(&engine);
+    let instance_pre = linker.instantiate_pre(&module).unwrap();
+    let instance = instance_pre.instantiate().unwrap();
+
+    {
+        let mut config = ExecutionConfig::default();
+        config.set_gas(Gas::new(2).unwrap());
+
+        let result = instance.get_typed_func::<(), i32>("main").unwrap().call_ex(&mut (), (), config);
+        assert!(matches!(result, Ok(666)), "unexpected result: {result:?}");
+        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+    }
+
+    {
+        let mut config = ExecutionConfig::default();
+        config.set_gas(Gas::new(1).unwrap());
+
+        let result = instance.get_typed_func::<(), i32>("main").unwrap().call_ex(&mut (), (), config);
+        assert!(matches!(result, Err(ExecutionError::OutOfGas)), "unexpected result: {result:?}");
+        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+    }
+
+    {
+        let mut config = ExecutionConfig::default();
+        config.set_gas(Gas::new(4).unwrap());
+
+        let result = instance.get_typed_func::<(), i32>("main").unwrap().call_ex(&mut (), (), config);
+        assert!(matches!(result, Ok(666)), "unexpected result: {result:?}");
+        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(2).unwrap());
+
+        let result = instance.get_typed_func::<(), i32>("main").unwrap().call(&mut (), ());
+        assert!(matches!(result, Ok(666)), "unexpected result: {result:?}");
+        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+
+        let result = instance.get_typed_func::<(), i32>("main").unwrap().call(&mut (), ());
+        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+        assert!(matches!(result, Err(ExecutionError::OutOfGas)), "unexpected result: {result:?}");
+    }
+
+    {
+        core::mem::drop(instance);
+        let instance = instance_pre.instantiate().unwrap();
+        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+
+        let result = instance.get_typed_func::<(), i32>("main").unwrap().call(&mut (), ());
+        assert!(matches!(result, Err(ExecutionError::OutOfGas)), "unexpected result: {result:?}");
+        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+    }
+}
+
+fn basic_gas_metering_sync(config: Config) {
+    basic_gas_metering(config, GasMeteringKind::Sync);
+}
+
+fn basic_gas_metering_async(config: Config) {
+    basic_gas_metering(config, GasMeteringKind::Async);
+}
+
+fn gas_is_charged_per_basic_block(config: Config, gas_metering_kind: GasMeteringKind) {
+    let _ = env_logger::try_init();
+
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("short", &[], Some(I32)));
+    builder.add_export(1, &FnMetadata::new("long", &[], Some(I32)));
+    builder.set_code(&[
+        asm::add_imm(A0, A0, 1),
+        asm::ret(),
+        asm::add_imm(A0, A0, 1),
+        asm::add_imm(A0, A0, 1),
+        asm::add_imm(A0, A0, 1),
+        asm::ret(),
+    ]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let mut module_config = ModuleConfig::default();
+    module_config.set_gas_metering(Some(gas_metering_kind));
+
+    let module = Module::from_blob(&engine, &module_config, &blob).unwrap();
+    let linker = Linker::new(&engine);
+    let instance_pre = linker.instantiate_pre(&module).unwrap();
+    let instance = instance_pre.instantiate().unwrap();
+
+    for (name, expected_cost) in [("short", 2), ("long", 4)] {
+        let mut config = ExecutionConfig::default();
+        config.set_gas(Gas::new(10).unwrap());
+
+        let result = instance.get_typed_func::<(), i32>(name).unwrap().call_ex(&mut (), (), config);
+        assert!(result.is_ok(), "unexpected result: {result:?}");
+        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(10 - expected_cost).unwrap());
+    }
+}
+
+fn gas_is_charged_per_basic_block_sync(config: Config) {
+    gas_is_charged_per_basic_block(config, GasMeteringKind::Sync);
+}
+
+fn gas_is_charged_per_basic_block_async(config: Config) {
+    gas_is_charged_per_basic_block(config, GasMeteringKind::Async);
+}
+
+fn consume_gas_in_host_function(config: Config, gas_metering_kind: GasMeteringKind) {
+    let _ = env_logger::try_init();
+
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("main", &[], Some(I32)));
+    builder.add_import(0, &FnMetadata::new("hostfn", &[], Some(I32)));
+    builder.set_code(&[asm::ecalli(0), asm::ret()]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let mut module_config = ModuleConfig::default();
+    module_config.set_gas_metering(Some(gas_metering_kind));
+
+    let module = Module::from_blob(&engine, &module_config, &blob).unwrap();
+    let mut linker = Linker::new(&engine);
+    linker
+        .func_wrap("hostfn", |mut caller: Caller<u64>| -> u32 {
+            assert_eq!(caller.gas_remaining().unwrap().get(), 1);
+            caller.consume_gas(*caller.data());
+            666
+        })
+        .unwrap();
+
+    let instance_pre = linker.instantiate_pre(&module).unwrap();
+    let instance = instance_pre.instantiate().unwrap();
+
+    {
+        let mut config = ExecutionConfig::default();
+        config.set_gas(Gas::new(3).unwrap());
+
+        let result = instance.get_typed_func::<(), i32>("main").unwrap().call_ex(&mut 0, (), config);
+        assert!(matches!(result, Ok(666)), "unexpected result: {result:?}");
+        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(1).unwrap());
+    }
+
+    {
+        let mut config = ExecutionConfig::default();
+        config.set_gas(Gas::new(3).unwrap());
+
+        let result = instance.get_typed_func::<(), i32>("main").unwrap().call_ex(&mut 1, (), config);
+        assert!(matches!(result, Ok(666)), "unexpected result: {result:?}");
+        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+    }
+
+    {
+        let mut config = ExecutionConfig::default();
+        config.set_gas(Gas::new(3).unwrap());
+
+        let result = instance.get_typed_func::<(), i32>("main").unwrap().call_ex(&mut 2, (), config);
+        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+        assert!(matches!(result, Err(ExecutionError::OutOfGas)), "unexpected result: {result:?}");
+    }
+}
+
+fn consume_gas_in_host_function_sync(config: Config) {
+    consume_gas_in_host_function(config, GasMeteringKind::Sync);
+}
+
+fn consume_gas_in_host_function_async(config: Config) {
+    consume_gas_in_host_function(config, GasMeteringKind::Async);
+}
+
+fn consume_gas_in_async_host_function(config: Config) {
+    let _ = env_logger::try_init();
+
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("main", &[], Some(I32)));
+    builder.add_import(0, &FnMetadata::new("hostfn", &[], Some(I32)));
+    builder.set_code(&[asm::ecalli(0), asm::ret()]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let mut module_config = ModuleConfig::default();
+    module_config.set_gas_metering(Some(GasMeteringKind::Sync));
+
+    let module = Module::from_blob(&engine, &module_config, &blob).unwrap();
+    let mut linker = Linker::new(&engine);
+    linker
+        .func_wrap_async("hostfn", |mut caller: Caller<u64>| {
+            caller.consume_gas(*caller.data());
+            async move {
+                YieldOnce(false).await;
+                666_u32
+            }
+        })
+        .unwrap();
+
+    let instance_pre = linker.instantiate_pre(&module).unwrap();
+    let instance = instance_pre.instantiate().unwrap();
+    let func = instance.get_typed_func::<(), i32>("main").unwrap();
+
+    {
+        let mut config = ExecutionConfig::default();
+        config.set_gas(Gas::new(3).unwrap());
+
+        let result = block_on(func.call_async_ex(&mut 1, (), config));
+        assert!(matches!(result, Ok(666)), "unexpected result: {result:?}");
+        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+    }
+
+    // The gas consumed by the host function must be checked once the execution is resumed.
+    {
+        let mut config = ExecutionConfig::default();
+        config.set_gas(Gas::new(3).unwrap());
+
+        let result = block_on(func.call_async_ex(&mut 2, (), config));
+        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+        assert!(matches!(result, Err(ExecutionError::OutOfGas)), "unexpected result: {result:?}");
     }
+}
+
+fn running_out_of_gas_in_infinite_loop(config: Config, gas_metering_kind: GasMeteringKind) {
+    let _ = env_logger::try_init();
+
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("infinite_loop", &[], None));
+    builder.add_export(1, &FnMetadata::new("infinite_loop_with_hostcall", &[], None));
+    builder.add_import(0, &FnMetadata::new("hostfn", &[], None));
+    builder.set_code(&[asm::jump(0), asm::ecalli(0), asm::jump(1)]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let mut module_config = ModuleConfig::default();
+    module_config.set_gas_metering(Some(gas_metering_kind));
+
+    let module = Module::from_blob(&engine, &module_config, &blob).unwrap();
+    let mut linker = Linker::new(&engine);
+    linker
+        .func_wrap("hostfn", |mut caller: Caller<u32>| {
+            *caller.data_mut() += 1;
+        })
+        .unwrap();
+
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+
+    let mut config = ExecutionConfig::default();
+    config.set_gas(Gas::new(1000).unwrap());
+    let result = instance
+        .get_typed_func::<(), ()>("infinite_loop")
+        .unwrap()
+        .call_ex(&mut 0, (), config);
+    assert!(matches!(result, Err(ExecutionError::OutOfGas)), "unexpected result: {result:?}");
+
+    // The host function must be called exactly as many times as with synchronous gas metering.
+    let mut counter = 0;
+    let mut config = ExecutionConfig::default();
+    config.set_gas(Gas::new(1000).unwrap());
+    let result = instance
+        .get_typed_func::<(), ()>("infinite_loop_with_hostcall")
+        .unwrap()
+        .call_ex(&mut counter, (), config);
+    assert!(matches!(result, Err(ExecutionError::OutOfGas)), "unexpected result: {result:?}");
+    assert_eq!(counter, 500);
+}
+
+fn running_out_of_gas_in_infinite_loop_sync(config: Config) {
+    running_out_of_gas_in_infinite_loop(config, GasMeteringKind::Sync);
+}
+
+fn running_out_of_gas_in_infinite_loop_async(config: Config) {
+    running_out_of_gas_in_infinite_loop(config, GasMeteringKind::Async);
+}
+
+fn custom_gas_cost_model(config: Config, gas_metering_kind: GasMeteringKind) {
+    let _ = env_logger::try_init();
+
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("main", &[], Some(I32)));
+    builder.set_code(&[asm::add_imm(A0, A0, 666), asm::fallthrough(), asm::ret()]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let mut gas_cost_model = GasCostModel::new();
+    gas_cost_model
+        .set_opcode_cost(Opcode::add_imm, 10)
+        .unwrap()
+        .set_opcode_cost(Opcode::jump_indirect, 5)
+        .unwrap()
+        .set_basic_block_cost(100)
+        .unwrap();
 
-    let address = ext_get_framebuffer.call(&mut (), ()).unwrap();
-    let framebuffer = instance.read_memory_into_new_vec(address, 256 * 240 * 4).unwrap();
+    assert!(gas_cost_model
+        .clone()
+        .set_opcode_cost(Opcode::add_imm, i32::MAX as u32 + 1)
+        .is_err());
+    assert!(gas_cost_model.clone().set_basic_block_cost(i32::MAX as u32 + 1).is_err());
+    assert!(gas_cost_model.clone().set_bulk_memory_byte_cost(i32::MAX as u32 + 1).is_err());
+    assert!(gas_cost_model.clone().set_sbrk_byte_cost(i32::MAX as u32 + 1).is_err());
 
-    let expected_frame_raw = decompress_zstd(include_bytes!("../../../test-data/pinky_00256.tga.zst"));
-    let expected_frame = image::load_from_memory_with_format(&expected_frame_raw, image::ImageFormat::Tga)
-        .unwrap()
-        .to_rgba8();
+    let mut module_config = ModuleConfig::default();
+    module_config.set_gas_metering(Some(gas_metering_kind));
+    module_config.set_gas_cost_model(gas_cost_model);
 
-    if framebuffer != *expected_frame.as_raw() {
-        panic!("frames doesn't match!");
-    }
+    let module = Module::from_blob(&engine, &module_config, &blob).unwrap();
+    let linker = Linker::new(&engine);
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+
+    // The first basic block costs 100 + 10 + 1, and the second one costs 100 + 5.
+    let mut config = ExecutionConfig::default();
+    config.set_gas(Gas::new(1000).unwrap());
+    let result = instance.get_typed_func::<(), i32>("main").unwrap().call_ex(&mut (), (), config);
+    assert!(matches!(result, Ok(666)), "unexpected result: {result:?}");
+    assert_eq!(instance.gas_remaining().unwrap(), Gas::new(1000 - 216).unwrap());
+
+    let mut config = ExecutionConfig::default();
+    config.set_gas(Gas::new(216).unwrap());
+    let result = instance.get_typed_func::<(), i32>("main").unwrap().call_ex(&mut (), (), config);
+    assert!(matches!(result, Ok(666)), "unexpected result: {result:?}");
+    assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+
+    let mut config = ExecutionConfig::default();
+    config.set_gas(Gas::new(215).unwrap());
+    let result = instance.get_typed_func::<(), i32>("main").unwrap().call_ex(&mut (), (), config);
+    assert!(matches!(result, Err(ExecutionError::OutOfGas)), "unexpected result: {result:?}");
 }
 
-struct TestInstance {
-    instance: crate::Instance<()>,
+fn custom_gas_cost_model_sync(config: Config) {
+    custom_gas_cost_model(config, GasMeteringKind::Sync);
 }
 
-impl TestInstance {
-    fn new(config: &Config) -> Self {
-        let _ = env_logger::try_init();
-        let blob = get_blob(include_bytes!("../../../test-data/test-blob.elf.zst"));
+fn custom_gas_cost_model_async(config: Config) {
+    custom_gas_cost_model(config, GasMeteringKind::Async);
+}
 
-        let engine = Engine::new(config).unwrap();
-        let module = Module::from_blob(&engine, &Default::default(), &blob).unwrap();
-        let mut linker = Linker::new(&engine);
-        linker
-            .func_wrap("multiply_by_2", |_caller: Caller<()>, value: u32| -> Result<u32, Trap> {
-                Ok(value * 2)
-            })
-            .unwrap();
+fn basic_blocks_whose_gas_cost_does_not_fit_into_an_i32_are_rejected(config: Config) {
+    let _ = env_logger::try_init();
 
-        let instance_pre = linker.instantiate_pre(&module).unwrap();
-        let instance = instance_pre.instantiate().unwrap();
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("main", &[], Some(I32)));
+    builder.set_code(&[asm::add_imm(A0, A0, 1), asm::add_imm(A0, A0, 1), asm::ret()]);
 
-        TestInstance { instance }
-    }
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
 
-    pub fn call<FnArgs, FnResult>(&self, name: &str, args: FnArgs) -> Result<FnResult, crate::ExecutionError<crate::Error>>
-    where
-        FnArgs: crate::api::FuncArgs,
-        FnResult: crate::api::FuncResult,
-    {
-        let function = self
-            .instance
-            .get_typed_func::<FnArgs, FnResult>(name)
-            .expect("function doesn't exist");
-        function.call(&mut (), args)
-    }
-}
+    // Each instruction's cost is valid on its own, but the whole basic block's isn't.
+    let mut gas_cost_model = GasCostModel::new();
+    gas_cost_model.set_opcode_cost(Opcode::add_imm, i32::MAX as u32 / 2 + 1).unwrap();
 
-fn test_blob_basic_test(config: Config) {
-    let i = TestInstance::new(&config);
-    assert_eq!(i.call::<(), u32>("push_one_to_global_vec", ()).unwrap(), 1);
-    assert_eq!(i.call::<(), u32>("push_one_to_global_vec", ()).unwrap(), 2);
-    assert_eq!(i.call::<(), u32>("push_one_to_global_vec", ()).unwrap(), 3);
-}
+    let mut module_config = ModuleConfig::default();
+    module_config.set_gas_metering(Some(GasMeteringKind::Sync));
+    module_config.set_gas_cost_model(gas_cost_model.clone());
+    assert!(Module::from_blob(&engine, &module_config, &blob).is_err());
 
-fn test_blob_atomic_fetch_add(config: Config) {
-    let i = TestInstance::new(&config);
-    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_add", (1,)).unwrap(), 0);
-    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_add", (1,)).unwrap(), 1);
-    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_add", (1,)).unwrap(), 2);
-    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_add", (0,)).unwrap(), 3);
-    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_add", (0,)).unwrap(), 3);
-    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_add", (2,)).unwrap(), 3);
-    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_add", (0,)).unwrap(), 5);
+    gas_cost_model.set_opcode_cost(Opcode::add_imm, i32::MAX as u32 / 2).unwrap();
+    module_config.set_gas_cost_model(gas_cost_model);
+    assert!(Module::from_blob(&engine, &module_config, &blob).is_ok());
 }
 
-fn test_blob_atomic_fetch_swap(config: Config) {
-    let i = TestInstance::new(&config);
-    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_swap", (10,)).unwrap(), 0);
-    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_swap", (100,)).unwrap(), 10);
-    assert_eq!(i.call::<(u32,), u32>("atomic_fetch_swap", (1000,)).unwrap(), 100);
-}
+fn bulk_memory_gas_metering(config: Config, gas_metering_kind: GasMeteringKind) {
+    let _ = env_logger::try_init();
 
-fn test_blob_atomic_fetch_minmax(config: Config) {
-    use core::cmp::{max, min};
+    let mut builder = ProgramBlobBuilder::new();
+    builder.set_bss_size(VM_PAGE_SIZE);
+    builder.add_export(0, &FnMetadata::new("memset", &[I32, I32], None));
+    builder.set_code(&[asm::memset(A0, A0, A1), asm::ret()]);
 
-    fn maxu(a: i32, b: i32) -> i32 {
-        max(a as u32, b as u32) as i32
-    }
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let mut gas_cost_model = GasCostModel::new();
+    gas_cost_model.set_bulk_memory_byte_cost(3).unwrap();
 
-    fn minu(a: i32, b: i32) -> i32 {
-        min(a as u32, b as u32) as i32
-    }
+    let mut module_config = ModuleConfig::default();
+    module_config.set_gas_metering(Some(gas_metering_kind));
+    module_config.set_gas_cost_model(gas_cost_model);
 
-    #[allow(clippy::type_complexity)]
-    let list: [(&str, fn(i32, i32) -> i32); 4] = [
-        ("atomic_fetch_max_signed", max),
-        ("atomic_fetch_min_signed", min),
-        ("atomic_fetch_max_unsigned", maxu),
-        ("atomic_fetch_min_unsigned", minu),
-    ];
+    let module = Module::from_blob(&engine, &module_config, &blob).unwrap();
+    let heap = module.memory_map().heap_range().start;
+    let linker = Linker::new(&engine);
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+    let memset = instance.get_typed_func::<(u32, u32), ()>("memset").unwrap();
+
+    // The basic block costs 2, and every byte costs 3.
+    let mut config = ExecutionConfig::default();
+    config.set_gas(Gas::new(1000).unwrap());
+    let result = memset.call_ex(&mut (), (heap, 100), config);
+    assert!(matches!(result, Ok(())), "unexpected result: {result:?}");
+    assert_eq!(instance.gas_remaining().unwrap(), Gas::new(1000 - 302).unwrap());
+
+    let mut config = ExecutionConfig::default();
+    config.set_gas(Gas::new(2).unwrap());
+    let result = memset.call_ex(&mut (), (heap, 0), config);
+    assert!(matches!(result, Ok(())), "unexpected result: {result:?}");
+    assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+
+    let mut config = ExecutionConfig::default();
+    config.set_gas(Gas::new(302).unwrap());
+    let result = memset.call_ex(&mut (), (heap, 100), config);
+    assert!(matches!(result, Ok(())), "unexpected result: {result:?}");
+    assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+
+    let mut config = ExecutionConfig::default();
+    config.set_gas(Gas::new(301).unwrap());
+    let result = memset.call_ex(&mut (), (heap, 100), config);
+    assert!(matches!(result, Err(ExecutionError::OutOfGas)), "unexpected result: {result:?}");
+    assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+}
 
-    let i = TestInstance::new(&config);
-    for (name, cb) in list {
-        for a in [-10, 0, 10] {
-            for b in [-10, 0, 10] {
-                let new_value = cb(a, b);
-                i.call::<(i32,), ()>("set_atomic_global", (a,)).unwrap();
-                assert_eq!(i.call::<(i32,), i32>(name, (b,)).unwrap(), a);
-                assert_eq!(i.call::<(), i32>("get_atomic_global", ()).unwrap(), new_value);
-            }
-        }
-    }
+fn bulk_memory_gas_metering_sync(config: Config) {
+    bulk_memory_gas_metering(config, GasMeteringKind::Sync);
 }
 
-fn test_blob_hostcall(config: Config) {
-    let i = TestInstance::new(&config);
-    assert_eq!(i.call::<(u32,), u32>("test_multiply_by_6", (10,)).unwrap(), 60);
+fn bulk_memory_gas_metering_async(config: Config) {
+    bulk_memory_gas_metering(config, GasMeteringKind::Async);
 }
 
-fn basic_gas_metering(config: Config, gas_metering_kind: GasMeteringKind) {
+fn sbrk_gas_metering(config: Config, gas_metering_kind: GasMeteringKind) {
     let _ = env_logger::try_init();
 
     let mut builder = ProgramBlobBuilder::new();
-    builder.add_export(0, &FnMetadata::new("main", &[], Some(I32)));
-    builder.set_code(&[asm::add_imm(A0, A0, 666), asm::ret()]);
+    builder.add_export(0, &FnMetadata::new("sbrk", &[I32], Some(I32)));
+    builder.set_code(&[asm::sbrk(A0, A0), asm::ret()]);
 
     let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
     let engine = Engine::new(&config).unwrap();
+    let mut gas_cost_model = GasCostModel::new();
+    gas_cost_model.set_sbrk_byte_cost(3).unwrap();
+
     let mut module_config = ModuleConfig::default();
     module_config.set_gas_metering(Some(gas_metering_kind));
+    module_config.set_gas_cost_model(gas_cost_model);
+    module_config.set_max_heap_size(VM_PAGE_SIZE * 4);
 
     let module = Module::from_blob(&engine, &module_config, &blob).unwrap();
+    let heap_top = module.memory_map().heap_range().end;
     let linker = Linker::new(&engine);
-    let instance_pre = linker.instantiate_pre(&module).unwrap();
-    let instance = instance_pre.instantiate().unwrap();
-
-    {
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+    let sbrk = instance.get_typed_func::<(u32,), u32>("sbrk").unwrap();
+
+    // The basic block costs 2, and every byte costs 3.
+    let mut config = ExecutionConfig::default();
+    config.set_gas(Gas::new(1000).unwrap());
+    let result = sbrk.call_ex(&mut (), (100,), config);
+    assert_eq!(result.unwrap(), heap_top);
+    assert_eq!(instance.gas_remaining().unwrap(), Gas::new(1000 - 302).unwrap());
+
+    let mut config = ExecutionConfig::default();
+    config.set_gas(Gas::new(2).unwrap());
+    let result = sbrk.call_ex(&mut (), (0,), config);
+    assert_eq!(result.unwrap(), heap_top + 100);
+    assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+
+    // Failing to grow the heap isn't free either.
+    let mut config = ExecutionConfig::default();
+    config.set_gas(Gas::new(1_000_000).unwrap());
+    let result = sbrk.call_ex(&mut (), (VM_PAGE_SIZE * 4,), config);
+    assert_eq!(result.unwrap(), 0);
+    assert_eq!(
+        instance.gas_remaining().unwrap(),
+        Gas::new(1_000_000 - 2 - u64::from(VM_PAGE_SIZE * 4) * 3).unwrap()
+    );
+
+    let mut config = ExecutionConfig::default();
+    config.set_gas(Gas::new(301).unwrap());
+    let result = sbrk.call_ex(&mut (), (100,), config);
+    assert!(matches!(result, Err(ExecutionError::OutOfGas)), "unexpected result: {result:?}");
+    assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+
+    if matches!(gas_metering_kind, GasMeteringKind::Sync) {
+        // The heap doesn't grow if there's not enough gas.
         let mut config = ExecutionConfig::default();
         config.set_gas(Gas::new(2).unwrap());
-
-        let result = instance.get_typed_func::<(), i32>("main").unwrap().call_ex(&mut (), (), config);
-        assert!(matches!(result, Ok(666)), "unexpected result: {result:?}");
-        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+        let result = sbrk.call_ex(&mut (), (0,), config);
+        assert_eq!(result.unwrap(), heap_top + 100);
     }
+}
 
-    {
-        let mut config = ExecutionConfig::default();
-        config.set_gas(Gas::new(1).unwrap());
+fn sbrk_gas_metering_sync(config: Config) {
+    sbrk_gas_metering(config, GasMeteringKind::Sync);
+}
 
-        let result = instance.get_typed_func::<(), i32>("main").unwrap().call_ex(&mut (), (), config);
-        assert!(matches!(result, Err(ExecutionError::OutOfGas)), "unexpected result: {result:?}");
-        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
-    }
+fn sbrk_gas_metering_async(config: Config) {
+    sbrk_gas_metering(config, GasMeteringKind::Async);
+}
 
-    {
-        let mut config = ExecutionConfig::default();
-        config.set_gas(Gas::new(4).unwrap());
+fn out_of_gas_handler_can_refill_gas(config: Config) {
+    let _ = env_logger::try_init();
 
-        let result = instance.get_typed_func::<(), i32>("main").unwrap().call_ex(&mut (), (), config);
-        assert!(matches!(result, Ok(666)), "unexpected result: {result:?}");
-        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(2).unwrap());
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("infinite_loop_with_hostcall", &[], None));
+    builder.add_import(0, &FnMetadata::new("hostfn", &[], None));
+    builder.set_code(&[asm::ecalli(0), asm::jump(0)]);
 
-        let result = instance.get_typed_func::<(), i32>("main").unwrap().call(&mut (), ());
-        assert!(matches!(result, Ok(666)), "unexpected result: {result:?}");
-        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let mut module_config = ModuleConfig::default();
+    module_config.set_gas_metering(Some(GasMeteringKind::Sync));
 
-        let result = instance.get_typed_func::<(), i32>("main").unwrap().call(&mut (), ());
-        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
-        assert!(matches!(result, Err(ExecutionError::OutOfGas)), "unexpected result: {result:?}");
+    #[derive(Default)]
+    struct State {
+        hostcalls: u32,
+        refills: u32,
+        should_fail: bool,
+        hostfn_should_fail: bool,
     }
 
-    {
-        core::mem::drop(instance);
-        let instance = instance_pre.instantiate().unwrap();
-        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
+    let module = Module::from_blob(&engine, &module_config, &blob).unwrap();
+    let mut linker = Linker::new(&engine);
+    linker
+        .func_wrap("hostfn", |mut caller: Caller<State>| -> Result<(), Trap> {
+            caller.data_mut().hostcalls += 1;
+            if caller.data().hostfn_should_fail {
+                caller.consume_gas(1000);
+                return Err(Trap::default());
+            }
 
-        let result = instance.get_typed_func::<(), i32>("main").unwrap().call(&mut (), ());
-        assert!(matches!(result, Err(ExecutionError::OutOfGas)), "unexpected result: {result:?}");
-        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
-    }
-}
+            Ok(())
+        })
+        .unwrap();
 
-fn basic_gas_metering_sync(config: Config) {
-    basic_gas_metering(config, GasMeteringKind::Sync);
-}
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
 
-fn basic_gas_metering_async(config: Config) {
-    basic_gas_metering(config, GasMeteringKind::Async);
+    // Without a handler we just run out of gas; every iteration of the loop costs 2 gas.
+    let mut state = State::default();
+    let mut config = ExecutionConfig::default();
+    config.set_gas(Gas::new(10).unwrap());
+    let result = instance
+        .get_typed_func::<(), ()>("infinite_loop_with_hostcall")
+        .unwrap()
+        .call_ex(&mut state, (), config);
+    assert!(matches!(result, Err(ExecutionError::OutOfGas)), "unexpected result: {result:?}");
+    assert_eq!(state.hostcalls, 5);
+
+    linker.on_out_of_gas(|mut caller: Caller<State>| {
+        let state = caller.data_mut();
+        state.refills += 1;
+        if state.should_fail {
+            return Err(Trap::default());
+        }
+
+        if state.refills > 3 {
+            return Ok(Gas::MIN);
+        }
+
+        Ok(Gas::new(100).unwrap())
+    });
+
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+
+    // The execution continues from where it left off after every refill.
+    let mut state = State::default();
+    let mut config = ExecutionConfig::default();
+    config.set_gas(Gas::new(10).unwrap());
+    let result = instance
+        .get_typed_func::<(), ()>("infinite_loop_with_hostcall")
+        .unwrap()
+        .call_ex(&mut state, (), config);
+    assert!(matches!(result, Err(ExecutionError::OutOfGas)), "unexpected result: {result:?}");
+    assert_eq!(state.refills, 4);
+    assert_eq!(state.hostcalls, 5 + 3 * 50);
+
+    // An error returned from the handler aborts the execution.
+    let mut state = State {
+        should_fail: true,
+        ..State::default()
+    };
+    let mut config = ExecutionConfig::default();
+    config.set_gas(Gas::new(10).unwrap());
+    let result = instance
+        .get_typed_func::<(), ()>("infinite_loop_with_hostcall")
+        .unwrap()
+        .call_ex(&mut state, (), config);
+    assert!(
+        matches!(result, Err(ExecutionError::Trap(ref trap)) if trap.kind() == TrapKind::HostFunctionError),
+        "unexpected result: {result:?}"
+    );
+    assert_eq!(state.refills, 1);
+    assert_eq!(state.hostcalls, 5);
+
+    // But an error returned from any other host function after the gas has run out doesn't take precedence.
+    let mut state = State {
+        hostfn_should_fail: true,
+        ..State::default()
+    };
+    let mut config = ExecutionConfig::default();
+    config.set_gas(Gas::new(10).unwrap());
+    let result = instance
+        .get_typed_func::<(), ()>("infinite_loop_with_hostcall")
+        .unwrap()
+        .call_ex(&mut state, (), config);
+    assert!(matches!(result, Err(ExecutionError::OutOfGas)), "unexpected result: {result:?}");
+    assert_eq!(state.refills, 0);
+    assert_eq!(state.hostcalls, 1);
 }
 
-fn consume_gas_in_host_function(config: Config, gas_metering_kind: GasMeteringKind) {
+fn execution_report_is_collected(config: Config) {
     let _ = env_logger::try_init();
 
     let mut builder = ProgramBlobBuilder::new();
-    builder.add_export(0, &FnMetadata::new("main", &[], Some(I32)));
-    builder.add_import(0, &FnMetadata::new("hostfn", &[], Some(I32)));
-    builder.set_code(&[asm::ecalli(0), asm::ret()]);
+    builder.add_export(0, &FnMetadata::new("main", &[], None));
+    builder.add_import(0, &FnMetadata::new("hostfn_a", &[], None));
+    builder.add_import(1, &FnMetadata::new("hostfn_b", &[], None));
+    builder.set_code(&[asm::ecalli(0), asm::ecalli(0), asm::ecalli(1), asm::ret()]);
 
     let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
     let engine = Engine::new(&config).unwrap();
     let mut module_config = ModuleConfig::default();
-    module_config.set_gas_metering(Some(gas_metering_kind));
+    module_config.set_gas_metering(Some(GasMeteringKind::Sync));
 
     let module = Module::from_blob(&engine, &module_config, &blob).unwrap();
     let mut linker = Linker::new(&engine);
-    linker
-        .func_wrap("hostfn", |mut caller: Caller<u64>| -> u32 {
-            assert_eq!(caller.gas_remaining().unwrap().get(), 1);
-            caller.consume_gas(*caller.data());
-            666
-        })
-        .unwrap();
-
-    let instance_pre = linker.instantiate_pre(&module).unwrap();
-    let instance = instance_pre.instantiate().unwrap();
+    linker.func_wrap("hostfn_a", || {}).unwrap();
+    linker.func_wrap("hostfn_b", || {}).unwrap();
 
-    {
-        let mut config = ExecutionConfig::default();
-        config.set_gas(Gas::new(3).unwrap());
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+    let func = instance.get_typed_func::<(), ()>("main").unwrap();
 
-        let result = instance.get_typed_func::<(), i32>("main").unwrap().call_ex(&mut 0, (), config);
-        assert!(matches!(result, Ok(666)), "unexpected result: {result:?}");
-        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(1).unwrap());
-    }
+    let mut execution_config = ExecutionConfig::default();
+    execution_config.set_gas(Gas::new(100).unwrap());
+    let (result, report) = func.call_ex_with_report(&mut (), (), execution_config);
+    result.unwrap();
 
-    {
-        let mut config = ExecutionConfig::default();
-        config.set_gas(Gas::new(3).unwrap());
+    assert_eq!(report.gas_consumed(), Some(4));
+    assert_eq!(report.hostcall_count("hostfn_a"), 2);
+    assert_eq!(report.hostcall_count("hostfn_b"), 1);
+    assert_eq!(report.hostcall_counts().collect::<Vec<_>>(), vec![("hostfn_a", 2), ("hostfn_b", 1)]);
 
-        let result = instance.get_typed_func::<(), i32>("main").unwrap().call_ex(&mut 1, (), config);
-        assert!(matches!(result, Ok(666)), "unexpected result: {result:?}");
-        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
-    }
+    let is_interpreted = config.backend() == Some(BackendKind::Interpreter);
+    assert_eq!(report.instructions_executed(), is_interpreted.then_some(4));
+    assert_eq!(report.basic_blocks_executed(), is_interpreted.then_some(1));
 
-    {
-        let mut config = ExecutionConfig::default();
-        config.set_gas(Gas::new(3).unwrap());
+    let is_linux_sandbox = !is_interpreted && config.sandbox() == Some(SandboxKind::Linux);
+    assert_eq!(report.wait_loop_start_count().is_some(), is_linux_sandbox);
+    assert_eq!(report.futex_wait_count().is_some(), is_linux_sandbox);
 
-        let result = instance.get_typed_func::<(), i32>("main").unwrap().call_ex(&mut 2, (), config);
-        assert_eq!(instance.gas_remaining().unwrap(), Gas::new(0).unwrap());
-        assert!(matches!(result, Err(ExecutionError::OutOfGas)), "unexpected result: {result:?}");
-    }
+    // A call which can't even be started gets an empty report.
+    let (result, report) = instance
+        .get_func("main")
+        .unwrap()
+        .call_ex_with_report(&mut (), &[Val::from(1)], ExecutionConfig::default());
+    assert!(matches!(result, Err(ExecutionError::Error(..))), "unexpected result: {result:?}");
+    assert_eq!(report.gas_consumed(), None);
+    assert_eq!(report.basic_blocks_executed(), None);
+    assert_eq!(report.hostcall_counts().count(), 0);
 }
 
-fn consume_gas_in_host_function_sync(config: Config) {
-    consume_gas_in_host_function(config, GasMeteringKind::Sync);
+fn execution_report_is_collected_for_resumable_calls(config: Config) {
+    let _ = env_logger::try_init();
+
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("main", &[], None));
+    builder.add_import(0, &FnMetadata::new("hostfn_a", &[], None));
+    builder.add_import(1, &FnMetadata::new("hostfn_b", &[], None));
+    builder.set_code(&[asm::ecalli(0), asm::ecalli(0), asm::ecalli(1), asm::fallthrough(), asm::ret()]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let mut module_config = ModuleConfig::default();
+    module_config.set_gas_metering(Some(GasMeteringKind::Sync));
+    module_config.set_count_basic_blocks(true);
+
+    let module = Module::from_blob(&engine, &module_config, &blob).unwrap();
+    let mut linker: Linker<()> = Linker::new(&engine);
+    linker.func_wrap("hostfn_a", || {}).unwrap();
+    linker.func_wrap("hostfn_b", || {}).unwrap();
+    let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+    let func = instance.get_func("main").unwrap();
+
+    let mut execution_config = ExecutionConfig::default();
+    execution_config.set_gas(Gas::new(100).unwrap());
+    let (result, report) = func.call_resumable_with_report(&[], execution_config);
+    assert!(
+        matches!(result, Ok(CallState::Suspended { hostcall: 0 })),
+        "unexpected result: {result:?}"
+    );
+    assert_eq!(report.gas_consumed(), Some(4));
+    assert_eq!(report.basic_blocks_executed(), Some(1));
+    assert_eq!(report.hostcall_counts().collect::<Vec<_>>(), vec![("hostfn_a", 1)]);
+
+    // Every resumption gets its own report.
+    let (result, report) = instance.resume_with_values_and_report(&[]);
+    assert!(
+        matches!(result, Ok(CallState::Suspended { hostcall: 0 })),
+        "unexpected result: {result:?}"
+    );
+    assert_eq!(report.gas_consumed(), Some(0));
+    assert_eq!(report.basic_blocks_executed(), Some(0));
+    assert_eq!(report.hostcall_counts().collect::<Vec<_>>(), vec![("hostfn_a", 1)]);
+
+    let (result, report) = instance.resume_with_values_and_report(&[]);
+    assert!(
+        matches!(result, Ok(CallState::Suspended { hostcall: 1 })),
+        "unexpected result: {result:?}"
+    );
+    assert_eq!(report.gas_consumed(), Some(0));
+    assert_eq!(report.basic_blocks_executed(), Some(0));
+    assert_eq!(report.hostcall_counts().collect::<Vec<_>>(), vec![("hostfn_b", 1)]);
+
+    let (result, report) = instance.resume_with_values_and_report(&[]);
+    assert!(matches!(result, Ok(CallState::Finished(None))), "unexpected result: {result:?}");
+    assert_eq!(report.gas_consumed(), Some(1));
+    assert_eq!(report.basic_blocks_executed(), Some(1));
+    assert_eq!(report.hostcall_counts().count(), 0);
+    assert_eq!(instance.gas_remaining(), Some(Gas::new(95).unwrap()));
+
+    // There's nothing left to resume.
+    let (result, report) = instance.resume_with_values_and_report(&[]);
+    assert!(matches!(result, Err(ExecutionError::Error(..))), "unexpected result: {result:?}");
+    assert_eq!(report.gas_consumed(), None);
 }
 
-fn consume_gas_in_host_function_async(config: Config) {
-    consume_gas_in_host_function(config, GasMeteringKind::Async);
+fn execution_report_counts_basic_blocks_on_compiled_backends(config: Config) {
+    let _ = env_logger::try_init();
+
+    let mut builder = ProgramBlobBuilder::new();
+    builder.add_export(0, &FnMetadata::new("main", &[], None));
+    builder.set_code(&[
+        // A loop which runs ten times.
+        asm::load_imm(A0, 10),
+        asm::fallthrough(),
+        asm::add_imm(A0, A0, -1i32 as u32),
+        asm::branch_not_eq_imm(A0, 0, 1),
+        asm::ret(),
+    ]);
+
+    let blob = ProgramBlob::parse(builder.into_vec()).unwrap();
+    let engine = Engine::new(&config).unwrap();
+    let linker: Linker<()> = Linker::new(&engine);
+    for count_basic_blocks in [false, true] {
+        let mut module_config = ModuleConfig::default();
+        module_config.set_count_basic_blocks(count_basic_blocks);
+
+        let module = Module::from_blob(&engine, &module_config, &blob).unwrap();
+        let instance = linker.instantiate_pre(&module).unwrap().instantiate().unwrap();
+        let func = instance.get_typed_func::<(), ()>("main").unwrap();
+
+        let is_interpreted = config.backend() == Some(BackendKind::Interpreter);
+        let expected = (count_basic_blocks || is_interpreted).then_some(12);
+        for _ in 0..2 {
+            let (result, report) = func.call_ex_with_report(&mut (), (), ExecutionConfig::default());
+            result.unwrap();
+            assert_eq!(report.basic_blocks_executed(), expected);
+        }
+    }
 }
 
 run_tests! {
     caller_and_caller_ref_work
     caller_split_works
+    caller_with_memory_works
+    caller_with_memory_works_on_heap_and_stack
+    caller_typed_memory_access_works
+    multiple_return_values_work
+    exports_can_be_called_from_host_functions
+    imports_can_be_linked_to_exports_of_other_instances
+    calls_into_other_instances_are_gas_metered
+    namespaced_imports_work
+    module_can_be_introspected
     trapping_from_hostcall_handler_works
+    trap_kinds_are_reported
+    user_errors_are_propagated_from_host_functions
+    backtrace_is_captured_on_trap
+    backtrace_is_captured_on_trap_with_deep_stack
+    suspending_and_resuming_execution_works
+    asynchronous_host_functions_work
+    other_calls_are_refused_while_an_async_call_is_suspended
+    interrupting_execution_works
+    execution_report_is_collected
+    execution_report_is_collected_for_resumable_calls
+    execution_report_counts_basic_blocks_on_compiled_backends
+    sbrk_grows_the_heap
+    bit_manipulation_instructions_work
+    bit_manipulation_instructions_work_with_fallbacks
+    sixty_four_bit_instructions_work
+    sixty_four_bit_programs_pass_u64_in_a_single_register
+    sixty_four_bit_instructions_are_rejected_in_32_bit_programs
+    memcpy_and_memset_work
+    memcpy_and_memset_across_the_heap_top
+    snapshot_and_restore_work
+    freezing_an_instance_works
     doom_o3_dwarf5
     doom_o1_dwarf5
     doom_o3_dwarf2
@@ -690,11 +3179,25 @@ run_tests! {
     test_blob_atomic_fetch_swap
     test_blob_atomic_fetch_minmax
     test_blob_hostcall
+    test_blob_rv32emac
 
     basic_gas_metering_sync
     basic_gas_metering_async
+    gas_is_charged_per_basic_block_sync
+    gas_is_charged_per_basic_block_async
     consume_gas_in_host_function_sync
     consume_gas_in_host_function_async
+    consume_gas_in_async_host_function
+    running_out_of_gas_in_infinite_loop_sync
+    running_out_of_gas_in_infinite_loop_async
+    custom_gas_cost_model_sync
+    custom_gas_cost_model_async
+    basic_blocks_whose_gas_cost_does_not_fit_into_an_i32_are_rejected
+    bulk_memory_gas_metering_sync
+    bulk_memory_gas_metering_async
+    sbrk_gas_metering_sync
+    sbrk_gas_metering_async
+    out_of_gas_handler_can_refill_gas
 }
 
 // Source: https://users.rust-lang.org/t/a-macro-to-assert-that-a-type-does-not-implement-trait-bounds/31179
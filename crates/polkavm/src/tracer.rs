@@ -1,23 +1,36 @@
 use crate::api::BackendAccess;
 use crate::api::ExecutionConfig;
+use crate::api::HostcallOutcome;
+use crate::api::InstanceSnapshot;
 use crate::api::Module;
-use crate::interpreter::{InterpretedInstance, InterpreterContext};
+use crate::interpreter::{InterpretedInstance, InterpreterContext, SavedCallState};
 use crate::source_cache::SourceCache;
 use core::mem::MaybeUninit;
-use polkavm_common::error::Trap;
+use polkavm_common::error::{ExecutionError, Trap};
 use polkavm_common::program::{FrameKind, Opcode, ProgramExport, Reg};
 use polkavm_common::utils::Access;
 
+/// The state of the crosscheck interpreter at the point where a nested call was made.
+struct NestedCallCrosscheckState {
+    call_state: SavedCallState,
+    crosscheck_reg: Option<(Reg, u64)>,
+    crosscheck_store: Option<(u32, u32)>,
+    crosscheck_stopped_at: Option<u32>,
+}
+
 pub(crate) struct Tracer {
     module: Module,
     source_cache: SourceCache,
     program_counter_history: [u32; 8],
     program_counter_history_position: usize,
     crosscheck_interpreter: Option<InterpretedInstance>,
-    crosscheck_reg: Option<(Reg, u32)>,
+    crosscheck_reg: Option<(Reg, u64)>,
     crosscheck_store: Option<(u32, u32)>,
     crosscheck_store_bytes: [u8; 8],
     crosscheck_reset_memory_after_execution: bool,
+    crosscheck_nested_call_stack: Vec<NestedCallCrosscheckState>,
+    /// The instruction on which the crosscheck interpreter has stopped; the actual execution must not continue past it.
+    crosscheck_stopped_at: Option<u32>,
     current_line_program_position: Option<(usize, usize)>,
     current_source_location: Option<(u32, u32)>,
 
@@ -30,7 +43,7 @@ impl Tracer {
             program_counter_history: [!0; 8],
             program_counter_history_position: 0,
             crosscheck_interpreter: if module.compiled_module().is_some() {
-                InterpretedInstance::new(module.clone()).ok()
+                InterpretedInstance::new(module.clone(), None).ok()
             } else {
                 None
             },
@@ -40,6 +53,8 @@ impl Tracer {
             crosscheck_store: None,
             crosscheck_store_bytes: Default::default(),
             crosscheck_reset_memory_after_execution: false,
+            crosscheck_nested_call_stack: Vec::new(),
+            crosscheck_stopped_at: None,
             current_line_program_position: None,
             current_source_location: None,
 
@@ -57,27 +72,72 @@ impl Tracer {
 
         if let Some(ref mut interpreter) = self.crosscheck_interpreter {
             self.crosscheck_reset_memory_after_execution = config.reset_memory_after_execution;
+            self.crosscheck_stopped_at = None;
             interpreter.prepare_for_call(export_index, config);
         }
     }
 
-    pub fn on_after_call(&mut self) {
+    pub fn on_after_call<E>(&mut self, result: Result<(), ExecutionError<E>>) -> Result<(), ExecutionError<E>> {
         if let Some(ref mut interpreter) = self.crosscheck_interpreter {
             if self.crosscheck_reset_memory_after_execution {
                 interpreter.reset_memory();
             }
         }
+
+        if let (Ok(()), Some(program_counter)) = (&result, self.crosscheck_stopped_at.take()) {
+            log::error!("Execution mismatch! Crosscheck interpreter stopped at #{program_counter}, actual execution finished successfully");
+            self.debug_print_history();
+            return Err(ExecutionError::Trap(Trap::default()));
+        }
+
+        result
+    }
+
+    pub fn on_before_nested_call(&mut self, export_index: usize, export: &ProgramExport, regs: &[u64; Reg::ALL.len()]) {
+        log::trace!("Calling export from within a hostcall: '{}'", export.prototype().name());
+
+        if let Some(ref mut interpreter) = self.crosscheck_interpreter {
+            self.crosscheck_nested_call_stack.push(NestedCallCrosscheckState {
+                call_state: interpreter.save_call_state(),
+                crosscheck_reg: self.crosscheck_reg.take(),
+                crosscheck_store: self.crosscheck_store.take(),
+                crosscheck_stopped_at: self.crosscheck_stopped_at.take(),
+            });
+            interpreter.prepare_for_nested_call(export_index, regs);
+        }
+    }
+
+    pub fn on_after_nested_call(&mut self) {
+        if let Some(ref mut interpreter) = self.crosscheck_interpreter {
+            let state = self
+                .crosscheck_nested_call_stack
+                .pop()
+                .expect("internal error: unbalanced nested call");
+            interpreter.restore_call_state(state.call_state);
+            self.crosscheck_reg = state.crosscheck_reg;
+            self.crosscheck_store = state.crosscheck_store;
+            self.crosscheck_stopped_at = state.crosscheck_stopped_at;
+        }
     }
 
     pub fn on_trace(&mut self, access: &mut BackendAccess) -> Result<(), Trap> {
         assert!(self.module.is_debug_trace_execution_enabled());
 
-        self.crosscheck_last_instruction(access)?;
-
         let program_counter = access
             .program_counter()
             .expect("internal error: tracer called without valid program counter");
 
+        if let Some(stopped_at) = self.crosscheck_stopped_at.take() {
+            log::error!(
+                "Execution mismatch! Crosscheck interpreter stopped at #{stopped_at}, actual execution continued to #{program_counter}"
+            );
+            self.debug_print_history();
+            return Err(Trap::default());
+        }
+
+        self.crosscheck_last_instruction(access)?;
+        self.crosscheck_native_program_counter(access, program_counter)?;
+
         self.trace_current_instruction_source(program_counter);
 
         let instruction = self.module.instructions()[program_counter as usize];
@@ -91,9 +151,9 @@ impl Tracer {
         Ok(())
     }
 
-    pub fn on_set_reg_in_hostcall(&mut self, reg: Reg, value: u32) {
+    pub fn on_set_reg_in_hostcall(&mut self, reg: Reg, value: u64) {
         if let Some(ref mut interpreter) = self.crosscheck_interpreter {
-            interpreter.access().set_reg(reg, value);
+            interpreter.access().set_reg64(reg, value);
         }
     }
 
@@ -110,6 +170,15 @@ impl Tracer {
         Ok(())
     }
 
+    pub fn on_restore(&mut self, snapshot: &InstanceSnapshot) {
+        if let Some(ref mut interpreter) = self.crosscheck_interpreter {
+            interpreter.resize_heap(self.module.memory_config().heap_address() + snapshot.heap_size());
+            if let Err(error) = snapshot.write_into(&mut interpreter.access(), self.module.memory_config()) {
+                log::error!("Failed to restore a snapshot into the crosscheck interpreter: {error}");
+            }
+        }
+    }
+
     fn debug_print_history(&self) {
         log::error!("Program counter history:");
         for nth in (0..self.program_counter_history.len()).rev() {
@@ -124,7 +193,7 @@ impl Tracer {
 
     fn crosscheck_last_instruction(&mut self, access: &mut BackendAccess) -> Result<(), Trap> {
         if let Some((reg, expected_value)) = self.crosscheck_reg.take() {
-            let value = access.get_reg(reg);
+            let value = access.get_reg64(reg);
             if value != expected_value {
                 log::error!("Register value mismatch! Crosscheck interpreter has {reg} = 0x{expected_value:x}, actual execution has {reg} = 0x{value:x}");
                 self.debug_print_history();
@@ -153,6 +222,24 @@ impl Tracer {
         Ok(())
     }
 
+    fn crosscheck_native_program_counter(&self, access: &mut BackendAccess, program_counter: u32) -> Result<(), Trap> {
+        let Some(native_address) = access.native_program_counter() else {
+            return Ok(());
+        };
+
+        // This is the return address of the call into the tracer, so it's always past the start of the instruction.
+        let expected_program_counter = native_address
+            .checked_sub(1)
+            .and_then(|native_address| self.module.compiled_module().program_counter_by_native_address(native_address));
+        if expected_program_counter != Some(program_counter) {
+            log::error!("Native program counter mismatch! 0x{native_address:x} maps to {expected_program_counter:?}, actual execution is at #{program_counter}");
+            self.debug_print_history();
+            return Err(Trap::default());
+        }
+
+        Ok(())
+    }
+
     fn trace_current_instruction_source(&mut self, program_counter: u32) {
         #[cfg(not(windows))]
         const VT_DARK: &str = "\x1B[1;30m";
@@ -295,11 +382,13 @@ impl Tracer {
 
         let instruction = self.module.instructions()[program_counter as usize];
         if matches!(instruction.opcode(), Opcode::trap) {
+            self.crosscheck_stopped_at = Some(program_counter);
             return Ok(());
         }
 
-        let mut on_hostcall = |_hostcall: u32, _access: BackendAccess<'_>| -> Result<(), Trap> { Ok(()) };
-        let mut on_set_reg = |reg: Reg, value: u32| -> Result<(), Trap> {
+        let mut on_hostcall =
+            |_hostcall: u32, _access: BackendAccess<'_>| -> Result<HostcallOutcome, Trap> { Ok(HostcallOutcome::Continue) };
+        let mut on_set_reg = |reg: Reg, value: u64| -> Result<(), Trap> {
             assert!(self.crosscheck_reg.is_none());
             self.crosscheck_reg = Some((reg, value));
             Ok(())
@@ -320,12 +409,24 @@ impl Tracer {
         ctx.set_on_set_reg(&mut on_set_reg);
         ctx.set_on_store(&mut on_store);
 
-        if let Err(error) = interpreter.step_once(ctx) {
-            log::error!("Crosscheck interpreter encountered error: {}", error);
-            self.debug_print_history();
-            return Err(Trap::default());
+        match interpreter.step_once(ctx) {
+            Ok(()) => Ok(()),
+            Err(ExecutionError::Trap(_)) => {
+                // The actual execution is expected to trap on this instruction too, so let it do so
+                // to have it report the proper reason, but make sure it doesn't go any further.
+                self.crosscheck_stopped_at = Some(program_counter);
+                Ok(())
+            }
+            Err(ExecutionError::OutOfGas) => {
+                // The actual execution might not stop here since the host can refill the gas,
+                // which the crosscheck interpreter doesn't know about.
+                Ok(())
+            }
+            Err(error) => {
+                log::error!("Crosscheck interpreter encountered error: {}", error);
+                self.debug_print_history();
+                Err(Trap::default())
+            }
         }
-
-        Ok(())
     }
 }
@@ -19,3 +19,26 @@ impl From<u32> for RegImm {
         RegImm::Imm(value)
     }
 }
+
+/// Clears the upper 32-bits of every register when running a program targeting the 32-bit instruction set.
+#[inline]
+pub(crate) fn truncate_regs(regs: &mut [u64], is_64_bit: bool) {
+    if !is_64_bit {
+        for value in regs {
+            *value = u64::from(*value as u32);
+        }
+    }
+}
+
+/// Calculates a 64-bit FNV-1a hash of the given bytes.
+///
+/// This is stable across processes and toolchain versions, so it can be persisted.
+pub(crate) fn fnv1a_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
@@ -131,6 +131,110 @@ unsafe impl<const N: usize> alloc::alloc::GlobalAlloc for SimpleAlloc<N> {
     }
 }
 
+/// Grows the heap by `size` bytes and returns the previous top of the heap, or zero if the heap couldn't be grown.
+#[cfg(target_arch = "riscv32")]
+#[inline]
+fn sbrk(size: usize) -> usize {
+    let address: usize;
+    unsafe {
+        core::arch::asm!(
+            ".insn r 0x0b, 1, 0, {dst}, {size}, zero",
+            dst = lateout(reg) address,
+            size = in(reg) size,
+            options(nostack, preserves_flags)
+        );
+    }
+    address
+}
+
+/// An allocator which grows the heap on demand with the `sbrk` instruction.
+///
+/// The memory is handed out linearly and is never returned to the VM; only the most recent
+/// allocation can be freed and reused. The maximum size of the heap is controlled by the host
+/// through `ModuleConfig::set_max_heap_size`.
+///
+/// Guests are single-threaded, so no synchronization is done.
+#[cfg(target_arch = "riscv32")]
+pub struct SbrkAlloc {
+    next: core::cell::Cell<usize>,
+    end: core::cell::Cell<usize>,
+}
+
+#[cfg(target_arch = "riscv32")]
+unsafe impl Sync for SbrkAlloc {}
+
+#[cfg(target_arch = "riscv32")]
+impl SbrkAlloc {
+    #[inline]
+    pub const fn new() -> Self {
+        SbrkAlloc {
+            next: core::cell::Cell::new(0),
+            end: core::cell::Cell::new(0),
+        }
+    }
+
+    pub fn allocate(&self, size: usize, align: usize) -> *mut u8 {
+        if align.count_ones() != 1 {
+            // The alignment must be non-zero and be a power of two.
+            return core::ptr::null_mut();
+        }
+
+        if self.end.get() == 0 {
+            let heap_top = sbrk(0);
+            self.next.set(heap_top);
+            self.end.set(heap_top);
+        }
+
+        loop {
+            let Some(aligned_pointer) = self.next.get().checked_add(align - 1).map(|address| address & !(align - 1)) else {
+                return core::ptr::null_mut();
+            };
+
+            let Some(new_next) = aligned_pointer.checked_add(size) else {
+                return core::ptr::null_mut();
+            };
+
+            let end = self.end.get();
+            if new_next <= end {
+                self.next.set(new_next);
+                return aligned_pointer as *mut u8;
+            }
+
+            let grow_by = new_next - end;
+            let old_heap_top = sbrk(grow_by);
+            if old_heap_top == 0 {
+                return core::ptr::null_mut();
+            }
+
+            if old_heap_top != end {
+                // Someone else has grown the heap behind our back; start from the new top.
+                self.next.set(old_heap_top);
+            }
+
+            self.end.set(old_heap_top + grow_by);
+        }
+    }
+
+    pub fn deallocate(&self, pointer: *mut u8, size: usize) {
+        if (pointer as usize).wrapping_add(size) == self.next.get() {
+            self.next.set(pointer as usize);
+        }
+    }
+}
+
+#[cfg(target_arch = "riscv32")]
+unsafe impl alloc::alloc::GlobalAlloc for SbrkAlloc {
+    #[inline]
+    unsafe fn alloc(&self, layout: alloc::alloc::Layout) -> *mut u8 {
+        self.allocate(layout.size(), layout.align())
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: alloc::alloc::Layout) {
+        self.deallocate(pointer, layout.size())
+    }
+}
+
 #[test]
 fn test_simple_allocator_basics() {
     let alloc = SimpleAlloc::<1024>::new();
@@ -11,5 +11,17 @@ function build_test_data() {
     chmod -x $output_path
 }
 
+function build_test_data_from_assembly() {
+    output_path="../test-data/$1.elf.zst"
+
+    echo "> Assembling: '$1' (-> $output_path)"
+    mkdir -p target/$1
+    llvm-mc -triple=riscv32 -mattr=$2 -filetype=obj -o target/$1/$1.o $1.S
+    ld.lld --no-relax --emit-relocs -o target/$1/$1 target/$1/$1.o
+    zstd -f -q -19 -o $output_path target/$1/$1
+    chmod -x $output_path
+}
+
 build_test_data "bench-pinky" "release"
 build_test_data "test-blob" "no-lto"
+build_test_data_from_assembly "test-blob-rv32emac" "+e,+m,+a,+c"
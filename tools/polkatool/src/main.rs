@@ -3,6 +3,7 @@
 #![allow(clippy::exit)]
 
 use clap::Parser;
+use polkavm_common::abi::{GuestMemoryConfig, VM_PAGE_SIZE};
 use polkavm_common::program::{Opcode, ProgramBlob};
 use std::collections::HashMap;
 use std::{
@@ -56,6 +57,27 @@ enum Args {
         /// The input files.
         inputs: Vec<PathBuf>,
     },
+
+    /// Runs an export of a .polkavm blob and bakes the resulting memory into a new blob.
+    Preinit {
+        /// The output file.
+        #[clap(short = 'o', long)]
+        output: PathBuf,
+
+        /// The export which should be called to initialize the program.
+        #[clap(short = 'e', long)]
+        export: String,
+
+        /// An import which should be allowed to be called during the initialization, in the form of `name[=value]`.
+        ///
+        /// Calling it will do nothing except return the given value (or zero, if not specified).
+        /// Calling any other import will make the initialization fail.
+        #[clap(long = "stub-import", value_name = "NAME[=VALUE]")]
+        stub_imports: Vec<String>,
+
+        /// The input file.
+        input: PathBuf,
+    },
 }
 
 macro_rules! bail {
@@ -77,6 +99,12 @@ fn main() {
         } => main_link(input, output, strip, run_only_if_newer),
         Args::Disassemble { output, format, input } => main_disassemble(input, format, output),
         Args::Stats { inputs } => main_stats(inputs),
+        Args::Preinit {
+            output,
+            export,
+            stub_imports,
+            input,
+        } => main_preinit(input, output, export, stub_imports),
     };
 
     if let Err(error) = result {
@@ -171,6 +199,110 @@ fn main_stats(inputs: Vec<PathBuf>) -> Result<(), String> {
     Ok(())
 }
 
+fn main_preinit(input: PathBuf, output: PathBuf, export: String, stub_imports: Vec<String>) -> Result<(), String> {
+    let blob = load_blob(&input)?;
+
+    let mut stub_value_by_name = HashMap::new();
+    for stub_import in stub_imports {
+        let (name, value) = match stub_import.split_once('=') {
+            Some((name, value)) => match value.parse::<u32>() {
+                Ok(value) => (name.to_owned(), value),
+                Err(error) => bail!("invalid value for the stubbed import '{name}': {error}"),
+            },
+            None => (stub_import, 0),
+        };
+
+        stub_value_by_name.insert(name, value);
+    }
+
+    let mut import_name_by_index = HashMap::new();
+    for import in blob.imports() {
+        let import = match import {
+            Ok(import) => import,
+            Err(error) => bail!("failed to parse an import: {error}"),
+        };
+
+        import_name_by_index.insert(import.index(), import.prototype().name().to_owned());
+    }
+
+    let mut config = polkavm::Config::new();
+    config.set_backend(Some(polkavm::BackendKind::Interpreter));
+
+    let engine = match polkavm::Engine::new(&config) {
+        Ok(engine) => engine,
+        Err(error) => bail!("failed to create VM engine: {error}"),
+    };
+
+    let module = match polkavm::Module::from_blob(&engine, &Default::default(), &blob) {
+        Ok(module) => module,
+        Err(error) => bail!("failed to load {input:?}: {error}"),
+    };
+
+    let mut linker = polkavm::Linker::new(&engine);
+    linker.func_fallback(move |mut caller, index| {
+        let name = import_name_by_index.get(&index).map_or("<unknown>", |name| name.as_str());
+        let Some(&value) = stub_value_by_name.get(name) else {
+            return Err(polkavm::Trap::from_error(format!(
+                "the program called the import '{name}', which is not available during the initialization"
+            )));
+        };
+
+        caller.set_reg(polkavm::Reg::A0, value);
+        Ok(())
+    });
+
+    let instance = match linker.instantiate_pre(&module).and_then(|instance_pre| instance_pre.instantiate()) {
+        Ok(instance) => instance,
+        Err(error) => bail!("failed to instantiate {input:?}: {error}"),
+    };
+
+    let Some(func) = instance.get_func(&export) else {
+        bail!("failed to find the export '{export}'");
+    };
+
+    if let Err(error) = func.call(&mut (), &[]) {
+        bail!("failed to run the export '{export}': {error}");
+    }
+
+    // Read the whole heap and shrink it back into read-write data followed by zeroed BSS.
+    let memory_config = match GuestMemoryConfig::new(
+        blob.ro_data().len() as u64,
+        blob.rw_data().len() as u64,
+        u64::from(blob.bss_size()),
+        u64::from(blob.stack_size()),
+    ) {
+        Ok(memory_config) => memory_config,
+        Err(error) => bail!("invalid memory configuration: {error}"),
+    };
+
+    let mut heap = match instance.read_memory_into_new_vec(memory_config.heap_address(), memory_config.heap_size()) {
+        Ok(heap) => heap,
+        Err(error) => bail!("failed to read the program's memory: {error}"),
+    };
+
+    let rw_data_length = heap.iter().rposition(|&byte| byte != 0).map_or(0, |position| position + 1);
+    heap.truncate(rw_data_length);
+
+    let Some(rw_data_size) = polkavm_common::utils::align_to_next_page_u32(VM_PAGE_SIZE, rw_data_length as u32) else {
+        unreachable!()
+    };
+    let bss_size = memory_config.heap_size() - rw_data_size;
+
+    let mut builder = match polkavm_common::writer::ProgramBlobBuilder::from_blob(&blob) {
+        Ok(builder) => builder,
+        Err(error) => bail!("failed to parse {input:?}: {error}"),
+    };
+
+    builder.set_rw_data(heap);
+    builder.set_bss_size(bss_size);
+
+    if let Err(error) = std::fs::write(&output, builder.into_vec()) {
+        bail!("failed to write the program blob to {output:?}: {error}");
+    }
+
+    Ok(())
+}
+
 fn main_disassemble(input: PathBuf, format: DisassemblyFormat, output: Option<PathBuf>) -> Result<(), String> {
     let blob = load_blob(&input)?;
 
@@ -500,3 +632,57 @@ fn disassemble_into(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::main_preinit;
+    use polkavm_common::abi::{VM_ADDR_USER_MEMORY, VM_PAGE_SIZE};
+    use polkavm_common::elf::FnMetadata;
+    use polkavm_common::program::{asm, ProgramBlob, Reg};
+    use polkavm_common::writer::ProgramBlobBuilder;
+
+    #[test]
+    fn preinit_bakes_memory_into_blob() {
+        let mut builder = ProgramBlobBuilder::new();
+        builder.set_rw_data(vec![1, 0, 0, 0]);
+        builder.set_bss_size(VM_PAGE_SIZE * 2);
+        builder.add_import(0, &FnMetadata::new("get_value", &[], Some(polkavm_common::program::ExternTy::I32)));
+        builder.add_export(0, &FnMetadata::new("init", &[], None));
+        builder.set_code(&[
+            asm::ecalli(0),
+            asm::store_u32(Reg::A0, VM_ADDR_USER_MEMORY + VM_PAGE_SIZE + 4),
+            asm::load_imm(Reg::A1, 100),
+            asm::store_u32(Reg::A1, VM_ADDR_USER_MEMORY + VM_PAGE_SIZE),
+            asm::ret(),
+        ]);
+
+        let directory = std::env::temp_dir().join(format!("polkatool-preinit-test-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let input = directory.join("input.polkavm");
+        let output = directory.join("output.polkavm");
+        std::fs::write(&input, builder.into_vec()).unwrap();
+
+        // Imports which aren't stubbed can't be called.
+        let result = main_preinit(input.clone(), output.clone(), "init".into(), vec![]);
+        assert!(result.unwrap_err().contains("get_value"));
+
+        main_preinit(input.clone(), output.clone(), "init".into(), vec!["get_value=7".into()]).unwrap();
+
+        let input_blob = ProgramBlob::parse(std::fs::read(&input).unwrap()).unwrap();
+        let output_blob = ProgramBlob::parse(std::fs::read(&output).unwrap()).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let mut expected_rw_data = vec![0; VM_PAGE_SIZE as usize + 5];
+        expected_rw_data[0] = 1;
+        expected_rw_data[VM_PAGE_SIZE as usize] = 100;
+        expected_rw_data[VM_PAGE_SIZE as usize + 4] = 7;
+        assert_eq!(output_blob.rw_data(), expected_rw_data);
+
+        // The memory map doesn't change, and neither does the code.
+        assert_eq!(
+            output_blob.rw_data().len().next_multiple_of(VM_PAGE_SIZE as usize) + output_blob.bss_size() as usize,
+            input_blob.rw_data().len().next_multiple_of(VM_PAGE_SIZE as usize) + input_blob.bss_size() as usize
+        );
+        assert_eq!(output_blob.code(), input_blob.code());
+    }
+}